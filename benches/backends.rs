@@ -0,0 +1,109 @@
+extern crate atom;
+
+use atom::bytecode::{Chunk, Constant};
+use atom::compile::*;
+use atom::parse::*;
+use std::time::Instant;
+
+// How many times every script is compiled when timing the compiler.
+const ROUNDS: u32 = 50;
+
+// Counts every instruction of a function and the functions declared in it.
+// There is no machine to run register code on yet, so the backends are compared
+// by the instructions each needs for the same scripts, which is what the
+// dispatch loop of either machine pays for.
+fn stack_instructions(chunk: &Chunk) -> usize {
+    let nested: usize = chunk
+        .constants()
+        .iter()
+        .map(|c| match c {
+            Constant::Function(f) => stack_instructions(&f.chunk),
+            _ => 0,
+        })
+        .sum();
+    chunk.instructions().count() + nested
+}
+
+fn register_instructions(function: &register::Function) -> usize {
+    let nested: usize = function.functions.iter().map(register_instructions).sum();
+    function.code.len() + nested
+}
+
+// Counts the instructions inside loops, which are what a script spends most
+// of its time running, once for every loop they are in.
+fn loop_instructions(function: &register::Function) -> usize {
+    let nested: usize = function.functions.iter().map(loop_instructions).sum();
+    let looped: usize = function
+        .code
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| match *instruction {
+            register::Instruction::Jump { target } if target <= i => i + 1 - target,
+            _ => 0,
+        })
+        .sum();
+    looped + nested
+}
+
+fn compile_time(program: &atom::ast::Program, backend: Backend) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        if compile_to(program, OptLevel::Basic, backend).is_err() {
+            panic!("{} does not compile", program.name);
+        }
+    }
+    start.elapsed().as_secs_f64() * 1e6 / f64::from(ROUNDS)
+}
+
+fn main() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/scripts");
+    let mut scripts: Vec<_> = std::fs::read_dir(dir)
+        .expect("cannot read the benchmark scripts")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "at"))
+        .collect();
+    scripts.sort();
+
+    println!(
+        "{:<16} {:>8} {:>8} {:>7} {:>6} {:>6} {:>11} {:>11}",
+        "script", "stack", "register", "change", "loops", "after", "stack us", "register us"
+    );
+    for path in scripts {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let program = match parse_program(&name[..], &source[..]) {
+            Ok(program) => program,
+            Err(errors) => panic!("{}", errors[0]),
+        };
+
+        let stack = match compile_to(&program, OptLevel::Basic, Backend::Stack) {
+            Ok(Compiled::Stack(function)) => function,
+            _ => panic!("{} does not compile", name),
+        };
+        // The loops of the register code before and after they are
+        // optimized.
+        let mut function = register::lower(&stack);
+        register::optimize(&mut function);
+        let loops = loop_instructions(&function);
+        register::optimize_loops(&mut function);
+        let after = loop_instructions(&function);
+
+        let stack = stack_instructions(&stack.chunk);
+        let register = match compile_to(&program, OptLevel::Basic, Backend::Register) {
+            Ok(Compiled::Register(function)) => register_instructions(&function),
+            _ => panic!("{} does not compile", name),
+        };
+        let change = (register as f64 / stack as f64 - 1.0) * 100.0;
+        println!(
+            "{:<16} {:>8} {:>8} {:>6.1}% {:>6} {:>6} {:>11.1} {:>11.1}",
+            name,
+            stack,
+            register,
+            change,
+            loops,
+            after,
+            compile_time(&program, Backend::Stack),
+            compile_time(&program, Backend::Register)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+extern crate atom;
+
+use atom::bytecode::Function;
+use atom::compile::*;
+use atom::parse::*;
+use atom::vm::cache::InlineCaches;
+use atom::vm::Vm;
+use std::io;
+use std::time::Instant;
+
+// How many times every script is run with each kind of cache, keeping the
+// fastest, which is the one the least else got in the way of. The kinds
+// take turns so that whatever else the machine is doing slows them alike.
+const ROUNDS: u32 = 9;
+
+const CACHES: [InlineCaches; 3] = [
+    InlineCaches::Off,
+    InlineCaches::Monomorphic,
+    InlineCaches::Polymorphic,
+];
+
+fn run_times(function: &Function) -> [f64; 3] {
+    let mut fastest = [f64::INFINITY; 3];
+    for _ in 0..ROUNDS {
+        for (i, &caches) in CACHES.iter().enumerate() {
+            let mut vm = Vm::with_output(Box::new(io::sink()));
+            vm.set_inline_caches(caches);
+            let start = Instant::now();
+            if let Err(error) = vm.run(function) {
+                panic!("{}", error);
+            }
+            fastest[i] = fastest[i].min(start.elapsed().as_secs_f64() * 1e3);
+        }
+    }
+    fastest
+}
+
+fn main() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/scripts");
+    let mut scripts: Vec<_> = std::fs::read_dir(dir)
+        .expect("cannot read the benchmark scripts")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "at"))
+        .collect();
+    scripts.sort();
+
+    println!(
+        "{:<16} {:>10} {:>10} {:>10} {:>7}",
+        "script", "off ms", "mono ms", "poly ms", "change"
+    );
+    for path in scripts {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let program = match parse_program(&name[..], &source[..]) {
+            Ok(program) => program,
+            Err(errors) => panic!("{}", errors[0]),
+        };
+        let function = match compile_with(&program, OptLevel::Basic) {
+            Ok(function) => function,
+            Err(errors) => panic!("{}", errors[0]),
+        };
+        let [off, mono, poly] = run_times(&function);
+        let change = (poly / off - 1.0) * 100.0;
+        println!(
+            "{:<16} {:>10.2} {:>10.2} {:>10.2} {:>6.1}%",
+            name, off, mono, poly, change
+        );
+    }
+}
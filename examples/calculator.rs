@@ -0,0 +1,102 @@
+// A calculator host. Each line typed by the user is parsed as an Atom
+// expression and evaluated by the host, with a few math functions exposed
+// to the script.
+use atom::ast::*;
+use atom::parse::*;
+
+fn call(name: &str, args: &[f64]) -> Result<f64, String> {
+    match (name, args) {
+        ("sqrt", [x]) => Ok(x.sqrt()),
+        ("abs", [x]) => Ok(x.abs()),
+        ("min", [a, b]) => Ok(a.min(*b)),
+        ("max", [a, b]) => Ok(a.max(*b)),
+        ("pow", [a, b]) => Ok(a.powf(*b)),
+        _ => Err(format!(
+            "unknown function {} taking {} arguments",
+            name,
+            args.len()
+        )),
+    }
+}
+
+fn evaluate(expr: &Expr) -> Result<f64, String> {
+    match &expr.kind {
+        ExprKind::Int(n) => Ok(*n as f64),
+        ExprKind::Float(n) => Ok(*n),
+        ExprKind::Variable(name) if name == "pi" => Ok(std::f64::consts::PI),
+        ExprKind::Unary {
+            op: UnaryOp::Negate,
+            operand,
+        } => Ok(-evaluate(operand)?),
+        ExprKind::Binary { op, left, right } => {
+            let (a, b) = (evaluate(left)?, evaluate(right)?);
+            match op {
+                BinaryOp::Add => Ok(a + b),
+                BinaryOp::Subtract => Ok(a - b),
+                BinaryOp::Multiply => Ok(a * b),
+                BinaryOp::Divide if b == 0.0 => Err(String::from("division by zero")),
+                BinaryOp::Divide => Ok(a / b),
+                BinaryOp::Modulo => Ok(a % b),
+                _ => Err(String::from("only arithmetic is supported")),
+            }
+        }
+        ExprKind::Call(c) => {
+            let name = match &c.callee.kind {
+                ExprKind::Variable(name) => name,
+                _ => return Err(String::from("only named functions can be called")),
+            };
+            let args = c.args.iter().map(evaluate).collect::<Result<Vec<_>, _>>()?;
+            call(&name[..], &args[..])
+        }
+        _ => Err(format!(
+            "unsupported expression at column {}",
+            expr.span.column
+        )),
+    }
+}
+
+fn calculate(line: &str) -> Result<f64, String> {
+    let expr =
+        parse_expression("calculator", line).map_err(|errors| String::from(errors[0].message()))?;
+    evaluate(&expr)
+}
+
+fn main() {
+    let mut lines: Vec<String> = std::env::args().skip(1).collect();
+    if lines.is_empty() {
+        lines = vec![
+            String::from("1 + 2 * 3"),
+            String::from("max(4, sqrt(81)) / 2"),
+            String::from("2 * pi"),
+        ];
+    }
+
+    for line in lines {
+        match calculate(&line[..]) {
+            Ok(result) => println!("{} = {}", line, result),
+            Err(e) => println!("{}: error: {}", line, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate() {
+        assert_eq!(calculate("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(calculate("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(calculate("-max(4, sqrt(81)) % 4"), Ok(-1.0));
+        assert_eq!(calculate("pow(2, 10)"), Ok(1024.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(calculate("1 / 0").is_err());
+        assert!(calculate("1 +").is_err());
+        assert!(calculate("launch(1)").is_err());
+        assert!(calculate("'text'").is_err());
+        assert!(calculate("1; 2").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+// A host that uses Atom as a configuration language. A config script is a
+// list of variable declarations whose values are computed when the file is
+// loaded, which gives users arithmetic and comments that plain formats lack.
+use atom::ast::*;
+use atom::parse::*;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Setting {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+fn evaluate(expr: &Expr, settings: &BTreeMap<String, Setting>) -> Result<Setting, String> {
+    let value = match &expr.kind {
+        ExprKind::Null => Setting::Null,
+        ExprKind::Bool(b) => Setting::Bool(*b),
+        ExprKind::Int(n) => Setting::Number(*n as f64),
+        ExprKind::Float(n) => Setting::Number(*n),
+        ExprKind::String(s) => Setting::Text(s.clone()),
+        // Settings can refer to the ones declared before them.
+        ExprKind::Variable(name) => match settings.get(name) {
+            Some(value) => value.clone(),
+            None => return Err(format!("unknown setting {}", name)),
+        },
+        ExprKind::Unary { op, operand } => match (op, evaluate(operand, settings)?) {
+            (UnaryOp::Negate, Setting::Number(n)) => Setting::Number(-n),
+            (UnaryOp::Not, Setting::Bool(b)) => Setting::Bool(!b),
+            _ => return Err(String::from("invalid operand")),
+        },
+        ExprKind::Binary { op, left, right } => {
+            match (op, evaluate(left, settings)?, evaluate(right, settings)?) {
+                (BinaryOp::Add, Setting::Number(a), Setting::Number(b)) => Setting::Number(a + b),
+                (BinaryOp::Add, Setting::Text(a), Setting::Text(b)) => Setting::Text(a + &b),
+                (BinaryOp::Subtract, Setting::Number(a), Setting::Number(b)) => {
+                    Setting::Number(a - b)
+                }
+                (BinaryOp::Multiply, Setting::Number(a), Setting::Number(b)) => {
+                    Setting::Number(a * b)
+                }
+                (BinaryOp::Divide, Setting::Number(a), Setting::Number(b)) => {
+                    Setting::Number(a / b)
+                }
+                (BinaryOp::And, Setting::Bool(a), Setting::Bool(b)) => Setting::Bool(a && b),
+                (BinaryOp::Or, Setting::Bool(a), Setting::Bool(b)) => Setting::Bool(a || b),
+                _ => return Err(String::from("invalid operands")),
+            }
+        }
+        _ => return Err(String::from("settings must be simple values")),
+    };
+
+    Ok(value)
+}
+
+fn load_config(name: &str, source: &str) -> Result<BTreeMap<String, Setting>, String> {
+    let program = parse_program(name, source).map_err(|errors| errors[0].to_string())?;
+
+    let mut settings = BTreeMap::new();
+    for stmt in &program.body {
+        match &stmt.kind {
+            StmtKind::Var {
+                name: var, init, ..
+            } => {
+                let value = match init {
+                    Some(init) => evaluate(init, &settings).map_err(|e| {
+                        format!("{}:{}:{}: {}", name, init.span.line, init.span.column, e)
+                    })?,
+                    None => Setting::Null,
+                };
+                settings.insert(var.name.clone(), value);
+            }
+            _ => {
+                return Err(format!(
+                    "{}:{}:{}: only settings are allowed in a config file",
+                    name, stmt.span.line, stmt.span.column
+                ))
+            }
+        }
+    }
+
+    Ok(settings)
+}
+
+const CONFIG: &str = "
+// Window settings.
+var width = 1280;
+var height = width * 9 / 16;
+var title = 'Atom' + ' Demo';
+var fullscreen = false;
+var vsync = not fullscreen;
+";
+
+fn main() {
+    match load_config("game.cfg", CONFIG) {
+        Ok(settings) => {
+            for (name, value) in settings {
+                println!("{} = {:?}", name, value);
+            }
+        }
+        Err(e) => println!("{}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config() {
+        let settings = load_config("game.cfg", CONFIG).unwrap();
+        assert_eq!(settings["width"], Setting::Number(1280.0));
+        assert_eq!(settings["height"], Setting::Number(720.0));
+        assert_eq!(settings["title"], Setting::Text(String::from("Atom Demo")));
+        assert_eq!(settings["fullscreen"], Setting::Bool(false));
+        assert_eq!(settings["vsync"], Setting::Bool(true));
+    }
+
+    #[test]
+    fn test_bad_config() {
+        assert_eq!(
+            load_config("game.cfg", "var a = 1;\nvar b = c;"),
+            Err(String::from("game.cfg:2:9: unknown setting c"))
+        );
+        assert!(load_config("game.cfg", "launch();").is_err());
+        assert!(load_config("game.cfg", "var a = 'a' * 2;").is_err());
+    }
+}
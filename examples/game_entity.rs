@@ -0,0 +1,126 @@
+// A game host that lets designers script entities. Every class in a script
+// describes an entity and its methods are the event hooks the engine calls.
+// The host validates the hooks when the script is loaded so mistakes show
+// up in the editor instead of in the middle of a play session.
+use atom::ast::*;
+use atom::parse::*;
+use std::collections::BTreeMap;
+
+// Hooks the engine knows how to call and the arguments it passes to them.
+const HOOKS: &[(&str, usize)] = &[("on_spawn", 0), ("on_update", 1), ("on_hit", 2)];
+
+#[derive(Debug, PartialEq)]
+struct EntityType {
+    name: String,
+    base: Option<String>,
+    hooks: Vec<String>,
+}
+
+fn load_entities(name: &str, source: &str) -> Result<BTreeMap<String, EntityType>, Vec<String>> {
+    let program = parse_program(name, source)
+        .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())?;
+
+    let mut entities = BTreeMap::new();
+    let mut problems = Vec::new();
+    for stmt in &program.body {
+        let class = match &stmt.kind {
+            StmtKind::Class(class) => class,
+            _ => continue,
+        };
+
+        let mut hooks = Vec::new();
+        for method in &class.methods {
+            let expected = HOOKS.iter().find(|(hook, _)| *hook == method.name.name);
+            match expected {
+                Some((hook, arity)) if *arity != method.arity() => problems.push(format!(
+                    "{}:{}:{}: hook {} must take {} arguments",
+                    name, method.name.span.line, method.name.span.column, hook, arity
+                )),
+                Some((hook, _)) => hooks.push(String::from(*hook)),
+                // Anything else is a helper method the hooks can call.
+                None => {}
+            }
+        }
+
+        entities.insert(
+            class.name.name.clone(),
+            EntityType {
+                name: class.name.name.clone(),
+                base: class.superclass.as_ref().map(|s| s.name.clone()),
+                hooks,
+            },
+        );
+    }
+
+    if problems.is_empty() {
+        Ok(entities)
+    } else {
+        Err(problems)
+    }
+}
+
+const SCRIPT: &str = "
+class Goblin extends Enemy {
+    init(x, y) { }
+    on_spawn() { play_sound('growl'); }
+    on_update(dt) { wander(dt); }
+    on_hit(damage, source) { flee(source); }
+    wander(dt) { }
+}
+
+class Chest {
+    on_hit(damage, source) { open(); }
+}
+";
+
+fn main() {
+    match load_entities("entities.at", SCRIPT) {
+        Ok(entities) => {
+            for entity in entities.values() {
+                println!(
+                    "{} (base: {}) handles {}",
+                    entity.name,
+                    entity.base.as_ref().map(|b| &b[..]).unwrap_or("none"),
+                    entity.hooks.join(", ")
+                );
+            }
+        }
+        Err(problems) => {
+            for problem in problems {
+                println!("{}", problem);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_entities() {
+        let entities = load_entities("entities.at", SCRIPT).ok().unwrap();
+        assert_eq!(entities.len(), 2);
+
+        let goblin = &entities["Goblin"];
+        assert_eq!(goblin.base, Some(String::from("Enemy")));
+        assert_eq!(goblin.hooks, vec!["on_spawn", "on_update", "on_hit"]);
+        assert_eq!(entities["Chest"].hooks, vec!["on_hit"]);
+    }
+
+    #[test]
+    fn test_bad_hooks() {
+        let problems = load_entities("bad.at", "class Bat {\n  on_update() {}\n}")
+            .err()
+            .unwrap();
+        assert_eq!(
+            problems,
+            vec!["bad.at:2:3: hook on_update must take 1 arguments"]
+        );
+
+        let problems = load_entities("bad.at", "class Bat { on_update(dt) }")
+            .err()
+            .unwrap();
+        assert_eq!(problems.len(), 1);
+    }
+}
@@ -0,0 +1,603 @@
+pub mod dump;
+pub mod index;
+pub mod visit;
+
+use std::collections::HashMap;
+
+// A span records where in the source code a node came from. The offsets
+// are byte offsets into the source while the line and column point at the
+// first character of the node which is what error messages report.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: u32, column: u32) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    pub fn to(&self, other: Span) -> Span {
+        // Joining two spans keeps the start position of the first one so
+        // that errors point at the beginning of the whole construct.
+        Span::new(self.start, other.end, self.line, self.column)
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+// Every node is given an id by the parser which is unique within its
+// program. Analyses record what they learn about a node in a side table
+// keyed by the id rather than in the tree itself, so the tree can be shared
+// and cached while the results come and go.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+    // Nodes built outside of the parser have no id until one is assigned.
+    pub const DUMMY: NodeId = NodeId(u32::MAX);
+
+    pub fn next(self) -> NodeId {
+        NodeId(self.0 + 1)
+    }
+}
+
+impl Default for NodeId {
+    fn default() -> Self {
+        NodeId::DUMMY
+    }
+}
+
+// A side table holding information about nodes, like the declaration a name
+// resolves to or the type of an expression.
+pub type NodeMap<T> = HashMap<NodeId, T>;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Identifier {
+    pub id: NodeId,
+    pub name: String,
+    pub span: Span,
+}
+
+impl Identifier {
+    pub fn new(name: &str, span: Span) -> Self {
+        Self {
+            id: NodeId::DUMMY,
+            name: String::from(name),
+            span,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    BitNot,
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "not",
+            UnaryOp::BitNot => "~",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitAnd,
+    BitOr,
+    BitXor,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Expr {
+    pub id: NodeId,
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self {
+            id: NodeId::DUMMY,
+            kind,
+            span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ExprKind {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Null,
+    This,
+    // Only method lookups are allowed on super, so the method name is
+    // stored right in the node.
+    Super(Identifier),
+    Variable(String),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    // Plain assignment has no operator while compound assignment like
+    // `x += 1` keeps the operator that is applied before storing.
+    Assign {
+        op: Option<BinaryOp>,
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    // An inline `condition ? then : otherwise`.
+    Conditional {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    // The numbers from start up to end, which is left out unless the range
+    // is inclusive as in `1..=10`.
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+    },
+    // Boxed for the same reason as lambdas below.
+    Call(Box<Call>),
+    Member {
+        object: Box<Expr>,
+        name: Identifier,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    // Either end can be left off, as in `items[1:]`.
+    Slice {
+        object: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+    // Storing the parts of a list or map into several targets at once, as in
+    // `[a, b] = [b, a]`.
+    Destructure {
+        pattern: Box<Pattern>,
+        value: Box<Expr>,
+    },
+    InterpolatedString(Vec<StringPart>),
+    ListLiteral(Vec<Expr>),
+    MapLiteral(Vec<MapEntry>),
+    // Suspends the coroutine that is running, handing it the value, or null
+    // when there is none.
+    Yield(Option<Box<Expr>>),
+    // Boxed since a lambda is much larger than any other expression, and
+    // every expression would otherwise be as large as a lambda.
+    Lambda(Box<Lambda>),
+}
+
+// Formatted strings are split into the literal text and the expressions
+// that are spliced in between that text.
+#[derive(Clone, PartialEq, Debug)]
+pub enum StringPart {
+    Literal(String),
+    Expr(Expr),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum MapKey {
+    // A bare name like `{ health: 10 }` which is the same as a string key.
+    Identifier(Identifier),
+    String(String),
+    // A key that is computed when the map is built, like `{ [id]: player }`.
+    Computed(Expr),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MapEntry {
+    pub key: MapKey,
+    pub value: Expr,
+    pub span: Span,
+}
+
+// Patterns pull values out of lists and maps, like `[first, second]` or
+// `{ x, y: top }`, and bind them to names or store them into targets.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pattern {
+    pub id: NodeId,
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, span: Span) -> Self {
+        Self {
+            id: NodeId::DUMMY,
+            kind,
+            span,
+        }
+    }
+
+    pub fn names(&self) -> Vec<&Identifier> {
+        // Every name the pattern binds, from left to right.
+        let mut names = Vec::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names<'a>(&'a self, names: &mut Vec<&'a Identifier>) {
+        match &self.kind {
+            PatternKind::Name(name) => names.push(name),
+            PatternKind::Target(_) => {}
+            PatternKind::List(items) => {
+                for item in items {
+                    item.collect_names(names);
+                }
+            }
+            PatternKind::Map(entries) => {
+                for entry in entries {
+                    entry.pattern.collect_names(names);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum PatternKind {
+    Name(Identifier),
+    // Assignments can also store into members and indexes, as in
+    // `[hero.x, hero.y] = position`.
+    Target(Expr),
+    List(Vec<Pattern>),
+    Map(Vec<MapPatternEntry>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MapPatternEntry {
+    pub key: MapKey,
+    pub pattern: Pattern,
+    pub span: Span,
+}
+
+// A type written in the source, like `number` or `map<string, list<number>>`.
+// Types make no difference when a script runs, they are only looked at by
+// the type checker.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TypeAnnotation {
+    pub name: Identifier,
+    pub args: Vec<TypeAnnotation>,
+    pub span: Span,
+}
+
+// Named arguments, like `spawn(x: 10)`, always come after the positional
+// ones, so the names line up with the last arguments.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Call {
+    pub callee: Box<Expr>,
+    pub args: Vec<Expr>,
+    pub names: Vec<Identifier>,
+    pub span: Span,
+}
+
+impl Call {
+    pub fn arity(&self) -> usize {
+        self.args.len()
+    }
+
+    pub fn positional(&self) -> &[Expr] {
+        &self.args[..self.args.len() - self.names.len()]
+    }
+
+    pub fn named(&self) -> impl Iterator<Item = (&Identifier, &Expr)> {
+        let positional = self.args.len() - self.names.len();
+        self.names.iter().zip(&self.args[positional..])
+    }
+}
+
+// Parameters with a default value always come after the required ones, so
+// the defaults line up with the last parameters. The rest parameter collects
+// any extra arguments into a list.
+//
+// Decorators are written before a declaration, as in `@memoize`, and wrap
+// what it declares. They are worked out from top to bottom before anything
+// is declared and then applied from the bottom up, so `@a @b function f`
+// makes f `a(b(f))`. The same goes for classes and methods.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FunctionDecl {
+    pub id: NodeId,
+    pub decorators: Vec<Expr>,
+    pub name: Identifier,
+    pub params: Vec<Identifier>,
+    // One entry for every parameter, holding its type if it was given one.
+    pub param_types: Vec<Option<TypeAnnotation>>,
+    pub defaults: Vec<Expr>,
+    pub rest: Option<Identifier>,
+    pub return_type: Option<TypeAnnotation>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+impl FunctionDecl {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn min_arity(&self) -> usize {
+        self.params.len() - self.defaults.len()
+    }
+
+    // There is no upper limit on the number of arguments when the function
+    // has a rest parameter.
+    pub fn max_arity(&self) -> Option<usize> {
+        match self.rest {
+            Some(_) => None,
+            None => Some(self.params.len()),
+        }
+    }
+}
+
+// An anonymous function written as an expression, so that it can be passed
+// straight to another function as a callback.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Lambda {
+    pub params: Vec<Identifier>,
+    pub param_types: Vec<Option<TypeAnnotation>>,
+    pub defaults: Vec<Expr>,
+    pub rest: Option<Identifier>,
+    pub return_type: Option<TypeAnnotation>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+impl Lambda {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn min_arity(&self) -> usize {
+        self.params.len() - self.defaults.len()
+    }
+
+    pub fn max_arity(&self) -> Option<usize> {
+        match self.rest {
+            Some(_) => None,
+            None => Some(self.params.len()),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ClassDecl {
+    pub id: NodeId,
+    pub decorators: Vec<Expr>,
+    pub name: Identifier,
+    pub superclass: Option<Identifier>,
+    pub initializer: Option<FunctionDecl>,
+    pub methods: Vec<FunctionDecl>,
+    // Accessors look like fields from the outside, as in `get area() {}`
+    // which runs when `shape.area` is read. A property can have a getter, a
+    // setter taking the new value, or both.
+    pub getters: Vec<FunctionDecl>,
+    pub setters: Vec<FunctionDecl>,
+    pub span: Span,
+}
+
+// One arm of a match statement. The values are literals compared against
+// the subject, and the default arm is the one without any values.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MatchArm {
+    pub values: Vec<Expr>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+impl MatchArm {
+    pub fn is_default(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+// The handler of a try statement. The binding is optional for handlers which
+// do not care about what was thrown.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CatchClause {
+    pub binding: Option<Identifier>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ImportSource {
+    // A module found by name, as in `import math;`.
+    Module(Identifier),
+    // A module found by its path, as in `import "utils.at" as u;`.
+    File(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImportDecl {
+    pub source: ImportSource,
+    // The name the module is known by in the importing file. When there is
+    // no `as` this comes from the module name or the name of the file.
+    pub alias: Identifier,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Stmt {
+    pub id: NodeId,
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self {
+            id: NodeId::DUMMY,
+            kind,
+            span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum StmtKind {
+    Expression(Expr),
+    // Declared with `const` instead of `var` when constant is set, in which
+    // case the name can never be assigned to again.
+    Var {
+        name: Identifier,
+        ty: Option<TypeAnnotation>,
+        init: Option<Expr>,
+        constant: bool,
+    },
+    // A declaration like `var [a, b] = pair;` which always needs a value to
+    // take apart.
+    Destructure {
+        pattern: Pattern,
+        init: Expr,
+        constant: bool,
+    },
+    // Assigning to several targets at once, as in `a, b = b, a;`. All of the
+    // values are worked out before anything is stored. A single value, like
+    // in `x, y = position();`, is unpacked into the targets instead.
+    MultipleAssign {
+        targets: Vec<Pattern>,
+        values: Vec<Expr>,
+    },
+    Block(Vec<Stmt>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    // Loops can be given a label, as in `outer: while`, so that a break or
+    // continue in a nested loop can name the loop it applies to.
+    While {
+        label: Option<Identifier>,
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+    // The body of a do-while loop always runs once before the condition is
+    // checked for the first time.
+    DoWhile {
+        label: Option<Identifier>,
+        body: Box<Stmt>,
+        condition: Expr,
+    },
+    For {
+        label: Option<Identifier>,
+        variable: Identifier,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+    Match {
+        subject: Expr,
+        arms: Vec<MatchArm>,
+    },
+    Try {
+        body: Vec<Stmt>,
+        catch: Option<CatchClause>,
+        finally: Option<Vec<Stmt>>,
+    },
+    // Any value can be thrown, and runtime errors are caught as their
+    // message.
+    Throw(Expr),
+    Return(Option<Expr>),
+    Break(Option<Identifier>),
+    Continue(Option<Identifier>),
+    // Declarations are boxed to keep every other statement small, since the
+    // parser holds several statements on the stack at each level of nesting.
+    Function(Box<FunctionDecl>),
+    Class(Box<ClassDecl>),
+    // Imports and exports are only allowed at the top level of a module.
+    Import(ImportDecl),
+    Export(Box<Stmt>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Program {
+    pub name: String,
+    pub body: Vec<Stmt>,
+    // The id the next node added to the program will get. Reparsing after
+    // an edit carries on from here so that ids are never reused.
+    pub next_id: NodeId,
+}
+
+impl Program {
+    // The names the program exports, in the order they are declared.
+    pub fn exports(&self) -> Vec<&Identifier> {
+        let mut names = Vec::new();
+        for stmt in &self.body {
+            if let StmtKind::Export(decl) = &stmt.kind {
+                match &decl.kind {
+                    StmtKind::Var { name, .. } => names.push(name),
+                    StmtKind::Destructure { pattern, .. } => names.extend(pattern.names()),
+                    StmtKind::Function(decl) => names.push(&decl.name),
+                    StmtKind::Class(decl) => names.push(&decl.name),
+                    _ => {}
+                }
+            }
+        }
+        names
+    }
+}
@@ -0,0 +1,957 @@
+use crate::ast::*;
+use crate::bytecode::format_number;
+
+// Dumping turns a tree into text that shows its structure. S-expressions
+// are compact and easy to read when checking precedence, for example
+// `1 + 2 * 3` dumps as `(+ 1 (* 2 3))`. JSON keeps every detail, including
+// spans, for tools that want to consume the tree.
+pub trait Dump {
+    fn to_sexpr(&self) -> String;
+    fn to_json(&self) -> String;
+}
+
+impl Dump for Program {
+    fn to_sexpr(&self) -> String {
+        // One top level statement per line keeps larger dumps readable.
+        self.body
+            .iter()
+            .map(|s| s.to_sexpr())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"node\":\"program\",\"name\":{},\"body\":{}}}",
+            json_string(&self.name[..]),
+            json_list(self.body.iter().map(|s| s.to_json()))
+        )
+    }
+}
+
+impl Dump for Stmt {
+    fn to_sexpr(&self) -> String {
+        match &self.kind {
+            // Expression statements are dumped as just the expression since
+            // that is by far the most common statement.
+            StmtKind::Expression(expr) => expr.to_sexpr(),
+            StmtKind::Var {
+                name,
+                ty,
+                init,
+                constant,
+            } => {
+                let name = sexpr_typed(name, ty);
+                match init {
+                    Some(init) => format!(
+                        "({} {} {})",
+                        declaration_keyword(*constant),
+                        name,
+                        init.to_sexpr()
+                    ),
+                    None => format!("({} {})", declaration_keyword(*constant), name),
+                }
+            }
+            StmtKind::Destructure {
+                pattern,
+                init,
+                constant,
+            } => format!(
+                "({} {} {})",
+                declaration_keyword(*constant),
+                pattern.to_sexpr(),
+                init.to_sexpr()
+            ),
+            StmtKind::MultipleAssign { targets, values } => format!(
+                "(= ({}) ({}))",
+                targets
+                    .iter()
+                    .map(|t| t.to_sexpr())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                values
+                    .iter()
+                    .map(|v| v.to_sexpr())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            StmtKind::Block(body) => sexpr_list("block", body.iter().map(|s| s.to_sexpr())),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => match else_branch {
+                Some(else_branch) => format!(
+                    "(if {} {} {})",
+                    condition.to_sexpr(),
+                    then_branch.to_sexpr(),
+                    else_branch.to_sexpr()
+                ),
+                None => format!("(if {} {})", condition.to_sexpr(), then_branch.to_sexpr()),
+            },
+            StmtKind::While {
+                label,
+                condition,
+                body,
+            } => sexpr_label(
+                label,
+                format!("(while {} {})", condition.to_sexpr(), body.to_sexpr()),
+            ),
+            StmtKind::DoWhile {
+                label,
+                body,
+                condition,
+            } => sexpr_label(
+                label,
+                format!("(do {} {})", body.to_sexpr(), condition.to_sexpr()),
+            ),
+            StmtKind::For {
+                label,
+                variable,
+                iterable,
+                body,
+            } => sexpr_label(
+                label,
+                format!(
+                    "(for {} {} {})",
+                    variable.name,
+                    iterable.to_sexpr(),
+                    body.to_sexpr()
+                ),
+            ),
+            StmtKind::Match { subject, arms } => sexpr_list(
+                &format!("match {}", subject.to_sexpr())[..],
+                arms.iter().map(|arm| {
+                    let head = if arm.is_default() {
+                        String::from("default")
+                    } else {
+                        sexpr_list("case", arm.values.iter().map(|v| v.to_sexpr()))
+                    };
+                    sexpr_list(&head[..], arm.body.iter().map(|s| s.to_sexpr()))
+                }),
+            ),
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                let mut parts = vec![sexpr_block(body)];
+                if let Some(catch) = catch {
+                    parts.push(match &catch.binding {
+                        Some(binding) => {
+                            format!("(catch {} {})", binding.name, sexpr_block(&catch.body))
+                        }
+                        None => format!("(catch {})", sexpr_block(&catch.body)),
+                    });
+                }
+                if let Some(finally) = finally {
+                    parts.push(format!("(finally {})", sexpr_block(finally)));
+                }
+                sexpr_list("try", parts.into_iter())
+            }
+            StmtKind::Throw(value) => format!("(throw {})", value.to_sexpr()),
+            StmtKind::Return(value) => match value {
+                Some(value) => format!("(return {})", value.to_sexpr()),
+                None => String::from("(return)"),
+            },
+            StmtKind::Break(label) => sexpr_jump("break", label),
+            StmtKind::Continue(label) => sexpr_jump("continue", label),
+            StmtKind::Function(decl) => decl.to_sexpr(),
+            StmtKind::Class(decl) => decl.to_sexpr(),
+            StmtKind::Import(decl) => match &decl.source {
+                ImportSource::Module(name) => format!("(import {} {})", name.name, decl.alias.name),
+                ImportSource::File(path) => format!("(import {:?} {})", path, decl.alias.name),
+            },
+            StmtKind::Export(stmt) => format!("(export {})", stmt.to_sexpr()),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let (kind, fields) = match &self.kind {
+            StmtKind::Expression(expr) => ("expression", vec![("expr", expr.to_json())]),
+            StmtKind::Var {
+                name,
+                ty,
+                init,
+                constant,
+            } => (
+                "var",
+                vec![
+                    ("name", json_identifier(name)),
+                    ("type", json_option(ty.as_ref().map(json_type))),
+                    ("init", json_option(init.as_ref().map(|i| i.to_json()))),
+                    ("constant", format!("{}", constant)),
+                ],
+            ),
+            StmtKind::Destructure {
+                pattern,
+                init,
+                constant,
+            } => (
+                "destructure",
+                vec![
+                    ("pattern", pattern.to_json()),
+                    ("init", init.to_json()),
+                    ("constant", format!("{}", constant)),
+                ],
+            ),
+            StmtKind::MultipleAssign { targets, values } => (
+                "multiple_assign",
+                vec![
+                    ("targets", json_list(targets.iter().map(|t| t.to_json()))),
+                    ("values", json_list(values.iter().map(|v| v.to_json()))),
+                ],
+            ),
+            StmtKind::Block(body) => (
+                "block",
+                vec![("body", json_list(body.iter().map(|s| s.to_json())))],
+            ),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => (
+                "if",
+                vec![
+                    ("condition", condition.to_json()),
+                    ("then", then_branch.to_json()),
+                    (
+                        "else",
+                        json_option(else_branch.as_ref().map(|e| e.to_json())),
+                    ),
+                ],
+            ),
+            StmtKind::While {
+                label,
+                condition,
+                body,
+            } => (
+                "while",
+                vec![
+                    ("label", json_option(label.as_ref().map(json_identifier))),
+                    ("condition", condition.to_json()),
+                    ("body", body.to_json()),
+                ],
+            ),
+            StmtKind::DoWhile {
+                label,
+                body,
+                condition,
+            } => (
+                "do_while",
+                vec![
+                    ("label", json_option(label.as_ref().map(json_identifier))),
+                    ("body", body.to_json()),
+                    ("condition", condition.to_json()),
+                ],
+            ),
+            StmtKind::For {
+                label,
+                variable,
+                iterable,
+                body,
+            } => (
+                "for",
+                vec![
+                    ("label", json_option(label.as_ref().map(json_identifier))),
+                    ("variable", json_identifier(variable)),
+                    ("iterable", iterable.to_json()),
+                    ("body", body.to_json()),
+                ],
+            ),
+            StmtKind::Match { subject, arms } => (
+                "match",
+                vec![
+                    ("subject", subject.to_json()),
+                    (
+                        "arms",
+                        json_list(arms.iter().map(|arm| {
+                            format!(
+                                "{{\"values\":{},\"body\":{},\"span\":{}}}",
+                                json_list(arm.values.iter().map(|v| v.to_json())),
+                                json_list(arm.body.iter().map(|s| s.to_json())),
+                                json_span(arm.span)
+                            )
+                        })),
+                    ),
+                ],
+            ),
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => (
+                "try",
+                vec![
+                    ("body", json_list(body.iter().map(|s| s.to_json()))),
+                    (
+                        "catch",
+                        json_option(catch.as_ref().map(|catch| {
+                            format!(
+                                "{{\"binding\":{},\"body\":{},\"span\":{}}}",
+                                json_option(catch.binding.as_ref().map(json_identifier)),
+                                json_list(catch.body.iter().map(|s| s.to_json())),
+                                json_span(catch.span)
+                            )
+                        })),
+                    ),
+                    (
+                        "finally",
+                        json_option(
+                            finally
+                                .as_ref()
+                                .map(|f| json_list(f.iter().map(|s| s.to_json()))),
+                        ),
+                    ),
+                ],
+            ),
+            StmtKind::Throw(value) => ("throw", vec![("value", value.to_json())]),
+            StmtKind::Return(value) => (
+                "return",
+                vec![("value", json_option(value.as_ref().map(|v| v.to_json())))],
+            ),
+            StmtKind::Break(label) => (
+                "break",
+                vec![("label", json_option(label.as_ref().map(json_identifier)))],
+            ),
+            StmtKind::Continue(label) => (
+                "continue",
+                vec![("label", json_option(label.as_ref().map(json_identifier)))],
+            ),
+            StmtKind::Function(decl) => return decl.to_json(),
+            StmtKind::Class(decl) => return decl.to_json(),
+            StmtKind::Import(decl) => (
+                "import",
+                vec![
+                    (
+                        "source",
+                        match &decl.source {
+                            ImportSource::Module(name) => format!(
+                                "{{\"kind\":\"module\",\"name\":{}}}",
+                                json_identifier(name)
+                            ),
+                            ImportSource::File(path) => {
+                                format!("{{\"kind\":\"file\",\"path\":{}}}", json_string(&path[..]))
+                            }
+                        },
+                    ),
+                    ("alias", json_identifier(&decl.alias)),
+                ],
+            ),
+            StmtKind::Export(stmt) => ("export", vec![("declaration", stmt.to_json())]),
+        };
+
+        json_node(kind, self.span, fields)
+    }
+}
+
+impl Dump for FunctionDecl {
+    fn to_sexpr(&self) -> String {
+        let head = format!(
+            "function {} {}",
+            self.name.name,
+            sexpr_signature(
+                &self.params[..],
+                &self.param_types[..],
+                &self.defaults[..],
+                &self.rest,
+                &self.return_type
+            )
+        );
+        let decl = sexpr_list(&head[..], self.body.iter().map(|s| s.to_sexpr()));
+        sexpr_decorated(&self.decorators[..], decl)
+    }
+
+    fn to_json(&self) -> String {
+        json_node(
+            "function",
+            self.span,
+            vec![
+                (
+                    "decorators",
+                    json_list(self.decorators.iter().map(|d| d.to_json())),
+                ),
+                ("name", json_identifier(&self.name)),
+                ("params", json_list(self.params.iter().map(json_identifier))),
+                ("param_types", json_types(&self.param_types[..])),
+                (
+                    "defaults",
+                    json_list(self.defaults.iter().map(|d| d.to_json())),
+                ),
+                ("rest", json_option(self.rest.as_ref().map(json_identifier))),
+                (
+                    "return_type",
+                    json_option(self.return_type.as_ref().map(json_type)),
+                ),
+                ("body", json_list(self.body.iter().map(|s| s.to_json()))),
+            ],
+        )
+    }
+}
+
+impl Dump for ClassDecl {
+    fn to_sexpr(&self) -> String {
+        let head = match &self.superclass {
+            Some(superclass) => format!("class {} {}", self.name.name, superclass.name),
+            None => format!("class {}", self.name.name),
+        };
+        let decl = sexpr_list(
+            &head[..],
+            self.initializer
+                .iter()
+                .chain(self.methods.iter())
+                .map(|m| m.to_sexpr())
+                .chain(
+                    self.getters
+                        .iter()
+                        .map(|g| format!("(get {})", g.to_sexpr())),
+                )
+                .chain(
+                    self.setters
+                        .iter()
+                        .map(|s| format!("(set {})", s.to_sexpr())),
+                ),
+        );
+        sexpr_decorated(&self.decorators[..], decl)
+    }
+
+    fn to_json(&self) -> String {
+        json_node(
+            "class",
+            self.span,
+            vec![
+                (
+                    "decorators",
+                    json_list(self.decorators.iter().map(|d| d.to_json())),
+                ),
+                ("name", json_identifier(&self.name)),
+                (
+                    "superclass",
+                    json_option(self.superclass.as_ref().map(json_identifier)),
+                ),
+                (
+                    "initializer",
+                    json_option(self.initializer.as_ref().map(|i| i.to_json())),
+                ),
+                (
+                    "methods",
+                    json_list(self.methods.iter().map(|m| m.to_json())),
+                ),
+                (
+                    "getters",
+                    json_list(self.getters.iter().map(|g| g.to_json())),
+                ),
+                (
+                    "setters",
+                    json_list(self.setters.iter().map(|s| s.to_json())),
+                ),
+            ],
+        )
+    }
+}
+
+impl Dump for Expr {
+    fn to_sexpr(&self) -> String {
+        match &self.kind {
+            ExprKind::Int(n) => format!("{}", n),
+            ExprKind::Float(n) => format_number(*n),
+            ExprKind::String(s) => format!("{:?}", s),
+            ExprKind::Bool(b) => format!("{}", b),
+            ExprKind::Null => String::from("null"),
+            ExprKind::This => String::from("this"),
+            ExprKind::Super(method) => format!("(super {})", method.name),
+            ExprKind::Variable(name) => name.clone(),
+            ExprKind::Unary { op, operand } => format!("({} {})", op.symbol(), operand.to_sexpr()),
+            ExprKind::Binary { op, left, right } => {
+                format!("({} {} {})", op.symbol(), left.to_sexpr(), right.to_sexpr())
+            }
+            ExprKind::Assign { op, target, value } => format!(
+                "({}= {} {})",
+                op.map(|op| op.symbol()).unwrap_or(""),
+                target.to_sexpr(),
+                value.to_sexpr()
+            ),
+            ExprKind::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => format!(
+                "(? {} {} {})",
+                condition.to_sexpr(),
+                then_branch.to_sexpr(),
+                else_branch.to_sexpr()
+            ),
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => format!(
+                "({} {} {})",
+                if *inclusive { "..=" } else { ".." },
+                start.to_sexpr(),
+                end.to_sexpr()
+            ),
+            // Named arguments are written as `(x: 10)`.
+            ExprKind::Call(call) => sexpr_list(
+                "call",
+                std::iter::once(&*call.callee)
+                    .chain(call.positional())
+                    .map(|e| e.to_sexpr())
+                    .chain(
+                        call.named()
+                            .map(|(name, arg)| format!("({}: {})", name.name, arg.to_sexpr())),
+                    ),
+            ),
+            ExprKind::Member { object, name } => format!("(. {} {})", object.to_sexpr(), name.name),
+            ExprKind::Index { object, index } => {
+                format!("(index {} {})", object.to_sexpr(), index.to_sexpr())
+            }
+            // A missing end of a slice is written as an underscore so that
+            // `a[:n]` and `a[n:]` can be told apart.
+            ExprKind::Slice { object, start, end } => format!(
+                "(slice {} {} {})",
+                object.to_sexpr(),
+                start
+                    .as_ref()
+                    .map(|s| s.to_sexpr())
+                    .unwrap_or_else(|| String::from("_")),
+                end.as_ref()
+                    .map(|e| e.to_sexpr())
+                    .unwrap_or_else(|| String::from("_"))
+            ),
+            ExprKind::Destructure { pattern, value } => {
+                format!("(= {} {})", pattern.to_sexpr(), value.to_sexpr())
+            }
+            ExprKind::InterpolatedString(parts) => sexpr_list(
+                "format",
+                parts.iter().map(|p| match p {
+                    StringPart::Literal(s) => format!("{:?}", s),
+                    StringPart::Expr(e) => e.to_sexpr(),
+                }),
+            ),
+            ExprKind::ListLiteral(items) => sexpr_list("list", items.iter().map(|i| i.to_sexpr())),
+            ExprKind::MapLiteral(entries) => sexpr_list(
+                "map",
+                entries
+                    .iter()
+                    .map(|entry| format!("({} {})", sexpr_key(&entry.key), entry.value.to_sexpr())),
+            ),
+            ExprKind::Lambda(lambda) => {
+                let head = format!(
+                    "lambda {}",
+                    sexpr_signature(
+                        &lambda.params[..],
+                        &lambda.param_types[..],
+                        &lambda.defaults[..],
+                        &lambda.rest,
+                        &lambda.return_type
+                    )
+                );
+                sexpr_list(&head[..], lambda.body.iter().map(|s| s.to_sexpr()))
+            }
+            ExprKind::Yield(value) => match value {
+                Some(value) => format!("(yield {})", value.to_sexpr()),
+                None => String::from("(yield)"),
+            },
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let (kind, fields) = match &self.kind {
+            ExprKind::Int(n) => ("int", vec![("value", format!("{}", n))]),
+            ExprKind::Float(n) => ("float", vec![("value", json_float(*n))]),
+            ExprKind::String(s) => ("string", vec![("value", json_string(&s[..]))]),
+            ExprKind::Bool(b) => ("bool", vec![("value", format!("{}", b))]),
+            ExprKind::Null => ("null", vec![]),
+            ExprKind::This => ("this", vec![]),
+            ExprKind::Super(method) => ("super", vec![("method", json_identifier(method))]),
+            ExprKind::Variable(name) => ("variable", vec![("name", json_string(&name[..]))]),
+            ExprKind::Unary { op, operand } => (
+                "unary",
+                vec![
+                    ("op", json_string(op.symbol())),
+                    ("operand", operand.to_json()),
+                ],
+            ),
+            ExprKind::Binary { op, left, right } => (
+                "binary",
+                vec![
+                    ("op", json_string(op.symbol())),
+                    ("left", left.to_json()),
+                    ("right", right.to_json()),
+                ],
+            ),
+            ExprKind::Assign { op, target, value } => (
+                "assign",
+                vec![
+                    ("op", json_option(op.map(|op| json_string(op.symbol())))),
+                    ("target", target.to_json()),
+                    ("value", value.to_json()),
+                ],
+            ),
+            ExprKind::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => (
+                "conditional",
+                vec![
+                    ("condition", condition.to_json()),
+                    ("then", then_branch.to_json()),
+                    ("else", else_branch.to_json()),
+                ],
+            ),
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => (
+                "range",
+                vec![
+                    ("start", start.to_json()),
+                    ("end", end.to_json()),
+                    ("inclusive", format!("{}", inclusive)),
+                ],
+            ),
+            ExprKind::Call(call) => (
+                "call",
+                vec![
+                    ("callee", call.callee.to_json()),
+                    ("args", json_list(call.args.iter().map(|a| a.to_json()))),
+                    ("names", json_list(call.names.iter().map(json_identifier))),
+                ],
+            ),
+            ExprKind::Member { object, name } => (
+                "member",
+                vec![
+                    ("object", object.to_json()),
+                    ("name", json_identifier(name)),
+                ],
+            ),
+            ExprKind::Index { object, index } => (
+                "index",
+                vec![("object", object.to_json()), ("index", index.to_json())],
+            ),
+            ExprKind::Slice { object, start, end } => (
+                "slice",
+                vec![
+                    ("object", object.to_json()),
+                    ("start", json_option(start.as_ref().map(|s| s.to_json()))),
+                    ("end", json_option(end.as_ref().map(|e| e.to_json()))),
+                ],
+            ),
+            ExprKind::Destructure { pattern, value } => (
+                "destructure",
+                vec![("pattern", pattern.to_json()), ("value", value.to_json())],
+            ),
+            ExprKind::InterpolatedString(parts) => (
+                "interpolated_string",
+                vec![(
+                    "parts",
+                    json_list(parts.iter().map(|p| match p {
+                        StringPart::Literal(s) => json_string(&s[..]),
+                        StringPart::Expr(e) => e.to_json(),
+                    })),
+                )],
+            ),
+            ExprKind::ListLiteral(items) => (
+                "list",
+                vec![("items", json_list(items.iter().map(|i| i.to_json())))],
+            ),
+            ExprKind::MapLiteral(entries) => (
+                "map",
+                vec![(
+                    "entries",
+                    json_list(entries.iter().map(|entry| {
+                        let key = json_key(&entry.key);
+                        format!(
+                            "{{\"key\":{},\"value\":{},\"span\":{}}}",
+                            key,
+                            entry.value.to_json(),
+                            json_span(entry.span)
+                        )
+                    })),
+                )],
+            ),
+            ExprKind::Lambda(lambda) => (
+                "lambda",
+                vec![
+                    (
+                        "params",
+                        json_list(lambda.params.iter().map(json_identifier)),
+                    ),
+                    ("param_types", json_types(&lambda.param_types[..])),
+                    (
+                        "defaults",
+                        json_list(lambda.defaults.iter().map(|d| d.to_json())),
+                    ),
+                    (
+                        "rest",
+                        json_option(lambda.rest.as_ref().map(json_identifier)),
+                    ),
+                    (
+                        "return_type",
+                        json_option(lambda.return_type.as_ref().map(json_type)),
+                    ),
+                    ("body", json_list(lambda.body.iter().map(|s| s.to_json()))),
+                ],
+            ),
+            ExprKind::Yield(value) => (
+                "yield",
+                vec![("value", json_option(value.as_ref().map(|v| v.to_json())))],
+            ),
+        };
+
+        json_node(kind, self.span, fields)
+    }
+}
+
+impl Dump for Pattern {
+    fn to_sexpr(&self) -> String {
+        // Patterns are written the way they look in the source, so `[a b]`
+        // for a list and `{x y:top}` for a map where `x` is short for `x:x`.
+        match &self.kind {
+            PatternKind::Name(name) => name.name.clone(),
+            PatternKind::Target(target) => target.to_sexpr(),
+            PatternKind::List(items) => format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(|i| i.to_sexpr())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            PatternKind::Map(entries) => format!(
+                "{{{}}}",
+                entries
+                    .iter()
+                    .map(|entry| match (&entry.key, &entry.pattern.kind) {
+                        (MapKey::Identifier(key), PatternKind::Name(name))
+                            if key.name == name.name =>
+                        {
+                            name.name.clone()
+                        }
+                        (key, _) => format!("{}:{}", sexpr_key(key), entry.pattern.to_sexpr()),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let (kind, fields) = match &self.kind {
+            PatternKind::Name(name) => ("name_pattern", vec![("name", json_identifier(name))]),
+            PatternKind::Target(target) => ("target_pattern", vec![("target", target.to_json())]),
+            PatternKind::List(items) => (
+                "list_pattern",
+                vec![("items", json_list(items.iter().map(|i| i.to_json())))],
+            ),
+            PatternKind::Map(entries) => (
+                "map_pattern",
+                vec![(
+                    "entries",
+                    json_list(entries.iter().map(|entry| {
+                        format!(
+                            "{{\"key\":{},\"pattern\":{},\"span\":{}}}",
+                            json_key(&entry.key),
+                            entry.pattern.to_json(),
+                            json_span(entry.span)
+                        )
+                    })),
+                )],
+            ),
+        };
+
+        json_node(kind, self.span, fields)
+    }
+}
+
+fn sexpr_key(key: &MapKey) -> String {
+    match key {
+        MapKey::Identifier(ident) => ident.name.clone(),
+        MapKey::String(s) => format!("{:?}", s),
+        MapKey::Computed(e) => format!("[{}]", e.to_sexpr()),
+    }
+}
+
+fn sexpr_list<I: Iterator<Item = String>>(head: &str, items: I) -> String {
+    let mut out = format!("({}", head);
+    for item in items {
+        out.push(' ');
+        out.push_str(&item[..]);
+    }
+    out.push(')');
+    out
+}
+
+// Decorators wrap the declaration in the order they are applied, so
+// `@a @b function f() {}` is `(@ a (@ b (function f ())))`.
+fn sexpr_decorated(decorators: &[Expr], decl: String) -> String {
+    decorators.iter().rev().fold(decl, |decl, decorator| {
+        format!("(@ {} {})", decorator.to_sexpr(), decl)
+    })
+}
+
+fn sexpr_block(body: &[Stmt]) -> String {
+    sexpr_list("block", body.iter().map(|s| s.to_sexpr()))
+}
+
+fn sexpr_label(label: &Option<Identifier>, stmt: String) -> String {
+    match label {
+        Some(label) => format!("(label {} {})", label.name, stmt),
+        None => stmt,
+    }
+}
+
+fn sexpr_jump(head: &str, label: &Option<Identifier>) -> String {
+    match label {
+        Some(label) => format!("({} {})", head, label.name),
+        None => format!("({})", head),
+    }
+}
+
+fn declaration_keyword(constant: bool) -> &'static str {
+    if constant {
+        "const"
+    } else {
+        "var"
+    }
+}
+
+// Types are written right after what they describe with no space, as in
+// `(a:number b):list<string>`, so each part stays a single atom.
+fn sexpr_signature(
+    params: &[Identifier],
+    types: &[Option<TypeAnnotation>],
+    defaults: &[Expr],
+    rest: &Option<Identifier>,
+    return_type: &Option<TypeAnnotation>,
+) -> String {
+    let required = params.len() - defaults.len();
+    let mut out: Vec<String> = params[..required]
+        .iter()
+        .zip(types)
+        .map(|(p, ty)| sexpr_typed(p, ty))
+        .collect();
+    for ((param, ty), default) in params[required..]
+        .iter()
+        .zip(&types[required..])
+        .zip(defaults)
+    {
+        out.push(format!(
+            "(= {} {})",
+            sexpr_typed(param, ty),
+            default.to_sexpr()
+        ));
+    }
+    if let Some(rest) = rest {
+        out.push(format!("...{}", rest.name));
+    }
+    match return_type {
+        Some(ty) => format!("({}):{}", out.join(" "), sexpr_type(ty)),
+        None => format!("({})", out.join(" ")),
+    }
+}
+
+fn sexpr_typed(name: &Identifier, ty: &Option<TypeAnnotation>) -> String {
+    match ty {
+        Some(ty) => format!("{}:{}", name.name, sexpr_type(ty)),
+        None => name.name.clone(),
+    }
+}
+
+fn sexpr_type(ty: &TypeAnnotation) -> String {
+    if ty.args.is_empty() {
+        return ty.name.name.clone();
+    }
+    let args: Vec<String> = ty.args.iter().map(sexpr_type).collect();
+    format!("{}<{}>", ty.name.name, args.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)[..]),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// JSON has no infinities or NaN, so they are written as the strings that
+// format_number shows them as.
+fn json_float(n: f64) -> String {
+    if n.is_finite() {
+        format!("{}", n)
+    } else {
+        json_string(&format_number(n)[..])
+    }
+}
+
+fn json_list<I: Iterator<Item = String>>(items: I) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+fn json_option(value: Option<String>) -> String {
+    value.unwrap_or_else(|| String::from("null"))
+}
+
+fn json_span(span: Span) -> String {
+    format!(
+        "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{}}}",
+        span.start, span.end, span.line, span.column
+    )
+}
+
+fn json_key(key: &MapKey) -> String {
+    match key {
+        MapKey::Identifier(ident) => format!(
+            "{{\"kind\":\"identifier\",\"name\":{}}}",
+            json_identifier(ident)
+        ),
+        MapKey::String(s) => format!("{{\"kind\":\"string\",\"value\":{}}}", json_string(&s[..])),
+        MapKey::Computed(e) => format!("{{\"kind\":\"computed\",\"expr\":{}}}", e.to_json()),
+    }
+}
+
+fn json_identifier(ident: &Identifier) -> String {
+    format!(
+        "{{\"name\":{},\"span\":{}}}",
+        json_string(&ident.name[..]),
+        json_span(ident.span)
+    )
+}
+
+fn json_type(ty: &TypeAnnotation) -> String {
+    format!(
+        "{{\"name\":{},\"args\":{},\"span\":{}}}",
+        json_identifier(&ty.name),
+        json_list(ty.args.iter().map(json_type)),
+        json_span(ty.span)
+    )
+}
+
+fn json_types(types: &[Option<TypeAnnotation>]) -> String {
+    json_list(
+        types
+            .iter()
+            .map(|ty| json_option(ty.as_ref().map(json_type))),
+    )
+}
+
+fn json_node(kind: &str, span: Span, fields: Vec<(&str, String)>) -> String {
+    let mut out = format!("{{\"node\":\"{}\"", kind);
+    for (name, value) in fields {
+        out.push_str(&format!(",\"{}\":{}", name, value)[..]);
+    }
+    out.push_str(&format!(",\"span\":{}}}", json_span(span))[..]);
+    out
+}
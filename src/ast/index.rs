@@ -0,0 +1,166 @@
+use crate::ast::visit::*;
+use crate::ast::*;
+
+// Tools like hover and go to definition start from a position in the
+// source and need to know which node is there. The span index answers that
+// without walking the whole tree for every question.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NodeKind {
+    Stmt,
+    Expr,
+    Pattern,
+    Function,
+    Class,
+    Identifier,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct IndexedNode {
+    pub id: NodeId,
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+struct Entry {
+    node: IndexedNode,
+    // The innermost node whose span contains this one.
+    parent: Option<usize>,
+}
+
+pub struct SpanIndex {
+    // Sorted by where the spans start, and outer nodes before inner ones
+    // when they start at the same place.
+    entries: Vec<Entry>,
+}
+
+impl SpanIndex {
+    pub fn new(program: &Program) -> Self {
+        let mut collector = Collector { nodes: Vec::new() };
+        collector.visit_program(program);
+        let mut nodes = collector.nodes;
+        // The sort is stable so that a node keeps coming before a child with
+        // the same span, like a class statement and its declaration.
+        nodes.sort_by_key(|n| (n.span.start, std::cmp::Reverse(n.span.end)));
+
+        // Spans in the tree nest inside of each other, so the parent of a
+        // node is the closest node still open when it starts.
+        let mut entries: Vec<Entry> = Vec::with_capacity(nodes.len());
+        let mut open: Vec<usize> = Vec::new();
+        for node in nodes {
+            while let Some(&last) = open.last() {
+                if covers(entries[last].node.span, node.span) {
+                    break;
+                }
+                open.pop();
+            }
+            entries.push(Entry {
+                node,
+                parent: open.last().copied(),
+            });
+            open.push(entries.len() - 1);
+        }
+
+        Self { entries }
+    }
+
+    // The innermost node with the character at the offset in it.
+    pub fn find_node_at(&self, offset: usize) -> Option<IndexedNode> {
+        self.find(offset, |span| span.start <= offset && offset < span.end)
+    }
+
+    // The innermost node whose span contains all of the given span.
+    pub fn find_innermost(&self, span: Span) -> Option<IndexedNode> {
+        self.find(span.start, |s| covers(s, span))
+    }
+
+    fn find(&self, start: usize, contains: impl Fn(Span) -> bool) -> Option<IndexedNode> {
+        // Every node that contains the position starts at or before it, and
+        // the last node to start there is either inside of the node we want
+        // or is that node. Going up through the parents finds it.
+        let last = self
+            .entries
+            .partition_point(|e| e.node.span.start <= start)
+            .checked_sub(1)?;
+        let mut current = Some(last);
+        while let Some(i) = current {
+            let entry = &self.entries[i];
+            if contains(entry.node.span) {
+                return Some(entry.node);
+            }
+            current = entry.parent;
+        }
+        None
+    }
+}
+
+fn covers(outer: Span, inner: Span) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+struct Collector {
+    nodes: Vec<IndexedNode>,
+}
+
+impl Collector {
+    fn add(&mut self, id: NodeId, kind: NodeKind, span: Span) {
+        self.nodes.push(IndexedNode { id, kind, span });
+    }
+
+    fn label(&mut self, label: &Option<Identifier>) {
+        if let Some(label) = label {
+            self.visit_identifier(label);
+        }
+    }
+}
+
+impl Visitor for Collector {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        self.add(stmt.id, NodeKind::Stmt, stmt.span);
+        // Labels and module names are not visited on their own.
+        match &stmt.kind {
+            StmtKind::While { label, .. }
+            | StmtKind::DoWhile { label, .. }
+            | StmtKind::For { label, .. }
+            | StmtKind::Break(label)
+            | StmtKind::Continue(label) => self.label(label),
+            StmtKind::Import(decl) => {
+                if let ImportSource::Module(module) = &decl.source {
+                    self.visit_identifier(module);
+                }
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.add(expr.id, NodeKind::Expr, expr.span);
+        walk_expr(self, expr);
+    }
+
+    fn visit_function(&mut self, decl: &FunctionDecl) {
+        self.add(decl.id, NodeKind::Function, decl.span);
+        walk_function(self, decl);
+    }
+
+    fn visit_class(&mut self, decl: &ClassDecl) {
+        self.add(decl.id, NodeKind::Class, decl.span);
+        walk_class(self, decl);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        self.add(pattern.id, NodeKind::Pattern, pattern.span);
+        if let PatternKind::Map(entries) = &pattern.kind {
+            for entry in entries {
+                if let MapKey::Identifier(key) = &entry.key {
+                    self.visit_identifier(key);
+                }
+            }
+        }
+        walk_pattern(self, pattern);
+    }
+
+    fn visit_identifier(&mut self, ident: &Identifier) {
+        self.add(ident.id, NodeKind::Identifier, ident.span);
+    }
+}
@@ -0,0 +1,683 @@
+use crate::ast::*;
+
+// Visitors walk the syntax tree for analyses that only need to look at it.
+// Every method has a default that keeps walking into the children, so an
+// analysis only overrides the nodes it cares about and calls the matching
+// walk function when it still wants to see what is underneath.
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_function(&mut self, decl: &FunctionDecl) {
+        walk_function(self, decl);
+    }
+
+    fn visit_class(&mut self, decl: &ClassDecl) {
+        walk_class(self, decl);
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) {
+        walk_lambda(self, lambda);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern);
+    }
+
+    fn visit_type(&mut self, ty: &TypeAnnotation) {
+        walk_type(self, ty);
+    }
+
+    // Called for every name that is declared or referenced by a declaration,
+    // such as parameters, loop variables and superclasses.
+    fn visit_identifier(&mut self, _ident: &Identifier) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for stmt in &program.body {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match &stmt.kind {
+        StmtKind::Expression(expr) => visitor.visit_expr(expr),
+        StmtKind::Var { name, ty, init, .. } => {
+            visitor.visit_identifier(name);
+            if let Some(ty) = ty {
+                visitor.visit_type(ty);
+            }
+            if let Some(init) = init {
+                visitor.visit_expr(init);
+            }
+        }
+        StmtKind::Destructure { pattern, init, .. } => {
+            visitor.visit_pattern(pattern);
+            visitor.visit_expr(init);
+        }
+        StmtKind::MultipleAssign { targets, values } => {
+            for target in targets {
+                visitor.visit_pattern(target);
+            }
+            for value in values {
+                visitor.visit_expr(value);
+            }
+        }
+        StmtKind::Block(body) => {
+            for stmt in body {
+                visitor.visit_stmt(stmt);
+            }
+        }
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        StmtKind::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+        StmtKind::DoWhile {
+            body, condition, ..
+        } => {
+            visitor.visit_stmt(body);
+            visitor.visit_expr(condition);
+        }
+        StmtKind::For {
+            variable,
+            iterable,
+            body,
+            ..
+        } => {
+            visitor.visit_identifier(variable);
+            visitor.visit_expr(iterable);
+            visitor.visit_stmt(body);
+        }
+        StmtKind::Match { subject, arms } => {
+            visitor.visit_expr(subject);
+            for arm in arms {
+                for value in &arm.values {
+                    visitor.visit_expr(value);
+                }
+                for stmt in &arm.body {
+                    visitor.visit_stmt(stmt);
+                }
+            }
+        }
+        StmtKind::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            for stmt in body {
+                visitor.visit_stmt(stmt);
+            }
+            if let Some(catch) = catch {
+                if let Some(binding) = &catch.binding {
+                    visitor.visit_identifier(binding);
+                }
+                for stmt in &catch.body {
+                    visitor.visit_stmt(stmt);
+                }
+            }
+            if let Some(finally) = finally {
+                for stmt in finally {
+                    visitor.visit_stmt(stmt);
+                }
+            }
+        }
+        StmtKind::Throw(value) => visitor.visit_expr(value),
+        StmtKind::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        StmtKind::Break(_) | StmtKind::Continue(_) => {}
+        StmtKind::Function(decl) => visitor.visit_function(decl),
+        StmtKind::Class(decl) => visitor.visit_class(decl),
+        StmtKind::Import(decl) => visitor.visit_identifier(&decl.alias),
+        StmtKind::Export(stmt) => visitor.visit_stmt(stmt),
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Null
+        | ExprKind::This
+        | ExprKind::Variable(_) => {}
+        ExprKind::Super(method) => visitor.visit_identifier(method),
+        ExprKind::Unary { operand, .. } => visitor.visit_expr(operand),
+        ExprKind::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        ExprKind::Assign { target, value, .. } => {
+            visitor.visit_expr(target);
+            visitor.visit_expr(value);
+        }
+        ExprKind::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_expr(then_branch);
+            visitor.visit_expr(else_branch);
+        }
+        ExprKind::Range { start, end, .. } => {
+            visitor.visit_expr(start);
+            visitor.visit_expr(end);
+        }
+        ExprKind::Call(call) => {
+            visitor.visit_expr(&call.callee);
+            for arg in &call.args {
+                visitor.visit_expr(arg);
+            }
+            for name in &call.names {
+                visitor.visit_identifier(name);
+            }
+        }
+        ExprKind::Member { object, name } => {
+            visitor.visit_expr(object);
+            visitor.visit_identifier(name);
+        }
+        ExprKind::Index { object, index } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(index);
+        }
+        ExprKind::Slice { object, start, end } => {
+            visitor.visit_expr(object);
+            if let Some(start) = start {
+                visitor.visit_expr(start);
+            }
+            if let Some(end) = end {
+                visitor.visit_expr(end);
+            }
+        }
+        ExprKind::Destructure { pattern, value } => {
+            visitor.visit_pattern(pattern);
+            visitor.visit_expr(value);
+        }
+        ExprKind::InterpolatedString(parts) => {
+            for part in parts {
+                if let StringPart::Expr(expr) = part {
+                    visitor.visit_expr(expr);
+                }
+            }
+        }
+        ExprKind::ListLiteral(items) => {
+            for item in items {
+                visitor.visit_expr(item);
+            }
+        }
+        ExprKind::MapLiteral(entries) => {
+            for entry in entries {
+                match &entry.key {
+                    MapKey::Identifier(ident) => visitor.visit_identifier(ident),
+                    MapKey::String(_) => {}
+                    MapKey::Computed(key) => visitor.visit_expr(key),
+                }
+                visitor.visit_expr(&entry.value);
+            }
+        }
+        ExprKind::Lambda(lambda) => visitor.visit_lambda(lambda),
+        ExprKind::Yield(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, decl: &FunctionDecl) {
+    for decorator in &decl.decorators {
+        visitor.visit_expr(decorator);
+    }
+    visitor.visit_identifier(&decl.name);
+    for param in &decl.params {
+        visitor.visit_identifier(param);
+    }
+    for ty in decl.param_types.iter().flatten() {
+        visitor.visit_type(ty);
+    }
+    for default in &decl.defaults {
+        visitor.visit_expr(default);
+    }
+    if let Some(rest) = &decl.rest {
+        visitor.visit_identifier(rest);
+    }
+    if let Some(ty) = &decl.return_type {
+        visitor.visit_type(ty);
+    }
+    for stmt in &decl.body {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_class<V: Visitor + ?Sized>(visitor: &mut V, decl: &ClassDecl) {
+    for decorator in &decl.decorators {
+        visitor.visit_expr(decorator);
+    }
+    visitor.visit_identifier(&decl.name);
+    if let Some(superclass) = &decl.superclass {
+        visitor.visit_identifier(superclass);
+    }
+    if let Some(initializer) = &decl.initializer {
+        visitor.visit_function(initializer);
+    }
+    for method in decl
+        .methods
+        .iter()
+        .chain(&decl.getters)
+        .chain(&decl.setters)
+    {
+        visitor.visit_function(method);
+    }
+}
+
+pub fn walk_lambda<V: Visitor + ?Sized>(visitor: &mut V, lambda: &Lambda) {
+    for param in &lambda.params {
+        visitor.visit_identifier(param);
+    }
+    for ty in lambda.param_types.iter().flatten() {
+        visitor.visit_type(ty);
+    }
+    for default in &lambda.defaults {
+        visitor.visit_expr(default);
+    }
+    if let Some(rest) = &lambda.rest {
+        visitor.visit_identifier(rest);
+    }
+    if let Some(ty) = &lambda.return_type {
+        visitor.visit_type(ty);
+    }
+    for stmt in &lambda.body {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    match &pattern.kind {
+        PatternKind::Name(name) => visitor.visit_identifier(name),
+        PatternKind::Target(target) => visitor.visit_expr(target),
+        PatternKind::List(items) => {
+            for item in items {
+                visitor.visit_pattern(item);
+            }
+        }
+        PatternKind::Map(entries) => {
+            for entry in entries {
+                match &entry.key {
+                    MapKey::Identifier(_) | MapKey::String(_) => {}
+                    MapKey::Computed(key) => visitor.visit_expr(key),
+                }
+                visitor.visit_pattern(&entry.pattern);
+            }
+        }
+    }
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(visitor: &mut V, ty: &TypeAnnotation) {
+    visitor.visit_identifier(&ty.name);
+    for arg in &ty.args {
+        visitor.visit_type(arg);
+    }
+}
+
+// The mutable visitor mirrors the visitor above but hands out mutable
+// references so that passes can rewrite the tree in place.
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_function_mut(&mut self, decl: &mut FunctionDecl) {
+        walk_function_mut(self, decl);
+    }
+
+    fn visit_class_mut(&mut self, decl: &mut ClassDecl) {
+        walk_class_mut(self, decl);
+    }
+
+    fn visit_lambda_mut(&mut self, lambda: &mut Lambda) {
+        walk_lambda_mut(self, lambda);
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern);
+    }
+
+    fn visit_type_mut(&mut self, ty: &mut TypeAnnotation) {
+        walk_type_mut(self, ty);
+    }
+
+    fn visit_identifier_mut(&mut self, _ident: &mut Identifier) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for stmt in &mut program.body {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Expression(expr) => visitor.visit_expr_mut(expr),
+        StmtKind::Var { name, ty, init, .. } => {
+            visitor.visit_identifier_mut(name);
+            if let Some(ty) = ty {
+                visitor.visit_type_mut(ty);
+            }
+            if let Some(init) = init {
+                visitor.visit_expr_mut(init);
+            }
+        }
+        StmtKind::Destructure { pattern, init, .. } => {
+            visitor.visit_pattern_mut(pattern);
+            visitor.visit_expr_mut(init);
+        }
+        StmtKind::MultipleAssign { targets, values } => {
+            for target in targets {
+                visitor.visit_pattern_mut(target);
+            }
+            for value in values {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        StmtKind::Block(body) => {
+            for stmt in body {
+                visitor.visit_stmt_mut(stmt);
+            }
+        }
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt_mut(else_branch);
+            }
+        }
+        StmtKind::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_stmt_mut(body);
+        }
+        StmtKind::DoWhile {
+            body, condition, ..
+        } => {
+            visitor.visit_stmt_mut(body);
+            visitor.visit_expr_mut(condition);
+        }
+        StmtKind::For {
+            variable,
+            iterable,
+            body,
+            ..
+        } => {
+            visitor.visit_identifier_mut(variable);
+            visitor.visit_expr_mut(iterable);
+            visitor.visit_stmt_mut(body);
+        }
+        StmtKind::Match { subject, arms } => {
+            visitor.visit_expr_mut(subject);
+            for arm in arms {
+                for value in &mut arm.values {
+                    visitor.visit_expr_mut(value);
+                }
+                for stmt in &mut arm.body {
+                    visitor.visit_stmt_mut(stmt);
+                }
+            }
+        }
+        StmtKind::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            for stmt in body {
+                visitor.visit_stmt_mut(stmt);
+            }
+            if let Some(catch) = catch {
+                if let Some(binding) = &mut catch.binding {
+                    visitor.visit_identifier_mut(binding);
+                }
+                for stmt in &mut catch.body {
+                    visitor.visit_stmt_mut(stmt);
+                }
+            }
+            if let Some(finally) = finally {
+                for stmt in finally {
+                    visitor.visit_stmt_mut(stmt);
+                }
+            }
+        }
+        StmtKind::Throw(value) => visitor.visit_expr_mut(value),
+        StmtKind::Return(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(value);
+            }
+        }
+        StmtKind::Break(_) | StmtKind::Continue(_) => {}
+        StmtKind::Function(decl) => visitor.visit_function_mut(decl),
+        StmtKind::Class(decl) => visitor.visit_class_mut(decl),
+        StmtKind::Import(decl) => visitor.visit_identifier_mut(&mut decl.alias),
+        StmtKind::Export(stmt) => visitor.visit_stmt_mut(stmt),
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Int(_)
+        | ExprKind::Float(_)
+        | ExprKind::String(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Null
+        | ExprKind::This
+        | ExprKind::Variable(_) => {}
+        ExprKind::Super(method) => visitor.visit_identifier_mut(method),
+        ExprKind::Unary { operand, .. } => visitor.visit_expr_mut(operand),
+        ExprKind::Binary { left, right, .. } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_expr_mut(right);
+        }
+        ExprKind::Assign { target, value, .. } => {
+            visitor.visit_expr_mut(target);
+            visitor.visit_expr_mut(value);
+        }
+        ExprKind::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr_mut(condition);
+            visitor.visit_expr_mut(then_branch);
+            visitor.visit_expr_mut(else_branch);
+        }
+        ExprKind::Range { start, end, .. } => {
+            visitor.visit_expr_mut(start);
+            visitor.visit_expr_mut(end);
+        }
+        ExprKind::Call(call) => {
+            visitor.visit_expr_mut(&mut call.callee);
+            for arg in &mut call.args {
+                visitor.visit_expr_mut(arg);
+            }
+            for name in &mut call.names {
+                visitor.visit_identifier_mut(name);
+            }
+        }
+        ExprKind::Member { object, name } => {
+            visitor.visit_expr_mut(object);
+            visitor.visit_identifier_mut(name);
+        }
+        ExprKind::Index { object, index } => {
+            visitor.visit_expr_mut(object);
+            visitor.visit_expr_mut(index);
+        }
+        ExprKind::Slice { object, start, end } => {
+            visitor.visit_expr_mut(object);
+            if let Some(start) = start {
+                visitor.visit_expr_mut(start);
+            }
+            if let Some(end) = end {
+                visitor.visit_expr_mut(end);
+            }
+        }
+        ExprKind::Destructure { pattern, value } => {
+            visitor.visit_pattern_mut(pattern);
+            visitor.visit_expr_mut(value);
+        }
+        ExprKind::InterpolatedString(parts) => {
+            for part in parts {
+                if let StringPart::Expr(expr) = part {
+                    visitor.visit_expr_mut(expr);
+                }
+            }
+        }
+        ExprKind::ListLiteral(items) => {
+            for item in items {
+                visitor.visit_expr_mut(item);
+            }
+        }
+        ExprKind::MapLiteral(entries) => {
+            for entry in entries {
+                match &mut entry.key {
+                    MapKey::Identifier(ident) => visitor.visit_identifier_mut(ident),
+                    MapKey::String(_) => {}
+                    MapKey::Computed(key) => visitor.visit_expr_mut(key),
+                }
+                visitor.visit_expr_mut(&mut entry.value);
+            }
+        }
+        ExprKind::Lambda(lambda) => visitor.visit_lambda_mut(lambda),
+        ExprKind::Yield(value) => {
+            if let Some(value) = value {
+                visitor.visit_expr_mut(value);
+            }
+        }
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, decl: &mut FunctionDecl) {
+    for decorator in &mut decl.decorators {
+        visitor.visit_expr_mut(decorator);
+    }
+    visitor.visit_identifier_mut(&mut decl.name);
+    for param in &mut decl.params {
+        visitor.visit_identifier_mut(param);
+    }
+    for ty in decl.param_types.iter_mut().flatten() {
+        visitor.visit_type_mut(ty);
+    }
+    for default in &mut decl.defaults {
+        visitor.visit_expr_mut(default);
+    }
+    if let Some(rest) = &mut decl.rest {
+        visitor.visit_identifier_mut(rest);
+    }
+    if let Some(ty) = &mut decl.return_type {
+        visitor.visit_type_mut(ty);
+    }
+    for stmt in &mut decl.body {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_class_mut<V: VisitorMut + ?Sized>(visitor: &mut V, decl: &mut ClassDecl) {
+    for decorator in &mut decl.decorators {
+        visitor.visit_expr_mut(decorator);
+    }
+    visitor.visit_identifier_mut(&mut decl.name);
+    if let Some(superclass) = &mut decl.superclass {
+        visitor.visit_identifier_mut(superclass);
+    }
+    if let Some(initializer) = &mut decl.initializer {
+        visitor.visit_function_mut(initializer);
+    }
+    let accessors = decl.getters.iter_mut().chain(&mut decl.setters);
+    for method in decl.methods.iter_mut().chain(accessors) {
+        visitor.visit_function_mut(method);
+    }
+}
+
+pub fn walk_lambda_mut<V: VisitorMut + ?Sized>(visitor: &mut V, lambda: &mut Lambda) {
+    for param in &mut lambda.params {
+        visitor.visit_identifier_mut(param);
+    }
+    for ty in lambda.param_types.iter_mut().flatten() {
+        visitor.visit_type_mut(ty);
+    }
+    for default in &mut lambda.defaults {
+        visitor.visit_expr_mut(default);
+    }
+    if let Some(rest) = &mut lambda.rest {
+        visitor.visit_identifier_mut(rest);
+    }
+    if let Some(ty) = &mut lambda.return_type {
+        visitor.visit_type_mut(ty);
+    }
+    for stmt in &mut lambda.body {
+        visitor.visit_stmt_mut(stmt);
+    }
+}
+
+pub fn walk_pattern_mut<V: VisitorMut + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match &mut pattern.kind {
+        PatternKind::Name(name) => visitor.visit_identifier_mut(name),
+        PatternKind::Target(target) => visitor.visit_expr_mut(target),
+        PatternKind::List(items) => {
+            for item in items {
+                visitor.visit_pattern_mut(item);
+            }
+        }
+        PatternKind::Map(entries) => {
+            for entry in entries {
+                match &mut entry.key {
+                    MapKey::Identifier(_) | MapKey::String(_) => {}
+                    MapKey::Computed(key) => visitor.visit_expr_mut(key),
+                }
+                visitor.visit_pattern_mut(&mut entry.pattern);
+            }
+        }
+    }
+}
+
+pub fn walk_type_mut<V: VisitorMut + ?Sized>(visitor: &mut V, ty: &mut TypeAnnotation) {
+    visitor.visit_identifier_mut(&mut ty.name);
+    for arg in &mut ty.args {
+        visitor.visit_type_mut(arg);
+    }
+}
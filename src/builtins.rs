@@ -0,0 +1,732 @@
+use crate::compile::Signature;
+use crate::value::ops;
+use crate::value::*;
+use crate::vm::{Coroutine, CoroutineState};
+use std::fmt;
+use std::io::Write;
+
+// The functions every script can call without declaring them, and the
+// methods of the builtin values, which are written in Rust and shared by
+// the interpreter and the virtual machine.
+
+// What a native function can get at while it runs.
+pub struct Context<'a> {
+    pub heap: &'a mut Heap,
+    // Where `print` writes to.
+    pub out: &'a mut dyn Write,
+}
+
+// A native function gets the arguments of the call, which it has already
+// been checked to accept, and gives back its result or the message of the
+// error it ran into.
+pub type NativeFn = fn(&mut Context, &[Value]) -> Result<Value, String>;
+
+#[derive(Clone, Copy)]
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub min_arity: usize,
+    // Any arguments past the arity are passed along too.
+    pub rest: bool,
+    pub function: NativeFn,
+}
+
+impl Native {
+    pub(crate) fn signature(&self) -> Signature {
+        Signature {
+            arity: self.arity,
+            min_arity: self.min_arity,
+            rest: self.rest,
+        }
+    }
+
+    // The error for a call with the wrong number of arguments, if it has
+    // the wrong number.
+    pub fn check_arity(&self, count: usize) -> Result<(), String> {
+        let signature = self.signature();
+        if signature.accepts(count) {
+            Ok(())
+        } else {
+            Err(signature.mismatch(self.name, count))
+        }
+    }
+}
+
+// Natives are the same when they have the same name, since there is only
+// ever one native with a name.
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
+}
+
+pub const BUILTINS: &[Native] = &[
+    Native {
+        name: "print",
+        arity: 0,
+        min_arity: 0,
+        rest: true,
+        function: print,
+    },
+    Native {
+        name: "len",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: len,
+    },
+    Native {
+        name: "str",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: str,
+    },
+    Native {
+        name: "coroutine",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: coroutine,
+    },
+    Native {
+        name: "weak",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: weak,
+    },
+    #[cfg(feature = "bigint")]
+    Native {
+        name: "bigint",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: bigint,
+    },
+];
+
+// Prints the arguments on one line with a space between them.
+fn print(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let parts: Vec<String> = args
+        .iter()
+        .map(|arg| arg.display(context.heap).to_string())
+        .collect();
+    writeln!(context.out, "{}", parts.join(" ")).map_err(|e| e.to_string())?;
+    Ok(Value::Null)
+}
+
+// The number of characters in a string, items in a list or entries in a
+// map.
+fn len(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let len = match &args[0] {
+        Value::String(s) => s.chars().count(),
+        Value::Object(handle) => match context.heap.get(*handle) {
+            Object::List(items) => items.len(),
+            Object::Map(map) => map.len(),
+            object => return Err(format!("cannot take the length of {}", object.type_name())),
+        },
+        value => {
+            let msg = format!(
+                "cannot take the length of {}",
+                value.type_name(context.heap)
+            );
+            return Err(msg);
+        }
+    };
+    Ok(Value::Int(len as i64))
+}
+
+// The value as `print` shows it.
+fn str(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(args[0].display(context.heap).to_string()))
+}
+
+// A coroutine that calls the function the first time it is resumed. Only
+// the functions of a script can yield, so builtins are turned away.
+fn coroutine(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let native = match &args[0] {
+        Value::Object(handle) => match context.heap.get(*handle) {
+            Object::Closure(_) | Object::Interpreted(_) => false,
+            Object::BoundMethod(bound) => {
+                let method = bound.method.as_object().expect("only functions are bound");
+                matches!(context.heap.get(method), Object::Native(_))
+            }
+            Object::Native(_) | Object::Host(_) => true,
+            object => {
+                return Err(format!(
+                    "cannot make a coroutine out of {}",
+                    object.type_name()
+                ))
+            }
+        },
+        value => {
+            let msg = format!(
+                "cannot make a coroutine out of {}",
+                value.type_name(context.heap)
+            );
+            return Err(msg);
+        }
+    };
+    if native {
+        return Err(String::from(
+            "cannot make a coroutine out of a builtin function",
+        ));
+    }
+    let coroutine = Coroutine::new(args[0].clone());
+    Ok(Value::Object(
+        context.heap.alloc(Object::Coroutine(coroutine)),
+    ))
+}
+
+// A reference to an object that doesn't keep it alive, for caches. Other
+// values are copied rather than referred to, so they can't be weak.
+fn weak(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Object(handle) => {
+            let weak = Object::Weak(gc::Weak::new(*handle));
+            Ok(Value::Object(context.heap.alloc(weak)))
+        }
+        value => Err(format!(
+            "cannot make a weak reference to {}",
+            value.type_name(context.heap)
+        )),
+    }
+}
+
+// Makes a bigint out of an int, a whole float or the decimal digits of a
+// string. A number small enough to be an int stays one.
+#[cfg(feature = "bigint")]
+fn bigint(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    use crate::value::bigint::BigInt;
+
+    let n = match &args[0] {
+        Value::Int(_) | Value::BigInt(_) => return Ok(args[0].clone()),
+        Value::Float(n) => BigInt::from_f64(*n),
+        Value::String(s) => BigInt::parse(s),
+        value => {
+            return Err(format!(
+                "cannot make a bigint out of {}",
+                value.type_name(context.heap)
+            ))
+        }
+    };
+    n.map(Value::from)
+        .ok_or_else(|| format!("cannot make a bigint out of {}", args[0]))
+}
+
+// A method of a builtin value is a native that gets the value before the
+// arguments of the call, which its arity leaves out. Its name says what
+// kind of value it belongs to, as the names of the methods of a class do.
+pub fn method(heap: &Heap, value: &Value, name: &str) -> Option<Native> {
+    let methods = match value {
+        Value::String(_) => STRING_METHODS,
+        Value::Object(handle) => match heap.get(*handle) {
+            Object::List(_) => LIST_METHODS,
+            Object::Map(_) => MAP_METHODS,
+            Object::Range(_) => RANGE_METHODS,
+            Object::Iterator(_) => ITERATOR_METHODS,
+            Object::Coroutine(_) => COROUTINE_METHODS,
+            Object::Weak(_) => WEAK_METHODS,
+            Object::Foreign(foreign) => foreign.methods(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    methods
+        .iter()
+        .find(|method| method.name.split('.').nth(1) == Some(name))
+        .copied()
+}
+
+// The builtin or method of a builtin value with the name, for finding the
+// natives of a snapshot again.
+pub(crate) fn native(name: &str) -> Option<Native> {
+    let tables = [
+        BUILTINS,
+        STRING_METHODS,
+        LIST_METHODS,
+        MAP_METHODS,
+        RANGE_METHODS,
+        ITERATOR_METHODS,
+        COROUTINE_METHODS,
+        WEAK_METHODS,
+    ];
+    tables
+        .iter()
+        .flat_map(|natives| natives.iter())
+        .find(|native| native.name == name)
+        .copied()
+}
+
+const STRING_METHODS: &[Native] = &[
+    Native {
+        name: "string.iter",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: iter,
+    },
+    Native {
+        name: "string.length",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: string_length,
+    },
+    Native {
+        name: "string.upper",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: upper,
+    },
+    Native {
+        name: "string.lower",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: lower,
+    },
+    Native {
+        name: "string.trim",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: trim,
+    },
+    Native {
+        name: "string.split",
+        arity: 1,
+        min_arity: 0,
+        rest: false,
+        function: split,
+    },
+    Native {
+        name: "string.contains",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: contains,
+    },
+    Native {
+        name: "string.starts_with",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: starts_with,
+    },
+    Native {
+        name: "string.replace",
+        arity: 2,
+        min_arity: 2,
+        rest: false,
+        function: replace,
+    },
+    Native {
+        name: "string.slice",
+        arity: 2,
+        min_arity: 1,
+        rest: false,
+        function: string_slice,
+    },
+    Native {
+        name: "string.find",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: find,
+    },
+];
+
+const LIST_METHODS: &[Native] = &[
+    Native {
+        name: "list.iter",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: iter,
+    },
+    Native {
+        name: "list.length",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: list_length,
+    },
+    Native {
+        name: "list.push",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: push,
+    },
+    Native {
+        name: "list.pop",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: pop,
+    },
+    Native {
+        name: "list.insert",
+        arity: 2,
+        min_arity: 2,
+        rest: false,
+        function: insert,
+    },
+    Native {
+        name: "list.remove",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: remove,
+    },
+];
+
+const MAP_METHODS: &[Native] = &[
+    Native {
+        name: "map.iter",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: iter,
+    },
+    Native {
+        name: "map.length",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: map_length,
+    },
+    Native {
+        name: "map.keys",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: keys,
+    },
+    Native {
+        name: "map.values",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: values,
+    },
+    Native {
+        name: "map.has",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: has,
+    },
+    Native {
+        name: "map.remove",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: map_remove,
+    },
+];
+
+const RANGE_METHODS: &[Native] = &[Native {
+    name: "range.iter",
+    arity: 0,
+    min_arity: 0,
+    rest: false,
+    function: iter,
+}];
+
+const ITERATOR_METHODS: &[Native] = &[
+    Native {
+        name: "iterator.has_next",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: has_next,
+    },
+    Native {
+        name: "iterator.next",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: next,
+    },
+];
+
+const COROUTINE_METHODS: &[Native] = &[Native {
+    name: "coroutine.done",
+    arity: 0,
+    min_arity: 0,
+    rest: false,
+    function: done,
+}];
+
+const WEAK_METHODS: &[Native] = &[Native {
+    name: "weak.get",
+    arity: 0,
+    min_arity: 0,
+    rest: false,
+    function: weak_get,
+}];
+
+// Going through a map gives a list of the key and the value of every entry,
+// and going through a string gives its characters.
+fn iter(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let items = match &args[0] {
+        Value::String(s) => Items::Values(s.chars().map(|c| Value::from(c.to_string())).collect()),
+        Value::Object(handle) => match context.heap.get(*handle) {
+            Object::List(_) => Items::List(*handle),
+            Object::Map(map) => {
+                let entries: Vec<Vec<Value>> = map
+                    .iter()
+                    .map(|(key, value)| vec![key.value(), value.clone()])
+                    .collect();
+                let entries = entries
+                    .into_iter()
+                    .map(|entry| Value::Object(context.heap.alloc(Object::List(entry))));
+                Items::Values(entries.collect())
+            }
+            Object::Range(range) => Items::Range(*range),
+            _ => unreachable!("only the values with an iter method are gone through"),
+        },
+        _ => unreachable!("only the values with an iter method are gone through"),
+    };
+    let iter = context.heap.alloc(Object::Iterator(Iter::new(items)));
+    Ok(Value::Object(iter))
+}
+
+fn has_next(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let iter = iterator(context.heap, &args[0]);
+    Ok(Value::Bool(iter.peek(context.heap).is_some()))
+}
+
+fn next(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let item = iterator(context.heap, &args[0])
+        .peek(context.heap)
+        .ok_or_else(|| String::from("the iterator has no items left"))?;
+    if let Value::Object(handle) = &args[0] {
+        if let Object::Iterator(iter) = context.heap.get_mut(*handle) {
+            iter.position += 1;
+        }
+    }
+    Ok(item)
+}
+
+fn iterator<'a>(heap: &'a Heap, value: &Value) -> &'a Iter {
+    match value {
+        Value::Object(handle) => match heap.get(*handle) {
+            Object::Iterator(iter) => iter,
+            _ => unreachable!("iterator methods are only called on iterators"),
+        },
+        _ => unreachable!("iterator methods are only called on iterators"),
+    }
+}
+
+// Whether the function of the coroutine has returned, after which it can't
+// be resumed again.
+fn done(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let state = match &args[0] {
+        Value::Object(handle) => match context.heap.get(*handle) {
+            Object::Coroutine(coroutine) => coroutine.state(),
+            _ => unreachable!("coroutine methods are only called on coroutines"),
+        },
+        _ => unreachable!("coroutine methods are only called on coroutines"),
+    };
+    Ok(Value::Bool(state == CoroutineState::Finished))
+}
+
+// The object, or null once it has been collected.
+fn weak_get(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    match args[0].as_object().map(|handle| context.heap.get(handle)) {
+        Some(Object::Weak(weak)) => Ok(weak.get().map_or(Value::Null, Value::Object)),
+        _ => unreachable!("weak methods are only called on weak references"),
+    }
+}
+
+fn list_length(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Int(list(context.heap, args).len() as i64))
+}
+
+fn push(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    list(context.heap, args).push(args[1].clone());
+    Ok(Value::Null)
+}
+
+// Takes the last item off the list.
+fn pop(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    list(context.heap, args)
+        .pop()
+        .ok_or_else(|| String::from("cannot pop from an empty list"))
+}
+
+// Puts the item at the position, which can be one past the last item for
+// the end of the list, moving the items from there along.
+fn insert(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let len = list(context.heap, args).len();
+    let i = ops::position(context.heap, "list", &args[1], len + 1)?;
+    list(context.heap, args).insert(i, args[2].clone());
+    Ok(Value::Null)
+}
+
+// Takes the item at the position out of the list.
+fn remove(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let len = list(context.heap, args).len();
+    let i = ops::position(context.heap, "list", &args[1], len)?;
+    Ok(list(context.heap, args).remove(i))
+}
+
+fn map_length(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Int(map(context.heap, args).len() as i64))
+}
+
+fn keys(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let keys = map(context.heap, args)
+        .iter()
+        .map(|(key, _)| key.value())
+        .collect();
+    Ok(Value::Object(context.heap.alloc(Object::List(keys))))
+}
+
+fn values(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let values = map(context.heap, args)
+        .iter()
+        .map(|(_, value)| value.clone())
+        .collect();
+    Ok(Value::Object(context.heap.alloc(Object::List(values))))
+}
+
+fn has(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let has = match args[1].key() {
+        Some(key) => map(context.heap, args).get(&key).is_some(),
+        None => false,
+    };
+    Ok(Value::Bool(has))
+}
+
+// Takes the entry for the key out of the map, giving back its value, or
+// null if there was none.
+fn map_remove(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let value = match args[1].key() {
+        Some(key) => map(context.heap, args).remove(&key),
+        None => None,
+    };
+    Ok(value.unwrap_or_default())
+}
+
+fn map<'a>(heap: &'a mut Heap, args: &[Value]) -> &'a mut Map {
+    let handle = args[0]
+        .as_object()
+        .expect("map methods are only called on maps");
+    match heap.get_mut(handle) {
+        Object::Map(map) => map,
+        _ => unreachable!("map methods are only called on maps"),
+    }
+}
+
+fn list<'a>(heap: &'a mut Heap, args: &[Value]) -> &'a mut Vec<Value> {
+    let handle = args[0]
+        .as_object()
+        .expect("list methods are only called on lists");
+    match heap.get_mut(handle) {
+        Object::List(items) => items,
+        _ => unreachable!("list methods are only called on lists"),
+    }
+}
+
+// Strings are worked on as characters rather than bytes, so positions and
+// lengths are the same whatever the characters are.
+
+fn string_length(_: &mut Context, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Int(string(args).chars().count() as i64))
+}
+
+fn upper(_: &mut Context, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(string(args).to_uppercase()))
+}
+
+fn lower(_: &mut Context, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(string(args).to_lowercase()))
+}
+
+fn trim(_: &mut Context, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(string(args).trim()))
+}
+
+// Splits at every separator, or at runs of whitespace without one, which
+// leaves out the empty strings at either end. An empty separator splits
+// the string into its characters.
+fn split(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let s = string(args);
+    let parts: Vec<Value> = match args.get(1) {
+        None | Some(Value::Null) => s.split_whitespace().map(Value::from).collect(),
+        Some(_) => match string_arg(context.heap, "string.split", args, 1)? {
+            "" => s.chars().map(|c| Value::from(c.to_string())).collect(),
+            separator => s.split(separator).map(Value::from).collect(),
+        },
+    };
+    Ok(Value::Object(context.heap.alloc(Object::List(parts))))
+}
+
+fn contains(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let part = string_arg(context.heap, "string.contains", args, 1)?;
+    Ok(Value::Bool(string(args).contains(part)))
+}
+
+fn starts_with(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let prefix = string_arg(context.heap, "string.starts_with", args, 1)?;
+    Ok(Value::Bool(string(args).starts_with(prefix)))
+}
+
+// Replaces every time the part is found.
+fn replace(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let from = string_arg(context.heap, "string.replace", args, 1)?;
+    let to = string_arg(context.heap, "string.replace", args, 2)?;
+    Ok(Value::from(string(args).replace(from, to)))
+}
+
+// The same as slicing with `s[start:end]`.
+fn string_slice(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let end = args.get(2).cloned().unwrap_or_default();
+    ops::slice(context.heap, &args[0], &args[1], &end)
+}
+
+// The position of the first character of the part, or null if the string
+// doesn't have it.
+fn find(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let s = string(args);
+    let part = string_arg(context.heap, "string.find", args, 1)?;
+    Ok(match s.find(part) {
+        Some(byte) => Value::Int(s[..byte].chars().count() as i64),
+        None => Value::Null,
+    })
+}
+
+fn string(args: &[Value]) -> &str {
+    match &args[0] {
+        Value::String(s) => s,
+        _ => unreachable!("string methods are only called on strings"),
+    }
+}
+
+fn string_arg<'a>(
+    heap: &Heap,
+    method: &str,
+    args: &'a [Value],
+    i: usize,
+) -> Result<&'a str, String> {
+    match &args[i] {
+        Value::String(s) => Ok(s),
+        arg => Err(format!(
+            "{} expects a string but got {}",
+            method,
+            arg.type_name(heap)
+        )),
+    }
+}
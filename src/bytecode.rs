@@ -0,0 +1,996 @@
+pub mod disassemble;
+pub mod serialize;
+
+use crate::ast::Span;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+// Scripts are compiled to bytecode for a stack machine. Every instruction is
+// an opcode byte followed by its operands, which are either one byte or two
+// bytes with the high byte first. Instructions take their inputs from the
+// top of the stack and leave their results there.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[repr(u8)]
+pub enum OpCode {
+    // Pushes the constant with the index in the operand.
+    Constant,
+    Null,
+    True,
+    False,
+    Pop,
+    // Duplicates the value on top of the stack, or the top two values.
+    Dup,
+    Dup2,
+    // Locals live in the slots of the current call frame, numbered from the
+    // bottom of the frame.
+    GetLocal,
+    SetLocal,
+    // Globals are found by a name which is a string constant.
+    DefineGlobal,
+    GetGlobal,
+    SetGlobal,
+    // Globals declared in the script are kept in numbered slots instead,
+    // which the chunk of the script gives the names of. Finding a global by
+    // name is left for the ones the host provides and the ones a script
+    // assigns to without declaring.
+    DefineSlot,
+    GetSlot,
+    SetSlot,
+    // Pushes the module whose path is the string constant in the operand,
+    // running the module first if nothing has imported it yet.
+    Import,
+    // Upvalues are the variables of enclosing functions that a closure
+    // uses, numbered in the order the closure first used them.
+    GetUpvalue,
+    SetUpvalue,
+    // Fields and methods are also found by a name in the constants.
+    GetProperty,
+    SetProperty,
+    // Takes the instance and the superclass, and pushes the method of the
+    // superclass with the name, bound to the instance.
+    GetSuper,
+    GetIndex,
+    SetIndex,
+    // Takes the object, the start and the end, where a null start or end
+    // leaves that side of the slice open.
+    Slice,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Negate,
+    Not,
+    BitNot,
+    // Jumps are relative to the end of the jump instruction. The conditional
+    // jump leaves the condition on the stack so that `and` and `or` can use
+    // it as their result.
+    Jump,
+    JumpIfFalse,
+    Loop,
+    // Pops a value and looks for it in the cases constant in the operand.
+    // A table with a jump for every case follows, and the switch goes on at
+    // the jump for the case that matches, or past the table when none does.
+    // Every jump in a table has the same width, so the right one is found
+    // without going through the ones before it.
+    Switch,
+    // Sets up a handler for anything thrown before the matching end_try,
+    // which goes on at the jump in the operand, with the stack as it was at
+    // the try and the exception pushed on top.
+    Try,
+    EndTry,
+    // Throws the value on top of the stack.
+    Throw,
+    // Throws the exception on top of the stack on once a finally block has
+    // run, as if from wherever it was first thrown.
+    Rethrow,
+    // The operand is the number of arguments, which are on the stack above
+    // the value being called.
+    Call,
+    // Calls in place of the function that is running, which returns what
+    // the call returns. The frame of the running function is reused, so a
+    // chain of tail calls never grows the stack.
+    TailCall,
+    Return,
+    // Hands the value on top of the stack to whatever resumed the running
+    // coroutine, and is left with the value the coroutine is resumed with
+    // next in its place.
+    Yield,
+    // Makes a closure out of the function constant in the operand, which
+    // captures the variables listed in the upvalues of the function.
+    Closure,
+    // Pops a local that a closure has captured, moving the value into the
+    // closure so that it lives on after the local goes out of scope.
+    CloseUpvalue,
+    // Builds a list or a map out of that many items on the stack. Maps take
+    // a key and then a value for every entry.
+    List,
+    Map,
+    // Turns that many values on the stack into strings and joins them, for
+    // formatted strings. The string is only allocated once, at its length.
+    Concat,
+    // The operand is 1 when the end is part of the range.
+    Range,
+    // Pushes a new class with no methods, named by the string constant.
+    Class,
+    // Takes the class and the class it extends, leaving the class.
+    Inherit,
+    // Take the class and a function, adding the function to the class under
+    // the name in the constants and leaving the class.
+    Method,
+    Getter,
+    Setter,
+    // Goes in front of an instruction to make its operand twice as wide. A
+    // one byte operand becomes two, for chunks with more than 256 constants
+    // or locals, and the two bytes of a jump become four for functions with
+    // too much code to jump over otherwise.
+    Wide,
+}
+
+// Every opcode in the order of its byte.
+const OPCODES: [OpCode; 65] = [
+    OpCode::Constant,
+    OpCode::Null,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Pop,
+    OpCode::Dup,
+    OpCode::Dup2,
+    OpCode::GetLocal,
+    OpCode::SetLocal,
+    OpCode::DefineGlobal,
+    OpCode::GetGlobal,
+    OpCode::SetGlobal,
+    OpCode::DefineSlot,
+    OpCode::GetSlot,
+    OpCode::SetSlot,
+    OpCode::Import,
+    OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty,
+    OpCode::SetProperty,
+    OpCode::GetSuper,
+    OpCode::GetIndex,
+    OpCode::SetIndex,
+    OpCode::Slice,
+    OpCode::Equal,
+    OpCode::NotEqual,
+    OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Modulo,
+    OpCode::BitAnd,
+    OpCode::BitOr,
+    OpCode::BitXor,
+    OpCode::Negate,
+    OpCode::Not,
+    OpCode::BitNot,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::Loop,
+    OpCode::Switch,
+    OpCode::Try,
+    OpCode::EndTry,
+    OpCode::Throw,
+    OpCode::Rethrow,
+    OpCode::Call,
+    OpCode::TailCall,
+    OpCode::Return,
+    OpCode::Yield,
+    OpCode::Closure,
+    OpCode::CloseUpvalue,
+    OpCode::List,
+    OpCode::Map,
+    OpCode::Concat,
+    OpCode::Range,
+    OpCode::Class,
+    OpCode::Inherit,
+    OpCode::Method,
+    OpCode::Getter,
+    OpCode::Setter,
+    OpCode::Wide,
+];
+
+impl OpCode {
+    pub fn all() -> &'static [OpCode] {
+        &OPCODES[..]
+    }
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+
+    // The number of bytes of operands that follow the opcode.
+    pub fn operand_len(&self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::DefineGlobal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::DefineSlot
+            | OpCode::GetSlot
+            | OpCode::SetSlot
+            | OpCode::Import
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Switch
+            | OpCode::Call
+            | OpCode::TailCall
+            | OpCode::Closure
+            | OpCode::List
+            | OpCode::Map
+            | OpCode::Concat
+            | OpCode::Range
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::Getter
+            | OpCode::Setter => 1,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop | OpCode::Try => 2,
+            _ => 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Constant => "constant",
+            OpCode::Null => "null",
+            OpCode::True => "true",
+            OpCode::False => "false",
+            OpCode::Pop => "pop",
+            OpCode::Dup => "dup",
+            OpCode::Dup2 => "dup2",
+            OpCode::GetLocal => "get_local",
+            OpCode::SetLocal => "set_local",
+            OpCode::DefineGlobal => "define_global",
+            OpCode::GetGlobal => "get_global",
+            OpCode::SetGlobal => "set_global",
+            OpCode::DefineSlot => "define_slot",
+            OpCode::GetSlot => "get_slot",
+            OpCode::SetSlot => "set_slot",
+            OpCode::Import => "import",
+            OpCode::GetUpvalue => "get_upvalue",
+            OpCode::SetUpvalue => "set_upvalue",
+            OpCode::GetProperty => "get_property",
+            OpCode::SetProperty => "set_property",
+            OpCode::GetSuper => "get_super",
+            OpCode::GetIndex => "get_index",
+            OpCode::SetIndex => "set_index",
+            OpCode::Slice => "slice",
+            OpCode::Equal => "equal",
+            OpCode::NotEqual => "not_equal",
+            OpCode::Less => "less",
+            OpCode::LessEqual => "less_equal",
+            OpCode::Greater => "greater",
+            OpCode::GreaterEqual => "greater_equal",
+            OpCode::Add => "add",
+            OpCode::Subtract => "subtract",
+            OpCode::Multiply => "multiply",
+            OpCode::Divide => "divide",
+            OpCode::Modulo => "modulo",
+            OpCode::BitAnd => "bit_and",
+            OpCode::BitOr => "bit_or",
+            OpCode::BitXor => "bit_xor",
+            OpCode::Negate => "negate",
+            OpCode::Not => "not",
+            OpCode::BitNot => "bit_not",
+            OpCode::Jump => "jump",
+            OpCode::JumpIfFalse => "jump_if_false",
+            OpCode::Loop => "loop",
+            OpCode::Switch => "switch",
+            OpCode::Try => "try",
+            OpCode::EndTry => "end_try",
+            OpCode::Throw => "throw",
+            OpCode::Rethrow => "rethrow",
+            OpCode::Call => "call",
+            OpCode::TailCall => "tail_call",
+            OpCode::Return => "return",
+            OpCode::Yield => "yield",
+            OpCode::Closure => "closure",
+            OpCode::CloseUpvalue => "close_upvalue",
+            OpCode::List => "list",
+            OpCode::Map => "map",
+            OpCode::Concat => "concat",
+            OpCode::Range => "range",
+            OpCode::Class => "class",
+            OpCode::Inherit => "inherit",
+            OpCode::Method => "method",
+            OpCode::Getter => "getter",
+            OpCode::Setter => "setter",
+            OpCode::Wide => "wide",
+        }
+    }
+
+    // Whether a wide prefix can make the operand wider.
+    pub fn can_be_wide(&self) -> bool {
+        self.operand_len() > 0
+    }
+
+    // Whether the operand is an index into the constants.
+    pub fn takes_constant(&self) -> bool {
+        matches!(
+            self,
+            OpCode::Constant
+                | OpCode::DefineGlobal
+                | OpCode::GetGlobal
+                | OpCode::SetGlobal
+                | OpCode::Import
+                | OpCode::GetProperty
+                | OpCode::SetProperty
+                | OpCode::GetSuper
+                | OpCode::Switch
+                | OpCode::Closure
+                | OpCode::Class
+                | OpCode::Method
+                | OpCode::Getter
+                | OpCode::Setter
+        )
+    }
+
+    // How many values the instruction takes off the stack and puts back.
+    pub fn stack_effect(&self, operand: usize) -> (usize, usize) {
+        match self {
+            OpCode::Constant
+            | OpCode::Null
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetLocal
+            | OpCode::GetGlobal
+            | OpCode::GetSlot
+            | OpCode::Import
+            | OpCode::GetUpvalue
+            | OpCode::Closure
+            | OpCode::Class => (0, 1),
+            OpCode::Pop
+            | OpCode::CloseUpvalue
+            | OpCode::DefineGlobal
+            | OpCode::DefineSlot
+            | OpCode::Switch
+            | OpCode::Throw
+            | OpCode::Rethrow
+            | OpCode::Return => (1, 0),
+            OpCode::Dup => (1, 2),
+            OpCode::Dup2 => (2, 4),
+            OpCode::SetLocal | OpCode::SetGlobal | OpCode::SetSlot | OpCode::SetUpvalue => (1, 1),
+            OpCode::GetProperty | OpCode::Negate | OpCode::Not | OpCode::BitNot => (1, 1),
+            OpCode::Yield => (1, 1),
+            OpCode::SetProperty | OpCode::GetIndex | OpCode::Range | OpCode::GetSuper => (2, 1),
+            OpCode::Inherit | OpCode::Method | OpCode::Getter | OpCode::Setter => (2, 1),
+            OpCode::SetIndex | OpCode::Slice => (3, 1),
+            OpCode::Call => (operand + 1, 1),
+            OpCode::TailCall => (operand + 1, 0),
+            OpCode::List | OpCode::Concat => (operand, 1),
+            OpCode::Map => (operand * 2, 1),
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop | OpCode::Wide => (0, 0),
+            OpCode::Try | OpCode::EndTry => (0, 0),
+            _ => (2, 1),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    // Strings are shared, so that the chunks of a script which use the same
+    // string all point at one copy of it.
+    String(Arc<str>),
+    Function(Box<Function>),
+    Cases(Cases),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(n) => write!(f, "{}", n),
+            Constant::Float(n) => f.write_str(&format_number(*n)),
+            Constant::String(s) => write!(f, "{:?}", s),
+            Constant::Function(function) => write!(f, "<function {}>", function.name),
+            Constant::Cases(Cases::Range { start, len }) => {
+                write!(f, "<cases {}..{}>", start, *start + *len as i64)
+            }
+            Constant::Cases(Cases::Sorted(values)) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "<cases {}>", values.join(", "))
+            }
+        }
+    }
+}
+
+// The values a switch looks for, each of which has its own jump in the
+// table. Ints that follow each other make a range, where a value is found
+// by taking the start away from it. Any other numbers and strings are
+// sorted, numbers first, and found with a binary search.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Cases {
+    Range { start: i64, len: usize },
+    Sorted(Vec<Constant>),
+}
+
+impl Cases {
+    pub fn len(&self) -> usize {
+        match self {
+            Cases::Range { len, .. } => *len,
+            Cases::Sorted(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn find_int(&self, n: i64) -> Option<usize> {
+        match self {
+            Cases::Range { start, len } => match n.checked_sub(*start) {
+                Some(index) if index >= 0 && (index as u64) < *len as u64 => Some(index as usize),
+                _ => None,
+            },
+            Cases::Sorted(values) => values
+                .binary_search_by(|value| match value {
+                    Constant::Int(m) => m.cmp(&n),
+                    _ => compare_number(value, n as f64),
+                })
+                .ok(),
+        }
+    }
+
+    // The case a float matches, the same as comparing it with every case in
+    // turn, so -0 matches 0, 2.0 matches 2 and NaN matches nothing.
+    pub fn find_number(&self, n: f64) -> Option<usize> {
+        match self {
+            Cases::Range { start, len } => {
+                let index = n - *start as f64;
+                if index.fract() == 0.0 && index >= 0.0 && index < *len as f64 {
+                    Some(index as usize)
+                } else {
+                    None
+                }
+            }
+            Cases::Sorted(values) => values
+                .binary_search_by(|value| compare_number(value, n))
+                .ok(),
+        }
+    }
+
+    pub fn find_string(&self, s: &str) -> Option<usize> {
+        match self {
+            Cases::Range { .. } => None,
+            Cases::Sorted(values) => values
+                .binary_search_by(|value| match value {
+                    Constant::String(t) => (**t).cmp(s),
+                    _ => Ordering::Less,
+                })
+                .ok(),
+        }
+    }
+
+    // Sorted cases can only be numbers and strings, in order and with no
+    // two the same, for the search to find them.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Cases::Range { start, len } => start.checked_add(*len as i64).is_some(),
+            Cases::Sorted(values) => {
+                values.iter().all(|value| match value {
+                    Constant::Int(_) => true,
+                    Constant::Float(n) => !n.is_nan(),
+                    Constant::String(_) => true,
+                    _ => false,
+                }) && values
+                    .windows(2)
+                    .all(|pair| compare_cases(&pair[0], &pair[1]) == Ordering::Less)
+            }
+        }
+    }
+}
+
+// The order of sorted cases, which only ever holds numbers and strings.
+// Ints and floats are ordered by the numbers they are.
+pub fn compare_cases(a: &Constant, b: &Constant) -> Ordering {
+    match (a, b) {
+        (Constant::Int(a), Constant::Int(b)) => a.cmp(b),
+        (Constant::String(a), Constant::String(b)) => a.cmp(b),
+        (_, Constant::Int(n)) => compare_number(a, *n as f64),
+        (_, Constant::Float(n)) => compare_number(a, *n),
+        (Constant::Int(_), _) | (Constant::Float(_), _) => Ordering::Less,
+        _ => Ordering::Greater,
+    }
+}
+
+// Where a case is in relation to a number, with strings after every number.
+fn compare_number(case: &Constant, n: f64) -> Ordering {
+    match case {
+        Constant::Int(m) => (*m as f64).partial_cmp(&n).unwrap_or(Ordering::Less),
+        Constant::Float(m) => m.partial_cmp(&n).unwrap_or(Ordering::Less),
+        _ => Ordering::Greater,
+    }
+}
+
+// How a float looks as a string, which is the same when a formatted string
+// is folded while compiling and when it is built at runtime. Whole floats
+// keep a decimal point, so that 1.0 can be told apart from the int 1.
+pub fn format_number(n: f64) -> String {
+    let s = format!("{}", n);
+    if n.is_finite() && !s.contains('.') {
+        s + ".0"
+    } else {
+        s
+    }
+}
+
+// A compiled function. Calling it makes a frame whose first slot holds the
+// function itself, followed by a slot for each parameter. Parameters after
+// the first `min_arity` have defaults, and with `rest` the last parameter
+// gets a list of any arguments left over.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub min_arity: usize,
+    pub rest: bool,
+    // What a closure made from the function captures, in the order of the
+    // operands of `get_upvalue` and `set_upvalue`.
+    pub upvalues: Vec<Upvalue>,
+    pub chunk: Chunk,
+}
+
+impl Function {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            arity: 0,
+            min_arity: 0,
+            rest: false,
+            upvalues: Vec::new(),
+            chunk: Chunk::new(name),
+        }
+    }
+}
+
+// A captured variable is either a local in the slot of the function making
+// the closure, or one of the upvalues that function captured itself.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Upvalue {
+    pub local: bool,
+    pub index: usize,
+}
+
+impl fmt::Display for Upvalue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.local {
+            write!(f, "local {}", self.index)
+        } else {
+            write!(f, "upvalue {}", self.index)
+        }
+    }
+}
+
+// Floats are compared by their bits so that 0 and -0 stay apart and NaN
+// is the same as itself. Every NaN counts as the same one, whatever bits
+// the arithmetic that made it left behind, since those can differ from one
+// machine to the next.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum ConstantKey {
+    Int(i64),
+    Float(u64),
+    String(Arc<str>),
+}
+
+impl Constant {
+    // Functions are never shared, each one comes from its own declaration,
+    // and neither are the cases of a switch.
+    fn key(&self) -> Option<ConstantKey> {
+        match self {
+            Constant::Int(n) => Some(ConstantKey::Int(*n)),
+            Constant::Float(n) => Some(ConstantKey::Float(number_bits(*n))),
+            Constant::String(s) => Some(ConstantKey::String(s.clone())),
+            Constant::Function(_) | Constant::Cases(_) => None,
+        }
+    }
+}
+
+// The bits a number is stored as, which are the same for every NaN.
+pub(crate) fn number_bits(n: f64) -> u64 {
+    if n.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        n.to_bits()
+    }
+}
+
+// Hands out one shared copy of every string it is given. The compiler keeps
+// one for everything it compiles together.
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(string) = self.strings.get(s) {
+            return string.clone();
+        }
+        let string: Arc<str> = Arc::from(s);
+        self.strings.insert(string.clone());
+        string
+    }
+
+    // The number of different strings interned so far.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+// Debug symbols give the slots and upvalues of a function the names they
+// have in the source, so that debuggers and stack traces can show variables
+// by name. They are left out of release builds to keep the code small.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct DebugSymbols {
+    pub locals: Vec<LocalSymbol>,
+    // The name of every upvalue of the function, in order.
+    pub upvalues: Vec<String>,
+}
+
+// Blocks reuse the slots of the ones before them, so a local only has its
+// slot for the code from `start` up to `end`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LocalSymbol {
+    pub name: String,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl DebugSymbols {
+    // The name of the local in the slot while the code at the offset runs.
+    pub fn local(&self, slot: usize, offset: usize) -> Option<&str> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|l| l.slot == slot && l.start <= offset && offset < l.end)
+            .map(|l| &l.name[..])
+    }
+
+    pub fn upvalue(&self, index: usize) -> Option<&str> {
+        self.upvalues.get(index).map(|name| &name[..])
+    }
+
+    // Every local in scope while the code at the offset runs, by slot.
+    pub fn locals_at(&self, offset: usize) -> Vec<&LocalSymbol> {
+        let mut locals: Vec<&LocalSymbol> = self
+            .locals
+            .iter()
+            .filter(|l| l.start <= offset && offset < l.end)
+            .collect();
+        locals.sort_by_key(|l| l.slot);
+        locals
+    }
+}
+
+// The most constants a chunk can hold, since a wide operand is two bytes.
+pub const MAX_CONSTANTS: usize = u16::MAX as usize + 1;
+
+// A chunk holds the code of one function along with the constants it uses
+// and the span of source each byte of code came from, for reporting errors.
+// A value used many times is only added to the constants once.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Chunk {
+    name: String,
+    // The names of the global slots, in order. Only the chunk of a script
+    // has them, the functions inside it use the same slots.
+    globals: Vec<String>,
+    code: Vec<u8>,
+    constants: Vec<Constant>,
+    constant_indices: HashMap<ConstantKey, usize>,
+    spans: Vec<SpanRun>,
+    symbols: Option<DebugSymbols>,
+}
+
+// Most instructions in a row come from the same expression, so spans are
+// stored once for a run of code that starts at an offset and goes on until
+// the next run starts.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct SpanRun {
+    offset: usize,
+    span: Span,
+}
+
+impl Chunk {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            ..Self::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name[..]
+    }
+
+    pub fn globals(&self) -> &[String] {
+        &self.globals[..]
+    }
+
+    pub fn set_globals(&mut self, globals: Vec<String>) {
+        self.globals = globals;
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code[..]
+    }
+
+    pub fn constants(&self) -> &[Constant] {
+        &self.constants[..]
+    }
+
+    // Only the functions can be changed, since the other constants are
+    // looked up by their values.
+    pub fn functions_mut(&mut self) -> impl Iterator<Item = &mut Function> {
+        self.constants
+            .iter_mut()
+            .filter_map(|constant| match constant {
+                Constant::Function(function) => Some(&mut **function),
+                _ => None,
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    // The span of source the byte at the offset came from.
+    pub fn span(&self, offset: usize) -> Span {
+        assert!(offset < self.code.len());
+        let run = self.spans.partition_point(|run| run.offset <= offset);
+        self.spans[run - 1].span
+    }
+
+    pub fn line(&self, offset: usize) -> u32 {
+        self.span(offset).line
+    }
+
+    // Forgets where the code came from, leaving one run of the default span
+    // for all of it, which makes the span table as small as it gets.
+    pub fn strip_spans(&mut self) {
+        self.spans.clear();
+        if !self.code.is_empty() {
+            self.spans.push(SpanRun {
+                offset: 0,
+                span: Span::default(),
+            });
+        }
+    }
+
+    pub fn symbols(&self) -> Option<&DebugSymbols> {
+        self.symbols.as_ref()
+    }
+
+    pub fn set_symbols(&mut self, symbols: DebugSymbols) {
+        self.symbols = Some(symbols);
+    }
+
+    pub fn strip_symbols(&mut self) {
+        self.symbols = None;
+    }
+
+    // Moves the offsets in the debug symbols once passes over the code have
+    // moved the instructions.
+    pub(crate) fn remap_symbols(&mut self, new_offset: impl Fn(usize) -> usize) {
+        if let Some(symbols) = &mut self.symbols {
+            for local in &mut symbols.locals {
+                local.start = new_offset(local.start);
+                local.end = new_offset(local.end);
+            }
+        }
+    }
+
+    // The number of runs in the span table, which is what it costs to keep.
+    pub fn span_runs(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn write(&mut self, op: OpCode, span: Span) {
+        self.write_byte(op as u8, span);
+    }
+
+    pub fn write_byte(&mut self, byte: u8, span: Span) {
+        if self.spans.last().map(|run| run.span) != Some(span) {
+            self.spans.push(SpanRun {
+                offset: self.code.len(),
+                span,
+            });
+        }
+        self.code.push(byte);
+    }
+
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        let [high, low] = value.to_be_bytes();
+        self.write_byte(high, span);
+        self.write_byte(low, span);
+    }
+
+    pub fn write_u32(&mut self, value: u32, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.write_byte(byte, span);
+        }
+    }
+
+    // Writes an instruction with a one byte operand, like a constant index
+    // or a slot, with a wide prefix when the operand doesn't fit in a byte.
+    pub fn write_indexed(&mut self, op: OpCode, index: usize, span: Span) {
+        debug_assert!(op.operand_len() == 1 && index <= u16::MAX as usize);
+        if index <= u8::MAX as usize {
+            self.write(op, span);
+            self.write_byte(index as u8, span);
+        } else {
+            self.write(OpCode::Wide, span);
+            self.write(op, span);
+            self.write_u16(index as u16, span);
+        }
+    }
+
+    // Adds a constant and writes the instruction that pushes it.
+    pub fn write_constant(&mut self, constant: Constant, span: Span) -> Option<usize> {
+        let index = self.add_constant(constant)?;
+        self.write_indexed(OpCode::Constant, index, span);
+        Some(index)
+    }
+
+    // Gives back the index of a constant, adding it unless the chunk has an
+    // equal one already. Nothing is given back once the chunk is full.
+    pub fn add_constant(&mut self, constant: Constant) -> Option<usize> {
+        let key = constant.key();
+        if let Some(&index) = key.as_ref().and_then(|key| self.constant_indices.get(key)) {
+            return Some(index);
+        }
+        if self.constants.len() >= MAX_CONSTANTS {
+            return None;
+        }
+        let index = self.constants.len();
+        self.constants.push(constant);
+        if let Some(key) = key {
+            self.constant_indices.insert(key, index);
+        }
+        Some(index)
+    }
+
+    // Throws away the code while keeping the constants, so that passes over
+    // the code can write it out again.
+    pub(crate) fn clear_code(&mut self) {
+        self.code.clear();
+        self.spans.clear();
+    }
+
+    pub fn read_byte(&self, offset: usize) -> u8 {
+        self.code[offset]
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.code[offset..offset + 4]);
+        u32::from_be_bytes(bytes)
+    }
+
+    // Jumps are written before the code they jump over, so their operand is
+    // filled in once the target is known.
+    pub fn patch_u16(&mut self, offset: usize, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.code[offset] = high;
+        self.code[offset + 1] = low;
+    }
+
+    // Swaps the opcode at the offset for another one with the same operand.
+    pub fn patch_op(&mut self, offset: usize, op: OpCode) {
+        debug_assert!(self.op_at(offset).map(|o| o.operand_len()) == Some(op.operand_len()));
+        self.code[offset] = op as u8;
+    }
+
+    // The opcode of the instruction at the offset, past any wide prefix.
+    pub fn op_at(&self, offset: usize) -> Option<OpCode> {
+        if self.is_wide(offset) {
+            return OpCode::from_byte(*self.code.get(offset + 1)?);
+        }
+        OpCode::from_byte(*self.code.get(offset)?)
+    }
+
+    pub fn is_wide(&self, offset: usize) -> bool {
+        self.code.get(offset) == Some(&(OpCode::Wide as u8))
+    }
+
+    // The operand of the instruction at the offset, for instructions that
+    // have one.
+    pub fn operand(&self, offset: usize) -> usize {
+        if self.is_wide(offset) {
+            return match self.op_at(offset).map_or(0, |op| op.operand_len()) {
+                2 => self.read_u32(offset + 2) as usize,
+                _ => self.read_u16(offset + 2) as usize,
+            };
+        }
+        match self.op_at(offset).map_or(0, |op| op.operand_len()) {
+            1 => self.read_byte(offset + 1) as usize,
+            2 => self.read_u16(offset + 1) as usize,
+            _ => 0,
+        }
+    }
+
+    // The cases of the switch at the offset, for as many jumps as there are
+    // in the table that follows it.
+    pub fn cases(&self, offset: usize) -> Option<&Cases> {
+        if self.op_at(offset) != Some(OpCode::Switch) {
+            return None;
+        }
+        match self.constants.get(self.operand(offset)) {
+            Some(Constant::Cases(cases)) => Some(cases),
+            _ => None,
+        }
+    }
+
+    // Where the instruction after the one at the offset starts.
+    pub fn next_offset(&self, offset: usize) -> usize {
+        let operand_len = self.op_at(offset).map_or(0, |op| op.operand_len());
+        if self.is_wide(offset) {
+            offset + 2 + operand_len * 2
+        } else {
+            offset + 1 + operand_len
+        }
+    }
+
+    // The offset and opcode of every instruction from the start of the code.
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            chunk: self,
+            offset: 0,
+        }
+    }
+}
+
+pub struct Instructions<'a> {
+    chunk: &'a Chunk,
+    offset: usize,
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = (usize, OpCode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let op = self.chunk.op_at(offset)?;
+        self.offset = self.chunk.next_offset(offset);
+        Some((offset, op))
+    }
+}
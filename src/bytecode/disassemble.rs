@@ -0,0 +1,108 @@
+use crate::bytecode::*;
+
+// Disassembling renders a chunk as text with one instruction per line: its
+// offset, the line it came from, its name and its operand. Constants are
+// shown with their values and jumps with where they go, for example
+//
+//     == main ==
+//     0000    1 constant      0 1.5
+//     0002    | jump_if_false 3 -> 8
+//
+// A line of `|` means the instruction came from the same line as the one
+// before it. Global slots are shown with their names. The chunks of
+// functions in the constants follow the chunk that holds them.
+pub fn disassemble(chunk: &Chunk) -> String {
+    disassemble_chunk(chunk, chunk.globals())
+}
+
+// The functions in a script use the global slots of the script.
+fn disassemble_chunk(chunk: &Chunk, globals: &[String]) -> String {
+    let mut text = format!("== {} ==\n", chunk.name());
+    let mut offset = 0;
+    let mut previous_line = None;
+    while offset < chunk.len() {
+        let line = chunk.line(offset);
+        if previous_line == Some(line) {
+            text.push_str(&format!("{:04}    | ", offset));
+        } else {
+            text.push_str(&format!("{:04} {:>4} ", offset, line));
+        }
+        previous_line = Some(line);
+        let (instruction, next) = instruction(chunk, offset, globals);
+        text.push_str(&instruction);
+        text.push('\n');
+        offset = next;
+    }
+    for constant in chunk.constants() {
+        if let Constant::Function(function) = constant {
+            text.push('\n');
+            text.push_str(&disassemble_chunk(&function.chunk, globals));
+        }
+    }
+    text
+}
+
+// Renders the instruction at the offset without its offset or line, and
+// gives back where the next instruction starts. Global slots only have
+// names in the chunk of a script.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    instruction(chunk, offset, chunk.globals())
+}
+
+fn instruction(chunk: &Chunk, offset: usize, globals: &[String]) -> (String, usize) {
+    let op = match chunk.op_at(offset) {
+        Some(op) => op,
+        None => {
+            let byte = chunk.read_byte(offset);
+            return (format!("unknown 0x{:02x}", byte), offset + 1);
+        }
+    };
+    let next = chunk.next_offset(offset);
+    if next > chunk.len() {
+        return (format!("{} (truncated)", op.name()), chunk.len());
+    }
+    let name = if chunk.is_wide(offset) {
+        format!("wide {}", op.name())
+    } else {
+        String::from(op.name())
+    };
+    if op.operand_len() == 0 {
+        return (name, next);
+    }
+
+    let operand = chunk.operand(offset);
+    let text = match op {
+        // Closures list what they capture after the function.
+        OpCode::Closure => match chunk.constants().get(operand) {
+            Some(constant @ Constant::Function(function)) => {
+                let upvalues: Vec<String> =
+                    function.upvalues.iter().map(|u| u.to_string()).collect();
+                format!(
+                    "{:<13} {} {} [{}]",
+                    name,
+                    operand,
+                    constant,
+                    upvalues.join(", ")
+                )
+            }
+            _ => format!("{:<13} {} (missing)", name, operand),
+        },
+        _ if op.takes_constant() => match chunk.constants().get(operand) {
+            Some(constant) => format!("{:<13} {} {}", name, operand, constant),
+            None => format!("{:<13} {} (missing)", name, operand),
+        },
+        OpCode::DefineSlot | OpCode::GetSlot | OpCode::SetSlot => match globals.get(operand) {
+            Some(global) => format!("{:<13} {} {}", name, operand, global),
+            None => format!("{:<13} {}", name, operand),
+        },
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Try => {
+            format!("{:<13} {} -> {}", name, operand, next + operand)
+        }
+        OpCode::Loop => match next.checked_sub(operand) {
+            Some(target) => format!("{:<13} {} -> {}", name, operand, target),
+            None => format!("{:<13} {} -> (before start)", name, operand),
+        },
+        _ => format!("{:<13} {}", name, operand),
+    };
+    (text, next)
+}
@@ -0,0 +1,733 @@
+use crate::bytecode::*;
+use crate::compile::MAX_LOCALS;
+use crate::error::Error;
+use crate::link::Bundle;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Compiled scripts are saved in `.atc` files so that hosts can compile them
+// ahead of time and load them without the source. A file starts with a
+// magic number and the version of the format, then the strings used by the
+// constants of every chunk as a u32 count followed by each string, so that
+// a string is only stored once however many chunks use it. The chunk comes
+// last:
+//
+//     name         string
+//     globals      u32 count, then the name of every global slot
+//     constants    u32 count, then a tag byte and a value for each
+//     code         u32 count, then the bytes
+//     spans        u32 count, then a run for each
+//     symbols      a byte that is 1 when there are debug symbols, then a
+//                  u32 count of locals and for each its name, slot, start
+//                  and end, and the name of every upvalue
+//
+// A run is the offset of code it starts at followed by the start, end, line
+// and column of its span, each a u32.
+//
+// A function constant holds its name, its arity and minimum arity as u32s,
+// a byte that is 1 when it has a rest parameter, the number of upvalues
+// followed by a byte that is 1 for a local and the index of each, and then
+// its own chunk. A string constant is the index of its string in the table.
+// The cases of a switch are a byte that is 0 for a range, followed by its
+// start as an i64 and its length as a u32, or 1 for sorted cases, followed
+// by a u32 count and a number or string constant for each.
+// Strings are a u32 length followed by UTF-8, ints are an i64 and floats
+// are the bits of the f64. Everything is stored with the high byte first, like operands.
+// Loading checks that the code makes sense, since a file could have come
+// from anywhere. Nothing about when or where a script was compiled is
+// stored, so compiling the same source with the same options always gives
+// the same bytes.
+//
+// Only the chunk of a script has global slots, and the chunks of the
+// functions inside it use the same ones.
+//
+// A bundle of linked modules is stored the same way under its own magic
+// number, with a u32 count of modules after the strings and then the
+// function of every module, in the order they run.
+//
+// The functions of a snapshot of a virtual machine are stored the same way
+// again under a magic number of their own, with a u32 count and then a byte
+// that is 1 for a script, the number of global slots of the script it is
+// in as a u32 and the function itself for each, since the functions inside
+// a script use its slots without having any of their own.
+pub const MAGIC: [u8; 4] = *b"ATC\0";
+pub const BUNDLE_MAGIC: [u8; 4] = *b"ATB\0";
+pub const FUNCTIONS_MAGIC: [u8; 4] = *b"ATF\0";
+pub const FORMAT_VERSION: u16 = 16;
+pub const EXTENSION: &str = "atc";
+pub const BUNDLE_EXTENSION: &str = "atb";
+
+const FLOAT_TAG: u8 = 0;
+const STRING_TAG: u8 = 1;
+const FUNCTION_TAG: u8 = 2;
+const CASES_TAG: u8 = 3;
+const INT_TAG: u8 = 4;
+
+impl Chunk {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut writer = Writer::new(MAGIC, &[self]);
+        writer.chunk(self);
+        writer.bytes
+    }
+
+    // The file is only used in errors.
+    pub fn deserialize(file: &str, bytes: &[u8]) -> Result<Chunk, Error> {
+        let mut reader = Reader::new(file, bytes, MAGIC, "not a compiled script")?;
+        let chunk = reader.chunk(0, 1, true)?;
+        if reader.offset != bytes.len() {
+            return Err(reader.error("unexpected bytes after the chunk"));
+        }
+        Ok(chunk)
+    }
+}
+
+impl Bundle {
+    pub fn serialize(&self) -> Vec<u8> {
+        let chunks: Vec<&Chunk> = self.modules.iter().map(|m| &m.chunk).collect();
+        let mut writer = Writer::new(BUNDLE_MAGIC, &chunks);
+        writer.u32(self.modules.len());
+        for module in &self.modules {
+            writer.function(module);
+        }
+        writer.bytes
+    }
+
+    pub fn deserialize(file: &str, bytes: &[u8]) -> Result<Bundle, Error> {
+        let mut reader = Reader::new(file, bytes, BUNDLE_MAGIC, "not a bundle of modules")?;
+        let mut modules = Vec::new();
+        for _ in 0..reader.count(1)? {
+            let module = reader.function(true)?;
+            if !module.upvalues.is_empty() {
+                return Err(reader.error("invalid module in bundle"));
+            }
+            modules.push(module);
+        }
+        if modules.is_empty() {
+            return Err(reader.error("a bundle needs an entry point"));
+        }
+        if reader.offset != bytes.len() {
+            return Err(reader.error("unexpected bytes after the bundle"));
+        }
+        Ok(Bundle { modules })
+    }
+}
+
+// Every function with the number of global slots of its script.
+pub(crate) fn serialize_functions(functions: &[(&Function, usize)]) -> Vec<u8> {
+    let chunks: Vec<&Chunk> = functions
+        .iter()
+        .map(|(function, _)| &function.chunk)
+        .collect();
+    let mut writer = Writer::new(FUNCTIONS_MAGIC, &chunks);
+    writer.u32(functions.len());
+    for (function, globals) in functions {
+        writer.bytes.push(!function.chunk.globals.is_empty() as u8);
+        writer.u32(*globals);
+        writer.function(function);
+    }
+    writer.bytes
+}
+
+pub(crate) fn deserialize_functions(
+    file: &str,
+    bytes: &[u8],
+) -> Result<Vec<(Function, usize)>, Error> {
+    let wrong = "not the functions of a snapshot";
+    let mut reader = Reader::new(file, bytes, FUNCTIONS_MAGIC, wrong)?;
+    let mut functions = Vec::new();
+    for _ in 0..reader.count(1)? {
+        let script = match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(reader.error("invalid function in compiled script")),
+        };
+        let globals = reader.u32()?;
+        reader.globals = globals;
+        let function = reader.function(script)?;
+        if script && function.chunk.globals.len() != globals {
+            return Err(reader.error("invalid function in compiled script"));
+        }
+        functions.push((function, globals));
+    }
+    if reader.offset != bytes.len() {
+        return Err(reader.error("unexpected bytes after the functions"));
+    }
+    Ok(functions)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    // The index of every string in the table.
+    strings: HashMap<Arc<str>, usize>,
+}
+
+impl Writer {
+    // Writes the header and the table of every string the chunks use.
+    fn new(magic: [u8; 4], chunks: &[&Chunk]) -> Self {
+        let mut writer = Writer {
+            bytes: Vec::new(),
+            strings: HashMap::new(),
+        };
+        writer.bytes.extend_from_slice(&magic);
+        writer
+            .bytes
+            .extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        let mut strings = Vec::new();
+        for chunk in chunks {
+            writer.collect_strings(chunk, &mut strings);
+        }
+        writer.u32(strings.len());
+        for string in &strings {
+            writer.string(string);
+        }
+        writer
+    }
+
+    // Gives every string of the constants of the chunk and the chunks inside
+    // it an index in the table, in the order they are first found.
+    fn collect_strings(&mut self, chunk: &Chunk, table: &mut Vec<Arc<str>>) {
+        for constant in &chunk.constants {
+            self.collect_string(constant, table);
+        }
+    }
+
+    fn collect_string(&mut self, constant: &Constant, table: &mut Vec<Arc<str>>) {
+        match constant {
+            Constant::String(s) => {
+                self.strings.entry(s.clone()).or_insert_with(|| {
+                    table.push(s.clone());
+                    table.len() - 1
+                });
+            }
+            Constant::Function(function) => self.collect_strings(&function.chunk, table),
+            Constant::Cases(Cases::Sorted(values)) => {
+                for value in values {
+                    self.collect_string(value, table);
+                }
+            }
+            Constant::Int(_) | Constant::Float(_) | Constant::Cases(Cases::Range { .. }) => {}
+        }
+    }
+
+    fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.string(&function.name);
+        self.u32(function.arity);
+        self.u32(function.min_arity);
+        self.bytes.push(function.rest as u8);
+        self.u32(function.upvalues.len());
+        for upvalue in &function.upvalues {
+            self.bytes.push(upvalue.local as u8);
+            self.u32(upvalue.index);
+        }
+        self.chunk(&function.chunk);
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::Int(n) => {
+                self.bytes.push(INT_TAG);
+                self.bytes.extend_from_slice(&n.to_be_bytes());
+            }
+            Constant::Float(n) => {
+                self.bytes.push(FLOAT_TAG);
+                self.bytes.extend_from_slice(&number_bits(*n).to_be_bytes());
+            }
+            Constant::String(s) => {
+                self.bytes.push(STRING_TAG);
+                self.u32(self.strings[s]);
+            }
+            Constant::Function(function) => {
+                self.bytes.push(FUNCTION_TAG);
+                self.function(function);
+            }
+            Constant::Cases(Cases::Range { start, len }) => {
+                self.bytes.push(CASES_TAG);
+                self.bytes.push(0);
+                self.bytes.extend_from_slice(&start.to_be_bytes());
+                self.u32(*len);
+            }
+            Constant::Cases(Cases::Sorted(values)) => {
+                self.bytes.push(CASES_TAG);
+                self.bytes.push(1);
+                self.u32(values.len());
+                for value in values {
+                    self.constant(value);
+                }
+            }
+        }
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.string(&chunk.name);
+        self.u32(chunk.globals.len());
+        for name in &chunk.globals {
+            self.string(name);
+        }
+        self.u32(chunk.constants.len());
+        for constant in &chunk.constants {
+            self.constant(constant);
+        }
+        self.u32(chunk.code.len());
+        self.bytes.extend_from_slice(&chunk.code);
+        self.u32(chunk.spans.len());
+        for run in &chunk.spans {
+            self.u32(run.offset);
+            self.u32(run.span.start);
+            self.u32(run.span.end);
+            self.u32(run.span.line as usize);
+            self.u32(run.span.column as usize);
+        }
+        match &chunk.symbols {
+            Some(symbols) => {
+                self.bytes.push(1);
+                self.u32(symbols.locals.len());
+                for local in &symbols.locals {
+                    self.string(&local.name);
+                    self.u32(local.slot);
+                    self.u32(local.start);
+                    self.u32(local.end);
+                }
+                self.u32(symbols.upvalues.len());
+                for name in &symbols.upvalues {
+                    self.string(name);
+                }
+            }
+            None => self.bytes.push(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    file: &'a str,
+    bytes: &'a [u8],
+    offset: usize,
+    strings: Vec<Arc<str>>,
+    // The number of global slots of the script being read.
+    globals: usize,
+}
+
+impl<'a> Reader<'a> {
+    // Checks the header and reads the table of strings.
+    fn new(file: &'a str, bytes: &'a [u8], magic: [u8; 4], wrong: &str) -> Result<Self, Error> {
+        let mut reader = Reader {
+            file,
+            bytes,
+            offset: 0,
+            strings: Vec::new(),
+            globals: 0,
+        };
+        if reader.take(magic.len())? != magic {
+            return Err(reader.error(wrong));
+        }
+        let version = u16::from_be_bytes([reader.u8()?, reader.u8()?]);
+        if version != FORMAT_VERSION {
+            let msg = format!(
+                "compiled with format version {} but only version {} can be loaded",
+                version, FORMAT_VERSION
+            );
+            return Err(reader.error(&msg[..]));
+        }
+        // The string constants of every chunk are shared out of the table.
+        for _ in 0..reader.count(4)? {
+            let string: Arc<str> = Arc::from(reader.string()?);
+            reader.strings.push(string);
+        }
+        Ok(reader)
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        Error::new(msg, self.file, 0, 0)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        match self.bytes.get(self.offset..self.offset + len) {
+            Some(bytes) => {
+                self.offset += len;
+                Ok(bytes)
+            }
+            None => Err(self.error("unexpected end of compiled script")),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    // Counts are checked against what is left so that a broken file can't
+    // make loading allocate a huge amount of memory.
+    fn count(&mut self, item_len: usize) -> Result<usize, Error> {
+        let count = self.u32()?;
+        if count.saturating_mul(item_len) > self.bytes.len() - self.offset {
+            return Err(self.error("unexpected end of compiled script"));
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.count(1)?;
+        match std::str::from_utf8(self.take(len)?) {
+            Ok(s) => Ok(String::from(s)),
+            Err(_) => Err(self.error("invalid string in compiled script")),
+        }
+    }
+
+    fn function(&mut self, script: bool) -> Result<Function, Error> {
+        let name = self.string()?;
+        let arity = self.u32()?;
+        let min_arity = self.u32()?;
+        let rest = match self.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(self.error("invalid function in compiled script")),
+        };
+        // The compiler never gives a function more parameters than it has
+        // slots for.
+        if min_arity > arity || arity + rest as usize >= MAX_LOCALS {
+            return Err(self.error("invalid function in compiled script"));
+        }
+        let mut upvalues = Vec::new();
+        for _ in 0..self.count(5)? {
+            let local = match self.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(self.error("invalid function in compiled script")),
+            };
+            let index = self.u32()?;
+            upvalues.push(Upvalue { local, index });
+        }
+        let slots = 1 + arity + rest as usize;
+        let chunk = self.chunk(upvalues.len(), slots, script)?;
+        Ok(Function {
+            name,
+            arity,
+            min_arity,
+            rest,
+            upvalues,
+            chunk,
+        })
+    }
+
+    fn symbols(&mut self, len: usize, upvalues: usize) -> Result<DebugSymbols, Error> {
+        let mut symbols = DebugSymbols::default();
+        for _ in 0..self.count(16)? {
+            let name = self.string()?;
+            let (slot, start, end) = (self.u32()?, self.u32()?, self.u32()?);
+            if start > end || end > len {
+                return Err(self.error("the debug symbols do not match the code"));
+            }
+            symbols.locals.push(LocalSymbol {
+                name,
+                slot,
+                start,
+                end,
+            });
+        }
+        for _ in 0..self.count(4)? {
+            symbols.upvalues.push(self.string()?);
+        }
+        if symbols.upvalues.len() != upvalues {
+            return Err(self.error("the debug symbols do not match the code"));
+        }
+        Ok(symbols)
+    }
+
+    fn constant(&mut self) -> Result<Constant, Error> {
+        Ok(match self.u8()? {
+            INT_TAG => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                Constant::Int(i64::from_be_bytes(bytes))
+            }
+            FLOAT_TAG => {
+                let bytes = self.take(8)?;
+                let mut bits = [0; 8];
+                bits.copy_from_slice(bytes);
+                Constant::Float(f64::from_bits(u64::from_be_bytes(bits)))
+            }
+            STRING_TAG => {
+                let index = self.u32()?;
+                match self.strings.get(index) {
+                    Some(s) => Constant::String(s.clone()),
+                    None => return Err(self.error("invalid string in compiled script")),
+                }
+            }
+            FUNCTION_TAG => Constant::Function(Box::new(self.function(false)?)),
+            CASES_TAG => {
+                let cases = match self.u8()? {
+                    0 => {
+                        let mut bytes = [0; 8];
+                        bytes.copy_from_slice(self.take(8)?);
+                        Cases::Range {
+                            start: i64::from_be_bytes(bytes),
+                            len: self.u32()?,
+                        }
+                    }
+                    1 => {
+                        let mut values = Vec::new();
+                        for _ in 0..self.count(5)? {
+                            values.push(self.constant()?);
+                        }
+                        Cases::Sorted(values)
+                    }
+                    _ => return Err(self.error("invalid cases in compiled script")),
+                };
+                if !cases.is_valid() {
+                    return Err(self.error("invalid cases in compiled script"));
+                }
+                Constant::Cases(cases)
+            }
+            tag => {
+                let msg = format!("unknown constant tag {}", tag);
+                return Err(self.error(&msg[..]));
+            }
+        })
+    }
+
+    // The code can only use as many upvalues as its function captures.
+    fn chunk(&mut self, upvalues: usize, slots: usize, script: bool) -> Result<Chunk, Error> {
+        let mut chunk = Chunk::new(&self.string()?[..]);
+        for _ in 0..self.count(4)? {
+            chunk.globals.push(self.string()?);
+        }
+        if script {
+            self.globals = chunk.globals.len();
+        } else if !chunk.globals.is_empty() {
+            return Err(self.error("only a script can have global slots"));
+        }
+        for _ in 0..self.count(1)? {
+            let constant = self.constant()?;
+            if chunk.constants.len() >= MAX_CONSTANTS {
+                return Err(self.error("too many constants in compiled script"));
+            }
+            // The index only finds the first of two equal constants, which
+            // is fine since both are the same value.
+            if let Some(key) = constant.key() {
+                chunk
+                    .constant_indices
+                    .entry(key)
+                    .or_insert(chunk.constants.len());
+            }
+            chunk.constants.push(constant);
+        }
+        let len = self.count(1)?;
+        chunk.code = self.take(len)?.to_vec();
+        for _ in 0..self.count(20)? {
+            let offset = self.u32()?;
+            let (start, end) = (self.u32()?, self.u32()?);
+            let (line, column) = (self.u32()? as u32, self.u32()? as u32);
+            // Runs must cover the code from its start, in order.
+            let expected = match chunk.spans.last() {
+                Some(run) => run.offset < offset && offset < chunk.code.len(),
+                None => offset == 0,
+            };
+            if !expected {
+                return Err(self.error("the span table does not match the code"));
+            }
+            chunk.spans.push(SpanRun {
+                offset,
+                span: Span::new(start, end, line, column),
+            });
+        }
+        if chunk.spans.is_empty() != chunk.code.is_empty() {
+            return Err(self.error("the span table does not match the code"));
+        }
+        chunk.symbols = match self.u8()? {
+            0 => None,
+            1 => Some(self.symbols(chunk.len(), upvalues)?),
+            _ => return Err(self.error("invalid debug symbols in compiled script")),
+        };
+        let checked =
+            check_code(&chunk, upvalues, self.globals).and_then(|()| check_stack(&chunk, slots));
+        if let Err(msg) = checked {
+            let msg = format!("invalid code in {}: {}", chunk.name, msg);
+            return Err(self.error(&msg[..]));
+        }
+        Ok(chunk)
+    }
+}
+
+// Makes sure every instruction is known and complete, every constant,
+// upvalue and global slot it uses is there, every closure only captures
+// upvalues that are there and every jump lands on the start of an
+// instruction.
+fn check_code(chunk: &Chunk, upvalues: usize, globals: usize) -> Result<(), String> {
+    let mut starts = vec![false; chunk.len() + 1];
+    let mut jumps = Vec::new();
+    let mut offset = 0;
+    while offset < chunk.len() {
+        starts[offset] = true;
+        let op = match chunk.op_at(offset) {
+            Some(OpCode::Wide) | None => {
+                return Err(format!("unknown instruction at {}", offset));
+            }
+            Some(op) => op,
+        };
+        if chunk.is_wide(offset) && !op.can_be_wide() {
+            return Err(format!("{} at {} cannot be wide", op.name(), offset));
+        }
+        let next = chunk.next_offset(offset);
+        if next > chunk.len() {
+            return Err(format!("{} at {} is cut off", op.name(), offset));
+        }
+        let operand = chunk.operand(offset);
+        if op.takes_constant() && operand >= chunk.constants.len() {
+            return Err(format!(
+                "{} at {} uses a missing constant",
+                op.name(),
+                offset
+            ));
+        }
+        let captures = match chunk.constants.get(operand) {
+            Some(Constant::Function(function)) => Some(&function.upvalues),
+            _ => None,
+        };
+        match (op, captures) {
+            (OpCode::Closure, None) => {
+                return Err(format!("closure at {} needs a function", offset));
+            }
+            // The closure captures the upvalues of the function running.
+            (OpCode::Closure, Some(captures))
+                if captures.iter().any(|c| !c.local && c.index >= upvalues) =>
+            {
+                return Err(format!("closure at {} uses a missing upvalue", offset));
+            }
+            // A constant function is made into a closure that captures
+            // nothing.
+            (OpCode::Constant, Some(captures)) if !captures.is_empty() => {
+                return Err(format!("constant at {} needs a closure", offset));
+            }
+            _ => {}
+        }
+        if matches!(op, OpCode::DefineSlot | OpCode::GetSlot | OpCode::SetSlot)
+            && operand >= globals
+        {
+            return Err(format!("{} at {} uses a missing global", op.name(), offset));
+        }
+        if op == OpCode::Switch {
+            check_table(chunk, offset)?;
+        }
+        if matches!(op, OpCode::GetUpvalue | OpCode::SetUpvalue) && operand >= upvalues {
+            return Err(format!(
+                "{} at {} uses a missing upvalue",
+                op.name(),
+                offset
+            ));
+        }
+        match op {
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Try => {
+                jumps.push((offset, Some(next + operand)))
+            }
+            OpCode::Loop => jumps.push((offset, next.checked_sub(operand))),
+            _ => {}
+        }
+        offset = next;
+    }
+    starts[chunk.len()] = true;
+    for (offset, target) in jumps {
+        match target {
+            Some(target) if target < starts.len() && starts[target] => {}
+            _ => return Err(format!("the jump at {} goes nowhere", offset)),
+        }
+    }
+    Ok(())
+}
+
+// Follows every path through the code with the number of values on the
+// stack of the frame, which starts out holding the function and its
+// parameters. No instruction can take more values than there are or use a
+// local past them, every path to an instruction has to leave the same
+// number, and none can run off the end of the code. Code that nothing
+// reaches never runs, so it isn't checked.
+fn check_stack(chunk: &Chunk, slots: usize) -> Result<(), String> {
+    let mut depths = vec![None; chunk.len()];
+    let mut pending = vec![(0, slots)];
+    while let Some((offset, depth)) = pending.pop() {
+        match depths.get(offset) {
+            None => return Err(String::from("the code runs off the end")),
+            Some(&Some(seen)) if seen == depth => continue,
+            Some(Some(_)) => return Err(format!("the stack is uneven at {}", offset)),
+            Some(None) => depths[offset] = Some(depth),
+        }
+        let op = chunk.op_at(offset).expect("every instruction was checked");
+        let operand = chunk.operand(offset);
+        let next = chunk.next_offset(offset);
+        let (pops, pushes) = op.stack_effect(operand);
+        if pops > depth {
+            return Err(format!(
+                "{} at {} takes more than the stack holds",
+                op.name(),
+                offset
+            ));
+        }
+        let missing = match op {
+            OpCode::GetLocal | OpCode::SetLocal => operand >= depth,
+            OpCode::Closure => match &chunk.constants()[operand] {
+                Constant::Function(function) => function
+                    .upvalues
+                    .iter()
+                    .any(|upvalue| upvalue.local && upvalue.index >= depth),
+                _ => false,
+            },
+            _ => false,
+        };
+        if missing {
+            return Err(format!("{} at {} uses a missing local", op.name(), offset));
+        }
+        let after = depth - pops + pushes;
+        match op {
+            OpCode::Jump => pending.push((next + operand, after)),
+            OpCode::Loop => pending.push((next - operand, after)),
+            OpCode::JumpIfFalse => {
+                pending.push((next + operand, after));
+                pending.push((next, after));
+            }
+            OpCode::Try => {
+                pending.push((next + operand, after + 1));
+                pending.push((next, after));
+            }
+            OpCode::Switch => {
+                let mut entry = next;
+                for _ in 0..chunk.cases(offset).map_or(0, Cases::len) {
+                    pending.push((entry, after));
+                    entry = chunk.next_offset(entry);
+                }
+                pending.push((entry, after));
+            }
+            OpCode::Return | OpCode::TailCall | OpCode::Throw | OpCode::Rethrow => {}
+            _ => pending.push((next, after)),
+        }
+    }
+    Ok(())
+}
+
+// A switch needs cases and a table with a jump of the same width for each
+// of them.
+fn check_table(chunk: &Chunk, offset: usize) -> Result<(), String> {
+    let cases = match chunk.cases(offset) {
+        Some(cases) => cases,
+        None => return Err(format!("switch at {} needs cases", offset)),
+    };
+    let mut entry = chunk.next_offset(offset);
+    let wide = chunk.is_wide(entry);
+    for _ in 0..cases.len() {
+        if chunk.op_at(entry) != Some(OpCode::Jump) || chunk.is_wide(entry) != wide {
+            return Err(format!("switch at {} needs a jump for every case", offset));
+        }
+        entry = chunk.next_offset(entry);
+    }
+    Ok(())
+}
@@ -0,0 +1,1875 @@
+pub mod inline;
+mod layout;
+pub mod peephole;
+pub mod register;
+
+use crate::ast::visit::*;
+use crate::ast::*;
+use crate::bytecode::*;
+use crate::desugar::desugar;
+use crate::error::Error;
+use crate::fold::fold_constants;
+use crate::link::HOST_MODULES;
+use crate::parse::INITIALIZER_NAME;
+use crate::project::module_path;
+use crate::prune::prune_dead_code;
+use crate::resolve::resolve_program;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+// Compiling turns a program into bytecode. The program becomes a function
+// with no parameters whose chunk runs the top level statements, and every
+// function in it becomes a constant of the chunk it is declared in. Top
+// level declarations are globals, everything else lives in stack slots.
+pub fn compile(program: &Program) -> Result<Function, Vec<Error>> {
+    compile_with(program, OptLevel::default())
+}
+
+// How hard the compiler tries to make the code faster. Optimizing makes the
+// code harder to follow in the disassembler, which is why it can be turned
+// off.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum OptLevel {
+    None,
+    // Inlining of small functions, constant folding and pruning of dead
+    // code before compiling, and the peephole pass after.
+    #[default]
+    Basic,
+}
+
+// Which machine the code is made for. The stack machine is the one scripts
+// run on, the register machine needs fewer instructions for the same work.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Backend {
+    #[default]
+    Stack,
+    Register,
+}
+
+// Features are parts of the language that a host has to turn on, since
+// scripts can't be trusted with them everywhere.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Features {
+    // Whether scripts can import the fs module to read and write files.
+    pub fs: bool,
+}
+
+// Everything about how a program is compiled. Hosts pick between compiling
+// quickly, compiling to less code and keeping what they need to debug the
+// scripts that run.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CompilerOptions {
+    pub opt_level: OptLevel,
+    pub backend: Backend,
+    // Keeps the span of source every instruction came from and the names of
+    // the variables. Without it the code is smaller, but errors at runtime
+    // can't say where they happened and debuggers only see numbered slots.
+    pub debug_info: bool,
+    // Makes code that can never run an error rather than something that is
+    // quietly left out.
+    pub strict: bool,
+    pub features: Features,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::default(),
+            backend: Backend::default(),
+            debug_info: true,
+            strict: false,
+            features: Features::default(),
+        }
+    }
+}
+
+pub enum Compiled {
+    Stack(Function),
+    Register(register::Function),
+}
+
+pub fn compile_with(program: &Program, opt_level: OptLevel) -> Result<Function, Vec<Error>> {
+    let options = CompilerOptions {
+        opt_level,
+        ..CompilerOptions::default()
+    };
+    compile_stack(program, &options)
+}
+
+pub fn compile_to(
+    program: &Program,
+    opt_level: OptLevel,
+    backend: Backend,
+) -> Result<Compiled, Vec<Error>> {
+    let options = CompilerOptions {
+        opt_level,
+        backend,
+        ..CompilerOptions::default()
+    };
+    compile_with_options(program, &options)
+}
+
+// Register code is lowered from stack code, so everything the stack backend
+// can compile the register backend can too.
+pub fn compile_with_options(
+    program: &Program,
+    options: &CompilerOptions,
+) -> Result<Compiled, Vec<Error>> {
+    let function = compile_stack(program, options)?;
+    Ok(match options.backend {
+        Backend::Stack => Compiled::Stack(function),
+        Backend::Register => {
+            let mut function = register::lower(&function);
+            if options.opt_level >= OptLevel::Basic {
+                register::optimize(&mut function);
+                register::optimize_loops(&mut function);
+            }
+            Compiled::Register(function)
+        }
+    })
+}
+
+fn compile_stack(program: &Program, options: &CompilerOptions) -> Result<Function, Vec<Error>> {
+    // The resolver's errors stop a script whichever way it is run, so the
+    // checker and the compiler always agree on them.
+    let resolved = resolve_program(program).diagnostics;
+    let mut program = program.clone();
+    desugar(&mut program);
+    // The warnings are left for the checker to report, the compiler only
+    // reports errors unless it is strict.
+    let warnings = if options.opt_level >= OptLevel::Basic {
+        // Inlined code has no frame of its own, so it is only done when
+        // nothing needs to see the frames the script would have had.
+        if !options.debug_info {
+            inline::inline_functions(&mut program);
+        }
+        fold_constants(&mut program);
+        prune_dead_code(&mut program)
+    } else if options.strict {
+        prune_dead_code(&mut program.clone())
+    } else {
+        Vec::new()
+    };
+
+    let mut assigned = Assigned::default();
+    assigned.visit_program(&program);
+    let slots = global_slots(&program);
+    let mut compiler = Compiler {
+        file: &program.name[..],
+        opt_level: options.opt_level,
+        features: options.features,
+        frames: Vec::new(),
+        strings: Interner::new(),
+        globals: global_signatures(&program, &assigned.names),
+        slots: slots
+            .iter()
+            .enumerate()
+            .map(|(slot, name)| (name.clone(), slot))
+            .collect(),
+        assigned: assigned.names,
+        errors: resolved.into_iter().filter(|d| !d.is_warning()).collect(),
+    };
+    if options.strict {
+        for warning in warnings {
+            let error = Error::new(
+                warning.message(),
+                warning.file_name(),
+                warning.line(),
+                warning.column(),
+            );
+            compiler.errors.push(error);
+        }
+    }
+    compiler.begin_function(&program.name[..], FunctionKind::Function);
+    for stmt in &program.body {
+        compiler.statement(stmt);
+    }
+    let end = program.body.last().map_or(Span::default(), |s| s.span);
+    let exports = program.exports();
+    if !exports.is_empty() {
+        compiler.return_exports(&exports, end);
+    }
+    let mut function = compiler.end_function(end);
+    if slots.len() > MAX_GLOBALS {
+        compiler.error("too many globals in one script", end);
+    }
+    function.chunk.set_globals(slots);
+    if !compiler.errors.is_empty() {
+        return Err(compiler.errors);
+    }
+
+    if options.opt_level >= OptLevel::Basic {
+        peephole::optimize(&mut function.chunk);
+    }
+    if !options.debug_info {
+        strip_debug_info(&mut function);
+    }
+    Ok(function)
+}
+
+fn strip_debug_info(function: &mut Function) {
+    function.chunk.strip_spans();
+    function.chunk.strip_symbols();
+    for function in function.chunk.functions_mut() {
+        strip_debug_info(function);
+    }
+}
+
+// Temporaries start with a character that can never be part of a name in a
+// script, the same as the ones made up by desugaring.
+const SUBJECT: &str = "$subject";
+const VALUE: &str = "$value";
+const EXCEPTION: &str = "$exception";
+const RETURNED: &str = "$returned";
+
+// The most global slots a script can have, since a wide operand is two
+// bytes.
+const MAX_GLOBALS: usize = u16::MAX as usize + 1;
+// The same goes for the slots of a function, the first of which holds the
+// function itself and the next ones its parameters.
+pub(crate) const MAX_LOCALS: usize = u16::MAX as usize + 1;
+
+// The fewest cases a match needs to be compiled to a switch. Comparing with
+// fewer cases in turn is about as quick.
+pub const MIN_SWITCH_CASES: usize = 4;
+
+struct Local {
+    name: String,
+    depth: usize,
+    // Whether a closure uses the local, in which case it is closed over
+    // instead of popped when it goes out of scope.
+    captured: bool,
+    // The pops break and continue wrote for the local before anything
+    // captured it, which have to close it over as well if something does.
+    pops: Vec<usize>,
+    // The parameters of the function the local always holds, if it does.
+    signature: Option<Signature>,
+    // The local's entry in the debug symbols, which temporaries don't have.
+    symbol: Option<usize>,
+}
+
+// What a function accepts, for checking calls to it while compiling and
+// when they run.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct Signature {
+    pub(crate) arity: usize,
+    pub(crate) min_arity: usize,
+    pub(crate) rest: bool,
+}
+
+impl Signature {
+    pub(crate) fn accepts(&self, count: usize) -> bool {
+        count >= self.min_arity && (self.rest || count <= self.arity)
+    }
+
+    // The error for a call with the wrong number of arguments.
+    pub(crate) fn mismatch(&self, name: &str, count: usize) -> String {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let expected = if self.rest {
+            format!(
+                "at least {} argument{}",
+                self.min_arity,
+                plural(self.min_arity)
+            )
+        } else if self.min_arity < self.arity {
+            format!("{} to {} arguments", self.min_arity, self.arity)
+        } else {
+            format!("{} argument{}", self.arity, plural(self.arity))
+        };
+        format!("{} expects {} but got {}", name, expected, count)
+    }
+}
+
+// The signature of a declared function, unless decorators replace it with
+// whatever they give back.
+fn signature(decl: &FunctionDecl) -> Option<Signature> {
+    if !decl.decorators.is_empty() {
+        return None;
+    }
+    Some(Signature {
+        arity: decl.params.len(),
+        min_arity: decl.params.len() - decl.defaults.len(),
+        rest: decl.rest.is_some(),
+    })
+}
+
+// Finds every name that is assigned to anywhere in the program. A function
+// in a variable with one of these names could be swapped for anything, so
+// calls to it are left for the runtime to check.
+#[derive(Default)]
+pub(crate) struct Assigned {
+    pub(crate) names: HashSet<String>,
+}
+
+impl Visitor for Assigned {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Assign { target, .. } => {
+                if let ExprKind::Variable(name) = &target.kind {
+                    self.names.insert(name.clone());
+                }
+            }
+            ExprKind::Destructure { pattern, .. } => {
+                for name in pattern.names() {
+                    self.names.insert(name.name.clone());
+                }
+            }
+            _ => {}
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let StmtKind::MultipleAssign { targets, .. } = &stmt.kind {
+            for name in targets.iter().flat_map(|target| target.names()) {
+                self.names.insert(name.name.clone());
+            }
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+// Every name declared at the top level, along with the function for the
+// ones that are function declarations.
+fn top_level_names(program: &Program) -> Vec<(&Identifier, Option<&FunctionDecl>)> {
+    let mut names = Vec::new();
+    for stmt in &program.body {
+        let stmt = match &stmt.kind {
+            StmtKind::Export(decl) => decl,
+            _ => stmt,
+        };
+        match &stmt.kind {
+            StmtKind::Function(decl) => names.push((&decl.name, Some(&**decl))),
+            StmtKind::Var { name, .. } => names.push((name, None)),
+            StmtKind::Destructure { pattern, .. } => {
+                names.extend(pattern.names().into_iter().map(|name| (name, None)))
+            }
+            StmtKind::Class(decl) => names.push((&decl.name, None)),
+            StmtKind::Import(decl) => names.push((&decl.alias, None)),
+            _ => {}
+        }
+    }
+    names
+}
+
+// The functions declared at the top level, which calls anywhere in the
+// program can be checked against. A name declared twice is left out.
+fn global_signatures(program: &Program, assigned: &HashSet<String>) -> HashMap<String, Signature> {
+    let mut declared = HashMap::new();
+    let mut signatures = HashMap::new();
+    for (name, decl) in top_level_names(program) {
+        *declared.entry(&name.name[..]).or_insert(0) += 1;
+        if let Some(signature) = decl.and_then(signature) {
+            signatures.insert(name.name.clone(), signature);
+        }
+    }
+    signatures.retain(|name, _| declared[&name[..]] == 1 && !assigned.contains(name));
+    signatures
+}
+
+// The globals declared at the top level get a slot each, in the order they
+// are first declared.
+fn global_slots(program: &Program) -> Vec<String> {
+    let mut slots: Vec<String> = Vec::new();
+    for (name, _) in top_level_names(program) {
+        if !slots.contains(&name.name) {
+            slots.push(name.name.clone());
+        }
+    }
+    slots
+}
+
+struct Loop {
+    label: Option<String>,
+    // The number of locals when the loop started, which are the ones that
+    // stay when jumping out of it.
+    locals: usize,
+    // Where continue goes, when that is known before the body. Do while
+    // loops only know once the body is done.
+    start: Option<usize>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+// A try statement whose body, or catch block when there is a finally block,
+// is being compiled. Jumping out of it ends its handler and runs its finally
+// block on the way.
+#[derive(Clone)]
+struct Try {
+    // The number of loops and locals when it started.
+    loops: usize,
+    locals: usize,
+    finally: Option<Vec<Stmt>>,
+}
+
+// Methods are called with their instance in the first slot, which is
+// `this`, and an initializer gives it back however it returns.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum FunctionKind {
+    Function,
+    Method,
+    Initializer,
+}
+
+// The state of a function that is being compiled. Functions nest, so the
+// compiler keeps a stack of these.
+struct Frame {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    depth: usize,
+    loops: Vec<Loop>,
+    tries: Vec<Try>,
+    // Forward jumps that ended up too long for their operand, mapped to the
+    // offset they land on. They are made wide once the function is done.
+    long_jumps: HashMap<usize, usize>,
+    symbols: DebugSymbols,
+}
+
+enum Variable {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
+
+struct Compiler<'a> {
+    file: &'a str,
+    opt_level: OptLevel,
+    features: Features,
+    frames: Vec<Frame>,
+    // Shared by every function in the program.
+    strings: Interner,
+    globals: HashMap<String, Signature>,
+    slots: HashMap<String, usize>,
+    assigned: HashSet<String>,
+    errors: Vec<Error>,
+}
+
+impl Compiler<'_> {
+    fn error(&mut self, msg: &str, span: Span) {
+        self.errors
+            .push(Error::new(msg, self.file, span.line, span.column));
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames
+            .last_mut()
+            .expect("no function is being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.frame().function.chunk
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) {
+        self.chunk().write(op, span);
+    }
+
+    fn emit_indexed(&mut self, op: OpCode, index: usize, span: Span) {
+        self.chunk().write_indexed(op, index, span);
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) -> usize {
+        match self.chunk().add_constant(constant) {
+            Some(index) => index,
+            None => {
+                self.error("too many constants in one function", span);
+                0
+            }
+        }
+    }
+
+    fn emit_constant(&mut self, constant: Constant, span: Span) {
+        let index = self.constant(constant, span);
+        self.emit_indexed(OpCode::Constant, index, span);
+    }
+
+    // Strings go through the interner so that every chunk shares them.
+    fn string_constant(&mut self, s: &str, span: Span) -> usize {
+        let s = self.strings.intern(s);
+        self.constant(Constant::String(s), span)
+    }
+
+    fn emit_string(&mut self, s: &str, span: Span) {
+        let index = self.string_constant(s, span);
+        self.emit_indexed(OpCode::Constant, index, span);
+    }
+
+    // Writes a jump whose operand is filled in by `patch_jump` and gives
+    // back where the operand is.
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit(op, span);
+        self.chunk().write_u16(u16::MAX, span);
+        self.chunk().len() - 2
+    }
+
+    // Points the jump with its operand at the offset to the end of the code.
+    fn patch_jump(&mut self, operand: usize) {
+        let target = self.chunk().len();
+        let distance = target - (operand + 2);
+        if distance > u16::MAX as usize {
+            self.frame().long_jumps.insert(operand - 1, target);
+        } else {
+            self.chunk().patch_u16(operand, distance as u16);
+        }
+    }
+
+    // Loops jump back to code that is already there, so they know straight
+    // away whether they need to be wide.
+    fn emit_loop(&mut self, start: usize, span: Span) {
+        let end = self.chunk().len() + 3;
+        if end - start <= u16::MAX as usize {
+            self.emit(OpCode::Loop, span);
+            self.chunk().write_u16((end - start) as u16, span);
+        } else {
+            self.emit(OpCode::Wide, span);
+            self.emit(OpCode::Loop, span);
+            self.chunk().write_u32((end + 3 - start) as u32, span);
+        }
+    }
+
+    fn begin_function(&mut self, name: &str, kind: FunctionKind) {
+        // The first slot holds the function being called, or the instance
+        // for a method.
+        let mut symbols = DebugSymbols::default();
+        let this = if kind == FunctionKind::Function {
+            String::new()
+        } else {
+            symbols.locals.push(LocalSymbol {
+                name: String::from("this"),
+                slot: 0,
+                start: 0,
+                end: 0,
+            });
+            String::from("this")
+        };
+        let locals = vec![Local {
+            symbol: symbols.locals.first().map(|_| 0),
+            name: this,
+            depth: 0,
+            captured: false,
+            pops: Vec::new(),
+            signature: None,
+        }];
+        self.frames.push(Frame {
+            function: Function::new(name),
+            kind,
+            locals,
+            depth: 0,
+            loops: Vec::new(),
+            tries: Vec::new(),
+            long_jumps: HashMap::new(),
+            symbols,
+        });
+    }
+
+    fn end_function(&mut self, span: Span) -> Function {
+        // Falling off the end returns null.
+        self.emit_return(span);
+        let mut frame = self.frames.pop().expect("no function is being compiled");
+        // The locals still in scope last until the end of the code.
+        let end = frame.function.chunk.len();
+        for local in &frame.locals {
+            if let Some(symbol) = local.symbol {
+                frame.symbols.locals[symbol].end = end;
+            }
+        }
+        frame.function.chunk.set_symbols(frame.symbols);
+        if !frame.long_jumps.is_empty() {
+            let chunk = &mut frame.function.chunk;
+            let instructions = layout::decode(chunk, &frame.long_jumps)
+                .expect("the compiler only writes whole instructions");
+            layout::encode(chunk, &instructions);
+        }
+        frame.function
+    }
+
+    // Returns without a value, which is null unless the function is an
+    // initializer.
+    fn emit_return(&mut self, span: Span) {
+        if self.frame().kind == FunctionKind::Initializer {
+            self.emit_indexed(OpCode::GetLocal, 0, span);
+        } else {
+            self.emit(OpCode::Null, span);
+        }
+        self.emit(OpCode::Return, span);
+    }
+
+    // A module gives back a map of the values of its exports once it has
+    // run, which is what importing it evaluates to.
+    fn return_exports(&mut self, exports: &[&Identifier], span: Span) {
+        for name in exports {
+            self.emit_string(&name.name, name.span);
+            self.emit_global(OpCode::GetGlobal, &name.name, name.span);
+        }
+        self.count(OpCode::Map, exports.len(), "exports in a module", span);
+        self.emit(OpCode::Return, span);
+    }
+
+    fn is_global(&self) -> bool {
+        self.frames.len() == 1 && self.frames[0].depth == 0
+    }
+
+    fn begin_scope(&mut self) {
+        self.frame().depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        let frame = self.frame();
+        frame.depth -= 1;
+        let depth = frame.depth;
+        while let Some(local) = self.frame().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let local = self.frame().locals.pop().unwrap();
+            self.pop_local(&local, span);
+        }
+    }
+
+    fn pop_local(&mut self, local: &Local, span: Span) {
+        if let Some(symbol) = local.symbol {
+            let end = self.chunk().len();
+            self.frame().symbols.locals[symbol].end = end;
+        }
+        if local.captured {
+            self.emit(OpCode::CloseUpvalue, span);
+        } else {
+            self.emit(OpCode::Pop, span);
+        }
+    }
+
+    // Makes the value on top of the stack a local and gives back its slot.
+    fn declare_local(&mut self, name: &str, span: Span) -> usize {
+        let frame = self.frame();
+        let slot = frame.locals.len();
+        let depth = frame.depth;
+        let symbol = if name.starts_with('$') {
+            None
+        } else {
+            frame.symbols.locals.push(LocalSymbol {
+                name: String::from(name),
+                slot,
+                start: frame.function.chunk.len(),
+                end: frame.function.chunk.len(),
+            });
+            Some(frame.symbols.locals.len() - 1)
+        };
+        frame.locals.push(Local {
+            name: String::from(name),
+            depth,
+            captured: false,
+            pops: Vec::new(),
+            signature: None,
+            symbol,
+        });
+        if slot >= MAX_LOCALS {
+            self.error("too many locals in one function", span);
+        }
+        slot
+    }
+
+    // Takes the value on top of the stack as the value of a new variable.
+    fn define(&mut self, name: &str, span: Span) {
+        if self.is_global() {
+            self.emit_global(OpCode::DefineGlobal, name, span);
+        } else {
+            self.declare_local(name, span);
+        }
+    }
+
+    // Globals declared at the top level are in their slot, and any others
+    // are found by name.
+    fn emit_global(&mut self, op: OpCode, name: &str, span: Span) {
+        match self.slots.get(name) {
+            Some(&slot) => {
+                let op = match op {
+                    OpCode::DefineGlobal => OpCode::DefineSlot,
+                    OpCode::GetGlobal => OpCode::GetSlot,
+                    _ => OpCode::SetSlot,
+                };
+                self.emit_indexed(op, slot.min(u16::MAX as usize), span);
+            }
+            None => {
+                let index = self.string_constant(name, span);
+                self.emit_indexed(op, index, span);
+            }
+        }
+    }
+
+    fn resolve(&mut self, name: &str, span: Span) -> Variable {
+        let current = self.frames.len() - 1;
+        if let Some(slot) = self.find_local(current, name) {
+            return Variable::Local(slot);
+        }
+        // Names at the top level of the script are globals, so the locals
+        // of enclosing functions are all real locals.
+        match self.resolve_upvalue(current, name, span) {
+            Some(index) => Variable::Upvalue(index),
+            None => Variable::Global,
+        }
+    }
+
+    fn find_local(&self, frame: usize, name: &str) -> Option<usize> {
+        self.frames[frame]
+            .locals
+            .iter()
+            .rposition(|l| l.name == name)
+    }
+
+    // Finds the name in the enclosing functions, capturing it in every
+    // function on the way so that each closure can hand it to the next.
+    fn resolve_upvalue(&mut self, frame: usize, name: &str, span: Span) -> Option<usize> {
+        let enclosing = frame.checked_sub(1)?;
+        let upvalue = match self.find_local(enclosing, name) {
+            Some(slot) => {
+                self.capture(enclosing, slot);
+                Upvalue {
+                    local: true,
+                    index: slot,
+                }
+            }
+            None => Upvalue {
+                local: false,
+                index: self.resolve_upvalue(enclosing, name, span)?,
+            },
+        };
+        let upvalues = &mut self.frames[frame].function.upvalues;
+        if let Some(index) = upvalues.iter().position(|u| *u == upvalue) {
+            return Some(index);
+        }
+        upvalues.push(upvalue);
+        let index = upvalues.len() - 1;
+        let symbols = &mut self.frames[frame].symbols;
+        symbols.upvalues.push(String::from(name));
+        if index > u16::MAX as usize {
+            self.error("too many captured variables in one function", span);
+        }
+        Some(index)
+    }
+
+    fn capture(&mut self, frame: usize, slot: usize) {
+        let frame = &mut self.frames[frame];
+        let local = &mut frame.locals[slot];
+        local.captured = true;
+        for offset in local.pops.drain(..) {
+            frame.function.chunk.patch_op(offset, OpCode::CloseUpvalue);
+        }
+    }
+
+    fn get_variable(&mut self, name: &str, span: Span) {
+        match self.resolve(name, span) {
+            Variable::Local(slot) => self.emit_indexed(OpCode::GetLocal, slot, span),
+            Variable::Global => self.emit_global(OpCode::GetGlobal, name, span),
+            Variable::Upvalue(index) => self.emit_indexed(OpCode::GetUpvalue, index, span),
+        }
+    }
+
+    // Stores the value on top of the stack in a variable, leaving it there.
+    fn set_variable(&mut self, name: &str, span: Span) {
+        match self.resolve(name, span) {
+            Variable::Local(slot) => self.emit_indexed(OpCode::SetLocal, slot, span),
+            Variable::Global => self.emit_global(OpCode::SetGlobal, name, span),
+            Variable::Upvalue(index) => self.emit_indexed(OpCode::SetUpvalue, index, span),
+        }
+    }
+
+    // Functions that capture nothing are plain constants, the others are
+    // made into a closure every time the code runs.
+    fn emit_function(&mut self, function: Function, span: Span) {
+        let captures = !function.upvalues.is_empty();
+        let index = self.constant(Constant::Function(Box::new(function)), span);
+        if captures {
+            self.emit_indexed(OpCode::Closure, index, span);
+        } else {
+            self.emit_indexed(OpCode::Constant, index, span);
+        }
+    }
+
+    fn unsupported(&mut self, what: &str, span: Span) {
+        let msg = format!("{} are not supported by the compiler yet", what);
+        self.error(&msg[..], span);
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::Expression(Expr {
+                kind: ExprKind::Destructure { pattern, value },
+                ..
+            }) => self.destructuring_assignment(pattern, value, span),
+            StmtKind::Expression(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Pop, span);
+            }
+            StmtKind::Var { name, init, .. } => {
+                match init {
+                    Some(init) => self.expression(init),
+                    None => self.emit(OpCode::Null, span),
+                }
+                self.define(&name.name[..], name.span);
+            }
+            StmtKind::Destructure { pattern, init, .. } => {
+                self.expression(init);
+                let global = self.is_global();
+                self.declare_pattern(pattern, global);
+            }
+            StmtKind::MultipleAssign { targets, values } => {
+                self.multiple_assignment(targets, values, span)
+            }
+            StmtKind::Block(body) => {
+                self.begin_scope();
+                for stmt in body {
+                    self.statement(stmt);
+                }
+                self.end_scope(span);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.statement(then_branch);
+                let end_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop, span);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(end_jump);
+            }
+            StmtKind::While {
+                label,
+                condition,
+                body,
+            } => {
+                let start = self.chunk().len();
+                self.begin_loop(label, Some(start));
+                self.expression(condition);
+                let exit = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.statement(body);
+                self.emit_loop(start, span);
+                self.patch_jump(exit);
+                self.emit(OpCode::Pop, span);
+                self.end_loop();
+            }
+            StmtKind::DoWhile {
+                label,
+                body,
+                condition,
+            } => {
+                let start = self.chunk().len();
+                self.begin_loop(label, None);
+                self.statement(body);
+                let continues = std::mem::take(&mut self.current_loop().continues);
+                for continue_jump in continues {
+                    self.patch_jump(continue_jump);
+                }
+                self.expression(condition);
+                let exit = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.emit_loop(start, span);
+                self.patch_jump(exit);
+                self.emit(OpCode::Pop, span);
+                self.end_loop();
+            }
+            // Desugaring has already turned these into while loops.
+            StmtKind::For { .. } => unreachable!("for loops are desugared"),
+            StmtKind::Match { subject, arms } => self.match_statement(subject, arms, span),
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => self.try_statement(body, catch, finally, span),
+            StmtKind::Throw(value) => {
+                self.expression(value);
+                self.emit(OpCode::Throw, span);
+            }
+            StmtKind::Return(value) if !self.frame().tries.is_empty() => {
+                self.return_from_try(value, span)
+            }
+            StmtKind::Return(Some(value)) => self.tail_return(value, span),
+            StmtKind::Return(None) => self.emit_return(span),
+            StmtKind::Break(label) => self.jump_out_of_loop(label, true, span),
+            StmtKind::Continue(label) => self.jump_out_of_loop(label, false, span),
+            StmtKind::Function(decl) => self.function_declaration(decl),
+            StmtKind::Class(decl) => self.class_declaration(decl, span),
+            // Modules are found by their path, which the linker or the
+            // loader maps to the module. Host modules keep their name.
+            StmtKind::Import(decl) => {
+                let path = match &decl.source {
+                    ImportSource::Module(name) if HOST_MODULES.contains(&&name.name[..]) => {
+                        if name.name == "fs" && !self.features.fs {
+                            self.error("the fs module is not enabled", name.span);
+                        }
+                        name.name.clone()
+                    }
+                    source => module_path(self.file, source),
+                };
+                let index = self.string_constant(&path, span);
+                self.emit_indexed(OpCode::Import, index, span);
+                self.define(&decl.alias.name, decl.alias.span);
+            }
+            StmtKind::Export(decl) => self.statement(decl),
+        }
+    }
+
+    // Returns the value of the expression. A call whose result is returned
+    // straight away is a tail call, and so are the calls in both branches of
+    // a conditional that is returned.
+    fn tail_return(&mut self, value: &Expr, span: Span) {
+        match &value.kind {
+            ExprKind::Call(call) if call.names.is_empty() => {
+                self.call(call, OpCode::TailCall, value.span)
+            }
+            ExprKind::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, value.span);
+                self.emit(OpCode::Pop, value.span);
+                self.tail_return(then_branch, span);
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop, value.span);
+                self.tail_return(else_branch, span);
+            }
+            _ => {
+                self.expression(value);
+                self.emit(OpCode::Return, span);
+            }
+        }
+    }
+
+    fn begin_loop(&mut self, label: &Option<Identifier>, start: Option<usize>) {
+        let frame = self.frame();
+        let locals = frame.locals.len();
+        frame.loops.push(Loop {
+            label: label.as_ref().map(|l| l.name.clone()),
+            locals,
+            start,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    fn current_loop(&mut self) -> &mut Loop {
+        self.frame().loops.last_mut().expect("not in a loop")
+    }
+
+    fn end_loop(&mut self) {
+        let finished = self.frame().loops.pop().expect("not in a loop");
+        for break_jump in finished.breaks {
+            self.patch_jump(break_jump);
+        }
+    }
+
+    fn jump_out_of_loop(&mut self, label: &Option<Identifier>, is_break: bool, span: Span) {
+        let loops = &self.frame().loops;
+        let index = match label {
+            Some(label) => loops
+                .iter()
+                .rposition(|l| l.label.as_ref() == Some(&label.name)),
+            None => loops.len().checked_sub(1),
+        };
+        // The parser only allows break and continue inside of loops.
+        let index = match index {
+            Some(index) => index,
+            None => return,
+        };
+
+        let tries = &self.frame().tries;
+        let keep = tries
+            .iter()
+            .position(|t| t.loops > index)
+            .unwrap_or(tries.len());
+        self.exit_tries(keep, span);
+
+        // Locals declared inside of the loop are popped on the way out, but
+        // they are still in scope for the code that follows the jump.
+        let first = self.frame().loops[index].locals;
+        for slot in (first..self.frame().locals.len()).rev() {
+            let offset = self.chunk().len();
+            let local = &mut self.frame().locals[slot];
+            if local.captured {
+                self.emit(OpCode::CloseUpvalue, span);
+            } else {
+                local.pops.push(offset);
+                self.emit(OpCode::Pop, span);
+            }
+        }
+        let start = self.frame().loops[index].start;
+        match (is_break, start) {
+            (false, Some(start)) => self.emit_loop(start, span),
+            _ => {
+                let jump = self.emit_jump(OpCode::Jump, span);
+                let target = &mut self.frame().loops[index];
+                if is_break {
+                    target.breaks.push(jump);
+                } else {
+                    target.continues.push(jump);
+                }
+            }
+        }
+    }
+
+    // The handler catches anything thrown in the body, which leaves the
+    // exception on the stack as the variable of the catch block. A finally
+    // block is copied to every way out of the statement, including one more
+    // handler that runs it before throwing the exception on.
+    fn try_statement(
+        &mut self,
+        body: &[Stmt],
+        catch: &Option<CatchClause>,
+        finally: &Option<Vec<Stmt>>,
+        span: Span,
+    ) {
+        let handler = self.begin_try(finally, span);
+        self.begin_scope();
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.end_scope(span);
+        self.end_try(finally, span);
+        let mut ends = vec![self.emit_jump(OpCode::Jump, span)];
+        self.patch_jump(handler);
+
+        if let Some(catch) = catch {
+            let rethrow = finally
+                .as_ref()
+                .map(|_| self.begin_try(finally, catch.span));
+            self.begin_scope();
+            match &catch.binding {
+                Some(binding) => self.declare_local(&binding.name, binding.span),
+                None => self.declare_local(EXCEPTION, catch.span),
+            };
+            for stmt in &catch.body {
+                self.statement(stmt);
+            }
+            self.end_scope(catch.span);
+            if let Some(rethrow) = rethrow {
+                self.end_try(finally, span);
+                ends.push(self.emit_jump(OpCode::Jump, span));
+                self.patch_jump(rethrow);
+            }
+        }
+        if let Some(finally) = finally {
+            // The exception the catch block caught is still under the one
+            // thrown from it.
+            self.begin_scope();
+            if catch.is_some() {
+                self.declare_local(EXCEPTION, span);
+            }
+            self.declare_local(EXCEPTION, span);
+            self.finally_block(finally, span);
+            self.emit(OpCode::Rethrow, span);
+            self.forget_scope();
+        }
+        for end in ends {
+            self.patch_jump(end);
+        }
+    }
+
+    fn begin_try(&mut self, finally: &Option<Vec<Stmt>>, span: Span) -> usize {
+        let handler = self.emit_jump(OpCode::Try, span);
+        let frame = self.frame();
+        let loops = frame.loops.len();
+        let locals = frame.locals.len();
+        frame.tries.push(Try {
+            loops,
+            locals,
+            finally: finally.clone(),
+        });
+        handler
+    }
+
+    fn end_try(&mut self, finally: &Option<Vec<Stmt>>, span: Span) {
+        self.frame().tries.pop();
+        self.emit(OpCode::EndTry, span);
+        if let Some(finally) = finally {
+            self.finally_block(finally, span);
+        }
+    }
+
+    fn finally_block(&mut self, finally: &[Stmt], span: Span) {
+        self.begin_scope();
+        for stmt in finally {
+            self.statement(stmt);
+        }
+        self.end_scope(span);
+    }
+
+    // Ends the handlers of the try statements from the one given up,
+    // innermost first, running their finally blocks on the way. A finally
+    // block can't see the locals declared inside of its try.
+    fn exit_tries(&mut self, keep: usize, span: Span) {
+        let tries = self.frame().tries.clone();
+        for (i, exited) in tries.iter().enumerate().skip(keep).rev() {
+            self.frame().tries.truncate(i);
+            self.emit(OpCode::EndTry, span);
+            if let Some(finally) = &exited.finally {
+                let hidden: Vec<String> = self.frame().locals[exited.locals..]
+                    .iter_mut()
+                    .map(|local| std::mem::replace(&mut local.name, String::from("$hidden")))
+                    .collect();
+                self.finally_block(finally, span);
+                for (local, name) in self.frame().locals[exited.locals..].iter_mut().zip(hidden) {
+                    local.name = name;
+                }
+            }
+        }
+        self.frame().tries = tries;
+    }
+
+    // The value is worked out before the finally blocks run, waiting in a
+    // local meanwhile, so a return from inside a try is never a tail call.
+    fn return_from_try(&mut self, value: &Option<Expr>, span: Span) {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.exit_tries(0, span);
+                self.emit_return(span);
+                return;
+            }
+        };
+        self.expression(value);
+        self.begin_scope();
+        let slot = self.declare_local(RETURNED, span);
+        self.exit_tries(0, span);
+        self.emit_indexed(OpCode::GetLocal, slot, span);
+        self.emit(OpCode::Return, span);
+        self.forget_scope();
+    }
+
+    // Ends a scope that the code never falls out of, so nothing is popped.
+    fn forget_scope(&mut self) {
+        let frame = self.frame();
+        frame.depth -= 1;
+        let depth = frame.depth;
+        frame.locals.retain(|local| local.depth <= depth);
+    }
+
+    // Every case is compared with the subject in turn, and the default runs
+    // when none of them match, wherever it is written.
+    fn match_statement(&mut self, subject: &Expr, arms: &[MatchArm], span: Span) {
+        if self.opt_level >= OptLevel::Basic {
+            if let Some((cases, targets)) = self.switch_cases(arms) {
+                self.switch(subject, arms, cases, &targets, span);
+                return;
+            }
+        }
+        self.begin_scope();
+        self.expression(subject);
+        let slot = self.declare_local(SUBJECT, subject.span);
+
+        let mut ends = Vec::new();
+        for arm in arms.iter().filter(|arm| !arm.is_default()) {
+            let mut matched = Vec::new();
+            for value in &arm.values {
+                self.emit_indexed(OpCode::GetLocal, slot, value.span);
+                self.expression(value);
+                self.emit(OpCode::Equal, value.span);
+                let next = self.emit_jump(OpCode::JumpIfFalse, value.span);
+                self.emit(OpCode::Pop, value.span);
+                matched.push(self.emit_jump(OpCode::Jump, value.span));
+                self.patch_jump(next);
+                self.emit(OpCode::Pop, value.span);
+            }
+            let next_arm = self.emit_jump(OpCode::Jump, arm.span);
+            for jump in matched {
+                self.patch_jump(jump);
+            }
+            self.arm_body(&arm.body, arm.span);
+            ends.push(self.emit_jump(OpCode::Jump, arm.span));
+            self.patch_jump(next_arm);
+        }
+        if let Some(arm) = arms.iter().find(|arm| arm.is_default()) {
+            self.arm_body(&arm.body, arm.span);
+        }
+        for end in ends {
+            self.patch_jump(end);
+        }
+        self.end_scope(span);
+    }
+
+    // The cases of a match where every value is a number or a string, along
+    // with the arm that every jump in the table goes to, or nothing for the
+    // ones that go to the default. A value that is in more than one arm
+    // belongs to the first, which is the one comparing would find.
+    //
+    // Ints that fill at least half of the range from the smallest
+    // to the largest make a range, where a number is found straight away.
+    // Other cases are sorted and found with a binary search.
+    fn switch_cases(&mut self, arms: &[MatchArm]) -> Option<(Cases, Vec<Option<usize>>)> {
+        let mut values: Vec<(Constant, usize)> = Vec::new();
+        for (i, arm) in arms.iter().enumerate() {
+            for value in &arm.values {
+                let constant = match &value.kind {
+                    ExprKind::Int(n) => Constant::Int(*n),
+                    ExprKind::Float(n) if !n.is_nan() => Constant::Float(*n),
+                    ExprKind::String(s) => Constant::String(self.strings.intern(s)),
+                    _ => return None,
+                };
+                if !values
+                    .iter()
+                    .any(|(v, _)| compare_cases(v, &constant) == Ordering::Equal)
+                {
+                    values.push((constant, i));
+                }
+            }
+        }
+        if values.len() < MIN_SWITCH_CASES {
+            return None;
+        }
+        values.sort_by(|a, b| compare_cases(&a.0, &b.0));
+
+        let integers: Option<Vec<i64>> = values
+            .iter()
+            .map(|(value, _)| match value {
+                Constant::Int(n) => Some(*n),
+                _ => None,
+            })
+            .collect();
+        if let Some(integers) = integers {
+            let start = integers[0];
+            let span = integers[integers.len() - 1].checked_sub(start);
+            let len = span
+                .map_or(usize::MAX, |span| span as usize)
+                .saturating_add(1);
+            if len <= values.len() * 2 {
+                let mut targets = vec![None; len];
+                for (n, (_, arm)) in integers.iter().zip(&values) {
+                    targets[(n - start) as usize] = Some(*arm);
+                }
+                return Some((Cases::Range { start, len }, targets));
+            }
+        }
+        let targets = values.iter().map(|(_, arm)| Some(*arm)).collect();
+        let values = values.into_iter().map(|(value, _)| value).collect();
+        Some((Cases::Sorted(values), targets))
+    }
+
+    // A switch looks the subject up instead of comparing it with every case,
+    // and goes on at the jump in its table for the case it finds:
+    //
+    //     switch 0 <cases 1..5>
+    //     jump -> arm for 1       one jump for every case
+    //     ...
+    //     jump -> default         when none of them match
+    fn switch(
+        &mut self,
+        subject: &Expr,
+        arms: &[MatchArm],
+        cases: Cases,
+        targets: &[Option<usize>],
+        span: Span,
+    ) {
+        self.expression(subject);
+        let index = self.constant(Constant::Cases(cases), span);
+        self.emit_indexed(OpCode::Switch, index, span);
+        let table: Vec<usize> = targets
+            .iter()
+            .map(|_| self.emit_jump(OpCode::Jump, span))
+            .collect();
+        let missed = self.emit_jump(OpCode::Jump, span);
+
+        let mut ends = Vec::new();
+        for (i, arm) in arms.iter().enumerate() {
+            if arm.is_default() {
+                continue;
+            }
+            for (&jump, &target) in table.iter().zip(targets) {
+                if target == Some(i) {
+                    self.patch_jump(jump);
+                }
+            }
+            self.arm_body(&arm.body, arm.span);
+            ends.push(self.emit_jump(OpCode::Jump, arm.span));
+        }
+        for (&jump, target) in table.iter().zip(targets) {
+            if target.is_none() {
+                self.patch_jump(jump);
+            }
+        }
+        self.patch_jump(missed);
+        if let Some(arm) = arms.iter().find(|arm| arm.is_default()) {
+            self.arm_body(&arm.body, arm.span);
+        }
+        for end in ends {
+            self.patch_jump(end);
+        }
+    }
+
+    fn arm_body(&mut self, body: &[Stmt], span: Span) {
+        self.begin_scope();
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.end_scope(span);
+    }
+
+    // Decorators are worked out first and then called with the function,
+    // the one closest to it first.
+    //
+    // A local function is declared before its body is compiled so that it
+    // can call itself. The slot it gets is the one the decorated function
+    // ends up in.
+    fn function_declaration(&mut self, decl: &FunctionDecl) {
+        let name = &decl.name.name[..];
+        let global = self.is_global();
+        if !global {
+            let slot = self.declare_local(name, decl.name.span);
+            if !self.assigned.contains(name) {
+                self.frame().locals[slot].signature = signature(decl);
+            }
+        }
+        for decorator in &decl.decorators {
+            self.expression(decorator);
+        }
+        let function = self.function(
+            name,
+            FunctionKind::Function,
+            &decl.params,
+            &decl.defaults,
+            &decl.rest,
+            &decl.body,
+            decl.span,
+        );
+        self.emit_function(function, decl.span);
+        for decorator in decl.decorators.iter().rev() {
+            self.emit_indexed(OpCode::Call, 1, decorator.span);
+        }
+        if global {
+            self.define(name, decl.name.span);
+        }
+    }
+
+    // A class is given its methods before its decorators are called with it,
+    // the same as a function. The superclass is kept in a local named super
+    // for the methods to capture, which nothing else can use since super is
+    // a keyword. A local class is declared before any of it is compiled, so
+    // that its methods can use it, and takes its place once it is done.
+    fn class_declaration(&mut self, decl: &ClassDecl, span: Span) {
+        let name = &decl.name.name[..];
+        let slot = if self.is_global() {
+            None
+        } else {
+            self.emit(OpCode::Null, decl.name.span);
+            Some(self.declare_local(name, decl.name.span))
+        };
+        self.begin_scope();
+        if let Some(superclass) = &decl.superclass {
+            self.get_variable(&superclass.name, superclass.span);
+            self.declare_local("super", superclass.span);
+        }
+        for decorator in &decl.decorators {
+            self.expression(decorator);
+        }
+        let index = self.string_constant(name, decl.name.span);
+        self.emit_indexed(OpCode::Class, index, decl.name.span);
+        if let Some(superclass) = &decl.superclass {
+            self.get_variable("super", superclass.span);
+            self.emit(OpCode::Inherit, superclass.span);
+        }
+
+        let methods = decl.initializer.iter().chain(&decl.methods);
+        let members = methods
+            .map(|method| (OpCode::Method, method))
+            .chain(decl.getters.iter().map(|getter| (OpCode::Getter, getter)))
+            .chain(decl.setters.iter().map(|setter| (OpCode::Setter, setter)));
+        for (op, method) in members {
+            let kind = if op == OpCode::Method && method.name.name == INITIALIZER_NAME {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            for decorator in &method.decorators {
+                self.expression(decorator);
+            }
+            let function = self.function(
+                &format!("{}.{}", name, method.name.name),
+                kind,
+                &method.params,
+                &method.defaults,
+                &method.rest,
+                &method.body,
+                method.span,
+            );
+            self.emit_function(function, method.span);
+            for decorator in method.decorators.iter().rev() {
+                self.emit_indexed(OpCode::Call, 1, decorator.span);
+            }
+            let index = self.string_constant(&method.name.name, method.name.span);
+            self.emit_indexed(op, index, method.name.span);
+        }
+
+        for decorator in decl.decorators.iter().rev() {
+            self.emit_indexed(OpCode::Call, 1, decorator.span);
+        }
+        match slot {
+            Some(slot) => {
+                self.emit_indexed(OpCode::SetLocal, slot, decl.name.span);
+                self.emit(OpCode::Pop, decl.name.span);
+            }
+            None => self.emit_global(OpCode::DefineGlobal, name, decl.name.span),
+        }
+        self.end_scope(span);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn function(
+        &mut self,
+        name: &str,
+        kind: FunctionKind,
+        params: &[Identifier],
+        defaults: &[Expr],
+        rest: &Option<Identifier>,
+        body: &[Stmt],
+        span: Span,
+    ) -> Function {
+        self.begin_function(name, kind);
+        let frame = self.frame();
+        frame.function.arity = params.len();
+        frame.function.min_arity = params.len() - defaults.len();
+        frame.function.rest = rest.is_some();
+        for param in params.iter().chain(rest) {
+            self.declare_local(&param.name[..], param.span);
+        }
+
+        // Parameters that are left out are null, and so are the ones that
+        // get their default.
+        let first_default = params.len() - defaults.len();
+        for (i, default) in defaults.iter().enumerate() {
+            let slot = 1 + first_default + i;
+            let span = default.span;
+            self.emit_indexed(OpCode::GetLocal, slot, span);
+            self.emit(OpCode::Null, span);
+            self.emit(OpCode::Equal, span);
+            let skip = self.emit_jump(OpCode::JumpIfFalse, span);
+            self.emit(OpCode::Pop, span);
+            self.expression(default);
+            self.emit_indexed(OpCode::SetLocal, slot, span);
+            self.emit(OpCode::Pop, span);
+            let end = self.emit_jump(OpCode::Jump, span);
+            self.patch_jump(skip);
+            self.emit(OpCode::Pop, span);
+            self.patch_jump(end);
+        }
+
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.end_function(span)
+    }
+
+    // A variable gets the value on top of the stack, otherwise the value is
+    // kept in a temporary so that its parts can be taken out of it.
+    fn declare_pattern(&mut self, pattern: &Pattern, global: bool) {
+        let name = match &pattern.kind {
+            PatternKind::Name(name) => name,
+            PatternKind::Target(_) => {
+                self.error("invalid declaration target", pattern.span);
+                return;
+            }
+            _ => {
+                let slot = self.declare_local(VALUE, pattern.span);
+                self.each_part(pattern, slot, |compiler, part| {
+                    compiler.declare_pattern(part, global)
+                });
+                // Temporaries of locals are stuck below the locals, but the
+                // globals have been taken off the stack already.
+                if global {
+                    self.frame().locals.pop();
+                    self.emit(OpCode::Pop, pattern.span);
+                }
+                return;
+            }
+        };
+        if global {
+            self.emit_global(OpCode::DefineGlobal, &name.name, name.span);
+        } else {
+            self.declare_local(&name.name[..], name.span);
+        }
+    }
+
+    // Pushes each part of the value in the slot and hands it to `part` along
+    // with the pattern for it.
+    fn each_part<F>(&mut self, pattern: &Pattern, slot: usize, mut part: F)
+    where
+        F: FnMut(&mut Self, &Pattern),
+    {
+        match &pattern.kind {
+            PatternKind::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.emit_indexed(OpCode::GetLocal, slot, item.span);
+                    self.emit_constant(Constant::Int(i as i64), item.span);
+                    self.emit(OpCode::GetIndex, item.span);
+                    part(self, item);
+                }
+            }
+            PatternKind::Map(entries) => {
+                for entry in entries {
+                    self.emit_indexed(OpCode::GetLocal, slot, entry.span);
+                    self.map_key(&entry.key, entry.span);
+                    self.emit(OpCode::GetIndex, entry.span);
+                    part(self, &entry.pattern);
+                }
+            }
+            PatternKind::Name(_) | PatternKind::Target(_) => {}
+        }
+    }
+
+    fn destructuring_assignment(&mut self, pattern: &Pattern, value: &Expr, span: Span) {
+        self.begin_scope();
+        self.expression(value);
+        let slot = self.declare_local(VALUE, value.span);
+        self.assign_pattern(pattern, slot);
+        self.end_scope(span);
+    }
+
+    // Every value is worked out before anything is stored, so they are kept
+    // in temporaries until then.
+    fn multiple_assignment(&mut self, targets: &[Pattern], values: &[Expr], span: Span) {
+        self.begin_scope();
+        let mut slots = Vec::new();
+        for value in values {
+            self.expression(value);
+            slots.push(self.declare_local(VALUE, value.span));
+        }
+        if values.len() == 1 && targets.len() > 1 {
+            let list = Pattern::new(PatternKind::List(targets.to_vec()), span);
+            self.assign_pattern(&list, slots[0]);
+        } else {
+            for (target, slot) in targets.iter().zip(slots) {
+                self.assign_pattern(target, slot);
+            }
+        }
+        self.end_scope(span);
+    }
+
+    // Stores the value in the slot into the pattern.
+    fn assign_pattern(&mut self, pattern: &Pattern, slot: usize) {
+        let target = match &pattern.kind {
+            PatternKind::Name(name) => {
+                self.emit_indexed(OpCode::GetLocal, slot, name.span);
+                self.set_variable(&name.name[..], name.span);
+                self.emit(OpCode::Pop, name.span);
+                return;
+            }
+            PatternKind::Target(target) => target,
+            PatternKind::List(_) | PatternKind::Map(_) => {
+                self.each_part(pattern, slot, |compiler, part| {
+                    let slot = compiler.declare_local(VALUE, part.span);
+                    compiler.assign_pattern(part, slot);
+                    compiler.frame().locals.pop();
+                    compiler.emit(OpCode::Pop, part.span);
+                });
+                return;
+            }
+        };
+        let span = target.span;
+        let load = |compiler: &mut Self| compiler.emit_indexed(OpCode::GetLocal, slot, span);
+        self.store(target, load);
+        self.emit(OpCode::Pop, span);
+    }
+
+    // Stores a value pushed by `load` into an assignment target, leaving the
+    // value on the stack. Objects and indexes are worked out before it.
+    fn store<F>(&mut self, target: &Expr, load: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        let span = target.span;
+        match &target.kind {
+            ExprKind::Variable(name) => {
+                load(self);
+                self.set_variable(&name[..], span);
+            }
+            ExprKind::Member { object, name } => {
+                self.expression(object);
+                load(self);
+                let index = self.string_constant(&name.name[..], name.span);
+                self.emit_indexed(OpCode::SetProperty, index, span);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                load(self);
+                self.emit(OpCode::SetIndex, span);
+            }
+            _ => self.error("invalid assignment target", span),
+        }
+    }
+
+    fn map_key(&mut self, key: &MapKey, span: Span) {
+        match key {
+            MapKey::Identifier(name) => self.emit_string(&name.name[..], name.span),
+            MapKey::String(s) => self.emit_string(&s[..], span),
+            MapKey::Computed(expr) => self.expression(expr),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Int(n) => self.emit_constant(Constant::Int(*n), span),
+            ExprKind::Float(n) => self.emit_constant(Constant::Float(*n), span),
+            ExprKind::String(s) => self.emit_string(&s[..], span),
+            ExprKind::Bool(true) => self.emit(OpCode::True, span),
+            ExprKind::Bool(false) => self.emit(OpCode::False, span),
+            ExprKind::Null => self.emit(OpCode::Null, span),
+            ExprKind::This => self.get_variable("this", span),
+            ExprKind::Super(method) => {
+                self.get_variable("this", span);
+                self.get_variable("super", span);
+                let index = self.string_constant(&method.name[..], method.span);
+                self.emit_indexed(OpCode::GetSuper, index, span);
+            }
+            ExprKind::Variable(name) => self.get_variable(&name[..], span),
+            ExprKind::Unary { op, operand } => {
+                self.expression(operand);
+                let op = match op {
+                    UnaryOp::Negate => OpCode::Negate,
+                    UnaryOp::Not => OpCode::Not,
+                    UnaryOp::BitNot => OpCode::BitNot,
+                };
+                self.emit(op, span);
+            }
+            ExprKind::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => {
+                self.expression(left);
+                let end = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.expression(right);
+                self.patch_jump(end);
+            }
+            ExprKind::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+            } => {
+                self.expression(left);
+                let right_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                let end = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(right_jump);
+                self.emit(OpCode::Pop, span);
+                self.expression(right);
+                self.patch_jump(end);
+            }
+            ExprKind::Binary { op, left, right } => {
+                self.expression(left);
+                self.expression(right);
+                self.binary_op(*op, span);
+            }
+            ExprKind::Assign {
+                op: None,
+                target,
+                value,
+            } => self.store(target, |compiler| compiler.expression(value)),
+            ExprKind::Assign {
+                op: Some(op),
+                target,
+                value,
+            } => self.compound_assignment(*op, target, value, span),
+            ExprKind::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.expression(then_branch);
+                let end = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop, span);
+                self.expression(else_branch);
+                self.patch_jump(end);
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.expression(start);
+                self.expression(end);
+                self.emit_indexed(OpCode::Range, *inclusive as usize, span);
+            }
+            ExprKind::Call(call) => {
+                if !call.names.is_empty() {
+                    self.unsupported("named arguments", call.names[0].span);
+                }
+                self.call(call, OpCode::Call, span);
+            }
+            ExprKind::Member { object, name } => {
+                self.expression(object);
+                // Errors are reported at the name, rather than at the start
+                // of a chain of them that could be a whole line long.
+                let index = self.string_constant(&name.name[..], name.span);
+                self.emit_indexed(OpCode::GetProperty, index, name.span);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(OpCode::GetIndex, span);
+            }
+            ExprKind::Slice { object, start, end } => {
+                self.expression(object);
+                for bound in [start, end].iter() {
+                    match bound {
+                        Some(bound) => self.expression(bound),
+                        None => self.emit(OpCode::Null, span),
+                    }
+                }
+                self.emit(OpCode::Slice, span);
+            }
+            ExprKind::Destructure { .. } => self.error(
+                "destructuring assignments can only be used as statements",
+                span,
+            ),
+            // The parts are joined in one go rather than added one by one,
+            // which would make a new string for every part.
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    match part {
+                        StringPart::Literal(text) => self.emit_string(text, span),
+                        StringPart::Expr(expr) => self.expression(expr),
+                    }
+                }
+                self.count(OpCode::Concat, parts.len(), "parts in a string", span);
+            }
+            ExprKind::ListLiteral(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+                self.count(OpCode::List, items.len(), "items in a list", span);
+            }
+            ExprKind::MapLiteral(entries) => {
+                for entry in entries {
+                    self.map_key(&entry.key, entry.span);
+                    self.expression(&entry.value);
+                }
+                self.count(OpCode::Map, entries.len(), "entries in a map", span);
+            }
+            ExprKind::Lambda(lambda) => {
+                let function = self.function(
+                    "lambda",
+                    FunctionKind::Function,
+                    &lambda.params,
+                    &lambda.defaults,
+                    &lambda.rest,
+                    &lambda.body,
+                    span,
+                );
+                self.emit_function(function, span);
+            }
+            ExprKind::Yield(value) => {
+                match value {
+                    Some(value) => self.expression(value),
+                    None => self.emit(OpCode::Null, span),
+                }
+                self.emit(OpCode::Yield, span);
+            }
+        }
+    }
+
+    fn call(&mut self, call: &Call, op: OpCode, span: Span) {
+        if let ExprKind::Variable(name) = &call.callee.kind {
+            self.check_arity(name, call.args.len(), span);
+        }
+        self.expression(&call.callee);
+        for arg in &call.args {
+            self.expression(arg);
+        }
+        self.count(op, call.args.len(), "arguments", span);
+    }
+
+    // Calls to a function declared in the program are checked against its
+    // parameters, as long as nothing else can end up in its variable.
+    fn check_arity(&mut self, name: &str, count: usize, span: Span) {
+        let local = self.frames.iter().rev().find_map(|frame| {
+            let local = frame.locals.iter().rev().find(|l| l.name == name)?;
+            Some(local.signature)
+        });
+        let signature = match local {
+            Some(signature) => signature,
+            None => self.globals.get(name).copied(),
+        };
+        let signature = match signature {
+            Some(signature) if !signature.accepts(count) => signature,
+            _ => return,
+        };
+        let msg = signature.mismatch(name, count);
+        self.error(&msg[..], span);
+    }
+
+    fn count(&mut self, op: OpCode, count: usize, what: &str, span: Span) {
+        if count > u16::MAX as usize {
+            let msg = format!("too many {}", what);
+            self.error(&msg[..], span);
+        }
+        self.emit_indexed(op, count, span);
+    }
+
+    fn binary_op(&mut self, op: BinaryOp, span: Span) {
+        let op = match op {
+            BinaryOp::Add => OpCode::Add,
+            BinaryOp::Subtract => OpCode::Subtract,
+            BinaryOp::Multiply => OpCode::Multiply,
+            BinaryOp::Divide => OpCode::Divide,
+            BinaryOp::Modulo => OpCode::Modulo,
+            BinaryOp::Equal => OpCode::Equal,
+            BinaryOp::NotEqual => OpCode::NotEqual,
+            BinaryOp::Less => OpCode::Less,
+            BinaryOp::LessEqual => OpCode::LessEqual,
+            BinaryOp::Greater => OpCode::Greater,
+            BinaryOp::GreaterEqual => OpCode::GreaterEqual,
+            BinaryOp::BitAnd => OpCode::BitAnd,
+            BinaryOp::BitOr => OpCode::BitOr,
+            BinaryOp::BitXor => OpCode::BitXor,
+            BinaryOp::And | BinaryOp::Or => unreachable!("logical operators short circuit"),
+        };
+        self.emit(op, span);
+    }
+
+    // Desugaring leaves compound assignments inside of larger expressions
+    // when their targets can't be worked out twice. The object and index
+    // are duplicated on the stack instead.
+    fn compound_assignment(&mut self, op: BinaryOp, target: &Expr, value: &Expr, span: Span) {
+        match &target.kind {
+            ExprKind::Variable(name) => {
+                self.get_variable(&name[..], target.span);
+                self.expression(value);
+                self.binary_op(op, span);
+                self.set_variable(&name[..], span);
+            }
+            ExprKind::Member { object, name } => {
+                self.expression(object);
+                self.emit(OpCode::Dup, span);
+                let index = self.string_constant(&name.name[..], name.span);
+                self.emit_indexed(OpCode::GetProperty, index, name.span);
+                self.expression(value);
+                self.binary_op(op, span);
+                self.emit_indexed(OpCode::SetProperty, index, span);
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(OpCode::Dup2, span);
+                self.emit(OpCode::GetIndex, target.span);
+                self.expression(value);
+                self.binary_op(op, span);
+                self.emit(OpCode::SetIndex, span);
+            }
+            _ => self.error("invalid assignment target", target.span),
+        }
+    }
+}
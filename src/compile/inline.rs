@@ -0,0 +1,249 @@
+use super::Assigned;
+use crate::ast::visit::*;
+use crate::ast::*;
+use std::collections::HashMap;
+
+// Inlining replaces calls to tiny functions with the expression they return,
+// which saves a call for scripts that wrap every field in a getter:
+//
+//     function health(e) { return e.health; }
+//     health(player)         becomes  player.health
+//
+// Only top level functions are inlined, and only when nothing can swap them
+// for something else. The function has to be a single return of an
+// expression that makes no calls and uses nothing but its parameters, so
+// there is nothing the expression could capture. Arguments have to be plain
+// values or variables, since a parameter can be used any number of times.
+// Inlined code runs in the frame of its caller, so the compiler leaves it
+// out when it keeps debug info.
+pub fn inline_functions(program: &mut Program) {
+    let mut assigned = Assigned::default();
+    assigned.visit_program(program);
+    let mut declared = Declared::default();
+    declared.visit_program(program);
+
+    let mut functions = HashMap::new();
+    for (position, stmt) in program.body.iter().enumerate() {
+        if let Some(decl) = declaration(stmt) {
+            let name = &decl.name.name;
+            if declared.names[name] == 1 && !assigned.names.contains(name) {
+                if let Some(function) = inlinable(decl, position) {
+                    functions.insert(name.clone(), function);
+                }
+            }
+        }
+    }
+    if functions.is_empty() {
+        return;
+    }
+
+    // A call that runs before the function is declared fails, so it is left
+    // for the runtime to report. A call in the body of a function can run as
+    // soon as that function is declared, so it only counts as coming after
+    // the functions declared before it, the same as a top level call does.
+    let mut inliner = Inliner {
+        functions,
+        position: 0,
+    };
+    for (position, stmt) in program.body.iter_mut().enumerate() {
+        inliner.position = position;
+        inliner.visit_stmt_mut(stmt);
+    }
+}
+
+fn declaration(stmt: &Stmt) -> Option<&FunctionDecl> {
+    match &stmt.kind {
+        StmtKind::Export(decl) => declaration(decl),
+        StmtKind::Function(decl) => Some(decl),
+        _ => None,
+    }
+}
+
+// The most expressions a function can return and still be inlined.
+pub const INLINE_BUDGET: usize = 12;
+
+struct Inlinable {
+    // The top level statement that declares the function.
+    position: usize,
+    params: Vec<String>,
+    body: Expr,
+}
+
+fn inlinable(decl: &FunctionDecl, position: usize) -> Option<Inlinable> {
+    if !decl.decorators.is_empty() || !decl.defaults.is_empty() || decl.rest.is_some() {
+        return None;
+    }
+    let body = match &decl.body[..] {
+        [Stmt {
+            kind: StmtKind::Return(Some(body)),
+            ..
+        }] => body,
+        _ => return None,
+    };
+    let params: Vec<String> = decl.params.iter().map(|p| p.name.clone()).collect();
+    let mut leaf = Leaf {
+        params: &params,
+        size: 0,
+        leaf: true,
+    };
+    leaf.visit_expr(body);
+    if !leaf.leaf || leaf.size > INLINE_BUDGET {
+        return None;
+    }
+    Some(Inlinable {
+        position,
+        params,
+        body: body.clone(),
+    })
+}
+
+// Counts how many times every name is declared anywhere in the program. A
+// function whose name is declared again could be hidden by a local at the
+// place it is called.
+#[derive(Default)]
+struct Declared {
+    names: HashMap<String, usize>,
+}
+
+impl Declared {
+    fn declare(&mut self, name: &Identifier) {
+        *self.names.entry(name.name.clone()).or_insert(0) += 1;
+    }
+}
+
+impl Visitor for Declared {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Var { name, .. } => self.declare(name),
+            StmtKind::Class(decl) => self.declare(&decl.name),
+            StmtKind::Import(decl) => self.declare(&decl.alias),
+            StmtKind::Try {
+                catch: Some(catch), ..
+            } => {
+                if let Some(binding) = &catch.binding {
+                    self.declare(binding);
+                }
+            }
+            _ => {}
+        }
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_function(&mut self, decl: &FunctionDecl) {
+        self.declare(&decl.name);
+        for param in decl.params.iter().chain(&decl.rest) {
+            self.declare(param);
+        }
+        walk_function(self, decl);
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) {
+        for param in lambda.params.iter().chain(&lambda.rest) {
+            self.declare(param);
+        }
+        walk_lambda(self, lambda);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Name(name) = &pattern.kind {
+            self.declare(name);
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+// Checks that an expression does nothing but read its parameters and work
+// out a value from them, and counts how big it is.
+struct Leaf<'a> {
+    params: &'a [String],
+    size: usize,
+    leaf: bool,
+}
+
+impl Visitor for Leaf<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.size += 1;
+        match &expr.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::String(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Null
+            | ExprKind::Unary { .. }
+            | ExprKind::Binary { .. }
+            | ExprKind::Conditional { .. }
+            | ExprKind::Range { .. }
+            | ExprKind::Member { .. }
+            | ExprKind::Index { .. }
+            | ExprKind::Slice { .. }
+            | ExprKind::ListLiteral(_)
+            | ExprKind::MapLiteral(_) => walk_expr(self, expr),
+            ExprKind::Variable(name) => self.leaf &= self.params.contains(name),
+            _ => self.leaf = false,
+        }
+    }
+}
+
+struct Inliner {
+    functions: HashMap<String, Inlinable>,
+    // The top level statement being visited.
+    position: usize,
+}
+
+impl VisitorMut for Inliner {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let call = match &expr.kind {
+            ExprKind::Call(call) if call.names.is_empty() => call,
+            _ => return,
+        };
+        let function = match &call.callee.kind {
+            ExprKind::Variable(name) => match self.functions.get(name) {
+                Some(function) => function,
+                None => return,
+            },
+            _ => return,
+        };
+        let simple = call.args.iter().all(|arg| {
+            matches!(
+                arg.kind,
+                ExprKind::Int(_)
+                    | ExprKind::Float(_)
+                    | ExprKind::String(_)
+                    | ExprKind::Bool(_)
+                    | ExprKind::Null
+                    | ExprKind::Variable(_)
+            )
+        });
+        if function.position >= self.position || call.args.len() != function.params.len() || !simple
+        {
+            return;
+        }
+
+        // Errors in the inlined code are reported at the call.
+        let mut substitute = Substitute {
+            args: function.params.iter().zip(&call.args).collect(),
+            span: expr.span,
+        };
+        let mut body = function.body.clone();
+        substitute.visit_expr_mut(&mut body);
+        *expr = body;
+    }
+}
+
+struct Substitute<'a> {
+    args: HashMap<&'a String, &'a Expr>,
+    span: Span,
+}
+
+impl VisitorMut for Substitute<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let ExprKind::Variable(name) = &expr.kind {
+            if let Some(&arg) = self.args.get(name) {
+                *expr = arg.clone();
+            }
+        }
+        expr.span = self.span;
+        walk_expr_mut(self, expr);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::ast::Span;
+use crate::bytecode::*;
+use std::collections::HashMap;
+
+// Passes that move code around work on a list of instructions, where a jump
+// points at the instruction it lands on rather than an offset. The code is
+// decoded into the list and encoded again once the pass is done, which works
+// out every jump distance and every wide prefix from scratch.
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    // Where the instruction was when the code was decoded.
+    pub offset: usize,
+    pub op: OpCode,
+    pub operand: usize,
+    pub span: Span,
+    // The instruction a jump lands on, which is one past the last one for
+    // jumps to the end of the code.
+    pub target: Option<usize>,
+}
+
+// Jumps in the overrides have not been patched yet, so they land on the
+// offset they are mapped to rather than where their operand says.
+pub fn decode(chunk: &Chunk, overrides: &HashMap<usize, usize>) -> Option<Vec<Instruction>> {
+    let offsets: Vec<usize> = chunk.instructions().map(|(offset, _)| offset).collect();
+    if offsets
+        .last()
+        .map_or(0, |&offset| chunk.next_offset(offset))
+        != chunk.len()
+    {
+        return None;
+    }
+    let index_of = |offset: usize| match offsets.binary_search(&offset) {
+        Ok(index) => Some(index),
+        Err(index) if index == offsets.len() && offset == chunk.len() => Some(index),
+        Err(_) => None,
+    };
+
+    let mut instructions = Vec::new();
+    for (offset, op) in chunk.instructions() {
+        let operand = chunk.operand(offset);
+        let next = chunk.next_offset(offset);
+        let target = match (op, overrides.get(&offset)) {
+            (_, Some(&target)) => Some(index_of(target)?),
+            (OpCode::Jump, _) | (OpCode::JumpIfFalse, _) | (OpCode::Try, _) => {
+                Some(index_of(next + operand)?)
+            }
+            (OpCode::Loop, _) => Some(index_of(next.checked_sub(operand)?)?),
+            _ => None,
+        };
+        instructions.push(Instruction {
+            offset,
+            op,
+            operand,
+            span: chunk.span(offset),
+            target,
+        });
+    }
+    Some(instructions)
+}
+
+// Whether every instruction is one of the jumps in the table of a switch,
+// which have to stay where they are for the switch to find them.
+pub fn table_entries(chunk: &Chunk, instructions: &[Instruction]) -> Vec<bool> {
+    let mut entries = vec![false; instructions.len()];
+    for (i, instruction) in instructions.iter().enumerate() {
+        if let Some(len) = table_len(chunk, instruction) {
+            for entry in entries.iter_mut().skip(i + 1).take(len) {
+                *entry = true;
+            }
+        }
+    }
+    entries
+}
+
+fn table_len(chunk: &Chunk, instruction: &Instruction) -> Option<usize> {
+    match (instruction.op, chunk.constants().get(instruction.operand)) {
+        (OpCode::Switch, Some(Constant::Cases(cases))) => Some(cases.len()),
+        _ => None,
+    }
+}
+
+fn instruction_len(op: OpCode, wide: bool) -> usize {
+    if wide {
+        2 + op.operand_len() * 2
+    } else {
+        1 + op.operand_len()
+    }
+}
+
+// Writes the instructions out as the code of the chunk. Jumps start out with
+// two byte distances and are made wide when their target is out of reach,
+// which makes the code longer and can push other jumps out of reach too.
+// The jumps in the table of a switch are all made wide if one of them is.
+// Offsets in the debug symbols move along with the instructions, and ones
+// that were on an instruction that has gone move to the next one left.
+pub fn encode(chunk: &mut Chunk, instructions: &[Instruction]) {
+    let mut wide: Vec<bool> = instructions
+        .iter()
+        .map(|i| i.target.is_none() && i.op.can_be_wide() && i.operand > u8::MAX as usize)
+        .collect();
+    let mut offsets = Vec::with_capacity(instructions.len() + 1);
+    loop {
+        offsets.clear();
+        let mut offset = 0;
+        for (instruction, &wide) in instructions.iter().zip(&wide) {
+            offsets.push(offset);
+            offset += instruction_len(instruction.op, wide);
+        }
+        offsets.push(offset);
+
+        let mut changed = false;
+        for (i, instruction) in instructions.iter().enumerate() {
+            if let Some(target) = instruction.target {
+                if !wide[i] && distance(instruction.op, &offsets, i, target) > u16::MAX as usize {
+                    wide[i] = true;
+                    changed = true;
+                }
+            }
+        }
+        for (i, instruction) in instructions.iter().enumerate() {
+            if let Some(len) = table_len(chunk, instruction) {
+                let table = &mut wide[i + 1..i + 1 + len];
+                if table.contains(&true) && table.contains(&false) {
+                    table.iter_mut().for_each(|wide| *wide = true);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    chunk.clear_code();
+    for (i, instruction) in instructions.iter().enumerate() {
+        let (op, span) = (instruction.op, instruction.span);
+        match instruction.target {
+            Some(target) => {
+                let distance = distance(op, &offsets, i, target);
+                if wide[i] {
+                    chunk.write(OpCode::Wide, span);
+                    chunk.write(op, span);
+                    chunk.write_u32(distance as u32, span);
+                } else {
+                    chunk.write(op, span);
+                    chunk.write_u16(distance as u16, span);
+                }
+            }
+            None if op.operand_len() > 0 => chunk.write_indexed(op, instruction.operand, span),
+            None => chunk.write(op, span),
+        }
+    }
+
+    let old: Vec<usize> = instructions.iter().map(|i| i.offset).collect();
+    chunk.remap_symbols(|offset| offsets[old.partition_point(|&o| o < offset)]);
+}
+
+// Jumps are measured from the end of the jump, backwards for a loop.
+fn distance(op: OpCode, offsets: &[usize], jump: usize, target: usize) -> usize {
+    let next = offsets[jump + 1];
+    if op == OpCode::Loop {
+        next - offsets[target]
+    } else {
+        offsets[target] - next
+    }
+}
@@ -0,0 +1,137 @@
+use super::layout::{decode, encode, table_entries, Instruction};
+use crate::bytecode::*;
+use std::collections::HashMap;
+
+// The peephole pass looks at instructions next to each other and replaces
+// them with fewer or cheaper ones:
+//
+//     constant 1, negate     becomes  constant -1
+//     jump 0                 goes away, it lands where it would anyway
+//     constant 1, pop        goes away, along with other pushes that are
+//                            popped straight off again
+//     equal, not             becomes  not_equal, and the other way around
+//
+// Ordering comparisons are left alone when followed by `not`, since with
+// NaN `not (a < b)` is true while `a >= b` is false.
+//
+// The code is decoded into a list first so that removing instructions can't
+// break jumps. Nothing is merged with an instruction that a jump lands on,
+// and the jumps in the table of a switch stay even when they go nowhere.
+pub fn optimize(chunk: &mut Chunk) {
+    for function in chunk.functions_mut() {
+        optimize(&mut function.chunk);
+    }
+
+    let mut instructions = match decode(chunk, &HashMap::new()) {
+        Some(instructions) => instructions,
+        None => return,
+    };
+    while rewrite(chunk, &mut instructions) {}
+    encode(chunk, &instructions);
+}
+
+// Makes one pass over the instructions and says whether anything changed.
+fn rewrite(chunk: &mut Chunk, instructions: &mut Vec<Instruction>) -> bool {
+    let mut targets = vec![false; instructions.len() + 1];
+    for instruction in instructions.iter() {
+        if let Some(target) = instruction.target {
+            targets[target] = true;
+        }
+    }
+
+    let entries = table_entries(chunk, instructions);
+    let mut removed = vec![false; instructions.len()];
+    let mut changed = false;
+    let mut i = 0;
+    while i < instructions.len() {
+        let next = instructions.get(i + 1).filter(|_| !targets[i + 1]);
+        let current = &instructions[i];
+        match (current.op, next.map(|n| n.op)) {
+            (OpCode::Jump, _) | (OpCode::JumpIfFalse, _)
+                if current.target == Some(i + 1) && !entries[i] =>
+            {
+                removed[i] = true;
+            }
+            (OpCode::Constant, Some(OpCode::Negate)) => {
+                let negated = match chunk.constants().get(current.operand) {
+                    Some(Constant::Int(n)) if *n != i64::MIN => Constant::Int(-n),
+                    Some(Constant::Float(n)) => Constant::Float(-n),
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                };
+                let index = match chunk.add_constant(negated) {
+                    Some(index) => index,
+                    None => {
+                        i += 1;
+                        continue;
+                    }
+                };
+                instructions[i].operand = index;
+                instructions[i].span = instructions[i + 1].span;
+                removed[i + 1] = true;
+                i += 1;
+            }
+            (op, Some(OpCode::Pop)) if is_pure_push(op) => {
+                removed[i] = true;
+                removed[i + 1] = true;
+                i += 1;
+            }
+            (OpCode::Equal, Some(OpCode::Not)) | (OpCode::NotEqual, Some(OpCode::Not)) => {
+                instructions[i].op = match current.op {
+                    OpCode::Equal => OpCode::NotEqual,
+                    _ => OpCode::Equal,
+                };
+                instructions[i].span = instructions[i + 1].span;
+                removed[i + 1] = true;
+                i += 1;
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        changed = true;
+        i += 1;
+    }
+    if !changed {
+        return false;
+    }
+
+    // Jumps to a removed instruction land on the next one that is left.
+    let mut new_index = vec![0; instructions.len() + 1];
+    let mut count = 0;
+    for (i, index) in new_index.iter_mut().enumerate() {
+        *index = count;
+        if i < removed.len() && !removed[i] {
+            count += 1;
+        }
+    }
+    let mut i = 0;
+    instructions.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    for instruction in instructions.iter_mut() {
+        if let Some(target) = instruction.target {
+            instruction.target = Some(new_index[target]);
+        }
+    }
+    true
+}
+
+// Instructions that only push a value and can't fail, so nothing is lost
+// when the value is popped again straight away.
+fn is_pure_push(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::Constant
+            | OpCode::Null
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Dup
+            | OpCode::GetLocal
+            | OpCode::GetUpvalue
+    )
+}
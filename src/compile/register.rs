@@ -0,0 +1,1512 @@
+use crate::ast::Span;
+use crate::bytecode::{self, Constant, OpCode, Upvalue};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+// The register backend turns the stack code of a function into code for a
+// register machine, where every instruction names the registers it reads
+// and writes. A script like `x = x + y * 2;` pushes and pops its way through
+// seven stack instructions but only needs three with registers.
+//
+// The registers are the stack slots of the call frame. The stack depth
+// before every instruction is known from the code, so every push becomes a
+// write to the register at that depth and every pop just lowers the depth.
+// That leaves a lot of moves between registers, which the optimizer then
+// folds into the instructions around them.
+pub type Register = usize;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Instruction {
+    // Loads a number or a string from the constants of the function.
+    Constant {
+        dst: Register,
+        index: usize,
+    },
+    // Loads a function declared inside this one, or makes a closure out of
+    // it when it captures anything.
+    Function {
+        dst: Register,
+        index: usize,
+    },
+    Closure {
+        dst: Register,
+        index: usize,
+    },
+    Null {
+        dst: Register,
+    },
+    Bool {
+        dst: Register,
+        value: bool,
+    },
+    Move {
+        dst: Register,
+        src: Register,
+    },
+    // Globals, fields and methods are found by a name in the constants.
+    DefineGlobal {
+        name: usize,
+        src: Register,
+    },
+    GetGlobal {
+        dst: Register,
+        name: usize,
+    },
+    SetGlobal {
+        name: usize,
+        src: Register,
+    },
+    // The global slots of the script, the same as on the stack.
+    DefineSlot {
+        slot: usize,
+        src: Register,
+    },
+    GetSlot {
+        dst: Register,
+        slot: usize,
+    },
+    SetSlot {
+        slot: usize,
+        src: Register,
+    },
+    // The path of the module is a string in the constants.
+    Import {
+        dst: Register,
+        path: usize,
+    },
+    GetUpvalue {
+        dst: Register,
+        index: usize,
+    },
+    SetUpvalue {
+        index: usize,
+        src: Register,
+    },
+    // Moves the value of a register that a closure captured into the
+    // closure, before the register is used for something else.
+    Close {
+        src: Register,
+    },
+    GetProperty {
+        dst: Register,
+        object: Register,
+        name: usize,
+    },
+    SetProperty {
+        object: Register,
+        name: usize,
+        src: Register,
+    },
+    GetIndex {
+        dst: Register,
+        object: Register,
+        index: Register,
+    },
+    SetIndex {
+        object: Register,
+        index: Register,
+        src: Register,
+    },
+    Slice {
+        dst: Register,
+        object: Register,
+        start: Register,
+        end: Register,
+    },
+    // The operator is the opcode the stack code uses for it.
+    Binary {
+        op: OpCode,
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Unary {
+        op: OpCode,
+        dst: Register,
+        src: Register,
+    },
+    // Targets are the index of an instruction in the code, or the length of
+    // the code for a jump to the end.
+    Jump {
+        target: usize,
+    },
+    JumpIfFalse {
+        condition: Register,
+        target: usize,
+    },
+    // Anything thrown before the matching end_try goes on at the target,
+    // with the exception in the destination.
+    Try {
+        dst: Register,
+        target: usize,
+    },
+    EndTry,
+    Throw {
+        src: Register,
+    },
+    Rethrow {
+        src: Register,
+    },
+    // The jumps for that many cases follow, the same as on the stack.
+    Switch {
+        value: Register,
+        cases: usize,
+        count: usize,
+    },
+    // The arguments are in the registers straight after the callee.
+    Call {
+        dst: Register,
+        callee: Register,
+        count: usize,
+    },
+    // Reuses the frame of the running function, like a call followed by
+    // a return of its result.
+    TailCall {
+        callee: Register,
+        count: usize,
+    },
+    Return {
+        src: Register,
+    },
+    // Leaves the value the coroutine is resumed with in the destination.
+    Yield {
+        dst: Register,
+        src: Register,
+    },
+    // The items are in that many registers from the first, with a key and
+    // then a value for every entry of a map.
+    List {
+        dst: Register,
+        start: Register,
+        count: usize,
+    },
+    Map {
+        dst: Register,
+        start: Register,
+        count: usize,
+    },
+    Concat {
+        dst: Register,
+        start: Register,
+        count: usize,
+    },
+    Range {
+        dst: Register,
+        start: Register,
+        end: Register,
+        inclusive: bool,
+    },
+    // Members are added to the class in the register, which is where the
+    // class being declared stays until it is done.
+    Class {
+        dst: Register,
+        name: usize,
+    },
+    Inherit {
+        class: Register,
+        superclass: Register,
+    },
+    // The operator is the opcode the stack code uses for the kind of
+    // member.
+    Member {
+        op: OpCode,
+        class: Register,
+        name: usize,
+        src: Register,
+    },
+    GetSuper {
+        dst: Register,
+        object: Register,
+        superclass: Register,
+        name: usize,
+    },
+}
+
+impl Instruction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::Constant { .. } => "constant",
+            Instruction::Function { .. } => "function",
+            Instruction::Closure { .. } => "closure",
+            Instruction::Null { .. } => "null",
+            Instruction::Bool { value: true, .. } => "true",
+            Instruction::Bool { value: false, .. } => "false",
+            Instruction::Move { .. } => "move",
+            Instruction::DefineGlobal { .. } => "define_global",
+            Instruction::GetGlobal { .. } => "get_global",
+            Instruction::SetGlobal { .. } => "set_global",
+            Instruction::DefineSlot { .. } => "define_slot",
+            Instruction::GetSlot { .. } => "get_slot",
+            Instruction::SetSlot { .. } => "set_slot",
+            Instruction::Import { .. } => "import",
+            Instruction::GetUpvalue { .. } => "get_upvalue",
+            Instruction::SetUpvalue { .. } => "set_upvalue",
+            Instruction::Close { .. } => "close",
+            Instruction::GetProperty { .. } => "get_property",
+            Instruction::SetProperty { .. } => "set_property",
+            Instruction::GetIndex { .. } => "get_index",
+            Instruction::SetIndex { .. } => "set_index",
+            Instruction::Slice { .. } => "slice",
+            Instruction::Binary { op, .. } | Instruction::Unary { op, .. } => op.name(),
+            Instruction::Jump { .. } => "jump",
+            Instruction::JumpIfFalse { .. } => "jump_if_false",
+            Instruction::Try { .. } => "try",
+            Instruction::EndTry => "end_try",
+            Instruction::Throw { .. } => "throw",
+            Instruction::Rethrow { .. } => "rethrow",
+            Instruction::Switch { .. } => "switch",
+            Instruction::Call { .. } => "call",
+            Instruction::TailCall { .. } => "tail_call",
+            Instruction::Return { .. } => "return",
+            Instruction::Yield { .. } => "yield",
+            Instruction::List { .. } => "list",
+            Instruction::Map { .. } => "map",
+            Instruction::Concat { .. } => "concat",
+            Instruction::Range { .. } => "range",
+            Instruction::Class { .. } => "class",
+            Instruction::Inherit { .. } => "inherit",
+            Instruction::Member { op, .. } => op.name(),
+            Instruction::GetSuper { .. } => "get_super",
+        }
+    }
+
+    // The register the instruction writes its result to.
+    pub fn dst(&self) -> Option<Register> {
+        match *self {
+            Instruction::Constant { dst, .. }
+            | Instruction::Function { dst, .. }
+            | Instruction::Closure { dst, .. }
+            | Instruction::GetUpvalue { dst, .. }
+            | Instruction::Null { dst }
+            | Instruction::Bool { dst, .. }
+            | Instruction::Move { dst, .. }
+            | Instruction::GetGlobal { dst, .. }
+            | Instruction::GetSlot { dst, .. }
+            | Instruction::Import { dst, .. }
+            | Instruction::GetProperty { dst, .. }
+            | Instruction::GetIndex { dst, .. }
+            | Instruction::Slice { dst, .. }
+            | Instruction::Binary { dst, .. }
+            | Instruction::Unary { dst, .. }
+            | Instruction::Call { dst, .. }
+            | Instruction::Yield { dst, .. }
+            | Instruction::Try { dst, .. }
+            | Instruction::List { dst, .. }
+            | Instruction::Map { dst, .. }
+            | Instruction::Concat { dst, .. }
+            | Instruction::Range { dst, .. }
+            | Instruction::Class { dst, .. }
+            | Instruction::GetSuper { dst, .. } => Some(dst),
+            _ => None,
+        }
+    }
+
+    fn dst_mut(&mut self) -> Option<&mut Register> {
+        match self {
+            Instruction::Constant { dst, .. }
+            | Instruction::Function { dst, .. }
+            | Instruction::Closure { dst, .. }
+            | Instruction::GetUpvalue { dst, .. }
+            | Instruction::Null { dst }
+            | Instruction::Bool { dst, .. }
+            | Instruction::Move { dst, .. }
+            | Instruction::GetGlobal { dst, .. }
+            | Instruction::GetSlot { dst, .. }
+            | Instruction::Import { dst, .. }
+            | Instruction::GetProperty { dst, .. }
+            | Instruction::GetIndex { dst, .. }
+            | Instruction::Slice { dst, .. }
+            | Instruction::Binary { dst, .. }
+            | Instruction::Unary { dst, .. }
+            | Instruction::Call { dst, .. }
+            | Instruction::Yield { dst, .. }
+            | Instruction::Try { dst, .. }
+            | Instruction::List { dst, .. }
+            | Instruction::Map { dst, .. }
+            | Instruction::Concat { dst, .. }
+            | Instruction::Range { dst, .. }
+            | Instruction::Class { dst, .. }
+            | Instruction::GetSuper { dst, .. } => Some(dst),
+            _ => None,
+        }
+    }
+
+    // Every register the instruction reads.
+    pub fn reads(&self) -> Vec<Register> {
+        let mut reads = self.operands();
+        match *self {
+            Instruction::Call { callee, count, .. } | Instruction::TailCall { callee, count } => {
+                reads.extend(callee..=callee + count)
+            }
+            Instruction::List { start, count, .. } | Instruction::Concat { start, count, .. } => {
+                reads.extend(start..start + count)
+            }
+            Instruction::Map { start, count, .. } => reads.extend(start..start + count * 2),
+            _ => {}
+        }
+        reads
+    }
+
+    // The registers read that are named on their own, which unlike the
+    // arguments of a call can be swapped for any other register.
+    fn operands(&self) -> Vec<Register> {
+        let mut copy = *self;
+        copy.operands_mut().into_iter().map(|r| *r).collect()
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Register> {
+        match self {
+            Instruction::Move { src, .. }
+            | Instruction::DefineGlobal { src, .. }
+            | Instruction::SetGlobal { src, .. }
+            | Instruction::DefineSlot { src, .. }
+            | Instruction::SetSlot { src, .. }
+            | Instruction::SetUpvalue { src, .. }
+            | Instruction::Close { src }
+            | Instruction::Unary { src, .. }
+            | Instruction::Yield { src, .. }
+            | Instruction::Throw { src }
+            | Instruction::Rethrow { src }
+            | Instruction::Return { src } => vec![src],
+            Instruction::GetProperty { object, .. } => vec![object],
+            Instruction::SetProperty { object, src, .. } => vec![object, src],
+            Instruction::GetIndex { object, index, .. } => vec![object, index],
+            Instruction::SetIndex { object, index, src } => vec![object, index, src],
+            Instruction::Slice {
+                object, start, end, ..
+            } => vec![object, start, end],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::JumpIfFalse { condition, .. } => vec![condition],
+            Instruction::Switch { value, .. } => vec![value],
+            Instruction::Range { start, end, .. } => vec![start, end],
+            Instruction::Inherit { class, superclass } => vec![class, superclass],
+            Instruction::Member { class, src, .. } => vec![class, src],
+            Instruction::GetSuper {
+                object, superclass, ..
+            } => vec![object, superclass],
+            _ => vec![],
+        }
+    }
+
+    fn target(&self) -> Option<usize> {
+        match *self {
+            Instruction::Jump { target }
+            | Instruction::JumpIfFalse { target, .. }
+            | Instruction::Try { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    fn target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Instruction::Jump { target }
+            | Instruction::JumpIfFalse { target, .. }
+            | Instruction::Try { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    // Whether the instruction does nothing but write its result, so it can
+    // go if nothing reads that result.
+    fn is_pure(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Constant { .. }
+                | Instruction::Function { .. }
+                | Instruction::Null { .. }
+                | Instruction::Bool { .. }
+                | Instruction::Move { .. }
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub min_arity: usize,
+    pub rest: bool,
+    // How many registers a call to the function needs.
+    pub registers: usize,
+    // The names of the global slots, which only the function of the script
+    // has.
+    pub globals: Vec<String>,
+    // The registers and upvalues a closure made from the function captures.
+    pub upvalues: Vec<Upvalue>,
+    pub code: Vec<Instruction>,
+    // The span every instruction was compiled from.
+    pub spans: Vec<Span>,
+    // Only numbers and strings, functions have a list of their own.
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
+}
+
+// The registers holding the callee and the parameters when a call starts.
+fn entry_depth(arity: usize, rest: bool) -> usize {
+    1 + arity + rest as usize
+}
+
+// Turns the stack code of a function, and every function declared in it,
+// into register code. The code is expected to come from the compiler, which
+// always leaves the stack at the same depth wherever two paths meet.
+pub fn lower(function: &bytecode::Function) -> Function {
+    let chunk = &function.chunk;
+
+    let mut constants = Vec::new();
+    let mut functions = Vec::new();
+    let mut indices = Vec::new();
+    for constant in chunk.constants() {
+        match constant {
+            Constant::Function(function) => {
+                indices.push(Err(functions.len()));
+                functions.push(lower(function));
+            }
+            constant => {
+                indices.push(Ok(constants.len()));
+                constants.push(constant.clone());
+            }
+        }
+    }
+    let name = |index: usize| match indices[index] {
+        Ok(index) => index,
+        Err(_) => unreachable!("names and cases are never functions"),
+    };
+
+    // The depth before every instruction, found by following every path
+    // through the code. Instructions that nothing reaches have no depth.
+    let mut depths = vec![None; chunk.len() + 1];
+    let mut pending = vec![(0, entry_depth(function.arity, function.rest))];
+    while let Some((offset, depth)) = pending.pop() {
+        if offset >= chunk.len() || depths[offset].is_some() {
+            continue;
+        }
+        depths[offset] = Some(depth);
+        let op = match chunk.op_at(offset) {
+            Some(op) => op,
+            None => continue,
+        };
+        let operand = chunk.operand(offset);
+        let next = chunk.next_offset(offset);
+        let (pops, pushes) = op.stack_effect(operand);
+        let after = depth - pops + pushes;
+        match op {
+            OpCode::Jump => pending.push((next + operand, after)),
+            OpCode::Loop => pending.push((next - operand, after)),
+            OpCode::JumpIfFalse => {
+                pending.push((next + operand, after));
+                pending.push((next, after));
+            }
+            // The handler starts with the exception pushed.
+            OpCode::Try => {
+                pending.push((next + operand, after + 1));
+                pending.push((next, after));
+            }
+            // Every jump in the table is reached from the switch, and so is
+            // the code after it.
+            OpCode::Switch => {
+                let mut entry = next;
+                for _ in 0..chunk.cases(offset).map_or(0, |cases| cases.len()) {
+                    pending.push((entry, after));
+                    entry = chunk.next_offset(entry);
+                }
+                pending.push((entry, after));
+            }
+            OpCode::Return | OpCode::TailCall | OpCode::Throw | OpCode::Rethrow => {}
+            _ => pending.push((next, after)),
+        }
+    }
+
+    let mut code = Vec::new();
+    let mut spans = Vec::new();
+    // Where the register code for every instruction starts, which jumps are
+    // pointed at once all of the code is there.
+    let mut starts = vec![0; chunk.len() + 1];
+    let mut registers = entry_depth(function.arity, function.rest);
+    for (offset, op) in chunk.instructions() {
+        starts[offset] = code.len();
+        let d = match depths[offset] {
+            Some(depth) => depth,
+            None => continue,
+        };
+        let operand = chunk.operand(offset);
+        let next = chunk.next_offset(offset);
+        let span = chunk.span(offset);
+        let (pops, pushes) = op.stack_effect(operand);
+        registers = registers.max(d - pops + pushes);
+        // The first register the instruction reads.
+        let base = d - pops;
+        let mut emit = |instruction| {
+            code.push(instruction);
+            spans.push(span);
+        };
+        match op {
+            OpCode::Constant => emit(match indices[operand] {
+                Ok(index) => Instruction::Constant { dst: d, index },
+                Err(index) => Instruction::Function { dst: d, index },
+            }),
+            OpCode::Null => emit(Instruction::Null { dst: d }),
+            OpCode::True | OpCode::False => emit(Instruction::Bool {
+                dst: d,
+                value: op == OpCode::True,
+            }),
+            OpCode::Pop | OpCode::Wide => {}
+            OpCode::Dup => emit(Instruction::Move { dst: d, src: base }),
+            OpCode::Dup2 => {
+                emit(Instruction::Move { dst: d, src: base });
+                emit(Instruction::Move {
+                    dst: d + 1,
+                    src: base + 1,
+                });
+            }
+            OpCode::GetLocal => emit(Instruction::Move {
+                dst: d,
+                src: operand,
+            }),
+            OpCode::SetLocal => emit(Instruction::Move {
+                dst: operand,
+                src: base,
+            }),
+            OpCode::DefineGlobal => emit(Instruction::DefineGlobal {
+                name: name(operand),
+                src: base,
+            }),
+            OpCode::GetGlobal => emit(Instruction::GetGlobal {
+                dst: d,
+                name: name(operand),
+            }),
+            OpCode::SetGlobal => emit(Instruction::SetGlobal {
+                name: name(operand),
+                src: base,
+            }),
+            OpCode::DefineSlot => emit(Instruction::DefineSlot {
+                slot: operand,
+                src: base,
+            }),
+            OpCode::GetSlot => emit(Instruction::GetSlot {
+                dst: d,
+                slot: operand,
+            }),
+            OpCode::SetSlot => emit(Instruction::SetSlot {
+                slot: operand,
+                src: base,
+            }),
+            OpCode::Import => emit(Instruction::Import {
+                dst: d,
+                path: name(operand),
+            }),
+            OpCode::GetUpvalue => emit(Instruction::GetUpvalue {
+                dst: d,
+                index: operand,
+            }),
+            OpCode::SetUpvalue => emit(Instruction::SetUpvalue {
+                index: operand,
+                src: base,
+            }),
+            OpCode::Closure => emit(Instruction::Closure {
+                dst: d,
+                index: match indices[operand] {
+                    Err(index) => index,
+                    Ok(_) => unreachable!("closures are always made of functions"),
+                },
+            }),
+            OpCode::CloseUpvalue => emit(Instruction::Close { src: base }),
+            OpCode::GetProperty => emit(Instruction::GetProperty {
+                dst: base,
+                object: base,
+                name: name(operand),
+            }),
+            // Setting a field or an item leaves the value where the object
+            // was, the same as on the stack.
+            OpCode::SetProperty => {
+                emit(Instruction::SetProperty {
+                    object: base,
+                    name: name(operand),
+                    src: base + 1,
+                });
+                emit(Instruction::Move {
+                    dst: base,
+                    src: base + 1,
+                });
+            }
+            OpCode::GetIndex => emit(Instruction::GetIndex {
+                dst: base,
+                object: base,
+                index: base + 1,
+            }),
+            OpCode::SetIndex => {
+                emit(Instruction::SetIndex {
+                    object: base,
+                    index: base + 1,
+                    src: base + 2,
+                });
+                emit(Instruction::Move {
+                    dst: base,
+                    src: base + 2,
+                });
+            }
+            OpCode::Slice => emit(Instruction::Slice {
+                dst: base,
+                object: base,
+                start: base + 1,
+                end: base + 2,
+            }),
+            OpCode::Negate | OpCode::Not | OpCode::BitNot => emit(Instruction::Unary {
+                op,
+                dst: base,
+                src: base,
+            }),
+            OpCode::Yield => emit(Instruction::Yield {
+                dst: base,
+                src: base,
+            }),
+            OpCode::Jump => emit(Instruction::Jump {
+                target: next + operand,
+            }),
+            OpCode::Loop => emit(Instruction::Jump {
+                target: next - operand,
+            }),
+            OpCode::JumpIfFalse => emit(Instruction::JumpIfFalse {
+                condition: d - 1,
+                target: next + operand,
+            }),
+            OpCode::Try => {
+                registers = registers.max(d + 1);
+                emit(Instruction::Try {
+                    dst: d,
+                    target: next + operand,
+                })
+            }
+            OpCode::EndTry => emit(Instruction::EndTry),
+            OpCode::Throw => emit(Instruction::Throw { src: base }),
+            OpCode::Rethrow => emit(Instruction::Rethrow { src: base }),
+            OpCode::Switch => emit(Instruction::Switch {
+                value: base,
+                cases: name(operand),
+                count: chunk.cases(offset).map_or(0, |cases| cases.len()),
+            }),
+            OpCode::Call => emit(Instruction::Call {
+                dst: base,
+                callee: base,
+                count: operand,
+            }),
+            OpCode::TailCall => emit(Instruction::TailCall {
+                callee: base,
+                count: operand,
+            }),
+            OpCode::Return => emit(Instruction::Return { src: base }),
+            OpCode::List => emit(Instruction::List {
+                dst: base,
+                start: base,
+                count: operand,
+            }),
+            OpCode::Map => emit(Instruction::Map {
+                dst: base,
+                start: base,
+                count: operand,
+            }),
+            OpCode::Concat => emit(Instruction::Concat {
+                dst: base,
+                start: base,
+                count: operand,
+            }),
+            OpCode::Range => emit(Instruction::Range {
+                dst: base,
+                start: base,
+                end: base + 1,
+                inclusive: operand == 1,
+            }),
+            OpCode::GetSuper => emit(Instruction::GetSuper {
+                dst: base,
+                object: base,
+                superclass: base + 1,
+                name: name(operand),
+            }),
+            OpCode::Class => emit(Instruction::Class {
+                dst: d,
+                name: name(operand),
+            }),
+            OpCode::Inherit => emit(Instruction::Inherit {
+                class: base,
+                superclass: base + 1,
+            }),
+            OpCode::Method | OpCode::Getter | OpCode::Setter => emit(Instruction::Member {
+                op,
+                class: base,
+                name: name(operand),
+                src: base + 1,
+            }),
+            _ => emit(Instruction::Binary {
+                op,
+                dst: base,
+                left: base,
+                right: base + 1,
+            }),
+        }
+    }
+    starts[chunk.len()] = code.len();
+    for instruction in &mut code {
+        if let Some(target) = instruction.target_mut() {
+            *target = starts[*target];
+        }
+    }
+
+    Function {
+        name: function.name.clone(),
+        arity: function.arity,
+        min_arity: function.min_arity,
+        rest: function.rest,
+        registers,
+        globals: chunk.globals().to_vec(),
+        upvalues: function.upvalues.clone(),
+        code,
+        spans,
+        constants,
+        functions,
+    }
+}
+
+// Cleans up after lowering, until there is nothing left to do:
+//
+//     move r2, r1; add r3, r2, r0     becomes  add r3, r1, r0
+//     add r3, r1, r0; move r1, r3     becomes  add r1, r1, r0
+//
+// along with throwing away values nothing reads, like the constant in
+// `1;`, and jumps to the next instruction.
+//
+// Registers that a closure captures are left alone, since a call can change
+// them behind the optimizer's back.
+pub fn optimize(function: &mut Function) {
+    for function in &mut function.functions {
+        optimize(function);
+    }
+    if handles_exceptions(function) {
+        return;
+    }
+    let captured = captured_registers(function);
+    clean_up(function, &captured);
+}
+
+fn clean_up(function: &mut Function, captured: &HashSet<Register>) {
+    loop {
+        let mut changed = propagate_copies(function, captured);
+        changed |= remove_dead_code(function, captured);
+        if !changed {
+            break;
+        }
+    }
+    function.registers = used_registers(function).max(entry_depth(function.arity, function.rest));
+}
+
+// Any instruction between a try and its end_try can go on at the handler,
+// which the flow of control between instructions doesn't show, so code with
+// a handler is left as it was lowered.
+fn handles_exceptions(function: &Function) -> bool {
+    function
+        .code
+        .iter()
+        .any(|i| matches!(i, Instruction::Try { .. }))
+}
+
+fn used_registers(function: &Function) -> usize {
+    function
+        .code
+        .iter()
+        .flat_map(|i| i.reads().into_iter().chain(i.dst()))
+        .max()
+        .map_or(0, |r| r + 1)
+}
+
+fn captured_registers(function: &Function) -> HashSet<Register> {
+    let mut captured = HashSet::new();
+    for instruction in &function.code {
+        if let Instruction::Closure { index, .. } = *instruction {
+            let upvalues = &function.functions[index].upvalues;
+            captured.extend(upvalues.iter().filter(|u| u.local).map(|u| u.index));
+        }
+    }
+    captured
+}
+
+// Which instructions start a basic block, either because a jump lands on
+// them or because they follow a jump.
+fn leaders(code: &[Instruction]) -> Vec<bool> {
+    let mut leaders = vec![false; code.len() + 1];
+    leaders[0] = true;
+    for (i, instruction) in code.iter().enumerate() {
+        match *instruction {
+            Instruction::Jump { target }
+            | Instruction::JumpIfFalse { target, .. }
+            | Instruction::Try { target, .. } => {
+                leaders[target] = true;
+                leaders[i + 1] = true;
+            }
+            Instruction::Switch { count, .. } => {
+                for leader in leaders.iter_mut().skip(i + 1).take(count + 1) {
+                    *leader = true;
+                }
+            }
+            Instruction::Return { .. }
+            | Instruction::TailCall { .. }
+            | Instruction::Throw { .. }
+            | Instruction::Rethrow { .. } => leaders[i + 1] = true,
+            _ => {}
+        }
+    }
+    leaders
+}
+
+// Whether every instruction is one of the jumps in the table of a switch.
+fn table_entries(code: &[Instruction]) -> Vec<bool> {
+    let mut entries = vec![false; code.len()];
+    for (i, instruction) in code.iter().enumerate() {
+        if let Instruction::Switch { count, .. } = *instruction {
+            for entry in entries.iter_mut().skip(i + 1).take(count) {
+                *entry = true;
+            }
+        }
+    }
+    entries
+}
+
+// The instructions that can run straight after the one at `i`.
+fn successors(code: &[Instruction], i: usize) -> Vec<usize> {
+    match code[i] {
+        Instruction::Jump { target } => vec![target],
+        Instruction::JumpIfFalse { target, .. } | Instruction::Try { target, .. } => {
+            vec![i + 1, target]
+        }
+        Instruction::Switch { count, .. } => (i + 1..=i + 1 + count).collect(),
+        Instruction::Return { .. }
+        | Instruction::TailCall { .. }
+        | Instruction::Throw { .. }
+        | Instruction::Rethrow { .. } => vec![],
+        _ => vec![i + 1],
+    }
+}
+
+// Reads from a register that was just copied from another read the
+// original instead, which often leaves the copy unused.
+fn propagate_copies(function: &mut Function, captured: &HashSet<Register>) -> bool {
+    let leaders = leaders(&function.code);
+    let mut copies: HashMap<Register, Register> = HashMap::new();
+    let mut changed = false;
+    for (i, instruction) in function.code.iter_mut().enumerate() {
+        if leaders[i] {
+            copies.clear();
+        }
+        for register in instruction.operands_mut() {
+            if let Some(&src) = copies.get(register) {
+                *register = src;
+                changed = true;
+            }
+        }
+        if let Some(dst) = instruction.dst() {
+            copies.retain(|&copy, &mut src| copy != dst && src != dst);
+        }
+        if let Instruction::Move { dst, src } = *instruction {
+            if dst != src && !captured.contains(&dst) && !captured.contains(&src) {
+                copies.insert(dst, src);
+            }
+        }
+    }
+    changed
+}
+
+// The registers that may still be read after every instruction.
+fn live_out(code: &[Instruction], registers: usize) -> Vec<Vec<bool>> {
+    let mut live_in = vec![vec![false; registers]; code.len() + 1];
+    let mut live_out = vec![vec![false; registers]; code.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..code.len()).rev() {
+            let mut out = vec![false; registers];
+            for successor in successors(code, i) {
+                for (r, live) in live_in[successor].iter().enumerate() {
+                    out[r] |= *live;
+                }
+            }
+            let mut into = out.clone();
+            if let Some(dst) = code[i].dst() {
+                into[dst] = false;
+            }
+            for r in code[i].reads() {
+                into[r] = true;
+            }
+            if into != live_in[i] {
+                live_in[i] = into;
+                changed = true;
+            }
+            live_out[i] = out;
+        }
+    }
+    live_out
+}
+
+fn remove_dead_code(function: &mut Function, captured: &HashSet<Register>) -> bool {
+    let code = &mut function.code;
+    let registers = code
+        .iter()
+        .flat_map(|i| i.reads().into_iter().chain(i.dst()))
+        .max()
+        .map_or(0, |r| r + 1);
+    let live = live_out(code, registers);
+    let leaders = leaders(code);
+    let entries = table_entries(code);
+
+    let mut removed = vec![false; code.len()];
+    let mut changed = false;
+    let mut i = 0;
+    while i < code.len() {
+        match code[i] {
+            Instruction::Move { dst, src } if dst == src => removed[i] = true,
+            Instruction::Jump { target } | Instruction::JumpIfFalse { target, .. }
+                if target == i + 1 && !entries[i] =>
+            {
+                removed[i] = true
+            }
+            instruction
+                if instruction.is_pure()
+                    && !live[i][instruction.dst().unwrap()]
+                    && !captured.contains(&instruction.dst().unwrap()) =>
+            {
+                removed[i] = true
+            }
+            // A result that is only moved somewhere else can be written
+            // there in the first place.
+            instruction => match code.get(i + 1) {
+                Some(&Instruction::Move { dst, src })
+                    if !leaders[i + 1]
+                        && instruction.dst() == Some(src)
+                        && dst != src
+                        && !live[i + 1][src]
+                        && !captured.contains(&src) =>
+                {
+                    *code[i].dst_mut().unwrap() = dst;
+                    removed[i + 1] = true;
+                    i += 1;
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            },
+        }
+        changed = true;
+        i += 1;
+    }
+    if changed {
+        remove(function, &removed);
+    }
+    changed
+}
+
+// Jumps to a removed instruction land on the next one that is left.
+fn remove(function: &mut Function, removed: &[bool]) {
+    let code = &mut function.code;
+    let mut new_index = vec![0; code.len() + 1];
+    let mut count = 0;
+    for (i, index) in new_index.iter_mut().enumerate() {
+        *index = count;
+        if i < removed.len() && !removed[i] {
+            count += 1;
+        }
+    }
+    let mut i = 0;
+    code.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    let mut i = 0;
+    function.spans.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    for instruction in function.code.iter_mut() {
+        if let Some(target) = instruction.target_mut() {
+            *target = new_index[*target];
+        }
+    }
+}
+
+// Takes work that is the same on every pass out of loops, for the loops
+// that `while` and `for` statements compile to. A loop runs from the target
+// of a jump back up to the last jump back to it, and is only entered at the
+// top, so code put in front of it runs once before the loop starts.
+//
+// This runs after `optimize`, which it relies on to clean up after it.
+pub fn optimize_loops(function: &mut Function) {
+    for function in &mut function.functions {
+        optimize_loops(function);
+    }
+    if handles_exceptions(function) {
+        return;
+    }
+    let captured = captured_registers(function);
+    loop {
+        let changed = hoist_invariants(function, &captured) || reduce_strength(function, &captured);
+        clean_up(function, &captured);
+        if !changed {
+            break;
+        }
+    }
+}
+
+// The first and last instruction of every loop in the code.
+fn loops(code: &[Instruction]) -> Vec<(usize, usize)> {
+    let mut ends: BTreeMap<usize, usize> = BTreeMap::new();
+    for (i, instruction) in code.iter().enumerate() {
+        if let Instruction::Jump { target } = *instruction {
+            if target <= i {
+                ends.insert(target, i);
+            }
+        }
+    }
+    ends.into_iter()
+        .filter(|&(start, end)| {
+            code.iter()
+                .enumerate()
+                .all(|(i, instruction)| match instruction.target() {
+                    Some(target) if i < start || i > end => target <= start || target > end,
+                    _ => true,
+                })
+        })
+        .collect()
+}
+
+// Puts instructions in front of the one at `at`. Jumps to it from the
+// instructions that `enters` picks run the new instructions first, the
+// others skip them.
+fn insert(
+    function: &mut Function,
+    at: usize,
+    instructions: &[Instruction],
+    span: Span,
+    enters: impl Fn(usize) -> bool,
+) {
+    let count = instructions.len();
+    for (i, instruction) in function.code.iter_mut().enumerate() {
+        if let Some(target) = instruction.target_mut() {
+            if *target > at || (*target == at && !enters(i)) {
+                *target += count;
+            }
+        }
+    }
+    function.code.splice(at..at, instructions.iter().copied());
+    function
+        .spans
+        .splice(at..at, std::iter::repeat_n(span, count));
+}
+
+// Which instructions write to every register.
+fn writers(function: &Function, registers: usize) -> Vec<Vec<usize>> {
+    let mut writers = vec![vec![]; registers];
+    for (i, instruction) in function.code.iter().enumerate() {
+        if let Some(dst) = instruction.dst() {
+            writers[dst].push(i);
+        }
+    }
+    writers
+}
+
+// Loads of constants move in front of the loop they are in:
+//
+//     loop: constant r5, 10; less r4, r3, r5     becomes
+//     constant r8, 10; loop: move r5, r8; less r4, r3, r5
+//
+// after which the move is usually propagated away. A register that nothing
+// else writes to and that holds nothing yet when the loop starts can have
+// its load moved as it is, without the move.
+fn hoist_invariants(function: &mut Function, captured: &HashSet<Register>) -> bool {
+    let mut fresh = used_registers(function).max(function.registers);
+    let writers = writers(function, fresh);
+    let live = live_out(&function.code, fresh);
+
+    for (start, end) in loops(&function.code) {
+        let mut live_in = live[start].clone();
+        if let Some(dst) = function.code[start].dst() {
+            live_in[dst] = false;
+        }
+        for r in function.code[start].reads() {
+            live_in[r] = true;
+        }
+
+        let mut hoisted: Vec<Instruction> = vec![];
+        let mut removed = vec![false; function.code.len()];
+        let mut span = function.spans[start];
+        for (i, slot) in function
+            .code
+            .iter_mut()
+            .enumerate()
+            .take(end + 1)
+            .skip(start)
+        {
+            let instruction = *slot;
+            let dst = match instruction {
+                Instruction::Constant { dst, .. }
+                | Instruction::Null { dst }
+                | Instruction::Bool { dst, .. }
+                    if !captured.contains(&dst) =>
+                {
+                    dst
+                }
+                _ => continue,
+            };
+            if hoisted.is_empty() {
+                span = function.spans[i];
+            }
+            if writers[dst].len() == 1 && !live_in[dst] {
+                hoisted.push(instruction);
+                removed[i] = true;
+                continue;
+            }
+            let mut load = instruction;
+            *load.dst_mut().unwrap() = fresh;
+            let src = match hoisted.iter().find(|h| {
+                let mut h = **h;
+                *h.dst_mut().unwrap() = fresh;
+                h == load
+            }) {
+                Some(h) => h.dst().unwrap(),
+                None => {
+                    hoisted.push(load);
+                    fresh += 1;
+                    fresh - 1
+                }
+            };
+            *slot = Instruction::Move { dst, src };
+        }
+        if hoisted.is_empty() {
+            continue;
+        }
+
+        let start = start - removed[..start].iter().filter(|r| **r).count();
+        let end = end - removed[..end].iter().filter(|r| **r).count();
+        remove(function, &removed);
+        insert(function, start, &hoisted, span, |i| i < start || i > end);
+        return true;
+    }
+    false
+}
+
+// The instructions that may have written what a register holds when a loop
+// is entered, or nothing if it may not have been written yet.
+fn entering_writers(
+    code: &[Instruction],
+    r: Register,
+    start: usize,
+    end: usize,
+) -> Option<Vec<usize>> {
+    let mut predecessors = vec![vec![]; code.len() + 1];
+    for i in 0..code.len() {
+        for successor in successors(code, i) {
+            predecessors[successor].push(i);
+        }
+    }
+    if start == 0 {
+        return None;
+    }
+    let mut pending: Vec<usize> = predecessors[start]
+        .iter()
+        .copied()
+        .filter(|&i| i < start || i > end)
+        .collect();
+    let mut seen = vec![false; code.len()];
+    let mut writers = vec![];
+    while let Some(i) = pending.pop() {
+        if seen[i] {
+            continue;
+        }
+        seen[i] = true;
+        if code[i].dst() == Some(r) {
+            writers.push(i);
+        } else if i == 0 {
+            return None;
+        } else {
+            pending.extend(&predecessors[i]);
+        }
+    }
+    Some(writers)
+}
+
+// A register that a loop only ever adds the same integer to, and that holds
+// an integer when the loop starts, counts the passes through the loop. Where the
+// loop multiplies it by a constant, a second counter stepped along with it
+// holds the product instead, which turns the index arithmetic of loops like
+//
+//     for (var i = 0; i < n; i = i + 1) { xs[i * 2] = 0; }
+//
+// into an addition. Sums of ints are exact, so both counters agree for as
+// long as the product fits in an int.
+fn reduce_strength(function: &mut Function, captured: &HashSet<Register>) -> bool {
+    let fresh = used_registers(function).max(function.registers);
+    let writers = writers(function, fresh);
+    let code = &function.code;
+    let constants = &function.constants;
+    let params = entry_depth(function.arity, function.rest);
+
+    let loads_integer = |i: usize| match code[i] {
+        Instruction::Constant { index, .. } => match constants[index] {
+            Constant::Int(n) => Some(n),
+            _ => None,
+        },
+        _ => None,
+    };
+    // The integer a register holds, if it is loaded once before `start`.
+    let integer = |r: Register, start: usize| match writers[r][..] {
+        [i] if i < start => loads_integer(i),
+        _ => None,
+    };
+    // The instruction that steps a counter, and how far.
+    let counter = |r: Register, start: usize, end: usize| {
+        if r < params || captured.contains(&r) {
+            return None;
+        }
+        let inside: Vec<usize> = writers[r]
+            .iter()
+            .copied()
+            .filter(|&i| i >= start && i <= end)
+            .collect();
+        // Hoisting leaves moves from the constant a counter starts at.
+        let starts_at_integer = entering_writers(code, r, start, end).is_some_and(|entering| {
+            entering.iter().all(|&i| match code[i] {
+                Instruction::Move { src, .. } => integer(src, i).is_some(),
+                _ => loads_integer(i).is_some(),
+            })
+        });
+        if !starts_at_integer {
+            return None;
+        }
+        let step = match inside[..] {
+            [i] => i,
+            _ => return None,
+        };
+        let by = match code[step] {
+            Instruction::Binary {
+                op: OpCode::Add,
+                left,
+                right,
+                ..
+            } if left == r => integer(right, start),
+            Instruction::Binary {
+                op: OpCode::Add,
+                left,
+                right,
+                ..
+            } if right == r => integer(left, start),
+            Instruction::Binary {
+                op: OpCode::Subtract,
+                left,
+                right,
+                ..
+            } if left == r => integer(right, start).and_then(i64::checked_neg),
+            _ => None,
+        }?;
+        Some((step, by))
+    };
+
+    for (start, end) in loops(code) {
+        for (i, instruction) in code.iter().enumerate().take(end + 1).skip(start) {
+            let (dst, left, right) = match *instruction {
+                Instruction::Binary {
+                    op: OpCode::Multiply,
+                    dst,
+                    left,
+                    right,
+                } => (dst, left, right),
+                _ => continue,
+            };
+            let found = [(left, right), (right, left)]
+                .iter()
+                .find_map(|&(r, factor)| {
+                    let (step, by) = counter(r, start, end)?;
+                    Some((r, factor, step, by, integer(factor, start)?))
+                });
+            let (r, factor, step, by, times) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            let by = match by.checked_mul(times) {
+                Some(_) if by == 1 => None,
+                Some(by) => Some(by),
+                None => continue,
+            };
+
+            // A counter that goes up by one steps the product by the factor,
+            // and any other by the step times the factor.
+            let product = fresh;
+            let mut setup = vec![Instruction::Binary {
+                op: OpCode::Multiply,
+                dst: product,
+                left: r,
+                right: factor,
+            }];
+            let increment = if let Some(by) = by {
+                let index = match constants
+                    .iter()
+                    .position(|c| matches!(c, Constant::Int(n) if *n == by))
+                {
+                    Some(index) => index,
+                    None => {
+                        function.constants.push(Constant::Int(by));
+                        function.constants.len() - 1
+                    }
+                };
+                setup.push(Instruction::Constant {
+                    dst: fresh + 1,
+                    index,
+                });
+                fresh + 1
+            } else {
+                factor
+            };
+            let span = function.spans[i];
+            let step_span = function.spans[step];
+            function.code[i] = Instruction::Move { dst, src: product };
+            let stepped = Instruction::Binary {
+                op: OpCode::Add,
+                dst: product,
+                left: product,
+                right: increment,
+            };
+            insert(function, step + 1, &[stepped], step_span, |_| false);
+            insert(function, start, &setup, span, |i| i < start || i > end + 1);
+            return true;
+        }
+    }
+    false
+}
+
+pub fn disassemble(function: &Function) -> String {
+    disassemble_function(function, &function.globals)
+}
+
+// The functions in a script use the global slots of the script.
+fn disassemble_function(function: &Function, globals: &[String]) -> String {
+    let mut out = String::new();
+    writeln!(out, "== {} ==", function.name).unwrap();
+    let register = |r: &Register| format!("r{}", r);
+    let constant = |index: usize| format!("{} {}", index, function.constants[index]);
+    let global = |slot: usize| match globals.get(slot) {
+        Some(name) => format!("{} {}", slot, name),
+        None => slot.to_string(),
+    };
+    for (i, instruction) in function.code.iter().enumerate() {
+        let line = function.spans[i].line;
+        if i > 0 && function.spans[i - 1].line == line {
+            write!(out, "{:04}    | ", i).unwrap();
+        } else {
+            write!(out, "{:04} {:>4} ", i, line).unwrap();
+        }
+        let operands = match *instruction {
+            Instruction::Constant { dst, index } => {
+                format!("{}, {}", register(&dst), constant(index))
+            }
+            Instruction::Function { dst, index } => format!(
+                "{}, {} <function {}>",
+                register(&dst),
+                index,
+                function.functions[index].name
+            ),
+            Instruction::Closure { dst, index } => {
+                let closure = &function.functions[index];
+                let upvalues: Vec<String> =
+                    closure.upvalues.iter().map(|u| u.to_string()).collect();
+                format!(
+                    "{}, {} <function {}> [{}]",
+                    register(&dst),
+                    index,
+                    closure.name,
+                    upvalues.join(", ")
+                )
+            }
+            Instruction::GetUpvalue { dst, index } => format!("{}, {}", register(&dst), index),
+            Instruction::SetUpvalue { index, src } => format!("{}, {}", index, register(&src)),
+            Instruction::DefineGlobal { name, src } | Instruction::SetGlobal { name, src } => {
+                format!("{}, {}", constant(name), register(&src))
+            }
+            Instruction::GetGlobal { dst, name } | Instruction::Import { dst, path: name } => {
+                format!("{}, {}", register(&dst), constant(name))
+            }
+            Instruction::DefineSlot { slot, src } | Instruction::SetSlot { slot, src } => {
+                format!("{}, {}", global(slot), register(&src))
+            }
+            Instruction::GetSlot { dst, slot } => format!("{}, {}", register(&dst), global(slot)),
+            Instruction::GetProperty { dst, object, name } => format!(
+                "{}, {}, {}",
+                register(&dst),
+                register(&object),
+                constant(name)
+            ),
+            Instruction::SetProperty { object, name, src } => format!(
+                "{}, {}, {}",
+                register(&object),
+                constant(name),
+                register(&src)
+            ),
+            Instruction::Jump { target } => format!("-> {:04}", target),
+            Instruction::JumpIfFalse { condition, target } => {
+                format!("{} -> {:04}", register(&condition), target)
+            }
+            Instruction::Try { dst, target } => format!("{} -> {:04}", register(&dst), target),
+            Instruction::Switch { value, cases, .. } => {
+                format!("{}, {}", register(&value), constant(cases))
+            }
+            Instruction::Call { dst, callee, count } => {
+                format!("{}, {}, {}", register(&dst), register(&callee), count)
+            }
+            Instruction::TailCall { callee, count } => format!("{}, {}", register(&callee), count),
+            Instruction::List { dst, start, count }
+            | Instruction::Map { dst, start, count }
+            | Instruction::Concat { dst, start, count } => {
+                format!("{}, {}, {}", register(&dst), register(&start), count)
+            }
+            Instruction::Range {
+                dst,
+                start,
+                end,
+                inclusive,
+            } => format!(
+                "{}, {}, {}{}",
+                register(&dst),
+                register(&start),
+                register(&end),
+                if inclusive { ", inclusive" } else { "" }
+            ),
+            Instruction::Class { dst, name } => format!("{}, {}", register(&dst), constant(name)),
+            Instruction::Member {
+                class, name, src, ..
+            } => format!(
+                "{}, {}, {}",
+                register(&class),
+                constant(name),
+                register(&src)
+            ),
+            Instruction::GetSuper {
+                dst,
+                object,
+                superclass,
+                name,
+            } => format!(
+                "{}, {}, {}, {}",
+                register(&dst),
+                register(&object),
+                register(&superclass),
+                constant(name)
+            ),
+            instruction => instruction
+                .dst()
+                .iter()
+                .chain(instruction.operands().iter())
+                .map(register)
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        writeln!(out, "{:<13} {}", instruction.name(), operands).unwrap();
+    }
+    for function in &function.functions {
+        out.push('\n');
+        out.push_str(&disassemble_function(function, globals));
+    }
+    out
+}
@@ -0,0 +1,304 @@
+use crate::ast::visit::*;
+use crate::ast::*;
+
+// Desugaring rewrites constructs that are only there to make scripts nicer
+// to write in terms of simpler ones, so the compiler has fewer kinds of node
+// to deal with. Afterwards a program has no for loops or compound
+// assignments, except for the compound assignments described below. Nodes
+// made up along the way get new ids and the span of the code they replace.
+pub fn desugar(program: &mut Program) {
+    let mut desugarer = Desugarer {
+        next_id: program.next_id,
+    };
+    desugarer.visit_program_mut(program);
+    program.next_id = desugarer.next_id;
+}
+
+// Temporaries start with a character that can never be part of a name in a
+// script, so they can never clash with a name the script uses.
+const ITERATOR: &str = "$iterator";
+const OBJECT: &str = "$object";
+const INDEX: &str = "$index";
+
+// The iterator protocol. Iterating over a value asks it for an iterator,
+// which hands out items until it has none left.
+pub const ITER_METHOD: &str = "iter";
+pub const HAS_NEXT_METHOD: &str = "has_next";
+pub const NEXT_METHOD: &str = "next";
+
+struct Desugarer {
+    next_id: NodeId,
+}
+
+impl Desugarer {
+    fn id(&mut self) -> NodeId {
+        let id = self.next_id;
+        self.next_id = id.next();
+        id
+    }
+
+    fn expr(&mut self, kind: ExprKind, span: Span) -> Expr {
+        Expr {
+            id: self.id(),
+            kind,
+            span,
+        }
+    }
+
+    fn stmt(&mut self, kind: StmtKind, span: Span) -> Stmt {
+        Stmt {
+            id: self.id(),
+            kind,
+            span,
+        }
+    }
+
+    fn identifier(&mut self, name: &str, span: Span) -> Identifier {
+        Identifier {
+            id: self.id(),
+            name: String::from(name),
+            span,
+        }
+    }
+
+    fn variable(&mut self, name: &str, span: Span) -> Expr {
+        self.expr(ExprKind::Variable(String::from(name)), span)
+    }
+
+    fn var(&mut self, name: &str, init: Expr) -> Stmt {
+        let span = init.span;
+        let name = self.identifier(name, span);
+        let kind = StmtKind::Var {
+            name,
+            ty: None,
+            init: Some(init),
+            constant: false,
+        };
+        self.stmt(kind, span)
+    }
+
+    fn method_call(&mut self, object: Expr, method: &str) -> Expr {
+        let span = object.span;
+        let name = self.identifier(method, span);
+        let callee = self.expr(
+            ExprKind::Member {
+                object: Box::new(object),
+                name,
+            },
+            span,
+        );
+        let call = Call {
+            callee: Box::new(callee),
+            args: Vec::new(),
+            names: Vec::new(),
+            span,
+        };
+        self.expr(ExprKind::Call(Box::new(call)), span)
+    }
+
+    // `for (item in items) body` becomes
+    //
+    //     {
+    //         var $iterator = items.iter();
+    //         while ($iterator.has_next()) {
+    //             var item = $iterator.next();
+    //             body
+    //         }
+    //     }
+    //
+    // The loop keeps its label, so break and continue still work the same.
+    // Every time around the loop gets a new item variable, which matters
+    // to lambdas that capture it.
+    fn for_loop(&mut self, stmt: &mut Stmt) {
+        let span = stmt.span;
+        let (label, variable, iterable, body) =
+            match std::mem::replace(&mut stmt.kind, StmtKind::Block(Vec::new())) {
+                StmtKind::For {
+                    label,
+                    variable,
+                    iterable,
+                    body,
+                } => (label, variable, iterable, body),
+                kind => {
+                    stmt.kind = kind;
+                    return;
+                }
+            };
+
+        let iter = self.method_call(iterable, ITER_METHOD);
+        let iterator = self.var(ITERATOR, iter);
+
+        let condition = self.variable(ITERATOR, span);
+        let condition = self.method_call(condition, HAS_NEXT_METHOD);
+        let variable_span = variable.span;
+        let next = self.variable(ITERATOR, variable_span);
+        let next = self.method_call(next, NEXT_METHOD);
+        let item = StmtKind::Var {
+            name: variable,
+            ty: None,
+            init: Some(next),
+            constant: false,
+        };
+        let item = self.stmt(item, variable_span);
+        let body_span = body.span;
+        let body = self.stmt(StmtKind::Block(vec![item, *body]), body_span);
+        let kind = StmtKind::While {
+            label,
+            condition,
+            body: Box::new(body),
+        };
+        let while_loop = self.stmt(kind, span);
+
+        stmt.kind = StmtKind::Block(vec![iterator, while_loop]);
+    }
+
+    // `target op= value` becomes `target = target op value`. The parts of a
+    // target like `items[i]` must only be worked out once, so when they
+    // could do something each time, like `next().count += 1`, they are first
+    // stored in temporaries. That takes a block, so it only happens for an
+    // assignment that is a whole statement on its own. Anywhere else those
+    // assignments are left as they are.
+    fn compound_assignment(&mut self, expr: &mut Expr) {
+        match &expr.kind {
+            ExprKind::Assign {
+                op: Some(_),
+                target,
+                ..
+            } if !needs_temporaries(target) => {}
+            _ => return,
+        }
+        let span = expr.span;
+        if let ExprKind::Assign {
+            op: Some(op),
+            target,
+            value,
+        } = std::mem::replace(&mut expr.kind, ExprKind::Null)
+        {
+            let load = self.copy(&target);
+            let value = self.expr(
+                ExprKind::Binary {
+                    op,
+                    left: Box::new(load),
+                    right: value,
+                },
+                span,
+            );
+            expr.kind = ExprKind::Assign {
+                op: None,
+                target,
+                value: Box::new(value),
+            };
+        }
+    }
+
+    // Moves the parts of an assignment target into temporaries, leaving
+    // variables holding them in their place. Both the object and the index
+    // are moved so that they are still worked out in the same order.
+    fn temporaries(&mut self, target: &mut Expr) -> Vec<Stmt> {
+        let mut temporaries = Vec::new();
+        if !needs_temporaries(target) {
+            return temporaries;
+        }
+        let (object, index) = match &mut target.kind {
+            ExprKind::Member { object, .. } => (object, None),
+            ExprKind::Index { object, index } => (object, Some(index)),
+            _ => return temporaries,
+        };
+        let variable = self.variable(OBJECT, object.span);
+        let object = std::mem::replace(&mut **object, variable);
+        temporaries.push(self.var(OBJECT, object));
+        if let Some(index) = index {
+            let variable = self.variable(INDEX, index.span);
+            let index = std::mem::replace(&mut **index, variable);
+            temporaries.push(self.var(INDEX, index));
+        }
+        temporaries
+    }
+
+    // A copy of an expression is a different node, so it needs new ids.
+    fn copy(&mut self, expr: &Expr) -> Expr {
+        let mut copy = expr.clone();
+        Renumber { desugarer: self }.visit_expr_mut(&mut copy);
+        copy
+    }
+}
+
+impl VisitorMut for Desugarer {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+        if let StmtKind::For { .. } = stmt.kind {
+            self.for_loop(stmt);
+            return;
+        }
+
+        // Compound assignments that are left after the expression has been
+        // desugared need temporaries.
+        let temporaries = match &mut stmt.kind {
+            StmtKind::Expression(Expr {
+                kind:
+                    ExprKind::Assign {
+                        op: Some(_),
+                        target,
+                        ..
+                    },
+                ..
+            }) => self.temporaries(target),
+            _ => return,
+        };
+        if temporaries.is_empty() {
+            return;
+        }
+        if let StmtKind::Expression(expr) = &mut stmt.kind {
+            self.compound_assignment(expr);
+        }
+        let assignment = std::mem::replace(&mut stmt.kind, StmtKind::Block(temporaries));
+        let assignment = self.stmt(assignment, stmt.span);
+        if let StmtKind::Block(body) = &mut stmt.kind {
+            body.push(assignment);
+        }
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        if let ExprKind::Assign { .. } = &expr.kind {
+            self.compound_assignment(expr);
+        }
+    }
+}
+
+// Whether the parts of an assignment target could do something when they
+// are worked out, in which case they can't be worked out twice.
+fn needs_temporaries(target: &Expr) -> bool {
+    let simple = |expr: &Expr| {
+        matches!(
+            expr.kind,
+            ExprKind::Variable(_)
+                | ExprKind::This
+                | ExprKind::Int(_)
+                | ExprKind::Float(_)
+                | ExprKind::String(_)
+                | ExprKind::Bool(_)
+                | ExprKind::Null
+        )
+    };
+    match &target.kind {
+        ExprKind::Member { object, .. } => !simple(object),
+        ExprKind::Index { object, index } => !simple(object) || !simple(index),
+        _ => false,
+    }
+}
+
+struct Renumber<'a> {
+    desugarer: &'a mut Desugarer,
+}
+
+impl VisitorMut for Renumber<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        expr.id = self.desugarer.id();
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_identifier_mut(&mut self, ident: &mut Identifier) {
+        ident.id = self.desugarer.id();
+    }
+}
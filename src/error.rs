@@ -1,33 +1,246 @@
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Severity {
+    // Something is definitely wrong and the code cannot be run.
+    Error,
+    // The code can run but probably does not do what was intended.
+    Warning,
+}
+
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// Another place that helps to explain a diagnostic, like where a name was
+// first declared. It is in the same file unless it says otherwise.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Related {
+    msg: String,
+    fname: String,
+    src_line: u32,
+    src_column: u32,
+}
+
+impl Related {
+    pub fn message(&self) -> &str {
+        &self.msg[..]
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.fname[..]
+    }
+
+    pub fn line(&self) -> u32 {
+        self.src_line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.src_column
+    }
+}
+
+// The details live behind a box so that an Error is only as large as a
+// pointer. The parser passes a result that could hold one out of every
+// function, and deeply nested code keeps a lot of those on the stack.
+#[derive(Clone, PartialEq, Debug)]
 pub struct Error {
+    details: Box<Details>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Details {
     msg: String,
     fname: String,
     src_line: u32,
     src_column: u32,
+    severity: Severity,
+    related: Vec<Related>,
 }
 
 impl Error {
     pub fn new(msg: &str, file: &str, ln: u32, col: u32) -> Self {
         Self {
+            details: Box::new(Details {
+                msg: String::from(msg),
+                fname: String::from(file),
+                src_line: ln,
+                src_column: col,
+                severity: Severity::Error,
+                related: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn warning(msg: &str, file: &str, ln: u32, col: u32) -> Self {
+        let mut warning = Error::new(msg, file, ln, col);
+        warning.details.severity = Severity::Warning;
+        warning
+    }
+
+    pub fn with_related(self, msg: &str, ln: u32, col: u32) -> Self {
+        let file = self.details.fname.clone();
+        self.with_related_in(msg, &file[..], ln, col)
+    }
+
+    pub fn with_related_in(mut self, msg: &str, file: &str, ln: u32, col: u32) -> Self {
+        self.details.related.push(Related {
             msg: String::from(msg),
             fname: String::from(file),
             src_line: ln,
             src_column: col,
+        });
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.details.msg[..]
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.details.fname[..]
+    }
+
+    pub fn line(&self) -> u32 {
+        self.details.src_line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.details.src_column
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.details.severity
+    }
+
+    pub fn is_warning(&self) -> bool {
+        self.details.severity == Severity::Warning
+    }
+
+    pub fn related(&self) -> &[Related] {
+        &self.details.related[..]
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.details.fname, self.details.src_line, self.details.src_column, self.details.msg
+        )
+    }
+}
+
+// An error that stopped a script while it was running, along with the calls
+// that were being made when it happened.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RuntimeError {
+    msg: String,
+    kind: RuntimeErrorKind,
+    // The innermost call first, so the first frame is where the error is.
+    trace: Vec<TraceFrame>,
+}
+
+// Why a script stopped. Only errors of the script itself can be caught by
+// it. The others come from limits the host put on it, and leave the script
+// where it was so the host can carry on running it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RuntimeErrorKind {
+    Error,
+    FuelExhausted,
+    Interrupted,
+    TimedOut,
+}
+
+// A function that was running and where it was, which is the call it was
+// making for every frame but the first.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceFrame {
+    // Nothing for the code at the top level of a script.
+    pub function: Option<String>,
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl RuntimeError {
+    pub fn new(msg: &str, trace: Vec<TraceFrame>) -> Self {
+        assert!(!trace.is_empty(), "an error happens in some function");
+        Self {
+            msg: String::from(msg),
+            kind: RuntimeErrorKind::Error,
+            trace,
         }
     }
 
+    pub fn with_kind(mut self, kind: RuntimeErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     pub fn message(&self) -> &str {
         &self.msg[..]
     }
 
+    pub fn kind(&self) -> RuntimeErrorKind {
+        self.kind
+    }
+
+    pub fn trace(&self) -> &[TraceFrame] {
+        &self.trace[..]
+    }
+
     pub fn file_name(&self) -> &str {
-        &self.fname[..]
+        &self.trace[0].file[..]
     }
 
     pub fn line(&self) -> u32 {
-        self.src_line
+        self.trace[0].line
     }
 
     pub fn column(&self) -> u32 {
-        self.src_column
+        self.trace[0].column
+    }
+
+    // The error as a diagnostic, with a note at every call that led to it.
+    pub fn diagnostic(&self) -> Error {
+        let mut diagnostic =
+            Error::new(&self.msg[..], self.file_name(), self.line(), self.column());
+        for pair in self.trace.windows(2) {
+            let (callee, caller) = (&pair[0], &pair[1]);
+            let msg = match &callee.function {
+                Some(name) => format!("{} was called from here", name),
+                None => String::from("the script was called from here"),
+            };
+            diagnostic =
+                diagnostic.with_related_in(&msg[..], &caller.file[..], caller.line, caller.column);
+        }
+        diagnostic
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file_name(),
+            self.line(),
+            self.column(),
+            self.msg
+        )
+    }
+}
+
+impl std::fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(name) = &self.function {
+            write!(f, "in {} ", name)?;
+        }
+        write!(f, "at {}:{}:{}", self.file, self.line, self.column)
     }
 }
@@ -0,0 +1,146 @@
+use crate::ast::visit::*;
+use crate::ast::*;
+use crate::bytecode::format_number;
+use crate::value::{ops, Value};
+
+// Constant folding evaluates the parts of expressions that do not depend on
+// anything at runtime, so `2 * 60 * 60` is stored as `7200` and never has to
+// be computed again. Only operations whose result is certain are folded.
+// Anything that could fail, like dividing by zero, is left for the runtime
+// so the error is still reported where it happens.
+pub fn fold_constants(program: &mut Program) {
+    ConstantFolder.visit_program_mut(program);
+}
+
+pub fn fold_expression(expr: &mut Expr) {
+    ConstantFolder.visit_expr_mut(expr);
+}
+
+struct ConstantFolder;
+
+impl VisitorMut for ConstantFolder {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        // Children are folded first so that constants bubble up through
+        // nested expressions like `(1 + 2) * 3`.
+        walk_expr_mut(self, expr);
+
+        let folded = match &mut expr.kind {
+            ExprKind::Unary { op, operand } => fold_unary(*op, operand),
+            ExprKind::Binary { op, left, right } => fold_binary(*op, left, right),
+            ExprKind::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => match condition.kind {
+                ExprKind::Bool(true) => Some(take(then_branch).kind),
+                ExprKind::Bool(false) => Some(take(else_branch).kind),
+                _ => None,
+            },
+            ExprKind::InterpolatedString(parts) => fold_interpolation(parts),
+            _ => None,
+        };
+
+        // The folded expression keeps the span of everything it replaced.
+        if let Some(kind) = folded {
+            expr.kind = kind;
+        }
+    }
+}
+
+fn take(expr: &mut Expr) -> Expr {
+    std::mem::replace(expr, Expr::new(ExprKind::Null, expr.span))
+}
+
+fn fold_unary(op: UnaryOp, operand: &Expr) -> Option<ExprKind> {
+    match (op, &operand.kind) {
+        (UnaryOp::Negate, ExprKind::Int(n)) => n.checked_neg().map(ExprKind::Int),
+        (UnaryOp::Negate, ExprKind::Float(n)) => Some(ExprKind::Float(-n)),
+        (UnaryOp::BitNot, ExprKind::Int(n)) => Some(ExprKind::Int(!n)),
+        (UnaryOp::Not, ExprKind::Bool(b)) => Some(ExprKind::Bool(!b)),
+        _ => None,
+    }
+}
+
+fn fold_binary(op: BinaryOp, left: &mut Expr, right: &mut Expr) -> Option<ExprKind> {
+    // A constant on the left of `and` or `or` can decide the result without
+    // looking at the right side at all, the same way it would at runtime.
+    match (op, &left.kind) {
+        (BinaryOp::And, ExprKind::Bool(false)) => return Some(ExprKind::Bool(false)),
+        (BinaryOp::Or, ExprKind::Bool(true)) => return Some(ExprKind::Bool(true)),
+        _ => {}
+    }
+
+    let kind = match (&left.kind, &right.kind) {
+        (ExprKind::Int(_), _) | (ExprKind::Float(_), _) => fold_numbers(op, left, right)?,
+        (ExprKind::String(a), ExprKind::String(b)) => match op {
+            BinaryOp::Add => ExprKind::String(format!("{}{}", a, b)),
+            BinaryOp::Equal => ExprKind::Bool(a == b),
+            BinaryOp::NotEqual => ExprKind::Bool(a != b),
+            _ => return None,
+        },
+        (ExprKind::Bool(a), ExprKind::Bool(b)) => match op {
+            BinaryOp::And => ExprKind::Bool(*a && *b),
+            BinaryOp::Or => ExprKind::Bool(*a || *b),
+            BinaryOp::Equal => ExprKind::Bool(a == b),
+            BinaryOp::NotEqual => ExprKind::Bool(a != b),
+            _ => return None,
+        },
+        (ExprKind::Null, ExprKind::Null) => match op {
+            BinaryOp::Equal => ExprKind::Bool(true),
+            BinaryOp::NotEqual => ExprKind::Bool(false),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+// Constants spliced into a formatted string become part of its text, and
+// text next to text is joined, so `"{1 + 1} of {n}"` is left with two parts
+// and `"{1 + 1} of {4}"` is just the string "2 of 4".
+fn fold_interpolation(parts: &mut Vec<StringPart>) -> Option<ExprKind> {
+    let mut folded: Vec<StringPart> = Vec::new();
+    for part in parts.drain(..) {
+        let part = match part {
+            StringPart::Expr(expr) => match expr.kind {
+                ExprKind::String(text) => StringPart::Literal(text),
+                ExprKind::Int(n) => StringPart::Literal(n.to_string()),
+                ExprKind::Float(n) => StringPart::Literal(format_number(n)),
+                ExprKind::Bool(b) => StringPart::Literal(b.to_string()),
+                ExprKind::Null => StringPart::Literal(String::from("null")),
+                _ => StringPart::Expr(expr),
+            },
+            literal => literal,
+        };
+        match (folded.last_mut(), part) {
+            (Some(StringPart::Literal(text)), StringPart::Literal(more)) => text.push_str(&more),
+            (_, StringPart::Literal(text)) if text.is_empty() => {}
+            (_, part) => folded.push(part),
+        }
+    }
+    match &folded[..] {
+        [] => Some(ExprKind::String(String::new())),
+        [StringPart::Literal(text)] => Some(ExprKind::String(text.clone())),
+        _ => {
+            *parts = folded;
+            None
+        }
+    }
+}
+
+// Numbers are worked out the same way as at runtime, and anything that
+// would be an error there, like an int that overflows, is left alone.
+fn fold_numbers(op: BinaryOp, left: &Expr, right: &Expr) -> Option<ExprKind> {
+    let number = |expr: &Expr| match expr.kind {
+        ExprKind::Int(n) => Some(Value::Int(n)),
+        ExprKind::Float(n) => Some(Value::Float(n)),
+        _ => None,
+    };
+    match ops::arithmetic(op, &number(left)?, &number(right)?)? {
+        Ok(Value::Int(n)) => Some(ExprKind::Int(n)),
+        Ok(Value::Float(n)) => Some(ExprKind::Float(n)),
+        Ok(Value::Bool(b)) => Some(ExprKind::Bool(b)),
+        _ => None,
+    }
+}
@@ -0,0 +1,1126 @@
+use crate::ast::*;
+use crate::builtins::{Context, Native, BUILTINS};
+use crate::compile::Signature;
+use crate::desugar::{HAS_NEXT_METHOD, ITER_METHOD, NEXT_METHOD};
+use crate::error::{RuntimeError, TraceFrame};
+use crate::parse::INITIALIZER_NAME;
+use crate::value::ops::{self, Access};
+use crate::value::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+// The interpreter runs a program straight from its syntax tree. It is much
+// slower than compiling the program and running the bytecode, but simple
+// enough to trust, which makes it the reference the virtual machine is
+// checked against: a script has to print the same things and end the same
+// way whichever of the two runs it.
+//
+// Variables live in scopes, which are objects in the heap so that a closure
+// can keep the scope it was made in for as long as it needs it. The top
+// level of the program has no scope, its variables are the globals.
+
+// How deep calls can go before the script is stopped. Every call takes up
+// some of the stack of the thread the interpreter runs on, so the limit is
+// kept low enough for the stack of a thread that was spawned.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+// A function and the scope it was made in, which it looks up the variables
+// it doesn't declare in.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Closure {
+    pub def: Arc<FunctionDef>,
+    pub scope: Option<Handle>,
+}
+
+// The parts of a function declaration or lambda that calling it needs.
+#[derive(PartialEq, Debug)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub defaults: Vec<Expr>,
+    pub rest: Option<String>,
+    pub body: Vec<Stmt>,
+    // Calling the initializer of a class always gives back the instance.
+    pub initializer: bool,
+}
+
+impl FunctionDef {
+    fn signature(&self) -> Signature {
+        Signature {
+            arity: self.params.len(),
+            min_arity: self.params.len() - self.defaults.len(),
+            rest: self.rest.is_some(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Scope {
+    pub vars: HashMap<String, Value>,
+    pub parent: Option<Handle>,
+}
+
+// What running a statement left the interpreter to do next.
+enum Flow {
+    Normal,
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Value),
+}
+
+type Exec<T> = Result<T, RuntimeError>;
+
+pub struct Interpreter {
+    heap: Heap,
+    globals: HashMap<String, Value>,
+    out: Box<dyn Write>,
+    file: String,
+    // The innermost scope of the code that is running.
+    scope: Option<Handle>,
+    // The name of every function that has been called and hasn't returned,
+    // and where it was called from.
+    calls: Vec<(String, Span)>,
+    // Every function is only turned into a definition once, however many
+    // closures are made from it.
+    defs: HashMap<NodeId, Arc<FunctionDef>>,
+    // The value of the throw statement that the error on its way out came
+    // from. Any other error is caught as its message.
+    thrown: Option<Value>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    // An interpreter that prints to stdout.
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        let mut heap = Heap::new();
+        let globals = BUILTINS
+            .iter()
+            .map(|native| {
+                let handle = heap.alloc(Object::Native(*native));
+                (String::from(native.name), Value::Object(handle))
+            })
+            .collect();
+        Self {
+            heap,
+            globals,
+            out,
+            file: String::new(),
+            scope: None,
+            calls: Vec::new(),
+            defs: HashMap::new(),
+            thrown: None,
+        }
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    // Runs the program, giving back what it returns from the top level, or
+    // null. Globals are kept from one program to the next.
+    pub fn run(&mut self, program: &Program) -> Result<Value, RuntimeError> {
+        self.file = program.name.clone();
+        self.scope = None;
+        self.calls.clear();
+        self.thrown = None;
+        for stmt in &program.body {
+            if let Flow::Return(value) = self.execute(stmt)? {
+                return Ok(value);
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    // The error at the span in the function that is running, traced back
+    // through the calls that led to it.
+    fn error(&self, msg: &str, span: Span) -> RuntimeError {
+        let frame = |function: Option<&(String, Span)>, span: Span| TraceFrame {
+            function: function.map(|(name, _)| name.clone()),
+            file: self.file.clone(),
+            line: span.line,
+            column: span.column,
+        };
+        let mut trace = vec![frame(self.calls.last(), span)];
+        for (i, (_, site)) in self.calls.iter().enumerate().rev() {
+            let caller = if i == 0 { None } else { self.calls.get(i - 1) };
+            trace.push(frame(caller, *site));
+        }
+        RuntimeError::new(msg, trace)
+    }
+
+    fn unsupported(&self, what: &str, span: Span) -> RuntimeError {
+        let msg = format!("{} are not supported by the interpreter yet", what);
+        self.error(&msg[..], span)
+    }
+
+    fn scope_at(&self, handle: Handle) -> &Scope {
+        match self.heap.get(handle) {
+            Object::Scope(scope) => scope,
+            _ => unreachable!("scopes only ever hold scopes"),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Value> {
+        let mut scope = self.scope;
+        while let Some(handle) = scope {
+            let current = self.scope_at(handle);
+            if let Some(value) = current.vars.get(name) {
+                return Some(value.clone());
+            }
+            scope = current.parent;
+        }
+        self.globals.get(name).cloned()
+    }
+
+    fn declare(&mut self, name: &str, value: Value) {
+        match self.scope {
+            Some(handle) => match self.heap.get_mut(handle) {
+                Object::Scope(scope) => {
+                    scope.vars.insert(String::from(name), value);
+                }
+                _ => unreachable!("scopes only ever hold scopes"),
+            },
+            None => {
+                self.globals.insert(String::from(name), value);
+            }
+        }
+    }
+
+    // Stores into a variable that has already been declared.
+    fn assign(&mut self, name: &str, value: Value, span: Span) -> Exec<()> {
+        let mut scope = self.scope;
+        while let Some(handle) = scope {
+            match self.heap.get_mut(handle) {
+                Object::Scope(current) => match current.vars.get_mut(name) {
+                    Some(var) => {
+                        *var = value;
+                        return Ok(());
+                    }
+                    None => scope = current.parent,
+                },
+                _ => unreachable!("scopes only ever hold scopes"),
+            }
+        }
+        match self.globals.get_mut(name) {
+            Some(var) => {
+                *var = value;
+                Ok(())
+            }
+            None => Err(self.undefined(name, span)),
+        }
+    }
+
+    fn undefined(&self, name: &str, span: Span) -> RuntimeError {
+        let msg = format!("{} is not defined", name);
+        self.error(&msg[..], span)
+    }
+
+    // Runs the statements in a scope of their own.
+    fn block(&mut self, stmts: &[Stmt]) -> Exec<Flow> {
+        let scope = self.heap.alloc(Object::Scope(Scope {
+            vars: HashMap::new(),
+            parent: self.scope,
+        }));
+        self.in_scope(scope, |interpreter| {
+            for stmt in stmts {
+                match interpreter.execute(stmt)? {
+                    Flow::Normal => {}
+                    flow => return Ok(flow),
+                }
+            }
+            Ok(Flow::Normal)
+        })
+    }
+
+    fn in_scope<T, F>(&mut self, scope: Handle, run: F) -> Exec<T>
+    where
+        F: FnOnce(&mut Self) -> Exec<T>,
+    {
+        let outer = self.scope.replace(scope);
+        let result = run(self);
+        self.scope = outer;
+        result
+    }
+
+    // Every statement and expression that takes more than a line or two is
+    // run by a method of its own. Calls in the script nest calls to these
+    // methods, and keeping them apart keeps every one of them small on the
+    // stack.
+    fn execute(&mut self, stmt: &Stmt) -> Exec<Flow> {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.evaluate(expr)?;
+            }
+            StmtKind::Var { name, init, .. } => {
+                let value = match init {
+                    Some(init) => self.evaluate(init)?,
+                    None => Value::Null,
+                };
+                self.declare(&name.name, value);
+            }
+            StmtKind::Destructure { pattern, init, .. } => {
+                let value = self.evaluate(init)?;
+                self.bind(pattern, value, true)?;
+            }
+            StmtKind::MultipleAssign { targets, values } => {
+                self.multiple_assignment(targets, values, span)?
+            }
+            StmtKind::Block(stmts) => return self.block(stmts),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    return self.execute(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.execute(else_branch);
+                }
+            }
+            StmtKind::While {
+                label,
+                condition,
+                body,
+            } => return self.while_loop(label, condition, body),
+            StmtKind::DoWhile {
+                label,
+                body,
+                condition,
+            } => return self.do_while_loop(label, body, condition),
+            StmtKind::For {
+                label,
+                variable,
+                iterable,
+                body,
+            } => return self.for_loop(label, variable, iterable, body, span),
+            StmtKind::Match { subject, arms } => return self.match_statement(subject, arms),
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => return self.try_statement(body, catch, finally),
+            StmtKind::Throw(value) => {
+                let value = self.evaluate(value)?;
+                let msg = value.display(&self.heap).to_string();
+                self.thrown = Some(value);
+                return Err(self.error(&msg[..], span));
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Null,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Break(label) => {
+                return Ok(Flow::Break(label.as_ref().map(|l| l.name.clone())));
+            }
+            StmtKind::Continue(label) => {
+                return Ok(Flow::Continue(label.as_ref().map(|l| l.name.clone())));
+            }
+            StmtKind::Function(decl) => self.function_declaration(decl)?,
+            StmtKind::Class(decl) => self.class_declaration(decl)?,
+            StmtKind::Import(_) => return Err(self.unsupported("imports", span)),
+            StmtKind::Export(decl) => return self.execute(decl),
+        }
+        Ok(Flow::Normal)
+    }
+
+    // A finally block that doesn't finish normally takes the place of
+    // whatever the rest of the statement was on its way out with.
+    fn try_statement(
+        &mut self,
+        body: &[Stmt],
+        catch: &Option<CatchClause>,
+        finally: &Option<Vec<Stmt>>,
+    ) -> Exec<Flow> {
+        let mut result = self.block(body);
+        if let (Err(error), Some(catch)) = (&result, catch) {
+            let exception = match self.thrown.take() {
+                Some(value) => value,
+                None => Value::from(error.message()),
+            };
+            let scope = self.heap.alloc(Object::Scope(Scope {
+                vars: HashMap::new(),
+                parent: self.scope,
+            }));
+            result = self.in_scope(scope, |interpreter| {
+                if let Some(binding) = &catch.binding {
+                    interpreter.declare(&binding.name, exception);
+                }
+                interpreter.block(&catch.body)
+            });
+        }
+        if let Some(finally) = finally {
+            let thrown = self.thrown.take();
+            match self.block(finally)? {
+                Flow::Normal => self.thrown = thrown,
+                flow => return Ok(flow),
+            }
+        }
+        result
+    }
+
+    // Every value is worked out before anything is stored.
+    fn multiple_assignment(
+        &mut self,
+        targets: &[Pattern],
+        values: &[Expr],
+        span: Span,
+    ) -> Exec<()> {
+        let mut evaluated = Vec::new();
+        for value in values {
+            evaluated.push(self.evaluate(value)?);
+        }
+        if evaluated.len() == 1 && targets.len() > 1 {
+            let list = Pattern::new(PatternKind::List(targets.to_vec()), span);
+            self.bind(&list, evaluated.remove(0), false)?;
+        } else {
+            for (target, value) in targets.iter().zip(evaluated) {
+                self.bind(target, value, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn while_loop(
+        &mut self,
+        label: &Option<Identifier>,
+        condition: &Expr,
+        body: &Stmt,
+    ) -> Exec<Flow> {
+        while self.evaluate(condition)?.is_truthy() {
+            match self.execute(body)? {
+                Flow::Break(target) if targets(label, &target) => break,
+                Flow::Continue(target) if targets(label, &target) => {}
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn do_while_loop(
+        &mut self,
+        label: &Option<Identifier>,
+        body: &Stmt,
+        condition: &Expr,
+    ) -> Exec<Flow> {
+        loop {
+            match self.execute(body)? {
+                Flow::Break(target) if targets(label, &target) => break,
+                Flow::Continue(target) if targets(label, &target) => {}
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+            if !self.evaluate(condition)?.is_truthy() {
+                break;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // Loops go through the same iterator protocol as the loops of the
+    // compiler, so anything with an iter method can be looped over.
+    fn for_loop(
+        &mut self,
+        label: &Option<Identifier>,
+        variable: &Identifier,
+        iterable: &Expr,
+        body: &Stmt,
+        span: Span,
+    ) -> Exec<Flow> {
+        let iterable_span = iterable.span;
+        let iterable = self.evaluate(iterable)?;
+        let iter = self.get_property(iterable, ITER_METHOD, iterable_span)?;
+        let iterator = self.call(iter, Vec::new(), iterable_span)?;
+        loop {
+            let has_next = self.get_property(iterator.clone(), HAS_NEXT_METHOD, span)?;
+            if !self.call(has_next, Vec::new(), span)?.is_truthy() {
+                break;
+            }
+            let next = self.get_property(iterator.clone(), NEXT_METHOD, variable.span)?;
+            let item = self.call(next, Vec::new(), variable.span)?;
+            // Every time around the loop has a variable of its own.
+            let scope = self.heap.alloc(Object::Scope(Scope {
+                vars: HashMap::new(),
+                parent: self.scope,
+            }));
+            let flow = self.in_scope(scope, |interpreter| {
+                interpreter.declare(&variable.name, item);
+                interpreter.execute(body)
+            })?;
+            match flow {
+                Flow::Break(target) if targets(label, &target) => break,
+                Flow::Continue(target) if targets(label, &target) => {}
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // The default arm is only tried once every other arm has been.
+    fn match_statement(&mut self, subject: &Expr, arms: &[MatchArm]) -> Exec<Flow> {
+        let subject = self.evaluate(subject)?;
+        for arm in arms.iter().filter(|arm| !arm.is_default()) {
+            for value in &arm.values {
+                let value = self.evaluate(value)?;
+                if ops::equal(&self.heap, &value, &subject) {
+                    return self.block(&arm.body);
+                }
+            }
+        }
+        match arms.iter().find(|arm| arm.is_default()) {
+            Some(arm) => self.block(&arm.body),
+            None => Ok(Flow::Normal),
+        }
+    }
+
+    fn function_declaration(&mut self, decl: &FunctionDecl) -> Exec<()> {
+        let function = self.declared_function(decl, decl.name.name.clone(), false)?;
+        self.declare(&decl.name.name, function);
+        Ok(())
+    }
+
+    // Decorators are worked out first and then called with the function,
+    // the one closest to it first.
+    fn declared_function(
+        &mut self,
+        decl: &FunctionDecl,
+        name: String,
+        initializer: bool,
+    ) -> Exec<Value> {
+        let decorators = self.decorators(&decl.decorators)?;
+        let def = self.defs.entry(decl.id).or_insert_with(|| {
+            Arc::new(FunctionDef {
+                name,
+                params: decl.params.iter().map(|p| p.name.clone()).collect(),
+                defaults: decl.defaults.clone(),
+                rest: decl.rest.as_ref().map(|r| r.name.clone()),
+                body: decl.body.clone(),
+                initializer,
+            })
+        });
+        let def = def.clone();
+        let function = self.closure(def);
+        self.decorate(function, decorators)
+    }
+
+    fn decorators(&mut self, decorators: &[Expr]) -> Exec<Vec<(Value, Span)>> {
+        let mut values = Vec::new();
+        for decorator in decorators {
+            values.push((self.evaluate(decorator)?, decorator.span));
+        }
+        Ok(values)
+    }
+
+    fn decorate(&mut self, mut value: Value, decorators: Vec<(Value, Span)>) -> Exec<Value> {
+        for (decorator, span) in decorators.into_iter().rev() {
+            value = self.call(decorator, vec![value], span)?;
+        }
+        Ok(value)
+    }
+
+    // The superclass is found before the decorators are worked out. The
+    // methods are named after the class, and close over a scope holding the
+    // superclass for `super`.
+    fn class_declaration(&mut self, decl: &ClassDecl) -> Exec<()> {
+        let superclass = match &decl.superclass {
+            Some(name) => match self.lookup(&name.name) {
+                Some(value) => Some((value, name.span)),
+                None => return Err(self.undefined(&name.name, name.span)),
+            },
+            None => None,
+        };
+        let decorators = self.decorators(&decl.decorators)?;
+        let class = self.heap.alloc(Object::Class(Class::new(&decl.name.name)));
+        let outer = self.scope;
+        if let Some((superclass, span)) = superclass {
+            let result = ops::inherit(&mut self.heap, class, &superclass);
+            self.at(result, span)?;
+            let mut vars = HashMap::new();
+            vars.insert(String::from("super"), superclass);
+            self.scope = Some(self.heap.alloc(Object::Scope(Scope {
+                vars,
+                parent: outer,
+            })));
+        }
+        let result = self.members(decl, class);
+        self.scope = outer;
+        result?;
+        let class = self.decorate(Value::Object(class), decorators)?;
+        self.declare(&decl.name.name, class);
+        Ok(())
+    }
+
+    fn members(&mut self, decl: &ClassDecl, class: Handle) -> Exec<()> {
+        let kinds = [
+            decl.initializer
+                .iter()
+                .chain(&decl.methods)
+                .collect::<Vec<_>>(),
+            decl.getters.iter().collect(),
+            decl.setters.iter().collect(),
+        ];
+        for (kind, members) in kinds.iter().enumerate() {
+            for member in members {
+                let name = format!("{}.{}", decl.name.name, member.name.name);
+                let initializer = member.name.name == INITIALIZER_NAME && kind == 0;
+                let value = self.declared_function(member, name, initializer)?;
+                let symbol = self.heap.intern(&member.name.name);
+                if let Object::Class(class) = self.heap.get_mut(class) {
+                    let members = match kind {
+                        0 => &mut class.methods,
+                        1 => &mut class.getters,
+                        _ => &mut class.setters,
+                    };
+                    members.insert(symbol, value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn closure(&mut self, def: Arc<FunctionDef>) -> Value {
+        let closure = Closure {
+            def,
+            scope: self.scope,
+        };
+        Value::Object(self.heap.alloc(Object::Interpreted(closure)))
+    }
+
+    // Stores a value into the names or targets of a pattern, declaring the
+    // names when it is a declaration.
+    fn bind(&mut self, pattern: &Pattern, value: Value, declare: bool) -> Exec<()> {
+        match &pattern.kind {
+            PatternKind::Name(name) if declare => self.declare(&name.name, value),
+            PatternKind::Name(name) => self.assign(&name.name, value, name.span)?,
+            PatternKind::Target(target) => self.store(target, value)?,
+            PatternKind::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let part = self.get_index(value.clone(), Value::Int(i as i64), item.span)?;
+                    self.bind(item, part, declare)?;
+                }
+            }
+            PatternKind::Map(entries) => {
+                for entry in entries {
+                    let key = self.map_key(&entry.key)?;
+                    let part = self.get_index(value.clone(), key, entry.span)?;
+                    self.bind(&entry.pattern, part, declare)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn map_key(&mut self, key: &MapKey) -> Exec<Value> {
+        match key {
+            MapKey::Identifier(name) => Ok(Value::from(&name.name[..])),
+            MapKey::String(s) => Ok(Value::from(&s[..])),
+            MapKey::Computed(expr) => self.evaluate(expr),
+        }
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Exec<Value> {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Int(n) => Ok(Value::Int(*n)),
+            ExprKind::Float(n) => Ok(Value::Float(*n)),
+            ExprKind::String(s) => Ok(Value::from(&s[..])),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Null => Ok(Value::Null),
+            ExprKind::This => self.this(span),
+            ExprKind::Super(method) => {
+                let this = self.this(span)?;
+                let superclass = self.lookup("super").unwrap_or_default();
+                let name = self.heap.intern(&method.name);
+                let result = ops::get_super(&mut self.heap, &this, &superclass, &name);
+                self.at(result, span)
+            }
+            ExprKind::Variable(name) => match self.lookup(name) {
+                Some(value) => Ok(value),
+                None => Err(self.undefined(name, span)),
+            },
+            ExprKind::Unary { op, operand } => {
+                let operand = self.evaluate(operand)?;
+                self.unary(*op, operand, span)
+            }
+            ExprKind::Binary { op, left, right } => self.binary_expression(*op, left, right, span),
+            ExprKind::Assign {
+                op: None,
+                target,
+                value,
+            } => self.assignment(target, value),
+            ExprKind::Assign {
+                op: Some(op),
+                target,
+                value,
+            } => self.compound_assignment(*op, target, value, span),
+            ExprKind::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.evaluate(then_branch)
+                } else {
+                    self.evaluate(else_branch)
+                }
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => self.range(start, end, *inclusive, span),
+            ExprKind::Call(call) => self.call_expression(call, span),
+            ExprKind::Member { object, name } => {
+                // Errors are reported at the name, the same as the compiler
+                // reports them.
+                let object = self.evaluate(object)?;
+                self.get_property(object, &name.name, name.span)
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                self.get_index(object, index, span)
+            }
+            ExprKind::Slice { object, start, end } => {
+                self.slice_expression(object, start, end, span)
+            }
+            ExprKind::Destructure { pattern, value } => {
+                let value = self.evaluate(value)?;
+                self.bind(pattern, value.clone(), false)?;
+                Ok(value)
+            }
+            ExprKind::InterpolatedString(parts) => self.interpolation(parts),
+            ExprKind::ListLiteral(items) => self.list_literal(items),
+            ExprKind::MapLiteral(entries) => self.map_literal(entries),
+            ExprKind::Lambda(lambda) => Ok(self.lambda(lambda, expr.id)),
+            ExprKind::Yield(_) => Err(self.unsupported("coroutines", expr.span)),
+        }
+    }
+
+    // `and` and `or` give back whichever side decided the result.
+    fn binary_expression(
+        &mut self,
+        op: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+        span: Span,
+    ) -> Exec<Value> {
+        let left = self.evaluate(left)?;
+        match op {
+            BinaryOp::And if left.is_truthy() => self.evaluate(right),
+            BinaryOp::Or if !left.is_truthy() => self.evaluate(right),
+            BinaryOp::And | BinaryOp::Or => Ok(left),
+            _ => {
+                let right = self.evaluate(right)?;
+                self.binary(op, left, right, span)
+            }
+        }
+    }
+
+    fn range(&mut self, start: &Expr, end: &Expr, inclusive: bool, span: Span) -> Exec<Value> {
+        let start = self.evaluate(start)?;
+        let end = self.evaluate(end)?;
+        let result = ops::range(&mut self.heap, &start, &end, inclusive);
+        self.at(result, span)
+    }
+
+    fn call_expression(&mut self, call: &Call, span: Span) -> Exec<Value> {
+        if !call.names.is_empty() {
+            return Err(self.unsupported("named arguments", call.names[0].span));
+        }
+        let callee = self.evaluate(&call.callee)?;
+        let mut args = Vec::new();
+        for arg in &call.args {
+            args.push(self.evaluate(arg)?);
+        }
+        self.call(callee, args, span)
+    }
+
+    fn slice_expression(
+        &mut self,
+        object: &Expr,
+        start: &Option<Box<Expr>>,
+        end: &Option<Box<Expr>>,
+        span: Span,
+    ) -> Exec<Value> {
+        let object = self.evaluate(object)?;
+        let mut bounds = [Value::Null, Value::Null];
+        for (bound, expr) in bounds.iter_mut().zip([start, end].iter()) {
+            if let Some(expr) = expr {
+                *bound = self.evaluate(expr)?;
+            }
+        }
+        let [start, end] = bounds;
+        self.slice(object, start, end, span)
+    }
+
+    fn interpolation(&mut self, parts: &[StringPart]) -> Exec<Value> {
+        let mut text = String::new();
+        for part in parts {
+            match part {
+                StringPart::Literal(literal) => text.push_str(literal),
+                StringPart::Expr(expr) => {
+                    let value = self.evaluate(expr)?;
+                    text.push_str(&value.display(&self.heap).to_string());
+                }
+            }
+        }
+        Ok(Value::from(text))
+    }
+
+    fn list_literal(&mut self, items: &[Expr]) -> Exec<Value> {
+        let mut values = Vec::new();
+        for item in items {
+            values.push(self.evaluate(item)?);
+        }
+        Ok(Value::Object(self.heap.alloc(Object::List(values))))
+    }
+
+    fn map_literal(&mut self, entries: &[MapEntry]) -> Exec<Value> {
+        let mut map = Map::new();
+        for entry in entries {
+            let key = self.map_key(&entry.key)?;
+            let value = self.evaluate(&entry.value)?;
+            map.insert(self.at(ops::key(&key), entry.span)?, value);
+        }
+        Ok(Value::Object(self.heap.alloc(Object::Map(map))))
+    }
+
+    fn lambda(&mut self, lambda: &Lambda, id: NodeId) -> Value {
+        let def = self.defs.entry(id).or_insert_with(|| {
+            Arc::new(FunctionDef {
+                name: String::from("lambda"),
+                params: lambda.params.iter().map(|p| p.name.clone()).collect(),
+                defaults: lambda.defaults.clone(),
+                rest: lambda.rest.as_ref().map(|r| r.name.clone()),
+                body: lambda.body.clone(),
+                initializer: false,
+            })
+        });
+        let def = def.clone();
+        self.closure(def)
+    }
+
+    // The object and index of the target are worked out before the value,
+    // in the order they are written.
+    fn assignment(&mut self, target: &Expr, value: &Expr) -> Exec<Value> {
+        match &target.kind {
+            ExprKind::Variable(name) => {
+                let value = self.evaluate(value)?;
+                self.assign(name, value.clone(), target.span)?;
+                Ok(value)
+            }
+            ExprKind::Member { object, name } => {
+                let object = self.evaluate(object)?;
+                let value = self.evaluate(value)?;
+                self.set_property(object, &name.name, value.clone(), target.span)?;
+                Ok(value)
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                self.set_index(object, index, value.clone(), target.span)?;
+                Ok(value)
+            }
+            _ => Err(self.error("invalid assignment target", target.span)),
+        }
+    }
+
+    // The parts of the target are only worked out once.
+    fn compound_assignment(
+        &mut self,
+        op: BinaryOp,
+        target: &Expr,
+        value: &Expr,
+        span: Span,
+    ) -> Exec<Value> {
+        let result = match &target.kind {
+            ExprKind::Variable(name) => {
+                let current = match self.lookup(name) {
+                    Some(current) => current,
+                    None => return Err(self.undefined(name, target.span)),
+                };
+                let value = self.evaluate(value)?;
+                let result = self.binary(op, current, value, span)?;
+                self.assign(name, result.clone(), target.span)?;
+                result
+            }
+            ExprKind::Member { object, name } => {
+                let object = self.evaluate(object)?;
+                let current = self.get_property(object.clone(), &name.name, name.span)?;
+                let value = self.evaluate(value)?;
+                let result = self.binary(op, current, value, span)?;
+                self.set_property(object, &name.name, result.clone(), target.span)?;
+                result
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let current = self.get_index(object.clone(), index.clone(), target.span)?;
+                let value = self.evaluate(value)?;
+                let result = self.binary(op, current, value, span)?;
+                self.set_index(object, index, result.clone(), target.span)?;
+                result
+            }
+            _ => return Err(self.error("invalid assignment target", target.span)),
+        };
+        Ok(result)
+    }
+
+    fn store(&mut self, target: &Expr, value: Value) -> Exec<()> {
+        match &target.kind {
+            ExprKind::Variable(name) => self.assign(name, value, target.span),
+            ExprKind::Member { object, name } => {
+                let object = self.evaluate(object)?;
+                self.set_property(object, &name.name, value, target.span)
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                self.set_index(object, index, value, target.span)
+            }
+            _ => Err(self.error("invalid assignment target", target.span)),
+        }
+    }
+
+    pub fn call(
+        &mut self,
+        callee: Value,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let handle = match &callee {
+            Value::Object(handle) => *handle,
+            _ => {
+                let msg = format!("cannot call {}", callee.type_name(&self.heap));
+                return Err(self.error(&msg[..], span));
+            }
+        };
+        let (closure, receiver) = match self.heap.get(handle) {
+            Object::Interpreted(closure) => (closure.clone(), None),
+            Object::BoundMethod(bound) => {
+                let receiver = bound.receiver.clone();
+                let method = bound.method.as_object().expect("only functions are bound");
+                match self.heap.get(method) {
+                    Object::Interpreted(closure) => (closure.clone(), Some(receiver)),
+                    Object::Native(native) => {
+                        return self.call_native(*native, Some(receiver), args, span);
+                    }
+                    _ => unreachable!("only functions are bound"),
+                }
+            }
+            Object::Class(_) => return self.instantiate(handle, args, span),
+            Object::Native(native) => return self.call_native(*native, None, args, span),
+            Object::Coroutine(_) => return Err(self.unsupported("coroutines", span)),
+            object => {
+                let msg = format!("cannot call {}", object.type_name());
+                return Err(self.error(&msg[..], span));
+            }
+        };
+        let signature = closure.def.signature();
+        if !signature.accepts(args.len()) {
+            let msg = signature.mismatch(&closure.def.name, args.len());
+            return Err(self.error(&msg[..], span));
+        }
+        if self.calls.len() >= MAX_CALL_DEPTH {
+            return Err(self.error("stack overflow", span));
+        }
+
+        let mut parent = closure.scope;
+        if let Some(receiver) = &receiver {
+            let mut vars = HashMap::new();
+            vars.insert(String::from("this"), receiver.clone());
+            parent = Some(self.heap.alloc(Object::Scope(Scope { vars, parent })));
+        }
+        let scope = self.heap.alloc(Object::Scope(Scope {
+            vars: HashMap::new(),
+            parent,
+        }));
+        self.calls.push((closure.def.name.clone(), span));
+        let result = self.in_scope(scope, |interpreter| {
+            interpreter.run_function(&closure.def, args)
+        });
+        self.calls.pop();
+        match receiver {
+            Some(receiver) if closure.def.initializer => result.map(|_| receiver),
+            _ => result,
+        }
+    }
+
+    // A method of a builtin value gets the value before the arguments.
+    fn call_native(
+        &mut self,
+        native: Native,
+        receiver: Option<Value>,
+        mut args: Vec<Value>,
+        span: Span,
+    ) -> Exec<Value> {
+        native
+            .check_arity(args.len())
+            .map_err(|msg| self.error(&msg[..], span))?;
+        if let Some(receiver) = receiver {
+            args.insert(0, receiver);
+        }
+        let mut context = Context {
+            heap: &mut self.heap,
+            out: &mut *self.out,
+        };
+        (native.function)(&mut context, &args).map_err(|msg| self.error(&msg[..], span))
+    }
+
+    // Calling a class makes an instance and hands the arguments to its
+    // initializer, if it has one.
+    fn instantiate(&mut self, class: Handle, args: Vec<Value>, span: Span) -> Exec<Value> {
+        let instance = Value::Object(self.heap.alloc(Object::Instance(Instance {
+            class,
+            fields: Map::new(),
+        })));
+        match ops::find_method(&self.heap, class, INITIALIZER_NAME) {
+            Some(initializer) => {
+                let initializer = ops::bind(&mut self.heap, &instance, initializer);
+                self.call(initializer, args, span)
+            }
+            None if args.is_empty() => Ok(instance),
+            None => {
+                let name = match self.heap.get(class) {
+                    Object::Class(class) => class.name.clone(),
+                    _ => unreachable!("only classes make instances"),
+                };
+                let signature = Signature {
+                    arity: 0,
+                    min_arity: 0,
+                    rest: false,
+                };
+                Err(self.error(&signature.mismatch(&name, args.len())[..], span))
+            }
+        }
+    }
+
+    // A missing argument, or a null one, takes the default, which can use
+    // the parameters before it.
+    fn run_function(&mut self, def: &FunctionDef, args: Vec<Value>) -> Exec<Value> {
+        let mut args = args.into_iter();
+        let min_arity = def.params.len() - def.defaults.len();
+        for (i, param) in def.params.iter().enumerate() {
+            let mut value = args.next().unwrap_or_default();
+            if i >= min_arity && value.is_null() {
+                value = self.evaluate(&def.defaults[i - min_arity])?;
+            }
+            self.declare(param, value);
+        }
+        if let Some(rest) = &def.rest {
+            let rest_list = Value::Object(self.heap.alloc(Object::List(args.collect())));
+            self.declare(rest, rest_list);
+        }
+        for stmt in &def.body {
+            if let Flow::Return(value) = self.execute(stmt)? {
+                return Ok(value);
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    // Puts the error of an operation on values at the span.
+    fn at<T>(&self, result: Result<T, String>, span: Span) -> Exec<T> {
+        result.map_err(|msg| self.error(&msg[..], span))
+    }
+
+    fn unary(&mut self, op: UnaryOp, operand: Value, span: Span) -> Exec<Value> {
+        let result = ops::unary(&mut self.heap, op, &operand);
+        match self.at(result, span)? {
+            Access::Done(value) => Ok(value),
+            Access::Call(method) => self.call(method, Vec::new(), span),
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, left: Value, right: Value, span: Span) -> Exec<Value> {
+        let result = ops::binary(&mut self.heap, op, &left, &right);
+        match self.at(result, span)? {
+            Access::Done(value) => Ok(value),
+            Access::Call(method) => {
+                let value = self.call(method, vec![right], span)?;
+                if op == BinaryOp::NotEqual {
+                    Ok(Value::Bool(!value.is_truthy()))
+                } else {
+                    Ok(value)
+                }
+            }
+        }
+    }
+
+    // Methods see the instance they were called on in a scope of its own.
+    fn this(&self, span: Span) -> Exec<Value> {
+        match self.lookup("this") {
+            Some(this) => Ok(this),
+            None => Err(self.undefined("this", span)),
+        }
+    }
+
+    fn get_property(&mut self, object: Value, name: &str, span: Span) -> Exec<Value> {
+        let name = self.heap.intern(name);
+        let result = ops::get_property(&mut self.heap, &object, &name);
+        match self.at(result, span)? {
+            Access::Done(value) => Ok(value),
+            Access::Call(getter) => self.call(getter, Vec::new(), span),
+        }
+    }
+
+    fn set_property(&mut self, object: Value, name: &str, value: Value, span: Span) -> Exec<()> {
+        let name = self.heap.intern(name);
+        let result = ops::set_property(&mut self.heap, &object, &name, value.clone());
+        if let Access::Call(setter) = self.at(result, span)? {
+            self.call(setter, vec![value], span)?;
+        }
+        Ok(())
+    }
+
+    fn get_index(&mut self, object: Value, index: Value, span: Span) -> Exec<Value> {
+        let result = ops::get_index(&mut self.heap, &object, &index);
+        match self.at(result, span)? {
+            Access::Done(value) => Ok(value),
+            Access::Call(method) => self.call(method, vec![index], span),
+        }
+    }
+
+    fn set_index(&mut self, object: Value, index: Value, value: Value, span: Span) -> Exec<()> {
+        let result = ops::set_index(&mut self.heap, &object, &index, value.clone());
+        if let Access::Call(method) = self.at(result, span)? {
+            self.call(method, vec![index, value], span)?;
+        }
+        Ok(())
+    }
+
+    fn slice(&mut self, object: Value, start: Value, end: Value, span: Span) -> Exec<Value> {
+        let result = ops::slice(&mut self.heap, &object, &start, &end);
+        self.at(result, span)
+    }
+}
+
+// Whether a break or continue for the label applies to the loop, which it
+// does when it names the loop or doesn't name any.
+fn targets(label: &Option<Identifier>, target: &Option<String>) -> bool {
+    match target {
+        None => true,
+        Some(target) => label.as_ref().is_some_and(|label| label.name == *target),
+    }
+}
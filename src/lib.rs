@@ -1,2 +1,17 @@
+pub mod ast;
+pub mod builtins;
+pub mod bytecode;
+pub mod compile;
+pub mod desugar;
 pub mod error;
+pub mod fold;
+pub mod interp;
+pub mod link;
+pub mod parse;
+pub mod project;
+pub mod prune;
+pub mod resolve;
 pub mod scan;
+pub mod typeck;
+pub mod value;
+pub mod vm;
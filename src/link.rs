@@ -0,0 +1,238 @@
+use crate::ast::visit::*;
+use crate::ast::*;
+use crate::bytecode::Function;
+use crate::compile::*;
+use crate::error::Error;
+use crate::project::module_path;
+use crate::resolve::resolve_program;
+use std::collections::{HashMap, HashSet};
+
+// Every module is compiled to a function of its own, which runs the module
+// and gives back a map of its exports. An import loads the module with the
+// path in its operand, which the host can do when the importing module is
+// loaded, or the modules can be linked ahead of time into a bundle that
+// holds everything a script needs in one file.
+
+// Modules that the host provides rather than a file, which are imported by
+// name and never linked.
+pub const HOST_MODULES: &[&str] = &["fs"];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Module {
+    // The path of the module, which is what other modules import it by.
+    pub path: String,
+    pub function: Function,
+    pub imports: Vec<Import>,
+    // The globals that importers can read, in the order they are declared.
+    pub exports: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Import {
+    pub path: String,
+    pub alias: String,
+    pub span: Span,
+    // The names read from the module through its alias and where, which
+    // linking checks against what the module exports.
+    pub uses: Vec<(String, Span)>,
+}
+
+// Modules are always compiled for the stack machine, since that is what
+// loads them.
+pub fn compile_module(program: &Program, options: &CompilerOptions) -> Result<Module, Vec<Error>> {
+    let options = CompilerOptions {
+        backend: Backend::Stack,
+        ..*options
+    };
+    let function = match compile_with_options(program, &options)? {
+        Compiled::Stack(function) => function,
+        Compiled::Register(_) => unreachable!("modules are compiled for the stack machine"),
+    };
+
+    let mut imports: Vec<Import> = program
+        .body
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Import(decl) => Some(decl),
+            _ => None,
+        })
+        .filter(|decl| match &decl.source {
+            ImportSource::Module(name) => !HOST_MODULES.contains(&&name.name[..]),
+            ImportSource::File(_) => true,
+        })
+        .map(|decl| Import {
+            path: module_path(&program.name, &decl.source),
+            alias: decl.alias.name.clone(),
+            span: decl.span,
+            uses: Vec::new(),
+        })
+        .collect();
+
+    // An alias that is assigned to could hold anything, and a local with
+    // the same name hides it.
+    let mut assigned = Assigned::default();
+    assigned.visit_program(program);
+    let mut uses = Uses {
+        aliases: imports
+            .iter()
+            .enumerate()
+            .filter(|(_, import)| !assigned.names.contains(&import.alias))
+            .map(|(i, import)| (import.alias.clone(), i))
+            .collect(),
+        bindings: resolve_program(program).bindings.into_keys().collect(),
+        uses: Vec::new(),
+    };
+    uses.visit_program(program);
+    for (i, name, span) in uses.uses {
+        imports[i].uses.push((name, span));
+    }
+
+    Ok(Module {
+        path: program.name.clone(),
+        function,
+        imports,
+        exports: program
+            .exports()
+            .into_iter()
+            .map(|name| name.name.clone())
+            .collect(),
+    })
+}
+
+struct Uses {
+    // The import every alias belongs to.
+    aliases: HashMap<String, usize>,
+    // The variables that refer to locals.
+    bindings: HashSet<NodeId>,
+    uses: Vec<(usize, String, Span)>,
+}
+
+impl Visitor for Uses {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprKind::Member { object, name } = &expr.kind {
+            if let ExprKind::Variable(alias) = &object.kind {
+                if !self.bindings.contains(&object.id) {
+                    if let Some(&i) = self.aliases.get(alias) {
+                        self.uses.push((i, name.name.clone(), name.span));
+                    }
+                }
+            }
+        }
+        walk_expr(self, expr);
+    }
+}
+
+// Modules linked ahead of time, in the order they have to run so that every
+// module comes after the modules it imports. The entry point is last.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Bundle {
+    pub(crate) modules: Vec<Function>,
+}
+
+impl Bundle {
+    pub fn modules(&self) -> &[Function] {
+        &self.modules[..]
+    }
+
+    pub fn module(&self, path: &str) -> Option<&Function> {
+        self.modules.iter().find(|m| m.name == path)
+    }
+
+    pub fn entry(&self) -> &Function {
+        self.modules
+            .last()
+            .expect("a bundle always has an entry point")
+    }
+}
+
+// Links the modules that the entry point needs into a bundle. Every import
+// has to be one of the modules, every name read from a module has to be
+// one it exports, and modules can't import each other in a cycle since one
+// of them would have to run before the other.
+pub fn link(modules: Vec<Module>, entry: &str) -> Result<Bundle, Vec<Error>> {
+    let by_path: HashMap<&str, &Module> = modules.iter().map(|m| (&m.path[..], m)).collect();
+    if !by_path.contains_key(entry) {
+        let msg = format!("cannot find module {}", entry);
+        return Err(vec![Error::new(&msg[..], entry, 0, 0)]);
+    }
+
+    let mut linker = Linker {
+        modules: &by_path,
+        order: Vec::new(),
+        done: HashSet::new(),
+        running: Vec::new(),
+        errors: Vec::new(),
+    };
+    linker.visit(entry);
+    if !linker.errors.is_empty() {
+        return Err(linker.errors);
+    }
+    let order: HashMap<String, usize> = linker
+        .order
+        .iter()
+        .enumerate()
+        .map(|(i, path)| (path.clone(), i))
+        .collect();
+    let mut linked: Vec<Function> = modules
+        .into_iter()
+        .filter(|m| order.contains_key(&m.path))
+        .map(|m| m.function)
+        .collect();
+    linked.sort_by_key(|f| order[&f.name]);
+    Ok(Bundle { modules: linked })
+}
+
+struct Linker<'a> {
+    modules: &'a HashMap<&'a str, &'a Module>,
+    // The modules in the order they have to run.
+    order: Vec<String>,
+    done: HashSet<String>,
+    // The chain of imports being followed, for finding cycles.
+    running: Vec<String>,
+    errors: Vec<Error>,
+}
+
+impl Linker<'_> {
+    fn visit(&mut self, path: &str) {
+        let module = self.modules[path];
+        self.running.push(String::from(path));
+        for import in &module.imports {
+            let error =
+                |msg: String| Error::new(&msg[..], path, import.span.line, import.span.column);
+            let imported = match self.modules.get(&import.path[..]) {
+                Some(imported) => imported,
+                None => {
+                    self.errors
+                        .push(error(format!("cannot find module {}", import.path)));
+                    continue;
+                }
+            };
+            if let Some(msg) = import_cycle(&self.running, &import.path) {
+                self.errors.push(error(msg));
+                continue;
+            }
+            for (name, span) in &import.uses {
+                if !imported.exports.contains(name) {
+                    let msg = format!("{} does not export {}", import.alias, name);
+                    self.errors
+                        .push(Error::new(&msg[..], path, span.line, span.column));
+                }
+            }
+            if !self.done.contains(&import.path) {
+                self.visit(&import.path);
+            }
+        }
+        self.running.pop();
+        self.done.insert(String::from(path));
+        self.order.push(String::from(path));
+    }
+}
+
+// The error for importing a module that is already in the chain of imports
+// being followed, which would have to run before itself.
+pub(crate) fn import_cycle(running: &[String], path: &str) -> Option<String> {
+    let start = running.iter().position(|p| p == path)?;
+    let mut cycle = running[start..].to_vec();
+    cycle.push(String::from(path));
+    Some(format!("import cycle: {}", cycle.join(" -> ")))
+}
@@ -0,0 +1,201 @@
+use atom::bytecode::serialize::BUNDLE_EXTENSION;
+use atom::compile::CompilerOptions;
+use atom::error::Error;
+use atom::link::Bundle;
+use atom::project::*;
+use atom::resolve::*;
+use atom::typeck;
+use atom::vm::Vm;
+
+const USAGE: &str = "usage: atom check [--warn-shadowing] [--check-types | --strict-types] [--manifest <file>] [<file>...]
+       atom build <file> [-o <file>]
+       atom run <file | bundle>";
+
+// Prints a diagnostic with a note for every place related to it.
+fn report(diagnostic: &Error) {
+    println!("{}: {}", diagnostic.severity().label(), diagnostic);
+    for related in diagnostic.related() {
+        println!(
+            "note: {}:{}:{}: {}",
+            related.file_name(),
+            related.line(),
+            related.column(),
+            related.message()
+        );
+    }
+}
+
+fn check(args: &[String]) -> i32 {
+    let mut project = Project::new();
+    let mut lints = Lints::default();
+    let mut type_check = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--warn-shadowing" {
+            lints.shadowing = true;
+        } else if arg == "--check-types" {
+            type_check = Some(typeck::Mode::Lenient);
+        } else if arg == "--strict-types" {
+            type_check = Some(typeck::Mode::Strict);
+        } else if arg == "--manifest" {
+            let path = match args.next() {
+                Some(path) => path,
+                None => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            };
+            match Project::from_manifest(&path[..]) {
+                Ok(manifest) => {
+                    for entry in manifest.entries() {
+                        project.add_entry(&entry[..]);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return 2;
+                }
+            }
+        } else {
+            project.add_entry(&arg[..]);
+        }
+    }
+
+    if project.entries().is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+    project.set_lints(lints);
+    project.set_type_check(type_check);
+
+    let summary = project.check_all();
+    for diagnostic in summary.diagnostics() {
+        report(diagnostic);
+    }
+    println!(
+        "checked {} file{}, {} error{}, {} warning{}",
+        summary.files().len(),
+        if summary.files().len() == 1 { "" } else { "s" },
+        summary.error_count(),
+        if summary.error_count() == 1 { "" } else { "s" },
+        summary.warning_count(),
+        if summary.warning_count() == 1 {
+            ""
+        } else {
+            "s"
+        },
+    );
+
+    if summary.is_ok() {
+        0
+    } else {
+        1
+    }
+}
+
+// Links a script and everything it imports into one bundle, written next to
+// the script unless an output file is given.
+fn build(args: &[String]) -> i32 {
+    let (entry, output) = match args {
+        [entry] => (entry, None),
+        [entry, flag, output] if flag == "-o" => (entry, Some(output.clone())),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&entry[..])
+            .with_extension(BUNDLE_EXTENSION)
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let project = Project::new();
+    match project.build(&entry[..], &CompilerOptions::default()) {
+        Ok(bundle) => match std::fs::write(&output, bundle.serialize()) {
+            Ok(()) => {
+                println!(
+                    "built {} module{} into {}",
+                    bundle.modules().len(),
+                    if bundle.modules().len() == 1 { "" } else { "s" },
+                    output
+                );
+                0
+            }
+            Err(e) => {
+                eprintln!("unable to write {}: {}", output, e);
+                1
+            }
+        },
+        Err(errors) => {
+            for error in &errors {
+                report(error);
+            }
+            1
+        }
+    }
+}
+
+// Runs a script, or a bundle that was built, in the virtual machine. A
+// script is built first, so that everything it imports is there and checked
+// before anything runs.
+fn run(args: &[String]) -> i32 {
+    let path = match args {
+        [path] => path,
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let is_bundle = std::path::Path::new(&path[..])
+        .extension()
+        .is_some_and(|extension| extension == BUNDLE_EXTENSION);
+    let bundle = if is_bundle {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("unable to read {}: {}", path, e);
+                return 1;
+            }
+        };
+        Bundle::deserialize(&path[..], &bytes[..]).map_err(|error| vec![error])
+    } else {
+        Project::new().build(&path[..], &CompilerOptions::default())
+    };
+    let bundle = match bundle {
+        Ok(bundle) => bundle,
+        Err(errors) => {
+            for error in &errors {
+                report(error);
+            }
+            return 1;
+        }
+    };
+
+    let mut vm = Vm::new();
+    let entry = bundle.entry().clone();
+    vm.set_module_loader(Some(Box::new(bundle)));
+    match vm.run(&entry) {
+        Ok(_) => 0,
+        Err(error) => {
+            report(&error.diagnostic());
+            1
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = match args.first().map(|a| &a[..]) {
+        Some("check") => check(&args[1..]),
+        Some("build") => build(&args[1..]),
+        Some("run") => run(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    };
+
+    std::process::exit(code);
+}
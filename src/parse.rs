@@ -0,0 +1,2248 @@
+pub mod incremental;
+pub mod precedence;
+
+use crate::ast::visit::*;
+use crate::ast::*;
+use crate::error::*;
+use crate::scan::*;
+use precedence::*;
+
+// Name reported by __function when it is used outside of any function.
+const SCRIPT_FUNCTION_NAME: &str = "<script>";
+// Name reported by __function inside of an anonymous function.
+const LAMBDA_FUNCTION_NAME: &str = "<lambda>";
+// The method that is called to set up a new instance of a class.
+pub const INITIALIZER_NAME: &str = "init";
+// How deeply statements and expressions can be nested before the parser
+// gives up. This is far deeper than any real script needs while still
+// leaving plenty of room on the stack of the host.
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+// How deep operator and call chains can make the tree before the parser
+// gives up. Folding a chain doesn't make the parser call itself, but the
+// passes after it walk the tree by recursing, and this many levels still
+// fit on the stack of a thread of the default size.
+pub const DEFAULT_MAX_CHAIN_DEPTH: usize = 250;
+
+type ParseResult<T> = Result<T, Error>;
+
+// A parameter list before it is split up between a function declaration and
+// a lambda, which both store it the same way.
+struct Parameters {
+    names: Vec<Identifier>,
+    types: Vec<Option<TypeAnnotation>>,
+    defaults: Vec<Expr>,
+    rest: Option<Identifier>,
+    return_type: Option<TypeAnnotation>,
+}
+
+// Everything in the body of a class, sorted by what kind of method it is.
+#[derive(Default)]
+struct ClassMembers {
+    initializer: Option<FunctionDecl>,
+    methods: Vec<FunctionDecl>,
+    getters: Vec<FunctionDecl>,
+    setters: Vec<FunctionDecl>,
+}
+
+struct LineIndex<'a> {
+    source: &'a str,
+    // Byte offsets where each line starts and ends, not counting the new
+    // line character, and if the line is plain ASCII.
+    lines: Vec<(usize, usize, bool)>,
+    origin: Span,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(source: &'a str, origin: Span) -> Self {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for text in source.split('\n') {
+            lines.push((start, start + text.len(), text.is_ascii()));
+            start += text.len() + 1;
+        }
+
+        Self {
+            source,
+            lines,
+            origin,
+        }
+    }
+
+    fn offset(&self, line: u32, column: u32) -> usize {
+        // The source might be a piece of a larger file which starts at the
+        // origin, so positions are made relative to the piece first.
+        let (line, column) = if line <= self.origin.line {
+            (1, column.saturating_sub(self.origin.column - 1))
+        } else {
+            (line - self.origin.line + 1, column)
+        };
+
+        let line = (line as usize - 1).min(self.lines.len() - 1);
+        let (start, end, ascii) = self.lines[line];
+        let column = column.max(1) as usize - 1;
+        // Lines and columns coming out of the scanner are one based and count
+        // characters, not bytes. Most lines are ASCII where the two are the
+        // same, otherwise we need to walk the line to find the offset. Columns
+        // past the end of the line refer to the new line character (or the
+        // end of the file) that follows it.
+        self.origin.start
+            + if ascii {
+                (start + column).min(end)
+            } else {
+                match self.source[start..end].char_indices().nth(column) {
+                    Some((i, _)) => start + i,
+                    None => end,
+                }
+            }
+    }
+}
+
+pub struct Parser {
+    src_name: String,
+    source: String,
+    origin: Span,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    eof: Span,
+    current: usize,
+    errors: Vec<Error>,
+    functions: Vec<String>,
+    // One entry for every class we are inside of, recording if the class has
+    // a superclass. This is used to validate this and super expressions.
+    classes: Vec<bool>,
+    // One entry for every loop we are inside of in the current function,
+    // with its label if it has one. Used to validate break and continue.
+    loops: Vec<Option<String>>,
+    // The parser calls itself for every level of nesting, so input like ten
+    // thousand '(' characters would overflow the stack without a limit.
+    depth: usize,
+    max_depth: usize,
+    // How many links of operator and call chains the expression being
+    // parsed is inside of.
+    chain_depth: usize,
+    max_chain_depth: usize,
+    // Strict mode requires a semicolon after every statement and does not
+    // allow a trailing comma at the end of a list.
+    strict: bool,
+    // The first id given out when the finished tree is numbered.
+    next_id: NodeId,
+}
+
+impl Parser {
+    pub fn new(name: &str, source: &str) -> Self {
+        Parser::new_at(name, source, Span::new(0, source.len(), 1, 1))
+    }
+
+    fn new_at(name: &str, source: &str, origin: Span) -> Self {
+        // The origin says where the source starts in the file it came from.
+        // Normally that is the very beginning, but pieces of a file, like the
+        // expressions inside of a formatted string, are parsed on their own.
+        let lines = LineIndex::new(source, origin);
+        let mut scanner = Scanner::new(name, source);
+        scanner.start_at(origin.line, origin.column - 1);
+        let mut tokens = Vec::new();
+        let mut spans = Vec::new();
+        let mut errors = Vec::new();
+
+        // The whole source is scanned up front. Having every token available
+        // makes looking ahead trivial and lets us report all of the scanner
+        // errors at once instead of stopping at the first one.
+        while let Some(next) = scanner.next() {
+            match next {
+                Ok(tok) => {
+                    let start = lines.offset(tok.source_line(), tok.source_column());
+                    // The scanner is left sitting on the last character of the
+                    // token, so the token ends right after that character.
+                    let end = lines.offset(scanner.current_line(), scanner.current_column() + 1);
+                    spans.push(Span::new(
+                        start,
+                        end.max(start),
+                        tok.source_line(),
+                        tok.source_column(),
+                    ));
+                    tokens.push(tok);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let eof = Span::new(
+            origin.start + source.len(),
+            origin.start + source.len(),
+            scanner.current_line(),
+            scanner.current_column(),
+        );
+
+        Self {
+            src_name: String::from(name),
+            source: String::from(source),
+            origin,
+            tokens,
+            spans,
+            eof,
+            current: 0,
+            errors,
+            functions: Vec::new(),
+            classes: Vec::new(),
+            loops: Vec::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            chain_depth: 0,
+            max_chain_depth: DEFAULT_MAX_CHAIN_DEPTH,
+            strict: false,
+            next_id: NodeId(0),
+        }
+    }
+
+    pub fn source_name(&self) -> &str {
+        &self.src_name[..]
+    }
+
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn set_max_chain_depth(&mut self, depth: usize) {
+        self.max_chain_depth = depth;
+    }
+
+    pub fn max_chain_depth(&self) -> usize {
+        self.max_chain_depth
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn parse_program(&mut self) -> Result<Program, Vec<Error>> {
+        let mut body = Vec::new();
+        while !self.is_at_end() {
+            if let Some(stmt) = self.recovering(|p| p.module_declaration()) {
+                body.push(stmt);
+            }
+        }
+
+        if self.errors.is_empty() {
+            let mut numbering = Numbering { next: self.next_id };
+            for stmt in &mut body {
+                numbering.visit_stmt_mut(stmt);
+            }
+            self.next_id = numbering.next;
+            Ok(Program {
+                name: self.src_name.clone(),
+                body,
+                next_id: self.next_id,
+            })
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    // Parses the whole source as a single expression, for hosts like a REPL
+    // where `1 + 2` is typed on its own. A trailing semicolon is allowed but
+    // not needed.
+    pub fn parse_expression(&mut self) -> Result<Expr, Vec<Error>> {
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        let expr = self.expression().and_then(|expr| {
+            self.matches(TokenType::Semicolon);
+            if !self.is_at_end() {
+                let msg = format!("expected end of expression but found {}", self.found());
+                return Err(self.error_at_current(&msg[..]));
+            }
+            Ok(expr)
+        });
+        let mut expr = expr.map_err(|e| vec![e])?;
+        let mut numbering = Numbering { next: self.next_id };
+        numbering.visit_expr_mut(&mut expr);
+        self.next_id = numbering.next;
+        Ok(expr)
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.tokens.len()
+    }
+
+    fn peek_type(&self) -> Option<TokenType> {
+        self.tokens.get(self.current).map(|t| t.token_type())
+    }
+
+    fn peek_type_at(&self, distance: usize) -> Option<TokenType> {
+        self.tokens
+            .get(self.current + distance)
+            .map(|t| t.token_type())
+    }
+
+    fn check(&self, token: TokenType) -> bool {
+        self.peek_type() == Some(token)
+    }
+
+    fn advance(&mut self) -> usize {
+        // Returns the index of the consumed token so the caller can look at
+        // its data and its span.
+        let index = self.current;
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+
+        index
+    }
+
+    fn matches(&mut self, token: TokenType) -> bool {
+        if self.check(token) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: TokenType, what: &str) -> ParseResult<usize> {
+        if self.check(token) {
+            Ok(self.advance())
+        } else {
+            Err(self.error_at_current(&format!("expected {} but found {}", what, self.found())[..]))
+        }
+    }
+
+    fn found(&self) -> String {
+        match self.tokens.get(self.current) {
+            Some(tok) => format!("'{}'", tok.token_data()),
+            None => String::from("eof"),
+        }
+    }
+
+    fn data(&self, index: usize) -> &str {
+        self.tokens[index].token_data()
+    }
+
+    fn span(&self, index: usize) -> Span {
+        self.spans.get(index).copied().unwrap_or(self.eof)
+    }
+
+    fn current_span(&self) -> Span {
+        self.span(self.current)
+    }
+
+    fn previous_span(&self) -> Span {
+        if self.current == 0 {
+            self.current_span()
+        } else {
+            self.span(self.current - 1)
+        }
+    }
+
+    fn after_newline(&self) -> bool {
+        if self.current == 0 || self.is_at_end() {
+            return false;
+        }
+        let from = self.previous_span().end - self.origin.start;
+        let to = self.current_span().start - self.origin.start;
+        self.source
+            .get(from..to)
+            .is_some_and(|between| between.contains('\n'))
+    }
+
+    fn at_statement_end(&self) -> bool {
+        // Outside of strict mode the semicolon can be left off when the
+        // statement is the last thing on its line or in its block.
+        self.check(TokenType::Semicolon)
+            || (!self.strict
+                && (self.is_at_end() || self.check(TokenType::RightBrace) || self.after_newline()))
+    }
+
+    fn end_statement(&mut self) -> ParseResult<()> {
+        if !self.at_statement_end() {
+            self.expect(TokenType::Semicolon, "';'")?;
+        }
+        self.matches(TokenType::Semicolon);
+        Ok(())
+    }
+
+    fn more_items(&mut self, close: TokenType) -> bool {
+        // A comma is needed between the items of a list. Outside of strict
+        // mode one more can come after the last item, which keeps diffs small
+        // when lists are written one item per line.
+        self.matches(TokenType::Comma) && (self.strict || !self.check(close))
+    }
+
+    fn error_at(&self, msg: &str, span: Span) -> Error {
+        Error::new(msg, &self.src_name[..], span.line, span.column)
+    }
+
+    fn error_at_current(&self, msg: &str) -> Error {
+        self.error_at(msg, self.current_span())
+    }
+
+    fn synchronize(&mut self) {
+        // After an error we skip ahead to something that looks like the start
+        // of a new statement so that one mistake does not cascade into dozens
+        // of confusing errors. Braces that are skipped over are tracked so
+        // that we do not stop on the closing brace of a block we never entered.
+        let mut nesting = 0;
+        while let Some(token) = self.peek_type() {
+            match token {
+                TokenType::Semicolon if nesting == 0 => {
+                    self.advance();
+                    return;
+                }
+                TokenType::RightBrace if nesting > 0 => {
+                    nesting -= 1;
+                    self.advance();
+                }
+                TokenType::LeftBrace => {
+                    nesting += 1;
+                    self.advance();
+                }
+                TokenType::RightBrace
+                | TokenType::Var
+                | TokenType::Const
+                | TokenType::Import
+                | TokenType::Export
+                | TokenType::Function
+                | TokenType::Class
+                | TokenType::At
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Do
+                | TokenType::For
+                | TokenType::Match
+                | TokenType::Try
+                | TokenType::Case
+                | TokenType::Default
+                | TokenType::Throw
+                | TokenType::Return => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    fn skip_to_closing_brace(&mut self) {
+        let mut nesting = 0;
+        while let Some(token) = self.peek_type() {
+            match token {
+                TokenType::RightBrace if nesting == 0 => return,
+                TokenType::RightBrace => nesting -= 1,
+                TokenType::LeftBrace => nesting += 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    fn nested<T>(
+        &mut self,
+        what: &str,
+        parse: impl FnOnce(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<T> {
+        // Every place where the parser can call itself without consuming a
+        // closing token first goes through here to keep the depth in check.
+        if self.depth >= self.max_depth {
+            let msg = format!("{} too deeply nested", what);
+            return Err(self.error_at_current(&msg[..]));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Loops that fold every operand or link onto the tree built so far, as
+    // `a + b + c` and `a.b.c` do, make the tree a level deeper each time
+    // around without the parser calling itself. They count against their
+    // own limit, until the caller puts the depth back.
+    fn lengthen_chain(&mut self) -> ParseResult<()> {
+        if self.chain_depth >= self.max_chain_depth {
+            return Err(self.error_at_current("expression too deeply nested"));
+        }
+        self.chain_depth += 1;
+        Ok(())
+    }
+
+    fn declaration(&mut self) -> Option<Stmt> {
+        self.recovering(|p| p.statement())
+    }
+
+    fn recovering(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<Stmt>) -> Option<Stmt> {
+        let start = self.current;
+        match parse(self) {
+            Ok(stmt) => Some(stmt),
+            Err(e) => {
+                self.errors.push(e);
+                // Always make some progress or a token that can never start a
+                // statement (like a stray '}') would be reported forever.
+                if self.current == start {
+                    self.advance();
+                }
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn module_declaration(&mut self) -> ParseResult<Stmt> {
+        match self.peek_type() {
+            Some(TokenType::Import) => self.import_declaration(),
+            Some(TokenType::Export) => self.export_declaration(),
+            _ => self.statement(),
+        }
+    }
+
+    fn import_declaration(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        self.advance();
+        let source = match self.peek_type() {
+            Some(TokenType::StringLiteral) | Some(TokenType::FormattedStringLiteral) => {
+                let index = self.advance();
+                ImportSource::File(String::from(self.data(index)))
+            }
+            _ => ImportSource::Module(self.identifier("module name")?),
+        };
+
+        let alias = if self.matches(TokenType::As) {
+            self.identifier("module alias")?
+        } else {
+            match &source {
+                ImportSource::Module(name) => name.clone(),
+                // Without an alias a file is known by its name without the
+                // extension, as long as that makes a usable name.
+                ImportSource::File(path) => {
+                    let span = self.previous_span();
+                    let stem = std::path::Path::new(&path[..])
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    if !Parser::is_identifier(&stem[..]) {
+                        let msg = format!("cannot name module '{}', add a name with 'as'", path);
+                        return Err(self.error_at(&msg[..], span));
+                    }
+                    Identifier::new(&stem[..], span)
+                }
+            }
+        };
+        self.end_statement()?;
+
+        let span = start.to(self.previous_span());
+        Ok(Stmt::new(
+            StmtKind::Import(ImportDecl {
+                source,
+                alias,
+                span,
+            }),
+            span,
+        ))
+    }
+
+    fn is_identifier(name: &str) -> bool {
+        // The name has to scan as exactly one identifier, which also rules
+        // out keywords.
+        let mut scanner = Scanner::new("", name);
+        match (scanner.next(), scanner.next()) {
+            (Some(Ok(token)), None) => {
+                token.token_type() == TokenType::Identifier && token.token_data() == name
+            }
+            _ => false,
+        }
+    }
+
+    fn export_declaration(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        self.advance();
+        let decl = match self.peek_type() {
+            Some(TokenType::Function) | Some(TokenType::Class) | Some(TokenType::At) => {
+                self.decorated_declaration()?
+            }
+            Some(TokenType::Var) | Some(TokenType::Const) => self.var_declaration()?,
+            _ => {
+                let msg = format!(
+                    "expected declaration after export but found {}",
+                    self.found()
+                );
+                return Err(self.error_at_current(&msg[..]));
+            }
+        };
+        let span = start.to(decl.span);
+
+        Ok(Stmt::new(StmtKind::Export(Box::new(decl)), span))
+    }
+
+    fn statement(&mut self) -> ParseResult<Stmt> {
+        self.nested("statement", |p| p.statement_kind())
+    }
+
+    fn statement_kind(&mut self) -> ParseResult<Stmt> {
+        match self.peek_type() {
+            Some(TokenType::Import) | Some(TokenType::Export) => {
+                let msg = format!("{} must be at the top level of a module", self.found());
+                Err(self.error_at_current(&msg[..]))
+            }
+            Some(TokenType::Var) | Some(TokenType::Const) => self.var_declaration(),
+            // Like JavaScript, a statement starting with the function keyword
+            // is always a declaration. Anonymous functions can only be used
+            // where an expression is expected.
+            Some(TokenType::Function) | Some(TokenType::Class) | Some(TokenType::At) => {
+                self.decorated_declaration()
+            }
+            Some(TokenType::If) => self.if_statement(),
+            Some(TokenType::While) => self.while_statement(None),
+            Some(TokenType::Do) => self.do_while_statement(None),
+            Some(TokenType::For) => self.for_statement(None),
+            Some(TokenType::Match) => self.match_statement(),
+            Some(TokenType::Try) => self.try_statement(),
+            Some(TokenType::Throw) => self.throw_statement(),
+            Some(TokenType::Identifier) if self.peek_type_at(1) == Some(TokenType::Colon) => {
+                self.labeled_statement()
+            }
+            Some(TokenType::Return) => self.return_statement(),
+            Some(TokenType::Break) => self.jump_statement(TokenType::Break),
+            Some(TokenType::Continue) => self.jump_statement(TokenType::Continue),
+            Some(TokenType::LeftBrace) if !self.looks_like_map() => {
+                let start = self.current_span();
+                let body = self.block()?;
+                Ok(Stmt::new(
+                    StmtKind::Block(body),
+                    start.to(self.previous_span()),
+                ))
+            }
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn identifier(&mut self, what: &str) -> ParseResult<Identifier> {
+        let index = self.expect(TokenType::Identifier, what)?;
+        Ok(Identifier::new(self.data(index), self.span(index)))
+    }
+
+    // Handles both `var` and `const`, which only differ in whether the name
+    // can be assigned to later.
+    fn var_declaration(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        let constant = self.check(TokenType::Const);
+        self.advance();
+        if self.check(TokenType::LeftBracket) || self.check(TokenType::LeftBrace) {
+            return self.destructure_declaration(start, constant);
+        }
+
+        let name = self.identifier("variable name")?;
+        let ty = self.optional_type()?;
+        let init = if self.matches(TokenType::Equals) {
+            Some(self.expression()?)
+        } else if constant {
+            // A constant without a value would be null forever.
+            let msg = format!("constant {} needs a value", name.name);
+            return Err(self.error_at(&msg[..], name.span));
+        } else {
+            None
+        };
+        self.end_statement()?;
+
+        Ok(Stmt::new(
+            StmtKind::Var {
+                name,
+                ty,
+                init,
+                constant,
+            },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn destructure_declaration(&mut self, start: Span, constant: bool) -> ParseResult<Stmt> {
+        let pattern = self.pattern()?;
+
+        let mut seen: Vec<&str> = Vec::new();
+        for name in pattern.names() {
+            if seen.contains(&&name.name[..]) {
+                let msg = format!("duplicate variable {} in pattern", name.name);
+                return Err(self.error_at(&msg[..], name.span));
+            }
+            seen.push(&name.name[..]);
+        }
+
+        // There is nothing to take apart without a value.
+        self.expect(TokenType::Equals, "'='")?;
+        let init = self.expression()?;
+        self.end_statement()?;
+
+        Ok(Stmt::new(
+            StmtKind::Destructure {
+                pattern,
+                init,
+                constant,
+            },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn pattern(&mut self) -> ParseResult<Pattern> {
+        let start = self.current_span();
+        let kind = match self.peek_type() {
+            Some(TokenType::LeftBracket) => {
+                self.advance();
+                let mut items = Vec::new();
+                if !self.check(TokenType::RightBracket) {
+                    loop {
+                        items.push(self.nested("pattern", |p| p.pattern())?);
+                        if !self.more_items(TokenType::RightBracket) {
+                            break;
+                        }
+                    }
+                }
+                self.expect(TokenType::RightBracket, "']'")?;
+                PatternKind::List(items)
+            }
+            Some(TokenType::LeftBrace) => {
+                self.advance();
+                let mut entries = Vec::new();
+                if !self.check(TokenType::RightBrace) {
+                    loop {
+                        entries.push(self.map_pattern_entry()?);
+                        if !self.more_items(TokenType::RightBrace) {
+                            break;
+                        }
+                    }
+                }
+                self.expect(TokenType::RightBrace, "'}'")?;
+                PatternKind::Map(entries)
+            }
+            _ => {
+                let name = self.identifier("variable name")?;
+                return Ok(Pattern::new(PatternKind::Name(name.clone()), name.span));
+            }
+        };
+
+        Ok(Pattern::new(kind, start.to(self.previous_span())))
+    }
+
+    fn map_pattern_entry(&mut self) -> ParseResult<MapPatternEntry> {
+        let start = self.current_span();
+        let key = self.map_key()?;
+
+        // Like map literals, a name on its own binds the entry to a variable
+        // with the same name.
+        if let MapKey::Identifier(name) = &key {
+            if !self.check(TokenType::Colon) {
+                let pattern = Pattern::new(PatternKind::Name(name.clone()), name.span);
+                return Ok(MapPatternEntry {
+                    key,
+                    pattern,
+                    span: start,
+                });
+            }
+        }
+
+        self.expect(TokenType::Colon, "':'")?;
+        let pattern = self.nested("pattern", |p| p.pattern())?;
+        let span = start.to(pattern.span);
+
+        Ok(MapPatternEntry { key, pattern, span })
+    }
+
+    // A function or class declaration with any decorators written before
+    // it. The declaration starts at the first decorator.
+    fn decorated_declaration(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        let decorators = self.decorators()?;
+        match self.peek_type() {
+            Some(TokenType::Function) => self.function_declaration(start, decorators),
+            Some(TokenType::Class) => self.class_declaration(start, decorators),
+            _ => {
+                let msg = format!(
+                    "expected function or class after decorator but found {}",
+                    self.found()
+                );
+                Err(self.error_at_current(&msg[..]))
+            }
+        }
+    }
+
+    fn decorators(&mut self) -> ParseResult<Vec<Expr>> {
+        let mut decorators = Vec::new();
+        while self.matches(TokenType::At) {
+            decorators.push(self.call()?);
+        }
+
+        Ok(decorators)
+    }
+
+    fn function_declaration(&mut self, start: Span, decorators: Vec<Expr>) -> ParseResult<Stmt> {
+        self.advance();
+        let name = self.identifier("function name")?;
+        let decl = self.function_body(name, start, None, decorators)?;
+        let span = decl.span;
+
+        Ok(Stmt::new(StmtKind::Function(Box::new(decl)), span))
+    }
+
+    fn function_body(
+        &mut self,
+        name: Identifier,
+        start: Span,
+        class: Option<&str>,
+        decorators: Vec<Expr>,
+    ) -> ParseResult<FunctionDecl> {
+        // Methods include the class name to make logging output from
+        // __function easier to follow.
+        let label = match class {
+            Some(class) => format!("{}.{}", class, name.name),
+            None => name.name.clone(),
+        };
+        let (params, body) = self.parameters_and_body(label)?;
+
+        Ok(FunctionDecl {
+            id: NodeId::DUMMY,
+            decorators,
+            name,
+            params: params.names,
+            param_types: params.types,
+            defaults: params.defaults,
+            rest: params.rest,
+            return_type: params.return_type,
+            body,
+            span: start.to(self.previous_span()),
+        })
+    }
+
+    fn parameters_and_body(&mut self, label: String) -> ParseResult<(Parameters, Vec<Stmt>)> {
+        let params = self.parameters()?;
+
+        // Keep track of which function we are in so that __function can be
+        // replaced with the name of the function at compile time.
+        // Loops outside of the function cannot be broken out of from inside.
+        self.functions.push(label);
+        let loops = std::mem::take(&mut self.loops);
+        let body = self.block();
+        self.loops = loops;
+        self.functions.pop();
+
+        Ok((params, body?))
+    }
+
+    fn class_declaration(&mut self, start: Span, decorators: Vec<Expr>) -> ParseResult<Stmt> {
+        self.advance();
+        let name = self.identifier("class name")?;
+        let superclass = if self.matches(TokenType::Extends) {
+            let superclass = self.identifier("superclass name")?;
+            if superclass.name == name.name {
+                return Err(self.error_at("a class cannot extend itself", superclass.span));
+            }
+            Some(superclass)
+        } else {
+            None
+        };
+
+        self.expect(TokenType::LeftBrace, "'{'")?;
+        self.classes.push(superclass.is_some());
+        let body = self.class_body(&name.name[..]);
+        self.classes.pop();
+        let members = match body {
+            Ok(members) => members,
+            Err(e) => {
+                // Skip the rest of the class body so that its closing brace is
+                // not mistaken for a stray brace after the class.
+                self.errors.push(e);
+                self.skip_to_closing_brace();
+                ClassMembers::default()
+            }
+        };
+        self.expect(TokenType::RightBrace, "'}'")?;
+
+        let span = start.to(self.previous_span());
+        Ok(Stmt::new(
+            StmtKind::Class(Box::new(ClassDecl {
+                id: NodeId::DUMMY,
+                decorators,
+                name,
+                superclass,
+                initializer: members.initializer,
+                methods: members.methods,
+                getters: members.getters,
+                setters: members.setters,
+                span,
+            })),
+            span,
+        ))
+    }
+
+    fn class_body(&mut self, class: &str) -> ParseResult<ClassMembers> {
+        let mut members = ClassMembers::default();
+
+        // Class bodies only contain methods which are written like a function
+        // declaration without the function keyword. Accessors start with get
+        // or set, which are only keywords right before the name of one.
+        while !self.is_at_end() && !self.check(TokenType::RightBrace) {
+            let start = self.current_span();
+            let decorators = self.decorators()?;
+            let accessor = if self.check(TokenType::Identifier)
+                && self.peek_type_at(1) == Some(TokenType::Identifier)
+                && matches!(self.data(self.current), "get" | "set")
+            {
+                let index = self.advance();
+                Some(self.data(index) == "get")
+            } else {
+                None
+            };
+            let name = self.identifier("method name")?;
+            if name.name == INITIALIZER_NAME {
+                if !decorators.is_empty() {
+                    return Err(self.error_at("the initializer cannot have decorators", start));
+                }
+                if accessor.is_some() {
+                    return Err(self.error_at("the initializer cannot be an accessor", start));
+                }
+            }
+            let method = self.function_body(name, start, Some(class), decorators)?;
+            let name = &method.name.name[..];
+            let span = method.name.span;
+
+            let is_method = |m: &FunctionDecl| m.name.name == name;
+            let msg = match accessor {
+                None if name == INITIALIZER_NAME && members.initializer.is_some() => {
+                    Some(format!("duplicate method {}", name))
+                }
+                None if members.methods.iter().any(is_method) => {
+                    Some(format!("duplicate method {}", name))
+                }
+                Some(true) if members.getters.iter().any(is_method) => {
+                    Some(format!("duplicate getter {}", name))
+                }
+                Some(false) if members.setters.iter().any(is_method) => {
+                    Some(format!("duplicate setter {}", name))
+                }
+                // A property and a method can't share a name since reading
+                // either one looks the same.
+                None if members
+                    .getters
+                    .iter()
+                    .chain(&members.setters)
+                    .any(is_method) =>
+                {
+                    Some(format!("{} is already a property", name))
+                }
+                Some(_) if members.methods.iter().any(is_method) => {
+                    Some(format!("{} is already a method", name))
+                }
+                Some(true) if method.arity() > 0 || method.rest.is_some() => {
+                    Some(format!("getter {} cannot have parameters", name))
+                }
+                Some(false) if method.arity() != 1 || method.rest.is_some() => {
+                    Some(format!("setter {} needs exactly one parameter", name))
+                }
+                _ => None,
+            };
+            if let Some(msg) = msg {
+                return Err(self.error_at(&msg[..], span));
+            }
+
+            match accessor {
+                Some(true) => members.getters.push(method),
+                Some(false) => members.setters.push(method),
+                None if method.name.name == INITIALIZER_NAME => members.initializer = Some(method),
+                None => members.methods.push(method),
+            }
+        }
+
+        Ok(members)
+    }
+
+    fn parameters(&mut self) -> ParseResult<Parameters> {
+        self.expect(TokenType::LeftParen, "'('")?;
+        let mut params = Parameters {
+            names: Vec::new(),
+            types: Vec::new(),
+            defaults: Vec::new(),
+            rest: None,
+            return_type: None,
+        };
+        if !self.check(TokenType::RightParen) {
+            loop {
+                let is_rest = self.matches(TokenType::Ellipsis);
+                let param = self.identifier("parameter name")?;
+                let duplicate = params.names.iter().any(|p| p.name == param.name);
+                if duplicate {
+                    return Err(self.error_at(
+                        &format!("duplicate parameter {}", param.name)[..],
+                        param.span,
+                    ));
+                }
+
+                if is_rest {
+                    if self.check(TokenType::Comma) {
+                        return Err(self.error_at("rest parameter must be last", param.span));
+                    }
+                    params.rest = Some(param);
+                    break;
+                }
+
+                params.types.push(self.optional_type()?);
+                // Once one parameter has a default all of the ones after it
+                // need one too, otherwise the arguments would be ambiguous.
+                if self.matches(TokenType::Equals) {
+                    params.defaults.push(self.expression()?);
+                } else if !params.defaults.is_empty() {
+                    let msg = format!("parameter {} needs a default value", param.name);
+                    return Err(self.error_at(&msg[..], param.span));
+                }
+                params.names.push(param);
+
+                if !self.more_items(TokenType::RightParen) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenType::RightParen, "')'")?;
+        params.return_type = self.optional_type()?;
+
+        Ok(params)
+    }
+
+    // A type follows a colon after the thing it describes.
+    fn optional_type(&mut self) -> ParseResult<Option<TypeAnnotation>> {
+        if self.matches(TokenType::Colon) {
+            Ok(Some(self.type_annotation()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn type_annotation(&mut self) -> ParseResult<TypeAnnotation> {
+        self.nested("type", |p| {
+            // Function is a keyword but it is also the type of a function.
+            let name = if p.check(TokenType::Function) {
+                let index = p.current;
+                p.advance();
+                Identifier::new(p.data(index), p.span(index))
+            } else {
+                p.identifier("type name")?
+            };
+
+            let mut args = Vec::new();
+            if p.matches(TokenType::LessThan) {
+                loop {
+                    args.push(p.type_annotation()?);
+                    if !p.matches(TokenType::Comma) {
+                        break;
+                    }
+                }
+                p.expect(TokenType::GreaterThan, "'>'")?;
+            }
+
+            let span = name.span.to(p.previous_span());
+            Ok(TypeAnnotation { name, args, span })
+        })
+    }
+
+    fn block(&mut self) -> ParseResult<Vec<Stmt>> {
+        self.expect(TokenType::LeftBrace, "'{'")?;
+        let mut body = Vec::new();
+        while !self.is_at_end() && !self.check(TokenType::RightBrace) {
+            if let Some(stmt) = self.declaration() {
+                body.push(stmt);
+            }
+        }
+        self.expect(TokenType::RightBrace, "'}'")?;
+
+        Ok(body)
+    }
+
+    fn condition(&mut self) -> ParseResult<Expr> {
+        self.expect(TokenType::LeftParen, "'('")?;
+        let condition = self.expression()?;
+        self.expect(TokenType::RightParen, "')'")?;
+
+        Ok(condition)
+    }
+
+    fn if_statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        self.advance();
+        let condition = self.condition()?;
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.matches(TokenType::Else) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::new(
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn labeled_statement(&mut self) -> ParseResult<Stmt> {
+        let label = self.identifier("loop label")?;
+        self.advance();
+
+        // Labels are only useful on loops since they are only used as the
+        // target of a break or continue.
+        if self.loops.iter().any(|l| l.as_ref() == Some(&label.name)) {
+            let msg = format!("duplicate loop label {}", label.name);
+            return Err(self.error_at(&msg[..], label.span));
+        }
+        match self.peek_type() {
+            Some(TokenType::While) => self.while_statement(Some(label)),
+            Some(TokenType::Do) => self.do_while_statement(Some(label)),
+            Some(TokenType::For) => self.for_statement(Some(label)),
+            _ => {
+                let msg = format!("expected loop after label but found {}", self.found());
+                Err(self.error_at_current(&msg[..]))
+            }
+        }
+    }
+
+    fn loop_body(&mut self, label: &Option<Identifier>) -> ParseResult<Box<Stmt>> {
+        self.loops.push(label.as_ref().map(|l| l.name.clone()));
+        let body = self.statement();
+        self.loops.pop();
+
+        Ok(Box::new(body?))
+    }
+
+    fn while_statement(&mut self, label: Option<Identifier>) -> ParseResult<Stmt> {
+        let start = label
+            .as_ref()
+            .map(|l| l.span)
+            .unwrap_or(self.current_span());
+        self.advance();
+        let condition = self.condition()?;
+        let body = self.loop_body(&label)?;
+
+        Ok(Stmt::new(
+            StmtKind::While {
+                label,
+                condition,
+                body,
+            },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn do_while_statement(&mut self, label: Option<Identifier>) -> ParseResult<Stmt> {
+        let start = label
+            .as_ref()
+            .map(|l| l.span)
+            .unwrap_or(self.current_span());
+        self.advance();
+        let body = self.loop_body(&label)?;
+        self.expect(TokenType::While, "'while'")?;
+        let condition = self.condition()?;
+        self.end_statement()?;
+
+        Ok(Stmt::new(
+            StmtKind::DoWhile {
+                label,
+                body,
+                condition,
+            },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn for_statement(&mut self, label: Option<Identifier>) -> ParseResult<Stmt> {
+        let start = label
+            .as_ref()
+            .map(|l| l.span)
+            .unwrap_or(self.current_span());
+        self.advance();
+        self.expect(TokenType::LeftParen, "'('")?;
+        let variable = self.identifier("loop variable")?;
+        self.expect(TokenType::In, "'in'")?;
+        let iterable = self.expression()?;
+        self.expect(TokenType::RightParen, "')'")?;
+        let body = self.loop_body(&label)?;
+
+        Ok(Stmt::new(
+            StmtKind::For {
+                label,
+                variable,
+                iterable,
+                body,
+            },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn match_statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        self.advance();
+        let subject = self.expression()?;
+        self.expect(TokenType::LeftBrace, "'{'")?;
+
+        let mut arms: Vec<MatchArm> = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let arm_start = self.current_span();
+            let mut values = Vec::new();
+            if self.matches(TokenType::Default) {
+                // The arm is still parsed, so that nothing in it is reported
+                // as a mistake of its own.
+                if arms.iter().any(|a| a.is_default()) {
+                    let error = self.error_at("duplicate default in match", arm_start);
+                    self.errors.push(error);
+                }
+            } else {
+                self.expect(TokenType::Case, "'case' or 'default'")?;
+                // Several values can share an arm, as in `case 1, 2:`.
+                loop {
+                    values.push(self.match_value()?);
+                    if !self.matches(TokenType::Comma) {
+                        break;
+                    }
+                }
+            }
+            self.expect(TokenType::Colon, "':'")?;
+
+            // An arm runs until the next arm starts, there is no falling
+            // through from one arm into the next.
+            let mut body = Vec::new();
+            while !self.check(TokenType::Case)
+                && !self.check(TokenType::Default)
+                && !self.check(TokenType::RightBrace)
+                && !self.is_at_end()
+            {
+                if let Some(stmt) = self.declaration() {
+                    body.push(stmt);
+                }
+            }
+
+            arms.push(MatchArm {
+                values,
+                body,
+                span: arm_start.to(self.previous_span()),
+            });
+        }
+        self.expect(TokenType::RightBrace, "'}'")?;
+
+        Ok(Stmt::new(
+            StmtKind::Match { subject, arms },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn match_value(&mut self) -> ParseResult<Expr> {
+        // Only literals can be matched against so that arms can be checked
+        // for duplicates and compared quickly at runtime.
+        let value = self.unary()?;
+        let literal = match &value.kind {
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::String(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Null => true,
+            ExprKind::Unary {
+                op: UnaryOp::Negate,
+                operand,
+            } => matches!(operand.kind, ExprKind::Int(_) | ExprKind::Float(_)),
+            _ => false,
+        };
+        if !literal {
+            return Err(self.error_at("match case must be a literal", value.span));
+        }
+
+        Ok(value)
+    }
+
+    fn try_statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        self.advance();
+        let body = self.block()?;
+
+        let catch = if self.check(TokenType::Catch) {
+            let catch_start = self.current_span();
+            self.advance();
+            let binding = if self.matches(TokenType::LeftParen) {
+                let binding = self.identifier("catch variable name")?;
+                self.expect(TokenType::RightParen, "')'")?;
+                Some(binding)
+            } else {
+                None
+            };
+            let body = self.block()?;
+            Some(CatchClause {
+                binding,
+                body,
+                span: catch_start.to(self.previous_span()),
+            })
+        } else {
+            None
+        };
+
+        let finally = if self.matches(TokenType::Finally) {
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        // A try without either handler would do nothing at all.
+        if catch.is_none() && finally.is_none() {
+            let msg = format!("expected 'catch' or 'finally' but found {}", self.found());
+            return Err(self.error_at_current(&msg[..]));
+        }
+
+        Ok(Stmt::new(
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn throw_statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        self.advance();
+        let value = self.expression()?;
+        self.end_statement()?;
+
+        Ok(Stmt::new(
+            StmtKind::Throw(value),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn return_statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        if self.functions.is_empty() {
+            return Err(self.error_at("return outside of function", start));
+        }
+
+        self.advance();
+        let value = if self.at_statement_end() {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        // An initializer always gives back the instance it was called with,
+        // and only methods have a dot in their label.
+        let initializer = format!(".{}", INITIALIZER_NAME);
+        if value.is_some() && self.functions.last().unwrap().ends_with(&initializer[..]) {
+            let msg = "cannot return a value from an initializer";
+            return Err(self.error_at(msg, start.to(self.previous_span())));
+        }
+        self.end_statement()?;
+
+        Ok(Stmt::new(
+            StmtKind::Return(value),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    // The value is left off when nothing that could start one follows, as in
+    // `yield;` or `f(yield)`.
+    fn yield_expression(&mut self) -> ParseResult<Expr> {
+        let start = self.current_span();
+        if self.functions.is_empty() {
+            return Err(self.error_at("yield outside of function", start));
+        }
+
+        self.advance();
+        let ends = [
+            TokenType::RightParen,
+            TokenType::RightBracket,
+            TokenType::Comma,
+            TokenType::Colon,
+        ];
+        let value = if self.at_statement_end() || ends.iter().any(|&end| self.check(end)) {
+            None
+        } else {
+            Some(Box::new(self.nested("expression", |p| p.assignment())?))
+        };
+
+        Ok(Expr::new(
+            ExprKind::Yield(value),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn jump_statement(&mut self, token: TokenType) -> ParseResult<Stmt> {
+        let start = self.current_span();
+        let keyword = String::from(self.data(self.current));
+        if self.loops.is_empty() {
+            let msg = format!("{} outside of loop", keyword);
+            return Err(self.error_at(&msg[..], start));
+        }
+
+        self.advance();
+        let label = if self.check(TokenType::Identifier) && !self.at_statement_end() {
+            let label = self.identifier("loop label")?;
+            if !self.loops.iter().any(|l| l.as_ref() == Some(&label.name)) {
+                let msg = format!("unknown loop label {}", label.name);
+                return Err(self.error_at(&msg[..], label.span));
+            }
+            Some(label)
+        } else {
+            None
+        };
+        self.end_statement()?;
+
+        let kind = if token == TokenType::Break {
+            StmtKind::Break(label)
+        } else {
+            StmtKind::Continue(label)
+        };
+        Ok(Stmt::new(kind, start.to(self.previous_span())))
+    }
+
+    fn expression_statement(&mut self) -> ParseResult<Stmt> {
+        let expr = self.expression()?;
+        if self.check(TokenType::Comma) {
+            return self.multiple_assignment(expr);
+        }
+        self.end_statement()?;
+        let span = expr.span.to(self.previous_span());
+
+        Ok(Stmt::new(StmtKind::Expression(expr), span))
+    }
+
+    fn multiple_assignment(&mut self, first: Expr) -> ParseResult<Stmt> {
+        let start = first.span;
+        let mut targets = vec![self.to_pattern(first)?];
+        // The targets stop short of assignment, otherwise the `b = b` in
+        // `a, b = b, a` would be taken as an assignment of its own.
+        while self.matches(TokenType::Comma) {
+            let target = self.nested("expression", |p| p.conditional())?;
+            targets.push(self.to_pattern(target)?);
+        }
+        self.expect(TokenType::Equals, "'='")?;
+
+        let mut values = vec![self.expression()?];
+        while self.matches(TokenType::Comma) {
+            values.push(self.expression()?);
+        }
+        if values.len() != 1 && values.len() != targets.len() {
+            let msg = format!(
+                "cannot assign {} values to {} targets",
+                values.len(),
+                targets.len()
+            );
+            let span = values[0].span.to(values[values.len() - 1].span);
+            return Err(self.error_at(&msg[..], span));
+        }
+        self.end_statement()?;
+
+        Ok(Stmt::new(
+            StmtKind::MultipleAssign { targets, values },
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn expression(&mut self) -> ParseResult<Expr> {
+        self.nested("expression", |p| p.assignment())
+    }
+
+    fn assignment(&mut self) -> ParseResult<Expr> {
+        if self.check(TokenType::Yield) {
+            return self.yield_expression();
+        }
+        let target = self.conditional()?;
+        let op = match self.peek_type() {
+            Some(TokenType::Equals) => None,
+            Some(TokenType::PlusEquals) => Some(BinaryOp::Add),
+            Some(TokenType::MinusEquals) => Some(BinaryOp::Subtract),
+            Some(TokenType::StarEquals) => Some(BinaryOp::Multiply),
+            Some(TokenType::SlashEquals) => Some(BinaryOp::Divide),
+            Some(TokenType::PercentEquals) => Some(BinaryOp::Modulo),
+            Some(TokenType::AmpersandEquals) => Some(BinaryOp::BitAnd),
+            Some(TokenType::PipeEquals) => Some(BinaryOp::BitOr),
+            Some(TokenType::CaretEquals) => Some(BinaryOp::BitXor),
+            _ => return Ok(target),
+        };
+
+        // A list or map on the left of a plain assignment is a pattern that
+        // takes the value apart, as in `[a, b] = [b, a]`.
+        if op.is_none()
+            && matches!(
+                target.kind,
+                ExprKind::ListLiteral(_) | ExprKind::MapLiteral(_)
+            )
+        {
+            self.advance();
+            let pattern = self.to_pattern(target)?;
+            let value = self.nested("expression", |p| p.assignment())?;
+            let span = pattern.span.to(value.span);
+            return Ok(Expr::new(
+                ExprKind::Destructure {
+                    pattern: Box::new(pattern),
+                    value: Box::new(value),
+                },
+                span,
+            ));
+        }
+
+        // The target has already been parsed as an ordinary expression since
+        // there is no way to know it is being assigned to until the equals
+        // sign is found. Only now can we check that it can be stored into.
+        if !Parser::is_assignable(&target) {
+            return Err(self.error_at("invalid assignment target", target.span));
+        }
+
+        self.advance();
+        // Assignment groups to the right so `a = b = c` stores c in both.
+        let value = self.nested("expression", |p| p.assignment())?;
+        let span = target.span.to(value.span);
+
+        Ok(Expr::new(
+            ExprKind::Assign {
+                op,
+                target: Box::new(target),
+                value: Box::new(value),
+            },
+            span,
+        ))
+    }
+
+    fn to_pattern(&self, expr: Expr) -> ParseResult<Pattern> {
+        let span = expr.span;
+        let kind = match expr.kind {
+            ExprKind::Variable(name) => PatternKind::Name(Identifier::new(&name[..], span)),
+            ExprKind::ListLiteral(items) => PatternKind::List(
+                items
+                    .into_iter()
+                    .map(|item| self.to_pattern(item))
+                    .collect::<ParseResult<_>>()?,
+            ),
+            ExprKind::MapLiteral(entries) => PatternKind::Map(
+                entries
+                    .into_iter()
+                    .map(|entry| {
+                        Ok(MapPatternEntry {
+                            key: entry.key,
+                            pattern: self.to_pattern(entry.value)?,
+                            span: entry.span,
+                        })
+                    })
+                    .collect::<ParseResult<_>>()?,
+            ),
+            kind => {
+                let target = Expr::new(kind, span);
+                if !Parser::is_assignable(&target) {
+                    return Err(self.error_at("invalid assignment target", span));
+                }
+                PatternKind::Target(target)
+            }
+        };
+
+        Ok(Pattern::new(kind, span))
+    }
+
+    fn is_assignable(expr: &Expr) -> bool {
+        // Slices make a copy so storing into one would be silently lost.
+        matches!(
+            expr.kind,
+            ExprKind::Variable(_) | ExprKind::Member { .. } | ExprKind::Index { .. }
+        )
+    }
+
+    fn conditional(&mut self) -> ParseResult<Expr> {
+        let condition = self.range()?;
+        if !self.matches(TokenType::Question) {
+            return Ok(condition);
+        }
+
+        // The middle is a full expression since the colon marks where it
+        // ends. The last part recurses so that chains group to the right,
+        // `a ? b : c ? d : e` is the same as `a ? b : (c ? d : e)`.
+        let then_branch = self.expression()?;
+        self.expect(TokenType::Colon, "':'")?;
+        let else_branch = self.nested("expression", |p| p.conditional())?;
+        let span = condition.span.to(else_branch.span);
+
+        Ok(Expr::new(
+            ExprKind::Conditional {
+                condition: Box::new(condition),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            },
+            span,
+        ))
+    }
+
+    fn range(&mut self) -> ParseResult<Expr> {
+        // Ranges bind looser than every binary operator, so `0..n - 1` is
+        // `0..(n - 1)` and `a < b..c` compares before making the range.
+        let start = self.binary_expression(LOWEST_PRECEDENCE)?;
+        let inclusive = match self.peek_type() {
+            Some(TokenType::DotDot) => false,
+            Some(TokenType::DotDotEquals) => true,
+            _ => return Ok(start),
+        };
+        self.advance();
+        let end = self.binary_expression(LOWEST_PRECEDENCE)?;
+        if self.check(TokenType::DotDot) || self.check(TokenType::DotDotEquals) {
+            return Err(self.error_at_current("ranges cannot be chained"));
+        }
+        let span = start.span.to(end.span);
+
+        Ok(Expr::new(
+            ExprKind::Range {
+                start: Box::new(start),
+                end: Box::new(end),
+                inclusive,
+            },
+            span,
+        ))
+    }
+
+    fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+        let span = left.span.to(right.span);
+        Expr::new(
+            ExprKind::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+            span,
+        )
+    }
+
+    fn binary_expression(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        let chain_depth = self.chain_depth;
+        let result = self.binary_chain(min_precedence);
+        self.chain_depth = chain_depth;
+        result
+    }
+
+    fn binary_chain(&mut self, min_precedence: u8) -> ParseResult<Expr> {
+        // Precedence climbing. Operators that bind at least as tightly as the
+        // caller allows are folded into the left hand side, and the right
+        // hand side is parsed with a higher minimum for left associative
+        // operators so that `a - b - c` is `(a - b) - c`.
+        let mut expr = self.unary()?;
+        while let Some(operator) = self.peek_type().and_then(binary_operator) {
+            if operator.precedence < min_precedence {
+                break;
+            }
+            self.lengthen_chain()?;
+            self.advance();
+
+            // The right hand side of a left associative operator binds more
+            // tightly, so there are only so many of them inside each other,
+            // but one of a right associative operator can be another chain.
+            let right = match operator.associativity {
+                Associativity::Left => self.binary_expression(operator.precedence + 1)?,
+                Associativity::Right => {
+                    self.nested("expression", |p| p.binary_expression(operator.precedence))?
+                }
+            };
+            expr = Parser::binary(expr, operator.op, right);
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        let op = match self.peek_type().and_then(unary_operator) {
+            Some(operator) => operator.op,
+            None => return self.call(),
+        };
+
+        let start = self.current_span();
+        self.advance();
+        let operand = self.nested("expression", |p| p.unary())?;
+        let span = start.to(operand.span);
+
+        Ok(Expr::new(
+            ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+            span,
+        ))
+    }
+
+    fn call(&mut self) -> ParseResult<Expr> {
+        let chain_depth = self.chain_depth;
+        let result = self.call_chain();
+        self.chain_depth = chain_depth;
+        result
+    }
+
+    fn call_chain(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            let link = match self.peek_type() {
+                Some(TokenType::LeftParen) => Parser::finish_call,
+                Some(TokenType::LeftBracket) => Parser::index,
+                Some(TokenType::Dot) => Parser::member,
+                _ => break,
+            };
+            self.lengthen_chain()?;
+            expr = link(self, expr)?;
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> ParseResult<Expr> {
+        self.advance();
+        let mut args = Vec::new();
+        let mut names: Vec<Identifier> = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if self.check(TokenType::Identifier)
+                    && self.peek_type_at(1) == Some(TokenType::Colon)
+                {
+                    let name = self.identifier("argument name")?;
+                    if names.iter().any(|n| n.name == name.name) {
+                        let msg = format!("duplicate named argument {}", name.name);
+                        return Err(self.error_at(&msg[..], name.span));
+                    }
+                    self.advance();
+                    names.push(name);
+                    args.push(self.expression()?);
+                } else {
+                    let arg = self.expression()?;
+                    if !names.is_empty() {
+                        let msg = "positional argument after named argument";
+                        return Err(self.error_at(msg, arg.span));
+                    }
+                    args.push(arg);
+                }
+                if !self.more_items(TokenType::RightParen) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenType::RightParen, "')'")?;
+
+        let span = callee.span.to(self.previous_span());
+        Ok(Expr::new(
+            ExprKind::Call(Box::new(Call {
+                callee: Box::new(callee),
+                args,
+                names,
+                span,
+            })),
+            span,
+        ))
+    }
+
+    fn member(&mut self, object: Expr) -> ParseResult<Expr> {
+        self.advance();
+        let name = self.identifier("member name")?;
+        // Each link in a chain like `a.b.c` gets its own node and span, so
+        // when one of them turns out to be null the error can point right at
+        // the name that failed instead of the whole chain.
+        let span = object.span.to(name.span);
+
+        Ok(Expr::new(
+            ExprKind::Member {
+                object: Box::new(object),
+                name,
+            },
+            span,
+        ))
+    }
+
+    fn index(&mut self, object: Expr) -> ParseResult<Expr> {
+        let object_span = object.span;
+        self.advance();
+        // Both ends of a slice are optional so `a[:n]` and `a[n:]` work as
+        // well as `a[:]` for a copy of the whole thing.
+        let start = if self.check(TokenType::Colon) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+
+        let kind = if self.matches(TokenType::Colon) {
+            let end = if self.check(TokenType::RightBracket) {
+                None
+            } else {
+                Some(Box::new(self.expression()?))
+            };
+            ExprKind::Slice {
+                object: Box::new(object),
+                start,
+                end,
+            }
+        } else {
+            // The first branch only leaves the start empty when a colon
+            // follows, so a plain index always has an expression.
+            ExprKind::Index {
+                object: Box::new(object),
+                index: start.unwrap(),
+            }
+        };
+        self.expect(TokenType::RightBracket, "']'")?;
+
+        Ok(Expr::new(kind, object_span.to(self.previous_span())))
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        let token = match self.peek_type() {
+            Some(t) => t,
+            None => return Err(self.error_at_current("expected expression but found eof")),
+        };
+
+        let span = self.current_span();
+        let kind = match token {
+            // A number with a decimal point is a float, and one without is an
+            // int, which has to fit in 64 bits.
+            TokenType::NumberLiteral if self.data(self.current).contains('.') => {
+                match self.data(self.current).parse::<f64>() {
+                    Ok(n) => ExprKind::Float(n),
+                    Err(_) => {
+                        let msg = format!("invalid number {}", self.data(self.current));
+                        return Err(self.error_at(&msg[..], span));
+                    }
+                }
+            }
+            TokenType::NumberLiteral => match self.data(self.current).parse::<i64>() {
+                Ok(n) => ExprKind::Int(n),
+                Err(_) => {
+                    let msg = format!("invalid number {}", self.data(self.current));
+                    return Err(self.error_at(&msg[..], span));
+                }
+            },
+            TokenType::StringLiteral => ExprKind::String(String::from(self.data(self.current))),
+            TokenType::FormattedStringLiteral => return self.formatted_string(),
+            TokenType::LeftBracket => return self.list_literal(),
+            TokenType::LeftBrace => return self.map_literal(),
+            TokenType::Function => return self.lambda(),
+            TokenType::TrueLiteral => ExprKind::Bool(true),
+            TokenType::FalseLiteral => ExprKind::Bool(false),
+            TokenType::NullLiteral => ExprKind::Null,
+            TokenType::ThisLiteral => {
+                if self.classes.is_empty() {
+                    return Err(self.error_at("this used outside of a class", span));
+                }
+                ExprKind::This
+            }
+            TokenType::SuperLiteral => return self.super_expression(),
+            TokenType::Identifier => ExprKind::Variable(String::from(self.data(self.current))),
+            TokenType::FunctionNameLiteral => ExprKind::String(String::from(
+                self.functions
+                    .last()
+                    .map(|f| &f[..])
+                    .unwrap_or(SCRIPT_FUNCTION_NAME),
+            )),
+            TokenType::LeftParen => {
+                self.advance();
+                let mut expr = self.expression()?;
+                self.expect(TokenType::RightParen, "')'")?;
+                // The span of a grouping includes the parentheses so that it
+                // covers everything the user wrote.
+                expr.span = span.to(self.previous_span());
+                return Ok(expr);
+            }
+            _ => {
+                let msg = format!("expected expression but found {}", self.found());
+                return Err(self.error_at(&msg[..], span));
+            }
+        };
+
+        self.advance();
+        Ok(Expr::new(kind, span))
+    }
+
+    fn formatted_string(&mut self) -> ParseResult<Expr> {
+        let span = self.current_span();
+        self.advance();
+
+        // Work from the raw text in the source rather than the token data.
+        // The token has already had its escape characters replaced, which
+        // would throw off the positions of the expressions in the string.
+        let raw_start = span.start - self.origin.start + 1;
+        let raw_end = span.end - self.origin.start - 1;
+        let raw = &self.source[raw_start..raw_end];
+        let chars: Vec<(usize, char)> = raw.char_indices().collect();
+
+        // Work out the line and column of every character up front, starting
+        // from the opening quote, so errors can be placed exactly.
+        let mut positions = Vec::with_capacity(chars.len());
+        let (mut line, mut column, mut previous) = (span.line, span.column, '"');
+        for &(_, c) in &chars {
+            if previous == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            positions.push((line, column));
+            previous = c;
+        }
+
+        let error_at = |i: usize, msg: &str| {
+            let (line, column) = positions[i];
+            Error::new(msg, &self.src_name[..], line, column)
+        };
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i].1;
+            let next = chars.get(i + 1).map(|&(_, c)| c);
+            match c {
+                // The scanner has already rejected any invalid escapes.
+                '\\' => {
+                    literal.push(match next {
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('n') => '\n',
+                        Some(c) => c,
+                        None => '\\',
+                    });
+                    i += 2;
+                }
+                // Doubling a brace is how a literal brace is written.
+                '{' if next == Some('{') => {
+                    literal.push('{');
+                    i += 2;
+                }
+                '}' if next == Some('}') => {
+                    literal.push('}');
+                    i += 2;
+                }
+                '}' => return Err(error_at(i, "unexpected '}' in formatted string, use '}}'")),
+                '{' => {
+                    let close = match Parser::closing_brace(&chars[..], i) {
+                        Some(close) => close,
+                        None => {
+                            return Err(error_at(i, "expected '}' to end the interpolation"));
+                        }
+                    };
+                    if close == i + 1 {
+                        return Err(error_at(i, "expected expression inside of '{}'"));
+                    }
+
+                    let (line, column) = positions[i + 1];
+                    let start = chars[i + 1].0;
+                    let end = chars[close].0;
+                    let origin = Span::new(
+                        self.origin.start + raw_start + start,
+                        self.origin.start + raw_start + end,
+                        line,
+                        column,
+                    );
+                    let expr = self.interpolation(&raw[start..end], origin)?;
+
+                    if !literal.is_empty() {
+                        parts.push(StringPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(StringPart::Expr(expr));
+                    i = close + 1;
+                }
+                c => {
+                    literal.push(c);
+                    i += 1;
+                }
+            }
+        }
+
+        // A formatted string without anything spliced into it is really just
+        // a plain string and is treated like one.
+        if parts.is_empty() {
+            return Ok(Expr::new(ExprKind::String(literal), span));
+        }
+        if !literal.is_empty() {
+            parts.push(StringPart::Literal(literal));
+        }
+
+        Ok(Expr::new(ExprKind::InterpolatedString(parts), span))
+    }
+
+    fn closing_brace(chars: &[(usize, char)], open: usize) -> Option<usize> {
+        // Braces can be nested inside of the expression and plain strings
+        // inside of it can contain any brace they like.
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut i = open;
+        while i < chars.len() {
+            match chars[i].1 {
+                '\\' if in_string => i += 1,
+                '\'' => in_string = !in_string,
+                '{' if !in_string => depth += 1,
+                '}' if !in_string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        None
+    }
+
+    fn interpolation(&self, source: &str, origin: Span) -> ParseResult<Expr> {
+        let mut parser = Parser::new_at(&self.src_name[..], source, origin);
+        // The expression is still inside of the same function and class so
+        // __function, this and super all keep working.
+        parser.functions = self.functions.clone();
+        parser.classes = self.classes.clone();
+        parser.depth = self.depth;
+        parser.max_depth = self.max_depth;
+        parser.chain_depth = self.chain_depth;
+        parser.max_chain_depth = self.max_chain_depth;
+        parser.strict = self.strict;
+        if !parser.errors.is_empty() {
+            return Err(parser.errors.remove(0));
+        }
+
+        let expr = parser.expression()?;
+        if !parser.is_at_end() {
+            let msg = format!("expected '}}' but found {}", parser.found());
+            return Err(parser.error_at_current(&msg[..]));
+        }
+
+        Ok(expr)
+    }
+
+    fn lambda(&mut self) -> ParseResult<Expr> {
+        let start = self.current_span();
+        self.advance();
+        let (params, body) = self.parameters_and_body(String::from(LAMBDA_FUNCTION_NAME))?;
+        let span = start.to(self.previous_span());
+
+        Ok(Expr::new(
+            ExprKind::Lambda(Box::new(Lambda {
+                params: params.names,
+                param_types: params.types,
+                defaults: params.defaults,
+                rest: params.rest,
+                return_type: params.return_type,
+                body,
+                span,
+            })),
+            span,
+        ))
+    }
+
+    fn looks_like_map(&self) -> bool {
+        // At the start of a statement a brace opens a block. It is only read
+        // as a map literal when what follows is clearly the first entry of a
+        // map, which is a key followed by a colon or a computed key, or a
+        // name on its own as in `{ x, y }`. A name and a colon followed by a
+        // loop is a labeled loop inside a block.
+        match self.peek_type_at(1) {
+            Some(TokenType::Identifier) => match self.peek_type_at(2) {
+                Some(TokenType::Colon) => !matches!(
+                    self.peek_type_at(3),
+                    Some(TokenType::While) | Some(TokenType::Do) | Some(TokenType::For)
+                ),
+                Some(TokenType::Comma) | Some(TokenType::RightBrace) => true,
+                _ => false,
+            },
+            Some(TokenType::StringLiteral) | Some(TokenType::FormattedStringLiteral) => {
+                self.peek_type_at(2) == Some(TokenType::Colon)
+            }
+            Some(TokenType::LeftBracket) => true,
+            _ => false,
+        }
+    }
+
+    fn list_literal(&mut self) -> ParseResult<Expr> {
+        let start = self.current_span();
+        self.advance();
+        let mut items = Vec::new();
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                items.push(self.expression()?);
+                if !self.more_items(TokenType::RightBracket) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenType::RightBracket, "']'")?;
+
+        Ok(Expr::new(
+            ExprKind::ListLiteral(items),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn map_literal(&mut self) -> ParseResult<Expr> {
+        let start = self.current_span();
+        self.advance();
+        let mut entries = Vec::new();
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                entries.push(self.map_entry()?);
+                if !self.more_items(TokenType::RightBrace) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenType::RightBrace, "'}'")?;
+
+        Ok(Expr::new(
+            ExprKind::MapLiteral(entries),
+            start.to(self.previous_span()),
+        ))
+    }
+
+    fn map_entry(&mut self) -> ParseResult<MapEntry> {
+        let start = self.current_span();
+        let key = self.map_key()?;
+
+        // A bare name without a value is short for using the variable with
+        // the same name, so `{ x, y }` is the same as `{ x: x, y: y }`.
+        if let MapKey::Identifier(name) = &key {
+            if !self.check(TokenType::Colon) {
+                let value = Expr::new(ExprKind::Variable(name.name.clone()), name.span);
+                return Ok(MapEntry {
+                    key,
+                    value,
+                    span: start,
+                });
+            }
+        }
+
+        self.expect(TokenType::Colon, "':'")?;
+        let value = self.expression()?;
+        let span = start.to(value.span);
+
+        Ok(MapEntry { key, value, span })
+    }
+
+    fn map_key(&mut self) -> ParseResult<MapKey> {
+        let key = match self.peek_type() {
+            Some(TokenType::Identifier) => MapKey::Identifier(self.identifier("map key")?),
+            Some(TokenType::StringLiteral) => {
+                let index = self.advance();
+                MapKey::String(String::from(self.data(index)))
+            }
+            // A formatted string is only a constant key when there is nothing
+            // interpolated into it.
+            Some(TokenType::FormattedStringLiteral) => {
+                let key = self.formatted_string()?;
+                match &key.kind {
+                    ExprKind::String(s) => MapKey::String(s.clone()),
+                    _ => MapKey::Computed(key),
+                }
+            }
+            Some(TokenType::LeftBracket) => {
+                self.advance();
+                let key = self.expression()?;
+                self.expect(TokenType::RightBracket, "']'")?;
+                MapKey::Computed(key)
+            }
+            _ => {
+                let msg = format!("expected map key but found {}", self.found());
+                return Err(self.error_at_current(&msg[..]));
+            }
+        };
+
+        Ok(key)
+    }
+
+    fn super_expression(&mut self) -> ParseResult<Expr> {
+        let start = self.current_span();
+        match self.classes.last() {
+            None => return Err(self.error_at("super used outside of a class", start)),
+            Some(false) => {
+                return Err(self.error_at("super used in a class with no superclass", start))
+            }
+            Some(true) => {}
+        }
+
+        self.advance();
+        self.expect(TokenType::Dot, "'.' after super")?;
+        let method = self.identifier("superclass method name")?;
+        let span = start.to(method.span);
+
+        Ok(Expr::new(ExprKind::Super(method), span))
+    }
+}
+
+// Nodes are numbered once the whole tree has been parsed, in the order they
+// appear in the source. Doing it in one pass afterwards keeps the ids of a
+// tree dense and means nodes thrown away by error recovery never use one.
+struct Numbering {
+    next: NodeId,
+}
+
+impl Numbering {
+    fn id(&mut self) -> NodeId {
+        let id = self.next;
+        self.next = id.next();
+        id
+    }
+
+    fn label(&mut self, label: &mut Option<Identifier>) {
+        if let Some(label) = label {
+            label.id = self.id();
+        }
+    }
+}
+
+impl VisitorMut for Numbering {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        stmt.id = self.id();
+        // Labels and module names are not visited on their own.
+        match &mut stmt.kind {
+            StmtKind::While { label, .. }
+            | StmtKind::DoWhile { label, .. }
+            | StmtKind::For { label, .. }
+            | StmtKind::Break(label)
+            | StmtKind::Continue(label) => self.label(label),
+            StmtKind::Import(decl) => {
+                if let ImportSource::Module(module) = &mut decl.source {
+                    module.id = self.id();
+                }
+            }
+            _ => {}
+        }
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        expr.id = self.id();
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_function_mut(&mut self, decl: &mut FunctionDecl) {
+        decl.id = self.id();
+        walk_function_mut(self, decl);
+    }
+
+    fn visit_class_mut(&mut self, decl: &mut ClassDecl) {
+        decl.id = self.id();
+        walk_class_mut(self, decl);
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        pattern.id = self.id();
+        if let PatternKind::Map(entries) = &mut pattern.kind {
+            for entry in entries {
+                if let MapKey::Identifier(key) = &mut entry.key {
+                    key.id = self.id();
+                }
+            }
+        }
+        walk_pattern_mut(self, pattern);
+    }
+
+    fn visit_identifier_mut(&mut self, ident: &mut Identifier) {
+        ident.id = self.id();
+    }
+}
+
+pub fn parse_program(name: &str, source: &str) -> Result<Program, Vec<Error>> {
+    Parser::new(name, source).parse_program()
+}
+
+pub fn parse_expression(name: &str, source: &str) -> Result<Expr, Vec<Error>> {
+    Parser::new(name, source).parse_expression()
+}
@@ -0,0 +1,304 @@
+use super::Parser;
+use crate::ast::visit::*;
+use crate::ast::*;
+use crate::error::*;
+
+// An edit replaces the bytes from start up to end of the old source with
+// new text. Inserting is an empty range and deleting is empty text.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new(start: usize, end: usize, text: &str) -> Self {
+        Self {
+            start,
+            end,
+            text: String::from(text),
+        }
+    }
+
+    pub fn apply(&self, source: &str) -> Option<String> {
+        if self.start > self.end {
+            return None;
+        }
+        let before = source.get(..self.start)?;
+        let after = source.get(self.end..)?;
+        Some(format!("{}{}{}", before, self.text, after))
+    }
+}
+
+// The result of reparsing after an edit. The dirty list holds the indices of
+// the top level statements in the new program which were parsed again, every
+// other statement was carried over from the old program.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Reparse {
+    pub program: Program,
+    pub dirty: Vec<usize>,
+}
+
+// The name the scanner replaces with the line it is on.
+const LINE_NAME: &str = "__line";
+
+// Editors reparse after every keystroke, so only the top level statements
+// touched by the edit are parsed again. The statements after the edit are
+// reused with their positions moved to where they are in the new source.
+pub fn reparse(program: &Program, old_source: &str, edit: &Edit) -> Result<Reparse, Vec<Error>> {
+    let name = &program.name[..];
+    let new_source = match edit.apply(old_source) {
+        Some(source) => source,
+        None => {
+            let msg = "edit is outside of the source";
+            return Err(vec![Error::new(msg, name, 1, 1)]);
+        }
+    };
+
+    let body = &program.body;
+    // Statements which end before the edit are kept as they are. One that
+    // ends right where the edit starts could be extended by it, and so could
+    // one without a semicolon.
+    let mut prefix = body.iter().take_while(|s| s.span.end < edit.start).count();
+    while prefix > 0 && !is_closed(&body[prefix - 1], old_source) {
+        prefix -= 1;
+    }
+    let region_start = if prefix > 0 {
+        body[prefix - 1].span.end
+    } else {
+        0
+    };
+
+    // The region that is parsed again always ends with a newline from the
+    // source that was not edited. That way a comment added by the edit can
+    // never run on into the statements that are reused. Only whitespace may
+    // come after that newline, otherwise it could be inside of a comment
+    // which the edit has broken.
+    let mut suffix = body.len();
+    let mut region_end = old_source.len();
+    for (i, stmt) in body.iter().enumerate().skip(prefix) {
+        let gap_start = match i {
+            0 => edit.end,
+            _ => edit.end.max(body[i - 1].span.end),
+        };
+        if gap_start >= stmt.span.start {
+            continue;
+        }
+        let gap = &old_source[gap_start..stmt.span.start];
+        if let Some(newline) = gap.rfind('\n') {
+            if gap[newline..].trim().is_empty() {
+                suffix = i;
+                region_end = gap_start + newline + 1;
+                break;
+            }
+        }
+    }
+
+    let delta = edit.text.len() as isize - (edit.end - edit.start) as isize;
+    let new_region_end = (region_end as isize + delta) as usize;
+    let (line, column) = line_and_column(&new_source[..], region_start);
+    let origin = Span::new(region_start, new_region_end, line, column);
+    // The new nodes are numbered after every node of the old program, so an
+    // id that is carried over can never end up meaning a different node.
+    let mut parser = Parser::new_at(name, &new_source[region_start..new_region_end], origin);
+    parser.next_id = program.next_id;
+    let region = match parser.parse_program() {
+        // The last statement of the region could carry on into the next one
+        // without a semicolon, like `a = b` followed by `(c)`.
+        Ok(region)
+            if suffix == body.len()
+                || region
+                    .body
+                    .last()
+                    .is_none_or(|s| is_closed(s, &new_source[..]))
+                || !old_source[body[suffix].span.start..].starts_with(&['(', '[', '-'][..]) =>
+        {
+            region
+        }
+        // The region might only be broken because it needs a statement from
+        // outside of it, like an else that now follows an if. Parsing the
+        // whole source settles that and reports the right errors if not.
+        _ => {
+            let mut parser = Parser::new(name, &new_source[..]);
+            parser.next_id = program.next_id;
+            let program = parser.parse_program()?;
+            let dirty = (0..program.body.len()).collect();
+            return Ok(Reparse { program, dirty });
+        }
+    };
+
+    let old_lines = newlines(&old_source[edit.start..edit.end]);
+    let mut shift = Shift {
+        offset: delta,
+        lines: newlines(&edit.text[..]) as i64 - old_lines as i64,
+    };
+    let mut dirty: Vec<usize> = (prefix..prefix + region.body.len()).collect();
+    let mut next_id = region.next_id;
+    let mut new_body = Vec::with_capacity(prefix + region.body.len() + body.len() - suffix);
+    new_body.extend(body[..prefix].iter().cloned());
+    new_body.extend(region.body);
+    for stmt in &body[suffix..] {
+        let mut stmt = stmt.clone();
+        shift.visit_stmt_mut(&mut stmt);
+        // The scanner turns `__line` into the number of the line it is on,
+        // so a statement using it is parsed again once it has moved to
+        // another line. On its own it parses the same as it did before.
+        if shift.lines != 0 && new_source[stmt.span.start..stmt.span.end].contains(LINE_NAME) {
+            let text = &new_source[stmt.span.start..stmt.span.end];
+            let mut parser = Parser::new_at(name, text, stmt.span);
+            parser.next_id = next_id;
+            let mut reparsed = parser.parse_program()?;
+            next_id = reparsed.next_id;
+            if reparsed.body.len() == 1 {
+                stmt = reparsed.body.remove(0);
+                dirty.push(new_body.len());
+            }
+        }
+        new_body.push(stmt);
+    }
+
+    Ok(Reparse {
+        program: Program {
+            name: program.name.clone(),
+            body: new_body,
+            next_id,
+        },
+        dirty,
+    })
+}
+
+// A statement is closed when nothing after it can become part of it. Only
+// statements that end in a semicolon or with a block are closed.
+fn is_closed(stmt: &Stmt, source: &str) -> bool {
+    match &stmt.kind {
+        StmtKind::Block(_)
+        | StmtKind::Function(_)
+        | StmtKind::Class(_)
+        | StmtKind::Match { .. }
+        | StmtKind::Try { .. } => true,
+        StmtKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } => is_closed(else_branch.as_ref().unwrap_or(then_branch), source),
+        StmtKind::While { body, .. } | StmtKind::For { body, .. } => is_closed(body, source),
+        StmtKind::Export(decl) => is_closed(decl, source),
+        _ => source[..stmt.span.end].ends_with(';'),
+    }
+}
+
+fn newlines(text: &str) -> usize {
+    text.bytes().filter(|&b| b == b'\n').count()
+}
+
+fn line_and_column(source: &str, offset: usize) -> (u32, u32) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = newlines(before) + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line as u32, column as u32)
+}
+
+// Moves every span in a statement that comes after an edit. The statement
+// starts on a line the edit did not touch so the columns stay the same. Ids
+// are left alone, the statement is still the same node it was before.
+struct Shift {
+    offset: isize,
+    lines: i64,
+}
+
+impl Shift {
+    fn span(&self, span: &mut Span) {
+        span.start = (span.start as isize + self.offset) as usize;
+        span.end = (span.end as isize + self.offset) as usize;
+        span.line = (span.line as i64 + self.lines) as u32;
+    }
+
+    fn label(&self, label: &mut Option<Identifier>) {
+        if let Some(label) = label {
+            self.span(&mut label.span);
+        }
+    }
+}
+
+impl VisitorMut for Shift {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        self.span(&mut stmt.span);
+        // Labels and a few other parts are not visited on their own.
+        match &mut stmt.kind {
+            StmtKind::While { label, .. }
+            | StmtKind::DoWhile { label, .. }
+            | StmtKind::For { label, .. }
+            | StmtKind::Break(label)
+            | StmtKind::Continue(label) => self.label(label),
+            StmtKind::Match { arms, .. } => {
+                for arm in arms {
+                    self.span(&mut arm.span);
+                }
+            }
+            StmtKind::Try {
+                catch: Some(catch), ..
+            } => self.span(&mut catch.span),
+            StmtKind::Import(decl) => {
+                self.span(&mut decl.span);
+                if let ImportSource::Module(module) = &mut decl.source {
+                    self.span(&mut module.span);
+                }
+            }
+            _ => {}
+        }
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        self.span(&mut expr.span);
+        match &mut expr.kind {
+            ExprKind::Call(call) => self.span(&mut call.span),
+            ExprKind::MapLiteral(entries) => {
+                for entry in entries {
+                    self.span(&mut entry.span);
+                }
+            }
+            _ => {}
+        }
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_function_mut(&mut self, decl: &mut FunctionDecl) {
+        self.span(&mut decl.span);
+        walk_function_mut(self, decl);
+    }
+
+    fn visit_class_mut(&mut self, decl: &mut ClassDecl) {
+        self.span(&mut decl.span);
+        walk_class_mut(self, decl);
+    }
+
+    fn visit_lambda_mut(&mut self, lambda: &mut Lambda) {
+        self.span(&mut lambda.span);
+        walk_lambda_mut(self, lambda);
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        self.span(&mut pattern.span);
+        if let PatternKind::Map(entries) = &mut pattern.kind {
+            for entry in entries {
+                self.span(&mut entry.span);
+                if let MapKey::Identifier(key) = &mut entry.key {
+                    self.span(&mut key.span);
+                }
+            }
+        }
+        walk_pattern_mut(self, pattern);
+    }
+
+    fn visit_type_mut(&mut self, ty: &mut TypeAnnotation) {
+        self.span(&mut ty.span);
+        walk_type_mut(self, ty);
+    }
+
+    fn visit_identifier_mut(&mut self, ident: &mut Identifier) {
+        self.span(&mut ident.span);
+    }
+}
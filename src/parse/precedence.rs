@@ -0,0 +1,103 @@
+use crate::ast::*;
+use crate::scan::*;
+
+// Every operator the parser understands is listed here along with how
+// tightly it binds. The parser, the tests and anything that prints code back
+// out all read from these tables so they can never disagree.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BinaryOperator {
+    pub token: TokenType,
+    pub op: BinaryOp,
+    pub precedence: u8,
+    pub associativity: Associativity,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct UnaryOperator {
+    pub token: TokenType,
+    pub op: UnaryOp,
+}
+
+// The loosest binding binary operator. A higher precedence binds tighter.
+pub const LOWEST_PRECEDENCE: u8 = 1;
+// Prefix operators bind tighter than any binary operator, so `-a * b` is
+// `(-a) * b`.
+pub const UNARY_PRECEDENCE: u8 = 10;
+
+const fn left(token: TokenType, op: BinaryOp, precedence: u8) -> BinaryOperator {
+    BinaryOperator {
+        token,
+        op,
+        precedence,
+        associativity: Associativity::Left,
+    }
+}
+
+pub const BINARY_OPERATORS: &[BinaryOperator] = &[
+    left(TokenType::Or, BinaryOp::Or, 1),
+    left(TokenType::And, BinaryOp::And, 2),
+    left(TokenType::Pipe, BinaryOp::BitOr, 3),
+    left(TokenType::Caret, BinaryOp::BitXor, 4),
+    left(TokenType::Ampersand, BinaryOp::BitAnd, 5),
+    left(TokenType::EqualsEquals, BinaryOp::Equal, 6),
+    left(TokenType::BangEquals, BinaryOp::NotEqual, 6),
+    left(TokenType::LessThan, BinaryOp::Less, 7),
+    left(TokenType::LessEquals, BinaryOp::LessEqual, 7),
+    left(TokenType::GreaterThan, BinaryOp::Greater, 7),
+    left(TokenType::GreaterEquals, BinaryOp::GreaterEqual, 7),
+    left(TokenType::Plus, BinaryOp::Add, 8),
+    left(TokenType::Minus, BinaryOp::Subtract, 8),
+    left(TokenType::Star, BinaryOp::Multiply, 9),
+    left(TokenType::Slash, BinaryOp::Divide, 9),
+    left(TokenType::Percent, BinaryOp::Modulo, 9),
+];
+
+pub const UNARY_OPERATORS: &[UnaryOperator] = &[
+    UnaryOperator {
+        token: TokenType::Minus,
+        op: UnaryOp::Negate,
+    },
+    UnaryOperator {
+        token: TokenType::Bang,
+        op: UnaryOp::Not,
+    },
+    UnaryOperator {
+        token: TokenType::Not,
+        op: UnaryOp::Not,
+    },
+    UnaryOperator {
+        token: TokenType::Tilde,
+        op: UnaryOp::BitNot,
+    },
+];
+
+pub fn binary_operator(token: TokenType) -> Option<&'static BinaryOperator> {
+    BINARY_OPERATORS.iter().find(|o| o.token == token)
+}
+
+pub fn unary_operator(token: TokenType) -> Option<&'static UnaryOperator> {
+    UNARY_OPERATORS.iter().find(|o| o.token == token)
+}
+
+pub fn precedence(op: BinaryOp) -> u8 {
+    // Every binary operator is in the table so this cannot fail.
+    BINARY_OPERATORS
+        .iter()
+        .find(|o| o.op == op)
+        .map(|o| o.precedence)
+        .unwrap()
+}
+
+pub fn associativity(op: BinaryOp) -> Associativity {
+    BINARY_OPERATORS
+        .iter()
+        .find(|o| o.op == op)
+        .map(|o| o.associativity)
+        .unwrap()
+}
@@ -0,0 +1,314 @@
+use crate::ast::*;
+use crate::compile::CompilerOptions;
+use crate::error::*;
+use crate::fold::*;
+use crate::link::*;
+use crate::parse::*;
+use crate::prune::*;
+use crate::resolve::*;
+use crate::typeck;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Component, Path, PathBuf};
+
+// Lines in a manifest starting with this character are ignored.
+const MANIFEST_COMMENT: char = '#';
+// Modules imported by name are found in a file with this extension.
+pub const MODULE_EXTENSION: &str = "at";
+
+pub struct CheckSummary {
+    files: Vec<String>,
+    diagnostics: Vec<Error>,
+}
+
+impl CheckSummary {
+    pub fn files(&self) -> &[String] {
+        &self.files[..]
+    }
+
+    pub fn diagnostics(&self) -> &[Error] {
+        &self.diagnostics[..]
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.diagnostics.iter().filter(|d| !d.is_warning()).count()
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.diagnostics.iter().filter(|d| d.is_warning()).count()
+    }
+
+    // Warnings are reported but do not make a check fail.
+    pub fn is_ok(&self) -> bool {
+        self.error_count() == 0
+    }
+}
+
+#[derive(Default)]
+pub struct Project {
+    entries: Vec<String>,
+    sources: HashMap<String, String>,
+    lints: Lints,
+    // Types are only checked when a mode has been set.
+    type_check: Option<typeck::Mode>,
+}
+
+impl Project {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_manifest(path: &str) -> Result<Self, Error> {
+        // A manifest is a plain list of entry points, one per line, written
+        // relative to the directory that the manifest lives in.
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                let msg = format!("unable to read manifest: {}", e);
+                return Err(Error::new(&msg[..], path, 0, 0));
+            }
+        };
+
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let mut project = Project::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(MANIFEST_COMMENT) {
+                continue;
+            }
+            project.add_entry(&dir.join(line).to_string_lossy()[..]);
+        }
+
+        Ok(project)
+    }
+
+    pub fn add_entry(&mut self, name: &str) {
+        if !self.entries.iter().any(|e| e == name) {
+            self.entries.push(String::from(name));
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries[..]
+    }
+
+    pub fn set_lints(&mut self, lints: Lints) {
+        self.lints = lints;
+    }
+
+    pub fn lints(&self) -> Lints {
+        self.lints
+    }
+
+    pub fn set_type_check(&mut self, mode: Option<typeck::Mode>) {
+        self.type_check = mode;
+    }
+
+    pub fn type_check(&self) -> Option<typeck::Mode> {
+        self.type_check
+    }
+
+    pub fn provide(&mut self, name: &str, source: &str) {
+        // Sources provided directly take priority over the file system. This
+        // lets editors check unsaved buffers and lets hosts check scripts
+        // that never existed on disk.
+        self.sources
+            .insert(String::from(name), String::from(source));
+    }
+
+    fn load(&self, name: &str) -> Option<String> {
+        match self.sources.get(name) {
+            Some(source) => Some(source.clone()),
+            None => std::fs::read_to_string(name).ok(),
+        }
+    }
+
+    pub fn check_all(&self) -> CheckSummary {
+        let mut files = Vec::new();
+        let mut diagnostics = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut missing: HashSet<String> = HashSet::new();
+        // What every module imports, for finding cycles once they are all
+        // parsed.
+        let mut graph: HashMap<String, Vec<(String, Span)>> = HashMap::new();
+
+        // Every module is paired with the place it was referenced from so a
+        // missing module can be reported where the mistake was made. Entry
+        // points are not referenced by anything.
+        let mut queue: VecDeque<(String, Option<(String, Span)>)> =
+            self.entries.iter().map(|e| (e.clone(), None)).collect();
+
+        while let Some((name, referenced_from)) = queue.pop_front() {
+            if seen.contains(&name) {
+                continue;
+            }
+
+            let source = match self.load(&name[..]) {
+                Some(source) => source,
+                None => {
+                    // A module that cannot be found is only reported once no
+                    // matter how many other modules depend on it. Otherwise one
+                    // typo in a file name buries the output in duplicates.
+                    if missing.insert(name.clone()) {
+                        let msg = format!("cannot find module {}", name);
+                        diagnostics.push(match referenced_from {
+                            Some((file, span)) => {
+                                Error::new(&msg[..], &file[..], span.line, span.column)
+                            }
+                            None => Error::new(&msg[..], &name[..], 0, 0),
+                        });
+                    }
+                    continue;
+                }
+            };
+
+            seen.insert(name.clone());
+            files.push(name.clone());
+            match parse_program(&name[..], &source[..]) {
+                Ok(program) => {
+                    diagnostics.extend(resolve_program_with(&program, self.lints).diagnostics);
+                    // Some errors are only found by compiling, like calls with
+                    // the wrong number of arguments, and they stop a build
+                    // just the same. The ones the resolver found come back
+                    // too, which the dedup below takes care of.
+                    if let Err(errors) = compile_module(&program, &CompilerOptions::default()) {
+                        diagnostics.extend(errors);
+                    }
+                    if let Some(mode) = self.type_check {
+                        diagnostics.extend(typeck::check_program(&program, mode).diagnostics);
+                    }
+                    // Dead code is found the same way the compiler finds it.
+                    // Code after a return is reported by the resolver as
+                    // well, which the dedup below takes care of.
+                    let mut pruned = program.clone();
+                    fold_constants(&mut pruned);
+                    diagnostics.extend(prune_dead_code(&mut pruned));
+                    let imports = dependencies(&program);
+                    for (dependency, span) in &imports {
+                        queue.push_back((dependency.clone(), Some((name.clone(), *span))));
+                    }
+                    graph.insert(name.clone(), imports);
+                }
+                Err(errors) => diagnostics.extend(errors),
+            }
+        }
+
+        // Cycles are found the same way linking finds them, following the
+        // imports from every entry point.
+        let mut done = HashSet::new();
+        for entry in &self.entries {
+            find_cycles(&graph, entry, &mut Vec::new(), &mut done, &mut diagnostics);
+        }
+
+        // Diagnostics are sorted so that the output is the same every time no
+        // matter which order the module graph was discovered in.
+        diagnostics.sort_by(|a, b| {
+            (a.file_name(), a.line(), a.column(), a.message()).cmp(&(
+                b.file_name(),
+                b.line(),
+                b.column(),
+                b.message(),
+            ))
+        });
+        diagnostics.dedup();
+
+        CheckSummary { files, diagnostics }
+    }
+
+    // Compiles the entry point and every module it imports, and links them
+    // into a bundle. Modules that can't be found are reported by linking,
+    // at the import that needs them.
+    pub fn build(&self, entry: &str, options: &CompilerOptions) -> Result<Bundle, Vec<Error>> {
+        let mut modules = Vec::new();
+        let mut errors = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut queue = vec![String::from(entry)];
+        while let Some(name) = queue.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let source = match self.load(&name[..]) {
+                Some(source) => source,
+                None => continue,
+            };
+            let module = parse_program(&name[..], &source[..])
+                .and_then(|program| compile_module(&program, options));
+            match module {
+                Ok(module) => {
+                    queue.extend(module.imports.iter().map(|i| i.path.clone()));
+                    modules.push(module);
+                }
+                Err(e) => errors.extend(e),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        link(modules, entry)
+    }
+}
+
+fn find_cycles(
+    graph: &HashMap<String, Vec<(String, Span)>>,
+    path: &str,
+    running: &mut Vec<String>,
+    done: &mut HashSet<String>,
+    diagnostics: &mut Vec<Error>,
+) {
+    let imports = match graph.get(path) {
+        Some(imports) => imports,
+        None => return,
+    };
+    running.push(String::from(path));
+    for (import, span) in imports {
+        if let Some(msg) = import_cycle(running, import) {
+            diagnostics.push(Error::new(&msg[..], path, span.line, span.column));
+        } else if !done.contains(import) {
+            find_cycles(graph, import, running, done, diagnostics);
+        }
+    }
+    running.pop();
+    done.insert(String::from(path));
+}
+
+fn dependencies(program: &Program) -> Vec<(String, Span)> {
+    program
+        .body
+        .iter()
+        .filter_map(|stmt| match &stmt.kind {
+            StmtKind::Import(ImportDecl {
+                source: ImportSource::Module(name),
+                ..
+            }) if HOST_MODULES.contains(&&name.name[..]) => None,
+            StmtKind::Import(decl) => Some((module_path(&program.name, &decl.source), decl.span)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Where the module that a file imports lives. Imports are found relative to
+// the directory of the file doing the importing, so a project can be moved
+// around without breaking them. Going up a directory is worked out here so
+// that a module has the same path however it is imported.
+pub fn module_path(importer: &str, source: &ImportSource) -> String {
+    let dir = Path::new(importer)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let file = match source {
+        ImportSource::Module(name) => format!("{}.{}", name.name, MODULE_EXTENSION),
+        ImportSource::File(path) => path.clone(),
+    };
+    let mut path = PathBuf::new();
+    for component in dir.join(file).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match path.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    path.pop();
+                }
+                _ => path.push(component),
+            },
+            _ => path.push(component),
+        }
+    }
+    path.to_string_lossy().into_owned()
+}
@@ -0,0 +1,227 @@
+use crate::ast::visit::*;
+use crate::ast::*;
+use crate::error::Error;
+
+// Pruning removes code that can never run, like the statements after a
+// return or the branch of an `if (false)`. It is meant to run after
+// constant folding, which is what turns conditions like `DEBUG_LEVEL > 2`
+// with a constant level into plain booleans. Each piece of code that is
+// removed is reported as a warning, since it is usually a mistake.
+//
+// Only code that is certain never to run is removed. A loop like
+// `while (true)` counts as never ending unless a break can leave it, but
+// anything that depends on what values turn up at runtime is left alone.
+pub fn prune_dead_code(program: &mut Program) -> Vec<Error> {
+    let mut pruner = Pruner {
+        file: program.name.clone(),
+        warnings: Vec::new(),
+    };
+    pruner.visit_program_mut(program);
+    pruner.warnings
+}
+
+struct Pruner {
+    file: String,
+    warnings: Vec<Error>,
+}
+
+impl Pruner {
+    fn warn(&mut self, span: Span) {
+        self.warnings.push(Error::warning(
+            "unreachable code",
+            &self.file[..],
+            span.line,
+            span.column,
+        ));
+    }
+
+    // Drops everything after the first statement that always jumps away.
+    // Only the first statement dropped is reported since the rest follow
+    // from it.
+    fn body(&mut self, body: &mut Vec<Stmt>) {
+        if let Some(jump) = body.iter().position(jumps_away) {
+            if let Some(next) = body.get(jump + 1) {
+                self.warn(next.span);
+                body.truncate(jump + 1);
+            }
+        }
+    }
+}
+
+impl VisitorMut for Pruner {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+        self.body(&mut program.body);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        // Nested statements are pruned first so that a branch which has
+        // been cut down to a return is known to jump away.
+        walk_stmt_mut(self, stmt);
+
+        let kept = match &mut stmt.kind {
+            StmtKind::Block(body) => {
+                self.body(body);
+                None
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => match condition.kind {
+                ExprKind::Bool(true) => {
+                    if let Some(else_branch) = else_branch {
+                        self.warn(else_branch.span);
+                    }
+                    Some(Some(take(then_branch)))
+                }
+                ExprKind::Bool(false) => {
+                    self.warn(then_branch.span);
+                    Some(else_branch.as_mut().map(|b| take(b)))
+                }
+                _ => None,
+            },
+            StmtKind::While {
+                condition:
+                    Expr {
+                        kind: ExprKind::Bool(false),
+                        ..
+                    },
+                body,
+                ..
+            } => {
+                self.warn(body.span);
+                Some(None)
+            }
+            StmtKind::Match { arms, .. } => {
+                for arm in arms {
+                    self.body(&mut arm.body);
+                }
+                None
+            }
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.body(body);
+                if let Some(catch) = catch {
+                    self.body(&mut catch.body);
+                }
+                if let Some(finally) = finally {
+                    self.body(finally);
+                }
+                None
+            }
+            _ => None,
+        };
+
+        // A branch that is kept stays in a block of its own, so that a
+        // declaration in it doesn't end up in the enclosing scope.
+        if let Some(kept) = kept {
+            let span = stmt.span;
+            *stmt = match kept {
+                Some(branch) if matches!(branch.kind, StmtKind::Block(_)) => branch,
+                Some(branch) => Stmt::new(StmtKind::Block(vec![branch]), span),
+                None => Stmt::new(StmtKind::Block(Vec::new()), span),
+            };
+        }
+    }
+
+    fn visit_function_mut(&mut self, decl: &mut FunctionDecl) {
+        walk_function_mut(self, decl);
+        self.body(&mut decl.body);
+    }
+
+    fn visit_lambda_mut(&mut self, lambda: &mut Lambda) {
+        walk_lambda_mut(self, lambda);
+        self.body(&mut lambda.body);
+    }
+}
+
+fn take(stmt: &mut Stmt) -> Stmt {
+    std::mem::replace(stmt, Stmt::new(StmtKind::Block(Vec::new()), stmt.span))
+}
+
+// Whether running the statement can never carry on to the one after it.
+fn jumps_away(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Return(_) | StmtKind::Throw(_) | StmtKind::Break(_) | StmtKind::Continue(_) => {
+            true
+        }
+        StmtKind::Block(body) => body.iter().any(jumps_away),
+        StmtKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => jumps_away(then_branch) && jumps_away(else_branch),
+        StmtKind::Match { arms, .. } => {
+            arms.iter().any(|arm| arm.is_default())
+                && arms.iter().all(|arm| arm.body.iter().any(jumps_away))
+        }
+        StmtKind::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            finally.as_ref().is_some_and(|f| f.iter().any(jumps_away))
+                || (body.iter().any(jumps_away)
+                    && catch.as_ref().is_none_or(|c| c.body.iter().any(jumps_away)))
+        }
+        StmtKind::While {
+            label,
+            condition:
+                Expr {
+                    kind: ExprKind::Bool(true),
+                    ..
+                },
+            body,
+        }
+        | StmtKind::DoWhile {
+            label,
+            body,
+            condition:
+                Expr {
+                    kind: ExprKind::Bool(true),
+                    ..
+                },
+        } => !breaks_out(body, label.as_ref(), false),
+        StmtKind::Export(decl) => jumps_away(decl),
+        _ => false,
+    }
+}
+
+// Whether a break in the statement leaves the loop it is the body of.
+// Inside a nested loop only a break naming the loop's label does.
+fn breaks_out(stmt: &Stmt, label: Option<&Identifier>, nested: bool) -> bool {
+    let any = |body: &[Stmt], nested| body.iter().any(|s| breaks_out(s, label, nested));
+    match &stmt.kind {
+        StmtKind::Break(None) => !nested,
+        StmtKind::Break(Some(target)) => label.is_some_and(|l| l.name == target.name),
+        StmtKind::Block(body) => any(body, nested),
+        StmtKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            breaks_out(then_branch, label, nested)
+                || else_branch
+                    .as_ref()
+                    .is_some_and(|b| breaks_out(b, label, nested))
+        }
+        StmtKind::While { body, .. }
+        | StmtKind::DoWhile { body, .. }
+        | StmtKind::For { body, .. } => breaks_out(body, label, true),
+        StmtKind::Match { arms, .. } => arms.iter().any(|arm| any(&arm.body, nested)),
+        StmtKind::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            any(body, nested)
+                || catch.as_ref().is_some_and(|c| any(&c.body, nested))
+                || finally.as_ref().is_some_and(|f| any(f, nested))
+        }
+        _ => false,
+    }
+}
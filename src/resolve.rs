@@ -0,0 +1,524 @@
+use crate::ast::visit::*;
+use crate::ast::*;
+use crate::bytecode::format_number;
+use crate::error::*;
+
+// The resolver works out which declaration every name refers to by
+// following the same scoping rules the runtime uses. Along the way it
+// notices code that is legal but almost certainly a mistake and reports it
+// as a warning, which never stops a script from running. Assigning to a
+// constant and declaring a name twice in the same block are reported as
+// errors, which the compiler reports too.
+pub fn resolve(program: &Program) -> Vec<Error> {
+    resolve_program(program).diagnostics
+}
+
+// What the resolver found out about a program. Bindings map every variable
+// expression that refers to a local to the identifier that declared it.
+// Names that are not found are globals, which are looked up at runtime.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Resolution {
+    pub bindings: NodeMap<NodeId>,
+    pub diagnostics: Vec<Error>,
+}
+
+// Lints are extra warnings that are off unless asked for, since they flag
+// code that is often written that way on purpose.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Lints {
+    // Warn when a declaration hides a parameter or a variable from an
+    // enclosing scope.
+    pub shadowing: bool,
+}
+
+pub fn resolve_program(program: &Program) -> Resolution {
+    resolve_program_with(program, Lints::default())
+}
+
+pub fn resolve_program_with(program: &Program, lints: Lints) -> Resolution {
+    let mut resolver = Resolver {
+        file: &program.name[..],
+        lints,
+        scopes: Vec::new(),
+        constants: Vec::new(),
+        declaring: false,
+        resolution: Resolution::default(),
+    };
+    // Top level constants are collected up front since a function declared
+    // before one can still assign to it.
+    for stmt in &program.body {
+        let stmt = match &stmt.kind {
+            StmtKind::Export(decl) => decl,
+            _ => stmt,
+        };
+        match &stmt.kind {
+            StmtKind::Var {
+                name,
+                constant: true,
+                ..
+            } => resolver.constants.push(name.name.clone()),
+            StmtKind::Destructure {
+                pattern,
+                constant: true,
+                ..
+            } => {
+                for name in pattern.names() {
+                    resolver.constants.push(name.name.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    resolver.body(&program.body);
+    resolver.resolution
+}
+
+struct Local {
+    id: NodeId,
+    name: String,
+    span: Span,
+    read: bool,
+    constant: bool,
+    parameter: bool,
+}
+
+struct Resolver<'a> {
+    file: &'a str,
+    lints: Lints,
+    // Top level declarations are globals which can be used by other
+    // modules and by the host, so only names inside of blocks and functions
+    // are tracked here.
+    scopes: Vec<Vec<Local>>,
+    // The names of the constants declared at the top level.
+    constants: Vec<String>,
+    // Set while the names in a destructuring declaration are visited, since
+    // those are being declared rather than assigned to.
+    declaring: bool,
+    resolution: Resolution,
+}
+
+impl Resolver<'_> {
+    fn warn(&mut self, msg: &str, span: Span) {
+        self.resolution
+            .diagnostics
+            .push(Error::warning(msg, self.file, span.line, span.column));
+    }
+
+    fn error(&mut self, msg: &str, span: Span) {
+        self.resolution
+            .diagnostics
+            .push(Error::new(msg, self.file, span.line, span.column));
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        for local in scope {
+            // Starting a name with an underscore is the usual way to say
+            // that it is unused on purpose.
+            if !local.read && !local.name.starts_with('_') {
+                let msg = format!("unused variable {}", local.name);
+                self.warn(&msg[..], local.span);
+            }
+        }
+    }
+
+    // A block can only declare a name once, and the parameters of a function
+    // are in the same block as its body.
+    fn declare(&mut self, name: &Identifier, read: bool) {
+        if self.lints.shadowing {
+            self.check_shadowing(name);
+        }
+        let earlier = self
+            .scopes
+            .last()
+            .and_then(|scope| scope.iter().find(|l| l.name == name.name))
+            .map(|l| l.span);
+        if let Some(earlier) = earlier {
+            let msg = format!("{} is already declared in this block", name.name);
+            let related = format!("{} is declared here", name.name);
+            let error = Error::new(&msg[..], self.file, name.span.line, name.span.column)
+                .with_related(&related[..], earlier.line, earlier.column);
+            self.resolution.diagnostics.push(error);
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Local {
+                id: name.id,
+                name: name.name.clone(),
+                span: name.span,
+                read,
+                constant: false,
+                parameter: false,
+            });
+        }
+    }
+
+    fn declare_parameter(&mut self, name: &Identifier) {
+        // Parameters are often required by the caller even when they are
+        // not needed, like a callback that ignores its arguments.
+        self.declare(name, true);
+        if let Some(local) = self.scopes.last_mut().and_then(|s| s.last_mut()) {
+            local.parameter = true;
+        }
+    }
+
+    fn declare_constant(&mut self, name: &Identifier) {
+        self.declare(name, false);
+        if let Some(local) = self.scopes.last_mut().and_then(|s| s.last_mut()) {
+            local.constant = true;
+        }
+    }
+
+    fn check_shadowing(&mut self, name: &Identifier) {
+        // Declaring a name again in the same scope is an error, which is
+        // reported on its own.
+        let outer = match self.scopes.split_last() {
+            Some((_, outer)) => outer,
+            None => return,
+        };
+        let shadowed = outer
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().rev().find(|l| l.name == name.name));
+
+        if let Some(local) = shadowed {
+            let what = if local.parameter {
+                "a parameter"
+            } else {
+                "an outer variable"
+            };
+            let msg = format!("declaration of {} shadows {}", name.name, what);
+            let related = format!("{} is declared here", name.name);
+            let warning = Error::warning(&msg[..], self.file, name.span.line, name.span.column)
+                .with_related(&related[..], local.span.line, local.span.column);
+            self.resolution.diagnostics.push(warning);
+        }
+    }
+
+    // Finds the local a name refers to and says if it is a constant. Names
+    // which are not locals are globals, which are constant when they were
+    // declared with const at the top level.
+    fn lookup(&mut self, id: NodeId, name: &str, read: bool) -> bool {
+        // Later declarations shadow earlier ones, even in the same scope, so
+        // search from the innermost and most recent name outwards.
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(local) = scope.iter_mut().rev().find(|l| l.name == name) {
+                local.read |= read;
+                self.resolution.bindings.insert(id, local.id);
+                return local.constant;
+            }
+        }
+        self.constants.iter().any(|c| c == name)
+    }
+
+    fn assign(&mut self, id: NodeId, name: &str, span: Span, read: bool) {
+        if self.lookup(id, name, read) {
+            let msg = format!("cannot assign to constant {}", name);
+            self.error(&msg[..], span);
+        }
+    }
+
+    // An arm with a value that an earlier arm already matched can never run
+    // for that value. A match on a bool without a default should handle
+    // both true and false, since any other subject would be a mistake.
+    fn check_match(&mut self, subject: &Expr, arms: &[MatchArm], span: Span) {
+        let mut seen: Vec<(Literal, Span)> = Vec::new();
+        for value in arms.iter().flat_map(|arm| &arm.values) {
+            let literal = match Literal::from_expr(value) {
+                Some(literal) => literal,
+                None => continue,
+            };
+            match seen.iter().find(|(l, _)| *l == literal) {
+                Some((_, first)) => {
+                    let msg = format!("duplicate match case {}", literal);
+                    let warning =
+                        Error::warning(&msg[..], self.file, value.span.line, value.span.column)
+                            .with_related("first matched here", first.line, first.column);
+                    self.resolution.diagnostics.push(warning);
+                }
+                None => seen.push((literal, value.span)),
+            }
+        }
+
+        let is_bool = is_bool(subject)
+            || (!seen.is_empty() && seen.iter().all(|(l, _)| matches!(l, Literal::Bool(_))));
+        if !is_bool || arms.iter().any(|arm| arm.is_default()) {
+            return;
+        }
+        for case in [true, false] {
+            if !seen.iter().any(|(l, _)| *l == Literal::Bool(case)) {
+                let msg = format!("match is missing a case for {}", case);
+                self.warn(&msg[..], span);
+            }
+        }
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        // Anything after a statement that always jumps away can never run.
+        // Only the first unreachable statement is reported since the rest
+        // follow from it.
+        if let Some(jump) = body.iter().position(|s| {
+            matches!(
+                s.kind,
+                StmtKind::Return(_)
+                    | StmtKind::Throw(_)
+                    | StmtKind::Break(_)
+                    | StmtKind::Continue(_)
+            )
+        }) {
+            if let Some(next) = body.get(jump + 1) {
+                self.warn("unreachable code", next.span);
+            }
+        }
+
+        for stmt in body {
+            self.visit_stmt(stmt);
+        }
+    }
+
+    fn function(
+        &mut self,
+        params: &[Identifier],
+        defaults: &[Expr],
+        rest: &Option<Identifier>,
+        body: &[Stmt],
+    ) {
+        self.begin_scope();
+        for param in params {
+            self.declare_parameter(param);
+        }
+        for default in defaults {
+            self.visit_expr(default);
+        }
+        if let Some(rest) = rest {
+            self.declare_parameter(rest);
+        }
+        self.body(body);
+        self.end_scope();
+    }
+}
+
+impl Visitor for Resolver<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Var {
+                name,
+                init,
+                constant,
+                ..
+            } => {
+                // The initializer is resolved before the name exists so that
+                // `var x = x;` refers to an outer x.
+                if let Some(init) = init {
+                    self.visit_expr(init);
+                }
+                if *constant {
+                    self.declare_constant(name);
+                } else {
+                    self.declare(name, false);
+                }
+            }
+            StmtKind::Destructure {
+                pattern,
+                init,
+                constant,
+            } => {
+                self.visit_expr(init);
+                self.declaring = true;
+                self.visit_pattern(pattern);
+                self.declaring = false;
+                for name in pattern.names() {
+                    if *constant {
+                        self.declare_constant(name);
+                    } else {
+                        self.declare(name, false);
+                    }
+                }
+            }
+            StmtKind::Block(body) => {
+                self.begin_scope();
+                self.body(body);
+                self.end_scope();
+            }
+            StmtKind::Match { subject, arms } => {
+                self.visit_expr(subject);
+                self.check_match(subject, arms, stmt.span);
+                for arm in arms {
+                    self.begin_scope();
+                    self.body(&arm.body);
+                    self.end_scope();
+                }
+            }
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.begin_scope();
+                self.body(body);
+                self.end_scope();
+                if let Some(catch) = catch {
+                    self.begin_scope();
+                    // Like parameters, the binding is needed to catch the
+                    // error even when the handler ignores it.
+                    if let Some(binding) = &catch.binding {
+                        self.declare(binding, true);
+                    }
+                    self.body(&catch.body);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.body(finally);
+                    self.end_scope();
+                }
+            }
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.visit_expr(iterable);
+                self.begin_scope();
+                self.declare(variable, true);
+                self.visit_stmt(body);
+                self.end_scope();
+            }
+            // Functions and classes are declared up front so that they can
+            // refer to themselves. They are never reported as unused since
+            // they are usually there to be found by name.
+            StmtKind::Function(decl) => {
+                self.declare(&decl.name, true);
+                self.visit_function(decl);
+            }
+            StmtKind::Class(decl) => {
+                self.declare(&decl.name, true);
+                self.visit_class(decl);
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Variable(name) => {
+                self.lookup(expr.id, &name[..], true);
+            }
+            // Storing into a variable does not count as using it, unless the
+            // old value is read first by a compound assignment.
+            ExprKind::Assign { op, target, value } => match &target.kind {
+                ExprKind::Variable(name) => {
+                    self.assign(target.id, &name[..], target.span, op.is_some());
+                    self.visit_expr(value);
+                }
+                _ => walk_expr(self, expr),
+            },
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        // Names in a pattern are stored into, which does not count as using
+        // them. Targets like `a.b` still read `a`.
+        if let PatternKind::Name(name) = &pattern.kind {
+            if !self.declaring {
+                self.assign(pattern.id, &name.name[..], name.span, false);
+            }
+            return;
+        }
+        walk_pattern(self, pattern);
+    }
+
+    fn visit_function(&mut self, decl: &FunctionDecl) {
+        for decorator in &decl.decorators {
+            self.visit_expr(decorator);
+        }
+        self.function(&decl.params, &decl.defaults, &decl.rest, &decl.body);
+    }
+
+    fn visit_lambda(&mut self, lambda: &Lambda) {
+        self.function(&lambda.params, &lambda.defaults, &lambda.rest, &lambda.body);
+    }
+}
+
+// The value of a match case, which the parser makes sure is a literal.
+enum Literal {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Null,
+}
+
+impl Literal {
+    fn from_expr(expr: &Expr) -> Option<Self> {
+        match &expr.kind {
+            ExprKind::Int(n) => Some(Literal::Int(*n)),
+            ExprKind::Float(n) => Some(Literal::Float(*n)),
+            ExprKind::String(s) => Some(Literal::String(s.clone())),
+            ExprKind::Bool(b) => Some(Literal::Bool(*b)),
+            ExprKind::Null => Some(Literal::Null),
+            ExprKind::Unary {
+                op: UnaryOp::Negate,
+                operand,
+            } => match operand.kind {
+                ExprKind::Int(n) => n.checked_neg().map(Literal::Int),
+                ExprKind::Float(n) => Some(Literal::Float(-n)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// Ints and floats are the same case when they are the same number, the same
+// as they are equal at runtime.
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Literal::Int(a), Literal::Int(b)) => a == b,
+            (Literal::Int(a), Literal::Float(b)) | (Literal::Float(b), Literal::Int(a)) => {
+                *a as f64 == *b
+            }
+            (Literal::Float(a), Literal::Float(b)) => a == b,
+            (Literal::String(a), Literal::String(b)) => a == b,
+            (Literal::Bool(a), Literal::Bool(b)) => a == b,
+            (Literal::Null, Literal::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Int(n) => write!(f, "{}", n),
+            Literal::Float(n) => write!(f, "{}", format_number(*n)),
+            Literal::String(s) => write!(f, "\"{}\"", s),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Null => write!(f, "null"),
+        }
+    }
+}
+
+// Whether an expression always gives back a bool when it runs.
+fn is_bool(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Bool(_) => true,
+        ExprKind::Unary { op, .. } => *op == UnaryOp::Not,
+        ExprKind::Binary { op, .. } => matches!(
+            op,
+            BinaryOp::Equal
+                | BinaryOp::NotEqual
+                | BinaryOp::Less
+                | BinaryOp::LessEqual
+                | BinaryOp::Greater
+                | BinaryOp::GreaterEqual
+        ),
+        _ => false,
+    }
+}
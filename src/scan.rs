@@ -3,10 +3,16 @@ use crate::error::*;
 #[derive(Copy, Clone, PartialEq, Eq, std::fmt::Debug)]
 pub enum TokenType {
     Semicolon,
+    Colon,
+    Question,
     Comma,
     Equals,
+    EqualsEquals,
+    BangEquals,
     GreaterThan,
+    GreaterEquals,
     LessThan,
+    LessEquals,
     Plus,
     Minus,
     Star,
@@ -17,7 +23,19 @@ pub enum TokenType {
     Caret,
     Percent,
     Tilde,
+    At,
+    PlusEquals,
+    MinusEquals,
+    StarEquals,
+    SlashEquals,
+    PercentEquals,
+    AmpersandEquals,
+    PipeEquals,
+    CaretEquals,
     Dot,
+    DotDot,
+    DotDotEquals,
+    Ellipsis,
     LeftParen,
     RightParen,
     LeftBracket,
@@ -33,6 +51,7 @@ pub enum TokenType {
     NullLiteral,
     ThisLiteral,
     SuperLiteral,
+    FunctionNameLiteral,
     And,
     Or,
     Not,
@@ -48,7 +67,19 @@ pub enum TokenType {
     Break,
     Continue,
     Return,
+    Yield,
     Var,
+    Const,
+    Import,
+    Export,
+    As,
+    Match,
+    Case,
+    Default,
+    Try,
+    Catch,
+    Finally,
+    Throw,
 }
 
 pub struct Token {
@@ -116,6 +147,13 @@ impl<'a> Scanner<'a> {
         self.src = source.chars().peekable();
     }
 
+    pub(crate) fn start_at(&mut self, line: u32, column: u32) {
+        // Used when the source being scanned is a piece cut out of a larger
+        // file, so that positions are still reported relative to that file.
+        self.src_ln = line;
+        self.src_col = column;
+    }
+
     pub fn source_name(&self) -> &str {
         &self.src_name[..]
     }
@@ -132,6 +170,14 @@ impl<'a> Scanner<'a> {
         self.src.peek()
     }
 
+    // Looks one character further ahead than peek, for the few operators that
+    // need to see two characters past the one that was just popped.
+    fn peek_second(&self) -> Option<char> {
+        let mut src = self.src.clone();
+        src.next();
+        src.next()
+    }
+
     fn pop(&mut self) -> Option<char> {
         let c = self.src.next();
         // Keep track of which column we are on for accurate debug
@@ -147,11 +193,11 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_identifier_character(ch: char, is_first: bool) -> bool {
-        (is_first && (ch.is_alphabetic() || ch == '_')) || ch.is_alphanumeric() || ch == '_'
+        ch == '_' || ch.is_alphanumeric() || (is_first && ch.is_alphabetic())
     }
 
     fn str_to_keyword(s: &str) -> Option<TokenType> {
-        return match s {
+        match s {
             "true" => Some(TokenType::TrueLiteral),
             "false" => Some(TokenType::FalseLiteral),
             "null" => Some(TokenType::NullLiteral),
@@ -172,15 +218,29 @@ impl<'a> Scanner<'a> {
             "break" => Some(TokenType::Break),
             "continue" => Some(TokenType::Continue),
             "return" => Some(TokenType::Return),
+            "yield" => Some(TokenType::Yield),
             "var" => Some(TokenType::Var),
+            "const" => Some(TokenType::Const),
+            "import" => Some(TokenType::Import),
+            "export" => Some(TokenType::Export),
+            "as" => Some(TokenType::As),
+            "match" => Some(TokenType::Match),
+            "case" => Some(TokenType::Case),
+            "default" => Some(TokenType::Default),
+            "try" => Some(TokenType::Try),
+            "catch" => Some(TokenType::Catch),
+            "finally" => Some(TokenType::Finally),
+            "throw" => Some(TokenType::Throw),
             _ => None,
-        };
+        }
     }
 
     fn operator(&self, op: char) -> Option<Token> {
         let operator = match op {
             ',' => Some(TokenType::Comma),
             ';' => Some(TokenType::Semicolon),
+            ':' => Some(TokenType::Colon),
+            '?' => Some(TokenType::Question),
             '=' => Some(TokenType::Equals),
             '>' => Some(TokenType::GreaterThan),
             '<' => Some(TokenType::LessThan),
@@ -194,6 +254,7 @@ impl<'a> Scanner<'a> {
             '^' => Some(TokenType::Caret),
             '%' => Some(TokenType::Percent),
             '~' => Some(TokenType::Tilde),
+            '@' => Some(TokenType::At),
             '.' => Some(TokenType::Dot),
             '(' => Some(TokenType::LeftParen),
             ')' => Some(TokenType::RightParen),
@@ -206,14 +267,12 @@ impl<'a> Scanner<'a> {
 
         // Handle the case that a bad character was passed
         // in that is not an operator.
-        if operator.is_none() {
-            return None;
-        }
+        let operator = operator?;
 
         // Create an object filled with data describing the operator
         // that was found.
         Some(Token::new(
-            operator.unwrap(),
+            operator,
             &self.src_name[..],
             self.src_ln,
             self.src_col,
@@ -221,6 +280,17 @@ impl<'a> Scanner<'a> {
         ))
     }
 
+    fn compound_operator(&mut self, token: TokenType, op: &str) -> Token {
+        // The first character of the operator has already been popped, so
+        // that is where the operator starts. The rest still needs to be consumed.
+        let start_column = self.src_col;
+        for _ in 1..op.chars().count() {
+            self.pop();
+        }
+
+        Token::new(token, &self.src_name[..], self.src_ln, start_column, op)
+    }
+
     fn consume_whitespace(&mut self) {
         while let Some(&c) = self.peek() {
             if !c.is_whitespace() {
@@ -279,7 +349,9 @@ impl<'a> Scanner<'a> {
                     }
                 }
                 // We did not see one of the comment start tokens. Which
-                // means that we found the single slash which is the slash operator.
+                // means that we found the slash operator, possibly as part of
+                // a compound assignment.
+                Some('=') => return Some(Ok(self.compound_operator(TokenType::SlashEquals, "/="))),
                 Some(_) => {
                     return Some(Ok(Token::new(
                         TokenType::Slash,
@@ -302,7 +374,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn consume_number(&mut self, starting: char) -> Token {
-        let mut dot = if starting == '.' { true } else { false };
+        let mut dot = starting == '.';
         let mut buffer = String::from(starting);
         // Remember where the number started for debug tracking purposes.
         let start_column = self.src_col;
@@ -313,7 +385,9 @@ impl<'a> Scanner<'a> {
             if c.is_ascii_digit() {
                 buffer.push(c);
                 self.pop();
-            } else if c == '.' && !dot {
+            } else if c == '.' && !dot && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+                // The dot has to be followed by a digit, otherwise it is the
+                // start of a range like `1..2`.
                 buffer.push(c);
                 dot = true;
                 self.pop();
@@ -334,6 +408,9 @@ impl<'a> Scanner<'a> {
 
     fn consume_string(&mut self, starting: char) -> Result<Token, Error> {
         let mut buffer = String::new();
+        // Strings can span several lines so the starting line needs to be
+        // remembered as well as the column.
+        let start_line = self.src_ln;
         let start_column = self.src_col;
 
         loop {
@@ -354,7 +431,7 @@ impl<'a> Scanner<'a> {
                     return Ok(Token::new(
                         string_type,
                         &self.src_name[..],
-                        self.src_ln,
+                        start_line,
                         start_column,
                         &buffer[..],
                     ));
@@ -422,27 +499,63 @@ impl<'a> Scanner<'a> {
             buffer.push(self.pop().unwrap());
         }
 
+        // The magic source location constants are resolved right here because
+        // the scanner is the one that knows exactly where in the source we are.
+        // This means logging helpers get the location for free at runtime.
+        match &buffer[..] {
+            "__file" => {
+                return Token::new(
+                    TokenType::StringLiteral,
+                    &self.src_name[..],
+                    self.src_ln,
+                    start_column,
+                    &self.src_name[..],
+                )
+            }
+            "__line" => {
+                return Token::new(
+                    TokenType::NumberLiteral,
+                    &self.src_name[..],
+                    self.src_ln,
+                    start_column,
+                    &self.src_ln.to_string()[..],
+                )
+            }
+            // The scanner has no idea which function it is inside of, so the
+            // function name is left for the parser to fill in.
+            "__function" => {
+                return Token::new(
+                    TokenType::FunctionNameLiteral,
+                    &self.src_name[..],
+                    self.src_ln,
+                    start_column,
+                    &buffer[..],
+                )
+            }
+            _ => {}
+        }
+
         // Check to see if the identifier we found is actually a keyword.
         if let Some(tok_type) = Scanner::str_to_keyword(&buffer[..]) {
             // The function returned a token type which means that it found a
             // keyword from the language.
-            return Token::new(
+            Token::new(
                 tok_type,
                 &self.src_name[..],
                 self.src_ln,
                 start_column,
                 &buffer[..],
-            );
+            )
         } else {
             // If no token type was returned, that means the identifier is not
             // a keyword and we can use it as an identifier.
-            return Token::new(
+            Token::new(
                 TokenType::Identifier,
                 &self.src_name[..],
                 self.src_ln,
                 start_column,
                 &buffer[..],
-            );
+            )
         }
     }
 }
@@ -458,12 +571,49 @@ impl Iterator for Scanner<'_> {
             return Some(token);
         }
 
-        return match self.pop() {
+        match self.pop() {
             // The unwraps here must be safe because we are passing in a character literal
             // and we know that those literals will always match in this case. If the operator
             // function is called from somewhere else, this may not be the case.
             Some(';') => Some(Ok(self.operator(';').unwrap())),
+            Some(':') => Some(Ok(self.operator(':').unwrap())),
+            Some('?') => Some(Ok(self.operator('?').unwrap())),
             Some(',') => Some(Ok(self.operator(',').unwrap())),
+            // Operators that are two characters long need to be checked before
+            // their single character prefix or they would never be matched.
+            Some('=') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::EqualsEquals, "==")))
+            }
+            Some('!') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::BangEquals, "!=")))
+            }
+            Some('<') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::LessEquals, "<=")))
+            }
+            Some('>') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::GreaterEquals, ">=")))
+            }
+            Some('+') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::PlusEquals, "+=")))
+            }
+            Some('-') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::MinusEquals, "-=")))
+            }
+            Some('*') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::StarEquals, "*=")))
+            }
+            Some('%') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::PercentEquals, "%=")))
+            }
+            Some('&') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::AmpersandEquals, "&=")))
+            }
+            Some('|') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::PipeEquals, "|=")))
+            }
+            Some('^') if self.peek() == Some(&'=') => {
+                Some(Ok(self.compound_operator(TokenType::CaretEquals, "^=")))
+            }
             Some('=') => Some(Ok(self.operator('=').unwrap())),
             Some('<') => Some(Ok(self.operator('<').unwrap())),
             Some('>') => Some(Ok(self.operator('>').unwrap())),
@@ -477,12 +627,22 @@ impl Iterator for Scanner<'_> {
             Some('^') => Some(Ok(self.operator('^').unwrap())),
             Some('%') => Some(Ok(self.operator('%').unwrap())),
             Some('~') => Some(Ok(self.operator('~').unwrap())),
+            Some('@') => Some(Ok(self.operator('@').unwrap())),
             Some('(') => Some(Ok(self.operator('(').unwrap())),
             Some(')') => Some(Ok(self.operator(')').unwrap())),
             Some('[') => Some(Ok(self.operator('[').unwrap())),
             Some(']') => Some(Ok(self.operator(']').unwrap())),
             Some('{') => Some(Ok(self.operator('{').unwrap())),
             Some('}') => Some(Ok(self.operator('}').unwrap())),
+            Some('.') if self.peek() == Some(&'.') && self.peek_second() == Some('.') => {
+                Some(Ok(self.compound_operator(TokenType::Ellipsis, "...")))
+            }
+            Some('.') if self.peek() == Some(&'.') && self.peek_second() == Some('=') => {
+                Some(Ok(self.compound_operator(TokenType::DotDotEquals, "..=")))
+            }
+            Some('.') if self.peek() == Some(&'.') => {
+                Some(Ok(self.compound_operator(TokenType::DotDot, "..")))
+            }
             Some('.') if !self.peek().unwrap_or(&'\0').is_ascii_digit() => Some(Ok(self.operator('.').unwrap())),
             // Handle strings which can start with a single quote or a double quote.
             Some('\'') => Some(self.consume_string('\'')),
@@ -501,14 +661,14 @@ impl Iterator for Scanner<'_> {
             Some(c) => {
                 // If we made it this far then we where unable to determine
                 // what the token was and we will report the error.
-                return Some(Err(Error::new(
+                Some(Err(Error::new(
                     &format!("unexpected {} token", c)[..],
                     &self.src_name[..],
                     self.src_ln,
                     self.src_col,
-                )));
+                )))
             }
             None => None,
-        };
+        }
     }
 }
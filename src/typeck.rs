@@ -0,0 +1,867 @@
+use crate::ast::*;
+use crate::error::*;
+use std::collections::HashMap;
+use std::fmt;
+
+// The type checker is opt in twice over. It only runs when asked for, and
+// even then it leaves unannotated code alone. Operators and calls are only
+// checked inside of functions that have a type annotation somewhere in their
+// signature, where the types of unannotated locals are worked out from the
+// values they start with. Anywhere else only the values given to annotated
+// variables are checked. Everything the checker cannot be sure about is
+// `any`, which goes with every other type.
+pub fn check(program: &Program) -> Vec<Error> {
+    check_program(program, Mode::Lenient).diagnostics
+}
+
+// Mismatches are warnings unless the checker is strict, which makes them
+// errors so that a project with a type mistake fails to check.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Lenient,
+    Strict,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Type {
+    Any,
+    Null,
+    Bool,
+    Number,
+    String,
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    // A plain `function` annotation says nothing about the signature.
+    Function(Option<Box<Signature>>),
+    // The class itself, which makes an instance when it is called.
+    Class(String),
+    Instance(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Signature {
+    pub params: Vec<Param>,
+    // Functions with a rest parameter take any number of extra arguments.
+    pub rest: bool,
+    pub ret: Type,
+}
+
+// Parameters of a `function<number, bool>` annotation have no names.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Null => write!(f, "null"),
+            Type::Bool => write!(f, "bool"),
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::List(item) => write!(f, "list<{}>", item),
+            Type::Map(key, value) => write!(f, "map<{}, {}>", key, value),
+            Type::Function(None) => write!(f, "function"),
+            Type::Function(Some(sig)) => {
+                write!(f, "function<")?;
+                for param in &sig.params {
+                    write!(f, "{}, ", param.ty)?;
+                }
+                write!(f, "{}>", sig.ret)
+            }
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// What the checker found out about a program. Types map every expression
+// to the type it was given, which is `any` for most unannotated code.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Typing {
+    pub types: NodeMap<Type>,
+    pub diagnostics: Vec<Error>,
+}
+
+pub fn check_program(program: &Program, mode: Mode) -> Typing {
+    let mut checker = Checker {
+        file: &program.name[..],
+        mode,
+        classes: HashMap::new(),
+        scopes: vec![Vec::new()],
+        functions: Vec::new(),
+        annotations: NodeMap::new(),
+        typing: Typing::default(),
+    };
+
+    // Classes and functions at the top level are globals, so they can be used
+    // before they are declared. The same goes for annotated variables.
+    let globals: Vec<&Stmt> = program
+        .body
+        .iter()
+        .map(|stmt| match &stmt.kind {
+            StmtKind::Export(decl) => &**decl,
+            _ => stmt,
+        })
+        .collect();
+    for stmt in &globals {
+        if let StmtKind::Class(decl) = &stmt.kind {
+            let superclass = decl.superclass.as_ref().map(|s| s.name.clone());
+            checker.classes.insert(decl.name.name.clone(), superclass);
+        }
+    }
+    for stmt in &globals {
+        match &stmt.kind {
+            StmtKind::Class(decl) => {
+                checker.declare(&decl.name.name, class_type(decl));
+            }
+            StmtKind::Function(decl) => {
+                let ty = checker.declaration_type(decl);
+                checker.declare(&decl.name.name, ty);
+            }
+            StmtKind::Var {
+                name, ty: Some(ty), ..
+            } => {
+                let ty = checker.annotation(ty);
+                checker.declare(&name.name, ty);
+            }
+            _ => {}
+        }
+    }
+
+    checker.body(&program.body);
+    checker.typing
+}
+
+// The function whose body is being checked.
+struct Function {
+    name: String,
+    // Whether anything in the signature is annotated, in which case the
+    // body is checked. Functions nested inside of one are checked as well.
+    checked: bool,
+    ret: Option<Type>,
+}
+
+struct Checker<'a> {
+    file: &'a str,
+    mode: Mode,
+    // Every class declared at the top level and the class it extends.
+    classes: HashMap<String, Option<String>>,
+    scopes: Vec<Vec<(String, Type)>>,
+    functions: Vec<Function>,
+    // Annotations are looked at more than once, like the signatures of
+    // globals which are needed before their bodies are checked, so they are
+    // only worked out and reported on once.
+    annotations: NodeMap<Type>,
+    typing: Typing,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, msg: &str, span: Span) {
+        let error = match self.mode {
+            Mode::Lenient => Error::warning(msg, self.file, span.line, span.column),
+            Mode::Strict => Error::new(msg, self.file, span.line, span.column),
+        };
+        self.typing.diagnostics.push(error);
+    }
+
+    fn checked(&self) -> bool {
+        self.functions.last().is_some_and(|f| f.checked)
+    }
+
+    fn declare(&mut self, name: &str, ty: Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((String::from(name), ty));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        // Later declarations shadow earlier ones, even in the same scope.
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.iter().rev().find(|(n, _)| n == name))
+            .map_or(Type::Any, |(_, ty)| ty.clone())
+    }
+
+    // Whether a value of the found type can be stored where the expected
+    // type is wanted. Null can stand in for anything since variables often
+    // start out empty, and lists and maps are compared by what they hold.
+    fn accepts(&self, expected: &Type, found: &Type) -> bool {
+        match (expected, found) {
+            (Type::Any, _) | (_, Type::Any) | (_, Type::Null) => true,
+            (Type::List(a), Type::List(b)) => self.accepts(a, b),
+            (Type::Map(ka, va), Type::Map(kb, vb)) => self.accepts(ka, kb) && self.accepts(va, vb),
+            (Type::Function(Some(a)), Type::Function(Some(b))) => {
+                a.params.len() == b.params.len()
+                    && a.params
+                        .iter()
+                        .zip(&b.params)
+                        .all(|(a, b)| self.accepts(&b.ty, &a.ty))
+                    && self.accepts(&a.ret, &b.ret)
+            }
+            (Type::Function(_), Type::Function(_)) => true,
+            (Type::Instance(a), Type::Instance(b)) => self.extends(b, a),
+            _ => expected == found,
+        }
+    }
+
+    fn extends(&self, class: &str, ancestor: &str) -> bool {
+        let mut current = Some(class);
+        // The chain is bounded in case a class is made to extend itself.
+        for _ in 0..=self.classes.len() {
+            match current {
+                Some(name) if name == ancestor => return true,
+                Some(name) => {
+                    current = self.classes.get(name).and_then(|s| s.as_deref());
+                }
+                None => return false,
+            }
+        }
+        false
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, span: Span) {
+        if !self.accepts(expected, found) {
+            let msg = format!("expected {} but found {}", expected, found);
+            self.report(&msg[..], span);
+        }
+    }
+
+    fn annotation(&mut self, ty: &TypeAnnotation) -> Type {
+        if let Some(cached) = self.annotations.get(&ty.name.id) {
+            return cached.clone();
+        }
+        let resolved = self.resolve_annotation(ty);
+        if ty.name.id != NodeId::DUMMY {
+            self.annotations.insert(ty.name.id, resolved.clone());
+        }
+        resolved
+    }
+
+    fn resolve_annotation(&mut self, ty: &TypeAnnotation) -> Type {
+        let args: Vec<Type> = ty.args.iter().map(|a| self.annotation(a)).collect();
+        let name = &ty.name.name[..];
+        let expected = match name {
+            "list" => 1,
+            "map" => 2,
+            "function" => return self.function_annotation(args),
+            _ => 0,
+        };
+        if !args.is_empty() && args.len() != expected {
+            let msg = format!("wrong number of type arguments for {}", name);
+            self.report(&msg[..], ty.span);
+            return Type::Any;
+        }
+
+        let mut args = args.into_iter();
+        let mut arg = || Box::new(args.next().unwrap_or(Type::Any));
+        match name {
+            "any" => Type::Any,
+            "null" => Type::Null,
+            "bool" => Type::Bool,
+            // Ints and floats mix freely in arithmetic, so the checker only
+            // tells numbers apart from everything else.
+            "number" | "int" | "float" => Type::Number,
+            "string" => Type::String,
+            "list" => Type::List(arg()),
+            "map" => Type::Map(arg(), arg()),
+            _ if self.classes.contains_key(name) => Type::Instance(String::from(name)),
+            _ => {
+                let msg = format!("unknown type {}", name);
+                self.report(&msg[..], ty.name.span);
+                Type::Any
+            }
+        }
+    }
+
+    // The last argument of a function type is what it returns, so
+    // `function<number, bool>` takes a number and returns a bool.
+    fn function_annotation(&self, mut args: Vec<Type>) -> Type {
+        let ret = match args.pop() {
+            Some(ret) => ret,
+            None => return Type::Function(None),
+        };
+        let params = args
+            .into_iter()
+            .map(|ty| Param {
+                name: String::new(),
+                ty,
+            })
+            .collect();
+        Type::Function(Some(Box::new(Signature {
+            params,
+            rest: false,
+            ret,
+        })))
+    }
+
+    fn function_type(
+        &mut self,
+        params: &[Identifier],
+        types: &[Option<TypeAnnotation>],
+        rest: &Option<Identifier>,
+        ret: &Option<TypeAnnotation>,
+    ) -> Type {
+        let params = params
+            .iter()
+            .zip(types)
+            .map(|(param, ty)| Param {
+                name: param.name.clone(),
+                ty: ty.as_ref().map_or(Type::Any, |ty| self.annotation(ty)),
+            })
+            .collect();
+        let ret = ret.as_ref().map_or(Type::Any, |ty| self.annotation(ty));
+        Type::Function(Some(Box::new(Signature {
+            params,
+            rest: rest.is_some(),
+            ret,
+        })))
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            self.stmt(stmt);
+        }
+    }
+
+    fn block(&mut self, body: &[Stmt]) {
+        self.scopes.push(Vec::new());
+        self.body(body);
+        self.scopes.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expression(expr) => {
+                self.expr(expr);
+            }
+            StmtKind::Var { name, ty, init, .. } => {
+                let declared = ty.as_ref().map(|ty| self.annotation(ty));
+                let found = init.as_ref().map(|init| (self.expr(init), init.span));
+                let ty = match (declared, found) {
+                    (Some(declared), Some((found, span))) => {
+                        self.expect(&declared, &found, span);
+                        declared
+                    }
+                    (Some(declared), None) => declared,
+                    // A variable that starts out as null is usually given a
+                    // value of some other type later on.
+                    (None, Some((found, _))) if self.checked() && found != Type::Null => found,
+                    (None, _) => Type::Any,
+                };
+                self.declare(&name.name, ty);
+            }
+            StmtKind::Destructure { pattern, init, .. } => {
+                self.expr(init);
+                for name in pattern.names() {
+                    self.declare(&name.name, Type::Any);
+                }
+            }
+            StmtKind::MultipleAssign { targets, values } => {
+                let found: Vec<Type> = values.iter().map(|v| self.expr(v)).collect();
+                if targets.len() == values.len() {
+                    for ((target, found), value) in targets.iter().zip(found).zip(values) {
+                        if let PatternKind::Name(name) = &target.kind {
+                            let expected = self.lookup(&name.name);
+                            self.expect(&expected, &found, value.span);
+                        }
+                    }
+                }
+            }
+            StmtKind::Block(body) => self.block(body),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            StmtKind::While {
+                condition, body, ..
+            }
+            | StmtKind::DoWhile {
+                condition, body, ..
+            } => {
+                self.expr(condition);
+                self.stmt(body);
+            }
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                let item = match (&iterable.kind, self.expr(iterable)) {
+                    (ExprKind::Range { .. }, _) => Type::Number,
+                    (_, Type::List(item)) => *item,
+                    (_, Type::String) => Type::String,
+                    _ => Type::Any,
+                };
+                self.scopes.push(Vec::new());
+                self.declare(&variable.name, item);
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            StmtKind::Match { subject, arms } => {
+                self.expr(subject);
+                for arm in arms {
+                    for value in &arm.values {
+                        self.expr(value);
+                    }
+                    self.block(&arm.body);
+                }
+            }
+            StmtKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.block(body);
+                if let Some(catch) = catch {
+                    self.scopes.push(Vec::new());
+                    if let Some(binding) = &catch.binding {
+                        self.declare(&binding.name, Type::Any);
+                    }
+                    self.body(&catch.body);
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.block(finally);
+                }
+            }
+            StmtKind::Throw(value) => {
+                self.expr(value);
+            }
+            StmtKind::Return(value) => {
+                let found = match value {
+                    Some(value) => self.expr(value),
+                    None => Type::Null,
+                };
+                let expected = self.functions.last().and_then(|f| f.ret.clone());
+                if let Some(expected) = expected {
+                    // Null goes with any type, but leaving out the value
+                    // altogether is almost always a mistake.
+                    let missing = value.is_none() && returns_value(&expected);
+                    if missing || !self.accepts(&expected, &found) {
+                        let msg = format!("expected to return {} but found {}", expected, found);
+                        self.report(&msg[..], value.as_ref().map_or(stmt.span, |v| v.span));
+                    }
+                }
+            }
+            StmtKind::Break(_) | StmtKind::Continue(_) | StmtKind::Import(_) => {}
+            StmtKind::Function(decl) => {
+                // Top level functions were declared up front.
+                if self.scopes.len() > 1 {
+                    let ty = self.declaration_type(decl);
+                    self.declare(&decl.name.name, ty);
+                }
+                self.function(&decl.name.name, decl.span, decl);
+            }
+            StmtKind::Class(decl) => {
+                for decorator in &decl.decorators {
+                    self.expr(decorator);
+                }
+                if self.scopes.len() > 1 {
+                    self.declare(&decl.name.name, class_type(decl));
+                }
+                let accessors = decl.getters.iter().chain(&decl.setters);
+                for method in decl
+                    .initializer
+                    .iter()
+                    .chain(&decl.methods)
+                    .chain(accessors)
+                {
+                    self.function(&method.name.name, method.span, method);
+                }
+            }
+            StmtKind::Export(decl) => self.stmt(decl),
+        }
+    }
+
+    // A decorator can replace what it decorates with anything at all.
+    fn declaration_type(&mut self, decl: &FunctionDecl) -> Type {
+        if !decl.decorators.is_empty() {
+            return Type::Any;
+        }
+        self.function_type(
+            &decl.params,
+            &decl.param_types,
+            &decl.rest,
+            &decl.return_type,
+        )
+    }
+
+    fn function(&mut self, name: &str, span: Span, decl: &FunctionDecl) {
+        for decorator in &decl.decorators {
+            self.expr(decorator);
+        }
+        self.function_body(
+            name,
+            span,
+            &decl.params,
+            &decl.param_types,
+            &decl.rest,
+            &decl.return_type,
+            &decl.body,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn function_body(
+        &mut self,
+        name: &str,
+        span: Span,
+        params: &[Identifier],
+        types: &[Option<TypeAnnotation>],
+        rest: &Option<Identifier>,
+        ret: &Option<TypeAnnotation>,
+        body: &[Stmt],
+    ) {
+        let annotated = ret.is_some() || types.iter().any(|t| t.is_some());
+        let ret = ret.as_ref().map(|ty| self.annotation(ty));
+        let checked = annotated || self.checked();
+
+        self.scopes.push(Vec::new());
+        for (param, ty) in params.iter().zip(types) {
+            let ty = ty.as_ref().map_or(Type::Any, |ty| self.annotation(ty));
+            self.declare(&param.name, ty);
+        }
+        if let Some(rest) = rest {
+            self.declare(&rest.name, Type::List(Box::new(Type::Any)));
+        }
+        self.functions.push(Function {
+            name: String::from(name),
+            checked,
+            ret,
+        });
+        self.body(body);
+        let function = self.functions.pop();
+        self.scopes.pop();
+
+        // Falling off the end of a function returns null, which is only
+        // fine when the function does not say it returns something else.
+        if let Some(Function {
+            name,
+            ret: Some(ret),
+            ..
+        }) = function
+        {
+            if returns_value(&ret) && !always_returns(body) {
+                let msg = format!("{} can end without returning {}", name, ret);
+                self.report(&msg[..], span);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        let ty = self.infer(expr);
+        self.typing.types.insert(expr.id, ty.clone());
+        ty
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Int(_) | ExprKind::Float(_) => Type::Number,
+            ExprKind::String(_) => Type::String,
+            ExprKind::Bool(_) => Type::Bool,
+            ExprKind::Null => Type::Null,
+            ExprKind::This | ExprKind::Super(_) => Type::Any,
+            ExprKind::Variable(name) => self.lookup(&name[..]),
+            ExprKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Expr(expr) = part {
+                        self.expr(expr);
+                    }
+                }
+                Type::String
+            }
+            ExprKind::Unary { op, operand } => {
+                let operand_ty = self.expr(operand);
+                match op {
+                    UnaryOp::Not => Type::Bool,
+                    UnaryOp::Negate | UnaryOp::BitNot => {
+                        if self.checked() && !self.accepts(&Type::Number, &operand_ty) {
+                            let msg = format!("cannot apply {} to {}", op.symbol(), operand_ty);
+                            self.report(&msg[..], expr.span);
+                            return Type::Any;
+                        }
+                        Type::Number
+                    }
+                }
+            }
+            ExprKind::Binary { op, left, right } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                self.binary(*op, &left, &right, expr.span)
+            }
+            ExprKind::Assign { op, target, value } => {
+                let found = self.expr(value);
+                let expected = self.expr(target);
+                let found = match op {
+                    Some(op) => self.binary(*op, &expected, &found, expr.span),
+                    None => found,
+                };
+                if let ExprKind::Variable(_) = &target.kind {
+                    self.expect(&expected, &found, value.span);
+                }
+                found
+            }
+            ExprKind::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                let then_ty = self.expr(then_branch);
+                let else_ty = self.expr(else_branch);
+                join(then_ty, else_ty)
+            }
+            ExprKind::Range { start, end, .. } => {
+                for bound in [start, end] {
+                    let ty = self.expr(bound);
+                    if self.checked() && !self.accepts(&Type::Number, &ty) {
+                        let msg = format!("expected number but found {}", ty);
+                        self.report(&msg[..], bound.span);
+                    }
+                }
+                Type::Any
+            }
+            ExprKind::Call(call) => self.call(call),
+            ExprKind::Member { object, .. } => {
+                self.expr(object);
+                Type::Any
+            }
+            ExprKind::Index { object, index } => {
+                let object_ty = self.expr(object);
+                let index_ty = self.expr(index);
+                let (key, item) = match &object_ty {
+                    Type::List(item) => (Type::Number, (**item).clone()),
+                    Type::Map(key, value) => ((**key).clone(), (**value).clone()),
+                    Type::String => (Type::Number, Type::String),
+                    _ => return Type::Any,
+                };
+                if self.checked() && !self.accepts(&key, &index_ty) {
+                    let msg = format!("cannot index {} with {}", object_ty, index_ty);
+                    self.report(&msg[..], index.span);
+                }
+                item
+            }
+            ExprKind::Slice { object, start, end } => {
+                let object_ty = self.expr(object);
+                for bound in start.iter().chain(end) {
+                    self.expr(bound);
+                }
+                match object_ty {
+                    Type::List(_) | Type::String => object_ty,
+                    _ => Type::Any,
+                }
+            }
+            ExprKind::Destructure { pattern, value } => {
+                if let PatternKind::Target(target) = &pattern.kind {
+                    self.expr(target);
+                }
+                self.expr(value)
+            }
+            ExprKind::ListLiteral(items) => {
+                let items: Vec<Type> = items.iter().map(|item| self.expr(item)).collect();
+                Type::List(Box::new(join_all(items)))
+            }
+            ExprKind::MapLiteral(entries) => {
+                let mut keys = Vec::new();
+                let mut values = Vec::new();
+                for entry in entries {
+                    keys.push(match &entry.key {
+                        MapKey::Identifier(_) | MapKey::String(_) => Type::String,
+                        MapKey::Computed(key) => self.expr(key),
+                    });
+                    values.push(self.expr(&entry.value));
+                }
+                Type::Map(Box::new(join_all(keys)), Box::new(join_all(values)))
+            }
+            ExprKind::Lambda(lambda) => {
+                for default in &lambda.defaults {
+                    self.expr(default);
+                }
+                self.function_body(
+                    "lambda",
+                    lambda.span,
+                    &lambda.params,
+                    &lambda.param_types,
+                    &lambda.rest,
+                    &lambda.return_type,
+                    &lambda.body,
+                );
+                self.function_type(
+                    &lambda.params,
+                    &lambda.param_types,
+                    &lambda.rest,
+                    &lambda.return_type,
+                )
+            }
+            // Nothing is known about what the coroutine is resumed with.
+            ExprKind::Yield(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                Type::Any
+            }
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, left: &Type, right: &Type, span: Span) -> Type {
+        let number = |ty: &Type| matches!(ty, Type::Number | Type::Any);
+        let ty = match op {
+            // Adding anything to a string turns it into a string first.
+            BinaryOp::Add if *left == Type::String || *right == Type::String => Some(Type::String),
+            BinaryOp::Add if *left == Type::Any || *right == Type::Any => Some(Type::Any),
+            BinaryOp::Add
+            | BinaryOp::Subtract
+            | BinaryOp::Multiply
+            | BinaryOp::Divide
+            | BinaryOp::Modulo
+            | BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor => {
+                if number(left) && number(right) {
+                    Some(Type::Number)
+                } else {
+                    None
+                }
+            }
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => {
+                let strings = |ty: &Type| matches!(ty, Type::String | Type::Any);
+                if (number(left) && number(right)) || (strings(left) && strings(right)) {
+                    Some(Type::Bool)
+                } else {
+                    None
+                }
+            }
+            BinaryOp::Equal | BinaryOp::NotEqual => Some(Type::Bool),
+            // And and or give back one of their operands.
+            BinaryOp::And | BinaryOp::Or => Some(join(left.clone(), right.clone())),
+        };
+
+        match ty {
+            Some(ty) => ty,
+            None => {
+                if self.checked() {
+                    let msg = format!("cannot apply {} to {} and {}", op.symbol(), left, right);
+                    self.report(&msg[..], span);
+                }
+                Type::Any
+            }
+        }
+    }
+
+    fn call(&mut self, call: &Call) -> Type {
+        let callee = self.expr(&call.callee);
+        let args: Vec<Type> = call.args.iter().map(|arg| self.expr(arg)).collect();
+        let sig = match callee {
+            Type::Function(Some(sig)) => sig,
+            Type::Class(name) => return Type::Instance(name),
+            Type::Any | Type::Function(None) => return Type::Any,
+            other => {
+                if self.checked() {
+                    let msg = format!("cannot call {}", other);
+                    self.report(&msg[..], call.callee.span);
+                }
+                return Type::Any;
+            }
+        };
+
+        // Only the types of the arguments are checked here. Calls with the
+        // wrong number of arguments are left for the runtime to report.
+        if self.checked() {
+            let positional = call.positional().len();
+            for (i, (arg, found)) in call.args.iter().zip(&args).enumerate() {
+                let param = if i < positional {
+                    sig.params.get(i)
+                } else {
+                    let name = &call.names[i - positional].name;
+                    sig.params.iter().find(|p| p.name == *name)
+                };
+                if let Some(param) = param {
+                    if !self.accepts(&param.ty, found) {
+                        let msg = match &param.name[..] {
+                            "" => format!(
+                                "expected {} for argument {} but found {}",
+                                param.ty,
+                                i + 1,
+                                found
+                            ),
+                            name => format!(
+                                "expected {} for argument {} but found {}",
+                                param.ty, name, found
+                            ),
+                        };
+                        self.report(&msg[..], arg.span);
+                    }
+                }
+            }
+        }
+        sig.ret
+    }
+}
+
+fn class_type(decl: &ClassDecl) -> Type {
+    if decl.decorators.is_empty() {
+        Type::Class(decl.name.name.clone())
+    } else {
+        Type::Any
+    }
+}
+
+// The type that covers both, which is `any` unless they are the same.
+fn join(a: Type, b: Type) -> Type {
+    match (a, b) {
+        (Type::Null, other) | (other, Type::Null) => other,
+        (a, b) if a == b => a,
+        _ => Type::Any,
+    }
+}
+
+fn join_all(types: Vec<Type>) -> Type {
+    types.into_iter().reduce(join).unwrap_or(Type::Any)
+}
+
+fn returns_value(ty: &Type) -> bool {
+    !matches!(ty, Type::Any | Type::Null)
+}
+
+// Whether every way through the statements ends at a return. This only
+// looks at the shape of the code, so a loop that never ends still needs a
+// return after it.
+fn always_returns(body: &[Stmt]) -> bool {
+    body.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Return(_) | StmtKind::Throw(_) => true,
+        StmtKind::Block(body) => always_returns(body),
+        StmtKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => {
+            always_returns(std::slice::from_ref(then_branch))
+                && always_returns(std::slice::from_ref(else_branch))
+        }
+        StmtKind::Match { arms, .. } => {
+            arms.iter().any(|arm| arm.is_default())
+                && arms.iter().all(|arm| always_returns(&arm.body))
+        }
+        StmtKind::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            finally.as_ref().is_some_and(|f| always_returns(f))
+                || (always_returns(body) && catch.as_ref().is_none_or(|c| always_returns(&c.body)))
+        }
+        _ => false,
+    })
+}
@@ -0,0 +1,745 @@
+#[cfg(feature = "bigint")]
+pub mod bigint;
+pub mod convert;
+pub mod foreign;
+pub mod gc;
+pub mod ops;
+pub mod symbol;
+
+use crate::builtins::Native;
+use crate::bytecode::{format_number, Constant};
+use crate::interp;
+use crate::vm;
+#[cfg(feature = "bigint")]
+use bigint::BigInt;
+use foreign::Foreign;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+use symbol::{Symbol, Symbols};
+
+// The values scripts work with at runtime. Anything bigger than a number
+// lives in a heap and is referred to by a handle, so that values are cheap
+// to copy and the heap can find every object that is still in use. Strings
+// can't be changed once they are made, so they are shared instead.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    // Numbers written without a decimal point are ints, and stay ints
+    // through arithmetic with other ints, while anything with a float in
+    // it is a float.
+    Int(i64),
+    Float(f64),
+    // The ints that don't fit in 64 bits, which are only ever made when
+    // bigints are turned on.
+    #[cfg(feature = "bigint")]
+    BigInt(Arc<BigInt>),
+    String(Arc<str>),
+    Object(Handle),
+}
+
+// Where an object is in the heap. Two handles are the same object when they
+// are equal.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Handle(u32);
+
+impl Handle {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    // The handle of whatever is in the slot, for restoring a snapshot,
+    // which puts every object back where it was.
+    pub(crate) fn from_index(index: u32) -> Self {
+        Self(index)
+    }
+}
+
+impl Value {
+    // Only null and false are false, so 0 and the empty string are true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Null | Value::Bool(false))
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // Any kind of number, with ints turned into floats.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::Float(n) => Some(*n),
+            #[cfg(feature = "bigint")]
+            Value::BigInt(n) => Some(n.to_f64()),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<Handle> {
+        match self {
+            Value::Object(handle) => Some(*handle),
+            _ => None,
+        }
+    }
+
+    // The value a constant pushes. Functions and the cases of a switch are
+    // not values until something is made from them.
+    pub fn from_constant(constant: &Constant) -> Option<Value> {
+        match constant {
+            Constant::Int(n) => Some(Value::Int(*n)),
+            Constant::Float(n) => Some(Value::Float(*n)),
+            Constant::String(s) => Some(Value::String(s.clone())),
+            Constant::Function(_) | Constant::Cases(_) => None,
+        }
+    }
+
+    // What the value is looked up by when it is a key in a map. NaN is never
+    // equal to itself, so it can't be a key.
+    pub fn key(&self) -> Option<Key> {
+        match self {
+            Value::Null => Some(Key::Null),
+            Value::Bool(b) => Some(Key::Bool(*b)),
+            Value::Int(n) => Some(Key::Int(*n)),
+            Value::Float(n) if n.is_nan() => None,
+            // A whole float is equal to the int it holds, so it is the same
+            // key, and so are -0 and 0.
+            Value::Float(n) => match whole(*n) {
+                Some(n) => Some(Key::Int(n)),
+                #[cfg(feature = "bigint")]
+                None if n.fract() == 0.0 && n.is_finite() => {
+                    BigInt::from_f64(*n).map(|n| Key::BigInt(Arc::new(n)))
+                }
+                None => Some(Key::Float(n.to_bits())),
+            },
+            #[cfg(feature = "bigint")]
+            Value::BigInt(n) => Some(Key::BigInt(n.clone())),
+            Value::String(s) => Some(Key::String(s.clone())),
+            Value::Object(handle) => Some(Key::Object(*handle)),
+        }
+    }
+
+    // The name of the type, for errors. Objects have to be looked up to
+    // know which kind they are.
+    pub fn type_name(&self, heap: &Heap) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            #[cfg(feature = "bigint")]
+            Value::BigInt(_) => "bigint",
+            Value::String(_) => "string",
+            Value::Object(handle) => heap.get(*handle).type_name(),
+        }
+    }
+
+    // Shows the value the way `print` does, looking inside any objects.
+    pub fn display<'a>(&'a self, heap: &'a Heap) -> Display<'a> {
+        Display { value: self, heap }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+// A bigint that fits in an int is one.
+#[cfg(feature = "bigint")]
+impl From<BigInt> for Value {
+    fn from(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(n) => Value::Int(n),
+            None => Value::BigInt(Arc::new(n)),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(Arc::from(s))
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(Arc::from(s))
+    }
+}
+
+impl From<Arc<str>> for Value {
+    fn from(s: Arc<str>) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<Handle> for Value {
+    fn from(handle: Handle) -> Self {
+        Value::Object(handle)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+// Without the heap an object can only be shown by where it is.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => f.write_str(&format_number(*n)),
+            #[cfg(feature = "bigint")]
+            Value::BigInt(n) => write!(f, "{}", n),
+            Value::String(s) => f.write_str(s),
+            Value::Object(handle) => write!(f, "<object {}>", handle.0),
+        }
+    }
+}
+
+// A value that can be shown with the objects it refers to.
+pub struct Display<'a> {
+    value: &'a Value,
+    heap: &'a Heap,
+}
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut showing = Vec::new();
+        write_value(f, self.value, self.heap, false, &mut showing)
+    }
+}
+
+// Strings inside lists and maps are quoted so that `["a, b"]` can be told
+// apart from `["a", "b"]`. An object that holds itself is shown as `...`
+// the second time it is reached.
+fn write_value(
+    f: &mut fmt::Formatter<'_>,
+    value: &Value,
+    heap: &Heap,
+    nested: bool,
+    showing: &mut Vec<Handle>,
+) -> fmt::Result {
+    let handle = match value {
+        Value::String(s) if nested => return write!(f, "{:?}", s),
+        Value::Object(handle) => *handle,
+        _ => return write!(f, "{}", value),
+    };
+    if showing.contains(&handle) {
+        return f.write_str("...");
+    }
+    showing.push(handle);
+    match heap.get(handle) {
+        Object::List(items) => {
+            f.write_str("[")?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_value(f, item, heap, true, showing)?;
+            }
+            f.write_str("]")?;
+        }
+        Object::Map(map) => {
+            f.write_str("{")?;
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_value(f, &key.value(), heap, true, showing)?;
+                f.write_str(": ")?;
+                write_value(f, value, heap, true, showing)?;
+            }
+            f.write_str("}")?;
+        }
+        // Instances are shown with the name of their class and then their
+        // fields, the same as a map.
+        Object::Instance(instance) => {
+            if let Object::Class(class) = heap.get(instance.class) {
+                write!(f, "{} ", class.name)?;
+            }
+            f.write_str("{")?;
+            for (i, (key, value)) in instance.fields.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}: ", key)?;
+                write_value(f, value, heap, true, showing)?;
+            }
+            f.write_str("}")?;
+        }
+        // A bound method is shown as the function it calls.
+        Object::BoundMethod(bound) => write_value(f, &bound.method, heap, nested, showing)?,
+        object => write!(f, "{}", object)?,
+    }
+    showing.pop();
+    Ok(())
+}
+
+// A value that can be a key in a map. Floats that aren't whole are kept by
+// their bits, and whole ones as the int they are equal to.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    #[cfg(feature = "bigint")]
+    BigInt(Arc<BigInt>),
+    String(Arc<str>),
+    Object(Handle),
+}
+
+impl Key {
+    pub fn value(&self) -> Value {
+        match self {
+            Key::Null => Value::Null,
+            Key::Bool(b) => Value::Bool(*b),
+            Key::Int(n) => Value::Int(*n),
+            Key::Float(bits) => Value::Float(f64::from_bits(*bits)),
+            #[cfg(feature = "bigint")]
+            Key::BigInt(n) => Value::BigInt(n.clone()),
+            Key::String(s) => Value::String(s.clone()),
+            Key::Object(handle) => Value::Object(*handle),
+        }
+    }
+}
+
+// The int a float is equal to, if it is whole and in the range of ints.
+pub fn whole(n: f64) -> Option<i64> {
+    // 2^63 is the first float past the largest int.
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+// The things values can refer to.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Object {
+    List(Vec<Value>),
+    Map(Map),
+    Range(Range),
+    Native(Native),
+    // A function the host gave the virtual machine.
+    Host(vm::host::HostFunction),
+    // A function of the tree-walking interpreter and the scope it was made
+    // in, along with the scopes themselves.
+    Interpreted(interp::Closure),
+    Scope(interp::Scope),
+    // A function of the virtual machine and the variables it captured.
+    Closure(vm::Closure),
+    Upvalue(vm::Upvalue),
+    Globals(vm::Globals),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    Iterator(Iter),
+    Coroutine(vm::Coroutine),
+    Foreign(Foreign),
+    Weak(gc::Weak),
+}
+
+impl Object {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::List(_) => "list",
+            Object::Map(_) => "map",
+            Object::Range(_) => "range",
+            Object::Native(_)
+            | Object::Host(_)
+            | Object::Interpreted(_)
+            | Object::Closure(_)
+            | Object::BoundMethod(_) => "function",
+            Object::Scope(_) => "scope",
+            Object::Upvalue(_) => "upvalue",
+            Object::Globals(_) => "globals",
+            Object::Class(_) => "class",
+            Object::Instance(_) => "instance",
+            Object::Iterator(_) => "iterator",
+            Object::Coroutine(_) => "coroutine",
+            Object::Foreign(foreign) => foreign.type_name(),
+            Object::Weak(_) => "weak",
+        }
+    }
+}
+
+// Shows the objects that don't hold other values.
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::List(_) => f.write_str("<list>"),
+            Object::Map(_) => f.write_str("<map>"),
+            Object::Range(range) => write!(f, "{}", range),
+            Object::Native(native) => write!(f, "<function {}>", native.name),
+            Object::Host(host) => write!(f, "<function {}>", host.name()),
+            Object::Interpreted(closure) => write!(f, "<function {}>", closure.def.name),
+            Object::Scope(_) => f.write_str("<scope>"),
+            Object::Closure(closure) => write!(f, "<function {}>", closure.proto.function.name),
+            Object::Upvalue(_) => f.write_str("<upvalue>"),
+            Object::Globals(_) => f.write_str("<globals>"),
+            Object::Class(class) => write!(f, "<class {}>", class.name),
+            Object::Instance(_) => f.write_str("<instance>"),
+            Object::BoundMethod(_) => f.write_str("<method>"),
+            Object::Iterator(_) => f.write_str("<iterator>"),
+            Object::Coroutine(_) => f.write_str("<coroutine>"),
+            Object::Foreign(foreign) => write!(f, "<{}>", foreign.type_name()),
+            Object::Weak(_) => f.write_str("<weak>"),
+        }
+    }
+}
+
+// A class holds the methods of its instances, which are looked up in the
+// class it extends when it doesn't have them itself. The methods are the
+// functions they were declared as, or whatever their decorators gave back.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Class {
+    pub name: String,
+    pub superclass: Option<Handle>,
+    pub methods: HashMap<Symbol, Value>,
+    pub getters: HashMap<Symbol, Value>,
+    pub setters: HashMap<Symbol, Value>,
+}
+
+impl Class {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            superclass: None,
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+        }
+    }
+}
+
+// The fields of an instance are kept in the order they were first set, for
+// showing them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instance {
+    pub class: Handle,
+    pub fields: Map<Symbol>,
+}
+
+// A method read from an instance, which is called with the instance as
+// `this`, or from a builtin value, which is the first argument of the
+// native it is.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Value,
+}
+
+// Goes through the items of a builtin value for a for loop. Lists are read
+// as the loop goes, so items added by the loop are reached too, while the
+// entries of a map and the characters of a string are taken up front.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Iter {
+    pub items: Items,
+    // How many items have been handed out.
+    pub position: usize,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Items {
+    List(Handle),
+    Range(Range),
+    Values(Vec<Value>),
+}
+
+impl Iter {
+    pub fn new(items: Items) -> Self {
+        Self { items, position: 0 }
+    }
+
+    // The item the iterator is at, if it hasn't run out.
+    pub fn peek(&self, heap: &Heap) -> Option<Value> {
+        match &self.items {
+            Items::List(handle) => match heap.get(*handle) {
+                Object::List(items) => items.get(self.position).cloned(),
+                _ => unreachable!("list iterators only go through lists"),
+            },
+            Items::Range(range) => range.nth(self.position).map(Value::Int),
+            Items::Values(values) => values.get(self.position).cloned(),
+        }
+    }
+}
+
+// The ints from start up to end, counting by one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    // The int the range gets to after the given number of steps, if it
+    // hasn't ended by then.
+    pub fn nth(&self, steps: usize) -> Option<i64> {
+        let n = i64::try_from(steps)
+            .ok()
+            .and_then(|steps| self.start.checked_add(steps))?;
+        let inside = if self.inclusive {
+            n <= self.end
+        } else {
+            n < self.end
+        };
+        if inside {
+            Some(n)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dots = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, dots, self.end)
+    }
+}
+
+// A map keeps its entries in the order they were added, which is the order
+// they are shown and gone through in. The fields of instances are kept the
+// same way, by their symbols.
+#[derive(Clone, Debug)]
+pub struct Map<K = Key> {
+    entries: Vec<(K, Value)>,
+    indices: HashMap<K, usize>,
+}
+
+// The indices only say where the entries are, so maps with the same entries
+// are the same.
+impl<K: PartialEq> PartialEq for Map<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl<K> Default for Map<K> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Map<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&Value> {
+        self.indices.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    // Where the key is in the order of the entries, which stays put until
+    // an entry before it is removed.
+    pub fn position(&self, key: &K) -> Option<usize> {
+        self.indices.get(key).copied()
+    }
+
+    pub fn entry(&self, i: usize) -> Option<(&K, &Value)> {
+        self.entries.get(i).map(|(key, value)| (key, value))
+    }
+
+    pub fn entry_mut(&mut self, i: usize) -> Option<(&K, &mut Value)> {
+        self.entries.get_mut(i).map(|(key, value)| (&*key, value))
+    }
+
+    // Setting a key that is already there keeps its place.
+    pub fn insert(&mut self, key: K, value: Value) {
+        match self.indices.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    // Taking a key out moves the entries after it up, keeping their order.
+    pub fn remove(&mut self, key: &K) -> Option<Value> {
+        let i = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.indices.get_mut(key).expect("every entry is indexed") -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+// Where objects live. A slot is freed when its object is no longer used
+// and handed out again to the next object.
+#[derive(Clone, Debug)]
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    free: Vec<u32>,
+    config: gc::GcConfig,
+    // How many objects the heap can hold before a collection is due.
+    threshold: usize,
+    // The objects allocated since the last collection.
+    allocated: usize,
+    // How many bytes every object was counted as taking up, and all of
+    // them together, which can't go over the limit.
+    sizes: Vec<usize>,
+    used: usize,
+    limit: Option<usize>,
+    // The objects that may have grown since they were counted, which are
+    // only kept track of while there is a limit.
+    changed: Vec<Handle>,
+    // The bytes of the strings held outside of objects, like the ones in
+    // the owner's stack slots, as the owner last counted them, along with
+    // every string made since.
+    outside: usize,
+    symbols: Symbols,
+    classes: u64,
+    // The collection that is still sweeping, if there is one.
+    sweep: Option<gc::Sweep>,
+    collections: u64,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        let config = gc::GcConfig::default();
+        Self {
+            objects: Vec::new(),
+            free: Vec::new(),
+            config,
+            threshold: config.initial_threshold,
+            allocated: 0,
+            sizes: Vec::new(),
+            used: 0,
+            limit: None,
+            changed: Vec::new(),
+            outside: 0,
+            symbols: Symbols::default(),
+            classes: 0,
+            sweep: None,
+            collections: 0,
+        }
+    }
+
+    pub fn alloc(&mut self, object: Object) -> Handle {
+        self.allocated += 1;
+        let size = object.size();
+        self.used += size;
+        match self.free.pop() {
+            Some(index) => {
+                if let Some(sweep) = &mut self.sweep {
+                    sweep.keep(index as usize);
+                }
+                self.objects[index as usize] = Some(object);
+                self.sizes[index as usize] = size;
+                Handle(index)
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.sizes.push(size);
+                Handle(self.objects.len() as u32 - 1)
+            }
+        }
+    }
+
+    // Handles only come from the heap and outlive their objects only when
+    // something has gone wrong, so a stale handle is a bug.
+    pub fn get(&self, handle: Handle) -> &Object {
+        self.objects[handle.index()]
+            .as_ref()
+            .expect("handle to a freed object")
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> &mut Object {
+        if self.limit.is_some() {
+            self.changed.push(handle);
+        }
+        let object = self.objects[handle.index()]
+            .as_mut()
+            .expect("handle to a freed object");
+        if let Object::Class(_) = object {
+            self.classes += 1;
+        }
+        object
+    }
+
+    // Changes every time a class may have changed, and every time one is
+    // freed, which can give its handle to another. Whatever remembers what
+    // it found in classes looks again once this isn't what it was then.
+    pub fn class_epoch(&self) -> u64 {
+        self.classes
+    }
+
+    // How many objects are in use.
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,325 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+// An integer of any size, for the work ints overflow on. Arithmetic on ints
+// that doesn't fit in one gives a bigint instead, and arithmetic on bigints
+// gives back an int again once the result fits, so the same number is never
+// both. Bigints are kept as their sign and the 32 bit digits of how big they
+// are, the lowest first, with no zeros on the end, which leaves zero with no
+// digits at all.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>,
+}
+
+// The largest power of ten that fits in a digit, for turning bigints into
+// text and back nine decimal digits at a time.
+const TEN_NINE: u32 = 1_000_000_000;
+
+impl BigInt {
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    // The int the bigint is equal to, if it fits in one.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0u64, |n, &digit| n << 32 | digit as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    // The nearest float, which is infinite for bigints too big for one.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0.0, |n, &digit| n * 4_294_967_296.0 + digit as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    // The bigint a whole float is equal to.
+    pub fn from_f64(n: f64) -> Option<BigInt> {
+        if n.fract() != 0.0 || !n.is_finite() {
+            return None;
+        }
+        // A whole float is its mantissa moved left by its exponent, and the
+        // ones that fit in an int already are one.
+        if n.abs() < 9_223_372_036_854_775_808.0 {
+            return Some(BigInt::from(n as i64));
+        }
+        let bits = n.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as usize - 1075;
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let mut digits = vec![0; exponent / 32];
+        let shifted = (mantissa as u128) << (exponent % 32);
+        digits.extend(
+            [
+                shifted as u32,
+                (shifted >> 32) as u32,
+                (shifted >> 64) as u32,
+            ]
+            .iter()
+            .copied(),
+        );
+        Some(BigInt::new(n < 0.0, digits))
+    }
+
+    // Reads a bigint from decimal digits, with a sign in front if it is
+    // negative.
+    pub fn parse(s: &str) -> Option<BigInt> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut magnitude = Vec::new();
+        let first = digits.len() % 9;
+        let chunks = std::iter::once(&digits[..first])
+            .chain(
+                digits.as_bytes()[first..]
+                    .chunks(9)
+                    .map(|chunk| std::str::from_utf8(chunk).expect("the digits are ascii")),
+            )
+            .filter(|chunk| !chunk.is_empty());
+        for chunk in chunks {
+            let scale = 10u32.pow(chunk.len() as u32);
+            let value = chunk.parse::<u32>().expect("the chunk is digits");
+            mul_small(&mut magnitude, scale, value);
+        }
+        Some(BigInt::new(negative, magnitude))
+    }
+
+    // Divides, rounding toward zero the same as ints do, and gives back the
+    // quotient and the remainder, which has the sign of the bigint divided.
+    // Dividing by zero is left to the caller to rule out.
+    pub fn div_rem(&self, divisor: &BigInt) -> (BigInt, BigInt) {
+        assert!(!divisor.is_zero(), "bigints can't be divided by zero");
+        let (quotient, remainder) = div_rem_magnitude(&self.digits, &divisor.digits);
+        (
+            BigInt::new(self.negative != divisor.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        )
+    }
+
+    fn new(negative: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        BigInt {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let magnitude = n.unsigned_abs();
+        BigInt::new(n < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitude(&self.digits, &other.digits),
+            (true, true) => compare_magnitude(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    // Adding numbers of different signs takes the smaller from the bigger,
+    // which leaves the sign of the bigger.
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitude(&self.digits, &other.digits));
+        }
+        match compare_magnitude(&self.digits, &other.digits) {
+            Ordering::Less => {
+                BigInt::new(other.negative, sub_magnitude(&other.digits, &self.digits))
+            }
+            _ => BigInt::new(self.negative, sub_magnitude(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut digits = vec![0u32; self.digits.len() + other.digits.len()];
+        for (i, &a) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.digits.iter().enumerate() {
+                let n = digits[i + j] as u64 + a as u64 * b as u64 + carry;
+                digits[i + j] = n as u32;
+                carry = n >> 32;
+            }
+            digits[i + other.digits.len()] = carry as u32;
+        }
+        BigInt::new(self.negative != other.negative, digits)
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+        // The groups of nine decimal digits, the lowest first.
+        let mut groups = Vec::new();
+        let mut magnitude = self.digits.clone();
+        while !magnitude.is_empty() {
+            groups.push(div_small(&mut magnitude, TEN_NINE));
+        }
+        if self.negative {
+            f.write_str("-")?;
+        }
+        let mut groups = groups.iter().rev();
+        write!(
+            f,
+            "{}",
+            groups.next().expect("a bigint that isn't zero has digits")
+        )?;
+        for group in groups {
+            write!(f, "{:09}", group)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut digits = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &digit) in long.iter().enumerate() {
+        let n = digit as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        digits.push(n as u32);
+        carry = n >> 32;
+    }
+    digits.push(carry as u32);
+    digits
+}
+
+// Takes the smaller magnitude from the one that is at least as big.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &digit) in a.iter().enumerate() {
+        let mut n = digit as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        borrow = 0;
+        if n < 0 {
+            n += 1 << 32;
+            borrow = 1;
+        }
+        digits.push(n as u32);
+    }
+    digits
+}
+
+// Multiplies the magnitude by a digit and adds another to it.
+fn mul_small(digits: &mut Vec<u32>, by: u32, add: u32) {
+    let mut carry = add as u64;
+    for digit in digits.iter_mut() {
+        let n = *digit as u64 * by as u64 + carry;
+        *digit = n as u32;
+        carry = n >> 32;
+    }
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+}
+
+// Divides the magnitude by a digit in place, giving back the remainder.
+fn div_small(digits: &mut Vec<u32>, by: u32) -> u32 {
+    let mut remainder = 0u64;
+    for digit in digits.iter_mut().rev() {
+        let n = remainder << 32 | *digit as u64;
+        *digit = (n / by as u64) as u32;
+        remainder = n % by as u64;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    remainder as u32
+}
+
+// Long division a bit at a time, which is slow for huge numbers but plenty
+// for the sizes scripts work with.
+fn div_rem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [by] = b {
+        let mut quotient = a.to_vec();
+        let remainder = div_small(&mut quotient, *by);
+        return (quotient, vec![remainder]);
+    }
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        mul_small(&mut remainder, 2, (a[i / 32] >> (i % 32)) & 1);
+        if compare_magnitude(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitude(&remainder, b);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (quotient, remainder)
+}
@@ -0,0 +1,289 @@
+use super::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::Hash;
+
+// Rust values go into scripts and come back out through these, so that
+// hosts don't have to match on values themselves. Lists and maps are made
+// in the heap and read back out of it, and their items are converted along
+// with them.
+//
+// A value that can't be converted fails with what is wrong with it, such as
+// "is string, not int", for the caller to put the name of the value it was
+// converting in front of.
+
+pub trait IntoAtom {
+    fn into_atom(self, heap: &mut Heap) -> Value;
+}
+
+pub trait FromAtom: Sized {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String>;
+}
+
+fn mismatch(value: &Value, heap: &Heap, expected: &str) -> String {
+    format!("is {}, not {}", value.type_name(heap), expected)
+}
+
+impl IntoAtom for Value {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        self
+    }
+}
+
+impl FromAtom for Value {
+    fn from_atom(value: &Value, _: &Heap) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+// Nothing is null, and any value can be taken as nothing, for a result
+// that isn't wanted.
+impl IntoAtom for () {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::Null
+    }
+}
+
+impl FromAtom for () {
+    fn from_atom(_: &Value, _: &Heap) -> Result<Self, String> {
+        Ok(())
+    }
+}
+
+impl IntoAtom for bool {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromAtom for bool {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        value.as_bool().ok_or_else(|| mismatch(value, heap, "bool"))
+    }
+}
+
+// Every int type goes through an int, and only comes back out of one that
+// fits in it. The unsigned ints that are too big for an int are floats.
+macro_rules! ints {
+    ($($int:ident),*) => {
+        $(
+            impl IntoAtom for $int {
+                fn into_atom(self, _: &mut Heap) -> Value {
+                    match i64::try_from(self) {
+                        Ok(n) => Value::Int(n),
+                        Err(_) => Value::Float(self as f64),
+                    }
+                }
+            }
+
+            impl FromAtom for $int {
+                fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+                    let n = value.as_int().ok_or_else(|| mismatch(value, heap, "int"))?;
+                    $int::try_from(n)
+                        .map_err(|_| format!("is out of range for {}", stringify!($int)))
+                }
+            }
+        )*
+    };
+}
+
+ints!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+// Floats can be made out of any number.
+impl IntoAtom for f64 {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl FromAtom for f64 {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        value
+            .as_number()
+            .ok_or_else(|| mismatch(value, heap, "float"))
+    }
+}
+
+impl IntoAtom for f32 {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::Float(self as f64)
+    }
+}
+
+impl FromAtom for f32 {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        f64::from_atom(value, heap).map(|n| n as f32)
+    }
+}
+
+impl IntoAtom for &str {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::from(self)
+    }
+}
+
+impl IntoAtom for String {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::from(self)
+    }
+}
+
+impl FromAtom for String {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        match value {
+            Value::String(s) => Ok(String::from(&s[..])),
+            value => Err(mismatch(value, heap, "string")),
+        }
+    }
+}
+
+impl IntoAtom for Arc<str> {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::String(self)
+    }
+}
+
+impl FromAtom for Arc<str> {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            value => Err(mismatch(value, heap, "string")),
+        }
+    }
+}
+
+// Any object, by where it is in the heap.
+impl IntoAtom for Handle {
+    fn into_atom(self, _: &mut Heap) -> Value {
+        Value::Object(self)
+    }
+}
+
+impl FromAtom for Handle {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        value
+            .as_object()
+            .ok_or_else(|| mismatch(value, heap, "object"))
+    }
+}
+
+impl<T: IntoAtom> IntoAtom for Option<T> {
+    fn into_atom(self, heap: &mut Heap) -> Value {
+        match self {
+            Some(value) => value.into_atom(heap),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: FromAtom> FromAtom for Option<T> {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_atom(value, heap).map(Some),
+        }
+    }
+}
+
+impl<T: IntoAtom> IntoAtom for Vec<T> {
+    fn into_atom(self, heap: &mut Heap) -> Value {
+        let items = self.into_iter().map(|item| item.into_atom(heap)).collect();
+        Value::Object(heap.alloc(Object::List(items)))
+    }
+}
+
+impl<T: FromAtom> FromAtom for Vec<T> {
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        list(value, heap)?
+            .iter()
+            .map(|item| T::from_atom(item, heap).map_err(|msg| format!("has an item that {}", msg)))
+            .collect()
+    }
+}
+
+fn list<'a>(value: &Value, heap: &'a Heap) -> Result<&'a [Value], String> {
+    match value.as_object().map(|handle| heap.get(handle)) {
+        Some(Object::List(items)) => Ok(items),
+        _ => Err(mismatch(value, heap, "list")),
+    }
+}
+
+// The keys are whatever values they turn into, which can always be keys,
+// since the types that can be hashed in Rust can't be NaN.
+impl<K, V> IntoAtom for HashMap<K, V>
+where
+    K: IntoAtom + Eq + Hash,
+    V: IntoAtom,
+{
+    fn into_atom(self, heap: &mut Heap) -> Value {
+        let mut map = Map::new();
+        for (key, value) in self {
+            let key = key.into_atom(heap).key().expect("hashable values are keys");
+            let value = value.into_atom(heap);
+            map.insert(key, value);
+        }
+        Value::Object(heap.alloc(Object::Map(map)))
+    }
+}
+
+impl<K, V> FromAtom for HashMap<K, V>
+where
+    K: FromAtom + Eq + Hash,
+    V: FromAtom,
+{
+    fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+        let map = match value.as_object().map(|handle| heap.get(handle)) {
+            Some(Object::Map(map)) => map,
+            _ => return Err(mismatch(value, heap, "map")),
+        };
+        map.iter()
+            .map(|(key, value)| {
+                let key = K::from_atom(&key.value(), heap)
+                    .map_err(|msg| format!("has a key that {}", msg))?;
+                let value =
+                    V::from_atom(value, heap).map_err(|msg| format!("has a value that {}", msg))?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+// Tuples are lists of just as many items.
+macro_rules! tuples {
+    ($(($len:expr, $($item:ident),+),)*) => {
+        $(
+            impl<$($item: IntoAtom),+> IntoAtom for ($($item,)+) {
+                #[allow(non_snake_case)]
+                fn into_atom(self, heap: &mut Heap) -> Value {
+                    let ($($item,)+) = self;
+                    let items = vec![$($item.into_atom(heap)),+];
+                    Value::Object(heap.alloc(Object::List(items)))
+                }
+            }
+
+            impl<$($item: FromAtom),+> FromAtom for ($($item,)+) {
+                fn from_atom(value: &Value, heap: &Heap) -> Result<Self, String> {
+                    let items = list(value, heap)?;
+                    if items.len() != $len {
+                        return Err(format!("has {} items, not {}", items.len(), $len));
+                    }
+                    let mut items = items.iter();
+                    Ok(($({
+                        let item = items.next().expect("the length was checked");
+                        $item::from_atom(item, heap)
+                            .map_err(|msg| format!("has an item that {}", msg))?
+                    },)+))
+                }
+            }
+        )*
+    };
+}
+
+tuples! {
+    (1, A),
+    (2, A, B),
+    (3, A, B, C),
+    (4, A, B, C, D),
+    (5, A, B, C, D, E),
+    (6, A, B, C, D, E, F),
+}
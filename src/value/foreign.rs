@@ -0,0 +1,98 @@
+use crate::builtins::Native;
+use crate::value::*;
+use std::any::Any;
+use std::fmt;
+
+// A foreign value is something of the host that scripts can hold and pass
+// around, like a window or an entity of a game, but can only look inside of
+// through the methods its type has. The methods are natives that get the
+// value first, as the methods of builtin values do, and are named after the
+// type, like `Window.close`. The data is shared rather than copied, so a
+// host that changes it from a method keeps it behind a lock.
+#[derive(Clone)]
+pub struct Foreign {
+    type_name: &'static str,
+    data: Arc<dyn Any + Send + Sync>,
+    methods: &'static [Native],
+    finalizer: Option<Finalizer>,
+}
+
+// Called with a foreign value once it is collected, to let go of whatever
+// it holds outside of the heap. The values still alive when the heap goes
+// away aren't finalized, and their data is just dropped.
+pub type Finalizer = fn(&Foreign);
+
+impl Foreign {
+    pub fn new<T: Any + Send + Sync>(type_name: &'static str, data: T) -> Self {
+        Self {
+            type_name,
+            data: Arc::new(data),
+            methods: &[],
+            finalizer: None,
+        }
+    }
+
+    pub fn with_methods(self, methods: &'static [Native]) -> Self {
+        Self { methods, ..self }
+    }
+
+    pub fn with_finalizer(self, finalizer: Finalizer) -> Self {
+        Self {
+            finalizer: Some(finalizer),
+            ..self
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn methods(&self) -> &'static [Native] {
+        self.methods
+    }
+
+    // The data, if it is a T.
+    pub fn downcast<T: Any>(&self) -> Option<&T> {
+        self.data.downcast_ref()
+    }
+
+    // The data, shared with the value, if it is a T.
+    pub fn downcast_arc<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.data.clone().downcast().ok()
+    }
+
+    pub(super) fn finalize(&self) {
+        if let Some(finalizer) = self.finalizer {
+            finalizer(self);
+        }
+    }
+}
+
+// Foreign values are only ever the same when they share their data.
+impl PartialEq for Foreign {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+}
+
+impl fmt::Debug for Foreign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Foreign({})", self.type_name)
+    }
+}
+
+impl Value {
+    // Puts the foreign value in the heap. Scripts only keep it for as long
+    // as they can reach it.
+    pub fn foreign(heap: &mut Heap, foreign: Foreign) -> Value {
+        Value::Object(heap.alloc(Object::Foreign(foreign)))
+    }
+
+    // The data of a foreign value, if the value is one and its data is a T.
+    pub fn as_foreign<'a, T: Any>(&self, heap: &'a Heap) -> Option<&'a T> {
+        match heap.get(self.as_object()?) {
+            Object::Foreign(foreign) => foreign.downcast(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use crate::value::*;
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+// Objects that nothing can reach any more are freed by marking every object
+// that can be reached from the roots and sweeping away the rest. The heap
+// can't know what the roots are, so whatever owns it hands them over, and
+// only at points where every value it is still using can be reached from
+// them.
+
+// When the heap is due for a collection. A collection is due once the heap
+// holds as many objects as the threshold, which is then set to the number of
+// objects that survived times the growth factor, so that a heap which keeps
+// growing isn't collected over and over for little gain.
+//
+// A collection marks everything it keeps all at once, but can sweep away
+// the rest a few objects at a time, in between instructions, so that it
+// doesn't hold the script up for as long.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GcConfig {
+    // The threshold before the first collection, which a heap that fills
+    // up with objects it keeps, like one that is loading, can set higher.
+    pub initial_threshold: usize,
+    // The threshold is never set below this, so small heaps aren't
+    // collected all the time.
+    pub min_threshold: usize,
+    pub growth_factor: f64,
+    // How many slots a collection sweeps at a time, or nothing to sweep
+    // them all at once, which is the default.
+    pub step_budget: Option<usize>,
+    // Makes a collection due after every allocation, which frees an object
+    // that is still in use but can't be reached from the roots as soon as
+    // possible, rather than once the heap happens to have grown.
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            initial_threshold: 1024,
+            min_threshold: 1024,
+            growth_factor: 2.0,
+            step_budget: None,
+            stress: false,
+        }
+    }
+}
+
+// A reference that doesn't keep its object alive. Once the object is
+// collected the reference is empty, even if its slot in the heap is handed
+// out again.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Weak {
+    target: Option<Handle>,
+}
+
+impl Weak {
+    pub fn new(target: Handle) -> Self {
+        Self {
+            target: Some(target),
+        }
+    }
+
+    pub fn get(&self) -> Option<Handle> {
+        self.target
+    }
+}
+
+// What the heap holds. Garbage counts until it is collected, but not once
+// it is only waiting to be swept.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct HeapStats {
+    pub objects: usize,
+    // Roughly how many bytes the objects take up, the way the memory limit
+    // counts them.
+    pub bytes: usize,
+    // How many objects there are of each type, by the name scripts know it
+    // by.
+    pub counts: BTreeMap<&'static str, usize>,
+    // How many collections there have been.
+    pub collections: u64,
+}
+
+// A collection that has marked what it keeps, and still has the slots
+// from the next one on to sweep.
+#[derive(Clone, Debug)]
+pub(super) struct Sweep {
+    marked: Vec<bool>,
+    next: usize,
+}
+
+impl Sweep {
+    // Whether the object in the slot is garbage that hasn't been swept.
+    fn is_garbage(&self, index: usize) -> bool {
+        index >= self.next && index < self.marked.len() && !self.marked[index]
+    }
+
+    // An object that takes a free slot the sweep hasn't got to yet is new,
+    // and kept.
+    pub(super) fn keep(&mut self, index: usize) {
+        if let Some(marked) = self.marked.get_mut(index) {
+            *marked = true;
+        }
+    }
+}
+
+impl Heap {
+    pub fn gc_config(&self) -> GcConfig {
+        self.config
+    }
+
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.config = config;
+        self.threshold = config.initial_threshold;
+    }
+
+    // Whether the owner of the heap should collect at its next chance,
+    // which it always should while a collection is sweeping.
+    pub fn is_due(&self) -> bool {
+        if self.sweep.is_some() {
+            true
+        } else if self.config.stress {
+            self.allocated > 0
+        } else {
+            self.len() >= self.threshold
+        }
+    }
+
+    // The most bytes the objects can take up, or nothing for no limit,
+    // which is the default. The heap only estimates how big its objects
+    // are, and counts a string again for every value that holds it.
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        self.changed.clear();
+        self.recount();
+    }
+
+    // How many bytes the objects took up when they were last counted, and
+    // the strings held outside of them.
+    pub fn memory_used(&self) -> usize {
+        self.used + self.outside
+    }
+
+    // Counts a string that was just made, which nothing may hold on to but
+    // the owner of the heap, until the owner counts what it holds again.
+    pub(crate) fn count_string(&mut self, s: &str) {
+        if self.limit.is_some() {
+            self.outside += s.len();
+        }
+    }
+
+    // Sets how many bytes of strings the owner of the heap holds outside of
+    // the objects, which it should count again before it gives up on the
+    // limit.
+    pub(crate) fn set_outside(&mut self, bytes: usize) {
+        self.outside = bytes;
+    }
+
+    // Whether the objects take up more than the limit, counting the ones
+    // that changed since they were last counted again. Garbage counts until
+    // it is collected, so the owner of the heap should collect before it
+    // gives up.
+    pub fn is_over_limit(&mut self) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        for handle in std::mem::take(&mut self.changed) {
+            let size = self.get(handle).size();
+            self.used = self.used - self.sizes[handle.index()] + size;
+            self.sizes[handle.index()] = size;
+        }
+        self.used + self.outside > limit
+    }
+
+    fn recount(&mut self) {
+        self.used = 0;
+        for (object, size) in self.objects.iter().zip(&mut self.sizes) {
+            *size = object.as_ref().map_or(0, Object::size);
+            self.used += *size;
+        }
+    }
+
+    // Whether a collection has marked what it keeps, and is still sweeping
+    // away the rest.
+    pub fn is_sweeping(&self) -> bool {
+        self.sweep.is_some()
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            collections: self.collections,
+            ..HeapStats::default()
+        };
+        for (index, object) in self.objects.iter().enumerate() {
+            let object = match object {
+                Some(object) => object,
+                None => continue,
+            };
+            if self
+                .sweep
+                .as_ref()
+                .is_some_and(|sweep| sweep.is_garbage(index))
+            {
+                continue;
+            }
+            stats.objects += 1;
+            stats.bytes += object.size();
+            *stats.counts.entry(object.type_name()).or_insert(0) += 1;
+        }
+        stats
+    }
+
+    // Frees every object that can't be reached from the roots, giving back
+    // how many were freed. The foreign values freed are finalized once
+    // everything is swept, and the names nothing holds a symbol of are
+    // forgotten along with them. A collection that was still sweeping is
+    // finished first.
+    pub fn collect<I>(&mut self, roots: I) -> usize
+    where
+        I: IntoIterator<Item = Handle>,
+    {
+        let freed = self.sweep(usize::MAX);
+        self.mark(roots);
+        freed + self.sweep(usize::MAX)
+    }
+
+    // Starts a collection that sweeps as the step budget allows, and takes
+    // its first step, giving back how many objects that freed.
+    pub fn start_collection<I>(&mut self, roots: I) -> usize
+    where
+        I: IntoIterator<Item = Handle>,
+    {
+        let freed = self.sweep(usize::MAX);
+        self.mark(roots);
+        freed + self.sweep_step()
+    }
+
+    // Sweeps as many slots as the step budget allows, giving back how many
+    // objects that freed. Does nothing unless a collection is sweeping.
+    pub fn sweep_step(&mut self) -> usize {
+        self.sweep(self.config.step_budget.unwrap_or(usize::MAX))
+    }
+
+    // Weak references to what isn't marked are emptied right away, since
+    // their objects can't be got at again even before they are swept.
+    fn mark<I>(&mut self, roots: I)
+    where
+        I: IntoIterator<Item = Handle>,
+    {
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<Handle> = roots.into_iter().collect();
+        while let Some(handle) = pending.pop() {
+            if marked[handle.index()] {
+                continue;
+            }
+            marked[handle.index()] = true;
+            self.get(handle).references(&mut pending);
+        }
+        for object in self.objects.iter_mut().flatten() {
+            if let Object::Weak(weak) = object {
+                if weak.target.is_some_and(|target| !marked[target.index()]) {
+                    weak.target = None;
+                }
+            }
+        }
+        self.collections += 1;
+        self.sweep = Some(Sweep { marked, next: 0 });
+    }
+
+    fn sweep(&mut self, budget: usize) -> usize {
+        let sweep = match &mut self.sweep {
+            Some(sweep) => sweep,
+            None => return 0,
+        };
+        let end = sweep.next.saturating_add(budget).min(sweep.marked.len());
+        let mut freed = 0;
+        let mut finalized = Vec::new();
+        for index in sweep.next..end {
+            if sweep.marked[index] {
+                continue;
+            }
+            match self.objects[index].take() {
+                None => continue,
+                Some(Object::Foreign(foreign)) => finalized.push(foreign),
+                Some(Object::Class(_)) => self.classes += 1,
+                Some(_) => {}
+            }
+            self.free.push(index as u32);
+            freed += 1;
+        }
+        sweep.next = end;
+        if end == sweep.marked.len() {
+            self.sweep = None;
+            self.symbols.sweep();
+            self.allocated = 0;
+            self.changed.clear();
+            self.recount();
+            let grown = (self.len() as f64 * self.config.growth_factor) as usize;
+            self.threshold = grown.max(self.config.min_threshold);
+        }
+        for foreign in finalized {
+            foreign.finalize();
+        }
+        freed
+    }
+
+    // Puts every object in the slot it was saved from, for restoring a
+    // snapshot, and frees the slots in between. The heap has to be new,
+    // though it can have interned the names the objects use.
+    pub(crate) fn restore(&mut self, objects: Vec<Option<Object>>) {
+        self.free = (0..objects.len() as u32)
+            .rev()
+            .filter(|&index| objects[index as usize].is_none())
+            .collect();
+        self.sizes = vec![0; objects.len()];
+        self.objects = objects;
+        self.sweep = None;
+        self.allocated = 0;
+        self.changed.clear();
+        self.classes += 1;
+        self.recount();
+        let grown = (self.len() as f64 * self.config.growth_factor) as usize;
+        self.threshold = grown.max(self.config.min_threshold);
+    }
+}
+
+impl Value {
+    // The bytes of the string the value holds, if it holds one.
+    pub(crate) fn string_bytes(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+            _ => 0,
+        }
+    }
+}
+
+fn string_bytes<'a>(values: impl Iterator<Item = &'a Value>) -> usize {
+    values.map(Value::string_bytes).sum()
+}
+
+impl Object {
+    // Roughly how many bytes the object takes up, enough to tell a heap that
+    // keeps growing from one that doesn't. The strings it holds count too,
+    // since they can be far bigger than the object.
+    pub(super) fn size(&self) -> usize {
+        let value = size_of::<Value>();
+        let entry = size_of::<(Key, Value)>() + size_of::<(Key, usize)>();
+        let field = size_of::<(Symbol, Value)>() + size_of::<(Symbol, usize)>();
+        let member = size_of::<(String, Value)>();
+        let symbol = size_of::<(Symbol, Value)>();
+        size_of::<Object>()
+            + match self {
+                Object::List(items) => items.len() * value + string_bytes(items.iter()),
+                Object::Map(map) => {
+                    let keys = map.iter().map(|(key, _)| match key {
+                        Key::String(s) => s.len(),
+                        _ => 0,
+                    });
+                    map.len() * entry
+                        + keys.sum::<usize>()
+                        + string_bytes(map.iter().map(|(_, v)| v))
+                }
+                Object::Scope(scope) => {
+                    scope.vars.len() * member + string_bytes(scope.vars.values())
+                }
+                Object::Closure(closure) => closure.size(),
+                Object::Class(class) => {
+                    (class.methods.len() + class.getters.len() + class.setters.len()) * symbol
+                }
+                Object::Instance(instance) => {
+                    let values = instance.fields.iter().map(|(_, v)| v);
+                    instance.fields.len() * field + string_bytes(values)
+                }
+                Object::Iterator(Iter {
+                    items: Items::Values(values),
+                    ..
+                }) => values.len() * value + string_bytes(values.iter()),
+                Object::Upvalue(vm::Upvalue::Closed(value)) => value.string_bytes(),
+                Object::Coroutine(coroutine) => coroutine.size(),
+                Object::Globals(globals) => globals.size(),
+                _ => 0,
+            }
+    }
+
+    // Adds the objects this one refers to.
+    pub(crate) fn references(&self, out: &mut Vec<Handle>) {
+        match self {
+            Object::List(items) => out.extend(items.iter().filter_map(Value::as_object)),
+            Object::Map(map) => {
+                for (key, value) in map.iter() {
+                    if let Key::Object(handle) = key {
+                        out.push(*handle);
+                    }
+                    out.extend(value.as_object());
+                }
+            }
+            // A weak reference doesn't keep its object alive.
+            Object::Range(_)
+            | Object::Native(_)
+            | Object::Host(_)
+            | Object::Foreign(_)
+            | Object::Weak(_) => {}
+            Object::Interpreted(closure) => out.extend(closure.scope),
+            Object::Scope(scope) => {
+                out.extend(scope.vars.values().filter_map(Value::as_object));
+                out.extend(scope.parent);
+            }
+            Object::Closure(closure) => {
+                out.push(closure.globals);
+                out.extend(&closure.upvalues);
+            }
+            Object::Upvalue(vm::Upvalue::Closed(value)) => out.extend(value.as_object()),
+            // An open upvalue's variable is on the stack, which is a root.
+            Object::Upvalue(vm::Upvalue::Open(_)) => {}
+            Object::Class(class) => {
+                out.extend(class.superclass);
+                let methods = class.methods.values();
+                let accessors = class.getters.values().chain(class.setters.values());
+                out.extend(methods.chain(accessors).filter_map(Value::as_object));
+            }
+            Object::Instance(instance) => {
+                out.push(instance.class);
+                out.extend(
+                    instance
+                        .fields
+                        .iter()
+                        .filter_map(|(_, value)| value.as_object()),
+                );
+            }
+            Object::BoundMethod(bound) => {
+                out.extend(bound.receiver.as_object());
+                out.extend(bound.method.as_object());
+            }
+            Object::Iterator(iter) => match &iter.items {
+                Items::List(handle) => out.push(*handle),
+                Items::Range(_) => {}
+                Items::Values(values) => out.extend(values.iter().filter_map(Value::as_object)),
+            },
+            Object::Coroutine(coroutine) => coroutine.references(out),
+            Object::Globals(globals) => globals.references(out),
+        }
+    }
+}
@@ -0,0 +1,677 @@
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::builtins;
+use crate::desugar::ITER_METHOD;
+use crate::value::*;
+
+// The operators and the reading and writing of parts of values, which the
+// interpreter and the virtual machine share so that they can't disagree on
+// what a script does. Errors are given back as their message, for the
+// caller to put at the place in the source it is running.
+//
+// An instance can stand in for a builtin value by having a method named for
+// an operator, which is handed back bound to it to be called with the other
+// operands: the right operand for a binary operator, the index for reading
+// an item and the index and the value for writing one.
+
+pub fn unary(heap: &mut Heap, op: UnaryOp, operand: &Value) -> Result<Access<Value>, String> {
+    let name = match op {
+        UnaryOp::Not => None,
+        UnaryOp::Negate => Some("__neg"),
+        UnaryOp::BitNot => Some("__bitnot"),
+    };
+    if let Some(method) = name.and_then(|name| special_method(heap, operand, name)) {
+        return Ok(Access::Call(method));
+    }
+    match (op, operand) {
+        (UnaryOp::Not, _) => Ok(Access::Done(Value::Bool(!operand.is_truthy()))),
+        (UnaryOp::Negate, Value::Int(n)) => match n.checked_neg() {
+            Some(n) => Ok(Access::Done(Value::Int(n))),
+            None => overflowed(BinaryOp::Subtract, &Value::Int(0), operand).map(Access::Done),
+        },
+        (UnaryOp::Negate, Value::Float(n)) => Ok(Access::Done(Value::Float(-n))),
+        #[cfg(feature = "bigint")]
+        (UnaryOp::Negate, Value::BigInt(n)) => Ok(Access::Done(Value::from(-&**n))),
+        (UnaryOp::BitNot, Value::Int(n)) => Ok(Access::Done(Value::Int(!n))),
+        _ => Err(format!(
+            "cannot use {} with {}",
+            op.symbol(),
+            operand.type_name(heap)
+        )),
+    }
+}
+
+// `and` and `or` only ever look at one side at a time, so they are left to
+// the caller. The left operand is the one asked for a method, and `!=` asks
+// for `__eq`, whose result the caller turns around. Equality only asks when
+// both sides are instances, so comparing one with null never runs a method.
+pub fn binary(
+    heap: &mut Heap,
+    op: BinaryOp,
+    left: &Value,
+    right: &Value,
+) -> Result<Access<Value>, String> {
+    let name = match op {
+        BinaryOp::Equal | BinaryOp::NotEqual if is_instance(heap, right) => Some("__eq"),
+        BinaryOp::Equal | BinaryOp::NotEqual | BinaryOp::And | BinaryOp::Or => None,
+        BinaryOp::Add => Some("__add"),
+        BinaryOp::Subtract => Some("__sub"),
+        BinaryOp::Multiply => Some("__mul"),
+        BinaryOp::Divide => Some("__div"),
+        BinaryOp::Modulo => Some("__mod"),
+        BinaryOp::Less => Some("__lt"),
+        BinaryOp::LessEqual => Some("__le"),
+        BinaryOp::Greater => Some("__gt"),
+        BinaryOp::GreaterEqual => Some("__ge"),
+        BinaryOp::BitAnd => Some("__bitand"),
+        BinaryOp::BitOr => Some("__bitor"),
+        BinaryOp::BitXor => Some("__bitxor"),
+    };
+    if let Some(method) = name.and_then(|name| special_method(heap, left, name)) {
+        return Ok(Access::Call(method));
+    }
+    let value = match (op, left, right) {
+        (BinaryOp::Equal, _, _) => Value::Bool(equal(heap, left, right)),
+        (BinaryOp::NotEqual, _, _) => Value::Bool(!equal(heap, left, right)),
+        (_, left, right) if is_number(left) && is_number(right) => {
+            match arithmetic(op, left, right) {
+                Some(value) => value?,
+                None => return Err(operands(heap, op.symbol(), left, right)),
+            }
+        }
+        (_, Value::String(a), Value::String(b)) => match op {
+            BinaryOp::Add => {
+                let joined = format!("{}{}", a, b);
+                heap.count_string(&joined);
+                Value::from(joined)
+            }
+            BinaryOp::Less => Value::Bool(a < b),
+            BinaryOp::LessEqual => Value::Bool(a <= b),
+            BinaryOp::Greater => Value::Bool(a > b),
+            BinaryOp::GreaterEqual => Value::Bool(a >= b),
+            _ => return Err(operands(heap, op.symbol(), left, right)),
+        },
+        // Adding lists makes a new list with the items of both.
+        (BinaryOp::Add, Value::Object(a), Value::Object(b)) => match (heap.get(*a), heap.get(*b)) {
+            (Object::List(a), Object::List(b)) => {
+                let items = a.iter().chain(b).cloned().collect();
+                Value::Object(heap.alloc(Object::List(items)))
+            }
+            _ => return Err(operands(heap, op.symbol(), left, right)),
+        },
+        _ => return Err(operands(heap, op.symbol(), left, right)),
+    };
+    Ok(Access::Done(value))
+}
+
+// The operators on two numbers. Two ints give an int, which is an error
+// when it doesn't fit, and dividing them rounds toward zero, with the
+// remainder taking the sign of the left side. An int and a float give a
+// float, and they are compared as the numbers they are. The bitwise
+// operators are only for ints. Gives back nothing for an operator that
+// numbers don't have.
+pub fn arithmetic(op: BinaryOp, left: &Value, right: &Value) -> Option<Result<Value, String>> {
+    let value = match (left, right) {
+        (Value::Int(a), Value::Int(b)) => {
+            let (a, b) = (*a, *b);
+            let fits = |n: Option<i64>| match n {
+                Some(n) => Ok(Value::Int(n)),
+                None => overflowed(op, left, right),
+            };
+            match op {
+                BinaryOp::Add => fits(a.checked_add(b)),
+                BinaryOp::Subtract => fits(a.checked_sub(b)),
+                BinaryOp::Multiply => fits(a.checked_mul(b)),
+                BinaryOp::Divide | BinaryOp::Modulo if b == 0 => {
+                    Err(String::from("division by zero"))
+                }
+                BinaryOp::Divide => fits(a.checked_div(b)),
+                // The smallest int over -1 overflows, but its remainder is 0.
+                BinaryOp::Modulo => Ok(Value::Int(a.wrapping_rem(b))),
+                BinaryOp::Equal => Ok(Value::Bool(a == b)),
+                BinaryOp::NotEqual => Ok(Value::Bool(a != b)),
+                BinaryOp::Less => Ok(Value::Bool(a < b)),
+                BinaryOp::LessEqual => Ok(Value::Bool(a <= b)),
+                BinaryOp::Greater => Ok(Value::Bool(a > b)),
+                BinaryOp::GreaterEqual => Ok(Value::Bool(a >= b)),
+                BinaryOp::BitAnd => Ok(Value::Int(a & b)),
+                BinaryOp::BitOr => Ok(Value::Int(a | b)),
+                BinaryOp::BitXor => Ok(Value::Int(a ^ b)),
+                BinaryOp::And | BinaryOp::Or => return None,
+            }
+        }
+        #[cfg(feature = "bigint")]
+        (Value::BigInt(_), Value::Int(_))
+        | (Value::Int(_), Value::BigInt(_))
+        | (Value::BigInt(_), Value::BigInt(_)) => return bigints(op, left, right),
+        _ => {
+            let (a, b) = (left.as_number()?, right.as_number()?);
+            match op {
+                BinaryOp::Add => Ok(Value::Float(a + b)),
+                BinaryOp::Subtract => Ok(Value::Float(a - b)),
+                BinaryOp::Multiply => Ok(Value::Float(a * b)),
+                BinaryOp::Divide | BinaryOp::Modulo if b == 0.0 => {
+                    Err(String::from("division by zero"))
+                }
+                BinaryOp::Divide => Ok(Value::Float(a / b)),
+                BinaryOp::Modulo => Ok(Value::Float(a % b)),
+                BinaryOp::Equal => Ok(Value::Bool(numbers_equal(left, right))),
+                BinaryOp::NotEqual => Ok(Value::Bool(!numbers_equal(left, right))),
+                BinaryOp::Less => Ok(Value::Bool(a < b)),
+                BinaryOp::LessEqual => Ok(Value::Bool(a <= b)),
+                BinaryOp::Greater => Ok(Value::Bool(a > b)),
+                BinaryOp::GreaterEqual => Ok(Value::Bool(a >= b)),
+                _ => return None,
+            }
+        }
+    };
+    Some(value)
+}
+
+// An int that doesn't fit in 64 bits is an error, unless bigints are turned
+// on, when it is worked out again as one.
+#[cfg(not(feature = "bigint"))]
+fn overflowed(_: BinaryOp, _: &Value, _: &Value) -> Result<Value, String> {
+    Err(String::from("integer overflow"))
+}
+
+#[cfg(feature = "bigint")]
+fn overflowed(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    bigints(op, left, right).expect("bigints have every operator that overflows")
+}
+
+// The operators on ints where at least one is a bigint, or would be. The
+// bitwise operators aren't for bigints.
+#[cfg(feature = "bigint")]
+fn bigints(op: BinaryOp, left: &Value, right: &Value) -> Option<Result<Value, String>> {
+    use crate::value::bigint::BigInt;
+
+    let big = |value: &Value| match value {
+        Value::Int(n) => Some(BigInt::from(*n)),
+        Value::BigInt(n) => Some(BigInt::clone(n)),
+        _ => None,
+    };
+    let (a, b) = (big(left)?, big(right)?);
+    let value = match op {
+        BinaryOp::Add => Value::from(&a + &b),
+        BinaryOp::Subtract => Value::from(&a - &b),
+        BinaryOp::Multiply => Value::from(&a * &b),
+        BinaryOp::Divide | BinaryOp::Modulo if b.is_zero() => {
+            return Some(Err(String::from("division by zero")));
+        }
+        BinaryOp::Divide => Value::from(a.div_rem(&b).0),
+        BinaryOp::Modulo => Value::from(a.div_rem(&b).1),
+        BinaryOp::Equal => Value::Bool(a == b),
+        BinaryOp::NotEqual => Value::Bool(a != b),
+        BinaryOp::Less => Value::Bool(a < b),
+        BinaryOp::LessEqual => Value::Bool(a <= b),
+        BinaryOp::Greater => Value::Bool(a > b),
+        BinaryOp::GreaterEqual => Value::Bool(a >= b),
+        _ => return None,
+    };
+    Some(Ok(value))
+}
+
+fn is_number(value: &Value) -> bool {
+    match value {
+        Value::Int(_) | Value::Float(_) => true,
+        #[cfg(feature = "bigint")]
+        Value::BigInt(_) => true,
+        _ => false,
+    }
+}
+
+// An int is only equal to a float that holds exactly it, which turning the
+// int into a float can't tell once it is too big for one.
+fn numbers_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => {
+            whole(*b) == Some(*a)
+        }
+        #[cfg(feature = "bigint")]
+        (Value::BigInt(a), Value::Float(b)) | (Value::Float(b), Value::BigInt(a)) => {
+            bigint::BigInt::from_f64(*b).as_ref() == Some(&**a)
+        }
+        (Value::Float(a), Value::Float(b)) => a == b,
+        // Ints that fit are never bigints, so they can't be the same number.
+        _ => left == right,
+    }
+}
+
+// Lists are equal when their items are, and every other value is only
+// equal to itself. Lists that hold themselves are taken to be equal once
+// comparing them comes back around to the same two lists.
+pub fn equal(heap: &Heap, left: &Value, right: &Value) -> bool {
+    equal_in(heap, left, right, &mut Vec::new())
+}
+
+fn equal_in(
+    heap: &Heap,
+    left: &Value,
+    right: &Value,
+    comparing: &mut Vec<(Handle, Handle)>,
+) -> bool {
+    if left == right {
+        return true;
+    }
+    let (a, b) = match (left, right) {
+        (left, right) if is_number(left) && is_number(right) => {
+            return numbers_equal(left, right);
+        }
+        (Value::Object(a), Value::Object(b)) => (*a, *b),
+        _ => return false,
+    };
+    let (xs, ys) = match (heap.get(a), heap.get(b)) {
+        (Object::List(xs), Object::List(ys)) if xs.len() == ys.len() => (xs, ys),
+        _ => return false,
+    };
+    if comparing.contains(&(a, b)) {
+        return true;
+    }
+    comparing.push((a, b));
+    let equal = xs
+        .iter()
+        .zip(ys)
+        .all(|(x, y)| equal_in(heap, x, y, comparing));
+    comparing.pop();
+    equal
+}
+
+fn operands(heap: &Heap, op: &str, left: &Value, right: &Value) -> String {
+    format!(
+        "cannot use {} with {} and {}",
+        op,
+        left.type_name(heap),
+        right.type_name(heap)
+    )
+}
+
+pub fn range(
+    heap: &mut Heap,
+    start: &Value,
+    end: &Value,
+    inclusive: bool,
+) -> Result<Value, String> {
+    match (start, end) {
+        (Value::Int(start), Value::Int(end)) => {
+            let range = Range {
+                start: *start,
+                end: *end,
+                inclusive,
+            };
+            Ok(Value::Object(heap.alloc(Object::Range(range))))
+        }
+        _ => {
+            let op = if inclusive { "..=" } else { ".." };
+            Err(operands(heap, op, start, end))
+        }
+    }
+}
+
+pub fn key(value: &Value) -> Result<Key, String> {
+    value
+        .key()
+        .ok_or_else(|| String::from("cannot use NaN as a map key"))
+}
+
+// Reading or writing a property of an instance can run an accessor, which
+// only the caller can call. It is handed back bound to the instance, to be
+// called with nothing when reading and with the value when writing.
+pub enum Access<T> {
+    Done(T),
+    Call(Value),
+}
+
+// The methods of a builtin value come before the keys of a map, so that a
+// map can always be gone through whatever keys it has, and reading a key a
+// map doesn't have gives null. An instance has its fields, then the getters
+// and then the methods of its class, and reading anything else is an error.
+pub fn get_property(
+    heap: &mut Heap,
+    object: &Value,
+    name: &Symbol,
+) -> Result<Access<Value>, String> {
+    if let Some(native) = builtins::method(heap, object, name) {
+        let bound = BoundMethod {
+            receiver: object.clone(),
+            method: Value::Object(heap.alloc(Object::Native(native))),
+        };
+        return Ok(Access::Done(Value::Object(
+            heap.alloc(Object::BoundMethod(bound)),
+        )));
+    }
+    if let Value::Object(handle) = object {
+        match heap.get(*handle) {
+            Object::Map(map) => {
+                let key = Key::from(name);
+                return Ok(Access::Done(map.get(&key).cloned().unwrap_or_default()));
+            }
+            Object::Instance(instance) => {
+                let class = instance.class;
+                return match find_property(heap, instance, name) {
+                    Some(Found::Field(value)) => Ok(Access::Done(value)),
+                    Some(Found::Getter(getter)) => Ok(Access::Call(bind(heap, object, getter))),
+                    Some(Found::Method(method)) => Ok(Access::Done(bind(heap, object, method))),
+                    None => Err(format!(
+                        "{} has no property {}",
+                        class_name(heap, class),
+                        name
+                    )),
+                };
+            }
+            _ => {}
+        }
+    }
+    // Only a for loop asks a value for an iterator without knowing it has
+    // one.
+    if &name[..] == ITER_METHOD {
+        return Err(format!("cannot iterate over {}", object.type_name(heap)));
+    }
+    Err(format!(
+        "cannot read property {} of {}",
+        name,
+        object.type_name(heap)
+    ))
+}
+
+// What a property of an instance is, which is looked for the same way
+// reading it does but left for the caller to bind.
+pub enum Found {
+    Field(Value),
+    Getter(Value),
+    Method(Value),
+}
+
+pub fn find_property(heap: &Heap, instance: &Instance, name: &Symbol) -> Option<Found> {
+    if let Some(value) = instance.fields.get(name) {
+        return Some(Found::Field(value.clone()));
+    }
+    if let Some(getter) = lookup(heap, instance.class, Member::Getter, name) {
+        return Some(Found::Getter(getter));
+    }
+    lookup(heap, instance.class, Member::Method, name).map(Found::Method)
+}
+
+// A property of an instance with a setter is set by calling it, and one
+// with only a getter can't be set at all.
+pub fn set_property(
+    heap: &mut Heap,
+    object: &Value,
+    name: &Symbol,
+    value: Value,
+) -> Result<Access<()>, String> {
+    if let Value::Object(handle) = object {
+        match heap.get(*handle) {
+            Object::Map(_) => {
+                if let Object::Map(map) = heap.get_mut(*handle) {
+                    map.insert(Key::from(name), value);
+                }
+                return Ok(Access::Done(()));
+            }
+            Object::Instance(instance) => {
+                let class = instance.class;
+                if let Some(setter) = lookup(heap, class, Member::Setter, name) {
+                    return Ok(Access::Call(bind(heap, object, setter)));
+                }
+                if lookup(heap, class, Member::Getter, name).is_some() {
+                    return Err(format!(
+                        "property {} of {} has no setter",
+                        name,
+                        class_name(heap, class)
+                    ));
+                }
+                if let Object::Instance(instance) = heap.get_mut(*handle) {
+                    instance.fields.insert(name.clone(), value);
+                }
+                return Ok(Access::Done(()));
+            }
+            _ => {}
+        }
+    }
+    Err(format!(
+        "cannot set property {} of {}",
+        name,
+        object.type_name(heap)
+    ))
+}
+
+#[derive(Copy, Clone)]
+enum Member {
+    Method,
+    Getter,
+    Setter,
+}
+
+// Looks for a member in the class and then in the classes it extends.
+fn lookup(heap: &Heap, class: Handle, member: Member, name: &Symbol) -> Option<Value> {
+    let mut current = Some(class);
+    while let Some(handle) = current {
+        let class = match heap.get(handle) {
+            Object::Class(class) => class,
+            _ => unreachable!("classes only ever extend classes"),
+        };
+        let members = match member {
+            Member::Method => &class.methods,
+            Member::Getter => &class.getters,
+            Member::Setter => &class.setters,
+        };
+        if let Some(value) = members.get(name) {
+            return Some(value.clone());
+        }
+        current = class.superclass;
+    }
+    None
+}
+
+pub fn find_method(heap: &Heap, class: Handle, name: &str) -> Option<Value> {
+    let name = heap.symbol(name)?;
+    lookup(heap, class, Member::Method, &name)
+}
+
+fn is_instance(heap: &Heap, value: &Value) -> bool {
+    match value {
+        Value::Object(handle) => matches!(heap.get(*handle), Object::Instance(_)),
+        _ => false,
+    }
+}
+
+// The method of an instance for an operator, bound to it.
+fn special_method(heap: &mut Heap, object: &Value, name: &str) -> Option<Value> {
+    let class = match object {
+        Value::Object(handle) => match heap.get(*handle) {
+            Object::Instance(instance) => instance.class,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let method = find_method(heap, class, name)?;
+    Some(bind(heap, object, method))
+}
+
+fn class_name(heap: &Heap, class: Handle) -> &str {
+    match heap.get(class) {
+        Object::Class(class) => &class.name[..],
+        _ => unreachable!("instances only ever have classes"),
+    }
+}
+
+// Methods that are functions are bound to the instance they were read
+// from. A decorator can make a method anything, and anything else is left
+// as it is.
+pub fn bind(heap: &mut Heap, receiver: &Value, method: Value) -> Value {
+    let is_function = match &method {
+        Value::Object(handle) => matches!(
+            heap.get(*handle),
+            Object::Interpreted(_) | Object::Closure(_)
+        ),
+        _ => false,
+    };
+    if !is_function {
+        return method;
+    }
+    let bound = BoundMethod {
+        receiver: receiver.clone(),
+        method,
+    };
+    Value::Object(heap.alloc(Object::BoundMethod(bound)))
+}
+
+pub fn inherit(heap: &mut Heap, class: Handle, superclass: &Value) -> Result<(), String> {
+    match superclass {
+        Value::Object(handle) if matches!(heap.get(*handle), Object::Class(_)) => {
+            let handle = *handle;
+            if let Object::Class(class) = heap.get_mut(class) {
+                class.superclass = Some(handle);
+            }
+            Ok(())
+        }
+        _ => Err(format!("cannot extend {}", superclass.type_name(heap))),
+    }
+}
+
+// Reads a method of the superclass for `super`, bound to `this`.
+pub fn get_super(
+    heap: &mut Heap,
+    receiver: &Value,
+    superclass: &Value,
+    name: &Symbol,
+) -> Result<Value, String> {
+    let class = match superclass {
+        Value::Object(handle) if matches!(heap.get(*handle), Object::Class(_)) => *handle,
+        _ => return Err(format!("cannot extend {}", superclass.type_name(heap))),
+    };
+    match lookup(heap, class, Member::Method, name) {
+        Some(method) => Ok(bind(heap, receiver, method)),
+        None => Err(format!(
+            "{} has no method {}",
+            class_name(heap, class),
+            name
+        )),
+    }
+}
+
+// The position an int refers to in a list or string of the length.
+// Negative ints count back from the end, so -1 is the last position.
+pub fn position(heap: &Heap, what: &str, index: &Value, len: usize) -> Result<usize, String> {
+    match index {
+        Value::Int(n) => {
+            let i = if *n < 0 {
+                n.checked_add(len as i64)
+            } else {
+                Some(*n)
+            };
+            match i {
+                Some(i) if i >= 0 && (i as u64) < len as u64 => Ok(i as usize),
+                _ => Err(format!("{} index {} is out of range", what, index)),
+            }
+        }
+        index => Err(format!(
+            "cannot index {} with {}",
+            what,
+            index.type_name(heap)
+        )),
+    }
+}
+
+pub fn get_index(heap: &mut Heap, object: &Value, index: &Value) -> Result<Access<Value>, String> {
+    if let Some(method) = special_method(heap, object, "__index") {
+        return Ok(Access::Call(method));
+    }
+    match object {
+        Value::String(s) => {
+            let i = position(heap, "string", index, s.chars().count())?;
+            let c = s.chars().nth(i).expect("the index is in range");
+            return Ok(Access::Done(Value::from(c.to_string())));
+        }
+        Value::Object(handle) => match heap.get(*handle) {
+            Object::List(items) => {
+                let i = position(heap, "list", index, items.len())?;
+                return Ok(Access::Done(items[i].clone()));
+            }
+            Object::Map(map) => {
+                let value = index.key().and_then(|key| map.get(&key).cloned());
+                return Ok(Access::Done(value.unwrap_or_default()));
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+    Err(format!("cannot index {}", object.type_name(heap)))
+}
+
+pub fn set_index(
+    heap: &mut Heap,
+    object: &Value,
+    index: &Value,
+    value: Value,
+) -> Result<Access<()>, String> {
+    if let Some(method) = special_method(heap, object, "__setindex") {
+        return Ok(Access::Call(method));
+    }
+    if let Value::Object(handle) = object {
+        match heap.get(*handle) {
+            Object::List(items) => {
+                let i = position(heap, "list", index, items.len())?;
+                if let Object::List(items) = heap.get_mut(*handle) {
+                    items[i] = value;
+                }
+                return Ok(Access::Done(()));
+            }
+            Object::Map(_) => {
+                let key = key(index)?;
+                if let Object::Map(map) = heap.get_mut(*handle) {
+                    map.insert(key, value);
+                }
+                return Ok(Access::Done(()));
+            }
+            _ => {}
+        }
+    }
+    Err(format!("cannot index {}", object.type_name(heap)))
+}
+
+// Negative bounds count back from the end, the same as indices do. Bounds
+// past either end are moved to that end, so slicing never fails on a list or
+// string that is shorter than expected.
+pub fn slice(heap: &mut Heap, object: &Value, start: &Value, end: &Value) -> Result<Value, String> {
+    let len = match object {
+        Value::String(s) => s.chars().count(),
+        Value::Object(handle) => match heap.get(*handle) {
+            Object::List(items) => items.len(),
+            object => return Err(format!("cannot slice {}", object.type_name())),
+        },
+        _ => return Err(format!("cannot slice {}", object.type_name(heap))),
+    };
+    let mut bounds = [0, len];
+    for (bound, value) in bounds.iter_mut().zip([start, end].iter()) {
+        match value {
+            Value::Null => {}
+            Value::Int(n) => {
+                let n = if *n < 0 {
+                    n.saturating_add(len as i64)
+                } else {
+                    *n
+                };
+                *bound = n.clamp(0, len as i64) as usize;
+            }
+            _ => return Err(String::from("slice bounds must be ints")),
+        }
+    }
+    let [start, end] = bounds;
+    let end = end.max(start);
+    let value = match object {
+        Value::String(s) => {
+            let sliced: String = s.chars().skip(start).take(end - start).collect();
+            heap.count_string(&sliced);
+            Value::from(sliced)
+        }
+        Value::Object(handle) => {
+            let items = match heap.get(*handle) {
+                Object::List(items) => items[start..end].to_vec(),
+                _ => unreachable!("only lists are sliced"),
+            };
+            Value::Object(heap.alloc(Object::List(items)))
+        }
+        _ => unreachable!("only lists and strings are sliced"),
+    };
+    Ok(value)
+}
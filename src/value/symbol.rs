@@ -0,0 +1,116 @@
+use crate::value::{Heap, Key, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+// A symbol is a string the heap has interned, like the name of a method or
+// a field. The heap hands out one copy of every name, so two symbols are the
+// same name exactly when they share it, and looking a symbol up compares and
+// hashes where its string is rather than the string itself. Symbols only
+// mean anything to the heap that made them.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as *const u8 as usize).hash(state);
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &self.0)
+    }
+}
+
+// A symbol is the same string as any other, so it can be a map key or a
+// value without being copied.
+impl From<&Symbol> for Key {
+    fn from(symbol: &Symbol) -> Self {
+        Key::String(symbol.0.clone())
+    }
+}
+
+impl From<&Symbol> for Value {
+    fn from(symbol: &Symbol) -> Self {
+        Value::String(symbol.0.clone())
+    }
+}
+
+// The names a heap has interned. A name nothing holds a symbol of anymore
+// is forgotten when garbage is collected, and the next symbol of it is a
+// new one, which is safe because no older one is left to compare it with.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Symbols {
+    names: HashSet<Arc<str>>,
+}
+
+impl Symbols {
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(name) = self.names.get(name) {
+            return Symbol(name.clone());
+        }
+        let name: Arc<str> = Arc::from(name);
+        self.names.insert(name.clone());
+        Symbol(name)
+    }
+
+    fn get(&self, name: &str) -> Option<Symbol> {
+        self.names.get(name).cloned().map(Symbol)
+    }
+
+    pub(super) fn sweep(&mut self) {
+        self.names.retain(|name| Arc::strong_count(name) > 1);
+    }
+}
+
+impl Heap {
+    // The symbol of the name, which is the same one every time. Natives
+    // that look the same names up over and over can intern them once and
+    // keep the symbols.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        self.symbols.intern(name)
+    }
+
+    // The symbol of the name, if it has one already. A name that was never
+    // interned isn't a member of any class or a field of any instance, so
+    // there is nothing to look it up in.
+    pub fn symbol(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name)
+    }
+
+    // How many names are interned.
+    pub fn symbol_count(&self) -> usize {
+        self.symbols.names.len()
+    }
+}
@@ -0,0 +1,1440 @@
+// Gives back the error of an operation, placed at the instruction it came
+// from. It comes before the modules so that the instructions can use it.
+macro_rules! attempt {
+    ($vm:ident, $start:ident, $result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(msg) => return Err($vm.fail($start, &msg[..]).into()),
+        }
+    };
+}
+
+pub mod cache;
+pub mod debug;
+pub mod host;
+mod instructions;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod module;
+pub mod profile;
+pub mod snapshot;
+mod trace;
+
+use crate::ast::BinaryOp;
+use crate::builtins::{Context, Native, BUILTINS};
+use crate::bytecode::{Constant, Function, OpCode};
+use crate::compile::Signature;
+use crate::error::{RuntimeError, RuntimeErrorKind, TraceFrame};
+use crate::parse::INITIALIZER_NAME;
+use crate::value::convert::{FromAtom, IntoAtom};
+use crate::value::gc::{GcConfig, HeapStats};
+use crate::value::ops;
+use crate::value::symbol::Symbol;
+use crate::value::*;
+use cache::{Cache, InlineCaches};
+use debug::Debugger;
+#[cfg(feature = "threaded-dispatch")]
+use instructions::Threaded;
+use instructions::{Flow, Instruction};
+use module::ModuleLoader;
+use profile::Profiler;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The virtual machine runs the bytecode of the stack backend. Every call has
+// a frame whose slots are a window onto one value stack, starting with the
+// function being called and its arguments, followed by the locals and then
+// whatever the instructions are working on.
+//
+// A closure keeps the variables it captures in upvalues. While the function
+// that declared the variable is still running, the upvalue points at the
+// variable's slot on the stack, so the function and the closure see the same
+// variable. Once the slot is popped the value moves into the upvalue, which
+// is called closing it.
+//
+// A coroutine runs on the same stack, with its frames on top of the ones
+// that resumed it. When it yields, its frames and the slots above the one it
+// was resumed from are moved out into the coroutine, and they are moved
+// back on top of whichever frames resume it next.
+//
+// Garbage is only collected between instructions, when every value the
+// script is using is on the stack or in a global.
+//
+// Every VM has a heap and globals of its own, and the only things VMs share
+// are compiled functions and strings, which never change. So a VM can be
+// sent to another thread, and as many can run at once as there are threads.
+
+// How deep calls can go before the script is stopped. Frames are on a stack
+// of their own rather than the stack of the thread, so this only keeps a
+// runaway recursion from taking all the memory there is.
+pub const MAX_FRAMES: usize = 1024;
+
+// A function that is ready to run. Closures are made from the functions in
+// its constants over and over, so those are made into prototypes once,
+// found by the index of their constant. The names of the properties and
+// methods it uses are interned up front the same way.
+#[derive(PartialEq, Debug)]
+pub struct Prototype {
+    pub function: Function,
+    functions: Vec<Option<Arc<Prototype>>>,
+    symbols: Vec<Option<Symbol>>,
+    // Which of the caches of its closures every instruction that reads or
+    // writes a property has, by the offset of the instruction.
+    sites: Vec<u32>,
+    caches: usize,
+    // Every instruction decoded ahead of time for threaded dispatch, by the
+    // offset it starts at.
+    #[cfg(feature = "threaded-dispatch")]
+    threaded: Vec<Threaded>,
+    // How hot the function is, and its native code once it is compiled.
+    #[cfg(feature = "jit")]
+    tier: jit::Tier,
+    // The script the function is in, for errors.
+    file: Arc<str>,
+}
+
+impl Prototype {
+    // The functions in the constants are moved into prototypes of their own
+    // rather than copied, leaving an empty function of the same name.
+    fn new(mut function: Function, file: &Arc<str>, heap: &mut Heap) -> Self {
+        let nested: Vec<Function> = function
+            .chunk
+            .functions_mut()
+            .map(|nested| {
+                let empty = Function::new(&nested.name);
+                std::mem::replace(nested, empty)
+            })
+            .collect();
+        let mut nested = nested.into_iter();
+        let functions = function
+            .chunk
+            .constants()
+            .iter()
+            .map(|constant| match constant {
+                Constant::Function(_) => {
+                    let nested = nested.next().expect("every function was moved out");
+                    Some(Arc::new(Prototype::new(nested, file, heap)))
+                }
+                _ => None,
+            })
+            .collect();
+        let chunk = &function.chunk;
+        let mut symbols = vec![None; chunk.constants().len()];
+        let mut sites = Vec::new();
+        let mut caches = 0;
+        for (offset, op) in chunk.instructions() {
+            if let OpCode::GetProperty | OpCode::SetProperty = op {
+                if sites.is_empty() {
+                    sites = vec![0; chunk.code().len()];
+                }
+                sites[offset] = caches as u32;
+                caches += 1;
+            }
+            let is_name = matches!(
+                op,
+                OpCode::GetProperty
+                    | OpCode::SetProperty
+                    | OpCode::GetSuper
+                    | OpCode::Method
+                    | OpCode::Getter
+                    | OpCode::Setter
+            );
+            let constant = chunk.operand(offset);
+            if let (true, Some(Constant::String(name))) = (is_name, chunk.constants().get(constant))
+            {
+                symbols[constant] = Some(heap.intern(name));
+            }
+        }
+        #[cfg(feature = "threaded-dispatch")]
+        let threaded = instructions::decode(chunk);
+        Self {
+            function,
+            functions,
+            symbols,
+            sites,
+            caches,
+            #[cfg(feature = "threaded-dispatch")]
+            threaded,
+            #[cfg(feature = "jit")]
+            tier: jit::Tier::default(),
+            file: file.clone(),
+        }
+    }
+
+    fn symbol(&self, constant: usize) -> Result<&Symbol, &'static str> {
+        match self.symbols.get(constant) {
+            Some(Some(symbol)) => Ok(symbol),
+            _ => Err("invalid constant"),
+        }
+    }
+
+    fn signature(&self) -> Signature {
+        Signature {
+            arity: self.function.arity,
+            min_arity: self.function.min_arity,
+            rest: self.function.rest,
+        }
+    }
+}
+
+// A function along with the globals of the script it is in, and the
+// upvalues it captured in the order of the operands of `get_upvalue` and
+// `set_upvalue`. The caches of its properties are made the first time it
+// uses one, so closures that never run don't pay for them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Closure {
+    pub proto: Arc<Prototype>,
+    pub globals: Handle,
+    pub upvalues: Vec<Handle>,
+    caches: Vec<Cache>,
+}
+
+impl Closure {
+    pub(crate) fn size(&self) -> usize {
+        self.upvalues.len() * std::mem::size_of::<Handle>()
+            + self.caches.len() * std::mem::size_of::<Cache>()
+    }
+}
+
+// The globals a script declares, which are empty until their declaration
+// runs. Every script and module has its own, which the functions in it keep
+// using wherever they are called from.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Globals {
+    names: Vec<String>,
+    slots: Vec<Option<Value>>,
+}
+
+impl Globals {
+    fn new(names: &[String]) -> Self {
+        Self {
+            names: names.to_vec(),
+            slots: vec![None; names.len()],
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        let strings: usize = self.slots.iter().flatten().map(Value::string_bytes).sum();
+        self.slots.len() * std::mem::size_of::<Option<Value>>() + strings
+    }
+
+    pub(crate) fn references(&self, out: &mut Vec<Handle>) {
+        out.extend(self.slots.iter().flatten().filter_map(Value::as_object));
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Upvalue {
+    // The slot on the stack the variable is in.
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Frame {
+    proto: Arc<Prototype>,
+    closure: Handle,
+    // Where the next instruction starts, which is only kept up to date
+    // while the frame is calling another one.
+    ip: usize,
+    // The slot on the stack of the function being called.
+    base: usize,
+    returned: Returned,
+}
+
+// What becomes of the value a frame returns. Methods the script doesn't
+// call itself stand in for an operation, and a setter leaves the value it
+// was given rather than what it returns, while `!=` turns around what
+// `__eq` returns.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Returned {
+    Kept,
+    Discarded,
+    Negated,
+    // The exports of a module, which are kept for the next import.
+    Imported,
+    // What a function the host called returns, which goes back to it.
+    Host,
+}
+
+// A function that runs a bit at a time, stopping at every yield until it is
+// resumed. While it is stopped its frames are kept here with the slots they
+// use, which are counted from the slot the function was called in.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Coroutine {
+    function: Value,
+    state: CoroutineState,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    // The upvalues of its slots, which are closed while it is stopped so
+    // that closures called in the meantime still find the variables.
+    upvalues: Vec<(usize, Handle)>,
+    // The tries it was inside of, counted from its first frame and slot.
+    handlers: Vec<Handler>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CoroutineState {
+    Suspended,
+    Running,
+    // Stopped until the future of the host it called is done.
+    Waiting,
+    Finished,
+}
+
+impl Coroutine {
+    // A coroutine that calls the function when it is first resumed.
+    pub fn new(function: Value) -> Self {
+        Self {
+            function,
+            state: CoroutineState::Suspended,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            upvalues: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> CoroutineState {
+        self.state
+    }
+
+    // Roughly how many bytes its frames and slots take up while it is
+    // stopped, along with the strings in its slots.
+    pub(crate) fn size(&self) -> usize {
+        self.stack.len() * std::mem::size_of::<Value>()
+            + self.stack.iter().map(Value::string_bytes).sum::<usize>()
+            + self.frames.len() * std::mem::size_of::<Frame>()
+            + self.upvalues.len() * std::mem::size_of::<(usize, Handle)>()
+            + self.handlers.len() * std::mem::size_of::<Handler>()
+    }
+
+    pub(crate) fn references(&self, out: &mut Vec<Handle>) {
+        out.extend(self.function.as_object());
+        out.extend(self.stack.iter().filter_map(Value::as_object));
+        out.extend(self.frames.iter().map(|frame| frame.closure));
+        out.extend(self.upvalues.iter().map(|&(_, upvalue)| upvalue));
+    }
+}
+
+// A coroutine that is running, and where what it yields or returns goes.
+struct Resumed {
+    coroutine: Handle,
+    // The number of frames below the ones of the coroutine.
+    frames: usize,
+    // The slot the coroutine was called in, which its result takes.
+    base: usize,
+    // Whether it was resumed by a tail call, so that the frame which
+    // resumed it returns whatever it hands back.
+    tail: bool,
+}
+
+// Where the script goes on when something is thrown inside of a try.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Handler {
+    // The number of frames, the last being the one the try is in.
+    frames: usize,
+    // The height of the stack at the try, which is what is left of it.
+    depth: usize,
+    ip: usize,
+}
+
+// Stops whatever script a virtual machine is running from another thread,
+// at the next loop or call the script gets to. The script is left paused
+// the same as when it runs out of fuel.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    // The upvalues that still point at the stack, ordered by their slot so
+    // that the ones to close when slots are popped are at the end.
+    open_upvalues: Vec<(usize, Handle)>,
+    // The coroutines that are running, the innermost last.
+    resumed: Vec<Resumed>,
+    // The tries the script is inside of, the innermost last.
+    handlers: Vec<Handler>,
+    // What the throw that the error on its way out came from threw. Any
+    // other error is caught as its message.
+    thrown: Option<Value>,
+    // The errors that exceptions on the stack were caught from, with the
+    // slot the exception is in, for a finally block to throw on.
+    caught: Vec<(usize, RuntimeError)>,
+    // How many more instructions scripts can run, or nothing for no limit.
+    fuel: Option<u64>,
+    interrupted: Arc<AtomicBool>,
+    // When the script running with a timeout has to stop by, so that an
+    // interrupt after then is known to be the timeout.
+    deadline: Option<Instant>,
+    // The limit that a call back from the host stopped at, for the call
+    // of the host that failed because of it to stop at too.
+    limited: Option<RuntimeErrorKind>,
+    debugger: Debugger,
+    profiler: Option<Box<Profiler>>,
+    trace: Option<Box<dyn Write + Send>>,
+    // The globals found by name, which are kept from one script to the next
+    // and start out as the builtins.
+    globals: HashMap<Arc<str>, Value>,
+    // The globals of the script that ran last.
+    script: Option<Handle>,
+    // The exports of every module that was imported, by path.
+    modules: HashMap<String, Value>,
+    // The functions the host was given handles to, with how many of the
+    // handles it hasn't released yet.
+    functions: Vec<(Handle, usize)>,
+    // What the async function of the host that was just called gave back,
+    // for the call to wait on.
+    awaiting: Option<host::HostFuture>,
+    // The coroutines waiting on the host, in the order they started.
+    waiting: Vec<host::Waiting>,
+    // The modules being run for an import, with the frame each runs in,
+    // starting with the script.
+    importing: Vec<(String, usize)>,
+    loader: Option<Box<dyn ModuleLoader>>,
+    inline_caches: InlineCaches,
+    #[cfg(feature = "jit")]
+    jit: jit::Jit,
+    out: Box<dyn Write + Send>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    // A virtual machine that prints to stdout.
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    pub fn with_output(out: Box<dyn Write + Send>) -> Self {
+        let mut heap = Heap::new();
+        let globals = BUILTINS
+            .iter()
+            .map(|native| {
+                let handle = heap.alloc(Object::Native(*native));
+                (Arc::from(native.name), Value::Object(handle))
+            })
+            .collect();
+        Self {
+            heap,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            resumed: Vec::new(),
+            handlers: Vec::new(),
+            thrown: None,
+            caught: Vec::new(),
+            fuel: None,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+            limited: None,
+            debugger: Debugger::default(),
+            profiler: None,
+            trace: None,
+            globals,
+            script: None,
+            modules: HashMap::new(),
+            functions: Vec::new(),
+            awaiting: None,
+            waiting: Vec::new(),
+            importing: Vec::new(),
+            loader: None,
+            inline_caches: InlineCaches::default(),
+            #[cfg(feature = "jit")]
+            jit: jit::Jit::default(),
+            out,
+        }
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    // For making values to hand to scripts. Objects are only kept while a
+    // script can reach them, so the ones made between scripts have to be
+    // handed over before the next one runs.
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.heap.set_gc_config(config);
+    }
+
+    // Collects everything scripts can no longer reach all at once, rather
+    // than whenever it is due, giving back how many objects were freed.
+    // Hosts can do this when a pause doesn't matter, like while a game is
+    // loading. Objects the host made or was given that scripts can't reach
+    // are collected too.
+    pub fn collect_garbage(&mut self) -> usize {
+        let roots = self.roots();
+        self.heap.collect(roots)
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    // The most bytes the objects of scripts and the strings they make can
+    // take up, as the heap estimates them. Going over the limit throws an
+    // out of memory error, which scripts can catch once they let go of
+    // enough.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.heap.set_memory_limit(limit);
+    }
+
+    // How many more instructions scripts can run, or nothing when there is
+    // no limit, which is the default. A script that runs out stops with a
+    // fuel exhausted error, and carries on once it is given more.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    // Does nothing when there is no limit.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(left) = &mut self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    // An interrupt before a script starts is dropped when it does.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.interrupted.clone())
+    }
+
+    // Whether a script stopped at a limit of the host, and can carry on.
+    pub fn is_paused(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    // Carries on running the script that stopped at a limit from where it
+    // stopped, giving back what running it would have.
+    //
+    // Panics if no script is paused.
+    pub fn continue_running(&mut self) -> Result<Value, RuntimeError> {
+        assert!(self.is_paused(), "no script is paused");
+        self.finish()
+    }
+
+    // A global of the script that ran last, or one found by name.
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        let script = match self.script.map(|script| self.heap.get(script)) {
+            Some(Object::Globals(globals)) => globals,
+            _ => return self.globals.get(name),
+        };
+        match script.names.iter().position(|slot| slot == name) {
+            Some(slot) => script.slots[slot].as_ref(),
+            None => self.globals.get(name),
+        }
+    }
+
+    // A global as a Rust value, failing when there is no such global or it
+    // can't be converted.
+    pub fn get_global<T: FromAtom>(&self, name: &str) -> Result<T, String> {
+        match self.global(name) {
+            Some(value) => {
+                T::from_atom(value, &self.heap).map_err(|msg| format!("{} {}", name, msg))
+            }
+            None => Err(format!("{} is not defined", name)),
+        }
+    }
+
+    // Sets a global the way `global` finds it. One the script that ran last
+    // declares is changed for its functions to see, and any other is set by
+    // name, for every script after that doesn't declare one of its own.
+    pub fn set_global(&mut self, name: &str, value: impl IntoAtom) {
+        let value = value.into_atom(&mut self.heap);
+        if let Some(script) = self.script {
+            if let Object::Globals(globals) = self.heap.get_mut(script) {
+                if let Some(slot) = globals.names.iter().position(|slot| slot == name) {
+                    globals.slots[slot] = Some(value);
+                    return;
+                }
+            }
+        }
+        self.globals.insert(Arc::from(name), value);
+    }
+
+    // Runs a compiled script, giving back what it returns from the top
+    // level, or null.
+    pub fn run(&mut self, script: &Function) -> Result<Value, RuntimeError> {
+        self.reset();
+        self.start(script)
+    }
+
+    // Runs a compiled script on a VM that was reset.
+    fn start(&mut self, script: &Function) -> Result<Value, RuntimeError> {
+        let closure = self.script_closure(script);
+        self.script = match self.heap.get(closure) {
+            Object::Closure(closure) => Some(closure.globals),
+            _ => unreachable!("scripts are run as closures"),
+        };
+        self.importing.push((String::from(script.chunk.name()), 0));
+        self.stack.push(Value::Object(closure));
+        self.push_frame(closure, 0, Returned::Kept);
+        self.finish()
+    }
+
+    // Runs a compiled script the way `run` does, but stops it with a timed
+    // out error at the first loop or call it gets to once it has run for
+    // longer than the timeout. Those check the clock, and a thread that
+    // waits out the timeout interrupts the script too, so the script only
+    // stops once any function of the host it is in returns. The script is
+    // left paused, and carries on with no timeout if it is continued.
+    pub fn eval_with_timeout(
+        &mut self,
+        script: &Function,
+        timeout: Duration,
+    ) -> Result<Value, RuntimeError> {
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            None => return self.run(script),
+        };
+        // Starting a script drops any interrupt from before, so the one the
+        // watchdog makes has to come after.
+        self.reset();
+        self.deadline = Some(deadline);
+        let (finished, finishing) = mpsc::channel::<()>();
+        let interrupt = self.interrupt_handle();
+        let watchdog = thread::spawn(move || loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match finishing.recv_timeout(left) {
+                Err(RecvTimeoutError::Timeout) if Instant::now() >= deadline => {
+                    interrupt.interrupt();
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        });
+        let result = self.start(script);
+        self.deadline = None;
+        drop(finished);
+        watchdog.join().expect("the watchdog doesn't panic");
+        result
+    }
+
+    // Resumes a coroutine the way a script does by calling it, giving back
+    // what it yields or, once its function returns, what that returns. The
+    // arguments are passed to the function the first time, and after that
+    // the one argument there can be is what the yield it stopped at gives.
+    //
+    // Panics if the value isn't a coroutine.
+    pub fn resume(&mut self, coroutine: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let handle = match coroutine {
+            Value::Object(handle) if matches!(self.heap.get(*handle), Object::Coroutine(_)) => {
+                *handle
+            }
+            _ => panic!("only coroutines can be resumed"),
+        };
+        self.reset();
+        self.stack.push(coroutine.clone());
+        self.stack.extend_from_slice(args);
+        if let Err(msg) = self.resume_coroutine(handle, args.len()) {
+            let error = RuntimeError::new(&msg[..], vec![self.coroutine_trace(handle)]);
+            self.reset();
+            return Err(error);
+        }
+        self.finish()
+    }
+
+    // Runs until the script is done, dropping what was running if it
+    // stopped with an error of its own.
+    fn finish(&mut self) -> Result<Value, RuntimeError> {
+        let result = self.execute();
+        if let Err(error) = &result {
+            if error.kind() == RuntimeErrorKind::Error {
+                self.reset();
+            }
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.stop(&self.frames);
+        }
+        result
+    }
+
+    // Drops whatever was running when a script stopped with an error, or
+    // was left paused, along with the coroutines it was in, which can't be
+    // resumed after.
+    fn reset(&mut self) {
+        for resumed in self.resumed.drain(..) {
+            if let Object::Coroutine(coroutine) = self.heap.get_mut(resumed.coroutine) {
+                coroutine.state = CoroutineState::Finished;
+            }
+        }
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.handlers.clear();
+        self.thrown = None;
+        self.caught.clear();
+        self.importing.clear();
+        self.interrupted.store(false, Ordering::Relaxed);
+        self.limited = None;
+        self.debugger.reset();
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("no function is running")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("the stack is never popped when empty")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack
+            .last()
+            .expect("the stack is never read when empty")
+    }
+
+    // The error for the instruction at the offset in the running function,
+    // traced back through the calls the frames below it are making.
+    fn fail(&self, offset: usize, msg: &str) -> RuntimeError {
+        let last = self.frames.len() - 1;
+        let trace = self
+            .frames
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, frame)| {
+                // The ip of a calling frame is past the call, which is at
+                // the byte before.
+                let offset = if i == last { offset } else { frame.ip - 1 };
+                let top_level = i == 0 && frame.returned != Returned::Host;
+                trace_frame(&frame.proto, offset, top_level)
+            })
+            .collect();
+        RuntimeError::new(msg, trace)
+    }
+
+    // Where a coroutine the host couldn't resume is, which is the yield it
+    // stopped at, or the start of its function.
+    fn coroutine_trace(&self, handle: Handle) -> TraceFrame {
+        let coroutine = match self.heap.get(handle) {
+            Object::Coroutine(coroutine) => coroutine,
+            _ => unreachable!("only coroutines are resumed"),
+        };
+        if let Some(frame) = coroutine.frames.last() {
+            return trace_frame(&frame.proto, frame.ip - 1, false);
+        }
+        let mut function = coroutine.function.as_object();
+        if let Some(Object::BoundMethod(bound)) = function.map(|handle| self.heap.get(handle)) {
+            function = bound.method.as_object();
+        }
+        match function.map(|handle| self.heap.get(handle)) {
+            Some(Object::Closure(closure)) => trace_frame(&closure.proto, 0, false),
+            _ => unreachable!("coroutines are only made out of functions"),
+        }
+    }
+
+    // Runs the frame on top until the script returns, going on at the
+    // innermost try whenever something is thrown.
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
+        loop {
+            let error = match self.dispatch() {
+                Ok(value) => return Ok(value),
+                Err(error) if error.kind() == RuntimeErrorKind::Error => error,
+                Err(error) => return Err(error),
+            };
+            self.handle_error(error)?;
+        }
+    }
+
+    // Goes on at the innermost try with what the error threw, or gives the
+    // error back if there is none.
+    fn handle_error(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        let exception = match self.thrown.take() {
+            Some(value) => value,
+            None => Value::from(error.message()),
+        };
+        let depth = match self.catch(exception) {
+            Some(depth) => depth,
+            None => return Err(error),
+        };
+        self.caught.retain(|&(slot, _)| slot < depth);
+        self.caught.push((depth, error));
+        Ok(())
+    }
+
+    // Unwinds the frames and the stack back to where the innermost try was,
+    // and leaves the exception for its handler. The coroutines resumed
+    // since then are finished. Gives back the slot of the exception, or
+    // nothing if there is no try.
+    fn catch(&mut self, exception: Value) -> Option<usize> {
+        let handler = self.handlers.pop()?;
+        while let Some(resumed) = self.resumed.last() {
+            if resumed.frames < handler.frames {
+                break;
+            }
+            let coroutine = resumed.coroutine;
+            self.resumed.pop();
+            if let Object::Coroutine(coroutine) = self.heap.get_mut(coroutine) {
+                coroutine.state = CoroutineState::Finished;
+            }
+        }
+        self.frames.truncate(handler.frames);
+        self.importing.retain(|&(_, frame)| frame < handler.frames);
+        self.close_upvalues(handler.depth);
+        self.stack.truncate(handler.depth);
+        self.stack.push(exception);
+        self.frames
+            .last_mut()
+            .expect("tries are inside of a frame")
+            .ip = handler.ip;
+        Some(handler.depth)
+    }
+
+    fn dispatch(&mut self) -> Result<Value, RuntimeError> {
+        let mut regs = self.registers();
+        loop {
+            if self.heap.is_due() {
+                self.collect();
+            }
+            let start = regs.ip;
+            if self.heap.is_over_limit() {
+                self.collect_garbage();
+                let strings = self.stack.iter().map(Value::string_bytes).sum();
+                self.heap.set_outside(strings);
+                if self.heap.is_over_limit() {
+                    return Err(self.fail(start, "out of memory"));
+                }
+            }
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    self.frames.last_mut().expect("no function is running").ip = start;
+                    let error = self.fail(start, "out of fuel");
+                    return Err(error.with_kind(RuntimeErrorKind::FuelExhausted));
+                }
+                *fuel -= 1;
+            }
+            if self.debugger.is_active() {
+                self.debug(start);
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.tick(&self.frames);
+            }
+            if self.trace.is_some() {
+                self.trace(start);
+            }
+            #[cfg(not(feature = "threaded-dispatch"))]
+            let (op, operand, next) = {
+                let chunk = &regs.proto.function.chunk;
+                let code = chunk.code();
+                let mut ip = start;
+                let mut wide = false;
+                if code[ip] == OpCode::Wide as u8 {
+                    wide = true;
+                    ip += 1;
+                }
+                let op = match OpCode::from_byte(code[ip]) {
+                    Some(OpCode::Wide) | None => {
+                        return Err(self.fail(start, "invalid instruction"))
+                    }
+                    Some(op) => op,
+                };
+                ip += 1;
+                let operand = match (op.operand_len(), wide) {
+                    (0, _) => 0,
+                    (1, false) => {
+                        ip += 1;
+                        code[ip - 1] as usize
+                    }
+                    (1, true) | (2, false) => {
+                        ip += 2;
+                        chunk.read_u16(ip - 2) as usize
+                    }
+                    _ => {
+                        ip += 4;
+                        chunk.read_u32(ip - 4) as usize
+                    }
+                };
+                (op, operand, ip)
+            };
+            // The instruction was decoded along with the prototype, and
+            // brings the method that runs it.
+            #[cfg(feature = "threaded-dispatch")]
+            let Threaded {
+                execute,
+                op,
+                operand,
+                next,
+            } = regs.proto.threaded[start];
+            #[cfg(feature = "opcode-counts")]
+            if let Some(profiler) = &mut self.profiler {
+                profiler.count(op);
+            }
+            if matches!(op, OpCode::Loop | OpCode::Call | OpCode::TailCall)
+                && (self.interrupted.load(Ordering::Relaxed)
+                    || self
+                        .deadline
+                        .is_some_and(|deadline| Instant::now() >= deadline))
+            {
+                self.interrupted.store(false, Ordering::Relaxed);
+                self.frames.last_mut().expect("no function is running").ip = start;
+                let (msg, kind) = match self.deadline {
+                    Some(deadline) if Instant::now() >= deadline => {
+                        ("timed out", RuntimeErrorKind::TimedOut)
+                    }
+                    _ => ("interrupted", RuntimeErrorKind::Interrupted),
+                };
+                return Err(self.fail(start, msg).with_kind(kind));
+            }
+            regs.ip = next;
+            let instruction = Instruction { op, operand, start };
+            #[cfg(not(feature = "threaded-dispatch"))]
+            let step = self.run_instruction(&mut regs, instruction);
+            #[cfg(feature = "threaded-dispatch")]
+            let step = execute(self, &mut regs, instruction);
+            match step {
+                Ok(Flow::Next) => {}
+                Ok(Flow::Resume) => regs = self.registers(),
+                Ok(Flow::Return(value)) => return Ok(value),
+                Err(error) => return Err(*error),
+            }
+        }
+    }
+
+    // The roots are the stack, the globals, the closures and coroutines
+    // that are running and the upvalues that still point at the stack,
+    // which are closed when their slot is popped whether or not a closure
+    // still has them. A collection that is still sweeping carries on
+    // where it was instead.
+    fn collect(&mut self) {
+        if self.heap.is_sweeping() {
+            self.heap.sweep_step();
+        } else {
+            let roots = self.roots();
+            self.heap.start_collection(roots);
+        }
+    }
+
+    fn roots(&self) -> Vec<Handle> {
+        let globals = self.globals.values().chain(self.modules.values());
+        self.stack
+            .iter()
+            .chain(globals)
+            .filter_map(Value::as_object)
+            .chain(self.script)
+            .chain(self.functions.iter().map(|&(function, _)| function))
+            .chain(self.frames.iter().map(|frame| frame.closure))
+            .chain(self.open_upvalues.iter().map(|&(_, upvalue)| upvalue))
+            .chain(self.resumed.iter().map(|resumed| resumed.coroutine))
+            .chain(self.waiting_coroutines())
+            .collect()
+    }
+
+    fn undefined(&self, name: &str, offset: usize) -> RuntimeError {
+        let msg = format!("{} is not defined", name);
+        self.fail(offset, &msg[..])
+    }
+
+    fn closure(
+        &mut self,
+        proto: &Prototype,
+        constant: usize,
+        globals: Handle,
+        upvalues: Vec<Handle>,
+    ) -> Value {
+        let proto = proto.functions[constant]
+            .clone()
+            .expect("functions are made from function constants");
+        let closure = Closure {
+            proto,
+            globals,
+            upvalues,
+            caches: Vec::new(),
+        };
+        Value::Object(self.heap.alloc(Object::Closure(closure)))
+    }
+
+    // The closure of the top level of a script or module, which has
+    // globals of its own.
+    fn script_closure(&mut self, script: &Function) -> Handle {
+        let file = Arc::from(script.chunk.name());
+        let proto = Arc::new(Prototype::new(script.clone(), &file, &mut self.heap));
+        let globals = Globals::new(script.chunk.globals());
+        let globals = self.heap.alloc(Object::Globals(globals));
+        self.heap.alloc(Object::Closure(Closure {
+            proto,
+            globals,
+            upvalues: Vec::new(),
+            caches: Vec::new(),
+        }))
+    }
+
+    fn push_frame(&mut self, closure: Handle, base: usize, returned: Returned) {
+        let proto = match self.heap.get(closure) {
+            Object::Closure(closure) => closure.proto.clone(),
+            _ => unreachable!("frames only run closures"),
+        };
+        self.frames.push(Frame {
+            proto,
+            closure,
+            ip: 0,
+            base,
+            returned,
+        });
+    }
+
+    fn globals_of(&self, closure: Handle) -> Handle {
+        match self.heap.get(closure) {
+            Object::Closure(closure) => closure.globals,
+            _ => unreachable!("frames only run closures"),
+        }
+    }
+
+    fn slots(&self, globals: Handle) -> &Globals {
+        match self.heap.get(globals) {
+            Object::Globals(globals) => globals,
+            _ => unreachable!("closures keep the globals of their script"),
+        }
+    }
+
+    fn slots_mut(&mut self, globals: Handle) -> &mut Globals {
+        match self.heap.get_mut(globals) {
+            Object::Globals(globals) => globals,
+            _ => unreachable!("closures keep the globals of their script"),
+        }
+    }
+
+    fn upvalue(&self, closure: Handle, index: usize) -> Handle {
+        match self.heap.get(closure) {
+            Object::Closure(closure) => closure.upvalues[index],
+            _ => unreachable!("frames only run closures"),
+        }
+    }
+
+    // Closures that capture the same slot share its upvalue.
+    fn capture(&mut self, slot: usize) -> Handle {
+        let position = self.open_upvalues.partition_point(|(open, _)| *open < slot);
+        if let Some(&(open, upvalue)) = self.open_upvalues.get(position) {
+            if open == slot {
+                return upvalue;
+            }
+        }
+        let upvalue = self.heap.alloc(Object::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position, (slot, upvalue));
+        upvalue
+    }
+
+    // Closes the upvalues of the slots from the one given up.
+    fn close_upvalues(&mut self, from: usize) {
+        while let Some(&(slot, upvalue)) = self.open_upvalues.last() {
+            if slot < from {
+                break;
+            }
+            self.open_upvalues.pop();
+            let value = self.stack[slot].clone();
+            *self.heap.get_mut(upvalue) = Object::Upvalue(Upvalue::Closed(value));
+        }
+    }
+
+    // The class being declared, which is on top of the stack. Compiled
+    // code only ever adds members to a class, but a loaded file could have
+    // anything there.
+    fn class(&self) -> Result<Handle, &'static str> {
+        match self.peek() {
+            Value::Object(handle) if matches!(self.heap.get(*handle), Object::Class(_)) => {
+                Ok(*handle)
+            }
+            _ => Err("invalid instruction"),
+        }
+    }
+
+    // Calls the value below the arguments on top of the stack. A closure
+    // gets a frame that runs next, and a native runs straight away, leaving
+    // its result in place of the call. A bound method runs with its
+    // instance in the slot of the function, and so does the initializer of
+    // a class with the instance it is making.
+    fn call(&mut self, argc: usize) -> Result<(), String> {
+        let base = self.stack.len() - argc - 1;
+        let handle = match &self.stack[base] {
+            Value::Object(handle) => *handle,
+            callee => return Err(format!("cannot call {}", callee.type_name(&self.heap))),
+        };
+        match self.heap.get(handle) {
+            Object::Closure(_) => self.call_closure(handle, argc),
+            Object::BoundMethod(bound) => {
+                let method = match bound.method {
+                    Value::Object(method) => method,
+                    _ => return Err(String::from("invalid method")),
+                };
+                self.stack[base] = bound.receiver.clone();
+                match self.heap.get(method) {
+                    Object::Native(native) => self.call_native(*native, argc, true),
+                    Object::Closure(_) => self.call_closure(method, argc),
+                    _ => Err(String::from("invalid method")),
+                }
+            }
+            Object::Class(class) => {
+                let name = class.name.clone();
+                let instance = Instance {
+                    class: handle,
+                    fields: Map::new(),
+                };
+                let instance = self.heap.alloc(Object::Instance(instance));
+                self.stack[base] = Value::Object(instance);
+                match ops::find_method(&self.heap, handle, INITIALIZER_NAME) {
+                    Some(Value::Object(initializer))
+                        if matches!(self.heap.get(initializer), Object::Closure(_)) =>
+                    {
+                        self.call_closure(initializer, argc)
+                    }
+                    // Compiled code only ever makes an initializer out of a
+                    // function, but a loaded file could have anything there.
+                    Some(_) => Err(String::from("invalid initializer")),
+                    None if argc == 0 => Ok(()),
+                    None => {
+                        let signature = Signature {
+                            arity: 0,
+                            min_arity: 0,
+                            rest: false,
+                        };
+                        Err(signature.mismatch(&name, argc))
+                    }
+                }
+            }
+            Object::Native(native) => self.call_native(*native, argc, false),
+            Object::Host(host) => self.call_host(host.clone(), argc),
+            Object::Coroutine(_) => self.resume_coroutine(handle, argc),
+            object => Err(format!("cannot call {}", object.type_name())),
+        }
+    }
+
+    // A method of a builtin value gets the value, which is in the slot of
+    // the function, before the arguments.
+    fn call_native(&mut self, native: Native, argc: usize, method: bool) -> Result<(), String> {
+        native.check_arity(argc)?;
+        let mut args = self.stack.split_off(self.stack.len() - argc);
+        let callee = self.pop();
+        if method {
+            args.insert(0, callee);
+        }
+        let mut context = Context {
+            heap: &mut self.heap,
+            out: &mut *self.out,
+        };
+        let result = (native.function)(&mut context, &args)?;
+        if let Value::String(s) = &result {
+            self.heap.count_string(s);
+        }
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn call_closure(&mut self, handle: Handle, argc: usize) -> Result<(), String> {
+        let base = self.stack.len() - argc - 1;
+        let proto = match self.heap.get(handle) {
+            Object::Closure(closure) => closure.proto.clone(),
+            _ => unreachable!("only closures get frames"),
+        };
+        let signature = proto.signature();
+        if !signature.accepts(argc) {
+            return Err(signature.mismatch(&proto.function.name, argc));
+        }
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(String::from("stack overflow"));
+        }
+        #[cfg(feature = "jit")]
+        if argc == signature.arity && !signature.rest && self.run_compiled(&proto, base) {
+            return Ok(());
+        }
+        // Parameters that are left out are null, and the rest parameter gets
+        // a list of the arguments left over.
+        let arity = signature.arity;
+        if argc < arity {
+            self.stack.resize(base + 1 + arity, Value::Null);
+        }
+        if signature.rest {
+            let rest = self.stack.split_off(base + 1 + arity);
+            let rest = self.heap.alloc(Object::List(rest));
+            self.stack.push(Value::Object(rest));
+        }
+        self.frames.push(Frame {
+            proto,
+            closure: handle,
+            ip: 0,
+            base,
+            returned: Returned::Kept,
+        });
+        Ok(())
+    }
+
+    // Puts the frames of a coroutine back on top of the stack, or calls its
+    // function with the arguments the first time. The value it is resumed
+    // with takes the place of the yield it stopped at.
+    fn resume_coroutine(&mut self, handle: Handle, argc: usize) -> Result<(), String> {
+        let base = self.stack.len() - argc - 1;
+        let coroutine = match self.heap.get_mut(handle) {
+            Object::Coroutine(coroutine) => coroutine,
+            _ => unreachable!("only coroutines are resumed"),
+        };
+        match coroutine.state {
+            CoroutineState::Suspended => {}
+            CoroutineState::Running => {
+                return Err(String::from("cannot resume a running coroutine"))
+            }
+            CoroutineState::Waiting => {
+                return Err(String::from("cannot resume a waiting coroutine"))
+            }
+            CoroutineState::Finished => {
+                return Err(String::from("cannot resume a finished coroutine"))
+            }
+        }
+        let frames = self.frames.len();
+        if coroutine.frames.is_empty() {
+            self.stack[base] = coroutine.function.clone();
+            self.call(argc)?;
+        } else {
+            if argc > 1 {
+                let msg = format!("a coroutine is resumed with 1 value but got {}", argc);
+                return Err(msg);
+            }
+            let value = self.stack.get(base + 1).cloned().unwrap_or_default();
+            self.stack.truncate(base);
+            self.stack.append(&mut coroutine.stack);
+            self.stack.push(value);
+            for frame in coroutine.frames.drain(..) {
+                self.frames.push(Frame {
+                    base: frame.base + base,
+                    ..frame
+                });
+            }
+            let upvalues = std::mem::take(&mut coroutine.upvalues);
+            let handlers = std::mem::take(&mut coroutine.handlers);
+            for (slot, upvalue) in upvalues {
+                let slot = slot + base;
+                let closed = std::mem::replace(
+                    self.heap.get_mut(upvalue),
+                    Object::Upvalue(Upvalue::Open(slot)),
+                );
+                if let Object::Upvalue(Upvalue::Closed(value)) = closed {
+                    self.stack[slot] = value;
+                }
+                self.open_upvalues.push((slot, upvalue));
+            }
+            for handler in handlers {
+                self.handlers.push(Handler {
+                    frames: handler.frames + frames,
+                    depth: handler.depth + base,
+                    ..handler
+                });
+            }
+        }
+        if let Object::Coroutine(coroutine) = self.heap.get_mut(handle) {
+            coroutine.state = CoroutineState::Running;
+        }
+        self.resumed.push(Resumed {
+            coroutine: handle,
+            frames,
+            base,
+            tail: false,
+        });
+        Ok(())
+    }
+
+    // Moves the frames of a coroutine that yields out into it, along with
+    // the slots they use.
+    fn suspend(&mut self, resumed: &Resumed) {
+        let frames = self
+            .frames
+            .split_off(resumed.frames)
+            .into_iter()
+            .map(|frame| Frame {
+                base: frame.base - resumed.base,
+                ..frame
+            })
+            .collect();
+        let position = self
+            .open_upvalues
+            .partition_point(|&(slot, _)| slot < resumed.base);
+        let upvalues = self
+            .open_upvalues
+            .split_off(position)
+            .into_iter()
+            .map(|(slot, upvalue)| {
+                let value = self.stack[slot].clone();
+                *self.heap.get_mut(upvalue) = Object::Upvalue(Upvalue::Closed(value));
+                (slot - resumed.base, upvalue)
+            })
+            .collect();
+        let position = self
+            .handlers
+            .partition_point(|handler| handler.frames <= resumed.frames);
+        let handlers = self
+            .handlers
+            .split_off(position)
+            .into_iter()
+            .map(|handler| Handler {
+                frames: handler.frames - resumed.frames,
+                depth: handler.depth - resumed.base,
+                ..handler
+            })
+            .collect();
+        let stack = self.stack.split_off(resumed.base);
+        if let Object::Coroutine(coroutine) = self.heap.get_mut(resumed.coroutine) {
+            coroutine.state = CoroutineState::Suspended;
+            coroutine.stack = stack;
+            coroutine.frames = frames;
+            coroutine.upvalues = upvalues;
+            coroutine.handlers = handlers;
+        }
+    }
+
+    // Gives what a coroutine yielded or returned to the frame that resumed
+    // it, or to the host when that is what resumed it.
+    fn hand_back(&mut self, value: Value, tail: bool) -> Option<Value> {
+        if self.frames.is_empty() {
+            return Some(value);
+        }
+        self.stack.push(value);
+        if tail {
+            self.return_value()
+        } else {
+            None
+        }
+    }
+
+    // Calls a method the script didn't call itself, in place of an
+    // operation.
+    fn invoke(
+        &mut self,
+        method: Value,
+        args: Vec<Value>,
+        returned: Returned,
+    ) -> Result<(), String> {
+        let calling = self.frames.len();
+        let argc = args.len();
+        self.stack.push(method);
+        self.stack.extend(args);
+        self.call(argc)?;
+        if self.frames.len() > calling {
+            self.frames
+                .last_mut()
+                .expect("the call made a frame")
+                .returned = returned;
+        } else {
+            let value = self.pop();
+            self.push_returned(value, returned);
+        }
+        Ok(())
+    }
+
+    fn push_returned(&mut self, value: Value, returned: Returned) {
+        match returned {
+            Returned::Kept => self.stack.push(value),
+            Returned::Imported => {
+                let (path, _) = self.importing.pop().expect("a module was imported");
+                self.modules.insert(path, value.clone());
+                self.stack.push(value);
+            }
+            Returned::Discarded => {}
+            Returned::Negated => self.stack.push(Value::Bool(!value.is_truthy())),
+            Returned::Host => unreachable!("the host gets what its calls return"),
+        }
+    }
+
+    // Takes the frame that made a tail call out from under the frame of the
+    // function it called, which moves down into its place.
+    fn replace_caller(&mut self) {
+        let callee = self.frames.pop().expect("the tail call made a frame");
+        let caller = self.frames.pop().expect("tail calls come from a function");
+        self.close_upvalues(caller.base);
+        self.stack.drain(caller.base..callee.base);
+        self.frames.push(Frame {
+            base: caller.base,
+            returned: caller.returned,
+            ..callee
+        });
+    }
+
+    // Ends the running frame with the value on top of the stack, which takes
+    // the place of the call in the frame that made it. The value is given
+    // back instead once the script itself returns.
+    fn return_value(&mut self) -> Option<Value> {
+        let value = self.pop();
+        let frame = self.frames.pop().expect("no function is running");
+        self.close_upvalues(frame.base);
+        self.stack.truncate(frame.base);
+        // A coroutine is finished once its function returns.
+        if self.resumed.last().map(|resumed| resumed.frames) == Some(self.frames.len()) {
+            let resumed = self.resumed.pop().expect("a coroutine is running");
+            if let Object::Coroutine(coroutine) = self.heap.get_mut(resumed.coroutine) {
+                coroutine.state = CoroutineState::Finished;
+            }
+            return self.hand_back(value, resumed.tail);
+        }
+        if self.frames.is_empty() || frame.returned == Returned::Host {
+            return Some(value);
+        }
+        self.push_returned(value, frame.returned);
+        None
+    }
+}
+
+// Where a function is at the offset, for the trace of an error. The top
+// level of a script has no name.
+fn trace_frame(proto: &Prototype, offset: usize, top_level: bool) -> TraceFrame {
+    let span = proto.function.chunk.span(offset);
+    TraceFrame {
+        function: if top_level {
+            None
+        } else {
+            Some(proto.function.name.clone())
+        },
+        file: String::from(&proto.file[..]),
+        line: span.line,
+        column: span.column,
+    }
+}
+
+// The string constant a global or property is found by.
+fn name(constants: &[Constant], index: usize) -> Result<Arc<str>, &'static str> {
+    match constants.get(index) {
+        Some(Constant::String(name)) => Ok(name.clone()),
+        _ => Err("invalid constant"),
+    }
+}
+
+fn binary_op(op: OpCode) -> BinaryOp {
+    match op {
+        OpCode::Equal => BinaryOp::Equal,
+        OpCode::NotEqual => BinaryOp::NotEqual,
+        OpCode::Less => BinaryOp::Less,
+        OpCode::LessEqual => BinaryOp::LessEqual,
+        OpCode::Greater => BinaryOp::Greater,
+        OpCode::GreaterEqual => BinaryOp::GreaterEqual,
+        OpCode::Add => BinaryOp::Add,
+        OpCode::Subtract => BinaryOp::Subtract,
+        OpCode::Multiply => BinaryOp::Multiply,
+        OpCode::Divide => BinaryOp::Divide,
+        OpCode::Modulo => BinaryOp::Modulo,
+        OpCode::BitAnd => BinaryOp::BitAnd,
+        OpCode::BitOr => BinaryOp::BitOr,
+        OpCode::BitXor => BinaryOp::BitXor,
+        _ => unreachable!("only binary operators are left"),
+    }
+}
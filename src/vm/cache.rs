@@ -0,0 +1,246 @@
+use super::{Prototype, Vm};
+use crate::value::ops::{self, Access, Found};
+use crate::value::symbol::Symbol;
+use crate::value::*;
+
+// Every instruction that reads or writes a property has an inline cache,
+// which remembers what the property turned out to be on the instances it
+// was last used on, by their class. The next time an instance of one of
+// those classes comes by, a field is read straight from where it was in
+// the fields, and a method or getter is taken from the cache, without
+// looking the name up in the instance or going through the classes.
+//
+// A field is only where it was if the instance set its fields in the same
+// order, so the name at that place is checked first, which compares the
+// symbols rather than hashing anything. Methods and getters come from the
+// classes, so their caches are thrown away whenever the heap says a class
+// might have changed, and a field of the same name is looked for because
+// it would come first.
+//
+// Only instances are cached. Maps and the builtin values have nothing for
+// a cache to skip.
+
+// How many classes the cache of an instruction remembers. An instruction
+// that sees more than that is left to look properties up every time.
+const POLYMORPHIC: usize = 4;
+
+// Instances with up to this many fields are checked for a field by going
+// through them, which is quicker than hashing for that few.
+const FEW_FIELDS: usize = 8;
+
+// Whether the VM caches properties, and for how many classes at every
+// instruction. Turning caches off is for comparing them with looking
+// properties up every time.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum InlineCaches {
+    Off,
+    Monomorphic,
+    #[default]
+    Polymorphic,
+}
+
+impl InlineCaches {
+    fn capacity(self) -> usize {
+        match self {
+            InlineCaches::Off => 0,
+            InlineCaches::Monomorphic => 1,
+            InlineCaches::Polymorphic => POLYMORPHIC,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Cache {
+    // The class epoch of the heap when the entries were made.
+    epoch: u64,
+    entries: [Option<Entry>; POLYMORPHIC],
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Entry {
+    class: Handle,
+    hit: Hit,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Hit {
+    // Where the field is in the fields of the instance.
+    Field(usize),
+    Getter(Value),
+    Method(Value),
+}
+
+impl Vm {
+    pub fn set_inline_caches(&mut self, caches: InlineCaches) {
+        self.inline_caches = caches;
+    }
+
+    pub fn inline_caches(&self) -> InlineCaches {
+        self.inline_caches
+    }
+
+    // Reads a property the same as `ops::get_property`, for the
+    // instruction at the offset.
+    pub(super) fn get_property(
+        &mut self,
+        closure: Handle,
+        proto: &Prototype,
+        offset: usize,
+        object: &Value,
+        name: &Symbol,
+    ) -> Result<Access<Value>, String> {
+        let instance = match self.instance(object) {
+            Some(instance) if self.inline_caches != InlineCaches::Off => instance,
+            _ => return ops::get_property(&mut self.heap, object, name),
+        };
+        let site = proto.sites[offset] as usize;
+        let class = instance.class;
+        match self.cached(closure, site, class) {
+            Some(Hit::Field(i)) => {
+                if let Some((key, value)) = instance.fields.entry(*i) {
+                    if key == name {
+                        return Ok(Access::Done(value.clone()));
+                    }
+                }
+            }
+            Some(Hit::Getter(getter)) if !has_field(instance, name) => {
+                let getter = getter.clone();
+                return Ok(Access::Call(ops::bind(&mut self.heap, object, getter)));
+            }
+            Some(Hit::Method(method)) if !has_field(instance, name) => {
+                let method = method.clone();
+                return Ok(Access::Done(ops::bind(&mut self.heap, object, method)));
+            }
+            _ => {}
+        }
+        let (hit, access) = match ops::find_property(&self.heap, instance, name) {
+            Some(Found::Field(value)) => {
+                let i = instance.fields.position(name).expect("the field was found");
+                (Hit::Field(i), Access::Done(value))
+            }
+            Some(Found::Getter(getter)) => (
+                Hit::Getter(getter.clone()),
+                Access::Call(ops::bind(&mut self.heap, object, getter)),
+            ),
+            Some(Found::Method(method)) => (
+                Hit::Method(method.clone()),
+                Access::Done(ops::bind(&mut self.heap, object, method)),
+            ),
+            None => return ops::get_property(&mut self.heap, object, name),
+        };
+        self.remember(closure, proto, site, class, hit);
+        Ok(access)
+    }
+
+    // Writes a property the same as `ops::set_property`, for the
+    // instruction at the offset. Only fields that are already there are
+    // cached, since setting one that isn't adds it somewhere new.
+    pub(super) fn set_property(
+        &mut self,
+        closure: Handle,
+        proto: &Prototype,
+        offset: usize,
+        object: &Value,
+        name: &Symbol,
+        value: Value,
+    ) -> Result<Access<()>, String> {
+        let class = match self.instance(object) {
+            Some(instance) if self.inline_caches != InlineCaches::Off => instance.class,
+            _ => return ops::set_property(&mut self.heap, object, name, value),
+        };
+        let site = proto.sites[offset] as usize;
+        if let Some(&Hit::Field(i)) = self.cached(closure, site, class) {
+            let handle = object.as_object().expect("the object is an instance");
+            if let Object::Instance(instance) = self.heap.get_mut(handle) {
+                if let Some((key, field)) = instance.fields.entry_mut(i) {
+                    if key == name {
+                        *field = value;
+                        return Ok(Access::Done(()));
+                    }
+                }
+            }
+        }
+        let result = ops::set_property(&mut self.heap, object, name, value)?;
+        // Setting the property of an instance without calling a setter
+        // means its class has no accessor of that name.
+        if let Access::Done(()) = result {
+            let instance = self.instance(object).expect("the object is an instance");
+            if let Some(i) = instance.fields.position(name) {
+                self.remember(closure, proto, site, class, Hit::Field(i));
+            }
+        }
+        Ok(result)
+    }
+
+    fn instance(&self, object: &Value) -> Option<&Instance> {
+        match self.heap.get(object.as_object()?) {
+            Object::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+
+    // What the cache of the closure has for the class, which is still to
+    // be checked against the instance.
+    fn cached(&self, closure: Handle, site: usize, class: Handle) -> Option<&Hit> {
+        let cache = match self.heap.get(closure) {
+            Object::Closure(closure) => closure.caches.get(site)?,
+            _ => unreachable!("frames only run closures"),
+        };
+        if cache.epoch != self.heap.class_epoch() {
+            return None;
+        }
+        cache
+            .entries
+            .iter()
+            .map_while(Option::as_ref)
+            .find(|entry| entry.class == class)
+            .map(|entry| &entry.hit)
+    }
+
+    fn remember(
+        &mut self,
+        closure: Handle,
+        proto: &Prototype,
+        site: usize,
+        class: Handle,
+        hit: Hit,
+    ) {
+        let epoch = self.heap.class_epoch();
+        let capacity = self.inline_caches.capacity();
+        let caches = match self.heap.get_mut(closure) {
+            Object::Closure(closure) => &mut closure.caches,
+            _ => unreachable!("frames only run closures"),
+        };
+        if caches.is_empty() {
+            caches.resize(proto.caches, Cache::default());
+        }
+        let cache = &mut caches[site];
+        if cache.epoch != epoch {
+            *cache = Cache {
+                epoch,
+                ..Cache::default()
+            };
+        }
+        let entries = &mut cache.entries[..capacity];
+        let slot = entries
+            .iter()
+            .position(|entry| entry.as_ref().is_some_and(|entry| entry.class == class))
+            .or_else(|| entries.iter().position(Option::is_none));
+        match slot {
+            Some(slot) => entries[slot] = Some(Entry { class, hit }),
+            // A monomorphic cache only ever remembers the last class.
+            None if capacity == 1 => entries[0] = Some(Entry { class, hit }),
+            None => {}
+        }
+    }
+}
+
+// Whether the instance has a field of the name, which would come before a
+// method or getter of its class.
+fn has_field(instance: &Instance, name: &Symbol) -> bool {
+    if instance.fields.len() <= FEW_FIELDS {
+        instance.fields.iter().any(|(key, _)| key == name)
+    } else {
+        instance.fields.get(name).is_some()
+    }
+}
@@ -0,0 +1,211 @@
+use super::{trace_frame, Frame, Upvalue, Vm};
+use crate::error::TraceFrame;
+use crate::value::{Heap, Object, Value};
+use std::collections::{HashMap, HashSet};
+
+// A debugger is told whenever the script stops, at a breakpoint or after a
+// step, and looks around at what it is given before saying how the script
+// carries on. Scripts only stop where a line starts, so a breakpoint stops
+// the script once every time its line runs rather than at every instruction
+// on it.
+pub trait DebugListener: Send {
+    fn stopped(&mut self, paused: &Paused, reason: StopReason) -> Step;
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+}
+
+// Stepping into stops at the next line that runs, wherever it is. Stepping
+// over doesn't stop in the functions called on the way, and stepping out
+// doesn't stop until the function that is running has returned.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    Continue,
+    Into,
+    Over,
+    Out,
+}
+
+#[derive(Default)]
+pub(super) struct Debugger {
+    listener: Option<Box<dyn DebugListener>>,
+    // The lines to stop at in every file.
+    breakpoints: HashMap<String, HashSet<u32>>,
+    // How the script is stepping, and how many frames there were when it
+    // started to.
+    step: Option<(Step, usize)>,
+    // The line every frame is on, the outermost first.
+    lines: Vec<u32>,
+}
+
+impl Debugger {
+    pub(super) fn is_active(&self) -> bool {
+        self.listener.is_some()
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.lines.clear();
+    }
+}
+
+impl Vm {
+    // Nothing is checked for the debugger while there is no listener, so
+    // scripts only run slower while they are being debugged. Functions
+    // compiled without debug info are all on line 0, and have no names for
+    // their locals.
+    pub fn set_debug_listener(&mut self, listener: Option<Box<dyn DebugListener>>) {
+        self.debugger.listener = listener;
+    }
+
+    pub fn set_breakpoint(&mut self, file: &str, line: u32) {
+        let lines = self.debugger.breakpoints.entry(String::from(file));
+        lines.or_default().insert(line);
+    }
+
+    // Gives back whether there was a breakpoint at the line.
+    pub fn clear_breakpoint(&mut self, file: &str, line: u32) -> bool {
+        match self.debugger.breakpoints.get_mut(file) {
+            Some(lines) => lines.remove(&line),
+            None => false,
+        }
+    }
+
+    // Steps from wherever the next script starts, which stepping into stops
+    // at.
+    pub fn set_step(&mut self, step: Step) {
+        self.debugger.step = match step {
+            Step::Continue => None,
+            step => Some((step, 0)),
+        };
+    }
+
+    // Stops for the listener if the instruction at the offset in the frame
+    // on top starts a line that it should stop at.
+    pub(super) fn debug(&mut self, offset: usize) {
+        let depth = self.frames.len();
+        let proto = self.frame().proto.clone();
+        let line = proto.function.chunk.line(offset);
+        let lines = &mut self.debugger.lines;
+        lines.resize(depth, u32::MAX);
+        // A function that was just called starts a line, even the one that
+        // the frame before it at the same depth ended on.
+        if lines[depth - 1] == line && offset > 0 {
+            return;
+        }
+        lines[depth - 1] = line;
+
+        let breakpoint = self
+            .debugger
+            .breakpoints
+            .get(&proto.file[..])
+            .is_some_and(|lines| lines.contains(&line));
+        let stepped = match self.debugger.step {
+            Some((Step::Into, _)) => true,
+            Some((Step::Over, from)) => depth <= from,
+            Some((Step::Out, from)) => depth < from,
+            _ => false,
+        };
+        if !breakpoint && !stepped {
+            return;
+        }
+        let reason = if breakpoint {
+            StopReason::Breakpoint
+        } else {
+            StopReason::Step
+        };
+        let mut listener = self.debugger.listener.take().expect("the debugger is on");
+        let step = listener.stopped(&Paused { vm: self, offset }, reason);
+        self.debugger.listener = Some(listener);
+        self.debugger.step = match step {
+            Step::Continue => None,
+            step => Some((step, depth)),
+        };
+    }
+}
+
+// The script where it stopped. Frames are counted from the innermost, the
+// same as the trace of an error.
+pub struct Paused<'a> {
+    vm: &'a Vm,
+    // Where the frame on top stopped.
+    offset: usize,
+}
+
+impl Paused<'_> {
+    pub fn heap(&self) -> &Heap {
+        &self.vm.heap
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.vm.global(name)
+    }
+
+    pub fn frames(&self) -> Vec<TraceFrame> {
+        let frames = self.vm.frames.len();
+        (0..frames)
+            .map(|i| {
+                let (frame, offset) = self.frame(i);
+                trace_frame(&frame.proto, offset, i == frames - 1)
+            })
+            .collect()
+    }
+
+    // The locals in scope in the frame, by name, in the order of their
+    // slots.
+    pub fn locals(&self, frame: usize) -> Vec<(String, Value)> {
+        let (frame, offset) = self.frame(frame);
+        let symbols = match frame.proto.function.chunk.symbols() {
+            Some(symbols) => symbols,
+            None => return Vec::new(),
+        };
+        symbols
+            .locals_at(offset)
+            .into_iter()
+            .filter_map(|local| {
+                let value = self.vm.stack.get(frame.base + local.slot)?;
+                Some((local.name.clone(), value.clone()))
+            })
+            .collect()
+    }
+
+    // The variables the function of the frame captured, by name.
+    pub fn upvalues(&self, frame: usize) -> Vec<(String, Value)> {
+        let (frame, _) = self.frame(frame);
+        let symbols = match frame.proto.function.chunk.symbols() {
+            Some(symbols) => symbols,
+            None => return Vec::new(),
+        };
+        let upvalues = match self.vm.heap.get(frame.closure) {
+            Object::Closure(closure) => &closure.upvalues,
+            _ => unreachable!("frames are always of closures"),
+        };
+        upvalues
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &upvalue)| {
+                let value = match self.vm.heap.get(upvalue) {
+                    Object::Upvalue(Upvalue::Open(slot)) => self.vm.stack[*slot].clone(),
+                    Object::Upvalue(Upvalue::Closed(value)) => value.clone(),
+                    _ => unreachable!("closures only capture upvalues"),
+                };
+                Some((String::from(symbols.upvalue(i)?), value))
+            })
+            .collect()
+    }
+
+    // A frame that isn't on top is at the call it is making, which is just
+    // before where it goes on.
+    fn frame(&self, index: usize) -> (&Frame, usize) {
+        let frames = &self.vm.frames;
+        let frame = &frames[frames.len() - 1 - index];
+        let offset = if index == 0 {
+            self.offset
+        } else {
+            frame.ip - 1
+        };
+        (frame, offset)
+    }
+}
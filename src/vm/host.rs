@@ -0,0 +1,542 @@
+use super::{trace_frame, CoroutineState, Prototype, Returned, Vm};
+use crate::compile::Signature;
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::value::convert::{FromAtom, IntoAtom};
+use crate::value::*;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// The host calls the functions of a script by handle, and scripts call the
+// functions of the host, which get the whole VM and so can call back into
+// the script in turn.
+//
+// A call from the host while the script is running, which can only come
+// from a function of the host the script called, runs on top of the frames
+// of the script, in a frame that hands what it returns back to the host.
+// The tries and coroutines of the script are put aside until the call is
+// done, so that nothing thrown or yielded inside of it gets out past the
+// function of the host, and whatever it left behind is dropped if it
+// fails. A limit of the host that it runs into can't pause it halfway, so
+// it fails, and if the function of the host fails too, the script that
+// called it stops at the limit the same way, paused at the call so that
+// carrying on calls the function of the host again.
+//
+// An async function of the host gives back a future instead of its result,
+// and the coroutine that called it waits for the future without holding up
+// the rest of the script, which goes on as if the coroutine had yielded
+// null. The host polls the futures the coroutines are waiting on, and each
+// one that is done resumes its coroutine with what it finished with, or
+// throws the error it ran into at the call.
+
+pub type HostFn = dyn Fn(&mut Vm, &[Value]) -> Result<Value, String> + Send + Sync;
+
+// What an async function of the host finished with, which is only turned
+// into a value once it is back with the heap.
+type Completion = Box<dyn FnOnce(&mut Heap) -> Result<Value, String> + Send>;
+
+pub(super) type HostFuture = Pin<Box<dyn Future<Output = Completion> + Send>>;
+
+type AsyncHostFn = dyn Fn(&mut Vm, &[Value]) -> Result<HostFuture, String> + Send + Sync;
+
+// A function of the host, which scripts call like any other. It gets the
+// arguments of the call, and gives back its result or the message of the
+// error it ran into, which the script can catch.
+#[derive(Clone)]
+pub struct HostFunction {
+    name: Arc<str>,
+    arity: usize,
+    body: Body,
+}
+
+#[derive(Clone)]
+enum Body {
+    Now(Arc<HostFn>),
+    Later(Arc<AsyncHostFn>),
+}
+
+// A coroutine that called an async function of the host, with the future
+// it is waiting on and where the call was, to throw at if the future fails.
+pub(super) struct Waiting {
+    coroutine: Handle,
+    future: HostFuture,
+    start: usize,
+    tail: bool,
+}
+
+impl HostFunction {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Two are the same when they are the same closure of the host.
+impl PartialEq for HostFunction {
+    fn eq(&self, other: &Self) -> bool {
+        let address = |host: &Self| match &host.body {
+            Body::Now(function) => Arc::as_ptr(function) as *const u8,
+            Body::Later(function) => Arc::as_ptr(function) as *const u8,
+        };
+        address(self) == address(other)
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+// A function of a script for the host to call. The VM keeps it until the
+// host releases the handle, so it can still be called after the globals of
+// its script are gone, but only with the VM it came from.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FunctionHandle(Handle);
+
+impl FunctionHandle {
+    // Calls the function, giving back what it returns. When no script is
+    // running this starts one the way `Vm::resume` does, and from inside a
+    // function of the host it calls back into the script that is running.
+    //
+    // Panics if every handle to the function was released.
+    pub fn call<A, R>(&self, vm: &mut Vm, args: A) -> Result<R, RuntimeError>
+    where
+        A: IntoArgs,
+        R: FromAtom,
+    {
+        assert!(
+            vm.functions.iter().any(|&(function, _)| function == self.0),
+            "the function was released"
+        );
+        let args = args.into_args(&mut vm.heap);
+        let value = if vm.frames.is_empty() {
+            vm.call_function(self.0, &args)?
+        } else {
+            vm.call_back(self.0, &args)?
+        };
+        R::from_atom(&value, &vm.heap).map_err(|msg| {
+            let proto = vm.proto_of(self.0);
+            let msg = format!("{} returned a value that {}", proto.function.name, msg);
+            RuntimeError::new(&msg[..], vec![trace_frame(proto, 0, false)])
+        })
+    }
+}
+
+// The arguments of a call from the host, which are values or a tuple of
+// anything that turns into them.
+pub trait IntoArgs {
+    fn into_args(self, heap: &mut Heap) -> Vec<Value>;
+}
+
+impl IntoArgs for &[Value] {
+    fn into_args(self, _: &mut Heap) -> Vec<Value> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> IntoArgs for &[Value; N] {
+    fn into_args(self, _: &mut Heap) -> Vec<Value> {
+        self.to_vec()
+    }
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self, _: &mut Heap) -> Vec<Value> {
+        self
+    }
+}
+
+// What a function of the host gives back, which is anything that turns
+// into a value, or the message of the error it ran into.
+pub trait IntoResult {
+    fn into_result(self, heap: &mut Heap) -> Result<Value, String>;
+}
+
+impl<T: IntoAtom> IntoResult for T {
+    fn into_result(self, heap: &mut Heap) -> Result<Value, String> {
+        Ok(self.into_atom(heap))
+    }
+}
+
+impl<T: IntoAtom> IntoResult for Result<T, String> {
+    fn into_result(self, heap: &mut Heap) -> Result<Value, String> {
+        self.map(|value| value.into_atom(heap))
+    }
+}
+
+// A Rust function whose arguments can be converted from values, which
+// becomes a function of the host taking as many.
+pub trait IntoHostFunction<Args> {
+    fn arity(&self) -> usize;
+
+    fn into_host(self, name: Arc<str>) -> Arc<HostFn>;
+}
+
+// A Rust function like those, that gives back a future of its result
+// instead, which becomes an async function of the host.
+pub trait IntoAsyncHostFunction<Args> {
+    fn arity(&self) -> usize;
+
+    fn into_async_host(self, name: Arc<str>) -> Arc<AsyncHostFn>;
+}
+
+macro_rules! host_functions {
+    ($(($arity:expr $(, $arg:ident)*),)*) => {
+        $(
+            impl<H, R, $($arg),*> IntoHostFunction<($($arg,)*)> for H
+            where
+                H: Fn($($arg),*) -> R + Send + Sync + 'static,
+                R: IntoResult,
+                $($arg: FromAtom,)*
+            {
+                fn arity(&self) -> usize {
+                    $arity
+                }
+
+                #[allow(unused_mut, unused_variables)]
+                fn into_host(self, name: Arc<str>) -> Arc<HostFn> {
+                    Arc::new(move |vm: &mut Vm, args: &[Value]| {
+                        let mut args = args.iter().enumerate();
+                        let result = self($({
+                            let (i, arg) = args.next().expect("the arity was checked");
+                            $arg::from_atom(arg, &vm.heap).map_err(|msg| {
+                                format!("argument {} of {} {}", i + 1, name, msg)
+                            })?
+                        }),*);
+                        result.into_result(&mut vm.heap)
+                    })
+                }
+            }
+
+            impl<H, T, R, $($arg),*> IntoAsyncHostFunction<($($arg,)*)> for H
+            where
+                H: Fn($($arg),*) -> T + Send + Sync + 'static,
+                T: Future<Output = R> + Send + 'static,
+                R: IntoResult + Send + 'static,
+                $($arg: FromAtom,)*
+            {
+                fn arity(&self) -> usize {
+                    $arity
+                }
+
+                #[allow(unused_mut, unused_variables)]
+                fn into_async_host(self, name: Arc<str>) -> Arc<AsyncHostFn> {
+                    Arc::new(move |vm: &mut Vm, args: &[Value]| {
+                        let mut args = args.iter().enumerate();
+                        let future = self($({
+                            let (i, arg) = args.next().expect("the arity was checked");
+                            $arg::from_atom(arg, &vm.heap).map_err(|msg| {
+                                format!("argument {} of {} {}", i + 1, name, msg)
+                            })?
+                        }),*);
+                        let future: HostFuture = Box::pin(async move {
+                            let result = future.await;
+                            Box::new(move |heap: &mut Heap| result.into_result(heap)) as Completion
+                        });
+                        Ok(future)
+                    })
+                }
+            }
+
+            impl<$($arg: IntoAtom),*> IntoArgs for ($($arg,)*) {
+                #[allow(non_snake_case, unused_variables)]
+                fn into_args(self, heap: &mut Heap) -> Vec<Value> {
+                    let ($($arg,)*) = self;
+                    vec![$($arg.into_atom(heap)),*]
+                }
+            }
+        )*
+    };
+}
+
+host_functions! {
+    (0),
+    (1, A),
+    (2, A, B),
+    (3, A, B, C),
+    (4, A, B, C, D),
+    (5, A, B, C, D, E),
+    (6, A, B, C, D, E, F),
+}
+
+impl Vm {
+    // The function a global holds, or nothing if it holds something else or
+    // isn't defined.
+    pub fn get_function(&mut self, name: &str) -> Option<FunctionHandle> {
+        let handle = self.global(name)?.as_object()?;
+        if !matches!(self.heap.get(handle), Object::Closure(_)) {
+            return None;
+        }
+        match self
+            .functions
+            .iter_mut()
+            .find(|(function, _)| *function == handle)
+        {
+            Some((_, handles)) => *handles += 1,
+            None => self.functions.push((handle, 1)),
+        }
+        Some(FunctionHandle(handle))
+    }
+
+    // Lets the function go once every handle to it is released, after which
+    // the garbage collector can free it along with what it captures. Each
+    // handle `get_function` gave out is released once.
+    pub fn release_function(&mut self, function: FunctionHandle) {
+        let position = self.functions.iter().position(|&(f, _)| f == function.0);
+        if let Some(i) = position {
+            self.functions[i].1 -= 1;
+            if self.functions[i].1 == 0 {
+                self.functions.remove(i);
+            }
+        }
+    }
+
+    // Makes a Rust function a global, set the way `set_global` sets one,
+    // converting the arguments it is called with and what it gives back.
+    pub fn define_function<F, Args>(&mut self, name: &str, function: F)
+    where
+        F: IntoHostFunction<Args>,
+    {
+        let host = HostFunction {
+            name: Arc::from(name),
+            arity: function.arity(),
+            body: Body::Now(function.into_host(Arc::from(name))),
+        };
+        let handle = self.heap.alloc(Object::Host(host));
+        self.set_global(name, handle);
+    }
+
+    // Makes a Rust function that gives back a future a global, which only
+    // coroutines can call. The arguments are converted before the future
+    // starts, so it should take what it needs out of them rather than keep
+    // objects of the heap, which can be collected while it waits.
+    pub fn define_async_function<F, Args>(&mut self, name: &str, function: F)
+    where
+        F: IntoAsyncHostFunction<Args>,
+    {
+        let host = HostFunction {
+            name: Arc::from(name),
+            arity: function.arity(),
+            body: Body::Later(function.into_async_host(Arc::from(name))),
+        };
+        let handle = self.heap.alloc(Object::Host(host));
+        self.set_global(name, handle);
+    }
+
+    // Makes a function of the host that gets the whole VM, and so can call
+    // back into the script, a global. It is called with exactly as many
+    // arguments as its arity.
+    pub fn define_raw_function<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&mut Vm, &[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let host = HostFunction {
+            name: Arc::from(name),
+            arity,
+            body: Body::Now(Arc::new(function)),
+        };
+        let handle = self.heap.alloc(Object::Host(host));
+        self.set_global(name, handle);
+    }
+
+    // The arguments stay on the stack while the function of the host runs,
+    // so that they aren't collected if it calls back into the script.
+    pub(super) fn call_host(&mut self, host: HostFunction, argc: usize) -> Result<(), String> {
+        let signature = Signature {
+            arity: host.arity,
+            min_arity: host.arity,
+            rest: false,
+        };
+        if !signature.accepts(argc) {
+            return Err(signature.mismatch(&host.name, argc));
+        }
+        let base = self.stack.len() - argc - 1;
+        let args = self.stack[base + 1..].to_vec();
+        let result = match &host.body {
+            Body::Now(function) => function(self, &args)?,
+            Body::Later(function) => {
+                if self.resumed.is_empty() {
+                    let msg = format!("cannot wait for {} outside of a coroutine", host.name);
+                    return Err(msg);
+                }
+                self.awaiting = Some(function(self, &args)?);
+                self.stack.truncate(base);
+                return Ok(());
+            }
+        };
+        self.stack.truncate(base);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    // Stops the innermost coroutine to wait on what the function of the
+    // host it called gave back, in place of a result.
+    pub(super) fn wait(&mut self, future: HostFuture, start: usize, tail: bool) -> Option<Value> {
+        let resumed = self.resumed.pop().expect("only coroutines wait");
+        self.suspend(&resumed);
+        if let Object::Coroutine(coroutine) = self.heap.get_mut(resumed.coroutine) {
+            coroutine.state = CoroutineState::Waiting;
+        }
+        self.waiting.push(Waiting {
+            coroutine: resumed.coroutine,
+            future,
+            start,
+            tail,
+        });
+        self.hand_back(Value::Null, resumed.tail)
+    }
+
+    // How many coroutines are waiting on the host.
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub(super) fn waiting_coroutines(&self) -> impl Iterator<Item = Handle> + '_ {
+        self.waiting.iter().map(|waiting| waiting.coroutine)
+    }
+
+    // Polls the future each waiting coroutine waits on once, and resumes
+    // the coroutines whose future is done, in the order they started
+    // waiting. What they yield or return is dropped. Like `resume`, this
+    // drops a script that was left paused. Gives back how many were
+    // resumed, or the error one of them stopped with, leaving the rest
+    // waiting.
+    pub fn poll_waiting(&mut self, cx: &mut Context<'_>) -> Result<usize, RuntimeError> {
+        // Coroutines that start waiting while the others run go on the end,
+        // and aren't polled until next time.
+        let mut polling = self.waiting.len();
+        let mut resumed = 0;
+        let mut i = 0;
+        while i < polling {
+            let completion = match self.waiting[i].future.as_mut().poll(cx) {
+                Poll::Ready(completion) => completion,
+                Poll::Pending => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let waiter = self.waiting.remove(i);
+            polling -= 1;
+            resumed += 1;
+            let value = completion(&mut self.heap);
+            self.wake(waiter, value)?;
+        }
+        Ok(resumed)
+    }
+
+    fn wake(&mut self, waiter: Waiting, value: Result<Value, String>) -> Result<(), RuntimeError> {
+        self.reset();
+        if let Object::Coroutine(coroutine) = self.heap.get_mut(waiter.coroutine) {
+            coroutine.state = CoroutineState::Suspended;
+        }
+        self.stack.push(Value::Object(waiter.coroutine));
+        self.stack.push(value.clone().unwrap_or_default());
+        self.resume_coroutine(waiter.coroutine, 1)
+            .expect("a waiting coroutine can be resumed");
+        match value {
+            Err(msg) => {
+                let error = self.fail(waiter.start, &msg[..]);
+                if let Err(error) = self.handle_error(error) {
+                    self.reset();
+                    return Err(error);
+                }
+            }
+            // A tail call to the function of the host returns what it
+            // finished with.
+            Ok(_) if waiter.tail => {
+                if self.return_value().is_some() {
+                    return Ok(());
+                }
+            }
+            Ok(_) => {}
+        }
+        self.finish().map(drop)
+    }
+
+    fn call_function(&mut self, function: Handle, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.reset();
+        if let Err(error) = self.push_call(function, args) {
+            self.reset();
+            return Err(error);
+        }
+        // A function compiled to native code is done already.
+        if self.frames.is_empty() {
+            return Ok(self.pop());
+        }
+        self.finish()
+    }
+
+    fn call_back(&mut self, function: Handle, args: &[Value]) -> Result<Value, RuntimeError> {
+        let frames = self.frames.len();
+        let base = self.stack.len();
+        let handlers = mem::take(&mut self.handlers);
+        let resumed = mem::take(&mut self.resumed);
+        let caught = mem::take(&mut self.caught);
+        let result = match self.push_call(function, args) {
+            Ok(()) if self.frames.len() > frames => self.execute(),
+            Ok(()) => Ok(self.pop()),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = &result {
+            for resumed in self.resumed.drain(..) {
+                if let Object::Coroutine(coroutine) = self.heap.get_mut(resumed.coroutine) {
+                    coroutine.state = CoroutineState::Finished;
+                }
+            }
+            self.frames.truncate(frames);
+            self.importing.retain(|&(_, frame)| frame < frames);
+            self.close_upvalues(base);
+            self.stack.truncate(base);
+            self.thrown = None;
+            if error.kind() != RuntimeErrorKind::Error {
+                self.limited = Some(error.kind());
+            }
+            if matches!(
+                error.kind(),
+                RuntimeErrorKind::Interrupted | RuntimeErrorKind::TimedOut
+            ) {
+                self.interrupted.store(true, Ordering::Relaxed);
+            }
+        }
+        self.handlers = handlers;
+        self.resumed = resumed;
+        self.caught = caught;
+        result
+    }
+
+    // Calls the function with the arguments, in a frame that returns to the
+    // host. A call that can't be made fails at the start of the function.
+    fn push_call(&mut self, function: Handle, args: &[Value]) -> Result<(), RuntimeError> {
+        let calling = self.frames.len();
+        self.stack.push(Value::Object(function));
+        self.stack.extend_from_slice(args);
+        if let Err(msg) = self.call(args.len()) {
+            let proto = self.proto_of(function);
+            return Err(RuntimeError::new(
+                &msg[..],
+                vec![trace_frame(proto, 0, false)],
+            ));
+        }
+        if self.frames.len() > calling {
+            self.frames
+                .last_mut()
+                .expect("the call made a frame")
+                .returned = Returned::Host;
+        }
+        Ok(())
+    }
+
+    fn proto_of(&self, function: Handle) -> &Prototype {
+        match self.heap.get(function) {
+            Object::Closure(closure) => &closure.proto,
+            _ => unreachable!("handles are only made for closures"),
+        }
+    }
+}
@@ -0,0 +1,791 @@
+use super::{binary_op, name, Handler, Prototype, Returned, Upvalue, Vm};
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::bytecode::{Constant, OpCode};
+use crate::error::RuntimeError;
+use crate::value::ops::{self, Access};
+use crate::value::*;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+// Every instruction is run by a method of its own, which the dispatch loop
+// picks by matching on the opcode. With threaded dispatch, the code of a
+// function is decoded once into the methods its instructions run, and the
+// loop calls them straight from there instead.
+
+// The instruction being run, and the offset it starts at.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct Instruction {
+    pub op: OpCode,
+    pub operand: usize,
+    pub start: usize,
+}
+
+// What the dispatch loop keeps of the frame on top, so that it doesn't have
+// to look it up for every instruction. The offset of the next instruction
+// is only written back to the frame when it calls another one.
+pub(super) struct Registers {
+    pub proto: Arc<Prototype>,
+    pub closure: Handle,
+    pub base: usize,
+    pub ip: usize,
+    pub globals: Handle,
+}
+
+// Where the script goes after an instruction.
+pub(super) enum Flow {
+    Next,
+    // A frame was pushed or popped, so the one on top runs next.
+    Resume,
+    // The script is done, or the coroutine it was running gave this back.
+    Return(Value),
+}
+
+// The error is boxed to keep what every instruction gives back small, which
+// the loop is a good deal faster for.
+pub(super) type Step = Result<Flow, Box<RuntimeError>>;
+
+#[cfg(feature = "threaded-dispatch")]
+pub(super) type Execute = fn(&mut Vm, &mut Registers, Instruction) -> Step;
+
+// An instruction of a prototype, decoded for threaded dispatch.
+#[cfg(feature = "threaded-dispatch")]
+#[derive(Copy, Clone, Debug)]
+pub(super) struct Threaded {
+    pub execute: Execute,
+    pub op: OpCode,
+    pub operand: usize,
+    // Where the instruction after it starts.
+    pub next: usize,
+}
+
+// The opcode decides the handler, which can't be compared itself.
+#[cfg(feature = "threaded-dispatch")]
+impl PartialEq for Threaded {
+    fn eq(&self, other: &Self) -> bool {
+        (self.op, self.operand, self.next) == (other.op, other.operand, other.next)
+    }
+}
+
+// Decodes every instruction of the chunk by the offset it starts at. The
+// offsets in the middle of an instruction can only be jumped to by broken
+// code, so they are invalid instructions of their own.
+#[cfg(feature = "threaded-dispatch")]
+pub(super) fn decode(chunk: &crate::bytecode::Chunk) -> Vec<Threaded> {
+    let invalid = Threaded {
+        execute: Vm::invalid,
+        op: OpCode::Wide,
+        operand: 0,
+        next: 0,
+    };
+    let mut threaded = vec![invalid; chunk.len()];
+    for (offset, op) in chunk.instructions() {
+        threaded[offset] = Threaded {
+            execute: execute_fn(op),
+            op,
+            operand: chunk.operand(offset),
+            next: chunk.next_offset(offset),
+        };
+    }
+    threaded
+}
+
+// Matches every opcode to the method that runs it, once for the dispatch
+// loop and once for the table of threaded dispatch, so the two can't run
+// an instruction differently.
+macro_rules! instructions {
+    ($($($op:path)|+ => $method:ident,)*) => {
+        impl Vm {
+            #[cfg(not(feature = "threaded-dispatch"))]
+            #[inline(always)]
+            pub(super) fn run_instruction(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+                match instruction.op {
+                    $($($op)|+ => self.$method(regs, instruction),)*
+                }
+            }
+        }
+
+        #[cfg(feature = "threaded-dispatch")]
+        pub(super) fn execute_fn(op: OpCode) -> Execute {
+            match op {
+                $($($op)|+ => Vm::$method,)*
+            }
+        }
+    };
+}
+
+instructions! {
+    OpCode::Constant => constant,
+    OpCode::Null | OpCode::True | OpCode::False => literal,
+    OpCode::Pop => pop_value,
+    OpCode::Dup => dup,
+    OpCode::Dup2 => dup2,
+    OpCode::GetLocal => get_local,
+    OpCode::SetLocal => set_local,
+    OpCode::DefineGlobal => define_global,
+    OpCode::GetGlobal => get_global_named,
+    OpCode::SetGlobal => set_global_named,
+    OpCode::DefineSlot => define_slot,
+    OpCode::GetSlot => get_slot,
+    OpCode::SetSlot => set_slot,
+    OpCode::Import => import_module,
+    OpCode::GetUpvalue => get_upvalue,
+    OpCode::SetUpvalue => set_upvalue,
+    OpCode::GetProperty => get_property_of,
+    OpCode::SetProperty => set_property_of,
+    OpCode::GetSuper => get_super,
+    OpCode::GetIndex => get_index,
+    OpCode::SetIndex => set_index,
+    OpCode::Slice => slice,
+    OpCode::Negate | OpCode::Not | OpCode::BitNot => unary,
+    OpCode::Jump => jump,
+    OpCode::JumpIfFalse => jump_if_false,
+    OpCode::Loop => loop_back,
+    OpCode::Try => begin_try,
+    OpCode::EndTry => end_try,
+    OpCode::Throw => throw,
+    OpCode::Rethrow => rethrow,
+    OpCode::Switch => switch,
+    OpCode::Call | OpCode::TailCall => call_value,
+    OpCode::Return => return_from,
+    OpCode::Yield => yield_value,
+    OpCode::Closure => make_closure,
+    OpCode::CloseUpvalue => close_upvalue,
+    OpCode::List => list,
+    OpCode::Map => map,
+    OpCode::Concat => concat,
+    OpCode::Range => range,
+    OpCode::Class => class_named,
+    OpCode::Inherit => inherit,
+    OpCode::Method | OpCode::Getter | OpCode::Setter => member,
+    OpCode::Wide => invalid,
+    OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Modulo
+        | OpCode::BitAnd
+        | OpCode::BitOr
+        | OpCode::BitXor
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual => binary,
+}
+
+impl Vm {
+    // The registers of the frame on top.
+    #[inline(always)]
+    pub(super) fn registers(&self) -> Registers {
+        let frame = self.frame();
+        Registers {
+            proto: frame.proto.clone(),
+            closure: frame.closure,
+            base: frame.base,
+            ip: frame.ip,
+            globals: self.globals_of(frame.closure),
+        }
+    }
+
+    // Calls a method in place of the instruction, which runs next if it has
+    // a frame.
+    fn invoke_for(
+        &mut self,
+        ip: usize,
+        start: usize,
+        method: Value,
+        args: Vec<Value>,
+        returned: Returned,
+    ) -> Step {
+        self.frames.last_mut().expect("no function is running").ip = ip;
+        attempt!(self, start, self.invoke(method, args, returned));
+        Ok(Flow::Resume)
+    }
+
+    #[inline(always)]
+    fn constant(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        match &regs.proto.function.chunk.constants()[operand] {
+            Constant::Function(_) => {
+                let function = self.closure(&regs.proto, operand, regs.globals, Vec::new());
+                self.stack.push(function);
+            }
+            constant => {
+                let value = Value::from_constant(constant);
+                let value = attempt!(self, start, value.ok_or("invalid constant"));
+                self.stack.push(value);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn literal(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let value = match instruction.op {
+            OpCode::True => Value::Bool(true),
+            OpCode::False => Value::Bool(false),
+            _ => Value::Null,
+        };
+        self.stack.push(value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn pop_value(&mut self, _: &mut Registers, _: Instruction) -> Step {
+        self.pop();
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn dup(&mut self, _: &mut Registers, _: Instruction) -> Step {
+        self.stack.push(self.peek().clone());
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn dup2(&mut self, _: &mut Registers, _: Instruction) -> Step {
+        let len = self.stack.len();
+        self.stack.extend_from_within(len - 2..);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn get_local(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        self.stack
+            .push(self.stack[regs.base + instruction.operand].clone());
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn set_local(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        self.stack[regs.base + instruction.operand] = self.peek().clone();
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn define_global(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let constants = regs.proto.function.chunk.constants();
+        let name = attempt!(self, start, name(constants, operand));
+        let value = self.pop();
+        self.globals.insert(name, value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn get_global_named(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let constants = regs.proto.function.chunk.constants();
+        let name = attempt!(self, start, name(constants, operand));
+        let value = match self.globals.get(&name) {
+            Some(value) => value.clone(),
+            None => return Err(self.undefined(&name, start).into()),
+        };
+        self.stack.push(value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn set_global_named(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let constants = regs.proto.function.chunk.constants();
+        let name = attempt!(self, start, name(constants, operand));
+        let value = self.peek().clone();
+        match self.globals.get_mut(&name) {
+            Some(global) => *global = value,
+            None => return Err(self.undefined(&name, start).into()),
+        }
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn define_slot(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let value = self.pop();
+        self.slots_mut(regs.globals).slots[instruction.operand] = Some(value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn get_slot(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let slots = self.slots(regs.globals);
+        match &slots.slots[operand] {
+            Some(value) => self.stack.push(value.clone()),
+            None => return Err(self.undefined(&slots.names[operand], start).into()),
+        }
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn set_slot(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let value = self.peek().clone();
+        match &mut self.slots_mut(regs.globals).slots[operand] {
+            Some(slot) => *slot = value,
+            None => {
+                let name = &self.slots(regs.globals).names[operand];
+                return Err(self.undefined(name, start).into());
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn import_module(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let constants = regs.proto.function.chunk.constants();
+        let path = attempt!(self, start, name(constants, operand));
+        match self.modules.get(&path[..]) {
+            Some(exports) => {
+                self.stack.push(exports.clone());
+                Ok(Flow::Next)
+            }
+            None => {
+                self.frames.last_mut().expect("no function is running").ip = regs.ip;
+                attempt!(self, start, self.import(&path));
+                Ok(Flow::Resume)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn get_upvalue(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let upvalue = self.upvalue(regs.closure, instruction.operand);
+        let value = match self.heap.get(upvalue) {
+            Object::Upvalue(Upvalue::Open(slot)) => self.stack[*slot].clone(),
+            Object::Upvalue(Upvalue::Closed(value)) => value.clone(),
+            _ => unreachable!("closures only capture upvalues"),
+        };
+        self.stack.push(value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn set_upvalue(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let upvalue = self.upvalue(regs.closure, instruction.operand);
+        let value = self.peek().clone();
+        match self.heap.get_mut(upvalue) {
+            Object::Upvalue(Upvalue::Open(slot)) => {
+                let slot = *slot;
+                self.stack[slot] = value;
+            }
+            Object::Upvalue(Upvalue::Closed(closed)) => *closed = value,
+            _ => unreachable!("closures only capture upvalues"),
+        }
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn get_property_of(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let name = attempt!(self, start, regs.proto.symbol(operand));
+        let object = self.pop();
+        let result = self.get_property(regs.closure, &regs.proto, start, &object, name);
+        match attempt!(self, start, result) {
+            Access::Done(value) => {
+                self.stack.push(value);
+                Ok(Flow::Next)
+            }
+            Access::Call(getter) => {
+                self.invoke_for(regs.ip, start, getter, Vec::new(), Returned::Kept)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn set_property_of(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let name = attempt!(self, start, regs.proto.symbol(operand));
+        let value = self.pop();
+        let object = self.pop();
+        let result = self.set_property(
+            regs.closure,
+            &regs.proto,
+            start,
+            &object,
+            name,
+            value.clone(),
+        );
+        self.stack.push(value.clone());
+        match attempt!(self, start, result) {
+            Access::Done(()) => Ok(Flow::Next),
+            Access::Call(setter) => {
+                self.invoke_for(regs.ip, start, setter, vec![value], Returned::Discarded)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn get_super(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let name = attempt!(self, start, regs.proto.symbol(operand));
+        let superclass = self.pop();
+        let receiver = self.pop();
+        let result = ops::get_super(&mut self.heap, &receiver, &superclass, name);
+        let value = attempt!(self, start, result);
+        self.stack.push(value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn get_index(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let start = instruction.start;
+        let index = self.pop();
+        let object = self.pop();
+        let result = ops::get_index(&mut self.heap, &object, &index);
+        match attempt!(self, start, result) {
+            Access::Done(value) => {
+                self.stack.push(value);
+                Ok(Flow::Next)
+            }
+            Access::Call(method) => {
+                self.invoke_for(regs.ip, start, method, vec![index], Returned::Kept)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn set_index(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let start = instruction.start;
+        let value = self.pop();
+        let index = self.pop();
+        let object = self.pop();
+        let result = ops::set_index(&mut self.heap, &object, &index, value.clone());
+        self.stack.push(value.clone());
+        match attempt!(self, start, result) {
+            Access::Done(()) => Ok(Flow::Next),
+            Access::Call(method) => {
+                let args = vec![index, value];
+                self.invoke_for(regs.ip, start, method, args, Returned::Discarded)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn slice(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let start = instruction.start;
+        let end = self.pop();
+        let start_bound = self.pop();
+        let object = self.pop();
+        let result = ops::slice(&mut self.heap, &object, &start_bound, &end);
+        let value = attempt!(self, start, result);
+        self.stack.push(value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn unary(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let start = instruction.start;
+        let op = match instruction.op {
+            OpCode::Negate => UnaryOp::Negate,
+            OpCode::Not => UnaryOp::Not,
+            _ => UnaryOp::BitNot,
+        };
+        let operand = self.pop();
+        match attempt!(self, start, ops::unary(&mut self.heap, op, &operand)) {
+            Access::Done(value) => {
+                self.stack.push(value);
+                Ok(Flow::Next)
+            }
+            Access::Call(method) => {
+                self.invoke_for(regs.ip, start, method, Vec::new(), Returned::Kept)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn jump(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        regs.ip += instruction.operand;
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn jump_if_false(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        if !self.peek().is_truthy() {
+            regs.ip += instruction.operand;
+        }
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn loop_back(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        regs.ip -= instruction.operand;
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn begin_try(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        self.handlers.push(Handler {
+            frames: self.frames.len(),
+            depth: self.stack.len(),
+            ip: regs.ip + instruction.operand,
+        });
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn end_try(&mut self, _: &mut Registers, _: Instruction) -> Step {
+        self.handlers.pop();
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn throw(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let value = self.pop();
+        let msg = value.display(&self.heap).to_string();
+        self.thrown = Some(value);
+        Err(self.fail(instruction.start, &msg[..]).into())
+    }
+
+    #[inline(always)]
+    fn rethrow(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let value = self.pop();
+        let slot = self.stack.len();
+        let error = match self.caught.iter().rposition(|&(s, _)| s == slot) {
+            Some(position) => self.caught.remove(position).1,
+            // A coroutine that stopped in the finally block has moved its
+            // slots since.
+            None => self.fail(instruction.start, &value.display(&self.heap).to_string()),
+        };
+        self.thrown = Some(value);
+        Err(error.into())
+    }
+
+    // Every jump in the table is as wide as the first.
+    #[inline(always)]
+    fn switch(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let chunk = &regs.proto.function.chunk;
+        let cases = match &chunk.constants()[instruction.operand] {
+            Constant::Cases(cases) => cases,
+            _ => return Err(self.fail(instruction.start, "invalid constant").into()),
+        };
+        let case = match self.pop() {
+            Value::Int(n) => cases.find_int(n),
+            Value::Float(n) => cases.find_number(n),
+            Value::String(s) => cases.find_string(&s),
+            _ => None,
+        };
+        let width = if chunk.is_wide(regs.ip) { 6 } else { 3 };
+        regs.ip += case.unwrap_or(cases.len()) * width;
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn call_value(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { op, operand, start } = instruction;
+        self.frames.last_mut().expect("no function is running").ip = regs.ip;
+        let calling = self.frames.len();
+        let resuming = self.resumed.len();
+        let called = self.call(operand);
+        // A call back from the host that stopped at a limit stops the script
+        // at this call, with what it called still on the stack.
+        if let Some(kind) = self.limited.take() {
+            if let Err(msg) = called {
+                self.interrupted.store(false, Ordering::Relaxed);
+                self.frames.last_mut().expect("no function is running").ip = start;
+                return Err(self.fail(start, &msg[..]).with_kind(kind).into());
+            }
+        }
+        attempt!(self, start, called);
+        if let Some(future) = self.awaiting.take() {
+            return match self.wait(future, start, op == OpCode::TailCall) {
+                Some(value) => Ok(Flow::Return(value)),
+                None => Ok(Flow::Resume),
+            };
+        }
+        let returned = if self.resumed.len() > resuming {
+            // The frames of the coroutine go on top of the frame that
+            // resumed it, even for a tail call.
+            let resumed = self.resumed.last_mut().expect("a coroutine was resumed");
+            resumed.tail = op == OpCode::TailCall;
+            false
+        } else if self.frames.len() > calling {
+            if op == OpCode::TailCall {
+                self.replace_caller();
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.tail_call();
+                }
+            }
+            false
+        } else {
+            // Natives are done by the time the call is, and a tail call to
+            // one returns what it gave back.
+            op == OpCode::TailCall
+        };
+        if returned {
+            if let Some(value) = self.return_value() {
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Resume)
+    }
+
+    #[inline(always)]
+    fn return_from(&mut self, _: &mut Registers, _: Instruction) -> Step {
+        match self.return_value() {
+            Some(value) => Ok(Flow::Return(value)),
+            None => Ok(Flow::Resume),
+        }
+    }
+
+    #[inline(always)]
+    fn yield_value(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let value = self.pop();
+        let resumed = match self.resumed.pop() {
+            Some(resumed) => resumed,
+            None => {
+                let msg = "cannot yield outside of a coroutine";
+                return Err(self.fail(instruction.start, msg).into());
+            }
+        };
+        self.frames.last_mut().expect("no function is running").ip = regs.ip;
+        self.suspend(&resumed);
+        match self.hand_back(value, resumed.tail) {
+            Some(value) => Ok(Flow::Return(value)),
+            None => Ok(Flow::Resume),
+        }
+    }
+
+    #[inline(always)]
+    fn make_closure(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let captures = match &regs.proto.functions[operand] {
+            Some(function) => function.function.upvalues.clone(),
+            None => return Err(self.fail(start, "invalid constant").into()),
+        };
+        let upvalues = captures
+            .iter()
+            .map(|capture| {
+                if capture.local {
+                    self.capture(regs.base + capture.index)
+                } else {
+                    self.upvalue(regs.closure, capture.index)
+                }
+            })
+            .collect();
+        let function = self.closure(&regs.proto, operand, regs.globals, upvalues);
+        self.stack.push(function);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn close_upvalue(&mut self, _: &mut Registers, _: Instruction) -> Step {
+        self.close_upvalues(self.stack.len() - 1);
+        self.pop();
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn list(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let items = self.stack.split_off(self.stack.len() - instruction.operand);
+        let list = self.heap.alloc(Object::List(items));
+        self.stack.push(Value::Object(list));
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn map(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let entries = self.stack.split_off(self.stack.len() - operand * 2);
+        let mut map = Map::new();
+        for entry in entries.chunks(2) {
+            let key = attempt!(self, start, ops::key(&entry[0]));
+            map.insert(key, entry[1].clone());
+        }
+        let map = self.heap.alloc(Object::Map(map));
+        self.stack.push(Value::Object(map));
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn concat(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let parts = self.stack.split_off(self.stack.len() - instruction.operand);
+        let parts: Vec<String> = parts
+            .iter()
+            .map(|part| part.display(&self.heap).to_string())
+            .collect();
+        self.stack.push(Value::from(parts.concat()));
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn range(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let end = self.pop();
+        let start_bound = self.pop();
+        let result = ops::range(&mut self.heap, &start_bound, &end, operand == 1);
+        let value = attempt!(self, start, result);
+        self.stack.push(value);
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn class_named(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { operand, start, .. } = instruction;
+        let constants = regs.proto.function.chunk.constants();
+        let name = attempt!(self, start, name(constants, operand));
+        let class = self.heap.alloc(Object::Class(Class::new(&name)));
+        self.stack.push(Value::Object(class));
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn inherit(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        let start = instruction.start;
+        let superclass = self.pop();
+        let class = attempt!(self, start, self.class());
+        attempt!(
+            self,
+            start,
+            ops::inherit(&mut self.heap, class, &superclass)
+        );
+        Ok(Flow::Next)
+    }
+
+    #[inline(always)]
+    fn member(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let Instruction { op, operand, start } = instruction;
+        let name = attempt!(self, start, regs.proto.symbol(operand));
+        let method = self.pop();
+        let class = attempt!(self, start, self.class());
+        if let Object::Class(class) = self.heap.get_mut(class) {
+            let members = match op {
+                OpCode::Method => &mut class.methods,
+                OpCode::Getter => &mut class.getters,
+                _ => &mut class.setters,
+            };
+            members.insert(name.clone(), method);
+        }
+        Ok(Flow::Next)
+    }
+
+    // Wide prefixes are read along with the instruction after them, so one
+    // is only ever run on its own by jumping into the middle of the code.
+    fn invalid(&mut self, _: &mut Registers, instruction: Instruction) -> Step {
+        Err(self.fail(instruction.start, "invalid instruction").into())
+    }
+
+    #[inline(always)]
+    fn binary(&mut self, regs: &mut Registers, instruction: Instruction) -> Step {
+        let start = instruction.start;
+        let right = self.pop();
+        let left = self.pop();
+        let op = binary_op(instruction.op);
+        let result = ops::binary(&mut self.heap, op, &left, &right);
+        match attempt!(self, start, result) {
+            Access::Done(value) => {
+                self.stack.push(value);
+                Ok(Flow::Next)
+            }
+            Access::Call(method) => {
+                let returned = if op == BinaryOp::NotEqual {
+                    Returned::Negated
+                } else {
+                    Returned::Kept
+                };
+                self.invoke_for(regs.ip, start, method, vec![right], returned)
+            }
+        }
+    }
+}
@@ -0,0 +1,767 @@
+use super::{Prototype, Vm};
+use crate::bytecode::{Chunk, Constant, OpCode};
+use crate::value::Value;
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{self, types, AbiParam, Block, InstBuilder, MemFlags};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+
+// Functions that get hot are compiled to native code, for the types of the
+// arguments of the call that made them hot. Only functions that work out
+// numbers are compiled: ones that use nothing but their own slots, ints,
+// floats, bools and null, jumps and loops. Anything else, like a call or
+// a global, leaves the function to the interpreter for good.
+//
+// Compiled code goes back to the interpreter whenever it comes across
+// something it can't do, such as an int that overflows, a division by
+// zero or an interrupt. Such functions can't have done anything anyone else
+// could see, so the interpreter runs the call again from the start, which
+// ends the same way it would have without compiling it.
+//
+// Compiled code doesn't count fuel, stop for the debugger or show up in
+// profiles and traces, so none of it runs while any of those are on.
+
+// How many times a function is called before it is compiled.
+pub const HOT_CALLS: u32 = 1000;
+
+// Functions that take more arguments than this are never compiled.
+const MAX_PARAMS: usize = 15;
+
+// What compiled code gives back.
+const DONE: i64 = 0;
+const BAILED: i64 = 1;
+
+// How many functions were compiled, how many got hot but couldn't be, and
+// how many calls to compiled code went back to the interpreter.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct JitStats {
+    pub compiled: usize,
+    pub rejected: usize,
+    pub bailouts: usize,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Jit {
+    threshold: Option<u32>,
+    stats: JitStats,
+}
+
+impl Default for Jit {
+    fn default() -> Self {
+        Self {
+            threshold: Some(HOT_CALLS),
+            stats: JitStats::default(),
+        }
+    }
+}
+
+// How many times a prototype was called, and the code it was compiled to
+// once it got hot, if it could be.
+#[derive(Default)]
+pub(super) struct Tier {
+    calls: AtomicU32,
+    compiled: OnceLock<Option<Compiled>>,
+}
+
+// Prototypes are the same whether or not they have been compiled.
+impl PartialEq for Tier {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tier")
+            .field("calls", &self.calls.load(Ordering::Relaxed))
+            .field("compiled", &matches!(self.compiled.get(), Some(Some(_))))
+            .finish()
+    }
+}
+
+struct Compiled {
+    params: Vec<Ty>,
+    returns: Ty,
+    code: Code,
+}
+
+// Compiled code takes the arguments in the slots after the first, where it
+// leaves what it returns.
+type Entry = unsafe extern "C" fn(*mut u64, *const u8) -> i64;
+
+// The code of a function, along with the module whose memory it is in.
+struct Code {
+    module: ManuallyDrop<JITModule>,
+    entry: Entry,
+}
+
+// SAFETY: the module isn't used again once the code is made, other than to
+// free its memory, and the code only touches the slots it is handed.
+unsafe impl Send for Code {}
+unsafe impl Sync for Code {}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        // SAFETY: the code is only run while the prototype it belongs to is
+        // kept, so nothing is running it by now.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() }
+    }
+}
+
+// The values compiled code works with, which are ints in registers except
+// for floats. Bools are 0 or 1, and null is 0. Anything else is opaque,
+// which compiled code can only move around.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Ty {
+    Int,
+    Float,
+    Bool,
+    Null,
+    Opaque,
+}
+
+impl Ty {
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Int(_) => Ty::Int,
+            Value::Float(_) => Ty::Float,
+            Value::Bool(_) => Ty::Bool,
+            Value::Null => Ty::Null,
+            _ => Ty::Opaque,
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, Ty::Int | Ty::Float)
+    }
+
+    fn bits(value: &Value) -> u64 {
+        match value {
+            Value::Int(n) => *n as u64,
+            Value::Float(n) => n.to_bits(),
+            Value::Bool(b) => *b as u64,
+            _ => 0,
+        }
+    }
+
+    fn value(self, bits: u64) -> Value {
+        match self {
+            Ty::Int => Value::Int(bits as i64),
+            Ty::Float => Value::Float(f64::from_bits(bits)),
+            Ty::Bool => Value::Bool(bits != 0),
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Vm {
+    // Compiles functions once they have been called this many times, or
+    // never.
+    pub fn set_jit_threshold(&mut self, calls: Option<u32>) {
+        self.jit.threshold = calls;
+    }
+
+    pub fn jit_threshold(&self) -> Option<u32> {
+        self.jit.threshold
+    }
+
+    pub fn jit_stats(&self) -> JitStats {
+        self.jit.stats
+    }
+
+    // Runs the call of the function in the slot, whose arguments are on top
+    // of the stack, in native code, compiling the function if this is the
+    // call that makes it hot. The result takes the place of the call, the
+    // same as for a native. Gives back whether it ran, and if it didn't the
+    // interpreter is left to make the call.
+    pub(super) fn run_compiled(&mut self, proto: &Prototype, base: usize) -> bool {
+        let threshold = match self.jit.threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        if self.fuel.is_some()
+            || self.debugger.is_active()
+            || self.profiler.is_some()
+            || self.trace.is_some()
+        {
+            return false;
+        }
+        let tier = &proto.tier;
+        let compiled = match tier.compiled.get() {
+            Some(Some(compiled)) => compiled,
+            Some(None) => return false,
+            None => {
+                if tier.calls.fetch_add(1, Ordering::Relaxed) + 1 < threshold {
+                    return false;
+                }
+                let compiled = compile(&proto.function.chunk, &self.stack[base + 1..]);
+                match compiled {
+                    Some(_) => self.jit.stats.compiled += 1,
+                    None => self.jit.stats.rejected += 1,
+                }
+                match tier.compiled.get_or_init(|| compiled) {
+                    Some(compiled) => compiled,
+                    None => return false,
+                }
+            }
+        };
+        let args = &self.stack[base + 1..];
+        if args.len() != compiled.params.len() {
+            return false;
+        }
+        let mut slots = [0; MAX_PARAMS + 1];
+        for (i, (arg, &ty)) in args.iter().zip(&compiled.params).enumerate() {
+            if Ty::of(arg) != ty {
+                return false;
+            }
+            slots[i + 1] = Ty::bits(arg);
+        }
+        let interrupted = self.interrupted.as_ptr() as *const u8;
+        // SAFETY: the code was compiled for arguments of these types, and
+        // only reads the slots and whether the script was interrupted.
+        let status = unsafe { (compiled.code.entry)(slots.as_mut_ptr(), interrupted) };
+        if status != DONE {
+            self.jit.stats.bailouts += 1;
+            return false;
+        }
+        self.stack.truncate(base);
+        self.stack.push(compiled.returns.value(slots[0]));
+        true
+    }
+}
+
+fn compile(chunk: &Chunk, args: &[Value]) -> Option<Compiled> {
+    let params: Vec<Ty> = args.iter().map(Ty::of).collect();
+    if params.len() > MAX_PARAMS || params.contains(&Ty::Opaque) {
+        return None;
+    }
+    let analysis = analyze(chunk, &params)?;
+    let code = generate(chunk, &analysis).ok()?;
+    Some(Compiled {
+        params,
+        returns: analysis.returns,
+        code,
+    })
+}
+
+// The types on the stack before every instruction the function can get to,
+// counted from the slot of the function, which is opaque. The types have
+// to be the same however the function gets to an instruction.
+struct Analysis {
+    stacks: BTreeMap<usize, Vec<Ty>>,
+    returns: Ty,
+    // The most slots the function uses at once.
+    depth: usize,
+}
+
+fn analyze(chunk: &Chunk, params: &[Ty]) -> Option<Analysis> {
+    let mut stack = vec![Ty::Opaque];
+    stack.extend_from_slice(params);
+    let mut stacks = BTreeMap::new();
+    let mut returns = None;
+    let mut depth = stack.len();
+    let mut work = vec![(0, stack)];
+    while let Some((offset, mut stack)) = work.pop() {
+        match stacks.get(&offset) {
+            Some(seen) if *seen == stack => continue,
+            Some(_) => return None,
+            None => {
+                stacks.insert(offset, stack.clone());
+            }
+        }
+        let op = chunk.op_at(offset)?;
+        let operand = chunk.operand(offset);
+        let next = chunk.next_offset(offset);
+        let mut to = [Some(next), None];
+        match op {
+            OpCode::Constant => match chunk.constants().get(operand)? {
+                Constant::Int(_) => stack.push(Ty::Int),
+                Constant::Float(_) => stack.push(Ty::Float),
+                _ => return None,
+            },
+            OpCode::Null => stack.push(Ty::Null),
+            OpCode::True | OpCode::False => stack.push(Ty::Bool),
+            OpCode::Pop => {
+                stack.pop()?;
+            }
+            OpCode::Dup => {
+                let top = *stack.last()?;
+                stack.push(top);
+            }
+            OpCode::Dup2 => {
+                let len = stack.len().checked_sub(2)?;
+                stack.extend_from_within(len..);
+            }
+            OpCode::GetLocal => match *stack.get(operand)? {
+                Ty::Opaque => return None,
+                ty => stack.push(ty),
+            },
+            OpCode::SetLocal => {
+                let top = *stack.last()?;
+                *stack.get_mut(operand)? = top;
+            }
+            OpCode::Negate => match stack.last()? {
+                ty if ty.is_number() => {}
+                _ => return None,
+            },
+            OpCode::BitNot => match stack.last()? {
+                Ty::Int => {}
+                _ => return None,
+            },
+            OpCode::Not => match stack.pop()? {
+                Ty::Opaque => return None,
+                _ => stack.push(Ty::Bool),
+            },
+            OpCode::Jump => to[0] = Some(next + operand),
+            OpCode::JumpIfFalse => match stack.last()? {
+                Ty::Opaque => return None,
+                _ => to[1] = Some(next + operand),
+            },
+            OpCode::Loop => to[0] = Some(next.checked_sub(operand)?),
+            OpCode::Return => {
+                let ty = stack.pop()?;
+                match returns {
+                    _ if ty == Ty::Opaque => return None,
+                    Some(returned) if returned != ty => return None,
+                    _ => returns = Some(ty),
+                }
+                to[0] = None;
+            }
+            op => {
+                let right = stack.pop()?;
+                let left = stack.pop()?;
+                stack.push(binary_type(op, left, right)?);
+            }
+        }
+        depth = depth.max(stack.len());
+        for &to in to.iter().flatten() {
+            work.push((to, stack.clone()));
+        }
+    }
+    Some(Analysis {
+        stacks,
+        returns: returns?,
+        depth,
+    })
+}
+
+// What a binary operator gives for the types, if compiled code can do it.
+// Ints and floats are equal when the float is whole and the same as the
+// int, which is left to the interpreter, and so is the remainder of floats.
+fn binary_type(op: OpCode, left: Ty, right: Ty) -> Option<Ty> {
+    let ints = left == Ty::Int && right == Ty::Int;
+    let numbers = left.is_number() && right.is_number();
+    match op {
+        OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide if ints => Some(Ty::Int),
+        OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide if numbers => {
+            Some(Ty::Float)
+        }
+        OpCode::Modulo | OpCode::BitAnd | OpCode::BitOr | OpCode::BitXor if ints => Some(Ty::Int),
+        OpCode::Less | OpCode::LessEqual | OpCode::Greater | OpCode::GreaterEqual if numbers => {
+            Some(Ty::Bool)
+        }
+        OpCode::Equal | OpCode::NotEqual if numbers && left != right => None,
+        OpCode::Equal | OpCode::NotEqual if left != Ty::Opaque && right != Ty::Opaque => {
+            Some(Ty::Bool)
+        }
+        _ => None,
+    }
+}
+
+fn generate(chunk: &Chunk, analysis: &Analysis) -> Result<Code, String> {
+    let builder = JITBuilder::with_flags(&[("opt_level", "speed")], default_libcall_names())
+        .map_err(|error| error.to_string())?;
+    let mut module = JITModule::new(builder);
+    match define(&mut module, chunk, analysis) {
+        Ok(entry) => Ok(Code {
+            module: ManuallyDrop::new(module),
+            entry,
+        }),
+        Err(error) => {
+            // SAFETY: none of the code of the module was handed out.
+            unsafe { module.free_memory() };
+            Err(error)
+        }
+    }
+}
+
+fn define(module: &mut JITModule, chunk: &Chunk, analysis: &Analysis) -> Result<Entry, String> {
+    let mut context = module.make_context();
+    let pointer = module.target_config().pointer_type();
+    let signature = &mut context.func.signature;
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(types::I64));
+    let mut builder_context = FunctionBuilderContext::new();
+    let builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+    Translator::new(builder, chunk, analysis).translate();
+    let id = module
+        .declare_anonymous_function(&context.func.signature)
+        .map_err(|error| error.to_string())?;
+    module
+        .define_function(id, &mut context)
+        .map_err(|error| error.to_string())?;
+    module
+        .finalize_definitions()
+        .map_err(|error| error.to_string())?;
+    let code = module.get_finalized_function(id);
+    // SAFETY: the function was declared with the signature of an entry.
+    Ok(unsafe { std::mem::transmute::<*const u8, Entry>(code) })
+}
+
+// Turns the instructions of a function into Cranelift IR. Every slot is a
+// variable for ints and one for floats, which is whichever the type in the
+// slot says.
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    chunk: &'a Chunk,
+    analysis: &'a Analysis,
+    // The instructions that can be got to other than from the one before,
+    // which start blocks.
+    blocks: BTreeMap<usize, Block>,
+    bail: Block,
+    slots: ir::Value,
+    interrupted: ir::Value,
+}
+
+impl<'a> Translator<'a> {
+    fn new(mut builder: FunctionBuilder<'a>, chunk: &'a Chunk, analysis: &'a Analysis) -> Self {
+        let mut targets = HashSet::new();
+        targets.insert(0);
+        for &offset in analysis.stacks.keys() {
+            let next = chunk.next_offset(offset);
+            let operand = chunk.operand(offset);
+            match chunk.op_at(offset) {
+                Some(OpCode::Jump) => {
+                    targets.insert(next + operand);
+                }
+                Some(OpCode::JumpIfFalse) => {
+                    targets.insert(next);
+                    targets.insert(next + operand);
+                }
+                Some(OpCode::Loop) => {
+                    targets.insert(next - operand);
+                }
+                _ => {}
+            }
+        }
+        let blocks = targets
+            .into_iter()
+            .map(|offset| (offset, builder.create_block()))
+            .collect();
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let params = builder.block_params(entry);
+        let (slots, interrupted) = (params[0], params[1]);
+        for slot in 0..analysis.depth {
+            builder.declare_var(Self::variable(slot, Ty::Int), types::I64);
+            builder.declare_var(Self::variable(slot, Ty::Float), types::F64);
+        }
+        let bail = builder.create_block();
+        Self {
+            builder,
+            chunk,
+            analysis,
+            blocks,
+            bail,
+            slots,
+            interrupted,
+        }
+    }
+
+    fn variable(slot: usize, ty: Ty) -> Variable {
+        Variable::from_u32((slot * 2 + (ty == Ty::Float) as usize) as u32)
+    }
+
+    fn translate(mut self) {
+        let params = &self.analysis.stacks[&0];
+        for (slot, &ty) in params.iter().enumerate().skip(1) {
+            let mem = if ty == Ty::Float {
+                types::F64
+            } else {
+                types::I64
+            };
+            let offset = (slot * 8) as i32;
+            let value = self
+                .builder
+                .ins()
+                .load(mem, MemFlags::trusted(), self.slots, offset);
+            self.set(slot, ty, value);
+        }
+        self.builder.ins().jump(self.blocks[&0], &[]);
+        let mut ended = true;
+        let analysis = self.analysis;
+        for (&offset, stack) in &analysis.stacks {
+            if let Some(&block) = self.blocks.get(&offset) {
+                if !ended {
+                    self.builder.ins().jump(block, &[]);
+                }
+                self.builder.switch_to_block(block);
+            }
+            ended = self.instruction(offset, stack);
+        }
+        self.builder.switch_to_block(self.bail);
+        let bailed = self.builder.ins().iconst(types::I64, BAILED);
+        self.builder.ins().return_(&[bailed]);
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    fn get(&mut self, slot: usize, ty: Ty) -> ir::Value {
+        self.builder.use_var(Self::variable(slot, ty))
+    }
+
+    fn set(&mut self, slot: usize, ty: Ty, value: ir::Value) {
+        self.builder.def_var(Self::variable(slot, ty), value);
+    }
+
+    fn copy(&mut self, from: usize, to: usize, ty: Ty) {
+        let value = self.get(from, ty);
+        self.set(to, ty, value);
+    }
+
+    // The number in the slot as a float.
+    fn float(&mut self, slot: usize, ty: Ty) -> ir::Value {
+        let value = self.get(slot, ty);
+        match ty {
+            Ty::Int => self.builder.ins().fcvt_from_sint(types::F64, value),
+            _ => value,
+        }
+    }
+
+    fn int(&mut self, n: i64) -> ir::Value {
+        self.builder.ins().iconst(types::I64, n)
+    }
+
+    fn bool(&mut self, condition: ir::Value) -> ir::Value {
+        self.builder.ins().uextend(types::I64, condition)
+    }
+
+    // Goes back to the interpreter if the condition holds.
+    fn bail_if(&mut self, condition: ir::Value) {
+        let next = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, self.bail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    // Translates the instruction, giving back whether it ends its block.
+    fn instruction(&mut self, offset: usize, stack: &[Ty]) -> bool {
+        let chunk = self.chunk;
+        let op = chunk.op_at(offset).expect("the instruction was analyzed");
+        let operand = chunk.operand(offset);
+        let next = chunk.next_offset(offset);
+        let top = stack.len();
+        match op {
+            OpCode::Constant => match &chunk.constants()[operand] {
+                Constant::Int(n) => {
+                    let value = self.int(*n);
+                    self.set(top, Ty::Int, value);
+                }
+                Constant::Float(n) => {
+                    let value = self.builder.ins().f64const(*n);
+                    self.set(top, Ty::Float, value);
+                }
+                _ => unreachable!("only numbers are compiled"),
+            },
+            OpCode::Null | OpCode::False => {
+                let value = self.int(0);
+                self.set(top, Ty::Int, value);
+            }
+            OpCode::True => {
+                let value = self.int(1);
+                self.set(top, Ty::Int, value);
+            }
+            OpCode::Pop => {}
+            OpCode::Dup => self.copy(top - 1, top, stack[top - 1]),
+            OpCode::Dup2 => {
+                self.copy(top - 2, top, stack[top - 2]);
+                self.copy(top - 1, top + 1, stack[top - 1]);
+            }
+            OpCode::GetLocal => self.copy(operand, top, stack[operand]),
+            OpCode::SetLocal => self.copy(top - 1, operand, stack[top - 1]),
+            OpCode::Negate => {
+                let ty = stack[top - 1];
+                let value = self.get(top - 1, ty);
+                let negated = if ty == Ty::Int {
+                    let overflows = self.builder.ins().icmp_imm(IntCC::Equal, value, i64::MIN);
+                    self.bail_if(overflows);
+                    self.builder.ins().ineg(value)
+                } else {
+                    self.builder.ins().fneg(value)
+                };
+                self.set(top - 1, ty, negated);
+            }
+            OpCode::BitNot => {
+                let value = self.get(top - 1, Ty::Int);
+                let flipped = self.builder.ins().bnot(value);
+                self.set(top - 1, Ty::Int, flipped);
+            }
+            OpCode::Not => {
+                let value = match stack[top - 1] {
+                    Ty::Bool => {
+                        let value = self.get(top - 1, Ty::Bool);
+                        self.builder.ins().bxor_imm(value, 1)
+                    }
+                    Ty::Null => self.int(1),
+                    _ => self.int(0),
+                };
+                self.set(top - 1, Ty::Bool, value);
+            }
+            OpCode::Jump => {
+                let block = self.blocks[&(next + operand)];
+                self.builder.ins().jump(block, &[]);
+                return true;
+            }
+            OpCode::JumpIfFalse => {
+                let truthy = match stack[top - 1] {
+                    Ty::Bool => self.get(top - 1, Ty::Bool),
+                    Ty::Null => self.int(0),
+                    _ => self.int(1),
+                };
+                let (then, otherwise) = (self.blocks[&next], self.blocks[&(next + operand)]);
+                self.builder.ins().brif(truthy, then, &[], otherwise, &[]);
+                return true;
+            }
+            OpCode::Loop => {
+                let interrupted =
+                    self.builder
+                        .ins()
+                        .load(types::I8, MemFlags::trusted(), self.interrupted, 0);
+                let block = self.blocks[&(next - operand)];
+                self.builder
+                    .ins()
+                    .brif(interrupted, self.bail, &[], block, &[]);
+                return true;
+            }
+            OpCode::Return => {
+                let ty = stack[top - 1];
+                let value = self.get(top - 1, ty);
+                self.builder
+                    .ins()
+                    .store(MemFlags::trusted(), value, self.slots, 0);
+                let done = self.int(DONE);
+                self.builder.ins().return_(&[done]);
+                return true;
+            }
+            op => self.binary(op, stack),
+        }
+        false
+    }
+
+    fn binary(&mut self, op: OpCode, stack: &[Ty]) {
+        let (left, right) = (stack.len() - 2, stack.len() - 1);
+        let (left_ty, right_ty) = (stack[left], stack[right]);
+        let ty = binary_type(op, left_ty, right_ty).expect("the operator was analyzed");
+        let value = if left_ty == Ty::Int && right_ty == Ty::Int {
+            let a = self.get(left, Ty::Int);
+            let b = self.get(right, Ty::Int);
+            self.ints(op, a, b)
+        } else if left_ty.is_number() && right_ty.is_number() {
+            let a = self.float(left, left_ty);
+            let b = self.float(right, right_ty);
+            self.floats(op, a, b)
+        } else {
+            // Other values are only ever compared, and only bools can be
+            // told apart.
+            let equal = match (left_ty, right_ty) {
+                (Ty::Bool, Ty::Bool) => {
+                    let a = self.get(left, Ty::Bool);
+                    let b = self.get(right, Ty::Bool);
+                    let equal = self.builder.ins().icmp(IntCC::Equal, a, b);
+                    self.bool(equal)
+                }
+                (Ty::Null, Ty::Null) => self.int(1),
+                _ => self.int(0),
+            };
+            match op {
+                OpCode::Equal => equal,
+                _ => self.builder.ins().bxor_imm(equal, 1),
+            }
+        };
+        self.set(left, ty, value);
+    }
+
+    // Ints that overflow, and dividing by zero, are left to the interpreter.
+    fn ints(&mut self, op: OpCode, a: ir::Value, b: ir::Value) -> ir::Value {
+        let cc = match op {
+            OpCode::Add => {
+                let (value, overflowed) = self.builder.ins().sadd_overflow(a, b);
+                self.bail_if(overflowed);
+                return value;
+            }
+            OpCode::Subtract => {
+                let (value, overflowed) = self.builder.ins().ssub_overflow(a, b);
+                self.bail_if(overflowed);
+                return value;
+            }
+            OpCode::Multiply => {
+                let (value, overflowed) = self.builder.ins().smul_overflow(a, b);
+                self.bail_if(overflowed);
+                return value;
+            }
+            OpCode::Divide => {
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+                self.bail_if(zero);
+                let smallest = self.builder.ins().icmp_imm(IntCC::Equal, a, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                let overflows = self.builder.ins().band(smallest, minus_one);
+                self.bail_if(overflows);
+                return self.builder.ins().sdiv(a, b);
+            }
+            OpCode::Modulo => {
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+                self.bail_if(zero);
+                // The remainder of anything over -1 is 0, which the machine
+                // would trap on for the smallest int.
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                let one = self.int(1);
+                let b = self.builder.ins().select(minus_one, one, b);
+                return self.builder.ins().srem(a, b);
+            }
+            OpCode::BitAnd => return self.builder.ins().band(a, b),
+            OpCode::BitOr => return self.builder.ins().bor(a, b),
+            OpCode::BitXor => return self.builder.ins().bxor(a, b),
+            OpCode::Equal => IntCC::Equal,
+            OpCode::NotEqual => IntCC::NotEqual,
+            OpCode::Less => IntCC::SignedLessThan,
+            OpCode::LessEqual => IntCC::SignedLessThanOrEqual,
+            OpCode::Greater => IntCC::SignedGreaterThan,
+            OpCode::GreaterEqual => IntCC::SignedGreaterThanOrEqual,
+            _ => unreachable!("the operator was analyzed"),
+        };
+        let compared = self.builder.ins().icmp(cc, a, b);
+        self.bool(compared)
+    }
+
+    fn floats(&mut self, op: OpCode, a: ir::Value, b: ir::Value) -> ir::Value {
+        let cc = match op {
+            OpCode::Add => return self.builder.ins().fadd(a, b),
+            OpCode::Subtract => return self.builder.ins().fsub(a, b),
+            OpCode::Multiply => return self.builder.ins().fmul(a, b),
+            OpCode::Divide => {
+                let zero = self.builder.ins().f64const(0.0);
+                let by_zero = self.builder.ins().fcmp(FloatCC::Equal, b, zero);
+                self.bail_if(by_zero);
+                return self.builder.ins().fdiv(a, b);
+            }
+            OpCode::Equal => FloatCC::Equal,
+            OpCode::NotEqual => FloatCC::NotEqual,
+            OpCode::Less => FloatCC::LessThan,
+            OpCode::LessEqual => FloatCC::LessThanOrEqual,
+            OpCode::Greater => FloatCC::GreaterThan,
+            OpCode::GreaterEqual => FloatCC::GreaterThanOrEqual,
+            _ => unreachable!("the operator was analyzed"),
+        };
+        let compared = self.builder.ins().fcmp(cc, a, b);
+        self.bool(compared)
+    }
+}
@@ -0,0 +1,112 @@
+use super::{Returned, Vm, MAX_FRAMES};
+use crate::bytecode::Function;
+use crate::compile::CompilerOptions;
+use crate::link::{compile_module, Bundle};
+use crate::parse::parse_program;
+use crate::value::Value;
+use std::collections::HashMap;
+
+// An import runs the module with the path in its operand the first time
+// anything imports it, and every import after that gets the same exports.
+// Paths are worked out by the compiler the same way for every importer, so
+// they are what modules are known by. A module is marked as being imported
+// while it runs, so importing it again before it is done is a cycle.
+
+// Finds the module with the path and compiles it, giving back why it
+// couldn't otherwise.
+pub trait ModuleLoader: Send {
+    fn load(&mut self, path: &str) -> Result<Function, String>;
+}
+
+// Loads modules from the file system, or from sources the host provides,
+// which come first.
+#[derive(Default)]
+pub struct FileLoader {
+    options: CompilerOptions,
+    sources: HashMap<String, String>,
+}
+
+impl FileLoader {
+    pub fn new(options: CompilerOptions) -> Self {
+        Self {
+            options,
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn provide(&mut self, path: &str, source: &str) {
+        self.sources
+            .insert(String::from(path), String::from(source));
+    }
+}
+
+impl ModuleLoader for FileLoader {
+    fn load(&mut self, path: &str) -> Result<Function, String> {
+        let source = match self.sources.get(path) {
+            Some(source) => source.clone(),
+            None => {
+                std::fs::read_to_string(path).map_err(|_| format!("cannot find module {}", path))?
+            }
+        };
+        let module = parse_program(path, &source[..])
+            .and_then(|program| compile_module(&program, &self.options));
+        match module {
+            Ok(module) => Ok(module.function),
+            Err(errors) => Err(format!("cannot compile module {}: {}", path, errors[0])),
+        }
+    }
+}
+
+// A bundle already has every module its entry point imports.
+impl ModuleLoader for Bundle {
+    fn load(&mut self, path: &str) -> Result<Function, String> {
+        match self.module(path) {
+            Some(module) => Ok(module.clone()),
+            None => Err(format!("cannot find module {}", path)),
+        }
+    }
+}
+
+impl Vm {
+    // Without a loader, scripts can only import the modules that were
+    // imported before.
+    pub fn set_module_loader(&mut self, loader: Option<Box<dyn ModuleLoader>>) {
+        self.loader = loader;
+    }
+
+    // The exports of a module that was imported.
+    pub fn module(&self, path: &str) -> Option<&Value> {
+        self.modules.get(path)
+    }
+
+    // Forgets the modules that were imported, so they run again the next
+    // time they are.
+    pub fn clear_modules(&mut self) {
+        self.modules.clear();
+    }
+
+    // Loads the module and calls it, leaving its exports in its place once
+    // it returns.
+    pub(super) fn import(&mut self, path: &str) -> Result<(), String> {
+        if let Some(start) = self.importing.iter().position(|(p, _)| p == path) {
+            let mut cycle: Vec<&str> = self.importing[start..]
+                .iter()
+                .map(|(p, _)| &p[..])
+                .collect();
+            cycle.push(path);
+            return Err(format!("import cycle: {}", cycle.join(" -> ")));
+        }
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(String::from("stack overflow"));
+        }
+        let function = match &mut self.loader {
+            Some(loader) => loader.load(path)?,
+            None => return Err(format!("cannot find module {}", path)),
+        };
+        let closure = self.script_closure(&function);
+        self.importing.push((String::from(path), self.frames.len()));
+        self.stack.push(Value::Object(closure));
+        self.push_frame(closure, self.stack.len() - 1, Returned::Imported);
+        Ok(())
+    }
+}
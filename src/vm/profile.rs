@@ -0,0 +1,238 @@
+use super::{Frame, Prototype, Vm};
+#[cfg(feature = "opcode-counts")]
+use crate::bytecode::OpCode;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// The profiler looks at the frames before every instruction. A frame it
+// hasn't seen yet is a call, and one that is gone has returned, however it
+// went, so returns, tail calls, throws and coroutines all count the same.
+// Resuming a coroutine calls the functions it is in again.
+//
+// A function's exclusive time is the time spent running its own
+// instructions, and its inclusive time also counts the functions it calls.
+// Natives count as part of the function that called them. A function that
+// calls itself only counts the time of the outermost call as inclusive, so
+// no time is counted twice.
+
+// What was found out about one function, which is known by where it is.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub file: String,
+    pub line: u32,
+    pub calls: u64,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Profile {
+    // The functions that ran, the most exclusive time first.
+    pub functions: Vec<FunctionProfile>,
+    // How many times every instruction ran, the most first.
+    #[cfg(feature = "opcode-counts")]
+    pub opcodes: Vec<(OpCode, u64)>,
+}
+
+impl Profile {
+    pub fn function(&self, name: &str) -> Option<&FunctionProfile> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    // A table of the functions, for people to read.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<32} {:>10} {:>14} {:>14}",
+            "function", "calls", "inclusive", "exclusive"
+        );
+        for f in &self.functions {
+            let place = format!("{} ({}:{})", f.name, f.file, f.line);
+            let _ = writeln!(
+                out,
+                "{:<32} {:>10} {:>14?} {:>14?}",
+                place, f.calls, f.inclusive, f.exclusive
+            );
+        }
+        #[cfg(feature = "opcode-counts")]
+        {
+            let _ = writeln!(out, "\n{:<32} {:>10}", "instruction", "count");
+            for (op, count) in &self.opcodes {
+                let _ = writeln!(out, "{:<32} {:>10}", op.name(), count);
+            }
+        }
+        out
+    }
+}
+
+// A frame the profiler has seen, known by its function and where its slots
+// start.
+struct Entry {
+    proto: Arc<Prototype>,
+    base: usize,
+    function: usize,
+    called: Instant,
+}
+
+#[derive(Default)]
+pub(super) struct Profiler {
+    functions: Vec<FunctionProfile>,
+    indices: HashMap<(Arc<str>, String, u32), usize>,
+    // How many frames every function has, to tell the outermost call.
+    active: Vec<usize>,
+    entries: Vec<Entry>,
+    // When the last instruction started, or nothing when the script isn't
+    // running.
+    last: Option<Instant>,
+    // Whether the frame on top was replaced by a tail call, which can be
+    // to the same function with the same slots.
+    tail: bool,
+    #[cfg(feature = "opcode-counts")]
+    opcodes: HashMap<OpCode, u64>,
+}
+
+impl Profiler {
+    // Counts the time since the last instruction for the frame that ran
+    // it, then the calls and returns it made.
+    pub(super) fn tick(&mut self, frames: &[Frame]) {
+        let now = Instant::now();
+        if let (Some(last), Some(entry)) = (self.last, self.entries.last()) {
+            self.functions[entry.function].exclusive += now - last;
+        }
+        self.last = Some(now);
+        self.sync(frames, now);
+    }
+
+    pub(super) fn tail_call(&mut self) {
+        self.tail = true;
+    }
+
+    #[cfg(feature = "opcode-counts")]
+    pub(super) fn count(&mut self, op: OpCode) {
+        *self.opcodes.entry(op).or_insert(0) += 1;
+    }
+
+    // Stops the clock when the script stops running, so the time until it
+    // runs again isn't counted.
+    pub(super) fn stop(&mut self, frames: &[Frame]) {
+        self.tick(frames);
+        self.last = None;
+    }
+
+    fn sync(&mut self, frames: &[Frame], now: Instant) {
+        let tail = std::mem::take(&mut self.tail);
+        let top = match (self.entries.last(), frames.last()) {
+            (Some(entry), Some(frame)) => self.entries.len() == frames.len() && is(entry, frame),
+            (None, None) => true,
+            _ => false,
+        };
+        if top && !tail {
+            return;
+        }
+        let mut kept = self
+            .entries
+            .iter()
+            .zip(frames)
+            .take_while(|(entry, frame)| is(entry, frame))
+            .count();
+        if tail {
+            kept = kept.min(frames.len() - 1);
+        }
+        while self.entries.len() > kept {
+            let entry = self
+                .entries
+                .pop()
+                .expect("there are more entries than kept");
+            self.active[entry.function] -= 1;
+            if self.active[entry.function] == 0 {
+                self.functions[entry.function].inclusive += now - entry.called;
+            }
+        }
+        for frame in &frames[kept..] {
+            let function = self.function(&frame.proto);
+            self.functions[function].calls += 1;
+            self.active[function] += 1;
+            self.entries.push(Entry {
+                proto: frame.proto.clone(),
+                base: frame.base,
+                function,
+                called: now,
+            });
+        }
+    }
+
+    fn function(&mut self, proto: &Prototype) -> usize {
+        let function = &proto.function;
+        let line = function.chunk.line(0);
+        let key = (proto.file.clone(), function.name.clone(), line);
+        let functions = &mut self.functions;
+        let active = &mut self.active;
+        *self.indices.entry(key).or_insert_with(|| {
+            functions.push(FunctionProfile {
+                name: function.name.clone(),
+                file: String::from(&proto.file[..]),
+                line,
+                calls: 0,
+                inclusive: Duration::default(),
+                exclusive: Duration::default(),
+            });
+            active.push(0);
+            functions.len() - 1
+        })
+    }
+
+    // What was found out so far, counting the functions still running up
+    // to now.
+    fn profile(&self) -> Profile {
+        let mut functions = self.functions.clone();
+        let now = Instant::now();
+        let mut counted = vec![false; functions.len()];
+        for entry in &self.entries {
+            if !counted[entry.function] {
+                counted[entry.function] = true;
+                functions[entry.function].inclusive += now - entry.called;
+            }
+        }
+        functions.sort_by_key(|f| Reverse(f.exclusive));
+        #[cfg(feature = "opcode-counts")]
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(&op, &n)| (op, n)).collect();
+        #[cfg(feature = "opcode-counts")]
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.name().cmp(b.0.name())));
+        Profile {
+            functions,
+            #[cfg(feature = "opcode-counts")]
+            opcodes,
+        }
+    }
+}
+
+fn is(entry: &Entry, frame: &Frame) -> bool {
+    entry.base == frame.base && Arc::ptr_eq(&entry.proto, &frame.proto)
+}
+
+impl Vm {
+    // Starts profiling the scripts that run from now on, forgetting any
+    // earlier profile. Scripts run slower while they are profiled, and
+    // nothing is checked for the profiler while they aren't.
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Box::default());
+    }
+
+    // Stops profiling, giving back what was found out, or nothing if the
+    // VM wasn't profiling.
+    pub fn stop_profiling(&mut self) -> Option<Profile> {
+        let profile = self.profile();
+        self.profiler = None;
+        profile
+    }
+
+    // What was found out so far, without stopping.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profiler.as_ref().map(|profiler| profiler.profile())
+    }
+}
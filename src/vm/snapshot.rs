@@ -0,0 +1,1151 @@
+use super::host::HostFunction;
+use super::{Closure, Coroutine, CoroutineState, Frame, Globals, Handler, Prototype, Returned};
+use super::{Upvalue, Vm};
+use crate::builtins::{self, Native};
+use crate::bytecode::serialize::{deserialize_functions, serialize_functions};
+use crate::bytecode::Function;
+use crate::parse::INITIALIZER_NAME;
+#[cfg(feature = "bigint")]
+use crate::value::bigint::BigInt;
+use crate::value::foreign::Foreign;
+use crate::value::gc::Weak;
+use crate::value::symbol::Symbol;
+use crate::value::*;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+// A snapshot is everything scripts can reach in a virtual machine that
+// isn't running, saved as bytes so that it can be restored later, into the
+// same VM or another one: the objects, the globals found by name, the
+// globals of the script that ran last, the exports of every module and the
+// functions the host has handles to. Coroutines are saved with the frames
+// they stopped in and pick up from there once they are resumed, which is
+// what a game saves in the middle of a cutscene needs.
+//
+// Every object goes back in the slot it was in, so the handles the host
+// kept to what was saved are still good after a restore. Anything else it
+// kept is gone along with the heap it was in.
+//
+// Foreign values are saved and loaded by callbacks of the host, found by
+// the name of their type. Functions of the host and natives aren't saved at
+// all, only their names, and are found again among the globals of the VM
+// that restores them, which is why a host defines its functions before it
+// restores a snapshot.
+//
+// A snapshot starts with a magic number and the version of its format,
+// then the functions, the objects and the VM:
+//
+//     functions    u32 length, then the functions of the closures, stored
+//                  the way compiled scripts are, the file of each as a
+//                  string and a u32 count of prototypes, each the index of
+//                  the function it is in and a u32 count of the constants
+//                  it is found through, then each index
+//     empty slots  u32 count, then the slot of every one below the last
+//                  object as a u32
+//     objects      u32 count, then for each its slot as a u32, a tag byte
+//                  and what it holds
+//     globals      u32 count, then the name and value of every global
+//     script       a byte that is 1 when there is one, then its slot
+//     modules      u32 count, then the path and exports of every module
+//     handles      u32 count, then the slot of every function and how many
+//                  handles to it the host hasn't released, each a u32
+//
+// Foreign values come first, so that the natives among their methods can
+// be found when something refers to one, and closures come next, so that
+// the frames of coroutines know what they are running. A value is a tag
+// byte followed by an i64 for an int, the bits of a float, a string or the
+// slot of an object, and the digits of a bigint.
+//
+// Listing the empty slots means a snapshot is never much smaller than the
+// heap it restores, so a damaged one can't ask for a heap of any size.
+pub const MAGIC: [u8; 4] = *b"ATS\0";
+pub const VERSION: u16 = 3;
+
+const NULL_TAG: u8 = 0;
+const FALSE_TAG: u8 = 1;
+const TRUE_TAG: u8 = 2;
+const INT_TAG: u8 = 3;
+const FLOAT_TAG: u8 = 4;
+const STRING_TAG: u8 = 5;
+const OBJECT_TAG: u8 = 6;
+#[cfg(feature = "bigint")]
+const BIGINT_TAG: u8 = 7;
+
+const LIST_TAG: u8 = 0;
+const MAP_TAG: u8 = 1;
+const RANGE_TAG: u8 = 2;
+const NATIVE_TAG: u8 = 3;
+const HOST_TAG: u8 = 4;
+const CLOSURE_TAG: u8 = 5;
+const UPVALUE_TAG: u8 = 6;
+const GLOBALS_TAG: u8 = 7;
+const CLASS_TAG: u8 = 8;
+const INSTANCE_TAG: u8 = 9;
+const BOUND_METHOD_TAG: u8 = 10;
+const ITERATOR_TAG: u8 = 11;
+const COROUTINE_TAG: u8 = 12;
+const FOREIGN_TAG: u8 = 13;
+const WEAK_TAG: u8 = 14;
+
+const INVALID: &str = "invalid snapshot";
+
+type SaveFn = dyn Fn(&Foreign) -> Result<Vec<u8>, String> + Send + Sync;
+type LoadFn = dyn Fn(&[u8]) -> Result<Foreign, String> + Send + Sync;
+
+// How the host saves and loads the foreign values of each type, by the name
+// of the type. Loading has to give back a value of the same type, with the
+// methods and the finalizer it had.
+#[derive(Default)]
+pub struct ForeignTypes {
+    types: HashMap<String, (Box<SaveFn>, Box<LoadFn>)>,
+}
+
+impl ForeignTypes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<S, L>(&mut self, type_name: &str, save: S, load: L)
+    where
+        S: Fn(&Foreign) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+        L: Fn(&[u8]) -> Result<Foreign, String> + Send + Sync + 'static,
+    {
+        self.types
+            .insert(String::from(type_name), (Box::new(save), Box::new(load)));
+    }
+}
+
+impl Vm {
+    // Saves what scripts can reach, failing if a script is running or a
+    // foreign value can't be saved.
+    pub fn snapshot(&self, types: &ForeignTypes) -> Result<Vec<u8>, String> {
+        self.check_idle("snapshot")?;
+        let saved = self.reachable();
+        let protos = Protos::collect(&self.heap, &saved);
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(&MAGIC);
+        writer.bytes.extend_from_slice(&VERSION.to_be_bytes());
+        protos.write(&mut writer);
+        let (foreign, rest): (Vec<Handle>, Vec<Handle>) = saved
+            .iter()
+            .partition(|&&handle| matches!(self.heap.get(handle), Object::Foreign(_)));
+        let (closures, rest): (Vec<Handle>, Vec<Handle>) = rest
+            .into_iter()
+            .partition(|&handle| matches!(self.heap.get(handle), Object::Closure(_)));
+        let saved: HashSet<Handle> = saved.iter().copied().collect();
+        let slots = saved.iter().map(|handle| handle.index() + 1).max();
+        let empty: Vec<usize> = (0..slots.unwrap_or(0))
+            .filter(|&index| !saved.contains(&Handle::from_index(index as u32)))
+            .collect();
+        writer.u32(empty.len());
+        for index in empty {
+            writer.u32(index);
+        }
+        writer.u32(foreign.len() + closures.len() + rest.len());
+        for handle in foreign.into_iter().chain(closures).chain(rest) {
+            writer.handle(handle);
+            writer.object(self.heap.get(handle), &protos, &saved, types)?;
+        }
+        let mut globals: Vec<_> = self.globals.iter().collect();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        writer.u32(globals.len());
+        for (name, value) in globals {
+            writer.string(name);
+            writer.value(value);
+        }
+        match self.script {
+            Some(script) => {
+                writer.u8(1);
+                writer.handle(script);
+            }
+            None => writer.u8(0),
+        }
+        let mut modules: Vec<_> = self.modules.iter().collect();
+        modules.sort_by(|a, b| a.0.cmp(b.0));
+        writer.u32(modules.len());
+        for (path, exports) in modules {
+            writer.string(path);
+            writer.value(exports);
+        }
+        writer.u32(self.functions.len());
+        for &(function, handles) in &self.functions {
+            writer.handle(function);
+            writer.u32(handles);
+        }
+        Ok(writer.bytes)
+    }
+
+    // Replaces everything scripts can reach with what the snapshot saved.
+    // The functions of the host it refers to are the ones defined now, and
+    // any defined now that it doesn't have are kept as globals. The settings
+    // of the VM, like its limits, stay as they are. Nothing changes if the
+    // snapshot can't be restored.
+    pub fn restore(&mut self, bytes: &[u8], types: &ForeignTypes) -> Result<(), String> {
+        self.check_idle("restore")?;
+        let mut heap = Heap::new();
+        heap.set_gc_config(self.heap.gc_config());
+        let mut loader = Loader {
+            reader: Reader { bytes, offset: 0 },
+            vm: self,
+            types,
+            heap,
+            protos: Vec::new(),
+            objects: Vec::new(),
+            methods: Vec::new(),
+        };
+        if loader.reader.take(MAGIC.len())? != MAGIC {
+            return Err(String::from("not a snapshot of a virtual machine"));
+        }
+        let version = u16::from_be_bytes([loader.reader.u8()?, loader.reader.u8()?]);
+        if version != VERSION {
+            return Err(format!(
+                "saved with snapshot version {} but only version {} can be restored",
+                version, VERSION
+            ));
+        }
+        loader.protos()?;
+        let mut empty = Vec::new();
+        for _ in 0..loader.reader.count(4)? {
+            empty.push(loader.reader.u32()?);
+        }
+        let count = loader.reader.count(5)?;
+        let mut taken = vec![false; empty.len() + count];
+        for &index in &empty {
+            match taken.get_mut(index) {
+                Some(taken) if !*taken => *taken = true,
+                _ => return Err(String::from(INVALID)),
+            }
+        }
+        loader.objects.resize(taken.len(), None);
+        for _ in 0..count {
+            let index = loader.reader.u32()?;
+            let object = loader.object()?;
+            match taken.get_mut(index) {
+                Some(taken) if !*taken => *taken = true,
+                _ => return Err(String::from(INVALID)),
+            }
+            loader.objects[index] = Some(object);
+        }
+        let mut globals = HashMap::new();
+        for _ in 0..loader.reader.count(5)? {
+            let name = loader.reader.string()?;
+            globals.insert(Arc::from(name), loader.value()?);
+        }
+        let script = match loader.reader.u8()? {
+            0 => None,
+            1 => Some(loader.reader.handle()?),
+            _ => return Err(String::from(INVALID)),
+        };
+        let mut modules = HashMap::new();
+        for _ in 0..loader.reader.count(5)? {
+            let path = loader.reader.string()?;
+            modules.insert(path, loader.value()?);
+        }
+        let mut functions = Vec::new();
+        for _ in 0..loader.reader.count(8)? {
+            functions.push((loader.reader.handle()?, loader.reader.u32()?));
+        }
+        if loader.reader.offset != bytes.len() {
+            return Err(String::from("unexpected bytes after the snapshot"));
+        }
+        let roots = globals.values().chain(modules.values());
+        let mut listed = HashSet::new();
+        let valid = loader.check()
+            && roots
+                .filter_map(Value::as_object)
+                .all(|handle| loader.get(handle).is_some())
+            && script.is_none_or(|handle| matches!(loader.get(handle), Some(Object::Globals(_))))
+            && functions.iter().all(|&(handle, handles)| {
+                handles > 0
+                    && listed.insert(handle)
+                    && matches!(loader.get(handle), Some(Object::Closure(_)))
+            });
+        if !valid {
+            return Err(String::from(INVALID));
+        }
+        let Loader {
+            mut heap, objects, ..
+        } = loader;
+        heap.restore(objects);
+        heap.set_memory_limit(self.heap.memory_limit());
+        for (name, value) in &self.globals {
+            if globals.contains_key(name) {
+                continue;
+            }
+            if let Some(Object::Host(host)) = value.as_object().map(|handle| self.heap.get(handle))
+            {
+                let handle = heap.alloc(Object::Host(host.clone()));
+                globals.insert(name.clone(), Value::Object(handle));
+            }
+        }
+        self.heap = heap;
+        self.globals = globals;
+        self.script = script;
+        self.modules = modules;
+        self.functions = functions;
+        self.reset();
+        Ok(())
+    }
+
+    fn check_idle(&self, doing: &str) -> Result<(), String> {
+        if !self.frames.is_empty() || !self.resumed.is_empty() {
+            Err(format!("cannot {} while a script is running", doing))
+        } else if !self.waiting.is_empty() {
+            Err(format!(
+                "cannot {} while coroutines wait on the host",
+                doing
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Every object that can be reached from the roots, in the order of
+    // their slots.
+    fn reachable(&self) -> Vec<Handle> {
+        let mut reached = HashSet::new();
+        let mut pending = self.roots();
+        while let Some(handle) = pending.pop() {
+            if reached.insert(handle) {
+                self.heap.get(handle).references(&mut pending);
+            }
+        }
+        let mut reached: Vec<Handle> = reached.into_iter().collect();
+        reached.sort_by_key(|handle| handle.index());
+        reached
+    }
+
+    // The function of the host with the name among the globals, which is
+    // what a saved one turns back into.
+    fn host_function(&self, name: &str) -> Option<HostFunction> {
+        let script = self
+            .script
+            .map(|script| self.slots(script).slots.iter().flatten());
+        let values = self.globals.values().chain(script.into_iter().flatten());
+        values
+            .filter_map(Value::as_object)
+            .find_map(|handle| match self.heap.get(handle) {
+                Object::Host(host) if host.name() == name => Some(host.clone()),
+                _ => None,
+            })
+    }
+
+    // A native of the host among the globals.
+    fn native(&self, name: &str) -> Option<Native> {
+        self.globals
+            .values()
+            .filter_map(Value::as_object)
+            .find_map(|handle| match self.heap.get(handle) {
+                Object::Native(native) if native.name == name => Some(*native),
+                _ => None,
+            })
+    }
+}
+
+// The prototypes of the closures being saved. The ones made from the same
+// script are saved along with the outermost of them and found through the
+// constants they are in, so that they are shared again once restored.
+struct Protos {
+    // The outermost prototypes, with the number of global slots of the
+    // script they are in.
+    roots: Vec<(Arc<Prototype>, usize)>,
+    // The root every prototype is in, and the constants it is found through.
+    places: Vec<(usize, Vec<usize>)>,
+    indices: HashMap<*const Prototype, usize>,
+}
+
+impl Protos {
+    fn collect(heap: &Heap, saved: &[Handle]) -> Self {
+        let mut all = Vec::new();
+        let mut indices = HashMap::new();
+        for &handle in saved {
+            if let Object::Closure(closure) = heap.get(handle) {
+                indices
+                    .entry(Arc::as_ptr(&closure.proto))
+                    .or_insert_with(|| {
+                        all.push(closure.proto.clone());
+                        all.len() - 1
+                    });
+            }
+        }
+        let mut nested = HashSet::new();
+        for proto in &all {
+            walk(proto, &mut Vec::new(), &mut |inner, path| {
+                if !path.is_empty() {
+                    nested.insert(Arc::as_ptr(inner));
+                }
+            });
+        }
+        let mut roots = Vec::new();
+        let mut places = vec![(0, Vec::new()); all.len()];
+        for proto in all
+            .iter()
+            .filter(|proto| !nested.contains(&Arc::as_ptr(proto)))
+        {
+            let root = roots.len();
+            walk(proto, &mut Vec::new(), &mut |inner, path| {
+                if let Some(&index) = indices.get(&Arc::as_ptr(inner)) {
+                    places[index] = (root, path.to_vec());
+                }
+            });
+            roots.push((proto.clone(), 0));
+        }
+        for &handle in saved {
+            if let Object::Closure(closure) = heap.get(handle) {
+                let (root, _) = places[indices[&Arc::as_ptr(&closure.proto)]];
+                if let Object::Globals(globals) = heap.get(closure.globals) {
+                    roots[root].1 = globals.slots.len();
+                }
+            }
+        }
+        Self {
+            roots,
+            places,
+            indices,
+        }
+    }
+
+    fn index(&self, proto: &Arc<Prototype>) -> usize {
+        self.indices[&Arc::as_ptr(proto)]
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        let functions: Vec<(Function, usize)> = self
+            .roots
+            .iter()
+            .map(|(proto, globals)| (unfold(proto), *globals))
+            .collect();
+        let functions: Vec<(&Function, usize)> = functions
+            .iter()
+            .map(|(function, globals)| (function, *globals))
+            .collect();
+        let bytes = serialize_functions(&functions);
+        writer.u32(bytes.len());
+        writer.bytes.extend_from_slice(&bytes);
+        for (proto, _) in &self.roots {
+            writer.string(&proto.file);
+        }
+        writer.u32(self.places.len());
+        for (root, path) in &self.places {
+            writer.u32(*root);
+            writer.u32(path.len());
+            for &constant in path {
+                writer.u32(constant);
+            }
+        }
+    }
+}
+
+// Goes through the prototype and every one inside of it, along with the
+// constants each is found through.
+fn walk(
+    proto: &Arc<Prototype>,
+    path: &mut Vec<usize>,
+    visit: &mut dyn FnMut(&Arc<Prototype>, &[usize]),
+) {
+    visit(proto, path);
+    for (constant, nested) in proto.functions.iter().enumerate() {
+        if let Some(nested) = nested {
+            path.push(constant);
+            walk(nested, path, visit);
+            path.pop();
+        }
+    }
+}
+
+// The function of a prototype with the functions that were moved out of
+// its constants put back, the way it was compiled.
+fn unfold(proto: &Prototype) -> Function {
+    let mut function = proto.function.clone();
+    let mut nested = proto.functions.iter().flatten();
+    for slot in function.chunk.functions_mut() {
+        *slot = unfold(nested.next().expect("every function has a prototype"));
+    }
+    function
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn handle(&mut self, handle: Handle) {
+        self.u32(handle.index());
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.u8(NULL_TAG),
+            Value::Bool(false) => self.u8(FALSE_TAG),
+            Value::Bool(true) => self.u8(TRUE_TAG),
+            Value::Int(n) => {
+                self.u8(INT_TAG);
+                self.u64(*n as u64);
+            }
+            Value::Float(n) => {
+                self.u8(FLOAT_TAG);
+                self.u64(n.to_bits());
+            }
+            #[cfg(feature = "bigint")]
+            Value::BigInt(n) => {
+                self.u8(BIGINT_TAG);
+                self.string(&n.to_string());
+            }
+            Value::String(s) => {
+                self.u8(STRING_TAG);
+                self.string(s);
+            }
+            Value::Object(handle) => {
+                self.u8(OBJECT_TAG);
+                self.handle(*handle);
+            }
+        }
+    }
+
+    fn values<'a>(&mut self, values: impl ExactSizeIterator<Item = &'a Value>) {
+        self.u32(values.len());
+        for value in values {
+            self.value(value);
+        }
+    }
+
+    // The members of a class are sorted by name, so that saving the same
+    // class always gives the same bytes.
+    fn members(&mut self, members: &HashMap<Symbol, Value>) {
+        let mut members: Vec<_> = members.iter().collect();
+        members.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        self.u32(members.len());
+        for (name, value) in members {
+            self.string(name);
+            self.value(value);
+        }
+    }
+
+    fn object(
+        &mut self,
+        object: &Object,
+        protos: &Protos,
+        saved: &HashSet<Handle>,
+        types: &ForeignTypes,
+    ) -> Result<(), String> {
+        match object {
+            Object::List(items) => {
+                self.u8(LIST_TAG);
+                self.values(items.iter());
+            }
+            Object::Map(map) => {
+                self.u8(MAP_TAG);
+                self.u32(map.len());
+                for (key, value) in map.iter() {
+                    self.value(&key.value());
+                    self.value(value);
+                }
+            }
+            Object::Range(range) => {
+                self.u8(RANGE_TAG);
+                self.range(range);
+            }
+            Object::Native(native) => {
+                self.u8(NATIVE_TAG);
+                self.string(native.name);
+            }
+            Object::Host(host) => {
+                self.u8(HOST_TAG);
+                self.string(host.name());
+            }
+            Object::Interpreted(_) | Object::Scope(_) => {
+                return Err(String::from(
+                    "cannot snapshot the functions of the interpreter",
+                ))
+            }
+            Object::Closure(closure) => {
+                self.u8(CLOSURE_TAG);
+                self.u32(protos.index(&closure.proto));
+                self.handle(closure.globals);
+                self.u32(closure.upvalues.len());
+                for &upvalue in &closure.upvalues {
+                    self.handle(upvalue);
+                }
+            }
+            Object::Upvalue(Upvalue::Closed(value)) => {
+                self.u8(UPVALUE_TAG);
+                self.value(value);
+            }
+            Object::Upvalue(Upvalue::Open(_)) => {
+                unreachable!("upvalues are only open while a script is running")
+            }
+            Object::Globals(globals) => {
+                self.u8(GLOBALS_TAG);
+                self.u32(globals.names.len());
+                for (name, slot) in globals.names.iter().zip(&globals.slots) {
+                    self.string(name);
+                    match slot {
+                        Some(value) => {
+                            self.u8(1);
+                            self.value(value);
+                        }
+                        None => self.u8(0),
+                    }
+                }
+            }
+            Object::Class(class) => {
+                self.u8(CLASS_TAG);
+                self.string(&class.name);
+                match class.superclass {
+                    Some(superclass) => {
+                        self.u8(1);
+                        self.handle(superclass);
+                    }
+                    None => self.u8(0),
+                }
+                self.members(&class.methods);
+                self.members(&class.getters);
+                self.members(&class.setters);
+            }
+            Object::Instance(instance) => {
+                self.u8(INSTANCE_TAG);
+                self.handle(instance.class);
+                self.u32(instance.fields.len());
+                for (name, value) in instance.fields.iter() {
+                    self.string(name);
+                    self.value(value);
+                }
+            }
+            Object::BoundMethod(bound) => {
+                self.u8(BOUND_METHOD_TAG);
+                self.value(&bound.receiver);
+                self.value(&bound.method);
+            }
+            Object::Iterator(iter) => {
+                self.u8(ITERATOR_TAG);
+                self.u32(iter.position);
+                match &iter.items {
+                    Items::List(list) => {
+                        self.u8(0);
+                        self.handle(*list);
+                    }
+                    Items::Range(range) => {
+                        self.u8(1);
+                        self.range(range);
+                    }
+                    Items::Values(values) => {
+                        self.u8(2);
+                        self.values(values.iter());
+                    }
+                }
+            }
+            Object::Coroutine(coroutine) => {
+                self.u8(COROUTINE_TAG);
+                self.coroutine(coroutine);
+            }
+            Object::Foreign(foreign) => {
+                let name = foreign.type_name();
+                let (save, _) = types.types.get(name).ok_or_else(|| {
+                    format!(
+                        "cannot snapshot {} values, which have no way to be saved",
+                        name
+                    )
+                })?;
+                let bytes =
+                    save(foreign).map_err(|msg| format!("cannot save {}: {}", name, msg))?;
+                self.u8(FOREIGN_TAG);
+                self.string(name);
+                self.u32(bytes.len());
+                self.bytes.extend_from_slice(&bytes);
+            }
+            // A weak reference to what isn't saved is saved empty, since
+            // nothing could have got at its object again.
+            Object::Weak(weak) => {
+                self.u8(WEAK_TAG);
+                match weak.get().filter(|target| saved.contains(target)) {
+                    Some(target) => {
+                        self.u8(1);
+                        self.handle(target);
+                    }
+                    None => self.u8(0),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn range(&mut self, range: &Range) {
+        self.u64(range.start as u64);
+        self.u64(range.end as u64);
+        self.u8(range.inclusive as u8);
+    }
+
+    // Only coroutines that are stopped are saved, since nothing is running.
+    fn coroutine(&mut self, coroutine: &Coroutine) {
+        self.value(&coroutine.function);
+        self.u8(match coroutine.state {
+            CoroutineState::Suspended => 0,
+            CoroutineState::Finished => 1,
+            CoroutineState::Running | CoroutineState::Waiting => {
+                unreachable!("coroutines only run or wait while a script is running")
+            }
+        });
+        self.values(coroutine.stack.iter());
+        self.u32(coroutine.frames.len());
+        for frame in &coroutine.frames {
+            self.handle(frame.closure);
+            self.u32(frame.ip);
+            self.u32(frame.base);
+            self.u8(match frame.returned {
+                Returned::Kept => 0,
+                Returned::Discarded => 1,
+                Returned::Negated => 2,
+                Returned::Imported => 3,
+                Returned::Host => 4,
+            });
+        }
+        self.u32(coroutine.upvalues.len());
+        for &(slot, upvalue) in &coroutine.upvalues {
+            self.u32(slot);
+            self.handle(upvalue);
+        }
+        self.u32(coroutine.handlers.len());
+        for handler in &coroutine.handlers {
+            self.u32(handler.frames);
+            self.u32(handler.depth);
+            self.u32(handler.ip);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        match self.bytes.get(self.offset..self.offset + len) {
+            Some(bytes) => {
+                self.offset += len;
+                Ok(bytes)
+            }
+            None => Err(String::from("unexpected end of snapshot")),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let bytes = <[u8; 8]>::try_from(self.take(8)?).expect("eight bytes were taken");
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(String::from(INVALID)),
+        }
+    }
+
+    // Counts are checked against what is left, the same as in compiled
+    // scripts, so that a broken snapshot can't allocate a huge amount.
+    fn count(&mut self, item_len: usize) -> Result<usize, String> {
+        let count = self.u32()?;
+        if count.saturating_mul(item_len) > self.bytes.len() - self.offset {
+            return Err(String::from("unexpected end of snapshot"));
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.count(1)?;
+        match std::str::from_utf8(self.take(len)?) {
+            Ok(s) => Ok(String::from(s)),
+            Err(_) => Err(String::from(INVALID)),
+        }
+    }
+
+    fn handle(&mut self) -> Result<Handle, String> {
+        Ok(Handle::from_index(self.u32()? as u32))
+    }
+
+    fn range(&mut self) -> Result<Range, String> {
+        Ok(Range {
+            start: self.u64()? as i64,
+            end: self.u64()? as i64,
+            inclusive: self.bool()?,
+        })
+    }
+}
+
+// Turns a snapshot back into objects in a new heap, which is only put in
+// the VM once all of it has been read and checked.
+struct Loader<'a> {
+    reader: Reader<'a>,
+    vm: &'a Vm,
+    types: &'a ForeignTypes,
+    heap: Heap,
+    // Every prototype, with the number of global slots of its script.
+    protos: Vec<(Arc<Prototype>, usize)>,
+    objects: Vec<Option<Object>>,
+    // The methods of the foreign values read so far.
+    methods: Vec<Native>,
+}
+
+impl Loader<'_> {
+    fn get(&self, handle: Handle) -> Option<&Object> {
+        self.objects.get(handle.index()).and_then(Option::as_ref)
+    }
+
+    fn protos(&mut self) -> Result<(), String> {
+        let len = self.reader.count(1)?;
+        let bytes = self.reader.take(len)?;
+        let functions = deserialize_functions("snapshot", bytes)
+            .map_err(|error| String::from(error.message()))?;
+        let mut roots = Vec::new();
+        for (function, globals) in functions {
+            let file: Arc<str> = Arc::from(self.reader.string()?);
+            let proto = Prototype::new(function, &file, &mut self.heap);
+            roots.push((Arc::new(proto), globals));
+        }
+        for _ in 0..self.reader.count(8)? {
+            let root = self.reader.u32()?;
+            let (mut proto, globals) = roots.get(root).cloned().ok_or(INVALID)?;
+            for _ in 0..self.reader.count(4)? {
+                let constant = self.reader.u32()?;
+                let nested = proto.functions.get(constant).cloned().flatten();
+                proto = nested.ok_or(INVALID)?;
+            }
+            self.protos.push((proto, globals));
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        Ok(match self.reader.u8()? {
+            NULL_TAG => Value::Null,
+            FALSE_TAG => Value::Bool(false),
+            TRUE_TAG => Value::Bool(true),
+            INT_TAG => Value::Int(self.reader.u64()? as i64),
+            FLOAT_TAG => Value::Float(f64::from_bits(self.reader.u64()?)),
+            #[cfg(feature = "bigint")]
+            BIGINT_TAG => {
+                let digits = self.reader.string()?;
+                Value::from(BigInt::parse(&digits).ok_or(INVALID)?)
+            }
+            STRING_TAG => Value::from(self.reader.string()?),
+            OBJECT_TAG => Value::Object(self.reader.handle()?),
+            _ => return Err(String::from(INVALID)),
+        })
+    }
+
+    fn values(&mut self) -> Result<Vec<Value>, String> {
+        let mut values = Vec::new();
+        for _ in 0..self.reader.count(1)? {
+            values.push(self.value()?);
+        }
+        Ok(values)
+    }
+
+    fn members(&mut self) -> Result<HashMap<Symbol, Value>, String> {
+        let mut members = HashMap::new();
+        for _ in 0..self.reader.count(5)? {
+            let name = self.reader.string()?;
+            members.insert(self.heap.intern(&name), self.value()?);
+        }
+        Ok(members)
+    }
+
+    fn object(&mut self) -> Result<Object, String> {
+        Ok(match self.reader.u8()? {
+            LIST_TAG => Object::List(self.values()?),
+            MAP_TAG => {
+                let mut map = Map::new();
+                for _ in 0..self.reader.count(2)? {
+                    let key = self.value()?.key().ok_or(INVALID)?;
+                    map.insert(key, self.value()?);
+                }
+                Object::Map(map)
+            }
+            RANGE_TAG => Object::Range(self.reader.range()?),
+            NATIVE_TAG => {
+                let name = self.reader.string()?;
+                let native = builtins::native(&name)
+                    .or_else(|| self.methods.iter().find(|m| m.name == name).copied())
+                    .or_else(|| self.vm.native(&name));
+                Object::Native(native.ok_or_else(|| format!("{} is not defined", name))?)
+            }
+            HOST_TAG => {
+                let name = self.reader.string()?;
+                let host = self.vm.host_function(&name);
+                Object::Host(host.ok_or_else(|| format!("{} is not defined", name))?)
+            }
+            CLOSURE_TAG => {
+                let (proto, _) = self.protos.get(self.reader.u32()?).ok_or(INVALID)?;
+                let proto = proto.clone();
+                let globals = self.reader.handle()?;
+                let mut upvalues = Vec::new();
+                for _ in 0..self.reader.count(4)? {
+                    upvalues.push(self.reader.handle()?);
+                }
+                if upvalues.len() != proto.function.upvalues.len() {
+                    return Err(String::from(INVALID));
+                }
+                Object::Closure(Closure {
+                    proto,
+                    globals,
+                    upvalues,
+                    caches: Vec::new(),
+                })
+            }
+            UPVALUE_TAG => Object::Upvalue(Upvalue::Closed(self.value()?)),
+            GLOBALS_TAG => {
+                let mut globals = Globals::new(&[]);
+                for _ in 0..self.reader.count(5)? {
+                    globals.names.push(self.reader.string()?);
+                    let slot = if self.reader.bool()? {
+                        Some(self.value()?)
+                    } else {
+                        None
+                    };
+                    globals.slots.push(slot);
+                }
+                Object::Globals(globals)
+            }
+            CLASS_TAG => {
+                let mut class = Class::new(&self.reader.string()?);
+                if self.reader.bool()? {
+                    class.superclass = Some(self.reader.handle()?);
+                }
+                class.methods = self.members()?;
+                class.getters = self.members()?;
+                class.setters = self.members()?;
+                Object::Class(class)
+            }
+            INSTANCE_TAG => {
+                let class = self.reader.handle()?;
+                let mut fields = Map::new();
+                for _ in 0..self.reader.count(5)? {
+                    let name = self.reader.string()?;
+                    fields.insert(self.heap.intern(&name), self.value()?);
+                }
+                Object::Instance(Instance { class, fields })
+            }
+            BOUND_METHOD_TAG => Object::BoundMethod(BoundMethod {
+                receiver: self.value()?,
+                method: self.value()?,
+            }),
+            ITERATOR_TAG => {
+                let position = self.reader.u32()?;
+                let items = match self.reader.u8()? {
+                    0 => Items::List(self.reader.handle()?),
+                    1 => Items::Range(self.reader.range()?),
+                    2 => Items::Values(self.values()?),
+                    _ => return Err(String::from(INVALID)),
+                };
+                Object::Iterator(Iter { items, position })
+            }
+            COROUTINE_TAG => Object::Coroutine(self.coroutine()?),
+            FOREIGN_TAG => {
+                let name = self.reader.string()?;
+                let len = self.reader.count(1)?;
+                let bytes = self.reader.take(len)?;
+                let (_, load) = self.types.types.get(&name).ok_or_else(|| {
+                    format!(
+                        "cannot restore {} values, which have no way to be loaded",
+                        name
+                    )
+                })?;
+                let foreign =
+                    load(bytes).map_err(|msg| format!("cannot load {}: {}", name, msg))?;
+                if foreign.type_name() != name {
+                    return Err(format!(
+                        "loading {} gave back {}",
+                        name,
+                        foreign.type_name()
+                    ));
+                }
+                self.methods.extend_from_slice(foreign.methods());
+                Object::Foreign(foreign)
+            }
+            WEAK_TAG => Object::Weak(match self.reader.bool()? {
+                true => Weak::new(self.reader.handle()?),
+                false => Weak::default(),
+            }),
+            _ => return Err(String::from(INVALID)),
+        })
+    }
+
+    // The frames of a coroutine have to run closures that were read
+    // already, and stop where an instruction starts, with their slots on
+    // the stack of the coroutine.
+    fn coroutine(&mut self) -> Result<Coroutine, String> {
+        let mut coroutine = Coroutine::new(self.value()?);
+        coroutine.state = match self.reader.u8()? {
+            0 => CoroutineState::Suspended,
+            1 => CoroutineState::Finished,
+            _ => return Err(String::from(INVALID)),
+        };
+        coroutine.stack = self.values()?;
+        for _ in 0..self.reader.count(13)? {
+            let closure = self.reader.handle()?;
+            let proto = match self.get(closure) {
+                Some(Object::Closure(closure)) => closure.proto.clone(),
+                _ => return Err(String::from(INVALID)),
+            };
+            let ip = self.reader.u32()?;
+            let base = self.reader.u32()?;
+            let returned = match self.reader.u8()? {
+                0 => Returned::Kept,
+                1 => Returned::Discarded,
+                2 => Returned::Negated,
+                3 => Returned::Imported,
+                4 => Returned::Host,
+                _ => return Err(String::from(INVALID)),
+            };
+            if !is_start(&proto, ip) || base >= coroutine.stack.len() {
+                return Err(String::from(INVALID));
+            }
+            coroutine.frames.push(Frame {
+                proto,
+                closure,
+                ip,
+                base,
+                returned,
+            });
+        }
+        for _ in 0..self.reader.count(8)? {
+            let slot = self.reader.u32()?;
+            let upvalue = self.reader.handle()?;
+            if slot >= coroutine.stack.len() {
+                return Err(String::from(INVALID));
+            }
+            coroutine.upvalues.push((slot, upvalue));
+        }
+        for _ in 0..self.reader.count(12)? {
+            let handler = Handler {
+                frames: self.reader.u32()?,
+                depth: self.reader.u32()?,
+                ip: self.reader.u32()?,
+            };
+            let frame = match handler.frames.checked_sub(1) {
+                Some(frame) => coroutine.frames.get(frame),
+                None => None,
+            };
+            let valid = frame.is_some_and(|frame| is_start(&frame.proto, handler.ip));
+            if !valid || handler.depth > coroutine.stack.len() {
+                return Err(String::from(INVALID));
+            }
+            coroutine.handlers.push(handler);
+        }
+        Ok(coroutine)
+    }
+
+    // Whether every handle refers to an object of the kind it has to be,
+    // and every closure has the globals of its script.
+    fn check(&self) -> bool {
+        let is = |handle: Handle, kind: fn(&Object) -> bool| self.get(handle).is_some_and(kind);
+        let globals: HashMap<*const Prototype, usize> = self
+            .protos
+            .iter()
+            .map(|(proto, globals)| (Arc::as_ptr(proto), *globals))
+            .collect();
+        self.objects.iter().flatten().all(|object| {
+            let mut references = Vec::new();
+            object.references(&mut references);
+            if !references.iter().all(|&handle| self.get(handle).is_some()) {
+                return false;
+            }
+            match object {
+                Object::Closure(closure) => {
+                    let globals = globals.get(&Arc::as_ptr(&closure.proto)).copied();
+                    let upvalues = closure
+                        .upvalues
+                        .iter()
+                        .all(|&upvalue| is(upvalue, |o| matches!(o, Object::Upvalue(_))));
+                    upvalues
+                        && matches!(
+                            self.get(closure.globals),
+                            Some(Object::Globals(slots)) if Some(slots.slots.len()) == globals
+                        )
+                }
+                // Decorators can make a method out of anything, but an
+                // initializer can't have any, so it is always a closure.
+                Object::Class(class) => {
+                    let superclass = class
+                        .superclass
+                        .is_none_or(|superclass| is(superclass, |o| matches!(o, Object::Class(_))));
+                    let initializer = class
+                        .methods
+                        .iter()
+                        .filter(|(name, _)| name.as_str() == INITIALIZER_NAME)
+                        .all(|(_, method)| {
+                            method.as_object().is_some_and(|method| {
+                                is(method, |o| matches!(o, Object::Closure(_)))
+                            })
+                        });
+                    superclass && initializer
+                }
+                // Only functions and the methods of builtin values are bound.
+                Object::BoundMethod(bound) => bound.method.as_object().is_some_and(|method| {
+                    is(method, |o| {
+                        matches!(o, Object::Closure(_) | Object::Native(_))
+                    })
+                }),
+                Object::Instance(instance) => is(instance.class, |o| matches!(o, Object::Class(_))),
+                Object::Iterator(Iter {
+                    items: Items::List(list),
+                    ..
+                }) => is(*list, |o| matches!(o, Object::List(_))),
+                Object::Coroutine(coroutine) => {
+                    let function = coroutine.function.as_object().is_some_and(|function| {
+                        is(function, |o| {
+                            matches!(o, Object::Closure(_) | Object::BoundMethod(_))
+                        })
+                    });
+                    let upvalues = coroutine
+                        .upvalues
+                        .iter()
+                        .all(|&(_, upvalue)| is(upvalue, |o| matches!(o, Object::Upvalue(_))));
+                    function && upvalues
+                }
+                Object::Weak(weak) => weak.get().is_none_or(|target| self.get(target).is_some()),
+                _ => true,
+            }
+        })
+    }
+}
+
+// Whether an instruction starts at the offset, past the first one, which is
+// where a frame that has stopped goes on from.
+fn is_start(proto: &Prototype, offset: usize) -> bool {
+    offset > 0
+        && proto
+            .function
+            .chunk
+            .instructions()
+            .any(|(start, _)| start == offset)
+}
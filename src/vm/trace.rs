@@ -0,0 +1,53 @@
+use super::Vm;
+use crate::bytecode::disassemble::disassemble_instruction;
+use std::io::Write;
+
+// Tracing writes a line for every instruction before it runs: where it is,
+// the function running it, the instruction the way the disassembler shows
+// it, and the value on top of the stack, for example
+//
+//     test:2 fib 0004 get_local     1 | 10
+//
+// The value is shown the way `print` shows it, and the top of an empty
+// stack as `-`.
+
+impl Vm {
+    // Traces the scripts that run from now on into the sink, or stops
+    // tracing them, giving back the sink they were traced into before.
+    // A sink that fails to be written to is dropped, and the script goes on
+    // without it.
+    pub fn set_trace(
+        &mut self,
+        out: Option<Box<dyn Write + Send>>,
+    ) -> Option<Box<dyn Write + Send>> {
+        std::mem::replace(&mut self.trace, out)
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    // Writes the instruction at the offset in the frame on top.
+    pub(super) fn trace(&mut self, offset: usize) {
+        let proto = &self.frame().proto;
+        let chunk = &proto.function.chunk;
+        let (instruction, _) = disassemble_instruction(chunk, offset);
+        let top = match self.stack.last() {
+            Some(value) => value.display(&self.heap).to_string(),
+            None => String::from("-"),
+        };
+        let line = format!(
+            "{}:{} {} {:04} {} | {}",
+            proto.file,
+            chunk.line(offset),
+            proto.function.name,
+            offset,
+            instruction,
+            top
+        );
+        let out = self.trace.as_mut().expect("the vm is tracing");
+        if writeln!(out, "{}", line).is_err() {
+            self.trace = None;
+        }
+    }
+}
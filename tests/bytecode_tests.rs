@@ -0,0 +1,638 @@
+extern crate atom;
+
+use atom::ast::Span;
+use atom::bytecode::*;
+
+fn line(line: u32) -> Span {
+    Span::new(0, 0, line, 1)
+}
+
+#[test]
+fn test_opcode_bytes() {
+    // Every opcode is found again from its byte.
+    for (i, op) in OpCode::all().iter().enumerate() {
+        assert_eq!(*op as u8 as usize, i);
+        assert_eq!(OpCode::from_byte(*op as u8), Some(*op));
+    }
+    assert_eq!(OpCode::from_byte(OpCode::all().len() as u8), None);
+    assert_eq!(OpCode::from_byte(u8::MAX), None);
+
+    // Names are unique so disassembled code is never ambiguous.
+    let mut names: Vec<&str> = OpCode::all().iter().map(|op| op.name()).collect();
+    names.sort_unstable();
+    names.dedup();
+    assert_eq!(names.len(), OpCode::all().len());
+}
+
+#[test]
+fn test_writing_and_reading() {
+    let mut chunk = Chunk::new("main");
+    assert!(chunk.is_empty());
+    let index = chunk.add_constant(Constant::Float(1.5)).unwrap();
+    chunk.write(OpCode::Constant, line(1));
+    chunk.write_byte(index as u8, line(1));
+    chunk.write(OpCode::Jump, line(2));
+    chunk.write_u16(0x1234, line(2));
+    chunk.write(OpCode::Return, line(3));
+
+    assert_eq!(chunk.name(), "main");
+    assert_eq!(chunk.len(), 6);
+    assert_eq!(chunk.code()[0], OpCode::Constant as u8);
+    assert_eq!(
+        chunk.constants()[chunk.read_byte(1) as usize],
+        Constant::Float(1.5)
+    );
+    // Two byte operands are stored with the high byte first.
+    assert_eq!(&chunk.code()[3..5], &[0x12, 0x34]);
+    assert_eq!(chunk.read_u16(3), 0x1234);
+    chunk.patch_u16(3, 0xbeef);
+    assert_eq!(chunk.read_u16(3), 0xbeef);
+
+    // Every byte knows the line it came from, operands included.
+    let lines: Vec<u32> = (0..chunk.len()).map(|i| chunk.line(i)).collect();
+    assert_eq!(lines, vec![1, 1, 2, 2, 2, 3]);
+}
+
+#[test]
+fn test_instructions() {
+    let mut chunk = Chunk::new("main");
+    chunk.write(OpCode::Null, line(1));
+    chunk.write(OpCode::GetLocal, line(1));
+    chunk.write_byte(0, line(1));
+    chunk.write(OpCode::Loop, line(1));
+    chunk.write_u16(4, line(1));
+    chunk.write(OpCode::Return, line(1));
+
+    let instructions: Vec<(usize, OpCode)> = chunk.instructions().collect();
+    assert_eq!(
+        instructions,
+        vec![
+            (0, OpCode::Null),
+            (1, OpCode::GetLocal),
+            (3, OpCode::Loop),
+            (6, OpCode::Return),
+        ]
+    );
+    assert_eq!(chunk.next_offset(3), 6);
+}
+
+#[test]
+fn test_constant_deduplication() {
+    let mut chunk = Chunk::new("main");
+    let score = Constant::String("score".into());
+    for _ in 0..200 {
+        assert_eq!(chunk.write_constant(score.clone(), line(1)), Some(0));
+    }
+    assert_eq!(chunk.add_constant(Constant::Float(1.0)), Some(1));
+    assert_eq!(chunk.add_constant(Constant::Float(1.0)), Some(1));
+    // Numbers are only the same constant when they are the same bits.
+    assert_eq!(chunk.add_constant(Constant::Float(0.0)), Some(2));
+    assert_eq!(chunk.add_constant(Constant::Float(-0.0)), Some(3));
+    assert_eq!(chunk.add_constant(Constant::Float(f64::NAN)), Some(4));
+    assert_eq!(chunk.add_constant(Constant::Float(f64::NAN)), Some(4));
+    // A string is never the same constant as a number.
+    assert_eq!(chunk.add_constant(Constant::String("1".into())), Some(5));
+    // Nor is an int the same constant as a float.
+    assert_eq!(chunk.add_constant(Constant::Int(1)), Some(6));
+    assert_eq!(chunk.add_constant(Constant::Int(1)), Some(6));
+    assert_eq!(chunk.constants().len(), 7);
+    assert_eq!(chunk.len(), 400);
+}
+
+#[test]
+fn test_interner() {
+    let mut strings = Interner::new();
+    let a = strings.intern("score");
+    let b = strings.intern(&String::from("score")[..]);
+    assert!(std::sync::Arc::ptr_eq(&a, &b));
+    strings.intern("lives");
+    assert_eq!(strings.len(), 2);
+}
+
+#[test]
+fn test_wide_constants() {
+    let mut chunk = Chunk::new("main");
+    for i in 0..300 {
+        chunk
+            .write_constant(Constant::Float(i as f64), line(1))
+            .unwrap();
+    }
+    chunk.write_indexed(OpCode::GetGlobal, 2, line(2));
+    chunk.write(OpCode::Return, line(2));
+
+    let instructions: Vec<(usize, OpCode)> = chunk.instructions().collect();
+    assert_eq!(instructions.len(), 302);
+    // The first 256 constants fit in a byte.
+    assert_eq!(instructions[255], (510, OpCode::Constant));
+    assert!(!chunk.is_wide(510));
+    assert_eq!(chunk.operand(510), 255);
+    // The rest get a wide prefix and a two byte operand.
+    assert_eq!(instructions[256], (512, OpCode::Constant));
+    assert!(chunk.is_wide(512));
+    assert_eq!(chunk.code()[512], OpCode::Wide as u8);
+    assert_eq!(chunk.operand(512), 256);
+    assert_eq!(chunk.next_offset(512), 516);
+    assert_eq!(instructions[299], (684, OpCode::Constant));
+    assert_eq!(chunk.operand(684), 299);
+    assert_eq!(instructions[300], (688, OpCode::GetGlobal));
+    assert_eq!(chunk.operand(688), 2);
+    assert_eq!(instructions[301], (690, OpCode::Return));
+    assert!(OpCode::Constant.takes_constant());
+    assert!(!OpCode::GetLocal.takes_constant());
+}
+
+#[test]
+fn test_wide_jumps() {
+    // A wide jump has a four byte distance.
+    let mut chunk = Chunk::new("main");
+    chunk.write(OpCode::Wide, line(1));
+    chunk.write(OpCode::Jump, line(1));
+    chunk.write_u32(70000, line(1));
+    assert_eq!(chunk.len(), 6);
+    assert_eq!(chunk.op_at(0), Some(OpCode::Jump));
+    assert_eq!(chunk.read_u32(2), 70000);
+    assert_eq!(chunk.operand(0), 70000);
+    assert_eq!(chunk.next_offset(0), 6);
+    assert_eq!(
+        disassemble::disassemble_instruction(&chunk, 0).0,
+        "wide jump     70000 -> 70006"
+    );
+    assert!(OpCode::Jump.can_be_wide());
+    assert!(OpCode::Call.can_be_wide());
+    assert!(!OpCode::Add.can_be_wide());
+}
+
+#[test]
+fn test_constant_limit() {
+    let mut chunk = Chunk::new("main");
+    for i in 0..MAX_CONSTANTS {
+        assert_eq!(chunk.add_constant(Constant::Float(i as f64)), Some(i));
+    }
+    assert_eq!(chunk.add_constant(Constant::String("x".into())), None);
+    assert_eq!(chunk.constants().len(), MAX_CONSTANTS);
+}
+
+#[test]
+fn test_disassemble() {
+    let mut chunk = Chunk::new("main");
+    chunk.write_constant(Constant::Float(1.5), line(1)).unwrap();
+    chunk.write(OpCode::JumpIfFalse, line(1));
+    chunk.write_u16(3, line(1));
+    chunk.write(OpCode::Pop, line(2));
+    chunk.write_indexed(OpCode::GetGlobal, 1, line(2));
+    chunk.add_constant(Constant::String("print".into()));
+    chunk.write(OpCode::Call, line(2));
+    chunk.write_byte(0, line(2));
+    chunk.write(OpCode::Loop, line(3));
+    chunk.write_u16(11, line(3));
+    chunk.write(OpCode::Return, line(3));
+    assert_eq!(
+        disassemble::disassemble(&chunk),
+        "== main ==
+0000    1 constant      0 1.5
+0002    | jump_if_false 3 -> 8
+0005    2 pop
+0006    | get_global    1 \"print\"
+0008    | call          0
+0010    3 loop          11 -> 2
+0013    | return
+"
+    );
+}
+
+#[test]
+fn test_disassemble_wide_and_unknown() {
+    let mut chunk = Chunk::new("f");
+    for i in 0..257 {
+        chunk.add_constant(Constant::Int(i)).unwrap();
+    }
+    chunk.write_indexed(OpCode::Constant, 256, line(7));
+    chunk.write_byte(0xff, line(7));
+    chunk.write(OpCode::Jump, line(7));
+    assert_eq!(
+        disassemble::disassemble(&chunk),
+        "== f ==
+0000    7 wide constant 256 256
+0004    | unknown 0xff
+0005    | jump (truncated)
+"
+    );
+}
+
+fn sample_chunk() -> Chunk {
+    let mut chunk = Chunk::new("main");
+    chunk
+        .write_constant(Constant::Float(-0.5), line(1))
+        .unwrap();
+    chunk
+        .write_constant(Constant::String("héllo".into()), line(1))
+        .unwrap();
+    chunk.write(OpCode::JumpIfFalse, line(2));
+    chunk.write_u16(1, line(2));
+    chunk.write(OpCode::Negate, line(2));
+    chunk.write(OpCode::Return, line(3));
+    chunk
+}
+
+#[test]
+fn test_serialize() {
+    let chunk = sample_chunk();
+    let bytes = chunk.serialize();
+    assert_eq!(&bytes[..4], &serialize::MAGIC);
+    assert_eq!(&bytes[4..6], &serialize::FORMAT_VERSION.to_be_bytes());
+    let loaded = Chunk::deserialize("main.atc", &bytes).unwrap();
+    assert_eq!(loaded, chunk);
+
+    // Constants are still shared after loading.
+    let mut loaded = loaded;
+    assert_eq!(loaded.add_constant(Constant::Float(-0.5)), Some(0));
+    assert_eq!(loaded.constants().len(), 2);
+
+    let mut ints = Chunk::new("ints");
+    ints.write_constant(Constant::Int(i64::MIN), line(1))
+        .unwrap();
+    ints.write(OpCode::Return, line(1));
+    assert_eq!(
+        Chunk::deserialize("ints.atc", &ints.serialize()).unwrap(),
+        ints
+    );
+
+    let mut many = Chunk::new("many");
+    for i in 0..300 {
+        many.write_constant(Constant::Float(i as f64), line(i))
+            .unwrap();
+    }
+    many.write(OpCode::Return, line(300));
+    assert_eq!(Chunk::deserialize("many.atc", &many.serialize()), Ok(many));
+}
+
+#[test]
+fn test_deserialize_errors() {
+    let error = |bytes: &[u8]| match Chunk::deserialize("main.atc", bytes) {
+        Ok(_) => panic!("expected an error"),
+        Err(error) => {
+            assert_eq!(error.file_name(), "main.atc");
+            String::from(error.message())
+        }
+    };
+    let bytes = sample_chunk().serialize();
+
+    assert_eq!(error(b"#!/usr/bin/env atom"), "not a compiled script");
+    let mut old = bytes.clone();
+    old[5] = 14;
+    assert_eq!(
+        error(&old),
+        "compiled with format version 14 but only version 16 can be loaded"
+    );
+    assert_eq!(
+        error(&bytes[..bytes.len() - 1]),
+        "unexpected end of compiled script"
+    );
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert_eq!(error(&extra), "unexpected bytes after the chunk");
+
+    let mut missing = bytes.clone();
+    // The string constant comes after the table, the name, the globals and
+    // the number.
+    let constants = 6 + 4 + 4 + "héllo".len() + 4 + "main".len() + 4;
+    missing[constants + 4 + 9 + 1 + 3] = 9;
+    assert_eq!(error(&missing), "invalid string in compiled script");
+
+    let invalid = |chunk: Chunk| error(&chunk.serialize());
+    let mut chunk = Chunk::new("f");
+    chunk.write_byte(0xee, line(1));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in f: unknown instruction at 0"
+    );
+    let mut chunk = Chunk::new("f");
+    chunk.write(OpCode::Constant, line(1));
+    chunk.write_byte(3, line(1));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in f: constant at 0 uses a missing constant"
+    );
+    let mut chunk = Chunk::new("f");
+    chunk.write(OpCode::Jump, line(1));
+    chunk.write_u16(1, line(1));
+    chunk.write(OpCode::Call, line(1));
+    chunk.write_byte(0, line(1));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in f: the jump at 0 goes nowhere"
+    );
+    let mut chunk = Chunk::new("f");
+    chunk.write(OpCode::Loop, line(1));
+    chunk.write_u16(4, line(1));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in f: the jump at 0 goes nowhere"
+    );
+    let mut chunk = Chunk::new("f");
+    chunk.write(OpCode::Call, line(1));
+    assert_eq!(invalid(chunk), "invalid code in f: call at 0 is cut off");
+    let mut chunk = Chunk::new("f");
+    chunk.write(OpCode::Wide, line(1));
+    chunk.write(OpCode::Add, line(1));
+    assert_eq!(invalid(chunk), "invalid code in f: add at 0 cannot be wide");
+    let mut chunk = Chunk::new("f");
+    chunk.add_constant(Constant::Float(1.0));
+    chunk.write_indexed(OpCode::Closure, 0, line(1));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in f: closure at 0 needs a function"
+    );
+    let mut function = Function::new("g");
+    function.chunk.write_indexed(OpCode::GetUpvalue, 0, line(1));
+    let mut chunk = Chunk::new("f");
+    chunk.add_constant(Constant::Function(Box::new(function)));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in g: get_upvalue at 0 uses a missing upvalue"
+    );
+    let mut chunk = Chunk::new("f");
+    chunk.set_globals(vec![String::from("x")]);
+    chunk.write_indexed(OpCode::GetSlot, 1, line(1));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in f: get_slot at 0 uses a missing global"
+    );
+    let mut function = Function::new("g");
+    function.chunk.set_globals(vec![String::from("x")]);
+    let mut chunk = Chunk::new("f");
+    chunk.add_constant(Constant::Function(Box::new(function)));
+    assert_eq!(invalid(chunk), "only a script can have global slots");
+    // A function can't need more arguments than it takes, or take more
+    // than it has slots for.
+    let with_arity = |arity: usize, min_arity: usize, rest: bool| {
+        let mut function = Function::new("g");
+        function.arity = arity;
+        function.min_arity = min_arity;
+        function.rest = rest;
+        function.chunk.write(OpCode::Null, line(1));
+        function.chunk.write(OpCode::Return, line(1));
+        let mut chunk = Chunk::new("f");
+        chunk.add_constant(Constant::Function(Box::new(function)));
+        chunk.write(OpCode::Null, line(1));
+        chunk.write(OpCode::Return, line(1));
+        chunk
+    };
+    assert_eq!(
+        invalid(with_arity(1, 2, false)),
+        "invalid function in compiled script"
+    );
+    assert_eq!(
+        invalid(with_arity(65_536, 0, false)),
+        "invalid function in compiled script"
+    );
+    assert_eq!(
+        invalid(with_arity(65_535, 0, true)),
+        "invalid function in compiled script"
+    );
+    let chunk = with_arity(65_535, 65_535, false);
+    assert!(Chunk::deserialize("main.atc", &chunk.serialize()).is_ok());
+    let mut chunk = Chunk::new("f");
+    chunk.add_constant(Constant::Float(1.0));
+    chunk.write_indexed(OpCode::Switch, 0, line(1));
+    assert_eq!(invalid(chunk), "invalid code in f: switch at 0 needs cases");
+    let mut chunk = Chunk::new("f");
+    chunk.add_constant(Constant::Cases(Cases::Range { start: 0, len: 2 }));
+    chunk.write_indexed(OpCode::Switch, 0, line(1));
+    chunk.write(OpCode::Jump, line(1));
+    chunk.write_u16(0, line(1));
+    chunk.write(OpCode::Pop, line(1));
+    assert_eq!(
+        invalid(chunk),
+        "invalid code in f: switch at 0 needs a jump for every case"
+    );
+    let mut chunk = Chunk::new("f");
+    let unsorted = vec![Constant::String("b".into()), Constant::String("a".into())];
+    chunk.add_constant(Constant::Cases(Cases::Sorted(unsorted)));
+    assert_eq!(invalid(chunk), "invalid cases in compiled script");
+}
+
+#[test]
+fn test_cases() {
+    let range = Cases::Range { start: -1, len: 3 };
+    assert_eq!(range.len(), 3);
+    assert_eq!(range.find_number(-1.0), Some(0));
+    assert_eq!(range.find_number(1.0), Some(2));
+    assert_eq!(range.find_number(-0.0), Some(1));
+    assert_eq!(range.find_number(0.5), None);
+    assert_eq!(range.find_number(2.0), None);
+    assert_eq!(range.find_number(f64::NAN), None);
+    assert_eq!(range.find_int(-1), Some(0));
+    assert_eq!(range.find_int(1), Some(2));
+    assert_eq!(range.find_int(2), None);
+    assert_eq!(range.find_int(i64::MIN), None);
+    assert_eq!(range.find_string("0"), None);
+
+    let sorted = Cases::Sorted(vec![
+        Constant::Float(-2.5),
+        Constant::Int(10),
+        Constant::String("a".into()),
+        Constant::String("b".into()),
+    ]);
+    assert!(sorted.is_valid());
+    assert_eq!(sorted.find_number(10.0), Some(1));
+    assert_eq!(sorted.find_int(10), Some(1));
+    assert_eq!(sorted.find_int(-2), None);
+    assert_eq!(sorted.find_number(3.0), None);
+    assert_eq!(sorted.find_number(f64::NAN), None);
+    assert_eq!(sorted.find_string("b"), Some(3));
+    assert_eq!(sorted.find_string("c"), None);
+    assert_eq!(
+        Constant::Cases(sorted).to_string(),
+        "<cases -2.5, 10, \"a\", \"b\">"
+    );
+    assert_eq!(Constant::Cases(range).to_string(), "<cases -1..2>");
+
+    let mut chunk = Chunk::new("main");
+    let cases = chunk
+        .add_constant(Constant::Cases(Cases::Sorted(vec![
+            Constant::Int(1),
+            Constant::String("x".into()),
+        ])))
+        .unwrap();
+    chunk.write_indexed(OpCode::Switch, cases, line(1));
+    for _ in 0..2 {
+        chunk.write(OpCode::Jump, line(1));
+        chunk.write_u16(0, line(1));
+    }
+    chunk.write(OpCode::Null, line(1));
+    chunk.write(OpCode::Return, line(1));
+    assert_eq!(
+        disassemble::disassemble(&chunk),
+        "== main ==
+0000    1 switch        0 <cases 1, \"x\">
+0002    | jump          0 -> 5
+0005    | jump          0 -> 8
+0008    | null
+0009    | return
+"
+    );
+    assert_eq!(
+        chunk.cases(0),
+        Some(&Cases::Sorted(vec![
+            Constant::Int(1),
+            Constant::String("x".into()),
+        ]))
+    );
+    assert_eq!(chunk.cases(2), None);
+    assert_eq!(
+        Chunk::deserialize("main.atc", &chunk.serialize()),
+        Ok(chunk)
+    );
+}
+
+#[test]
+fn test_spans() {
+    let mut chunk = Chunk::new("main");
+    let call = Span::new(10, 22, 2, 5);
+    let argument = Span::new(16, 21, 2, 11);
+    chunk.write_indexed(OpCode::GetGlobal, 0, call);
+    chunk.write_constant(Constant::Float(1.0), argument);
+    chunk.write(OpCode::Negate, argument);
+    chunk.write(OpCode::Call, call);
+    chunk.write_byte(1, call);
+    chunk.write(OpCode::Return, line(3));
+
+    assert_eq!(chunk.span(0), call);
+    assert_eq!(chunk.span(1), call);
+    assert_eq!(chunk.span(2), argument);
+    assert_eq!(chunk.span(4), argument);
+    assert_eq!(chunk.span(5), call);
+    assert_eq!(chunk.span(6), call);
+    assert_eq!(chunk.line(7), 3);
+    // Only a change of span starts a new run.
+    assert_eq!(chunk.span_runs(), 4);
+
+    let loaded = Chunk::deserialize("main.atc", &chunk.serialize()).unwrap();
+    assert_eq!(loaded.span(4), argument);
+    assert_eq!(loaded, chunk);
+
+    let mut bytes = chunk.serialize();
+    // The offset of the second run is moved in front of the first. The runs
+    // are followed by a byte saying there are no debug symbols.
+    let first_run = bytes.len() - 4 * 20 - 1;
+    bytes[first_run + 20 + 3] = 0;
+    assert_eq!(
+        Chunk::deserialize("main.atc", &bytes).map_err(|e| String::from(e.message())),
+        Err(String::from("the span table does not match the code"))
+    );
+}
+
+#[test]
+fn test_serialize_nan() {
+    // Every NaN is kept as the same one, whatever its bits, so that scripts
+    // compile the same everywhere.
+    let mut chunk = Chunk::new("main");
+    let nan = chunk.add_constant(Constant::Float(f64::NAN)).unwrap();
+    let other = f64::from_bits(f64::NAN.to_bits() | 1 << 63 | 1);
+    assert!(other.is_nan());
+    assert_eq!(chunk.add_constant(Constant::Float(other)), Some(nan));
+    chunk.write(OpCode::Return, line(1));
+
+    let mut odd = Chunk::new("main");
+    odd.add_constant(Constant::Float(other)).unwrap();
+    odd.write(OpCode::Return, line(1));
+    assert_eq!(odd.serialize(), chunk.serialize());
+}
+
+#[test]
+fn test_serialize_functions() {
+    let mut function = Function::new("add");
+    function.arity = 2;
+    function.min_arity = 1;
+    function.chunk.write(OpCode::GetLocal, line(1));
+    function.chunk.write_byte(1, line(1));
+    function.chunk.write(OpCode::Return, line(1));
+    let mut chunk = Chunk::new("main");
+    let function = Constant::Function(Box::new(function));
+    chunk.write_constant(function.clone(), line(1)).unwrap();
+    // Functions are never shared, even when they are the same.
+    assert_eq!(chunk.add_constant(function), Some(1));
+    chunk.write(OpCode::Return, line(1));
+
+    let loaded = Chunk::deserialize("main.atc", &chunk.serialize()).unwrap();
+    assert_eq!(loaded, chunk);
+    assert_eq!(loaded.constants()[0].to_string(), "<function add>");
+    assert!(disassemble::disassemble(&loaded).contains("\n== add ==\n0000    1 get_local     1\n"));
+}
+
+#[test]
+fn test_serialize_upvalues() {
+    let mut function = Function::new("inc");
+    function.upvalues.push(Upvalue {
+        local: true,
+        index: 1,
+    });
+    function.upvalues.push(Upvalue {
+        local: false,
+        index: 300,
+    });
+    function.chunk.write_indexed(OpCode::GetUpvalue, 1, line(1));
+    function.chunk.write_indexed(OpCode::SetUpvalue, 0, line(1));
+    function.chunk.write(OpCode::Return, line(1));
+    // The closure is made in a function with a parameter for it to capture
+    // and enough upvalues of its own.
+    let mut outer = Function::new("outer");
+    outer.arity = 1;
+    outer.min_arity = 1;
+    outer.upvalues = (0..=300)
+        .map(|index| Upvalue {
+            local: false,
+            index,
+        })
+        .collect();
+    let index = outer
+        .chunk
+        .add_constant(Constant::Function(Box::new(function)));
+    outer
+        .chunk
+        .write_indexed(OpCode::Closure, index.unwrap(), line(1));
+    outer.chunk.write(OpCode::CloseUpvalue, line(1));
+    outer.chunk.write(OpCode::Return, line(1));
+    let mut chunk = Chunk::new("main");
+    chunk.add_constant(Constant::Function(Box::new(outer)));
+    chunk.write(OpCode::Null, line(1));
+    chunk.write(OpCode::Return, line(1));
+
+    let loaded = Chunk::deserialize("main.atc", &chunk.serialize()).unwrap();
+    assert_eq!(loaded, chunk);
+    let text = disassemble::disassemble(&loaded);
+    assert!(text.contains("0000    1 closure       0 <function inc> [local 1, upvalue 300]\n"));
+    assert!(text.contains("0002    | close_upvalue\n"));
+    assert!(text.contains("0000    1 get_upvalue   1\n0002    | set_upvalue   0\n"));
+}
+
+#[test]
+fn test_serialize_symbols() {
+    let mut chunk = Chunk::new("main");
+    chunk.write(OpCode::Null, line(1));
+    chunk.write(OpCode::Return, line(1));
+    chunk.set_symbols(DebugSymbols {
+        locals: vec![LocalSymbol {
+            name: String::from("health"),
+            slot: 1,
+            start: 1,
+            end: 2,
+        }],
+        upvalues: Vec::new(),
+    });
+    let loaded = Chunk::deserialize("main.atc", &chunk.serialize()).unwrap();
+    assert_eq!(loaded, chunk);
+    assert_eq!(loaded.symbols().unwrap().local(1, 1), Some("health"));
+
+    // The range of a local has to be inside the code.
+    let mut bytes = chunk.serialize();
+    let end = bytes.len() - 4 - 1;
+    bytes[end] = 3;
+    assert_eq!(
+        Chunk::deserialize("main.atc", &bytes).map_err(|e| String::from(e.message())),
+        Err(String::from("the debug symbols do not match the code"))
+    );
+    chunk.strip_symbols();
+    assert_eq!(chunk.symbols(), None);
+}
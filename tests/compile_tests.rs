@@ -0,0 +1,1394 @@
+extern crate atom;
+
+use atom::ast::Span;
+use atom::bytecode::disassemble::disassemble;
+use atom::bytecode::*;
+use atom::compile::*;
+use atom::parse::*;
+use std::sync::Arc;
+
+fn compiled(source: &str, opt_level: OptLevel) -> String {
+    let program = match parse_program("test", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("unexpected parse error: {}", errors[0]),
+    };
+    match compile_with(&program, opt_level) {
+        Ok(function) => disassemble(&function.chunk),
+        Err(errors) => panic!("unexpected compile error: {}", errors[0]),
+    }
+}
+
+fn registers(source: &str, opt_level: OptLevel) -> String {
+    let program = parse_program("test", source).unwrap();
+    match compile_to(&program, opt_level, Backend::Register) {
+        Ok(Compiled::Register(function)) => register::disassemble(&function),
+        Ok(Compiled::Stack(_)) => panic!("expected register code"),
+        Err(errors) => panic!("unexpected compile error: {}", errors[0]),
+    }
+}
+
+fn errors(source: &str) -> Vec<String> {
+    let program = parse_program("test", source).unwrap();
+    match compile(&program) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+    }
+}
+
+#[test]
+fn test_expressions() {
+    assert_eq!(
+        compiled("var x = 1;\nx = -x + 2 * x;", OptLevel::None),
+        "== test ==
+0000    1 constant      0 1
+0002    | define_slot   0 x
+0004    2 get_slot      0 x
+0006    | negate
+0007    | constant      1 2
+0009    | get_slot      0 x
+0011    | multiply
+0012    | add
+0013    | set_slot      0 x
+0015    | pop
+0016    | null
+0017    | return
+"
+    );
+    // `and` and `or` leave the deciding value on the stack.
+    assert_eq!(
+        compiled("var ok = a and b or not c;", OptLevel::None),
+        "== test ==
+0000    1 get_global    0 \"a\"
+0002    | jump_if_false 3 -> 8
+0005    | pop
+0006    | get_global    1 \"b\"
+0008    | jump_if_false 3 -> 14
+0011    | jump          4 -> 18
+0014    | pop
+0015    | get_global    2 \"c\"
+0017    | not
+0018    | define_slot   0 ok
+0020    | null
+0021    | return
+"
+    );
+}
+
+#[test]
+fn test_locals_and_loops() {
+    let source = "{
+    var i = 0;
+    while (i < 3) {
+        var j = i;
+        if (j == 1) { break; }
+        i = j + 1;
+    }
+}";
+    // Breaking out of the loop pops the condition of the if and j, while i
+    // is popped at the end of the block.
+    assert_eq!(
+        compiled(source, OptLevel::None),
+        "== test ==
+0000    2 constant      0 0
+0002    3 get_local     1
+0004    | constant      1 3
+0006    | less
+0007    | jump_if_false 32 -> 42
+0010    | pop
+0011    4 get_local     1
+0013    5 get_local     2
+0015    | constant      2 1
+0017    | equal
+0018    | jump_if_false 8 -> 29
+0021    | pop
+0022    | pop
+0023    | jump          17 -> 43
+0026    | jump          1 -> 30
+0029    | pop
+0030    6 get_local     2
+0032    | constant      2 1
+0034    | add
+0035    | set_local     1
+0037    | pop
+0038    3 pop
+0039    | loop          40 -> 2
+0042    | pop
+0043    1 pop
+0044    | null
+0045    | return
+"
+    );
+}
+
+#[test]
+fn test_functions() {
+    let source = "@trace function f(a, b = 2) {
+    var c = a;
+    return c + b;
+}";
+    assert_eq!(
+        compiled(source, OptLevel::None),
+        "== test ==
+0000    1 get_global    0 \"trace\"
+0002    | constant      1 <function f>
+0004    | call          1
+0006    | define_slot   0 f
+0008    | null
+0009    | return
+
+== f ==
+0000    1 get_local     2
+0002    | null
+0003    | equal
+0004    | jump_if_false 9 -> 16
+0007    | pop
+0008    | constant      0 2
+0010    | set_local     2
+0012    | pop
+0013    | jump          1 -> 17
+0016    | pop
+0017    2 get_local     1
+0019    3 get_local     3
+0021    | get_local     2
+0023    | add
+0024    | return
+0025    1 null
+0026    | return
+"
+    );
+
+    let program = parse_program("test", "var g = function(x, y = 1, ...rest) {};").unwrap();
+    let script = compile(&program).unwrap();
+    let lambda = match &script.chunk.constants()[0] {
+        Constant::Function(function) => function,
+        constant => panic!("expected a function but found {}", constant),
+    };
+    assert_eq!((lambda.arity, lambda.min_arity, lambda.rest), (2, 1, true));
+}
+
+#[test]
+fn test_match() {
+    let source = "match (x) {
+    case 1, 2: a();
+    default: c();
+    case 3: b();
+}";
+    // The default comes last, whichever place it has in the source.
+    assert_eq!(
+        compiled(source, OptLevel::None),
+        "== test ==
+0000    1 get_global    0 \"x\"
+0002    2 get_local     1
+0004    | constant      1 1
+0006    | equal
+0007    | jump_if_false 4 -> 14
+0010    | pop
+0011    | jump          17 -> 31
+0014    | pop
+0015    | get_local     1
+0017    | constant      2 2
+0019    | equal
+0020    | jump_if_false 4 -> 27
+0023    | pop
+0024    | jump          4 -> 31
+0027    | pop
+0028    | jump          8 -> 39
+0031    | get_global    3 \"a\"
+0033    | call          0
+0035    | pop
+0036    | jump          29 -> 68
+0039    4 get_local     1
+0041    | constant      4 3
+0043    | equal
+0044    | jump_if_false 4 -> 51
+0047    | pop
+0048    | jump          4 -> 55
+0051    | pop
+0052    | jump          8 -> 63
+0055    | get_global    5 \"b\"
+0057    | call          0
+0059    | pop
+0060    | jump          5 -> 68
+0063    3 get_global    6 \"c\"
+0065    | call          0
+0067    | pop
+0068    1 pop
+0069    | null
+0070    | return
+"
+    );
+}
+
+#[test]
+fn test_destructuring() {
+    // Globals take their parts from a temporary that is popped afterwards.
+    assert_eq!(
+        compiled("var [a, {b: c}] = pair;", OptLevel::None),
+        "== test ==
+0000    1 get_global    0 \"pair\"
+0002    | get_local     1
+0004    | constant      1 0
+0006    | get_index
+0007    | define_slot   0 a
+0009    | get_local     1
+0011    | constant      2 1
+0013    | get_index
+0014    | get_local     2
+0016    | constant      3 \"b\"
+0018    | get_index
+0019    | define_slot   1 c
+0021    | pop
+0022    | pop
+0023    | null
+0024    | return
+"
+    );
+    // Every value is worked out before anything is stored.
+    assert_eq!(
+        compiled("a.b, c[0] = 1, 2;", OptLevel::None),
+        "== test ==
+0000    1 constant      0 1
+0002    | constant      1 2
+0004    | get_global    2 \"a\"
+0006    | get_local     1
+0008    | set_property  3 \"b\"
+0010    | pop
+0011    | get_global    4 \"c\"
+0013    | constant      5 0
+0015    | get_local     2
+0017    | set_index
+0018    | pop
+0019    | pop
+0020    | pop
+0021    | null
+0022    | return
+"
+    );
+}
+
+#[test]
+fn test_compound_assignment() {
+    // The object and index are only worked out once.
+    assert_eq!(
+        compiled("f(items[next()] += 1);", OptLevel::None),
+        "== test ==
+0000    1 get_global    0 \"f\"
+0002    | get_global    1 \"items\"
+0004    | get_global    2 \"next\"
+0006    | call          0
+0008    | dup2
+0009    | get_index
+0010    | constant      3 1
+0012    | add
+0013    | set_index
+0014    | call          1
+0016    | pop
+0017    | null
+0018    | return
+"
+    );
+}
+
+#[test]
+fn test_interpolation() {
+    // Every part goes on the stack and the string is built once.
+    let source = "print(\"hp: {hp}/{2 * 5}!\");";
+    assert_eq!(
+        compiled(source, OptLevel::None),
+        "== test ==
+0000    1 get_global    0 \"print\"
+0002    | constant      1 \"hp: \"
+0004    | get_global    2 \"hp\"
+0006    | constant      3 \"/\"
+0008    | constant      4 2
+0010    | constant      5 5
+0012    | multiply
+0013    | constant      6 \"!\"
+0015    | concat        5
+0017    | call          1
+0019    | pop
+0020    | null
+0021    | return
+"
+    );
+    // Constants are folded into the text around them.
+    assert_eq!(
+        compiled(source, OptLevel::Basic),
+        "== test ==
+0000    1 get_global    0 \"print\"
+0002    | constant      1 \"hp: \"
+0004    | get_global    2 \"hp\"
+0006    | constant      3 \"/10!\"
+0008    | concat        3
+0010    | call          1
+0012    | pop
+0013    | null
+0014    | return
+"
+    );
+}
+
+#[test]
+fn test_closures() {
+    let source = "function counter() {
+    var n = 0;
+    function inc() {
+        n += 1;
+        return n;
+    }
+    return inc;
+}";
+    assert_eq!(
+        compiled(source, OptLevel::None),
+        "== test ==
+0000    1 constant      0 <function counter>
+0002    | define_slot   0 counter
+0004    | null
+0005    | return
+
+== counter ==
+0000    2 constant      0 0
+0002    3 closure       1 <function inc> [local 1]
+0004    7 get_local     2
+0006    | return
+0007    1 null
+0008    | return
+
+== inc ==
+0000    4 get_upvalue   0
+0002    | constant      0 1
+0004    | add
+0005    | set_upvalue   0
+0007    | pop
+0008    5 get_upvalue   0
+0010    | return
+0011    3 null
+0012    | return
+"
+    );
+
+    // Variables from further out are passed down through every closure in
+    // between, and a function can call itself through its own local.
+    let source = "function outer(a) {
+    function f(k) { return function() { return a + f(k); }; }
+    return f;
+}";
+    let text = compiled(source, OptLevel::None);
+    assert!(text.contains("0000    2 closure       0 <function f> [local 1, local 2]\n"));
+    assert!(text
+        .contains("0000    2 closure       0 <function lambda> [upvalue 0, upvalue 1, local 1]\n"));
+    assert!(text.contains(
+        "0000    2 get_upvalue   0\n0002    | get_upvalue   1\n0004    | get_upvalue   2\n"
+    ));
+}
+
+#[test]
+fn test_closing_upvalues() {
+    // Locals are closed over when they go out of scope, including the ones
+    // a break leaves behind before anything has captured them.
+    let source = "function f(a) {
+    while (a) {
+        var x = a;
+        if (x) break;
+        var g = function() { return x; };
+    }
+}";
+    assert_eq!(
+        compiled(source, OptLevel::Basic),
+        "== test ==
+0000    1 constant      0 <function f>
+0002    | define_slot   0 f
+0004    | null
+0005    | return
+
+== f ==
+0000    2 get_local     1
+0002    | jump_if_false 24 -> 29
+0005    | pop
+0006    3 get_local     1
+0008    4 get_local     2
+0010    | jump_if_false 8 -> 21
+0013    | pop
+0014    | close_upvalue
+0015    | jump          12 -> 30
+0018    | jump          1 -> 22
+0021    | pop
+0022    5 closure       0 <function lambda> [local 2]
+0024    2 pop
+0025    | close_upvalue
+0026    | loop          29 -> 0
+0029    | pop
+0030    1 null
+0031    | return
+
+== lambda ==
+0000    5 get_upvalue   0
+0002    | return
+0003    | null
+0004    | return
+"
+    );
+}
+
+#[test]
+fn test_tail_calls() {
+    // A state machine written as functions that call each other only ever
+    // needs one frame.
+    let source = "function even(n) {
+    if (n == 0) return true;
+    return odd(n - 1);
+}
+function odd(n) {
+    return n == 0 ? false : even(n - 1);
+}
+function half(n) {
+    return 1 + half(n / 2);
+}";
+    let text = compiled(source, OptLevel::Basic);
+    assert!(text.contains(
+        "== even ==
+0000    2 get_local     1
+0002    | constant      0 0
+0004    | equal
+0005    | jump_if_false 6 -> 14
+0008    | pop
+0009    | true
+0010    | return
+0011    | jump          1 -> 15
+0014    | pop
+0015    3 get_slot      1 odd
+0017    | get_local     1
+0019    | constant      1 1
+0021    | subtract
+0022    | tail_call     1
+"
+    ));
+    assert!(text.contains(
+        "== odd ==
+0000    6 get_local     1
+0002    | constant      0 0
+0004    | equal
+0005    | jump_if_false 3 -> 11
+0008    | pop
+0009    | false
+0010    | return
+0011    | pop
+0012    | get_slot      0 even
+0014    | get_local     1
+0016    | constant      1 1
+0018    | subtract
+0019    | tail_call     1
+"
+    ));
+    // Only a call whose result is returned as it is can reuse the frame.
+    assert!(text.contains("0009    | call          1\n0011    | add\n0012    | return\n"));
+}
+
+#[test]
+fn test_block_scoping() {
+    // The slot a block's local used is free again once the block is done.
+    let source = "function f() {
+    { var a = 1; }
+    { var b = 2; print(b); }
+}";
+    assert!(compiled(source, OptLevel::None).contains(
+        "== f ==
+0000    2 constant      0 1
+0002    | pop
+0003    3 constant      1 2
+0005    | get_global    2 \"print\"
+0007    | get_local     1
+0009    | call          1
+0011    | pop
+0012    | pop
+"
+    ));
+
+    // A name can be declared again in an inner block but not in the same
+    // one, which includes the parameters for the body of a function.
+    let source = "function f(a, b) {
+    var c = a;
+    { var c = b; }
+    var c = 3;
+    var b;
+}";
+    assert_eq!(
+        errors(source),
+        vec![
+            "test:4:9: c is already declared in this block",
+            "test:5:9: b is already declared in this block",
+        ]
+    );
+}
+
+#[test]
+fn test_shared_strings() {
+    let source = "var greeting = \"hello\";
+function f() { return \"hello\"; }
+function g() { return {hello: f}; }";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let string = |chunk: &Chunk| match chunk.constants().iter().find_map(|c| match c {
+        Constant::String(s) if &s[..] == "hello" => Some(s.clone()),
+        _ => None,
+    }) {
+        Some(s) => s,
+        None => panic!("no string in {}", chunk.name()),
+    };
+    let chunks = |function: &Function| {
+        let mut chunks = vec![function.chunk.clone()];
+        for constant in function.chunk.constants() {
+            if let Constant::Function(function) = constant {
+                chunks.push(function.chunk.clone());
+            }
+        }
+        chunks
+    };
+
+    // Every chunk that uses a string has it in its own constants, but all of
+    // them point at the same copy, in memory and in a compiled file.
+    let hello = string(&function.chunk);
+    for chunk in chunks(&function) {
+        assert!(Arc::ptr_eq(&string(&chunk), &hello));
+    }
+    let bytes = function.chunk.serialize();
+    assert_eq!(bytes.windows(5).filter(|w| w == b"hello").count(), 1);
+    let loaded = Chunk::deserialize("test.atc", &bytes).unwrap();
+    assert_eq!(loaded, function.chunk);
+    let function = Function {
+        chunk: loaded,
+        ..function
+    };
+    let hello = string(&function.chunk);
+    for chunk in chunks(&function) {
+        assert!(Arc::ptr_eq(&string(&chunk), &hello));
+    }
+}
+
+#[test]
+fn test_arity_checks() {
+    let source = "function move(x, y, speed = 1) {}
+function log(level, ...parts) {}
+function tick() {
+    function step(dt) {}
+    step();
+    move(1);
+    log();
+    return tick(0);
+}
+move(1, 2, 3, 4);
+log(1, 2, 3);";
+    assert_eq!(
+        errors(source),
+        vec![
+            "test:5:5: step expects 1 argument but got 0",
+            "test:6:5: move expects 2 to 3 arguments but got 1",
+            "test:7:5: log expects at least 1 argument but got 0",
+            "test:8:12: tick expects 0 arguments but got 1",
+            "test:10:1: move expects 2 to 3 arguments but got 4",
+        ]
+    );
+
+    // Anything that could put another value in the variable turns the
+    // check off, and so do decorators and parameters of the same name.
+    let source = "function f(a) {}
+function g(a) {}
+function h(a) {}
+@wrap function w(a) {}
+g = print;
+var h = 1;
+function k(f) { f(); }
+f(1);
+g();
+h();
+w();";
+    assert_eq!(errors(source), Vec::<String>::new());
+}
+
+#[test]
+fn test_compiler_options() {
+    let options = |f: fn(&mut CompilerOptions)| {
+        let mut options = CompilerOptions::default();
+        f(&mut options);
+        options
+    };
+    let compile = |source: &str, options: &CompilerOptions| {
+        let program = parse_program("test", source).unwrap();
+        match compile_with_options(&program, options) {
+            Ok(Compiled::Stack(function)) => Ok(function),
+            Ok(Compiled::Register(_)) => panic!("expected stack code"),
+            Err(errors) => Err(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()),
+        }
+    };
+
+    // Without debug info every chunk has a single span for all of its code.
+    let source = "var x = 1;\nfunction f(a) {\n    return a +\n        x;\n}";
+    let full = compile(source, &CompilerOptions::default()).unwrap();
+    let stripped = compile(source, &options(|o| o.debug_info = false)).unwrap();
+    assert_eq!(full.chunk.code(), stripped.chunk.code());
+    let f = |function: &Function| {
+        let constants = function.chunk.constants().iter();
+        let mut f = constants.filter_map(|c| match c {
+            Constant::Function(f) => Some(f.chunk.clone()),
+            _ => None,
+        });
+        f.next().unwrap()
+    };
+    assert!(f(&full).span_runs() > 1);
+    assert_eq!(f(&stripped).span_runs(), 1);
+    assert_eq!(f(&stripped).span(0), Span::default());
+    assert!(stripped.chunk.serialize().len() < full.chunk.serialize().len());
+    assert!(f(&full).symbols().is_some());
+    assert_eq!(f(&stripped).symbols(), None);
+
+    // Strict mode turns code that can never run into an error, whether or
+    // not it would have been optimized away.
+    let source = "function f() {\n    return 1;\n    print(2);\n}";
+    assert!(compile(source, &CompilerOptions::default()).is_ok());
+    for opt_level in [OptLevel::None, OptLevel::Basic].iter() {
+        let strict = CompilerOptions {
+            opt_level: *opt_level,
+            strict: true,
+            ..CompilerOptions::default()
+        };
+        assert_eq!(
+            compile(source, &strict),
+            Err(vec![String::from("test:3:5: unreachable code")])
+        );
+    }
+
+    // Gated modules are reported unless the host turns them on.
+    assert_eq!(
+        compile("import fs;", &CompilerOptions::default()),
+        Err(vec![String::from("test:1:8: the fs module is not enabled")])
+    );
+    assert!(compile("import fs;", &options(|o| o.features.fs = true)).is_ok());
+
+    let program = parse_program("test", "var x = 1;").unwrap();
+    let register = options(|o| o.backend = Backend::Register);
+    assert!(matches!(
+        compile_with_options(&program, &register),
+        Ok(Compiled::Register(_))
+    ));
+}
+
+#[test]
+fn test_debug_symbols() {
+    let source = "function f(health) {
+    var armor = 2;
+    { var bonus = 1; health += bonus; }
+    { var shield = 3; }
+    return function() { return health + armor; };
+}";
+    let functions = |opt_level| {
+        let program = parse_program("test", source).unwrap();
+        let function = compile_with(&program, opt_level).unwrap();
+        let first_function = |chunk: &Chunk| {
+            let mut constants = chunk.constants().iter();
+            match constants.find(|c| matches!(c, Constant::Function(_))) {
+                Some(Constant::Function(f)) => (**f).clone(),
+                _ => panic!("expected a function"),
+            }
+        };
+        let f = first_function(&function.chunk);
+        let lambda = first_function(&f.chunk);
+        (f.chunk.symbols().unwrap().clone(), lambda)
+    };
+    let local = |name: &str, slot, start, end| LocalSymbol {
+        name: String::from(name),
+        slot,
+        start,
+        end,
+    };
+
+    // Each local lasts from where its value is pushed until it is popped,
+    // so the blocks that share slot 3 are told apart by the offset.
+    let (symbols, lambda) = functions(OptLevel::None);
+    assert_eq!(
+        symbols.locals,
+        vec![
+            local("health", 1, 0, 21),
+            local("armor", 2, 2, 21),
+            local("bonus", 3, 4, 12),
+            local("shield", 3, 15, 15),
+        ]
+    );
+    assert_eq!(symbols.local(3, 6), Some("bonus"));
+    assert_eq!(symbols.local(3, 12), None);
+    let names: Vec<&str> = symbols.locals_at(8).iter().map(|l| &l.name[..]).collect();
+    assert_eq!(names, vec!["health", "armor", "bonus"]);
+    let upvalues = lambda.chunk.symbols().unwrap();
+    assert_eq!(upvalues.upvalue(0), Some("health"));
+    assert_eq!(upvalues.upvalue(1), Some("armor"));
+
+    // The offsets move along with the code when optimizing takes out the
+    // constant that was popped straight away.
+    let (symbols, _) = functions(OptLevel::Basic);
+    assert_eq!(symbols.locals[2], local("bonus", 3, 4, 12));
+    assert_eq!(symbols.locals[3], local("shield", 3, 13, 13));
+    assert_eq!(symbols.locals[0], local("health", 1, 0, 18));
+}
+
+#[test]
+fn test_inlining() {
+    // Inlined code has no frame or lines of its own, so only code compiled
+    // without debug info is inlined.
+    let compiled = |source: &str, opt_level: OptLevel| {
+        let program = parse_program("test", source).unwrap();
+        let options = CompilerOptions {
+            opt_level,
+            debug_info: false,
+            ..CompilerOptions::default()
+        };
+        match compile_with_options(&program, &options) {
+            Ok(Compiled::Stack(function)) => disassemble(&function.chunk),
+            _ => panic!("expected stack code"),
+        }
+    };
+    let source = "function health(e) { return e.health; }
+function scaled(x) { return x * 2 + 1; }
+function f(player) { return health(player) + scaled(3); }";
+    assert!(self::compiled(source, OptLevel::Basic).contains("| call          1\n"));
+    // The inlined constants are folded too.
+    assert!(compiled(source, OptLevel::Basic).contains(
+        "== f ==
+0000    0 get_local     1
+0002    | get_property  0 \"health\"
+0004    | constant      1 7
+0006    | add
+0007    | return
+"
+    ));
+    assert!(compiled(source, OptLevel::None).contains("| call          1\n"));
+
+    // Calls stay when inlining could change what the script does: before
+    // the declaration, with an argument that does work of its own, with a
+    // function that could be replaced and with one that is too big.
+    let source = "var early = health(hero);
+function health(e) { return e.health; }
+function big(a) { return a + a + a + a + a + a + a; }
+function swapped(a) { return a; }
+function hidden(a) { return a; }
+function g(hidden) { return hidden(1); }
+swapped = big;
+health(hero.pet);
+big(1);
+swapped(1);";
+    let text = compiled(source, OptLevel::Basic);
+    let test = &text[..text.find("\n\n").unwrap()];
+    assert_eq!(test.matches("| call ").count(), 4);
+    assert!(text.contains("== g ==\n0000    0 get_local     1\n0002    | constant      0 1\n"));
+
+    // A function can be called before a function declared after it, so the
+    // call in its body is left for the runtime to report too.
+    let source = "function g() { return f(1); }
+print(g());
+function f(x) { return x + 1; }
+function h() { return f(2); }";
+    let text = compiled(source, OptLevel::Basic);
+    let g = &text[text.find("== g ==").unwrap()..];
+    assert!(g[..g.find("\n\n").unwrap()].contains("| tail_call "));
+    let h = &text[text.find("== h ==").unwrap()..];
+    assert!(!h.contains("call"));
+}
+
+#[test]
+fn test_compile_errors() {
+    assert_eq!(
+        errors(
+            "class A {}
+try { x(); } catch (e) {}
+f(a: 1);
+print([a, b] = c);"
+        ),
+        vec![
+            "test:3:3: named arguments are not supported by the compiler yet",
+            "test:4:7: destructuring assignments can only be used as statements",
+        ]
+    );
+}
+
+#[test]
+fn test_opt_levels() {
+    let source = "var x = -(2 * 3);";
+    assert_eq!(
+        compiled(source, OptLevel::None),
+        "== test ==
+0000    1 constant      0 2
+0002    | constant      1 3
+0004    | multiply
+0005    | negate
+0006    | define_slot   0 x
+0008    | null
+0009    | return
+"
+    );
+    assert_eq!(
+        compiled(source, OptLevel::Basic),
+        "== test ==
+0000    1 constant      0 -6
+0002    | define_slot   0 x
+0004    | null
+0005    | return
+"
+    );
+}
+
+#[test]
+fn test_dead_code() {
+    let source = "if (1 > 2) { print(1); }\nfunction f() { return 1; print(2); }";
+    assert_eq!(
+        compiled(source, OptLevel::Basic),
+        "== test ==
+0000    2 constant      0 <function f>
+0002    | define_slot   0 f
+0004    | null
+0005    | return
+
+== f ==
+0000    2 constant      0 1
+0002    | return
+0003    | null
+0004    | return
+"
+    );
+}
+
+#[test]
+fn test_register_backend() {
+    let source = "function f(a, b) {
+    var t = a;
+    while (t < b) t += 1;
+    return g(t, [a, b]);
+}";
+    // Every push writes the register at the depth of the stack.
+    assert_eq!(
+        registers(source, OptLevel::None),
+        "== test ==
+0000    1 function      r1, 0 <function f>
+0001    | define_slot   0 f, r1
+0002    | null          r1
+0003    | return        r1
+
+== f ==
+0000    2 move          r3, r1
+0001    3 move          r4, r3
+0002    | move          r5, r2
+0003    | less          r4, r4, r5
+0004    | jump_if_false r4 -> 0010
+0005    | move          r4, r3
+0006    | constant      r5, 0 1
+0007    | add           r4, r4, r5
+0008    | move          r3, r4
+0009    | jump          -> 0001
+0010    4 get_global    r4, 1 \"g\"
+0011    | move          r5, r3
+0012    | move          r6, r1
+0013    | move          r7, r2
+0014    | list          r6, r6, 2
+0015    | tail_call     r4, 2
+"
+    );
+    // Optimizing reads locals where they are and writes results straight
+    // to them, and loads the constant once before the loop. The arguments
+    // of a call have to stay next to each other.
+    assert_eq!(
+        registers(source, OptLevel::Basic),
+        "== test ==
+0000    1 function      r1, 0 <function f>
+0001    | define_slot   0 f, r1
+0002    | null          r1
+0003    | return        r1
+
+== f ==
+0000    2 move          r3, r1
+0001    3 constant      r8, 0 1
+0002    | less          r4, r3, r2
+0003    | jump_if_false r4 -> 0006
+0004    | add           r3, r3, r8
+0005    | jump          -> 0002
+0006    4 get_global    r4, 1 \"g\"
+0007    | move          r5, r3
+0008    | move          r6, r1
+0009    | move          r7, r2
+0010    | list          r6, r6, 2
+0011    | tail_call     r4, 2
+"
+    );
+}
+
+#[test]
+fn test_register_assignments() {
+    // Setting a field leaves the value where the object was, which is only
+    // kept when something reads it. The copy in q is read as p instead.
+    let source = "function f(p) {\n    p.x = p.y = 1;\n    var q = p;\n    q[0] = -q[1];\n}";
+    let program = parse_program("test", source).unwrap();
+    let function = match compile_to(&program, OptLevel::Basic, Backend::Register) {
+        Ok(Compiled::Register(function)) => function,
+        _ => panic!("expected register code"),
+    };
+    let f = &function.functions[0];
+    assert_eq!((f.arity, f.registers), (1, 7));
+    assert_eq!(
+        register::disassemble(f),
+        "== f ==
+0000    2 constant      r4, 0 1
+0001    | set_property  r1, 1 \"y\", r4
+0002    | set_property  r1, 2 \"x\", r4
+0003    4 constant      r4, 3 0
+0004    | constant      r6, 0 1
+0005    | get_index     r5, r1, r6
+0006    | negate        r5, r5
+0007    | set_index     r1, r4, r5
+0008    1 null          r3
+0009    | return        r3
+"
+    );
+}
+
+#[test]
+fn test_loop_optimizations() {
+    let source = "function f(n) {
+    var s = 0;
+    var i = 0;
+    while (i < n) {
+        var j = 0;
+        while (j < 4) {
+            s = s + i * 3 + j * 8;
+            j = j + 1;
+        }
+        i = i + 1;
+    }
+    return s;
+}";
+    // The constants are loaded once before the outer loop, and both
+    // products become counters that are stepped along with i and j.
+    let program = parse_program("test", source).unwrap();
+    let function = match compile_to(&program, OptLevel::Basic, Backend::Register) {
+        Ok(Compiled::Register(function)) => function,
+        _ => panic!("expected register code"),
+    };
+    assert_eq!(
+        register::disassemble(&function.functions[0]),
+        "== f ==
+0000    2 constant      r2, 0 0
+0001    3 constant      r3, 0 0
+0002    5 constant      r8, 0 0
+0003    | constant      r9, 1 4
+0004    | constant      r10, 2 3
+0005    | constant      r11, 3 8
+0006    | constant      r12, 4 1
+0007    7 multiply      r13, r3, r10
+0008    4 less          r4, r3, r1
+0009    | jump_if_false r4 -> 0022
+0010    5 move          r4, r8
+0011    7 multiply      r14, r8, r11
+0012    6 less          r5, r4, r9
+0013    | jump_if_false r5 -> 0019
+0014    7 add           r5, r2, r13
+0015    | add           r2, r5, r14
+0016    8 add           r4, r4, r12
+0017    | add           r14, r14, r11
+0018    6 jump          -> 0012
+0019   10 add           r3, r3, r12
+0020    | add           r13, r13, r10
+0021    4 jump          -> 0008
+0022   12 return        r2
+"
+    );
+
+    // A counter that does not step by an integer is left alone, since its
+    // products could round differently from the sums.
+    let source = "function f(xs) {
+    var i = 0;
+    while (i < 4) {
+        xs[i * 2] = 0;
+        i = i + 0.5;
+    }
+}";
+    let program = parse_program("test", source).unwrap();
+    let function = match compile_to(&program, OptLevel::Basic, Backend::Register) {
+        Ok(Compiled::Register(function)) => function,
+        _ => panic!("expected register code"),
+    };
+    assert!(register::disassemble(&function.functions[0]).contains("multiply      r4, r2, r7"));
+}
+
+#[test]
+fn test_global_slots() {
+    // Declared globals get a slot, in the order they are declared, which
+    // functions use as well. Names that are never declared are left for
+    // the runtime to find, like the ones the host provides.
+    let source = "var [a, b] = pair;
+function f() { a = b + late; return f; }
+var a = 1;
+late = 2;";
+    let program = parse_program("test", source).unwrap();
+    let function = compile_with(&program, OptLevel::None).unwrap();
+    assert_eq!(function.chunk.globals(), ["a", "b", "f"]);
+    assert_eq!(
+        disassemble(&function.chunk),
+        "== test ==
+0000    1 get_global    0 \"pair\"
+0002    | get_local     1
+0004    | constant      1 0
+0006    | get_index
+0007    | define_slot   0 a
+0009    | get_local     1
+0011    | constant      2 1
+0013    | get_index
+0014    | define_slot   1 b
+0016    | pop
+0017    2 constant      3 <function f>
+0019    | define_slot   2 f
+0021    3 constant      2 1
+0023    | define_slot   0 a
+0025    4 constant      4 2
+0027    | set_global    5 \"late\"
+0029    | pop
+0030    | null
+0031    | return
+
+== f ==
+0000    2 get_slot      1 b
+0002    | get_global    0 \"late\"
+0004    | add
+0005    | set_slot      0 a
+0007    | pop
+0008    | get_slot      2 f
+0010    | return
+0011    | null
+0012    | return
+"
+    );
+    let bytes = function.chunk.serialize();
+    assert_eq!(Chunk::deserialize("test.atc", &bytes), Ok(function.chunk));
+}
+
+#[test]
+fn test_switch() {
+    // Dense whole numbers index straight into the table, and the hole at 3
+    // goes to the default along with anything past the end.
+    let source = "match (x) {
+    case 1: a();
+    case 2, 4: b();
+    case 5: c();
+    default: d();
+}";
+    assert_eq!(
+        compiled(source, OptLevel::Basic),
+        "== test ==
+0000    1 get_global    0 \"x\"
+0002    | switch        1 <cases 1..6>
+0004    | jump          15 -> 22
+0007    | jump          20 -> 30
+0010    | jump          33 -> 46
+0013    | jump          14 -> 30
+0016    | jump          19 -> 38
+0019    | jump          24 -> 46
+0022    2 get_global    2 \"a\"
+0024    | call          0
+0026    | pop
+0027    | jump          21 -> 51
+0030    3 get_global    3 \"b\"
+0032    | call          0
+0034    | pop
+0035    | jump          13 -> 51
+0038    4 get_global    4 \"c\"
+0040    | call          0
+0042    | pop
+0043    | jump          5 -> 51
+0046    5 get_global    5 \"d\"
+0048    | call          0
+0050    | pop
+0051    1 null
+0052    | return
+"
+    );
+    // Without optimizations every case is still compared in turn.
+    assert!(!compiled(source, OptLevel::None).contains("switch"));
+
+    // Anything else is sorted for a binary search, and a value that comes
+    // up twice belongs to the first arm.
+    let source = "match (cmd) {
+    case \"go\", \"run\": go();
+    case 100, -5: stop();
+    case \"go\": never();
+}";
+    assert_eq!(
+        compiled(source, OptLevel::Basic),
+        "== test ==
+0000    1 get_global    0 \"cmd\"
+0002    | switch        1 <cases -5, 100, \"go\", \"run\">
+0004    | jump          20 -> 27
+0007    | jump          17 -> 27
+0010    | jump          6 -> 19
+0013    | jump          3 -> 19
+0016    | jump          21 -> 40
+0019    2 get_global    2 \"go\"
+0021    | call          0
+0023    | pop
+0024    | jump          13 -> 40
+0027    3 get_global    3 \"stop\"
+0029    | call          0
+0031    | pop
+0032    | jump          5 -> 40
+0035    4 get_global    4 \"never\"
+0037    | call          0
+0039    | pop
+0040    1 null
+0041    | return
+"
+    );
+
+    // Too few cases, or cases that aren't numbers or strings, are compared.
+    let small = "match (x) { case 1: a(); case 2: b(); case 3: c(); }";
+    assert!(!compiled(small, OptLevel::Basic).contains("switch"));
+    let mixed = "match (x) { case 1: a(); case 2: b(); case 3: c(); case true: d(); }";
+    assert!(!compiled(mixed, OptLevel::Basic).contains("switch"));
+
+    let registers = registers(source, OptLevel::Basic);
+    assert!(registers.contains("switch        r1, 1 <cases -5, 100, \"go\", \"run\">\n"));
+}
+
+#[test]
+fn test_long_switch() {
+    // One arm too long for a two byte jump makes every jump in the table
+    // wide, so that they stay the same width.
+    let source = format!(
+        "match (x) {{\n    case 1: a();\n    case 2: b();\n    case 3:\n{}    case 4: c();\n}}",
+        "y = y + 1;\n".repeat(9000)
+    );
+    let program = parse_program("test", &source).unwrap();
+    let function = match compile_with(&program, OptLevel::Basic) {
+        Ok(function) => function,
+        Err(errors) => panic!("unexpected compile error: {}", errors[0]),
+    };
+    let text = disassemble(&function.chunk);
+    assert!(text.contains(
+        "0002    | switch        1 <cases 1..5>
+0004    | wide jump     24 -> 34
+0010    | wide jump     29 -> 45
+0016    | wide jump     34 -> 56
+0022    | wide jump     72031 -> 72059
+0028    | wide jump     72030 -> 72064
+"
+    ));
+    let bytes = function.chunk.serialize();
+    assert_eq!(Chunk::deserialize("test.atc", &bytes), Ok(function.chunk));
+}
+
+#[test]
+fn test_long_jumps() {
+    // Every assignment is eight bytes, so the body is too long for a two
+    // byte jump in either direction.
+    let source = format!(
+        "var y = 0;\nwhile (y < 10) {{\n    if (y) {{\n{}    }}\n}}\nprint(y);",
+        "y = y + 1;\n".repeat(9000)
+    );
+    for opt_level in [OptLevel::None, OptLevel::Basic] {
+        let program = parse_program("test", &source).unwrap();
+        let function = match compile_with(&program, opt_level) {
+            Ok(function) => function,
+            Err(errors) => panic!("unexpected compile error: {}", errors[0]),
+        };
+        let text = disassemble(&function.chunk);
+        assert!(text.contains("0009    | wide jump_if_false 72020 -> 72035\n"));
+        assert!(text.contains("0018    | wide jump_if_false 72004 -> 72028\n"));
+        assert!(text.contains("72029    2 wide loop     72031 -> 4\n"));
+        assert!(text.contains("72036 9006 get_global    3 \"print\"\n"));
+
+        let bytes = function.chunk.serialize();
+        assert_eq!(Chunk::deserialize("test.atc", &bytes), Ok(function.chunk));
+    }
+}
+
+fn at(line: u32) -> Span {
+    Span::new(0, 0, line, 1)
+}
+
+fn optimized(chunk: &Chunk) -> String {
+    let mut chunk = chunk.clone();
+    peephole::optimize(&mut chunk);
+    disassemble(&chunk)
+}
+
+#[test]
+fn test_peephole() {
+    let mut chunk = Chunk::new("main");
+    chunk.write_constant(Constant::Int(2), at(1));
+    chunk.write(OpCode::Negate, at(1));
+    chunk.write_constant(Constant::String("unused".into()), at(2));
+    chunk.write(OpCode::Pop, at(2));
+    chunk.write(OpCode::GetLocal, at(3));
+    chunk.write_byte(1, at(3));
+    chunk.write(OpCode::Equal, at(3));
+    chunk.write(OpCode::Not, at(3));
+    chunk.write(OpCode::Jump, at(4));
+    chunk.write_u16(0, at(4));
+    chunk.write(OpCode::Less, at(5));
+    chunk.write(OpCode::Not, at(5));
+    chunk.write(OpCode::Return, at(5));
+    assert_eq!(
+        optimized(&chunk),
+        "== main ==
+0000    1 constant      2 -2
+0002    3 get_local     1
+0004    | not_equal
+0005    5 less
+0006    | not
+0007    | return
+"
+    );
+}
+
+#[test]
+fn test_peephole_jumps() {
+    // The first pop is where a jump lands, so it has to stay, while the
+    // jump to the second null and pop lands on the return instead.
+    let mut chunk = Chunk::new("main");
+    chunk.write(OpCode::True, at(1));
+    chunk.write(OpCode::JumpIfFalse, at(1));
+    chunk.write_u16(4, at(1));
+    chunk.write(OpCode::Jump, at(2));
+    chunk.write_u16(2, at(2));
+    chunk.write(OpCode::Null, at(3));
+    chunk.write(OpCode::Pop, at(3));
+    chunk.write(OpCode::Null, at(4));
+    chunk.write(OpCode::Pop, at(4));
+    chunk.write(OpCode::Return, at(4));
+    assert_eq!(
+        optimized(&chunk),
+        "== main ==
+0000    1 true
+0001    | jump_if_false 4 -> 8
+0004    2 jump          2 -> 9
+0007    3 null
+0008    | pop
+0009    4 return
+"
+    );
+
+    // Loops still land on the instruction they started at.
+    let mut chunk = Chunk::new("main");
+    chunk.write(OpCode::Null, at(1));
+    chunk.write(OpCode::Pop, at(1));
+    chunk.write(OpCode::GetLocal, at(2));
+    chunk.write_byte(0, at(2));
+    chunk.write(OpCode::Loop, at(2));
+    chunk.write_u16(7, at(2));
+    assert_eq!(
+        optimized(&chunk),
+        "== main ==
+0000    2 get_local     0
+0002    | loop          5 -> 0
+"
+    );
+}
+
+#[test]
+fn test_reproducible() {
+    // Compiling the same source with the same options always gives the same
+    // bytes, however the maps the compiler uses along the way are ordered.
+    // Every thread seeds its maps differently.
+    let source = "import fs;
+var names = [\"ada\", \"bob\", \"cy\"];
+function make(step) {
+    var total = 0;
+    return function(n) { total += n * step; return total; };
+}
+function kind(x) {
+    match (x) {
+        case 1: return \"one\";
+        case 2, 3: return \"few\";
+        case 5: return \"five\";
+        case 8: return \"eight\";
+        default: return \"many\";
+    }
+}
+var add = make(2);
+for (i in 0..10) {
+    print(\"{names[i % 3]}: {kind(i)} {add(i)}\");
+}
+print(fs.read(names[0]));";
+    for opt_level in [OptLevel::None, OptLevel::Basic] {
+        let compile = move || {
+            let program = parse_program("test", source).unwrap();
+            let options = CompilerOptions {
+                opt_level,
+                features: Features { fs: true },
+                ..CompilerOptions::default()
+            };
+            let stack = match compile_with_options(&program, &options) {
+                Ok(Compiled::Stack(function)) => function.chunk.serialize(),
+                _ => panic!("expected stack code"),
+            };
+            let options = CompilerOptions {
+                backend: Backend::Register,
+                ..options
+            };
+            let register = match compile_with_options(&program, &options) {
+                Ok(Compiled::Register(function)) => register::disassemble(&function),
+                _ => panic!("expected register code"),
+            };
+            (stack, register)
+        };
+        let first = compile();
+        for _ in 0..8 {
+            assert_eq!(std::thread::spawn(compile).join().unwrap(), first);
+        }
+    }
+}
+
+#[test]
+fn test_exceptions() {
+    // The finally block is copied to the end of the body, to the return and
+    // to the end of the catch block, and runs once more for whatever the
+    // catch block throws before it is thrown on.
+    let source = "function f(x) {
+    try {
+        return g(x);
+    } catch (e) {
+        print(e);
+    } finally {
+        done();
+    }
+}";
+    let text = compiled(source, OptLevel::Basic);
+    assert!(text.contains(
+        "== f ==
+0000    2 try           24 -> 27
+0003    3 get_global    0 \"g\"
+0005    | get_local     1
+0007    | call          1
+0009    | end_try
+0010    7 get_global    1 \"done\"
+0012    | call          0
+0014    | pop
+0015    3 get_local     2
+0017    | return
+0018    2 end_try
+0019    7 get_global    1 \"done\"
+0021    | call          0
+0023    | pop
+0024    2 jump          26 -> 53
+0027    4 try           17 -> 47
+0030    5 get_global    2 \"print\"
+0032    | get_local     2
+0034    | call          1
+0036    | pop
+0037    4 pop
+0038    2 end_try
+0039    7 get_global    1 \"done\"
+0041    | call          0
+0043    | pop
+0044    2 jump          6 -> 53
+0047    7 get_global    1 \"done\"
+0049    | call          0
+0051    | pop
+0052    2 rethrow
+0053    1 null
+0054    | return
+"
+    ));
+}
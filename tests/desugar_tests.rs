@@ -0,0 +1,126 @@
+extern crate atom;
+
+use atom::ast::dump::*;
+use atom::ast::visit::*;
+use atom::ast::*;
+use atom::desugar::*;
+use atom::parse::*;
+use std::collections::HashSet;
+
+fn parse(source: &str) -> Program {
+    match parse_program("test", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("unexpected parse error: {}", errors[0].message()),
+    }
+}
+
+fn lower(source: &str) -> String {
+    let mut program = parse(source);
+    desugar(&mut program);
+    program.to_sexpr()
+}
+
+#[test]
+fn test_for_loops() {
+    assert_eq!(
+        lower("for (e in all) e.update();"),
+        "(block (var $iterator (call (. all iter))) \
+         (while (call (. $iterator has_next)) \
+         (block (var e (call (. $iterator next))) (call (. e update)))))"
+    );
+    // Labels stay on the loop so break and continue find it.
+    assert_eq!(
+        lower("outer: for (i in 0..3) { if (i) continue outer; }"),
+        "(block (var $iterator (call (. (.. 0 3) iter))) \
+         (label outer (while (call (. $iterator has_next)) \
+         (block (var i (call (. $iterator next))) (block (if i (continue outer)))))))"
+    );
+    // Nested loops are desugared too.
+    assert_eq!(
+        lower("for (row in grid) for (cell in row) {}")
+            .matches("while")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_compound_assignment() {
+    assert_eq!(lower("x += 1;"), "(= x (+ x 1))");
+    assert_eq!(
+        lower("hero.hp -= damage;"),
+        "(= (. hero hp) (- (. hero hp) damage))"
+    );
+    assert_eq!(
+        lower("items[i] *= 2;"),
+        "(= (index items i) (* (index items i) 2))"
+    );
+    assert_eq!(lower("print(x |= 4);"), "(call print (= x (| x 4)))");
+}
+
+#[test]
+fn test_compound_assignment_temporaries() {
+    // Parts of the target that could do something are only worked out once.
+    assert_eq!(
+        lower("next().count += 1;"),
+        "(block (var $object (call next)) (= (. $object count) (+ (. $object count) 1)))"
+    );
+    assert_eq!(
+        lower("grid[row()][col()] += 1;"),
+        "(block (var $object (index grid (call row))) (var $index (call col)) \
+         (= (index $object $index) (+ (index $object $index) 1)))"
+    );
+    // There is nowhere to put temporaries in the middle of an expression.
+    assert_eq!(
+        lower("print(a[f()] += 1);"),
+        "(call print (+= (index a (call f)) 1))"
+    );
+}
+
+#[test]
+fn test_interpolation() {
+    // Formatted strings are left for the compiler, which joins the parts
+    // in one go, but the expressions in them are still desugared.
+    assert_eq!(
+        lower("var s = \"hp: {hp}/{max}\";"),
+        "(var s (format \"hp: \" hp \"/\" max))"
+    );
+    assert_eq!(
+        lower("var s = \"{a += 1}\";"),
+        "(var s (format (= a (+ a 1))))"
+    );
+}
+
+struct Ids {
+    ids: Vec<NodeId>,
+}
+
+impl Visitor for Ids {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        self.ids.push(stmt.id);
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.ids.push(expr.id);
+        walk_expr(self, expr);
+    }
+
+    fn visit_identifier(&mut self, ident: &Identifier) {
+        self.ids.push(ident.id);
+    }
+}
+
+#[test]
+fn test_new_nodes_get_new_ids() {
+    let mut program = parse("for (e in all) { e.hp += 1; next().x += \"{e}\"; items[i] -= 1; }");
+    let old_next_id = program.next_id;
+    desugar(&mut program);
+
+    let mut ids = Ids { ids: Vec::new() };
+    ids.visit_program(&program);
+    let unique: HashSet<NodeId> = ids.ids.iter().copied().collect();
+    assert_eq!(unique.len(), ids.ids.len());
+    assert!(ids.ids.iter().all(|&id| id < program.next_id));
+    assert!(program.next_id > old_next_id);
+}
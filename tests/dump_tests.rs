@@ -0,0 +1,105 @@
+extern crate atom;
+
+use atom::ast::dump::*;
+use atom::ast::*;
+use atom::parse::*;
+
+fn sexpr(source: &str) -> String {
+    parse_program("test", source).ok().unwrap().to_sexpr()
+}
+
+#[test]
+fn test_expression_sexpr() {
+    assert_eq!(sexpr("1 + 2 * 3;"), "(+ 1 (* 2 3))");
+    assert_eq!(sexpr("(1 + 2) * 3;"), "(* (+ 1 2) 3)");
+    assert_eq!(sexpr("1 - 2 - 3;"), "(- (- 1 2) 3)");
+    assert_eq!(sexpr("-x == ~y or !z;"), "(or (== (- x) (~ y)) (not z))");
+    assert_eq!(sexpr("a | b ^ c & d;"), "(| a (^ b (& c d)))");
+    assert_eq!(
+        sexpr("f(1.5, 'hi\\n')(true, null);"),
+        "(call (call f 1.5 \"hi\\n\") true null)"
+    );
+}
+
+#[test]
+fn test_statement_sexpr() {
+    assert_eq!(
+        sexpr("var x = 1; var y;\nif (x) { y; } else y;"),
+        "(var x 1)\n(var y)\n(if x (block y) y)"
+    );
+    assert_eq!(
+        sexpr("while (true) { break; continue; } for (i in items) {}"),
+        "(while true (block (break) (continue)))\n(for i items (block))"
+    );
+    assert_eq!(
+        sexpr("function add(a, b) { return a + b; } function f() { return; }"),
+        "(function add (a b) (return (+ a b)))\n(function f () (return))"
+    );
+    assert_eq!(
+        sexpr("class A extends B { init(x) { this; } go() { super.go(); } }"),
+        "(class A B (function init (x) this) (function go () (call (super go))))"
+    );
+}
+
+#[test]
+fn test_json() {
+    let program = parse_program("test", "f(\"a\\\"b\");").ok().unwrap();
+    assert_eq!(
+        program.to_json(),
+        "{\"node\":\"program\",\"name\":\"test\",\"body\":[\
+         {\"node\":\"expression\",\"expr\":\
+         {\"node\":\"call\",\
+         \"callee\":{\"node\":\"variable\",\"name\":\"f\",\
+         \"span\":{\"start\":0,\"end\":1,\"line\":1,\"column\":1}},\
+         \"args\":[{\"node\":\"string\",\"value\":\"a\\\"b\",\
+         \"span\":{\"start\":2,\"end\":8,\"line\":1,\"column\":3}}],\
+         \"names\":[],\
+         \"span\":{\"start\":0,\"end\":9,\"line\":1,\"column\":1}},\
+         \"span\":{\"start\":0,\"end\":10,\"line\":1,\"column\":1}}]}"
+    );
+
+    let program = parse_program("test", "var x;").ok().unwrap();
+    assert_eq!(
+        program.body[0].to_json(),
+        "{\"node\":\"var\",\"name\":{\"name\":\"x\",\
+         \"span\":{\"start\":4,\"end\":5,\"line\":1,\"column\":5}},\
+         \"type\":null,\"init\":null,\"constant\":false,\
+         \"span\":{\"start\":0,\"end\":6,\"line\":1,\"column\":1}}"
+    );
+
+    // Infinities and NaN aren't numbers in JSON, so they are strings.
+    let float = |n: f64| Expr::new(ExprKind::Float(n), Span::new(0, 1, 1, 1)).to_json();
+    assert!(float(2.5).starts_with("{\"node\":\"float\",\"value\":2.5,"));
+    assert!(float(f64::INFINITY).contains("\"value\":\"inf\""));
+    assert!(float(f64::NEG_INFINITY).contains("\"value\":\"-inf\""));
+    assert!(float(f64::NAN).contains("\"value\":\"NaN\""));
+}
+
+#[test]
+fn test_type_annotations() {
+    assert_eq!(
+        sexpr(
+            "function f(a: number, b: map<string, list<number>> = {}): bool { var x: list = []; }"
+        ),
+        "(function f (a:number (= b:map<string,list<number>> (map))):bool (var x:list (list)))"
+    );
+    assert_eq!(
+        sexpr("var f = function(...rest): function {};"),
+        "(var f (lambda (...rest):function))"
+    );
+
+    let program = parse_program("test", "var x: list<string>;").ok().unwrap();
+    assert_eq!(
+        program.body[0].to_json(),
+        "{\"node\":\"var\",\"name\":{\"name\":\"x\",\
+         \"span\":{\"start\":4,\"end\":5,\"line\":1,\"column\":5}},\
+         \"type\":{\"name\":{\"name\":\"list\",\
+         \"span\":{\"start\":7,\"end\":11,\"line\":1,\"column\":8}},\
+         \"args\":[{\"name\":{\"name\":\"string\",\
+         \"span\":{\"start\":12,\"end\":18,\"line\":1,\"column\":13}},\
+         \"args\":[],\"span\":{\"start\":12,\"end\":18,\"line\":1,\"column\":13}}],\
+         \"span\":{\"start\":7,\"end\":19,\"line\":1,\"column\":8}},\
+         \"init\":null,\"constant\":false,\
+         \"span\":{\"start\":0,\"end\":20,\"line\":1,\"column\":1}}"
+    );
+}
@@ -8,3 +8,87 @@ fn test_error() {
     assert_eq!(err.line(), 5);
     assert_eq!(err.column(), 7);
 }
+
+#[test]
+fn test_warning() {
+    let err = atom::error::Error::new("test error", "test file.at", 5, 7);
+    assert_eq!(err.severity(), atom::error::Severity::Error);
+    assert!(!err.is_warning());
+
+    let warning = atom::error::Error::warning("unused variable x", "test file.at", 2, 3);
+    assert_eq!(warning.severity(), atom::error::Severity::Warning);
+    assert!(warning.is_warning());
+    assert_eq!(warning.severity().label(), "warning");
+    assert_eq!(
+        format!("{}", warning),
+        "test file.at:2:3: unused variable x"
+    );
+}
+
+#[test]
+fn test_related() {
+    let err = atom::error::Error::warning("x shadows a parameter", "test file.at", 4, 9);
+    assert!(err.related().is_empty());
+
+    let err = err.with_related("x is declared here", 1, 12);
+    assert_eq!(err.related().len(), 1);
+    assert_eq!(err.related()[0].message(), "x is declared here");
+    assert_eq!(err.related()[0].line(), 1);
+    assert_eq!(err.related()[0].column(), 12);
+    // The related location is not part of the main message.
+    assert_eq!(
+        format!("{}", err),
+        "test file.at:4:9: x shadows a parameter"
+    );
+}
+
+#[test]
+fn test_related_in() {
+    let err = atom::error::Error::new("x is not defined", "main.at", 3, 1);
+    let err = err.with_related("x is used here", 2, 5).with_related_in(
+        "x is exported here",
+        "lib.at",
+        8,
+        1,
+    );
+    assert_eq!(err.related()[0].file_name(), "main.at");
+    assert_eq!(err.related()[1].file_name(), "lib.at");
+    assert_eq!(err.related()[1].line(), 8);
+}
+
+#[test]
+fn test_runtime_error() {
+    use atom::error::{RuntimeError, TraceFrame};
+
+    let frame = |function: Option<&str>, file: &str, line, column| TraceFrame {
+        function: function.map(String::from),
+        file: String::from(file),
+        line,
+        column,
+    };
+    let err = RuntimeError::new(
+        "division by zero",
+        vec![
+            frame(Some("half"), "lib.at", 2, 10),
+            frame(None, "main.at", 4, 7),
+        ],
+    );
+    assert_eq!(err.message(), "division by zero");
+    assert_eq!(err.file_name(), "lib.at");
+    assert_eq!(err.line(), 2);
+    assert_eq!(err.column(), 10);
+    assert_eq!(format!("{}", err), "lib.at:2:10: division by zero");
+    assert_eq!(format!("{}", err.trace()[0]), "in half at lib.at:2:10");
+    assert_eq!(format!("{}", err.trace()[1]), "at main.at:4:7");
+
+    let diagnostic = err.diagnostic();
+    assert_eq!(diagnostic.severity(), atom::error::Severity::Error);
+    assert_eq!(format!("{}", diagnostic), "lib.at:2:10: division by zero");
+    assert_eq!(diagnostic.related().len(), 1);
+    assert_eq!(
+        diagnostic.related()[0].message(),
+        "half was called from here"
+    );
+    assert_eq!(diagnostic.related()[0].file_name(), "main.at");
+    assert_eq!(diagnostic.related()[0].line(), 4);
+}
@@ -0,0 +1,111 @@
+extern crate atom;
+
+use atom::ast::dump::*;
+use atom::ast::*;
+use atom::fold::*;
+use atom::parse::*;
+
+fn fold(source: &str) -> String {
+    let mut program = match parse_program("test", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("unexpected parse error: {}", errors[0].message()),
+    };
+    fold_constants(&mut program);
+    program.to_sexpr()
+}
+
+#[test]
+fn test_arithmetic() {
+    assert_eq!(fold("var day = 2 * 60 * 60;"), "(var day 7200)");
+    assert_eq!(fold("(1 + 2) * 3 - 4 / 2 % 3;"), "7");
+    assert_eq!(fold("-(2 + 3);"), "-5");
+    assert_eq!(fold("1 < 2;"), "true");
+    assert_eq!(fold("3 == 3 and 2 != 2;"), "false");
+    // Only the constant parts of an expression are folded.
+    assert_eq!(fold("x + 2 * 3;"), "(+ x 6)");
+    assert_eq!(fold("1 + 2 + x;"), "(+ 3 x)");
+    assert_eq!(fold("x + 1 + 2;"), "(+ (+ x 1) 2)");
+    // Ints stay ints, and anything with a float in it is a float.
+    assert_eq!(fold("7 / 2;"), "3");
+    assert_eq!(fold("-7 % 2;"), "-1");
+    assert_eq!(fold("7 / 2.0;"), "3.5");
+    assert_eq!(fold("1 + 1.0;"), "2.0");
+    assert_eq!(fold("6 & 3;"), "2");
+    // An int that overflows is left for the runtime to report.
+    assert_eq!(
+        fold("9223372036854775807 + 1;"),
+        "(+ 9223372036854775807 1)"
+    );
+}
+
+#[test]
+fn test_strings() {
+    assert_eq!(fold("'save' + '_' + 'slot';"), "\"save_slot\"");
+    assert_eq!(fold("'a' == 'a';"), "true");
+    assert_eq!(fold("name + '!' + '!';"), "(+ (+ name \"!\") \"!\")");
+    // Mixing types is left for the runtime to decide.
+    assert_eq!(fold("'hp: ' + 10;"), "(+ \"hp: \" 10)");
+}
+
+#[test]
+fn test_interpolation() {
+    assert_eq!(fold("\"{1 + 1} of {4}\";"), "\"2 of 4\"");
+    assert_eq!(fold("\"{'a'}{true}{null}{0.5}\";"), "\"atruenull0.5\"");
+    assert_eq!(fold("\"{n}\";"), "(format n)");
+    // Text next to text is joined, and empty text is dropped.
+    assert_eq!(
+        fold("\"hp: {1 + 2}/{max}{''}!\";"),
+        "(format \"hp: 3/\" max \"!\")"
+    );
+}
+
+#[test]
+fn test_short_circuit() {
+    assert_eq!(fold("false and launch();"), "false");
+    assert_eq!(fold("true or launch();"), "true");
+    assert_eq!(fold("true and launch();"), "(and true (call launch))");
+    assert_eq!(fold("not true;"), "false");
+    assert_eq!(fold("null == null;"), "true");
+    assert_eq!(fold("true ? a : b;"), "a");
+    assert_eq!(fold("1 > 2 ? a : b;"), "b");
+    assert_eq!(fold("x ? 1 + 1 : 2 + 2;"), "(? x 2 4)");
+}
+
+#[test]
+fn test_runtime_errors_are_kept() {
+    // Dividing by zero has to fail when the script runs, not disappear.
+    assert_eq!(fold("1 / 0;"), "(/ 1 0)");
+    assert_eq!(fold("1 % 0;"), "(% 1 0)");
+    assert_eq!(fold("-'a';"), "(- \"a\")");
+    assert_eq!(fold("'a' * 2;"), "(* \"a\" 2)");
+}
+
+#[test]
+fn test_nested_bodies() {
+    assert_eq!(
+        fold("function f() { return 60 * 60; }"),
+        "(function f () (return 3600))"
+    );
+    assert_eq!(
+        fold("var f = function() { return 1 + 1; };"),
+        "(var f (lambda () (return 2)))"
+    );
+    assert_eq!(fold("items[1 + 1];"), "(index items 2)");
+}
+
+#[test]
+fn test_fold_keeps_span() {
+    let mut expr = match parse_program("test", "  2 * 60;")
+        .unwrap()
+        .body
+        .remove(0)
+        .kind
+    {
+        StmtKind::Expression(expr) => expr,
+        _ => panic!("expected an expression statement"),
+    };
+    fold_expression(&mut expr);
+    assert_eq!(expr.kind, ExprKind::Int(120));
+    assert_eq!(expr.span.start, 2);
+    assert_eq!(expr.span.end, 8);
+}
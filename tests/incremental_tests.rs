@@ -0,0 +1,221 @@
+extern crate atom;
+
+use atom::ast::dump::*;
+use atom::ast::*;
+use atom::parse::incremental::*;
+use atom::parse::*;
+
+const SOURCE: &str = "var a = 1;
+function f(x) {
+    return x + a;
+}
+
+// Some state.
+var b = [1, 2];
+class Point { init(x) { this.x = x; } }
+outer: while (a < 10) { a += 1; break outer; }
+";
+
+// Applies the edit both ways and checks that reparsing gives the same tree
+// as parsing the new source from scratch. The new nodes are numbered after
+// the old ones so the ids differ, which is why the dumps are compared.
+fn reparse_matches(source: &str, edit: Edit) -> Vec<usize> {
+    let program = parse_program("test", source).ok().unwrap();
+    let new_source = edit.apply(source).unwrap();
+    let expected = parse_program("test", &new_source[..]).ok().unwrap();
+
+    let result = reparse(&program, source, &edit).ok().unwrap();
+    assert_eq!(result.program.to_json(), expected.to_json());
+
+    // Statements which were carried over keep their ids and the ones which
+    // were parsed again get new ids.
+    let old_ids: Vec<NodeId> = program.body.iter().map(|s| s.id).collect();
+    for (i, stmt) in result.program.body.iter().enumerate() {
+        if result.dirty.contains(&i) {
+            assert!(stmt.id >= program.next_id);
+        } else {
+            assert!(old_ids.contains(&stmt.id));
+        }
+    }
+    assert!(result.program.next_id >= program.next_id);
+    result.dirty
+}
+
+fn offset_of(needle: &str) -> usize {
+    SOURCE.find(needle).unwrap()
+}
+
+#[test]
+fn test_edit_apply() {
+    assert_eq!(
+        Edit::new(4, 5, "b").apply("var a;"),
+        Some(String::from("var b;"))
+    );
+    assert_eq!(
+        Edit::new(6, 6, " x;").apply("var a;"),
+        Some(String::from("var a; x;"))
+    );
+    assert_eq!(
+        Edit::new(0, 4, "").apply("var a;"),
+        Some(String::from("a;"))
+    );
+    assert_eq!(Edit::new(3, 10, "").apply("var a;"), None);
+    assert_eq!(Edit::new(4, 2, "").apply("var a;"), None);
+}
+
+#[test]
+fn test_reparse_inside_statement() {
+    // Changing the body of the function only reparses the function.
+    let at = offset_of("x + a");
+    assert_eq!(
+        reparse_matches(SOURCE, Edit::new(at, at + 5, "x * 2")),
+        vec![1]
+    );
+    // Adding lines moves every statement after the edit down.
+    assert_eq!(
+        reparse_matches(SOURCE, Edit::new(at, at + 5, "\n        x\n        + a")),
+        vec![1]
+    );
+    let at = offset_of("2]");
+    assert_eq!(
+        reparse_matches(SOURCE, Edit::new(at, at + 1, "2, 3")),
+        vec![2]
+    );
+}
+
+#[test]
+fn test_reparse_new_statements() {
+    let at = offset_of("// Some");
+    assert_eq!(
+        reparse_matches(SOURCE, Edit::new(at, at, "var c = f(2);\nvar d;\n")),
+        vec![2, 3]
+    );
+    // Removing a statement only reparses the one that now starts where it
+    // used to be.
+    let start = offset_of("class");
+    let end = offset_of("outer");
+    assert_eq!(reparse_matches(SOURCE, Edit::new(start, end, "")), vec![3]);
+    let end = offset_of("\nouter");
+    assert_eq!(reparse_matches(SOURCE, Edit::new(start, end, "")), vec![]);
+    // Appending to the end of the source.
+    let end = SOURCE.len();
+    assert_eq!(
+        reparse_matches(SOURCE, Edit::new(end, end, "f(b);\n")),
+        vec![5]
+    );
+    assert_eq!(reparse_matches("", Edit::new(0, 0, "x;")), vec![0]);
+}
+
+#[test]
+fn test_reparse_keeps_comments_contained() {
+    // A new line comment swallows the rest of the line, so the statements
+    // after it on the same line are parsed again too.
+    assert_eq!(
+        reparse_matches("a;\nb; c;\nd;\n", Edit::new(3, 3, "// ")),
+        vec![]
+    );
+    assert_eq!(
+        reparse_matches("a;\nb; c; e;\nd;\n", Edit::new(6, 6, "f(); // ")),
+        vec![2]
+    );
+    // A block comment that is closed after the region needs the whole
+    // source to be parsed again.
+    assert_eq!(
+        reparse_matches("a;\nb;\nc; /* note */\n", Edit::new(3, 3, "/* ")),
+        vec![0]
+    );
+}
+
+#[test]
+fn test_reparse_across_statements() {
+    // An else added after an if belongs to the statement before the edit.
+    assert_eq!(
+        reparse_matches("if (a) b();\nc();\n", Edit::new(11, 11, " else d();")),
+        vec![0]
+    );
+    assert_eq!(
+        reparse_matches("if (a) b();\nc();\n", Edit::new(12, 12, "else d();\n")),
+        vec![0, 1]
+    );
+    // Merging two statements into one.
+    assert_eq!(
+        reparse_matches("a = 1;\nb = 2;\nc = 3;\n", Edit::new(5, 11, " +")),
+        vec![0]
+    );
+}
+
+#[test]
+fn test_reparse_errors() {
+    let program = parse_program("test", SOURCE).ok().unwrap();
+    let at = offset_of("x + a");
+    let errors = reparse(&program, SOURCE, &Edit::new(at, at + 5, "x +")).unwrap_err();
+    assert_eq!(errors[0].message(), "expected expression but found ';'");
+    assert_eq!(errors[0].line(), 3);
+
+    // Breaking the start of a comment turns the rest of it into code.
+    let source = "a;\n/* note\n */ b;\n";
+    let program = parse_program("test", source).ok().unwrap();
+    assert!(reparse(&program, source, &Edit::new(3, 4, "")).is_err());
+
+    let errors = reparse(&program, SOURCE, &Edit::new(0, SOURCE.len() + 1, "")).unwrap_err();
+    assert_eq!(errors[0].message(), "edit is outside of the source");
+}
+
+#[test]
+fn test_reparse_spans() {
+    let program = parse_program("test", "a;\nb(c);\n").ok().unwrap();
+    let result = reparse(&program, "a;\nb(c);\n", &Edit::new(0, 1, "first\n"))
+        .ok()
+        .unwrap();
+    assert_eq!(result.dirty, vec![0]);
+    let moved = &result.program.body[1];
+    assert_eq!(moved.span, Span::new(8, 13, 3, 1));
+    match &moved.kind {
+        StmtKind::Expression(expr) => match &expr.kind {
+            ExprKind::Call(call) => {
+                assert_eq!(call.span.start, 8);
+                assert_eq!(call.args[0].span, Span::new(10, 11, 3, 3));
+            }
+            _ => panic!("expected a call"),
+        },
+        _ => panic!("expected an expression statement"),
+    }
+}
+
+#[test]
+fn test_reparse_without_semicolons() {
+    // A statement without a semicolon before the edit is parsed again since
+    // the edit could continue it.
+    assert_eq!(
+        reparse_matches("a = b\nc()\n", Edit::new(6, 9, "(c)")),
+        vec![0]
+    );
+    assert_eq!(
+        reparse_matches("a = b;\nc()\n", Edit::new(7, 10, "(c)")),
+        vec![1]
+    );
+    // The reused statements would carry on from the end of the region.
+    assert_eq!(
+        reparse_matches("a = b;\nc = d;\n(e)\n", Edit::new(12, 13, "")),
+        vec![0, 1]
+    );
+    assert_eq!(
+        reparse_matches("a = b;\nc = d;\ne()\n", Edit::new(12, 13, "")),
+        vec![1]
+    );
+}
+
+#[test]
+fn test_reparse_line_constants() {
+    // `__line` is the line it is on, so a statement using it is parsed
+    // again when the edit moves it to another line.
+    let source = "var a = 1;\nvar b = __line;\nvar c = \"{__line}\";\nvar d = 2;\n";
+    let edit = Edit::new(0, 0, "var z = 0;\n");
+    assert_eq!(reparse_matches(source, edit.clone()), vec![0, 1, 2, 3]);
+    let program = parse_program("test", source).ok().unwrap();
+    let result = reparse(&program, source, &edit).ok().unwrap();
+    assert_eq!(result.program.body[2].to_sexpr(), "(var b 3)");
+
+    // Edits that keep the lines where they were leave them alone.
+    assert_eq!(reparse_matches(source, Edit::new(8, 9, "5")), vec![0]);
+}
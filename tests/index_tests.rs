@@ -0,0 +1,106 @@
+extern crate atom;
+
+use atom::ast::index::*;
+use atom::ast::*;
+use atom::parse::*;
+
+const SOURCE: &str = "var total = 0;
+function add(amount, scale = 2) {
+    total += amount * scale;
+}
+outer: for (x in [1, 2]) { break outer; }
+";
+
+fn at(index: &SpanIndex, needle: &str) -> (NodeKind, String) {
+    let offset = SOURCE.find(needle).unwrap();
+    let node = index.find_node_at(offset).unwrap();
+    (
+        node.kind,
+        String::from(&SOURCE[node.span.start..node.span.end]),
+    )
+}
+
+fn node(kind: NodeKind, text: &str) -> (NodeKind, String) {
+    (kind, String::from(text))
+}
+
+#[test]
+fn test_find_node_at() {
+    let program = parse_program("test", SOURCE).ok().unwrap();
+    let index = SpanIndex::new(&program);
+
+    assert_eq!(at(&index, "total ="), node(NodeKind::Identifier, "total"));
+    assert_eq!(at(&index, "0;"), node(NodeKind::Expr, "0"));
+    assert_eq!(at(&index, "scale ="), node(NodeKind::Identifier, "scale"));
+    assert_eq!(at(&index, "2)"), node(NodeKind::Expr, "2"));
+    assert_eq!(at(&index, "amount *"), node(NodeKind::Expr, "amount"));
+    assert_eq!(
+        at(&index, "* scale"),
+        node(NodeKind::Expr, "amount * scale")
+    );
+    assert_eq!(at(&index, "outer;"), node(NodeKind::Identifier, "outer"));
+    assert_eq!(at(&index, "x in"), node(NodeKind::Identifier, "x"));
+    assert_eq!(at(&index, "outer:"), node(NodeKind::Identifier, "outer"));
+
+    // Positions between the parts of a node find the node itself.
+    assert_eq!(
+        at(&index, "{\n    total"),
+        node(
+            NodeKind::Function,
+            "function add(amount, scale = 2) {\n    total += amount * scale;\n}"
+        )
+    );
+    assert_eq!(
+        at(&index, ";\nfunction"),
+        node(NodeKind::Stmt, "var total = 0;")
+    );
+
+    // Nothing is found outside of every statement.
+    assert_eq!(index.find_node_at(SOURCE.len() - 1), None);
+    assert_eq!(index.find_node_at(SOURCE.len() + 10), None);
+}
+
+#[test]
+fn test_find_innermost() {
+    let program = parse_program("test", SOURCE).ok().unwrap();
+    let index = SpanIndex::new(&program);
+    let span = |text: &str| {
+        let start = SOURCE.find(text).unwrap();
+        Span::new(start, start + text.len(), 1, 1)
+    };
+    let innermost = |text: &str| {
+        let node = index.find_innermost(span(text)).unwrap();
+        (
+            node.kind,
+            String::from(&SOURCE[node.span.start..node.span.end]),
+        )
+    };
+
+    assert_eq!(
+        innermost("amount * scale"),
+        node(NodeKind::Expr, "amount * scale")
+    );
+    assert_eq!(
+        innermost("+= amount"),
+        node(NodeKind::Expr, "total += amount * scale")
+    );
+    assert_eq!(innermost("1, 2"), node(NodeKind::Expr, "[1, 2]"));
+    assert_eq!(index.find_innermost(span("0;\nfunction")), None);
+
+    // An empty span is inside of a node that ends right where it is.
+    let end = SOURCE.find(" = 0").unwrap();
+    assert_eq!(
+        index
+            .find_innermost(Span::new(end, end, 1, 1))
+            .unwrap()
+            .kind,
+        NodeKind::Identifier
+    );
+
+    // The ids match the tree so side tables can be looked up.
+    let decl = index.find_node_at(SOURCE.find("add").unwrap()).unwrap();
+    match &program.body[1].kind {
+        StmtKind::Function(function) => assert_eq!(decl.id, function.name.id),
+        kind => panic!("expected a function but found {:?}", kind),
+    }
+}
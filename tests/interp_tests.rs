@@ -0,0 +1,404 @@
+extern crate atom;
+
+use atom::interp::*;
+use atom::parse::*;
+use atom::value::Value;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Collects what a script prints.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn run(source: &str) -> Result<String, String> {
+    let program = parse_program("test", source).unwrap();
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    match interpreter.run(&program) {
+        Ok(_) => Ok(String::from_utf8(output.0.borrow().clone()).unwrap()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn printed(source: &str) -> String {
+    match run(source) {
+        Ok(output) => output,
+        Err(error) => panic!("unexpected runtime error: {}", error),
+    }
+}
+
+fn error(source: &str) -> String {
+    match run(source) {
+        Ok(_) => panic!("expected an error"),
+        Err(error) => error,
+    }
+}
+
+#[test]
+fn test_expressions() {
+    assert_eq!(printed("print(1 + 2 * 3, 7 % 4, 1 / 4);"), "7 3 0\n");
+    assert_eq!(
+        printed("print(\"a\" + \"b\", \"a\" < \"b\", 2 >= 3);"),
+        "ab true false\n"
+    );
+    assert_eq!(
+        printed("print(-(1), not 0, not null, ~5, 6 & 3, 6 | 3, 6 ^ 3);"),
+        "-1 false true -6 2 7 5\n"
+    );
+    assert_eq!(
+        printed("print(1 == 1.0, \"1\" == 1, null != false);"),
+        "true false true\n"
+    );
+    // `and` and `or` give back the side that decided the result.
+    assert_eq!(
+        printed("print(0 and \"yes\", null or \"no\", false and x);"),
+        "yes no false\n"
+    );
+    assert_eq!(printed("print(1 > 2 ? \"a\" : \"b\");"), "b\n");
+    assert_eq!(
+        printed("var hp = 3; print(\"hp: {hp}/{hp * 2} {null}\");"),
+        "hp: 3/6 null\n"
+    );
+    assert_eq!(
+        printed("print(str([1, \"a\", {b: null}]), len(\"héllo\"), 0..=3);"),
+        "[1, \"a\", {\"b\": null}] 5 0..=3\n"
+    );
+}
+
+#[test]
+fn test_variables_and_scopes() {
+    let source = "var a = 1;
+{
+    var a = 2;
+    a += 1;
+    print(a);
+}
+print(a);
+var b;
+b = a = 5;
+print(a, b);";
+    assert_eq!(printed(source), "3\n1\n5 5\n");
+}
+
+#[test]
+fn test_control_flow() {
+    let source = "var i = 0;
+while (i < 10) {
+    i += 1;
+    if (i % 2 == 0) continue;
+    if (i > 6) break;
+    print(i);
+}
+do { print(\"once\"); } while (false);
+outer: for (x in 0..3) {
+    for (y in [10, 20, 30]) {
+        if (y == 20) continue outer;
+        if (x == 2) break outer;
+        print(x, y);
+    }
+}
+for (c in \"ab\") print(c);
+for (k in {a: 1, b: 2}) print(k);";
+    assert_eq!(
+        printed(source),
+        "1\n3\n5\nonce\n0 10\n1 10\na\nb\n[\"a\", 1]\n[\"b\", 2]\n"
+    );
+}
+
+#[test]
+fn test_iteration() {
+    let source = "class Pairs {
+    init(xs) { this.xs = xs; }
+    iter() { return this; }
+    has_next() { return len(this.xs) > 1; }
+    next() {
+        var pair = this.xs[0:2];
+        this.xs = this.xs[2:];
+        return pair;
+    }
+}
+for (pair in Pairs([1, 2, 3, 4, 5])) print(pair);
+var it = (0..2).iter();
+print(it.next(), it.next(), it.has_next());";
+    assert_eq!(printed(source), "[1, 2]\n[3, 4]\n0 1 false\n");
+}
+
+#[test]
+fn test_match() {
+    let source = "function kind(x) {
+    match (x) {
+        default: return \"many\";
+        case 1: return \"one\";
+        case 2, 3: return \"few\";
+        case \"x\": return \"letter\";
+    }
+}
+print(kind(1), kind(3), kind(\"x\"), kind(9));";
+    assert_eq!(printed(source), "one few letter many\n");
+}
+
+#[test]
+fn test_functions() {
+    let source = "function fib(n) { return n < 2 ? n : fib(n - 1) + fib(n - 2); }
+function greet(name, greeting = \"hi\", ...rest) { return \"{greeting} {name} {rest}\"; }
+print(fib(15));
+print(greet(\"bo\"), greet(\"al\", null), greet(\"cy\", \"yo\", 1, 2));
+function twice(f) { return function(x) { return f(f(x)); }; }
+@twice
+function inc(x) { return x + 1; }
+print(inc(1));
+var apply = function(f, x) { return f(x); };
+print(apply(function(x) { return x * 10; }, 4));";
+    assert_eq!(
+        printed(source),
+        "610\nhi bo [] hi al [] yo cy [1, 2]\n3\n40\n"
+    );
+}
+
+#[test]
+fn test_closures() {
+    // Closures share the variables they capture and keep them alive.
+    let source = "function counter() {
+    var count = 0;
+    return [function() { count += 1; return count; }, function() { return count; }];
+}
+var [inc, get] = counter();
+inc();
+inc();
+print(get());
+var fs = [];
+for (i in 0..3) fs = fs + [function() { return i; }];
+print(fs[0](), fs[2]());";
+    assert_eq!(printed(source), "2\n0 2\n");
+}
+
+#[test]
+fn test_lists_and_maps() {
+    let source = "var xs = [1, 2, 3];
+xs[0] = 10;
+xs[1] += 5;
+print(xs, xs[1:], xs[:1], xs[5:], len(xs));
+var m = {hp: 10, [1 + 1]: \"two\"};
+m.hp -= 3;
+m[\"mp\"] = 4;
+print(m, m.missing, m[2]);
+var {hp, mp: magic} = m;
+var [a, [b, c]] = [1, [2, 3]];
+print(hp, magic, a, b, c);
+a, b = b, a;
+print(a, b);
+print(\"hello\"[1], \"hello\"[1:3]);";
+    assert_eq!(
+        printed(source),
+        "[10, 7, 3] [7, 3] [10] [] 3\n{\"hp\": 7, 2: \"two\", \"mp\": 4} null two\n7 4 1 2 3\n2 1\ne el\n"
+    );
+}
+
+#[test]
+fn test_classes() {
+    let source = "class Point {
+    init(x, y) { this.x = x; this.y = y; }
+    get length() { return this.x + this.y; }
+    set both(n) { this.x = n; this.y = n; }
+    moved(by) { return Point(this.x + by, this.y + by); }
+}
+class Named extends Point {
+    init(name) { super.init(0, 0); this.name = name; }
+    moved(by) { return super.moved(by * 2); }
+}
+var p = Named(\"origin\");
+p.both = 2;
+print(p, p.length);
+print(p.moved(1));";
+    assert_eq!(
+        printed(source),
+        "Named {x: 2, y: 2, name: \"origin\"} 4\nPoint {x: 4, y: 4}\n"
+    );
+
+    // Methods keep their instance when they are passed around.
+    let source = "class Box {
+    init(value) { this.value = value; }
+    get() { return this.value; }
+}
+var get = Box(1).get;
+print(get(), [Box(2), Box(3)][1].get());";
+    assert_eq!(printed(source), "1 3\n");
+}
+
+#[test]
+fn test_operator_overloading() {
+    let source = "class Money {
+    init(cents) { this.cents = cents; }
+    __add(other) { return Money(this.cents + other.cents); }
+    __eq(other) { return this.cents == other.cents; }
+    __neg() { return Money(-this.cents); }
+}
+var total = Money(150) + Money(25);
+print(total.cents, -total == Money(-175), total != Money(175), total == 175);";
+    assert_eq!(printed(source), "175 true false false\n");
+}
+
+#[test]
+fn test_results() {
+    let program = parse_program("test", "var x = 2;").unwrap();
+    let mut interpreter = Interpreter::with_output(Box::new(Output::default()));
+    assert_eq!(interpreter.run(&program), Ok(Value::Null));
+    assert_eq!(interpreter.global("x"), Some(&Value::Int(2)));
+
+    // Globals carry over to the next program.
+    let program = parse_program("next", "x += 1;").unwrap();
+    interpreter.run(&program).unwrap();
+    assert_eq!(interpreter.global("x"), Some(&Value::Int(3)));
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(
+        error("print(1 + \"a\");"),
+        "test:1:7: cannot use + with int and string"
+    );
+    assert_eq!(error("var x = 1;\nprint(y);"), "test:2:7: y is not defined");
+    assert_eq!(error("z = 1;"), "test:1:1: z is not defined");
+    assert_eq!(error("print(1 / 0);"), "test:1:7: division by zero");
+    assert_eq!(error("var f = 3;\nf();"), "test:2:1: cannot call int");
+    assert_eq!(
+        error("function f(a, b = 1) {}\nf();"),
+        "test:2:1: f expects 1 to 2 arguments but got 0"
+    );
+    assert_eq!(error("len(1);"), "test:1:1: cannot take the length of int");
+    assert_eq!(
+        error("[1, 2][2];"),
+        "test:1:1: list index 2 is out of range"
+    );
+    assert_eq!(
+        error("[1][\"a\"];"),
+        "test:1:1: cannot index list with string"
+    );
+    assert_eq!(
+        error("for (x in 5) {}"),
+        "test:1:11: cannot iterate over int"
+    );
+    assert_eq!(
+        error("var n = 1; n.x = 2;"),
+        "test:1:12: cannot set property x of int"
+    );
+    assert_eq!(
+        error("class A {}\nA(1);"),
+        "test:2:1: A expects 0 arguments but got 1"
+    );
+    assert_eq!(error("class A {}\nA().x;"), "test:2:5: A has no property x");
+    assert_eq!(error("class A extends B {}"), "test:1:17: B is not defined");
+    assert_eq!(
+        error("class A { get x() { return 1; } }\nA().x = 2;"),
+        "test:2:1: property x of A has no setter"
+    );
+    assert_eq!(
+        error("function f(n) { return f(n + 1); }\nf(0);"),
+        "test:1:24: stack overflow"
+    );
+    assert_eq!(
+        error("function f() { yield 1; }\nvar c = coroutine(f);\nc();"),
+        "test:3:1: coroutines are not supported by the interpreter yet"
+    );
+}
+
+#[test]
+fn test_traces() {
+    let source = "function inner(x) {\n  return x + \"a\";\n}\nfunction outer() {\n  return 1 + inner(1);\n}\nprint(outer());";
+    let program = parse_program("test", source).unwrap();
+    let mut interpreter = Interpreter::with_output(Box::new(Output::default()));
+    let error = interpreter.run(&program).unwrap_err();
+    assert_eq!(error.message(), "cannot use + with int and string");
+    let trace: Vec<String> = error
+        .trace()
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect();
+    assert_eq!(
+        trace,
+        [
+            "in inner at test:2:10",
+            "in outer at test:5:14",
+            "at test:7:7"
+        ]
+    );
+
+    let diagnostic = error.diagnostic();
+    assert_eq!(
+        diagnostic.to_string(),
+        "test:2:10: cannot use + with int and string"
+    );
+    let notes: Vec<(&str, u32, u32)> = diagnostic
+        .related()
+        .iter()
+        .map(|related| (related.message(), related.line(), related.column()))
+        .collect();
+    assert_eq!(
+        notes,
+        [
+            ("inner was called from here", 5, 14),
+            ("outer was called from here", 7, 7)
+        ]
+    );
+
+    // Nothing is left of the calls once the script has stopped.
+    let program = parse_program("next", "1 + \"a\";").unwrap();
+    let error = interpreter.run(&program).unwrap_err();
+    assert_eq!(error.trace().len(), 1);
+    assert_eq!(error.trace()[0].to_string(), "at next:1:1");
+}
+
+#[test]
+fn test_longest_chains() {
+    // Chains as long as the parser takes can be run on a thread with a
+    // stack of the default size, and one link more doesn't parse.
+    let links = DEFAULT_MAX_CHAIN_DEPTH;
+    let chains = [
+        (
+            "var a = 1;\nvar x = a",
+            " + a",
+            links,
+            "\nprint(x);",
+            format!("{}\n", links + 1),
+        ),
+        (
+            "var x = true",
+            " and true",
+            links,
+            "\nprint(x);",
+            "true\n".to_string(),
+        ),
+        (
+            "function f() { return f; }\nvar x = f",
+            "()",
+            links,
+            "\nprint(x == f);",
+            "true\n".to_string(),
+        ),
+        (
+            "class B { m() { return this; } }\nvar b = B();\nvar x = b",
+            ".m()",
+            links / 2,
+            "\nprint(x == b);",
+            "true\n".to_string(),
+        ),
+    ];
+    for (before, link, count, after, expected) in chains {
+        let longer = format!("{}{};{}", before, link.repeat(count + 1), after);
+        assert!(parse_program("test", &longer[..]).is_err());
+        let source = format!("{}{};{}", before, link.repeat(count), after);
+        let thread = std::thread::spawn(move || printed(&source[..]));
+        assert_eq!(thread.join().unwrap(), expected);
+    }
+}
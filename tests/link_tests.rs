@@ -0,0 +1,291 @@
+extern crate atom;
+
+use atom::ast::Span;
+use atom::bytecode::disassemble::disassemble;
+use atom::bytecode::{Constant, Function, OpCode};
+use atom::compile::*;
+use atom::link::*;
+use atom::parse::*;
+use atom::project::*;
+use atom::vm::Vm;
+
+fn module(path: &str, source: &str) -> Module {
+    let program = parse_program(path, source).unwrap();
+    match compile_module(&program, &CompilerOptions::default()) {
+        Ok(module) => module,
+        Err(errors) => panic!("unexpected compile error: {}", errors[0]),
+    }
+}
+
+fn errors(modules: Vec<Module>, entry: &str) -> Vec<String> {
+    match link(modules, entry) {
+        Ok(_) => panic!("expected an error"),
+        Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+    }
+}
+
+#[test]
+fn test_compile_module() {
+    let source = "import \"lib/vector.at\" as v;
+import fs;
+export var origin = [0, 0];
+export function length(p) { return v.dot(p, p); }
+function scale(v, k) { return v.x * k; }";
+    let program = parse_program("game/main.at", source).unwrap();
+    let options = CompilerOptions {
+        features: Features { fs: true },
+        ..CompilerOptions::default()
+    };
+    let main = compile_module(&program, &options).unwrap();
+
+    // Imports are found relative to the module and host modules are left
+    // out. The parameter of scale hides the alias.
+    assert_eq!(
+        main.imports,
+        vec![Import {
+            path: String::from("game/lib/vector.at"),
+            alias: String::from("v"),
+            span: Span::new(0, 28, 1, 1),
+            uses: vec![(String::from("dot"), Span::new(105, 108, 4, 38))],
+        }]
+    );
+    assert_eq!(main.exports, vec!["origin", "length"]);
+
+    // Running the module gives back its exports.
+    assert_eq!(
+        disassemble(&main.function.chunk),
+        "== game/main.at ==
+0000    1 import        0 \"game/lib/vector.at\"
+0002    | define_slot   0 v
+0004    2 import        1 \"fs\"
+0006    | define_slot   1 fs
+0008    3 constant      2 0
+0010    | constant      2 0
+0012    | list          2
+0014    | define_slot   2 origin
+0016    4 constant      3 <function length>
+0018    | define_slot   3 length
+0020    5 constant      4 <function scale>
+0022    | define_slot   4 scale
+0024    3 constant      5 \"origin\"
+0026    | get_slot      2 origin
+0028    4 constant      6 \"length\"
+0030    | get_slot      3 length
+0032    5 map           2
+0034    | return
+0035    | null
+0036    | return
+
+== length ==
+0000    4 get_slot      0 v
+0002    | get_property  0 \"dot\"
+0004    | get_local     1
+0006    | get_local     1
+0008    | tail_call     2
+0010    | null
+0011    | return
+
+== scale ==
+0000    5 get_local     1
+0002    | get_property  0 \"x\"
+0004    | get_local     2
+0006    | multiply
+0007    | return
+0008    | null
+0009    | return
+"
+    );
+}
+
+#[test]
+fn test_link_order() {
+    // Every module runs after the modules it imports, and modules nothing
+    // imports are left out.
+    let modules = vec![
+        module("main.at", "import \"a.at\";\nimport \"b.at\";"),
+        module("b.at", "import \"c.at\";"),
+        module("unused.at", "import \"a.at\";"),
+        module("a.at", "import \"c.at\";"),
+        module("c.at", "export var c = 1;"),
+    ];
+    let bundle = link(modules, "main.at").unwrap();
+    let order: Vec<&str> = bundle.modules().iter().map(|m| &m.name[..]).collect();
+    assert_eq!(order, vec!["c.at", "a.at", "b.at", "main.at"]);
+    assert_eq!(bundle.entry().name, "main.at");
+    assert!(bundle.module("c.at").is_some());
+    assert_eq!(bundle.module("unused.at"), None);
+}
+
+#[test]
+fn test_link_errors() {
+    assert_eq!(
+        errors(vec![module("a.at", "")], "main.at"),
+        vec!["main.at:0:0: cannot find module main.at"]
+    );
+    assert_eq!(
+        errors(
+            vec![module("main.at", "var x = 1;\nimport \"lib/missing.at\";")],
+            "main.at"
+        ),
+        vec!["main.at:2:1: cannot find module lib/missing.at"]
+    );
+
+    // Only exported names can be read from a module.
+    let modules = vec![
+        module(
+            "main.at",
+            "import \"math.at\" as m;\nprint(m.pi, m.tau);\nfunction f(m) { return m.e; }",
+        ),
+        module("math.at", "export var pi = 3.14;\nvar tau = 6.28;"),
+    ];
+    assert_eq!(
+        errors(modules, "main.at"),
+        vec!["main.at:2:15: m does not export tau"]
+    );
+
+    let modules = vec![
+        module("a.at", "import \"b.at\";"),
+        module("b.at", "import \"c.at\";"),
+        module("c.at", "import \"a.at\";"),
+    ];
+    assert_eq!(
+        errors(modules, "a.at"),
+        vec!["c.at:1:1: import cycle: a.at -> b.at -> c.at -> a.at"]
+    );
+}
+
+#[test]
+fn test_serialize_bundle() {
+    let modules = vec![
+        module("main.at", "import \"lib.at\";\nprint(lib.name);"),
+        module("lib.at", "export var name = \"lib\";"),
+    ];
+    let bundle = link(modules, "main.at").unwrap();
+    let bytes = bundle.serialize();
+    assert_eq!(Bundle::deserialize("game.atb", &bytes), Ok(bundle));
+
+    let error = |bytes: &[u8]| match Bundle::deserialize("game.atb", bytes) {
+        Ok(_) => panic!("expected an error"),
+        Err(error) => String::from(error.message()),
+    };
+    let script = module("main.at", "").function.chunk.serialize();
+    assert_eq!(error(&script), "not a bundle of modules");
+    assert_eq!(
+        error(&bytes[..bytes.len() - 1]),
+        "unexpected end of compiled script"
+    );
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert_eq!(error(&extra), "unexpected bytes after the bundle");
+}
+
+// Loading only checks that the code is well formed, so a damaged bundle
+// that puts the wrong values under its class instructions fails when it
+// runs instead of stopping the process.
+#[test]
+fn test_run_damaged_bundle() {
+    let run = |names: &[&str], code: &[(OpCode, Option<usize>)]| {
+        let mut function = Function::new("main.at");
+        for name in names {
+            function
+                .chunk
+                .add_constant(Constant::String((*name).into()));
+        }
+        let span = Span::new(0, 0, 1, 1);
+        for &(op, operand) in code {
+            match operand {
+                Some(operand) => function.chunk.write_indexed(op, operand, span),
+                None => function.chunk.write(op, span),
+            }
+        }
+        let module = Module {
+            path: String::from("main.at"),
+            function,
+            imports: Vec::new(),
+            exports: Vec::new(),
+        };
+        let bytes = link(vec![module], "main.at").unwrap().serialize();
+        let bundle = Bundle::deserialize("game.atb", &bytes).unwrap();
+        let entry = bundle.entry().clone();
+        let mut vm = Vm::with_output(Box::new(std::io::sink()));
+        vm.set_module_loader(Some(Box::new(bundle)));
+        match vm.run(&entry) {
+            Ok(_) => panic!("expected an error"),
+            Err(error) => error.to_string(),
+        }
+    };
+    // A method added to something that isn't a class.
+    let code = [
+        (OpCode::Null, None),
+        (OpCode::Null, None),
+        (OpCode::Method, Some(0)),
+        (OpCode::Return, None),
+    ];
+    assert_eq!(run(&["m"], &code), "main.at:1:1: invalid instruction");
+    // A class with an initializer that isn't a function.
+    let code = [
+        (OpCode::Class, Some(0)),
+        (OpCode::Constant, Some(1)),
+        (OpCode::Method, Some(1)),
+        (OpCode::Call, Some(0)),
+        (OpCode::Return, None),
+    ];
+    assert_eq!(
+        run(&["A", "init"], &code),
+        "main.at:1:1: invalid initializer"
+    );
+}
+
+#[test]
+fn test_build_project() {
+    let mut project = Project::new();
+    project.provide(
+        "game/main.at",
+        "import math;\nimport \"lib/vector.at\" as v;\nprint(math.sqrt(v.zero));",
+    );
+    project.provide("game/math.at", "export function sqrt(x) { return x; }");
+    project.provide(
+        "game/lib/vector.at",
+        "import \"../math.at\" as m;\nexport var zero = m.sqrt(0);",
+    );
+    let bundle = project
+        .build("game/main.at", &CompilerOptions::default())
+        .unwrap();
+    let order: Vec<&str> = bundle.modules().iter().map(|m| &m.name[..]).collect();
+    assert_eq!(
+        order,
+        vec!["game/math.at", "game/lib/vector.at", "game/main.at"]
+    );
+
+    project.provide("game/main.at", "import \"missing.at\";\nprint(;");
+    let errors: Vec<String> = match project.build("game/main.at", &CompilerOptions::default()) {
+        Ok(_) => panic!("expected an error"),
+        Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+    };
+    assert_eq!(
+        errors,
+        vec!["game/main.at:2:7: expected expression but found ';'"]
+    );
+}
+
+#[test]
+fn test_reproducible_bundle() {
+    let build = || {
+        let mut project = Project::new();
+        project.provide(
+            "main.at",
+            "import \"a.at\";\nimport \"b.at\";\nprint(a.x, b.y);",
+        );
+        project.provide("a.at", "import \"c.at\";\nexport var x = c.z;");
+        project.provide("b.at", "import \"c.at\";\nexport var y = c.z + 1;");
+        project.provide("c.at", "export var z = \"c\";");
+        let bundle = project
+            .build("main.at", &CompilerOptions::default())
+            .unwrap();
+        bundle.serialize()
+    };
+    let first = build();
+    for _ in 0..8 {
+        assert_eq!(std::thread::spawn(build).join().unwrap(), first);
+    }
+}
@@ -0,0 +1,1677 @@
+extern crate atom;
+
+use atom::ast::dump::*;
+use atom::ast::*;
+use atom::error::*;
+use atom::parse::*;
+
+fn parse_ok(source: &str) -> Program {
+    match parse_program("test", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("unexpected parse error: {}", errors[0].message()),
+    }
+}
+
+fn verify_error_at(source: &str, line: u32, column: u32) {
+    match parse_program("test", source) {
+        Ok(_) => panic!("did not find a parser error"),
+        Err(errors) => {
+            // Only the position of the first error matters here, the exact
+            // message is ignored.
+            assert_eq!(errors[0].file_name(), "test");
+            assert_eq!(errors[0].line(), line);
+            assert_eq!(errors[0].column(), column);
+        }
+    }
+}
+
+fn single_expression(source: &str) -> Expr {
+    let mut program = parse_ok(source);
+    assert_eq!(program.body.len(), 1);
+    match program.body.remove(0).kind {
+        StmtKind::Expression(expr) => expr,
+        _ => panic!("expected an expression statement"),
+    }
+}
+
+#[test]
+fn test_function_declaration() {
+    let program = parse_ok("function add(a, b) { return a + b; }");
+    assert_eq!(program.name, "test");
+    assert_eq!(program.body.len(), 1);
+
+    let stmt = &program.body[0];
+    assert_eq!(stmt.span.line, 1);
+    assert_eq!(stmt.span.column, 1);
+    assert_eq!(stmt.span.start, 0);
+    assert_eq!(stmt.span.end, 36);
+
+    match &stmt.kind {
+        StmtKind::Function(decl) => {
+            assert_eq!(decl.name.name, "add");
+            assert_eq!(decl.name.span.column, 10);
+            assert_eq!(decl.arity(), 2);
+            assert_eq!(decl.params[0].name, "a");
+            assert_eq!(decl.params[1].name, "b");
+            assert_eq!(decl.params[1].span.column, 17);
+            assert_eq!(decl.body.len(), 1);
+            match &decl.body[0].kind {
+                StmtKind::Return(Some(expr)) => match &expr.kind {
+                    ExprKind::Binary { op, .. } => assert_eq!(*op, BinaryOp::Add),
+                    _ => panic!("expected a binary expression"),
+                },
+                _ => panic!("expected a return statement"),
+            }
+        }
+        _ => panic!("expected a function declaration"),
+    }
+}
+
+#[test]
+fn test_function_without_parameters() {
+    let program = parse_ok("function tick() {}");
+    match &program.body[0].kind {
+        StmtKind::Function(decl) => {
+            assert_eq!(decl.arity(), 0);
+            assert!(decl.body.is_empty());
+        }
+        _ => panic!("expected a function declaration"),
+    }
+}
+
+#[test]
+fn test_call() {
+    let expr = single_expression("add(1, 2);");
+    assert_eq!(expr.span.start, 0);
+    assert_eq!(expr.span.end, 9);
+    match expr.kind {
+        ExprKind::Call(call) => {
+            assert_eq!(call.arity(), 2);
+            assert_eq!(call.callee.kind, ExprKind::Variable(String::from("add")));
+            assert_eq!(call.args[0].kind, ExprKind::Int(1));
+            assert_eq!(call.args[1].kind, ExprKind::Int(2));
+            assert_eq!(call.args[1].span.column, 8);
+        }
+        _ => panic!("expected a call"),
+    }
+
+    // Calls can be chained when a function returns another function.
+    let expr = single_expression("make()(3);");
+    match expr.kind {
+        ExprKind::Call(outer) => {
+            assert_eq!(outer.arity(), 1);
+            match outer.callee.kind {
+                ExprKind::Call(inner) => assert_eq!(inner.arity(), 0),
+                _ => panic!("expected the callee to be a call"),
+            }
+        }
+        _ => panic!("expected a call"),
+    }
+}
+
+#[test]
+fn test_named_arguments() {
+    let expr = single_expression("spawn(1, x: 10, sprite: \"hero\");");
+    match &expr.kind {
+        ExprKind::Call(call) => {
+            assert_eq!(call.arity(), 3);
+            assert_eq!(call.positional().len(), 1);
+            let named: Vec<(&str, &ExprKind)> = call
+                .named()
+                .map(|(name, arg)| (&name.name[..], &arg.kind))
+                .collect();
+            assert_eq!(
+                named,
+                vec![
+                    ("x", &ExprKind::Int(10)),
+                    ("sprite", &ExprKind::String(String::from("hero")))
+                ]
+            );
+            assert_eq!(call.names[0].span.column, 10);
+        }
+        _ => panic!("expected a call"),
+    }
+    assert_eq!(expr.to_sexpr(), "(call spawn 1 (x: 10) (sprite: \"hero\"))");
+
+    // A conditional argument is not mistaken for a name.
+    assert_eq!(
+        single_expression("f(a ? b : c, d: e);").to_sexpr(),
+        "(call f (? a b c) (d: e))"
+    );
+}
+
+#[test]
+fn test_named_argument_errors() {
+    verify_error_at("f(x: 1, 2);", 1, 9);
+    verify_error_at("f(x: 1, x: 2);", 1, 9);
+    verify_error_at("f(x:);", 1, 5);
+
+    let errors = parse_program("test", "f(x: 1, 2);").unwrap_err();
+    assert_eq!(
+        errors[0].message(),
+        "positional argument after named argument"
+    );
+    let errors = parse_program("test", "f(x: 1, x: 2);").unwrap_err();
+    assert_eq!(errors[0].message(), "duplicate named argument x");
+}
+
+#[test]
+fn test_precedence() {
+    let expr = single_expression("1 + 2 * 3 == 7 and not false;");
+    match expr.kind {
+        ExprKind::Binary { op, left, right } => {
+            assert_eq!(op, BinaryOp::And);
+            match left.kind {
+                ExprKind::Binary { op, left, .. } => {
+                    assert_eq!(op, BinaryOp::Equal);
+                    match left.kind {
+                        ExprKind::Binary { op, right, .. } => {
+                            assert_eq!(op, BinaryOp::Add);
+                            match right.kind {
+                                ExprKind::Binary { op, .. } => assert_eq!(op, BinaryOp::Multiply),
+                                _ => panic!("expected multiplication"),
+                            }
+                        }
+                        _ => panic!("expected addition"),
+                    }
+                }
+                _ => panic!("expected equality"),
+            }
+            match right.kind {
+                ExprKind::Unary { op, .. } => assert_eq!(op, UnaryOp::Not),
+                _ => panic!("expected not"),
+            }
+        }
+        _ => panic!("expected a binary expression"),
+    }
+
+    let expr = single_expression("(1 + 2) * 3;");
+    assert_eq!(expr.span.start, 0);
+    match expr.kind {
+        ExprKind::Binary { op, left, .. } => {
+            assert_eq!(op, BinaryOp::Multiply);
+            assert_eq!(left.span.start, 0);
+            assert_eq!(left.span.end, 7);
+        }
+        _ => panic!("expected a binary expression"),
+    }
+}
+
+#[test]
+fn test_statements() {
+    let program = parse_ok(
+        "var x = 5;\n\
+         if (x >= 5) { x; } else x;\n\
+         while (x != 0) x;\n\
+         for (item in items) { break; continue; }",
+    );
+    assert_eq!(program.body.len(), 4);
+    match &program.body[0].kind {
+        StmtKind::Var { name, init, .. } => {
+            assert_eq!(name.name, "x");
+            assert_eq!(init.as_ref().unwrap().kind, ExprKind::Int(5));
+        }
+        _ => panic!("expected a variable declaration"),
+    }
+    match &program.body[1].kind {
+        StmtKind::If { else_branch, .. } => assert!(else_branch.is_some()),
+        _ => panic!("expected an if statement"),
+    }
+    assert_eq!(program.body[1].span.line, 2);
+    match &program.body[2].kind {
+        StmtKind::While { .. } => {}
+        _ => panic!("expected a while loop"),
+    }
+    match &program.body[3].kind {
+        StmtKind::For { variable, body, .. } => {
+            assert_eq!(variable.name, "item");
+            match &body.kind {
+                StmtKind::Block(stmts) => {
+                    assert_eq!(stmts[0].kind, StmtKind::Break(None));
+                    assert_eq!(stmts[1].kind, StmtKind::Continue(None));
+                }
+                _ => panic!("expected a block"),
+            }
+        }
+        _ => panic!("expected a for loop"),
+    }
+}
+
+#[test]
+fn test_yield() {
+    let program = parse_ok(
+        "function f() {\n\
+         yield;\n\
+         var x = yield 1 + 2;\n\
+         g(yield, yield x);\n\
+         }",
+    );
+    assert_eq!(
+        program.to_sexpr(),
+        "(function f () (yield) (var x (yield (+ 1 2))) (call g (yield) (yield x)))"
+    );
+    verify_error_at("yield 1;", 1, 1);
+}
+
+#[test]
+fn test_function_name_constant() {
+    let program = parse_ok("function log() { return __function; } __function;");
+    match &program.body[0].kind {
+        StmtKind::Function(decl) => match &decl.body[0].kind {
+            StmtKind::Return(Some(expr)) => {
+                assert_eq!(expr.kind, ExprKind::String(String::from("log")))
+            }
+            _ => panic!("expected a return statement"),
+        },
+        _ => panic!("expected a function declaration"),
+    }
+    match &program.body[1].kind {
+        StmtKind::Expression(expr) => {
+            assert_eq!(expr.kind, ExprKind::String(String::from("<script>")))
+        }
+        _ => panic!("expected an expression statement"),
+    }
+}
+
+#[test]
+fn test_errors() {
+    verify_error_at("function (a) {}", 1, 10);
+    verify_error_at("function f(a, a) {}", 1, 15);
+    verify_error_at("function f(a {}", 1, 14);
+    verify_error_at("add(1, 2;", 1, 9);
+    verify_error_at("var x = ;", 1, 9);
+    verify_error_at("return 5;", 1, 1);
+    verify_error_at("1 +", 1, 4);
+    verify_error_at("\n  #", 2, 3);
+}
+
+#[test]
+fn test_numbers() {
+    // Numbers are ints unless they have a decimal point, and ints have to
+    // fit in 64 bits.
+    assert_eq!(single_expression("42;").kind, ExprKind::Int(42));
+    assert_eq!(single_expression("4.0;").kind, ExprKind::Float(4.0));
+    assert_eq!(
+        single_expression("9223372036854775807;").kind,
+        ExprKind::Int(i64::MAX)
+    );
+    verify_error_at("x = 9223372036854775808;", 1, 5);
+}
+
+#[test]
+fn test_error_recovery() {
+    match parse_program("test", "var = 5;\nvar y = 6;\nfunction f( {}\nf();") {
+        Ok(_) => panic!("did not find a parser error"),
+        Err(errors) => {
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[0].line(), 1);
+            assert_eq!(errors[1].line(), 3);
+        }
+    }
+}
+
+#[test]
+fn test_class_declaration() {
+    let program = parse_ok(
+        "class Player extends Entity {\n\
+         \x20   init(name) { this; }\n\
+         \x20   update(dt) { return super.update(dt); }\n\
+         \x20   name() { return __function; }\n\
+         }",
+    );
+    assert_eq!(program.body.len(), 1);
+    assert_eq!(program.body[0].span.start, 0);
+    assert_eq!(program.body[0].span.line, 1);
+
+    match &program.body[0].kind {
+        StmtKind::Class(decl) => {
+            assert_eq!(decl.name.name, "Player");
+            assert_eq!(decl.superclass.as_ref().unwrap().name, "Entity");
+
+            let init = decl.initializer.as_ref().unwrap();
+            assert_eq!(init.arity(), 1);
+            match &init.body[0].kind {
+                StmtKind::Expression(expr) => assert_eq!(expr.kind, ExprKind::This),
+                _ => panic!("expected an expression statement"),
+            }
+
+            assert_eq!(decl.methods.len(), 2);
+            assert_eq!(decl.methods[0].name.name, "update");
+            assert_eq!(decl.methods[0].name.span.line, 3);
+            match &decl.methods[0].body[0].kind {
+                StmtKind::Return(Some(expr)) => match &expr.kind {
+                    ExprKind::Call(call) => match &call.callee.kind {
+                        ExprKind::Super(method) => assert_eq!(method.name, "update"),
+                        _ => panic!("expected a super expression"),
+                    },
+                    _ => panic!("expected a call"),
+                },
+                _ => panic!("expected a return statement"),
+            }
+            match &decl.methods[1].body[0].kind {
+                StmtKind::Return(Some(expr)) => {
+                    assert_eq!(expr.kind, ExprKind::String(String::from("Player.name")))
+                }
+                _ => panic!("expected a return statement"),
+            }
+        }
+        _ => panic!("expected a class declaration"),
+    }
+
+    let program = parse_ok("class Empty {}");
+    match &program.body[0].kind {
+        StmtKind::Class(decl) => {
+            assert!(decl.superclass.is_none());
+            assert!(decl.initializer.is_none());
+            assert!(decl.methods.is_empty());
+        }
+        _ => panic!("expected a class declaration"),
+    }
+}
+
+#[test]
+fn test_class_errors() {
+    verify_error_at("this;", 1, 1);
+    verify_error_at("function f() { return super.f(); }", 1, 23);
+    verify_error_at("class A { f() { super.f(); } }", 1, 17);
+    verify_error_at("class A extends B { f() { super; } }", 1, 32);
+    verify_error_at("class A extends A {}", 1, 17);
+    verify_error_at("class A { f() {} f() {} }", 1, 18);
+    verify_error_at("class A { init() {} init() {} }", 1, 21);
+    verify_error_at("class A { var x; }", 1, 11);
+    verify_error_at("class A { init() { return 1; } }", 1, 20);
+    parse_ok("class A { init() { return; } f() { return 1; } }");
+    parse_ok("class A { init() { var f = function() { return 1; }; } }");
+}
+
+#[test]
+fn test_accessors() {
+    let program = parse_ok(
+        "class Circle {\n\
+         \x20   get area() { return 3 * this.r * this.r; }\n\
+         \x20   set area(a) { this.r = a / 3; }\n\
+         \x20   get(key) { return key; }\n\
+         \x20   set() {}\n\
+         }",
+    );
+    match &program.body[0].kind {
+        StmtKind::Class(decl) => {
+            assert_eq!(decl.getters.len(), 1);
+            assert_eq!(decl.getters[0].name.name, "area");
+            assert_eq!(decl.getters[0].span.column, 5);
+            assert_eq!(decl.setters.len(), 1);
+            assert_eq!(decl.setters[0].params[0].name, "a");
+            // Get and set are still fine as names for ordinary methods.
+            let methods: Vec<&str> = decl.methods.iter().map(|m| &m.name.name[..]).collect();
+            assert_eq!(methods, vec!["get", "set"]);
+        }
+        _ => panic!("expected a class declaration"),
+    }
+
+    assert_eq!(
+        parse_ok("class A { @cached get b() {} set b(v) {} c() {} }").to_sexpr(),
+        "(class A (function c ()) (get (@ cached (function b ()))) (set (function b (v))))"
+    );
+
+    verify_error_at("class A { get b(x) {} }", 1, 15);
+    verify_error_at("class A { set b() {} }", 1, 15);
+    verify_error_at("class A { set b(x, y) {} }", 1, 15);
+    verify_error_at("class A { set b(...x) {} }", 1, 15);
+    verify_error_at("class A { get b() {} get b() {} }", 1, 26);
+    verify_error_at("class A { set b(x) {} set b(y) {} }", 1, 27);
+    verify_error_at("class A { b() {} get b() {} }", 1, 22);
+    verify_error_at("class A { set b(x) {} b() {} }", 1, 23);
+    verify_error_at("class A { get init() {} }", 1, 11);
+
+    let errors = parse_program("test", "class A { get b() {} b() {} }").unwrap_err();
+    assert_eq!(errors[0].message(), "b is already a property");
+}
+
+#[test]
+fn test_class_error_recovery() {
+    match parse_program("test", "class A { f() { } g(x }\nvar = 1;") {
+        Ok(_) => panic!("did not find a parser error"),
+        Err(errors) => {
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[0].column(), 23);
+            assert_eq!(errors[1].line(), 2);
+        }
+    }
+}
+
+#[test]
+fn test_formatted_strings() {
+    let expr = single_expression("\"Hello {name}, you have {count + 1} messages\";");
+    assert_eq!(
+        expr.to_sexpr(),
+        "(format \"Hello \" name \", you have \" (+ count 1) \" messages\")"
+    );
+    match expr.kind {
+        ExprKind::InterpolatedString(parts) => match &parts[3] {
+            StringPart::Expr(e) => {
+                // The expression is positioned where it appears in the file.
+                assert_eq!(e.span.line, 1);
+                assert_eq!(e.span.column, 26);
+                assert_eq!(e.span.start, 25);
+                assert_eq!(e.span.end, 34);
+            }
+            _ => panic!("expected an expression part"),
+        },
+        _ => panic!("expected an interpolated string"),
+    }
+
+    // Escapes before an interpolation must not shift its position.
+    let expr = single_expression("\"\\t\\n{f('}')}\";");
+    assert_eq!(expr.to_sexpr(), "(format \"\\t\\n\" (call f \"}\"))");
+    match expr.kind {
+        ExprKind::InterpolatedString(parts) => match &parts[1] {
+            StringPart::Expr(e) => assert_eq!(e.span.column, 7),
+            _ => panic!("expected an expression part"),
+        },
+        _ => panic!("expected an interpolated string"),
+    }
+
+    // Without any interpolation a formatted string is a plain string.
+    assert_eq!(
+        single_expression("\"{{literal}}\";").kind,
+        ExprKind::String(String::from("{literal}"))
+    );
+    assert_eq!(single_expression("\"{x}\";").to_sexpr(), "(format x)");
+
+    let program = parse_ok("function greet() { return \"in {__function}\"; }");
+    assert_eq!(
+        program.to_sexpr(),
+        "(function greet () (return (format \"in \" \"greet\")))"
+    );
+}
+
+#[test]
+fn test_formatted_string_errors() {
+    verify_error_at("var s = \"x{a +}\";", 1, 15);
+    verify_error_at("var s = \"x{a b}\";", 1, 14);
+    verify_error_at("var s = \"x{}\";", 1, 11);
+    verify_error_at("var s = \"x{a\";", 1, 11);
+    verify_error_at("var s = \"x}\";", 1, 11);
+    verify_error_at("var s = \"line\n  {1 +}\";", 2, 7);
+    verify_error_at("var s = \"{this}\";", 1, 11);
+}
+
+#[test]
+fn test_map_literals() {
+    assert_eq!(
+        parse_ok("var m = { health: 10, \"max health\": 5 * 2, 'x': 1, [id]: player };").to_sexpr(),
+        "(var m (map (health 10) (\"max health\" (* 5 2)) (\"x\" 1) ([id] player)))"
+    );
+    assert_eq!(parse_ok("f({});").to_sexpr(), "(call f (map))");
+    assert_eq!(
+        parse_ok("var m = { \"slot {n}\": { nested: true } };").to_sexpr(),
+        "(var m (map ([(format \"slot \" n)] (map (nested true)))))"
+    );
+
+    let expr = single_expression("{ a: 1, b: 2 };");
+    match expr.kind {
+        ExprKind::MapLiteral(entries) => {
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[1].span.column, 9);
+            match &entries[1].key {
+                MapKey::Identifier(ident) => assert_eq!(ident.name, "b"),
+                _ => panic!("expected an identifier key"),
+            }
+        }
+        _ => panic!("expected a map literal"),
+    }
+}
+
+#[test]
+fn test_block_or_map() {
+    // At the start of a statement a brace is a block unless the contents
+    // are clearly a map.
+    assert_eq!(parse_ok("{ a; b; }").to_sexpr(), "(block a b)");
+    assert_eq!(parse_ok("{}").to_sexpr(), "(block)");
+    assert_eq!(parse_ok("{ 'a': 1 };").to_sexpr(), "(map (\"a\" 1))");
+    assert_eq!(parse_ok("{ [k]: 1 };").to_sexpr(), "(map ([k] 1))");
+}
+
+#[test]
+fn test_map_errors() {
+    verify_error_at("var m = { 1: 2 };", 1, 11);
+    verify_error_at("var m = { a 2 };", 1, 13);
+    verify_error_at("var m = { a: 1 b: 2 };", 1, 16);
+    verify_error_at("var m = { [a: 1 };", 1, 13);
+}
+
+#[test]
+fn test_lambdas() {
+    assert_eq!(
+        parse_ok("var add = function(a, b) { return a + b; };").to_sexpr(),
+        "(var add (lambda (a b) (return (+ a b))))"
+    );
+    assert_eq!(
+        parse_ok("map(items, function(x) { return __function; });").to_sexpr(),
+        "(call map items (lambda (x) (return \"<lambda>\")))"
+    );
+    // An anonymous function can be called right away but it needs to be
+    // wrapped in parentheses at the start of a statement.
+    assert_eq!(
+        parse_ok("(function() { })();").to_sexpr(),
+        "(call (lambda ()))"
+    );
+
+    let expr = single_expression("(function(dt) { update(dt); });");
+    assert_eq!(expr.span.start, 0);
+    assert_eq!(expr.span.end, 30);
+    match expr.kind {
+        ExprKind::Lambda(lambda) => {
+            assert_eq!(lambda.arity(), 1);
+            assert_eq!(lambda.params[0].name, "dt");
+            assert_eq!(lambda.body.len(), 1);
+        }
+        _ => panic!("expected a lambda"),
+    }
+}
+
+#[test]
+fn test_lambda_errors() {
+    verify_error_at("var f = function(a, a) {};", 1, 21);
+    verify_error_at("var f = function(a) a;", 1, 21);
+    verify_error_at("var f = function {};", 1, 18);
+}
+
+#[test]
+fn test_conditional() {
+    assert_eq!(single_expression("a ? b : c;").to_sexpr(), "(? a b c)");
+    // Conditionals bind looser than every binary operator.
+    assert_eq!(
+        single_expression("a or b ? x + 1 : y * 2;").to_sexpr(),
+        "(? (or a b) (+ x 1) (* y 2))"
+    );
+    // Chains group to the right.
+    assert_eq!(
+        single_expression("a ? b : c ? d : e;").to_sexpr(),
+        "(? a b (? c d e))"
+    );
+    assert_eq!(
+        single_expression("a ? b ? c : d : e;").to_sexpr(),
+        "(? a (? b c d) e)"
+    );
+    assert_eq!(
+        parse_ok("var x = { hp: dead ? 0 : 10 };").to_sexpr(),
+        "(var x (map (hp (? dead 0 10))))"
+    );
+
+    let expr = single_expression("ready ? go() : wait();");
+    assert_eq!(expr.span.start, 0);
+    assert_eq!(expr.span.end, 21);
+}
+
+#[test]
+fn test_conditional_errors() {
+    verify_error_at("a ? b;", 1, 6);
+    verify_error_at("a ? : c;", 1, 5);
+}
+
+#[test]
+fn test_assignment() {
+    assert_eq!(single_expression("x = 1;").to_sexpr(), "(= x 1)");
+    assert_eq!(
+        single_expression("x += y * 2;").to_sexpr(),
+        "(+= x (* y 2))"
+    );
+    assert_eq!(single_expression("x /= 2;").to_sexpr(), "(/= x 2)");
+    assert_eq!(single_expression("x ^= 2;").to_sexpr(), "(^= x 2)");
+    // Assignment groups to the right and binds looser than conditionals.
+    assert_eq!(single_expression("a = b = c;").to_sexpr(), "(= a (= b c))");
+    assert_eq!(
+        single_expression("x = ok ? 1 : 2;").to_sexpr(),
+        "(= x (? ok 1 2))"
+    );
+    assert_eq!(
+        parse_ok("while (true) { count -= 1; }").to_sexpr(),
+        "(while true (block (-= count 1)))"
+    );
+
+    let expr = single_expression("total += cost;");
+    assert_eq!(expr.span.start, 0);
+    assert_eq!(expr.span.end, 13);
+}
+
+#[test]
+fn test_assignment_errors() {
+    verify_error_at("1 = x;", 1, 1);
+    verify_error_at("a + b = c;", 1, 1);
+    verify_error_at("f() += 1;", 1, 1);
+    verify_error_at("x = ;", 1, 5);
+
+    // The message says exactly what went wrong.
+    let errors = parse_program("test", "a = 1;\nthis = 2;").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message(), "this used outside of a class");
+    let errors = parse_program("test", "null = 2;").unwrap_err();
+    assert_eq!(errors[0].message(), "invalid assignment target");
+    assert_eq!(errors[0].line(), 1);
+    assert_eq!(errors[0].column(), 1);
+}
+
+#[test]
+fn test_index_and_slice() {
+    assert_eq!(single_expression("a[0];").to_sexpr(), "(index a 0)");
+    assert_eq!(
+        single_expression("grid[y][x + 1];").to_sexpr(),
+        "(index (index grid y) (+ x 1))"
+    );
+    assert_eq!(
+        single_expression("items()[i];").to_sexpr(),
+        "(index (call items) i)"
+    );
+    assert_eq!(single_expression("a[1:n];").to_sexpr(), "(slice a 1 n)");
+    assert_eq!(single_expression("a[:n];").to_sexpr(), "(slice a _ n)");
+    assert_eq!(single_expression("a[1:];").to_sexpr(), "(slice a 1 _)");
+    assert_eq!(single_expression("a[:];").to_sexpr(), "(slice a _ _)");
+    // A conditional inside the brackets keeps its own colon.
+    assert_eq!(
+        single_expression("a[ok ? 1 : 2];").to_sexpr(),
+        "(index a (? ok 1 2))"
+    );
+    assert_eq!(
+        single_expression("scores[i] += 10;").to_sexpr(),
+        "(+= (index scores i) 10)"
+    );
+
+    let expr = single_expression("names[1:3];");
+    assert_eq!(expr.span.start, 0);
+    assert_eq!(expr.span.end, 10);
+}
+
+#[test]
+fn test_index_errors() {
+    verify_error_at("a[];", 1, 3);
+    verify_error_at("a[1;", 1, 4);
+    verify_error_at("a[1:2:3];", 1, 6);
+    verify_error_at("a[1:2] = x;", 1, 1);
+}
+
+#[test]
+fn test_member_access() {
+    assert_eq!(single_expression("a.b;").to_sexpr(), "(. a b)");
+    assert_eq!(
+        single_expression("player.inventory.items[0].name();").to_sexpr(),
+        "(call (. (index (. (. player inventory) items) 0) name))"
+    );
+    assert_eq!(
+        single_expression("a.b().c;").to_sexpr(),
+        "(. (call (. a b)) c)"
+    );
+    assert_eq!(
+        single_expression("-hero.hp * 2;").to_sexpr(),
+        "(* (- (. hero hp)) 2)"
+    );
+    assert_eq!(
+        single_expression("hero.hp -= damage;").to_sexpr(),
+        "(-= (. hero hp) damage)"
+    );
+
+    // Every link of the chain has its own span.
+    let expr = single_expression("player.inventory.items;");
+    assert_eq!(expr.span.start, 0);
+    assert_eq!(expr.span.end, 22);
+    match expr.kind {
+        ExprKind::Member { object, name } => {
+            assert_eq!(name.name, "items");
+            assert_eq!(name.span.start, 17);
+            assert_eq!(name.span.column, 18);
+            assert_eq!(object.span.start, 0);
+            assert_eq!(object.span.end, 16);
+            match object.kind {
+                ExprKind::Member { name, .. } => {
+                    assert_eq!(name.name, "inventory");
+                    assert_eq!(name.span.column, 8);
+                }
+                _ => panic!("expected a member access"),
+            }
+        }
+        _ => panic!("expected a member access"),
+    }
+}
+
+#[test]
+fn test_member_errors() {
+    verify_error_at("a.;", 1, 3);
+    verify_error_at("a.class;", 1, 3);
+    verify_error_at("a.b() = 1;", 1, 1);
+}
+
+#[test]
+fn test_loop_labels() {
+    assert_eq!(
+        parse_ok("outer: while (a) { for (b in c) { break outer; } }").to_sexpr(),
+        "(label outer (while a (block (for b c (block (break outer))))))"
+    );
+    assert_eq!(
+        parse_ok("rows: for (r in grid) { cols: for (c in r) { continue rows; } }").to_sexpr(),
+        "(label rows (for r grid (block (label cols (for c r (block (continue rows)))))))"
+    );
+    // A labeled loop at the start of a block is not mistaken for a map.
+    assert_eq!(
+        parse_ok("{ outer: while (a) { break; } }").to_sexpr(),
+        "(block (label outer (while a (block (break)))))"
+    );
+
+    let program = parse_ok("search: while (true) { break search; }");
+    assert_eq!(program.body[0].span.start, 0);
+    match &program.body[0].kind {
+        StmtKind::While { label, .. } => {
+            let label = label.as_ref().unwrap();
+            assert_eq!(label.name, "search");
+            assert_eq!(label.span.end, 6);
+        }
+        _ => panic!("expected a while loop"),
+    }
+}
+
+#[test]
+fn test_jump_errors() {
+    verify_error_at("break;", 1, 1);
+    verify_error_at("if (a) { continue; }", 1, 10);
+    // A function body is a new context even inside of a loop.
+    verify_error_at("while (a) { var f = function() { break; }; }", 1, 34);
+    verify_error_at("while (a) { break outer; }", 1, 19);
+    verify_error_at("outer: while (a) { outer: while (b) {} }", 1, 20);
+    verify_error_at("outer: print(1);", 1, 8);
+    verify_error_at("a: while (x) {} while (y) { break a; }", 1, 35);
+
+    let errors = parse_program("test", "continue;").unwrap_err();
+    assert_eq!(errors[0].message(), "continue outside of loop");
+}
+
+fn first_error(source: &str) -> Error {
+    match parse_program("test", source) {
+        Ok(_) => panic!("did not find a parser error"),
+        Err(mut errors) => errors.remove(0),
+    }
+}
+
+#[test]
+fn test_nesting_limit() {
+    // None of these may overflow the stack of the thread running the test.
+    let parens = format!("{}1{};", "(".repeat(10000), ")".repeat(10000));
+    let error = first_error(&parens[..]);
+    assert_eq!(error.message(), "expression too deeply nested");
+    assert_eq!(error.line(), 1);
+
+    let negation = format!("{}1;", "-".repeat(10000));
+    assert_eq!(
+        first_error(&negation[..]).message(),
+        "expression too deeply nested"
+    );
+
+    let assignment = format!("{}1;", "a = ".repeat(10000));
+    assert_eq!(
+        first_error(&assignment[..]).message(),
+        "expression too deeply nested"
+    );
+
+    let conditional = format!("{}1;", "a ? b : ".repeat(10000));
+    assert_eq!(
+        first_error(&conditional[..]).message(),
+        "expression too deeply nested"
+    );
+
+    let blocks = format!("{}{}", "{".repeat(10000), "}".repeat(10000));
+    assert_eq!(
+        first_error(&blocks[..]).message(),
+        "statement too deeply nested"
+    );
+
+    let interpolation = format!("\"{{{}1{}}}\";", "(".repeat(10000), ")".repeat(10000));
+    assert_eq!(
+        first_error(&interpolation[..]).message(),
+        "expression too deeply nested"
+    );
+
+    // Chains are folded by a loop rather than by recursing, but every link
+    // makes the tree deeper all the same, so they have a limit of their own.
+    let sum = format!("var x = 1{};", " + 1".repeat(4000));
+    assert_eq!(
+        first_error(&sum[..]).message(),
+        "expression too deeply nested"
+    );
+    let mixed = format!("var x = 2{};", " * 2 + 1 < 2 and 1".repeat(10000));
+    assert_eq!(
+        first_error(&mixed[..]).message(),
+        "expression too deeply nested"
+    );
+    for link in ["(1)", "[1]", ".b"] {
+        let chain = format!("a{};", link.repeat(30000));
+        assert_eq!(
+            first_error(&chain[..]).message(),
+            "expression too deeply nested"
+        );
+    }
+
+    // Reasonable nesting is fine.
+    let parens = format!("{}1{};", "(".repeat(50), ")".repeat(50));
+    assert_eq!(parse_ok(&parens[..]).to_sexpr(), "1");
+    let sum = format!("1{};", " + 1".repeat(50));
+    assert!(parse_program("test", &sum[..]).is_ok());
+    let chain = format!("a{};", ".b".repeat(50));
+    assert!(parse_program("test", &chain[..]).is_ok());
+    let sum = format!("var x = a{};", " + a".repeat(200));
+    assert!(parse_program("test", &sum[..]).is_ok());
+    let builder = format!("var x = b{};", ".m()".repeat(100));
+    assert!(parse_program("test", &builder[..]).is_ok());
+    let nested = format!(
+        "{}a{}{};",
+        "(".repeat(50),
+        " + a".repeat(200),
+        ")".repeat(50)
+    );
+    assert!(parse_program("test", &nested[..]).is_ok());
+}
+
+#[test]
+fn test_configurable_nesting_limit() {
+    let mut parser = Parser::new("test", "((1));");
+    assert_eq!(parser.max_depth(), DEFAULT_MAX_DEPTH);
+    parser.set_max_depth(3);
+    let errors = parser.parse_program().unwrap_err();
+    assert_eq!(errors[0].message(), "expression too deeply nested");
+    assert_eq!(errors[0].column(), 3);
+
+    let mut parser = Parser::new("test", "((1));");
+    parser.set_max_depth(4);
+    assert!(parser.parse_program().is_ok());
+
+    // Chains have a limit of their own.
+    let sum = format!("1{};", " + 1".repeat(1000));
+    let mut parser = Parser::new("test", &sum[..]);
+    assert_eq!(parser.max_chain_depth(), DEFAULT_MAX_CHAIN_DEPTH);
+    assert!(parser.parse_program().is_err());
+    let mut parser = Parser::new("test", &sum[..]);
+    parser.set_max_chain_depth(1000);
+    assert!(parser.parse_program().is_ok());
+    let mut parser = Parser::new("test", "a.b.c;");
+    parser.set_max_chain_depth(1);
+    let errors = parser.parse_program().unwrap_err();
+    assert_eq!(errors[0].message(), "expression too deeply nested");
+}
+
+#[test]
+fn test_list_literal() {
+    assert_eq!(single_expression("[];").to_sexpr(), "(list)");
+    assert_eq!(
+        single_expression("[1, a + 2, [3]];").to_sexpr(),
+        "(list 1 (+ a 2) (list 3))"
+    );
+    assert_eq!(
+        single_expression("[1, 2][0];").to_sexpr(),
+        "(index (list 1 2) 0)"
+    );
+    // A name on its own in a map is short for a variable with that name.
+    assert_eq!(
+        parse_ok("var p = { x, y: 2 };").to_sexpr(),
+        "(var p (map (x x) (y 2)))"
+    );
+    assert_eq!(parse_ok("{ x, y };").to_sexpr(), "(map (x x) (y y))");
+}
+
+#[test]
+fn test_destructuring_declaration() {
+    assert_eq!(
+        parse_ok("var [a, b] = pair;").to_sexpr(),
+        "(var [a b] pair)"
+    );
+    assert_eq!(
+        parse_ok("var {x, y: top, 'z': [p, q]} = point;").to_sexpr(),
+        "(var {x y:top \"z\":[p q]} point)"
+    );
+    assert_eq!(parse_ok("var [] = nothing;").to_sexpr(), "(var [] nothing)");
+
+    let program = parse_ok("var [first, {second}] = f();");
+    assert_eq!(program.body[0].span.start, 0);
+    assert_eq!(program.body[0].span.end, 28);
+    match &program.body[0].kind {
+        StmtKind::Destructure { pattern, .. } => {
+            let names: Vec<&str> = pattern.names().iter().map(|n| &n.name[..]).collect();
+            assert_eq!(names, vec!["first", "second"]);
+            assert_eq!(pattern.span.start, 4);
+            assert_eq!(pattern.span.end, 21);
+            assert_eq!(pattern.names()[1].span.start, 13);
+        }
+        _ => panic!("expected a destructuring declaration"),
+    }
+}
+
+#[test]
+fn test_destructuring_assignment() {
+    assert_eq!(
+        single_expression("[a, b] = [b, a];").to_sexpr(),
+        "(= [a b] (list b a))"
+    );
+    assert_eq!(
+        single_expression("[hero.x, grid[0]] = position;").to_sexpr(),
+        "(= [(. hero x) (index grid 0)] position)"
+    );
+    assert_eq!(
+        parse_ok("{ x, y: [a, b] } = point;").to_sexpr(),
+        "(= {x y:[a b]} point)"
+    );
+    assert_eq!(
+        single_expression("[a, b] = [c, d] = pair;").to_sexpr(),
+        "(= [a b] (= [c d] pair))"
+    );
+}
+
+#[test]
+fn test_multiple_assignment() {
+    assert_eq!(parse_ok("a, b = b, a;").to_sexpr(), "(= (a b) (b a))");
+    assert_eq!(
+        parse_ok("x, y = get_position();").to_sexpr(),
+        "(= (x y) ((call get_position)))"
+    );
+    assert_eq!(
+        parse_ok("hero.x, [a, b], grid[0] = 1, pair, 2;").to_sexpr(),
+        "(= ((. hero x) [a b] (index grid 0)) (1 pair 2))"
+    );
+    // Each value is a full expression, only the targets stop at the equals.
+    assert_eq!(
+        parse_ok("a, b = c = 1, d ? e : f\ng()").to_sexpr(),
+        "(= (a b) ((= c 1) (? d e f)))\n(call g)"
+    );
+
+    let program = parse_ok("a, b = b, a;");
+    assert_eq!(program.body[0].span.start, 0);
+    assert_eq!(program.body[0].span.end, 12);
+    match &program.body[0].kind {
+        StmtKind::MultipleAssign { targets, values } => {
+            assert_eq!(targets.len(), 2);
+            assert_eq!(targets[1].span.start, 3);
+            assert_eq!(values[1].kind, ExprKind::Variable(String::from("a")));
+        }
+        _ => panic!("expected a multiple assignment"),
+    }
+}
+
+#[test]
+fn test_multiple_assignment_errors() {
+    verify_error_at("a, b = 1, 2, 3;", 1, 8);
+    verify_error_at("a, 1 = 1, 2;", 1, 4);
+    verify_error_at("a, b;", 1, 5);
+    verify_error_at("a, b += 1;", 1, 6);
+    verify_error_at("a = 1, b = 2;", 1, 1);
+
+    let errors = parse_program("test", "a, b = 1, 2, 3;").unwrap_err();
+    assert_eq!(errors[0].message(), "cannot assign 3 values to 2 targets");
+    let errors = parse_program("test", "a, b;").unwrap_err();
+    assert_eq!(errors[0].message(), "expected '=' but found ';'");
+}
+
+#[test]
+fn test_destructuring_errors() {
+    verify_error_at("var [a, b];", 1, 11);
+    verify_error_at("var [a, 1] = x;", 1, 9);
+    verify_error_at("var [a, a] = x;", 1, 9);
+    verify_error_at("var {a, b: [c, a]} = x;", 1, 16);
+    verify_error_at("[a, 1] = x;", 1, 5);
+    verify_error_at("[a, b] += x;", 1, 1);
+    verify_error_at("{ x: f() } = point;", 1, 6);
+
+    let nested = format!("var {}a{} = x;", "[".repeat(10000), "]".repeat(10000));
+    assert_eq!(
+        first_error(&nested[..]).message(),
+        "pattern too deeply nested"
+    );
+}
+
+#[test]
+fn test_imports() {
+    assert_eq!(parse_ok("import math;").to_sexpr(), "(import math math)");
+    assert_eq!(
+        parse_ok("import \"utils.at\" as u;").to_sexpr(),
+        "(import \"utils.at\" u)"
+    );
+    assert_eq!(
+        parse_ok("import 'lib/vector.at';").to_sexpr(),
+        "(import \"lib/vector.at\" vector)"
+    );
+    assert_eq!(parse_ok("import math as m;").to_sexpr(), "(import math m)");
+
+    let program = parse_ok("import 'lib/vector.at';");
+    match &program.body[0].kind {
+        StmtKind::Import(decl) => {
+            assert_eq!(
+                decl.source,
+                ImportSource::File(String::from("lib/vector.at"))
+            );
+            // A name taken from the file points at the path.
+            assert_eq!(decl.alias.span.start, 7);
+            assert_eq!(decl.span.end, 23);
+        }
+        _ => panic!("expected an import"),
+    }
+}
+
+#[test]
+fn test_const() {
+    assert_eq!(parse_ok("const MAX = 10;").to_sexpr(), "(const MAX 10)");
+    assert_eq!(
+        parse_ok("const [a, b] = pair;").to_sexpr(),
+        "(const [a b] pair)"
+    );
+    assert_eq!(
+        parse_ok("export const SPEED = 2;").to_sexpr(),
+        "(export (const SPEED 2))"
+    );
+
+    match &parse_ok("const MAX = 10;").body[0].kind {
+        StmtKind::Var { constant, .. } => assert!(*constant),
+        _ => panic!("expected a constant declaration"),
+    }
+    match &parse_ok("var max = 10;").body[0].kind {
+        StmtKind::Var { constant, .. } => assert!(!*constant),
+        _ => panic!("expected a variable declaration"),
+    }
+
+    verify_error_at("const MAX;", 1, 7);
+    verify_error_at("const [a] ;", 1, 11);
+    let errors = parse_program("test", "const MAX;").unwrap_err();
+    assert_eq!(errors[0].message(), "constant MAX needs a value");
+}
+
+#[test]
+fn test_type_annotations() {
+    let program = parse_ok(
+        "function f(a: number, b, c: string = \"x\"): bool { return true; }\n\
+         var g = function(x: list<number>): map<string, number> { return {}; };\n\
+         class A { move(dx: number) {} }",
+    );
+    match &program.body[0].kind {
+        StmtKind::Function(decl) => {
+            let types: Vec<Option<&str>> = decl
+                .param_types
+                .iter()
+                .map(|t| t.as_ref().map(|t| &t.name.name[..]))
+                .collect();
+            assert_eq!(types, vec![Some("number"), None, Some("string")]);
+            assert_eq!(decl.min_arity(), 2);
+            let ret = decl.return_type.as_ref().unwrap();
+            assert_eq!(ret.name.name, "bool");
+            assert_eq!(ret.span.column, 44);
+        }
+        _ => panic!("expected a function"),
+    }
+    match &program.body[1].kind {
+        StmtKind::Var {
+            init: Some(init), ..
+        } => match &init.kind {
+            ExprKind::Lambda(lambda) => {
+                let ty = lambda.param_types[0].as_ref().unwrap();
+                assert_eq!(ty.args[0].name.name, "number");
+                let ret = lambda.return_type.as_ref().unwrap();
+                assert_eq!(ret.args.len(), 2);
+                assert_eq!(ret.span.start, ret.name.span.start);
+            }
+            _ => panic!("expected a lambda"),
+        },
+        _ => panic!("expected a variable"),
+    }
+
+    let program = parse_ok("var x: list = [];\nconst y: number = 1;");
+    match &program.body[1].kind {
+        StmtKind::Var { ty: Some(ty), .. } => assert_eq!(ty.name.name, "number"),
+        _ => panic!("expected a typed constant"),
+    }
+
+    // Labels and conditionals still use the colon as before.
+    parse_ok("outer: while (a) { break outer; }\nvar z = a ? b : c;");
+
+    verify_error_at("var x: = 1;", 1, 8);
+    verify_error_at("var x: list<number = [];", 1, 20);
+    verify_error_at("function f(a:) {}", 1, 14);
+    verify_error_at("function f(...a: list) {}", 1, 16);
+    verify_error_at("function f(): {}", 1, 15);
+}
+
+#[test]
+fn test_decorators() {
+    assert_eq!(
+        parse_ok("@memoize function fib(n) {}").to_sexpr(),
+        "(@ memoize (function fib (n)))"
+    );
+    // Decorators are applied from the bottom up.
+    assert_eq!(
+        parse_ok("@trace(\"ai\")\n@cache.lru(10)\nclass Planner {}").to_sexpr(),
+        "(@ (call trace \"ai\") (@ (call (. cache lru) 10) (class Planner)))"
+    );
+    assert_eq!(
+        parse_ok("class A { @trace update(dt) {} draw() {} }").to_sexpr(),
+        "(class A (@ trace (function update (dt))) (function draw ()))"
+    );
+    assert_eq!(
+        parse_ok("export @memoize function f() {}").to_sexpr(),
+        "(export (@ memoize (function f ())))"
+    );
+
+    // The declaration starts at its first decorator.
+    let program = parse_ok("var x = 1;\n@a @b function f() {}");
+    let stmt = &program.body[1];
+    assert_eq!((stmt.span.line, stmt.span.column), (2, 1));
+    match &stmt.kind {
+        StmtKind::Function(decl) => {
+            assert_eq!(decl.decorators.len(), 2);
+            assert_eq!(decl.span, stmt.span);
+            assert_eq!(decl.decorators[1].span.column, 5);
+        }
+        _ => panic!("expected a function"),
+    }
+
+    verify_error_at("@memoize var x = 1;", 1, 10);
+    verify_error_at("@ function f() {}", 1, 12);
+    verify_error_at("class A { @trace init() {} }", 1, 11);
+    verify_error_at("f(@memoize);", 1, 3);
+}
+
+#[test]
+fn test_exports() {
+    assert_eq!(
+        parse_ok("export function f() {}").to_sexpr(),
+        "(export (function f ()))"
+    );
+    assert_eq!(
+        parse_ok("export class A {}").to_sexpr(),
+        "(export (class A))"
+    );
+    assert_eq!(
+        parse_ok("export var [a, b] = pair;").to_sexpr(),
+        "(export (var [a b] pair))"
+    );
+}
+
+#[test]
+fn test_module_errors() {
+    verify_error_at("import;", 1, 7);
+    verify_error_at("import 'my-utils.at';", 1, 8);
+    verify_error_at("import 'x.at' as;", 1, 17);
+    verify_error_at("import math x;", 1, 13);
+    verify_error_at("function f() { import math; }", 1, 16);
+    verify_error_at("if (a) { export var x = 1; }", 1, 10);
+    verify_error_at("export f();", 1, 8);
+    verify_error_at("export import math;", 1, 8);
+
+    let errors = parse_program("test", "{ import math; }").unwrap_err();
+    assert_eq!(
+        errors[0].message(),
+        "'import' must be at the top level of a module"
+    );
+}
+
+#[test]
+fn test_match() {
+    let program = parse_ok(
+        "match x {
+            case 1, -2: print(\"small\");
+            case \"x\": print(x); x = null;
+            default:
+        }",
+    );
+    match &program.body[0].kind {
+        StmtKind::Match { subject, arms } => {
+            assert_eq!(subject.kind, ExprKind::Variable(String::from("x")));
+            assert_eq!(arms.len(), 3);
+            assert_eq!(arms[0].values.len(), 2);
+            assert_eq!(arms[0].body.len(), 1);
+            assert_eq!(arms[1].values[0].kind, ExprKind::String(String::from("x")));
+            assert_eq!(arms[1].body.len(), 2);
+            assert!(arms[2].is_default());
+            assert!(arms[2].body.is_empty());
+        }
+        _ => panic!("expected a match statement"),
+    }
+
+    assert_eq!(
+        parse_ok("match f() { default: x; case true: y; }").body[0].to_sexpr(),
+        "(match (call f) (default x) ((case true) y))"
+    );
+    assert_eq!(parse_ok("match x {}").body[0].to_sexpr(), "(match x)");
+}
+
+#[test]
+fn test_match_errors() {
+    verify_error_at("match x { print(x); }", 1, 11);
+    verify_error_at("match x { case y: }", 1, 16);
+    verify_error_at("match x { case 1 print(x); }", 1, 18);
+    verify_error_at("match x { case 1, : }", 1, 19);
+    verify_error_at("match x { default: a; default: b; }", 1, 23);
+
+    let errors = parse_program("test", "match x { default: default: }").unwrap_err();
+    assert_eq!(errors[0].message(), "duplicate default in match");
+    // The rest of the match is parsed as usual after the duplicate.
+    let errors = parse_program(
+        "test",
+        "match x {\n    default: a();\n    default: b();\n    case 1: c();\n}\nd(;",
+    )
+    .unwrap_err();
+    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        errors,
+        vec![
+            "test:3:5: duplicate default in match",
+            "test:6:3: expected expression but found ';'",
+        ]
+    );
+    let errors = parse_program("test", "match x { case -y: }").unwrap_err();
+    assert_eq!(errors[0].message(), "match case must be a literal");
+}
+
+#[test]
+fn test_try() {
+    let program = parse_ok("try { risky(); } catch (e) { print(e); } finally { done(); }");
+    match &program.body[0].kind {
+        StmtKind::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            assert_eq!(body.len(), 1);
+            let catch = catch.as_ref().unwrap();
+            assert_eq!(catch.binding.as_ref().unwrap().name, "e");
+            assert_eq!(catch.body.len(), 1);
+            assert_eq!(catch.span.start, 17);
+            assert_eq!(finally.as_ref().unwrap().len(), 1);
+        }
+        _ => panic!("expected a try statement"),
+    }
+
+    assert_eq!(
+        parse_ok("try { a; } catch { b; }").body[0].to_sexpr(),
+        "(try (block a) (catch (block b)))"
+    );
+    assert_eq!(
+        parse_ok("try { a; } finally { b; }").body[0].to_sexpr(),
+        "(try (block a) (finally (block b)))"
+    );
+    assert_eq!(
+        parse_ok("try {} catch (e) {}").body[0].to_sexpr(),
+        "(try (block) (catch e (block)))"
+    );
+}
+
+#[test]
+fn test_try_errors() {
+    verify_error_at("try a; catch {}", 1, 5);
+    verify_error_at("try {}", 1, 7);
+    verify_error_at("try {} x;", 1, 8);
+    verify_error_at("try {} catch (1) {}", 1, 15);
+    verify_error_at("try {} catch (e {}", 1, 17);
+    verify_error_at("try {} catch e {}", 1, 14);
+    verify_error_at("try {} finally", 1, 15);
+    verify_error_at("try {} finally {} catch {}", 1, 19);
+}
+
+#[test]
+fn test_throw() {
+    assert_eq!(
+        parse_ok("try { throw error(1); } catch (e) { throw e; }").body[0].to_sexpr(),
+        "(try (block (throw (call error 1))) (catch e (block (throw e))))"
+    );
+    verify_error_at("throw;", 1, 6);
+}
+
+#[test]
+fn test_default_and_rest_parameters() {
+    let program = parse_ok("function f(a, b = 10, c = a, ...rest) {}");
+    match &program.body[0].kind {
+        StmtKind::Function(decl) => {
+            assert_eq!(decl.arity(), 3);
+            assert_eq!(decl.min_arity(), 1);
+            assert_eq!(decl.max_arity(), None);
+            assert_eq!(decl.defaults.len(), 2);
+            assert_eq!(decl.defaults[0].kind, ExprKind::Int(10));
+            let rest = decl.rest.as_ref().unwrap();
+            assert_eq!(rest.name, "rest");
+            assert_eq!(rest.span.column, 33);
+        }
+        _ => panic!("expected a function declaration"),
+    }
+    assert_eq!(
+        program.body[0].to_sexpr(),
+        "(function f (a (= b 10) (= c a) ...rest))"
+    );
+
+    match single_expression("(function(a, b = 2) {});").kind {
+        ExprKind::Lambda(lambda) => {
+            assert_eq!(lambda.min_arity(), 1);
+            assert_eq!(lambda.max_arity(), Some(2));
+        }
+        _ => panic!("expected a lambda"),
+    }
+    match single_expression("(function(...args) {});").kind {
+        ExprKind::Lambda(lambda) => {
+            assert_eq!(lambda.arity(), 0);
+            assert_eq!(lambda.min_arity(), 0);
+            assert_eq!(lambda.max_arity(), None);
+        }
+        _ => panic!("expected a lambda"),
+    }
+}
+
+#[test]
+fn test_parameter_errors() {
+    verify_error_at("function f(a = 1, b) {}", 1, 19);
+    verify_error_at("function f(...a, b) {}", 1, 15);
+    verify_error_at("function f(...a = 1) {}", 1, 17);
+    verify_error_at("function f(...) {}", 1, 15);
+    verify_error_at("function f(a, ...a) {}", 1, 18);
+    verify_error_at("function f(a = ) {}", 1, 16);
+
+    let errors = parse_program("test", "function f(a = 1, b) {}").unwrap_err();
+    assert_eq!(errors[0].message(), "parameter b needs a default value");
+    let errors = parse_program("test", "function f(...a, b) {}").unwrap_err();
+    assert_eq!(errors[0].message(), "rest parameter must be last");
+}
+
+#[test]
+fn test_do_while() {
+    let program = parse_ok("do { x = x + 1; } while (x < 10);");
+    assert_eq!(program.body[0].span.end, 33);
+    match &program.body[0].kind {
+        StmtKind::DoWhile {
+            label,
+            body,
+            condition,
+        } => {
+            assert!(label.is_none());
+            assert!(matches!(body.kind, StmtKind::Block(_)));
+            assert!(matches!(condition.kind, ExprKind::Binary { .. }));
+        }
+        _ => panic!("expected a do-while loop"),
+    }
+
+    assert_eq!(
+        parse_ok("do { if (a) break; continue; } while (b);").to_sexpr(),
+        "(do (block (if a (break)) (continue)) b)"
+    );
+    assert_eq!(parse_ok("do x(); while (y);").to_sexpr(), "(do (call x) y)");
+    // Labels and jumps work with nested loops of different kinds.
+    assert_eq!(
+        parse_ok("outer: do { while (a) { continue outer; } } while (b);").to_sexpr(),
+        "(label outer (do (block (while a (block (continue outer)))) b))"
+    );
+    assert_eq!(
+        parse_ok("{ again: do { break again; } while (a); }").to_sexpr(),
+        "(block (label again (do (block (break again)) a)))"
+    );
+}
+
+#[test]
+fn test_do_while_errors() {
+    verify_error_at("do { } (x);", 1, 8);
+    verify_error_at("do { } while x;", 1, 14);
+    verify_error_at("do { } while (x) y();", 1, 18);
+    verify_error_at("do { } while (x); break;", 1, 19);
+    verify_error_at("do { var f = function() { break; }; } while (x);", 1, 27);
+}
+
+#[test]
+fn test_range() {
+    let expr = single_expression("0..n - 1;");
+    assert_eq!(expr.span.start, 0);
+    assert_eq!(expr.span.end, 8);
+    match &expr.kind {
+        ExprKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            assert_eq!(start.kind, ExprKind::Int(0));
+            assert!(matches!(end.kind, ExprKind::Binary { .. }));
+            assert!(!inclusive);
+        }
+        _ => panic!("expected a range"),
+    }
+
+    assert_eq!(single_expression("1..=10;").to_sexpr(), "(..= 1 10)");
+    // Ranges bind looser than comparisons and tighter than the conditional.
+    assert_eq!(single_expression("a < b..c;").to_sexpr(), "(.. (< a b) c)");
+    assert_eq!(
+        single_expression("x ? 0..1 : 1..2;").to_sexpr(),
+        "(? x (.. 0 1) (.. 1 2))"
+    );
+    assert_eq!(single_expression("r = a..b;").to_sexpr(), "(= r (.. a b))");
+    assert_eq!(
+        parse_ok("for (i in 0..len(items)) { print(items[i..=i]); }").to_sexpr(),
+        "(for i (.. 0 (call len items)) (block (call print (index items (..= i i)))))"
+    );
+}
+
+#[test]
+fn test_range_errors() {
+    verify_error_at("1..;", 1, 4);
+    verify_error_at("..2;", 1, 1);
+    verify_error_at("1..2..3;", 1, 5);
+
+    let errors = parse_program("test", "a..b..=c;").unwrap_err();
+    assert_eq!(errors[0].message(), "ranges cannot be chained");
+}
+
+#[test]
+fn test_parse_expression() {
+    let expr = parse_expression("repl", "1 + 2").ok().unwrap();
+    assert_eq!(expr.to_sexpr(), "(+ 1 2)");
+    assert_eq!(expr.span.end, 5);
+    assert_eq!(
+        parse_expression("repl", "x = f(y);")
+            .ok()
+            .unwrap()
+            .to_sexpr(),
+        "(= x (call f y))"
+    );
+    assert_eq!(
+        parse_expression("repl", "{ a: 1 }")
+            .ok()
+            .unwrap()
+            .to_sexpr(),
+        "(map (a 1))"
+    );
+}
+
+#[test]
+fn test_parse_expression_errors() {
+    let errors = parse_expression("repl", "").unwrap_err();
+    assert_eq!(errors[0].message(), "expected expression but found eof");
+    assert_eq!(errors[0].file_name(), "repl");
+
+    let errors = parse_expression("repl", "1 2").unwrap_err();
+    assert_eq!(
+        errors[0].message(),
+        "expected end of expression but found '2'"
+    );
+    assert_eq!(errors[0].column(), 3);
+
+    let errors = parse_expression("repl", "1; 2").unwrap_err();
+    assert_eq!(errors[0].column(), 4);
+    assert!(parse_expression("repl", "var x = 1").is_err());
+    assert!(parse_expression("repl", "1 + @").is_err());
+}
+
+#[test]
+fn test_optional_semicolons() {
+    let program = parse_ok("var a = 1\nprint(a)\nfunction f() { return }\n");
+    assert_eq!(program.body.len(), 3);
+    assert_eq!(program.body[0].span.end, 9);
+    assert_eq!(
+        program.to_sexpr(),
+        "(var a 1)\n(call print a)\n(function f () (return))"
+    );
+
+    // A statement ends at a closing brace or at the end of the source.
+    assert_eq!(
+        parse_ok("while (a) { b(); continue }").to_sexpr(),
+        "(while a (block (call b) (continue)))"
+    );
+    assert_eq!(parse_ok("x = 1").to_sexpr(), "(= x 1)");
+    // Nothing is inserted where the next line carries on the statement.
+    assert_eq!(parse_ok("a = b\n  + c").to_sexpr(), "(= a (+ b c))");
+    assert_eq!(parse_ok("f\n(1)").to_sexpr(), "(call f 1)");
+    // A return, break or continue at the end of a line never takes the
+    // value or label from the next line.
+    assert_eq!(
+        parse_ok("function f() {\n    return\n    g()\n}").to_sexpr(),
+        "(function f () (return) (call g))"
+    );
+    assert_eq!(
+        parse_ok("outer: while (a) {\n    break\n    outer\n}").to_sexpr(),
+        "(label outer (while a (block (break) outer)))"
+    );
+    assert_eq!(
+        parse_ok("do x()\nwhile (y)\nimport m\n").to_sexpr(),
+        "(do (call x) y)\n(import m m)"
+    );
+
+    verify_error_at("a = 1 b = 2", 1, 7);
+    verify_error_at("var x = 1 var y", 1, 11);
+    verify_error_at("function f() { return 1 2 }", 1, 25);
+}
+
+#[test]
+fn test_trailing_commas() {
+    assert_eq!(parse_ok("f(1, 2,);").to_sexpr(), "(call f 1 2)");
+    assert_eq!(
+        parse_ok("var l = [\n    1,\n    2,\n];").to_sexpr(),
+        "(var l (list 1 2))"
+    );
+    assert_eq!(
+        parse_ok("var m = { a: 1, b, };").to_sexpr(),
+        "(var m (map (a 1) (b b)))"
+    );
+    assert_eq!(
+        parse_ok("function f(a, b = 1,) {}").to_sexpr(),
+        "(function f (a (= b 1)))"
+    );
+    assert_eq!(
+        parse_ok("var [a, {b,},] = x;").to_sexpr(),
+        "(var [a {b}] x)"
+    );
+
+    // Only one trailing comma is allowed and never on its own.
+    verify_error_at("f(1,,);", 1, 5);
+    verify_error_at("f(,);", 1, 3);
+    verify_error_at("[,];", 1, 2);
+    verify_error_at("function f(...a,) {}", 1, 15);
+}
+
+#[test]
+fn test_strict_mode() {
+    let strict_errors = |source: &str| {
+        let mut parser = Parser::new("test", source);
+        parser.set_strict(true);
+        parser.parse_program().unwrap_err()
+    };
+
+    let parser = Parser::new("test", "");
+    assert!(!parser.strict());
+
+    let errors = strict_errors("var a = 1\nprint(a);");
+    assert_eq!(errors[0].message(), "expected ';' but found 'print'");
+    assert_eq!(errors[0].line(), 2);
+    assert_eq!(strict_errors("{ a; b }")[0].column(), 8);
+    assert_eq!(
+        strict_errors("a")[0].message(),
+        "expected ';' but found eof"
+    );
+    assert_eq!(strict_errors("f(1,);")[0].column(), 5);
+    assert_eq!(strict_errors("[1,];")[0].column(), 4);
+    assert_eq!(strict_errors("var { a, } = b;")[0].column(), 10);
+
+    // Interpolated expressions follow the same rules.
+    assert_eq!(strict_errors("\"{f(1,)}\";")[0].column(), 7);
+
+    let mut parser = Parser::new("test", "var a = [1, 2];\nprint(a);");
+    parser.set_strict(true);
+    assert!(parser.strict());
+    assert!(parser.parse_program().is_ok());
+}
+
+#[test]
+fn test_node_ids() {
+    use atom::ast::visit::*;
+
+    // Collects the id of every node, including the labels and keys which the
+    // visitor does not walk into.
+    #[derive(Default)]
+    struct Ids(Vec<NodeId>);
+
+    impl Visitor for Ids {
+        fn visit_stmt(&mut self, stmt: &Stmt) {
+            self.0.push(stmt.id);
+            if let StmtKind::While {
+                label: Some(label), ..
+            }
+            | StmtKind::Break(Some(label)) = &stmt.kind
+            {
+                self.0.push(label.id);
+            }
+            walk_stmt(self, stmt);
+        }
+
+        fn visit_expr(&mut self, expr: &Expr) {
+            self.0.push(expr.id);
+            walk_expr(self, expr);
+        }
+
+        fn visit_function(&mut self, decl: &FunctionDecl) {
+            self.0.push(decl.id);
+            walk_function(self, decl);
+        }
+
+        fn visit_class(&mut self, decl: &ClassDecl) {
+            self.0.push(decl.id);
+            walk_class(self, decl);
+        }
+
+        fn visit_pattern(&mut self, pattern: &Pattern) {
+            self.0.push(pattern.id);
+            if let PatternKind::Map(entries) = &pattern.kind {
+                for entry in entries {
+                    if let MapKey::Identifier(key) = &entry.key {
+                        self.0.push(key.id);
+                    }
+                }
+            }
+            walk_pattern(self, pattern);
+        }
+
+        fn visit_identifier(&mut self, ident: &Identifier) {
+            self.0.push(ident.id);
+        }
+    }
+
+    let program = parse_program(
+        "test",
+        "var { x, y: [a] } = p;\n\
+         class A { init(n = 1) { this.n = n; } }\n\
+         outer: while (true) { break outer; }\n\
+         print(\"{x + 1}\", function(...r) { return r; });",
+    )
+    .ok()
+    .unwrap();
+    let mut ids = Ids::default();
+    ids.visit_program(&program);
+
+    // Every node has its own id and they are handed out densely from zero.
+    let mut sorted = ids.0.clone();
+    sorted.sort();
+    let expected: Vec<NodeId> = (0..program.next_id.0).map(NodeId).collect();
+    assert_eq!(sorted, expected);
+    assert_eq!(program.body[0].id, NodeId(0));
+    assert_eq!(Expr::new(ExprKind::Null, Span::default()).id, NodeId::DUMMY);
+
+    let expr = parse_expression("test", "a + f(b)").ok().unwrap();
+    assert_eq!(expr.id, NodeId(0));
+    match &expr.kind {
+        ExprKind::Binary { left, right, .. } => {
+            assert_eq!(left.id, NodeId(1));
+            assert_eq!(right.id, NodeId(2));
+        }
+        kind => panic!("expected a binary expression but found {:?}", kind),
+    }
+}
@@ -0,0 +1,66 @@
+extern crate atom;
+
+use atom::ast::dump::*;
+use atom::ast::*;
+use atom::parse::precedence::*;
+use atom::parse::*;
+use atom::scan::*;
+
+fn dump(source: &str) -> String {
+    match parse_program("test", source) {
+        Ok(program) => program.to_sexpr(),
+        Err(errors) => panic!("unexpected parse error: {}", errors[0].message()),
+    }
+}
+
+#[test]
+fn test_table_lookup() {
+    let plus = binary_operator(TokenType::Plus).unwrap();
+    assert_eq!(plus.op, BinaryOp::Add);
+    assert_eq!(plus.associativity, Associativity::Left);
+    assert!(binary_operator(TokenType::Equals).is_none());
+
+    assert_eq!(unary_operator(TokenType::Bang).unwrap().op, UnaryOp::Not);
+    assert_eq!(unary_operator(TokenType::Not).unwrap().op, UnaryOp::Not);
+    assert!(unary_operator(TokenType::Plus).is_none());
+
+    assert!(precedence(BinaryOp::Multiply) > precedence(BinaryOp::Add));
+    assert!(precedence(BinaryOp::And) > precedence(BinaryOp::Or));
+    assert_eq!(precedence(BinaryOp::Or), LOWEST_PRECEDENCE);
+    for operator in BINARY_OPERATORS {
+        assert!(operator.precedence >= LOWEST_PRECEDENCE);
+        assert!(operator.precedence < UNARY_PRECEDENCE);
+    }
+}
+
+#[test]
+fn test_every_pair_follows_table() {
+    // Try every pair of operators in `a x b y c` and check that the parser
+    // groups them the way the table says it should.
+    for first in BINARY_OPERATORS {
+        for second in BINARY_OPERATORS {
+            let source = format!("a {} b {} c;", first.op.symbol(), second.op.symbol());
+            let groups_left = first.precedence > second.precedence
+                || (first.precedence == second.precedence
+                    && first.associativity == Associativity::Left);
+            let expected = if groups_left {
+                format!("({} ({} a b) c)", second.op.symbol(), first.op.symbol())
+            } else {
+                format!("({} a ({} b c))", first.op.symbol(), second.op.symbol())
+            };
+            assert_eq!(dump(&source[..]), expected, "{}", source);
+        }
+    }
+}
+
+#[test]
+fn test_unary_binds_tightest() {
+    for unary in UNARY_OPERATORS {
+        for binary in BINARY_OPERATORS {
+            let source = format!("{} a {} b;", unary.op.symbol(), binary.op.symbol());
+            let expected = format!("({} ({} a) b)", binary.op.symbol(), unary.op.symbol());
+            assert_eq!(dump(&source[..]), expected, "{}", source);
+        }
+    }
+    assert_eq!(dump("- - a;"), "(- (- a))");
+}
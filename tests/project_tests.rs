@@ -0,0 +1,249 @@
+extern crate atom;
+
+use atom::compile::CompilerOptions;
+use atom::project::*;
+use atom::resolve::*;
+use atom::typeck;
+
+#[test]
+fn test_check_single_file() {
+    let mut project = Project::new();
+    project.add_entry("main.at");
+    project.provide("main.at", "function main() { return 1 + 2; }");
+
+    let summary = project.check_all();
+    assert!(summary.is_ok());
+    assert_eq!(summary.files(), &[String::from("main.at")]);
+    assert_eq!(summary.error_count(), 0);
+}
+
+#[test]
+fn test_diagnostics_are_ordered() {
+    let mut project = Project::new();
+    // Entries are checked in the order they were added but the diagnostics
+    // must come out sorted by file and position.
+    project.add_entry("b.at");
+    project.add_entry("a.at");
+    project.add_entry("a.at");
+    project.provide("a.at", "var = 1;\nvar y = ;");
+    project.provide("b.at", "function (x) {}");
+
+    let summary = project.check_all();
+    assert!(!summary.is_ok());
+    assert_eq!(
+        summary.files(),
+        &[String::from("b.at"), String::from("a.at")]
+    );
+
+    let diagnostics = summary.diagnostics();
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0].file_name(), "a.at");
+    assert_eq!(diagnostics[0].line(), 1);
+    assert_eq!(diagnostics[1].file_name(), "a.at");
+    assert_eq!(diagnostics[1].line(), 2);
+    assert_eq!(diagnostics[2].file_name(), "b.at");
+    assert_eq!(
+        format!("{}", diagnostics[2]),
+        "b.at:1:10: expected function name but found '('"
+    );
+}
+
+#[test]
+fn test_missing_entry() {
+    let mut project = Project::new();
+    project.add_entry("does/not/exist.at");
+
+    let summary = project.check_all();
+    assert!(summary.files().is_empty());
+    assert_eq!(summary.error_count(), 1);
+    assert_eq!(summary.diagnostics()[0].file_name(), "does/not/exist.at");
+}
+
+#[test]
+fn test_manifest() {
+    let dir = std::env::temp_dir().join(format!("atom_manifest_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("game.at"), "function update(dt) {}").unwrap();
+    std::fs::write(dir.join("broken.at"), "update(;").unwrap();
+    std::fs::write(
+        dir.join("atom.manifest"),
+        "# scripts for the game\ngame.at\n\nbroken.at\n",
+    )
+    .unwrap();
+
+    let project = Project::from_manifest(&dir.join("atom.manifest").to_string_lossy()[..])
+        .ok()
+        .unwrap();
+    assert_eq!(project.entries().len(), 2);
+
+    let summary = project.check_all();
+    assert_eq!(summary.files().len(), 2);
+    assert_eq!(summary.error_count(), 1);
+    assert!(summary.diagnostics()[0].file_name().ends_with("broken.at"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(Project::from_manifest(&dir.join("atom.manifest").to_string_lossy()[..]).is_err());
+}
+
+#[test]
+fn test_warnings_do_not_fail() {
+    let mut project = Project::new();
+    project.add_entry("main.at");
+    project.provide(
+        "main.at",
+        "function main() {\n    var unused = 1;\n    return 2;\n    main();\n}",
+    );
+
+    let summary = project.check_all();
+    assert!(summary.is_ok());
+    assert_eq!(summary.error_count(), 0);
+    assert_eq!(summary.warning_count(), 2);
+    assert_eq!(
+        format!("{}", summary.diagnostics()[0]),
+        "main.at:2:9: unused variable unused"
+    );
+    assert!(summary.diagnostics()[1].is_warning());
+}
+
+#[test]
+fn test_dead_code_warnings() {
+    let mut project = Project::new();
+    project.add_entry("main.at");
+    project.provide(
+        "main.at",
+        "function main() {\n    if (1 > 2) { main(); }\n    return 1;\n    main();\n}",
+    );
+
+    // Code after a return is found by the resolver too but only reported
+    // once.
+    let summary = project.check_all();
+    assert!(summary.is_ok());
+    assert_eq!(summary.warning_count(), 2);
+    assert_eq!(
+        format!("{}", summary.diagnostics()[0]),
+        "main.at:2:16: unreachable code"
+    );
+    assert_eq!(
+        format!("{}", summary.diagnostics()[1]),
+        "main.at:4:5: unreachable code"
+    );
+}
+
+#[test]
+fn test_compile_errors() {
+    let mut project = Project::new();
+    project.add_entry("main.at");
+    project.provide(
+        "main.at",
+        "function f(a) { return a; }\nf(1, 2);\nfunction g(b) { var b = 1; }",
+    );
+
+    // Errors that only the compiler finds are reported, and the ones the
+    // resolver finds as well are only reported once.
+    let summary = project.check_all();
+    assert_eq!(summary.error_count(), 2);
+    assert_eq!(
+        format!("{}", summary.diagnostics()[0]),
+        "main.at:2:1: f expects 1 argument but got 2"
+    );
+    assert_eq!(
+        format!("{}", summary.diagnostics()[1]),
+        "main.at:3:21: b is already declared in this block"
+    );
+}
+
+#[test]
+fn test_lints() {
+    let mut project = Project::new();
+    project.add_entry("main.at");
+    project.provide(
+        "main.at",
+        "function f(hp) {\n    if (hp) {\n        var hp = 1;\n        return hp;\n    }\n}",
+    );
+    assert_eq!(project.lints(), Lints::default());
+    assert_eq!(project.check_all().warning_count(), 0);
+
+    project.set_lints(Lints { shadowing: true });
+    let summary = project.check_all();
+    assert!(summary.is_ok());
+    assert_eq!(summary.warning_count(), 1);
+    assert_eq!(
+        summary.diagnostics()[0].message(),
+        "declaration of hp shadows a parameter"
+    );
+}
+
+#[test]
+fn test_type_check() {
+    let mut project = Project::new();
+    project.add_entry("main.at");
+    project.provide("main.at", "var hp: number = \"full\";");
+    assert_eq!(project.type_check(), None);
+    assert!(project.check_all().diagnostics().is_empty());
+
+    project.set_type_check(Some(typeck::Mode::Lenient));
+    let summary = project.check_all();
+    assert!(summary.is_ok());
+    assert_eq!(summary.warning_count(), 1);
+
+    project.set_type_check(Some(typeck::Mode::Strict));
+    let summary = project.check_all();
+    assert!(!summary.is_ok());
+    assert_eq!(
+        summary.diagnostics()[0].message(),
+        "expected number but found string"
+    );
+}
+
+#[test]
+fn test_imports_are_followed() {
+    let mut project = Project::new();
+    project.add_entry("game/main.at");
+    project.provide(
+        "game/main.at",
+        "import math;\nimport 'lib/vector.at' as v;\nimport math as m;",
+    );
+    project.provide("game/math.at", "export function sqrt(x) {}");
+    project.provide("game/lib/vector.at", "import 'missing.at';");
+
+    let summary = project.check_all();
+    assert_eq!(
+        summary.files(),
+        &[
+            String::from("game/main.at"),
+            String::from("game/math.at"),
+            String::from("game/lib/vector.at"),
+        ]
+    );
+
+    // The missing module is reported where it was imported.
+    assert_eq!(summary.error_count(), 1);
+    assert_eq!(
+        format!("{}", summary.diagnostics()[0]),
+        "game/lib/vector.at:1:1: cannot find module game/lib/missing.at"
+    );
+}
+
+#[test]
+fn test_import_cycles() {
+    let mut project = Project::new();
+    project.add_entry("a.at");
+    project.add_entry("b.at");
+    project.provide("a.at", "import \"b.at\";\nimport \"c.at\";");
+    project.provide("b.at", "import \"a.at\";");
+    project.provide("c.at", "var c = 1;");
+
+    // A cycle is reported the same way a build reports it, once, however
+    // many entry points lead to it.
+    let summary = project.check_all();
+    assert_eq!(summary.error_count(), 1);
+    assert_eq!(
+        format!("{}", summary.diagnostics()[0]),
+        "b.at:1:1: import cycle: a.at -> b.at -> a.at"
+    );
+    let build = project.build("a.at", &CompilerOptions::default());
+    assert_eq!(
+        build.err().unwrap()[0].to_string(),
+        summary.diagnostics()[0].to_string()
+    );
+}
@@ -0,0 +1,113 @@
+extern crate atom;
+
+use atom::ast::dump::*;
+use atom::fold::*;
+use atom::parse::*;
+use atom::prune::*;
+
+fn prune(source: &str) -> (String, Vec<(u32, u32)>) {
+    let mut program = match parse_program("test", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("unexpected parse error: {}", errors[0].message()),
+    };
+    fold_constants(&mut program);
+    let warnings = prune_dead_code(&mut program)
+        .iter()
+        .map(|w| {
+            assert!(w.is_warning());
+            assert_eq!(w.message(), "unreachable code");
+            (w.line(), w.column())
+        })
+        .collect();
+    (program.to_sexpr(), warnings)
+}
+
+#[test]
+fn test_code_after_jumps() {
+    assert_eq!(
+        prune("function f() { return 1; print(2); print(3); }"),
+        (String::from("(function f () (return 1))"), vec![(1, 26)])
+    );
+    assert_eq!(
+        prune("while (x) {\n    break;\n    x = 1;\n}"),
+        (String::from("(while x (block (break)))"), vec![(3, 5)])
+    );
+    // A statement jumps away when every way through it does.
+    assert_eq!(
+        prune("function f(a) {\n    if (a) { return 1; } else { return 2; }\n    return 3;\n}"),
+        (
+            String::from("(function f (a) (if a (block (return 1)) (block (return 2))))"),
+            vec![(3, 5)]
+        )
+    );
+    assert_eq!(
+        prune("function f(a) { if (a) { return 1; } return 2; }"),
+        (
+            String::from("(function f (a) (if a (block (return 1))) (return 2))"),
+            vec![]
+        )
+    );
+    // Lambdas are pruned as well.
+    assert_eq!(
+        prune("var f = function() { return; 1; };"),
+        (String::from("(var f (lambda () (return)))"), vec![(1, 30)])
+    );
+}
+
+#[test]
+fn test_constant_conditions() {
+    assert_eq!(
+        prune("if (1 > 2) { a(); } else b();"),
+        (String::from("(block (call b))"), vec![(1, 12)])
+    );
+    assert_eq!(
+        prune("if (true) a(); else { b(); }"),
+        (String::from("(block (call a))"), vec![(1, 21)])
+    );
+    assert_eq!(
+        prune("if (false) { a(); }"),
+        (String::from("(block)"), vec![(1, 12)])
+    );
+    assert_eq!(
+        prune("while (false) { a(); }"),
+        (String::from("(block)"), vec![(1, 15)])
+    );
+    // Conditions that depend on the runtime are left alone.
+    assert_eq!(
+        prune("if (x) a();"),
+        (String::from("(if x (call a))"), vec![])
+    );
+}
+
+#[test]
+fn test_endless_loops() {
+    assert_eq!(
+        prune("while (true) { a(); }\nb();"),
+        (String::from("(while true (block (call a)))"), vec![(2, 1)])
+    );
+    // A break that leaves the loop means the code after it can run.
+    assert_eq!(
+        prune("while (true) { if (x) break; }\nb();"),
+        (
+            String::from("(while true (block (if x (break))))\n(call b)"),
+            vec![]
+        )
+    );
+    assert_eq!(
+        prune("outer: while (true) { while (true) { break outer; } }\nb();"),
+        (
+            String::from(
+                "(label outer (while true (block (while true (block (break outer))))))\n(call b)"
+            ),
+            vec![]
+        )
+    );
+    // A break in a nested loop only leaves that loop.
+    assert_eq!(
+        prune("while (true) { while (true) { break; } }\nb();"),
+        (
+            String::from("(while true (block (while true (block (break)))))"),
+            vec![(2, 1)]
+        )
+    );
+}
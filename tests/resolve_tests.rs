@@ -0,0 +1,425 @@
+extern crate atom;
+
+use atom::ast::*;
+use atom::error::*;
+use atom::parse::*;
+use atom::resolve::*;
+
+fn warnings(source: &str) -> Vec<(String, u32, u32)> {
+    let program = match parse_program("test", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("unexpected parse error: {}", errors[0].message()),
+    };
+    resolve(&program)
+        .iter()
+        .map(|w| {
+            assert_eq!(w.severity(), Severity::Warning);
+            assert_eq!(w.file_name(), "test");
+            (String::from(w.message()), w.line(), w.column())
+        })
+        .collect()
+}
+
+fn warning(msg: &str, line: u32, column: u32) -> (String, u32, u32) {
+    (String::from(msg), line, column)
+}
+
+#[test]
+fn test_unused_variables() {
+    assert_eq!(
+        warnings("function f() {\n    var x = 1;\n    return 2;\n}"),
+        vec![warning("unused variable x", 2, 9)]
+    );
+    assert_eq!(warnings("function f() { var x = 1; return x; }"), vec![]);
+    // Globals can be used by other modules so they are never reported.
+    assert_eq!(warnings("var x = 1;"), vec![]);
+    // Parameters, loop variables and names starting with an underscore are
+    // allowed to go unused.
+    assert_eq!(
+        warnings("function f(a, b) { for (e in a) { var _skip = 1; } }"),
+        vec![]
+    );
+    assert_eq!(
+        warnings("var f = function() { var y; var z; return z; };"),
+        vec![warning("unused variable y", 1, 26)]
+    );
+}
+
+#[test]
+fn test_reads_and_writes() {
+    // Assigning to a variable is not the same as reading it.
+    assert_eq!(
+        warnings("{ var hp = 10; hp = 5; }"),
+        vec![warning("unused variable hp", 1, 7)]
+    );
+    assert_eq!(warnings("{ var hp = 10; hp -= 5; }"), vec![]);
+    assert_eq!(warnings("{ var a = { hp: 1 }; a.hp = 5; }"), vec![]);
+    assert_eq!(
+        warnings("{ var a = 1; var b = 2; a, b = 3, a; }"),
+        vec![warning("unused variable b", 1, 18)]
+    );
+    // Reads in nested functions count.
+    assert_eq!(
+        warnings("{ var n = 0; var inc = function() { n += 1; }; inc(); }"),
+        vec![]
+    );
+    // A shadowing declaration does not mark the outer one as read.
+    assert_eq!(
+        warnings("{ var x = 1; { var x = 2; print(x); } }"),
+        vec![warning("unused variable x", 1, 7)]
+    );
+    // The initializer refers to the outer name.
+    assert_eq!(
+        warnings("{ var x = 1; { var x = x; } }"),
+        vec![warning("unused variable x", 1, 20)]
+    );
+}
+
+#[test]
+fn test_unreachable_code() {
+    assert_eq!(
+        warnings("function f() {\n    return 1;\n    print(2);\n    print(3);\n}"),
+        vec![warning("unreachable code", 3, 5)]
+    );
+    assert_eq!(
+        warnings("while (true) { break; update(); }"),
+        vec![warning("unreachable code", 1, 23)]
+    );
+    assert_eq!(
+        warnings("for (e in all) { if (e) { continue; } e.update(); }"),
+        vec![]
+    );
+    assert_eq!(warnings("function f() { return; }"), vec![]);
+}
+
+#[test]
+fn test_decorators() {
+    // Decorators are used where the declaration is, not inside of it.
+    assert_eq!(
+        warnings("function f() {\n    var log = 1;\n    @trace(log) function g(log) {}\n    return g;\n}"),
+        vec![]
+    );
+    assert_eq!(
+        warnings("function f() {\n    var log = 1;\n    class A { @log m() {} }\n    return A;\n}"),
+        vec![]
+    );
+}
+
+#[test]
+fn test_match_cases() {
+    assert_eq!(
+        warnings("match x {\n  case 1, 2: f();\n  case -1, 2: g();\n  case 1: h();\n}"),
+        vec![
+            warning("duplicate match case 2", 3, 12),
+            warning("duplicate match case 1", 4, 8),
+        ]
+    );
+    assert_eq!(
+        warnings("match x { case \"a\", null: f(); case \"a\", \"b\", null: g(); }"),
+        vec![
+            warning("duplicate match case \"a\"", 1, 37),
+            warning("duplicate match case null", 1, 47),
+        ]
+    );
+    // The note points at the value that was matched first.
+    let program = parse_program("test", "match x {\n  case 1: f();\n  case 1: g();\n}")
+        .ok()
+        .unwrap();
+    let diagnostics = resolve(&program);
+    let related = &diagnostics[0].related()[0];
+    assert_eq!(
+        (related.message(), related.line(), related.column()),
+        ("first matched here", 2, 8)
+    );
+
+    // A match on a bool should handle both values unless it has a default.
+    assert_eq!(
+        warnings("match x > 1 { case true: f(); }"),
+        vec![warning("match is missing a case for false", 1, 1)]
+    );
+    assert_eq!(
+        warnings("match ready { case false: wait(); }"),
+        vec![warning("match is missing a case for true", 1, 1)]
+    );
+    assert_eq!(
+        warnings("match not ready {}"),
+        vec![
+            warning("match is missing a case for true", 1, 1),
+            warning("match is missing a case for false", 1, 1),
+        ]
+    );
+    assert_eq!(
+        warnings("match ready { case true: go(); case false: wait(); }"),
+        vec![]
+    );
+    assert_eq!(
+        warnings("match x == y { case true: go(); default: wait(); }"),
+        vec![]
+    );
+    // Other subjects can have any number of values.
+    assert_eq!(warnings("match x { case 1: f(); case 2: g(); }"), vec![]);
+}
+
+#[test]
+fn test_destructuring() {
+    assert_eq!(
+        warnings("function f(p) { var [a, b] = p; return a; }"),
+        vec![warning("unused variable b", 1, 25)]
+    );
+    assert_eq!(
+        warnings("function f(p) { var {x, y: top} = p; return x + top; }"),
+        vec![]
+    );
+    // Storing into names with a pattern does not read them.
+    assert_eq!(
+        warnings("{ var a = 1; var b = 2; [a, b] = p; print(b); }"),
+        vec![warning("unused variable a", 1, 7)]
+    );
+    assert_eq!(warnings("{ var hero = h; [hero.x] = p; }"), vec![]);
+}
+
+#[test]
+fn test_try_scopes() {
+    assert_eq!(
+        warnings("function f() { try { risky(); } catch (e) {} }"),
+        vec![]
+    );
+    assert_eq!(
+        warnings("function f() { try { var x = 1; } finally { var y = 2; } }"),
+        vec![
+            warning("unused variable x", 1, 26),
+            warning("unused variable y", 1, 49)
+        ]
+    );
+    assert_eq!(
+        warnings("function f() { try { return 1; risky(); } catch {} }"),
+        vec![warning("unreachable code", 1, 32)]
+    );
+}
+
+#[test]
+fn test_parameter_defaults() {
+    // Defaults can use the parameters before them and the rest parameter
+    // is never reported, just like the others.
+    assert_eq!(
+        warnings("function f() { var x = 1; return function(a, b = a + x, ...c) {}; }"),
+        vec![]
+    );
+}
+
+#[test]
+fn test_bindings() {
+    let program = parse_program(
+        "test",
+        "var g = 1;\nfunction f(a) {\n    var b = a;\n    b = g;\n    return b;\n}",
+    )
+    .ok()
+    .unwrap();
+    let resolution = resolve_program(&program);
+    assert!(resolution.diagnostics.is_empty());
+
+    let decl = match &program.body[1].kind {
+        StmtKind::Function(decl) => decl,
+        kind => panic!("expected a function but found {:?}", kind),
+    };
+    let param = decl.params[0].id;
+    let (local, read) = match &decl.body[0].kind {
+        StmtKind::Var {
+            name,
+            init: Some(init),
+            ..
+        } => (name.id, init.id),
+        kind => panic!("expected a variable but found {:?}", kind),
+    };
+    let (target, global) = match &decl.body[1].kind {
+        StmtKind::Expression(Expr {
+            kind: ExprKind::Assign { target, value, .. },
+            ..
+        }) => (target.id, value.id),
+        kind => panic!("expected an assignment but found {:?}", kind),
+    };
+
+    // Locals are bound to the identifier that declared them, both when they
+    // are read and when they are stored into. Globals are left unbound.
+    assert_eq!(resolution.bindings.get(&read), Some(&param));
+    assert_eq!(resolution.bindings.get(&target), Some(&local));
+    assert_eq!(resolution.bindings.get(&global), None);
+    assert_eq!(resolution.bindings.len(), 3);
+}
+
+#[test]
+fn test_constants() {
+    let errors = |source: &str| -> Vec<(String, u32, u32)> {
+        let program = parse_program("test", source).ok().unwrap();
+        resolve(&program)
+            .iter()
+            .filter(|e| e.severity() == Severity::Error)
+            .map(|e| (String::from(e.message()), e.line(), e.column()))
+            .collect()
+    };
+    let error = |msg: &str, line, column| (String::from(msg), line, column);
+
+    assert_eq!(
+        errors("{ const max = 1; max = 2; print(max); }"),
+        vec![error("cannot assign to constant max", 1, 18)]
+    );
+    assert_eq!(
+        errors("{ const max = 1; max += 2; }"),
+        vec![error("cannot assign to constant max", 1, 18)]
+    );
+    assert_eq!(
+        errors("{ const [a, b] = pair; [a, c] = pair; b, d = 1, 2; }"),
+        vec![
+            error("cannot assign to constant a", 1, 25),
+            error("cannot assign to constant b", 1, 39)
+        ]
+    );
+
+    // Top level constants are checked everywhere, even before they are
+    // declared.
+    assert_eq!(
+        errors("function f() { MAX = 2; }\nexport const MAX = 1;\nMAX = 3;"),
+        vec![
+            error("cannot assign to constant MAX", 1, 16),
+            error("cannot assign to constant MAX", 3, 1)
+        ]
+    );
+
+    // A variable with the same name hides the constant.
+    assert_eq!(
+        errors("const x = 1; { var x = 2; x = 3; print(x); }"),
+        vec![]
+    );
+    assert_eq!(
+        errors("{ const x = 1; function f(x) { x = 2; } f(x); }"),
+        vec![]
+    );
+
+    // Members and items of a constant can still be changed.
+    assert_eq!(errors("const p = {}; p.x = 1; p[0] = 2;"), vec![]);
+}
+
+#[test]
+fn test_redeclarations() {
+    let errors = |source: &str| -> Vec<(String, u32, u32, u32, u32)> {
+        let program = parse_program("test", source).ok().unwrap();
+        resolve(&program)
+            .iter()
+            .filter(|e| e.severity() == Severity::Error)
+            .map(|e| {
+                let related = &e.related()[0];
+                (
+                    String::from(e.message()),
+                    e.line(),
+                    e.column(),
+                    related.line(),
+                    related.column(),
+                )
+            })
+            .collect()
+    };
+    let error = |name: &str, line, column, earlier_line, earlier_column| {
+        let msg = format!("{} is already declared in this block", name);
+        (msg, line, column, earlier_line, earlier_column)
+    };
+
+    // The parameters are in the same block as the body of the function.
+    assert_eq!(
+        errors(
+            "function s(p) {
+    var p = 2;
+    var q = 1;
+    var q = 3;
+    return p + q;
+}"
+        ),
+        vec![error("p", 2, 9, 1, 12), error("q", 4, 9, 3, 9)]
+    );
+    assert_eq!(
+        errors("var f = function(a) { function a() {} };"),
+        vec![error("a", 1, 32, 1, 18)]
+    );
+    assert_eq!(
+        errors("try {} catch (e) { var e = 1; }"),
+        vec![error("e", 1, 24, 1, 15)]
+    );
+
+    // Inner blocks and globals can declare a name again.
+    assert_eq!(
+        errors("function f(a) { { var a = 1; { var a = 2; } } }"),
+        vec![]
+    );
+    assert_eq!(errors("var x = 1; var x = 2;"), vec![]);
+}
+
+#[test]
+fn test_shadowing() {
+    let shadowing = |source: &str| -> Vec<(String, u32, u32, u32, u32)> {
+        let program = parse_program("test", source).ok().unwrap();
+        resolve_program_with(&program, Lints { shadowing: true })
+            .diagnostics
+            .iter()
+            .filter(|w| w.message().contains("shadows"))
+            .map(|w| {
+                assert!(w.is_warning());
+                let related = &w.related()[0];
+                assert_eq!(related.message(), "x is declared here");
+                (
+                    String::from(w.message()),
+                    w.line(),
+                    w.column(),
+                    related.line(),
+                    related.column(),
+                )
+            })
+            .collect()
+    };
+    let outer = |line, column, outer_line, outer_column| {
+        (
+            String::from("declaration of x shadows an outer variable"),
+            line,
+            column,
+            outer_line,
+            outer_column,
+        )
+    };
+
+    assert_eq!(
+        shadowing("{ var x = 1;\n  { var x = 2; print(x); } print(x); }"),
+        vec![outer(2, 9, 1, 7)]
+    );
+    assert_eq!(
+        shadowing("function f(x) {\n  if (x) { var x = 1; return x; }\n}"),
+        vec![(
+            String::from("declaration of x shadows a parameter"),
+            2,
+            16,
+            1,
+            12
+        )]
+    );
+    // Parameters, loop variables and functions can all shadow.
+    assert_eq!(
+        shadowing("{ var x = 1; var f = function(x) { return x; }; f(x); }"),
+        vec![outer(1, 31, 1, 7)]
+    );
+    assert_eq!(
+        shadowing("{ var x = [1]; for (x in x) print(x); }"),
+        vec![outer(1, 21, 1, 7)]
+    );
+    assert_eq!(
+        shadowing("{ var x = 1; { function x() {} } print(x); }"),
+        vec![outer(1, 25, 1, 7)]
+    );
+
+    // Declaring a name again in the same scope is an error rather than
+    // shadowing, and globals are left alone.
+    assert_eq!(shadowing("{ var x = 1; var x = x; print(x); }"), vec![]);
+    assert_eq!(shadowing("var x = 1; { var x = 2; print(x); }"), vec![]);
+
+    // The lint is off unless asked for.
+    let program = parse_program("test", "function f(x) { { var x = 1; return x; } }")
+        .ok()
+        .unwrap();
+    assert!(resolve(&program).is_empty());
+}
@@ -34,8 +34,8 @@ fn verify_list(scanner: &mut Scanner, expected: &Vec<Token>, ignore_pos: bool) {
     for tok in expected {
         let actual = scanner.next().unwrap();
         match actual {
-            Ok(a) => verify_token(&a, &tok, ignore_pos),
-            Err(e) => panic!(e),
+            Ok(a) => verify_token(&a, tok, ignore_pos),
+            Err(e) => panic!("{}", e.message()),
         }
     }
 
@@ -223,3 +223,143 @@ fn test_identifier() {
         false,
     );
 }
+
+#[test]
+fn test_source_location_constants() {
+    let mut scanner: Scanner = Scanner::new("logger.at", "__file\n  __line __function __files");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::StringLiteral, "logger.at", 1, 1, "logger.at"),
+            Token::new(TokenType::NumberLiteral, "logger.at", 2, 3, "2"),
+            Token::new(
+                TokenType::FunctionNameLiteral,
+                "logger.at",
+                2,
+                10,
+                "__function",
+            ),
+            Token::new(TokenType::Identifier, "logger.at", 2, 21, "__files"),
+        ],
+        false,
+    );
+}
+
+#[test]
+fn test_comparison_operators() {
+    let mut scanner = Scanner::new("test", "== != <= >= = < > !");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::EqualsEquals, "test", 1, 1, "=="),
+            Token::new(TokenType::BangEquals, "test", 1, 4, "!="),
+            Token::new(TokenType::LessEquals, "test", 1, 7, "<="),
+            Token::new(TokenType::GreaterEquals, "test", 1, 10, ">="),
+            Token::new(TokenType::Equals, "test", 1, 13, "="),
+            Token::new(TokenType::LessThan, "test", 1, 15, "<"),
+            Token::new(TokenType::GreaterThan, "test", 1, 17, ">"),
+            Token::new(TokenType::Bang, "test", 1, 19, "!"),
+        ],
+        false,
+    );
+}
+
+#[test]
+fn test_colon() {
+    let mut scanner = Scanner::new("test", "a: 1");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::Identifier, "test", 1, 1, "a"),
+            Token::new(TokenType::Colon, "test", 1, 2, ":"),
+            Token::new(TokenType::NumberLiteral, "test", 1, 4, "1"),
+        ],
+        false,
+    );
+}
+
+#[test]
+fn test_question() {
+    let mut scanner = Scanner::new("test", "a ? b : c");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::Identifier, "test", 1, 1, "a"),
+            Token::new(TokenType::Question, "test", 1, 3, "?"),
+            Token::new(TokenType::Identifier, "test", 1, 5, "b"),
+            Token::new(TokenType::Colon, "test", 1, 7, ":"),
+            Token::new(TokenType::Identifier, "test", 1, 9, "c"),
+        ],
+        false,
+    );
+}
+
+#[test]
+fn test_at() {
+    let mut scanner = Scanner::new("test", "@memoize");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::At, "test", 1, 1, "@"),
+            Token::new(TokenType::Identifier, "test", 1, 2, "memoize"),
+        ],
+        false,
+    );
+}
+
+#[test]
+fn test_compound_assignment_operators() {
+    let mut scanner = Scanner::new("test", "+= -= *= /= %= &= |= ^=");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::PlusEquals, "test", 1, 1, "+="),
+            Token::new(TokenType::MinusEquals, "test", 1, 4, "-="),
+            Token::new(TokenType::StarEquals, "test", 1, 7, "*="),
+            Token::new(TokenType::SlashEquals, "test", 1, 10, "/="),
+            Token::new(TokenType::PercentEquals, "test", 1, 13, "%="),
+            Token::new(TokenType::AmpersandEquals, "test", 1, 16, "&="),
+            Token::new(TokenType::PipeEquals, "test", 1, 19, "|="),
+            Token::new(TokenType::CaretEquals, "test", 1, 22, "^="),
+        ],
+        false,
+    );
+}
+
+#[test]
+fn test_ellipsis() {
+    let mut scanner = Scanner::new("test", "...rest a.b ..");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::Ellipsis, "test", 1, 1, "..."),
+            Token::new(TokenType::Identifier, "test", 1, 4, "rest"),
+            Token::new(TokenType::Identifier, "test", 1, 9, "a"),
+            Token::new(TokenType::Dot, "test", 1, 10, "."),
+            Token::new(TokenType::Identifier, "test", 1, 11, "b"),
+            Token::new(TokenType::DotDot, "test", 1, 13, ".."),
+        ],
+        false,
+    );
+}
+
+#[test]
+fn test_ranges() {
+    let mut scanner = Scanner::new("test", "1..10 0..=n 1.5.. 2.");
+    verify_list(
+        &mut scanner,
+        &vec![
+            Token::new(TokenType::NumberLiteral, "test", 1, 1, "1"),
+            Token::new(TokenType::DotDot, "test", 1, 2, ".."),
+            Token::new(TokenType::NumberLiteral, "test", 1, 4, "10"),
+            Token::new(TokenType::NumberLiteral, "test", 1, 7, "0"),
+            Token::new(TokenType::DotDotEquals, "test", 1, 8, "..="),
+            Token::new(TokenType::Identifier, "test", 1, 11, "n"),
+            Token::new(TokenType::NumberLiteral, "test", 1, 13, "1.5"),
+            Token::new(TokenType::DotDot, "test", 1, 16, ".."),
+            Token::new(TokenType::NumberLiteral, "test", 1, 19, "2"),
+            Token::new(TokenType::Dot, "test", 1, 20, "."),
+        ],
+        false,
+    );
+}
@@ -0,0 +1,239 @@
+extern crate atom;
+
+use atom::ast::*;
+use atom::error::*;
+use atom::parse::*;
+use atom::typeck::*;
+
+fn parse(source: &str) -> Program {
+    match parse_program("test", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("unexpected parse error: {}", errors[0].message()),
+    }
+}
+
+fn warnings(source: &str) -> Vec<(String, u32, u32)> {
+    check(&parse(source))
+        .iter()
+        .map(|w| {
+            assert_eq!(w.severity(), Severity::Warning);
+            (String::from(w.message()), w.line(), w.column())
+        })
+        .collect()
+}
+
+fn warning(msg: &str, line: u32, column: u32) -> (String, u32, u32) {
+    (String::from(msg), line, column)
+}
+
+#[test]
+fn test_annotated_variables() {
+    assert_eq!(warnings("var x: number = 1;"), vec![]);
+    assert_eq!(
+        warnings("var x: number = \"one\";"),
+        vec![warning("expected number but found string", 1, 17)]
+    );
+    assert_eq!(
+        warnings("var x: list<number> = [\"one\", \"two\"];"),
+        vec![warning(
+            "expected list<number> but found list<string>",
+            1,
+            23
+        )]
+    );
+    assert_eq!(
+        warnings("var m: map<string, number> = { a: 1 };\nm = { a: true };"),
+        vec![warning(
+            "expected map<string, number> but found map<string, bool>",
+            2,
+            5
+        )]
+    );
+    // Null can stand in for any type.
+    assert_eq!(warnings("var s: string = null;"), vec![]);
+}
+
+#[test]
+fn test_unannotated_code_is_dynamic() {
+    assert_eq!(warnings("var x = 1 + \"a\" - true;"), vec![]);
+    assert_eq!(
+        warnings("function f(a) { var x = 1; x = \"one\"; return -\"a\"; }"),
+        vec![]
+    );
+    // Calls from unannotated code are not checked either.
+    assert_eq!(warnings("function f(a: number) {}\nf(\"one\");"), vec![]);
+}
+
+#[test]
+fn test_annotated_functions() {
+    assert_eq!(
+        warnings("function f(a: number): number { return a * 2; }"),
+        vec![]
+    );
+    assert_eq!(
+        warnings("function f(a: number): string { return a; }"),
+        vec![warning("expected to return string but found number", 1, 40)]
+    );
+    assert_eq!(
+        warnings("function f(a: string) { return a - 1; }"),
+        vec![warning("cannot apply - to string and number", 1, 32)]
+    );
+    // Adding anything to a string makes a string.
+    assert_eq!(
+        warnings("function f(a: number): string { return \"a\" + a; }"),
+        vec![]
+    );
+    // Locals without annotations get the type of the value they start with.
+    assert_eq!(
+        warnings("function f(): bool {\n    var x = 1;\n    x = \"one\";\n    return true;\n}"),
+        vec![warning("expected number but found string", 3, 9)]
+    );
+    // Unless that value is null.
+    assert_eq!(
+        warnings("function f(): bool { var x = null; x = 1; return true; }"),
+        vec![]
+    );
+}
+
+#[test]
+fn test_calls() {
+    let source = "function add(a: number, b: number): number { return a + b; }
+function g(s: string) {
+    add(1, s);
+    add(b: true, a: 2);
+    var n: number = add(1, 2);
+    var t: string = add(1, 2);
+}";
+    assert_eq!(
+        warnings(source),
+        vec![
+            warning("expected number for argument b but found string", 3, 12),
+            warning("expected number for argument b but found bool", 4, 12),
+            warning("expected string but found number", 6, 21),
+        ]
+    );
+    assert_eq!(
+        warnings("function f(g: function<number, bool>): bool { return g(\"x\"); }"),
+        vec![warning(
+            "expected number for argument 1 but found string",
+            1,
+            56
+        )]
+    );
+    assert_eq!(
+        warnings("function f(n: number) { n(); }"),
+        vec![warning("cannot call number", 1, 25)]
+    );
+}
+
+#[test]
+fn test_missing_returns() {
+    assert_eq!(
+        warnings("function f(a: number): number {\n    if (a > 0) { return 1; }\n}"),
+        vec![warning("f can end without returning number", 1, 1)]
+    );
+    assert_eq!(
+        warnings("function f(a: number): number { if (a > 0) { return 1; } else { return 2; } }"),
+        vec![]
+    );
+    assert_eq!(
+        warnings("function f(a: number): number { return; }"),
+        vec![warning("expected to return number but found null", 1, 33),]
+    );
+    // Functions that may return null can fall off the end.
+    assert_eq!(warnings("function f(a: number): any { }"), vec![]);
+}
+
+#[test]
+fn test_classes_and_lambdas() {
+    let source = "class Shape {}
+class Circle extends Shape {}
+function area(s: Shape): number { return 0; }
+function g(): number {
+    area(Circle());
+    area(1);
+    var h = function(x: number): number { return x; };
+    return h(\"a\");
+}";
+    assert_eq!(
+        warnings(source),
+        vec![
+            warning("expected Shape for argument s but found number", 6, 10),
+            warning("expected number for argument x but found string", 8, 14),
+        ]
+    );
+}
+
+#[test]
+fn test_decorators() {
+    // A decorator can turn a function into anything, so calls to it are not
+    // checked, but its body still is.
+    let source = "@memoize function f(n: number): number { return \"n\"; }
+function g(): number {
+    return f(\"x\");
+}";
+    assert_eq!(
+        warnings(source),
+        vec![warning("expected to return number but found string", 1, 49)]
+    );
+}
+
+#[test]
+fn test_loops_and_indexing() {
+    let source = "function f(items: list<string>, m: map<string, number>): number {
+    for (item in items) { var n: number = item; }
+    for (i in 0..10) { var n: number = i; }
+    var s: string = items[\"first\"];
+    return m[\"a\"];
+}";
+    assert_eq!(
+        warnings(source),
+        vec![
+            warning("expected number but found string", 2, 43),
+            warning("cannot index list<string> with string", 4, 27),
+        ]
+    );
+}
+
+#[test]
+fn test_unknown_types() {
+    assert_eq!(
+        warnings("var x: numbr = 1;\nvar y: list<number, string> = [];"),
+        vec![
+            warning("unknown type numbr", 1, 8),
+            warning("wrong number of type arguments for list", 2, 8),
+        ]
+    );
+
+    // Ints and floats are both numbers.
+    assert_eq!(
+        warnings("var i: int = 1.5;\nvar f: list<float> = [1, 2];\nvar t: int = \"s\";"),
+        vec![warning("expected number but found string", 3, 14)]
+    );
+}
+
+#[test]
+fn test_strict_mode() {
+    let program = parse("var x: number = \"one\";");
+    let typing = check_program(&program, Mode::Strict);
+    assert_eq!(typing.diagnostics.len(), 1);
+    assert_eq!(typing.diagnostics[0].severity(), Severity::Error);
+}
+
+#[test]
+fn test_expression_types() {
+    let program = parse("function f(a: number): list<number> { return [a, 2]; }");
+    let typing = check_program(&program, Mode::Lenient);
+    let list = match &program.body[0].kind {
+        StmtKind::Function(decl) => match &decl.body[0].kind {
+            StmtKind::Return(Some(value)) => value,
+            _ => panic!("expected a return"),
+        },
+        _ => panic!("expected a function"),
+    };
+    assert_eq!(
+        typing.types.get(&list.id),
+        Some(&Type::List(Box::new(Type::Number)))
+    );
+    assert_eq!(typing.types[&list.id].to_string(), "list<number>");
+}
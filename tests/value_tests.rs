@@ -0,0 +1,324 @@
+extern crate atom;
+
+use atom::bytecode::Constant;
+use atom::value::gc::GcConfig;
+use atom::value::*;
+use std::sync::Arc;
+
+#[test]
+fn test_truthiness() {
+    assert!(!Value::Null.is_truthy());
+    assert!(!Value::from(false).is_truthy());
+    assert!(Value::from(true).is_truthy());
+    assert!(Value::from(0.0).is_truthy());
+    assert!(Value::from("").is_truthy());
+    let mut heap = Heap::new();
+    assert!(Value::from(heap.alloc(Object::List(Vec::new()))).is_truthy());
+}
+
+#[test]
+fn test_equality() {
+    assert_eq!(Value::from(0.0), Value::from(-0.0));
+    assert_ne!(Value::from(f64::NAN), Value::from(f64::NAN));
+    assert_eq!(Value::from("hp"), Value::from(String::from("hp")));
+    assert_ne!(Value::from(1.0), Value::from("1"));
+    assert_ne!(Value::Null, Value::from(false));
+
+    // Objects are only equal to themselves, whatever they hold.
+    let mut heap = Heap::new();
+    let a = heap.alloc(Object::List(Vec::new()));
+    let b = heap.alloc(Object::List(Vec::new()));
+    assert_eq!(Value::from(a), Value::from(a));
+    assert_ne!(Value::from(a), Value::from(b));
+}
+
+#[test]
+fn test_conversions() {
+    assert_eq!(Value::from(1.5).as_number(), Some(1.5));
+    assert_eq!(Value::from(3).as_number(), Some(3.0));
+    assert_eq!(Value::from(3).as_int(), Some(3));
+    assert_eq!(Value::from(3.0).as_int(), None);
+    assert_eq!(Value::from("go").as_str(), Some("go"));
+    assert_eq!(Value::from(true).as_bool(), Some(true));
+    assert_eq!(Value::from(1.0).as_bool(), None);
+    assert_eq!(Value::from(None::<f64>), Value::Null);
+    assert_eq!(Value::from(Some("a")), Value::from("a"));
+    assert_eq!(
+        Value::from_constant(&Constant::String(Arc::from("x"))),
+        Some(Value::from("x"))
+    );
+    assert_eq!(
+        Value::from_constant(&Constant::Float(2.0)),
+        Some(Value::from(2.0))
+    );
+
+    let heap = Heap::new();
+    assert_eq!(Value::Null.type_name(&heap), "null");
+    assert_eq!(Value::from(2.0).type_name(&heap), "float");
+    assert_eq!(Value::from(2).type_name(&heap), "int");
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(Value::Null.to_string(), "null");
+    assert_eq!(Value::from(true).to_string(), "true");
+    assert_eq!(Value::from(3).to_string(), "3");
+    assert_eq!(Value::from(3.0).to_string(), "3.0");
+    assert_eq!(Value::from(-1e20).to_string(), "-100000000000000000000.0");
+    assert_eq!(Value::from(f64::INFINITY).to_string(), "inf");
+    assert_eq!(Value::from(0.25).to_string(), "0.25");
+    assert_eq!(Value::from("a \"b\"").to_string(), "a \"b\"");
+
+    // Strings inside lists and maps are quoted.
+    let mut heap = Heap::new();
+    let inner = heap.alloc(Object::List(vec![Value::from("a, b")]));
+    let mut map = Map::new();
+    map.insert(Value::from("hp").key().unwrap(), Value::from(10));
+    map.insert(Value::Null.key().unwrap(), Value::from(inner));
+    let map = heap.alloc(Object::Map(map));
+    assert_eq!(Value::from(map).type_name(&heap), "map");
+    assert_eq!(
+        Value::from(map).display(&heap).to_string(),
+        "{\"hp\": 10, null: [\"a, b\"]}"
+    );
+
+    // A list that holds itself is only shown once.
+    let list = heap.alloc(Object::List(vec![Value::from(1)]));
+    if let Object::List(items) = heap.get_mut(list) {
+        items.push(Value::from(list));
+    }
+    assert_eq!(Value::from(list).display(&heap).to_string(), "[1, ...]");
+}
+
+#[test]
+fn test_map_keys() {
+    let mut map = Map::new();
+    map.insert(Value::from(-0.0).key().unwrap(), Value::from("zero"));
+    map.insert(Value::from("b").key().unwrap(), Value::from(1.0));
+    map.insert(Value::from(0.0).key().unwrap(), Value::from("again"));
+    assert_eq!(map.len(), 2);
+    assert_eq!(
+        map.get(&Value::from(0.0).key().unwrap()),
+        Some(&Value::from("again"))
+    );
+    // Whole floats are the same key as the int they are equal to.
+    let keys: Vec<Value> = map.iter().map(|(key, _)| key.value()).collect();
+    assert_eq!(keys, vec![Value::from(0), Value::from("b")]);
+    assert_eq!(Value::from(f64::NAN).key(), None);
+    assert_eq!(Value::from(2.0).key(), Value::from(2).key());
+    assert_ne!(Value::from(2.5).key(), Value::from(2).key());
+    assert_ne!(Value::from(1e19).key(), Value::from(i64::MAX).key());
+
+    // The entries after a removed one keep their order and can still be
+    // found.
+    let c = Value::from("c").key().unwrap();
+    map.insert(c.clone(), Value::from(2.0));
+    let zero = Value::from(0.0).key().unwrap();
+    assert_eq!(map.remove(&zero), Some(Value::from("again")));
+    assert_eq!(map.remove(&zero), None);
+    assert_eq!(map.get(&c), Some(&Value::from(2.0)));
+    map.insert(c, Value::from(3.0));
+    let values: Vec<Value> = map.iter().map(|(_, value)| value.clone()).collect();
+    assert_eq!(values, vec![Value::from(1.0), Value::from(3.0)]);
+}
+
+#[test]
+fn test_heap() {
+    let mut heap = Heap::new();
+    assert!(heap.is_empty());
+    let list = heap.alloc(Object::List(vec![Value::from(1.0)]));
+    assert_eq!(heap.len(), 1);
+    assert_eq!(heap.get(list), &Object::List(vec![Value::from(1.0)]));
+    assert_eq!(heap.get(list).type_name(), "list");
+}
+
+#[test]
+fn test_collect() {
+    let mut heap = Heap::new();
+    let item = heap.alloc(Object::List(Vec::new()));
+    let list = heap.alloc(Object::List(vec![Value::from(item)]));
+    let mut map = Map::new();
+    map.insert(Key::Object(list), Value::Null);
+    let map = heap.alloc(Object::Map(map));
+
+    // Objects that only refer to each other are garbage all the same.
+    let a = heap.alloc(Object::List(Vec::new()));
+    let b = heap.alloc(Object::List(vec![Value::from(a)]));
+    *heap.get_mut(a) = Object::List(vec![Value::from(b)]);
+
+    assert_eq!(heap.collect(vec![map]), 2);
+    assert_eq!(heap.len(), 3);
+    assert_eq!(heap.get(list), &Object::List(vec![Value::from(item)]));
+    assert_eq!(heap.collect(Vec::new()), 3);
+    assert!(heap.is_empty());
+
+    // Freed slots are handed out again.
+    let reused = heap.alloc(Object::List(Vec::new()));
+    assert!(reused.index() < 5);
+}
+
+#[test]
+fn test_gc_config() {
+    let mut heap = Heap::new();
+    heap.set_gc_config(GcConfig {
+        initial_threshold: 2,
+        min_threshold: 2,
+        growth_factor: 1.5,
+        step_budget: None,
+        stress: false,
+    });
+    let kept = heap.alloc(Object::List(Vec::new()));
+    assert!(!heap.is_due());
+    heap.alloc(Object::List(Vec::new()));
+    assert!(heap.is_due());
+
+    // Once four objects survive, the next collection waits for six.
+    let more: Vec<Handle> = (0..3)
+        .map(|_| heap.alloc(Object::List(Vec::new())))
+        .collect();
+    heap.collect(more.iter().copied().chain(Some(kept)));
+    assert_eq!(heap.len(), 4);
+    heap.alloc(Object::List(Vec::new()));
+    assert!(!heap.is_due());
+    heap.alloc(Object::List(Vec::new()));
+    assert!(heap.is_due());
+
+    // Under stress every allocation makes a collection due.
+    heap.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    heap.collect(Vec::new());
+    assert!(!heap.is_due());
+    heap.alloc(Object::List(Vec::new()));
+    assert!(heap.is_due());
+}
+
+#[test]
+fn test_incremental_sweep() {
+    let mut heap = Heap::new();
+    heap.set_gc_config(GcConfig {
+        initial_threshold: 100,
+        step_budget: Some(1),
+        ..GcConfig::default()
+    });
+    let kept = heap.alloc(Object::List(Vec::new()));
+    let garbage: Vec<Handle> = (0..4)
+        .map(|_| heap.alloc(Object::List(Vec::new())))
+        .collect();
+    let weak = heap.alloc(Object::Weak(gc::Weak::new(garbage[3])));
+    heap.collect(vec![kept, weak]);
+    assert_eq!(heap.stats().collections, 1);
+    let free = heap.alloc(Object::List(Vec::new()));
+    let map = heap.alloc(Object::Map(Map::new()));
+    let to_map = heap.alloc(Object::Weak(gc::Weak::new(map)));
+    assert!(!heap.is_due());
+
+    // The first step only sweeps the first slot, but what was marked as
+    // garbage is already left out of the stats, and weak references to it
+    // are empty.
+    assert_eq!(heap.start_collection(vec![kept, weak, free, to_map]), 0);
+    assert!(heap.is_sweeping());
+    assert!(heap.is_due());
+    assert_eq!(heap.len(), 5);
+    match heap.get(to_map) {
+        Object::Weak(weak) => assert_eq!(weak.get(), None),
+        object => panic!("expected a weak reference, got {:?}", object),
+    }
+    let stats = heap.stats();
+    assert_eq!(stats.objects, 4);
+    assert_eq!(stats.counts.get("list"), Some(&2));
+    assert_eq!(stats.counts.get("map"), None);
+    assert_eq!(stats.collections, 2);
+
+    // An object made while it sweeps is kept, even in a slot it hasn't got
+    // to yet.
+    let made = heap.alloc(Object::List(Vec::new()));
+    let mut freed = 0;
+    let mut steps = 0;
+    while heap.is_sweeping() {
+        freed += heap.sweep_step();
+        steps += 1;
+    }
+    assert_eq!((freed, steps), (1, 5));
+    assert_eq!(heap.len(), 5);
+    assert_eq!(heap.get(made), &Object::List(Vec::new()));
+    assert_eq!(heap.stats().objects, 5);
+
+    // A collection all at once finishes any sweep first.
+    heap.start_collection(vec![kept]);
+    assert_eq!(heap.collect(vec![kept]), 4);
+    assert!(!heap.is_sweeping());
+    assert_eq!(heap.len(), 1);
+}
+
+#[test]
+fn test_symbols() {
+    let mut heap = Heap::new();
+    let x = heap.intern("x");
+    assert_eq!(x, heap.intern("x"));
+    assert_ne!(x, heap.intern("y"));
+    assert_eq!(x.as_str(), "x");
+    assert_eq!(heap.symbol("x"), Some(x.clone()));
+    assert_eq!(heap.symbol("z"), None);
+    assert_eq!(heap.symbol_count(), 2);
+
+    // A symbol is the same key and value as the string it is.
+    let mut map = Map::new();
+    map.insert(Key::from(&x), Value::from(1));
+    assert_eq!(map.get(&Key::String(Arc::from("x"))), Some(&Value::from(1)));
+    assert_eq!(Value::from(&x), Value::from("x"));
+
+    // Names nothing holds on to are forgotten by a collection, and the ones
+    // still held keep their symbols.
+    heap.collect(Vec::new());
+    assert_eq!(heap.symbol_count(), 1);
+    assert_eq!(heap.intern("x"), x);
+}
+
+#[cfg(feature = "bigint")]
+#[test]
+fn test_bigint() {
+    use atom::value::bigint::BigInt;
+
+    // Checked against i128, which holds every product of two i64s.
+    let samples: Vec<i128> = vec![
+        0,
+        1,
+        -1,
+        4_294_967_295,
+        4_294_967_296,
+        -9_223_372_036_854_775_808,
+        9_223_372_036_854_775_807,
+        123_456_789_012_345_678,
+        -987_654_321_987_654_321,
+    ];
+    let big = |n: i128| BigInt::parse(&n.to_string()).unwrap();
+    for &a in &samples {
+        assert_eq!(big(a).to_string(), a.to_string());
+        for &b in &samples {
+            assert_eq!((&big(a) + &big(b)).to_string(), (a + b).to_string());
+            assert_eq!((&big(a) - &big(b)).to_string(), (a - b).to_string());
+            assert_eq!((&big(a) * &big(b)).to_string(), (a * b).to_string());
+            assert_eq!(big(a).cmp(&big(b)), a.cmp(&b));
+            if b != 0 {
+                let (quotient, remainder) = big(a * b + a).div_rem(&big(b));
+                assert_eq!(quotient.to_string(), ((a * b + a) / b).to_string());
+                assert_eq!(remainder.to_string(), ((a * b + a) % b).to_string());
+            }
+        }
+    }
+
+    assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+    assert_eq!(big(1 << 63).to_i64(), None);
+    assert_eq!(big(-(1 << 70)).to_f64(), -(2f64.powi(70)));
+    assert_eq!(BigInt::from_f64(2f64.powi(80)), Some(big(1 << 80)));
+    assert_eq!(BigInt::from_f64(0.5), None);
+    assert_eq!(BigInt::parse("-0"), Some(BigInt::from(0)));
+    assert_eq!(BigInt::parse("1_000"), None);
+    assert_eq!(BigInt::parse("-"), None);
+
+    // A bigint that fits is an int.
+    assert_eq!(Value::from(BigInt::from(7)), Value::from(7));
+    assert_eq!(Value::from(big(1 << 64)).type_name(&Heap::new()), "bigint");
+}
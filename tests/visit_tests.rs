@@ -0,0 +1,116 @@
+extern crate atom;
+
+use atom::ast::visit::*;
+use atom::ast::*;
+use atom::parse::*;
+
+#[derive(Default)]
+struct Counter {
+    stmts: usize,
+    exprs: usize,
+    functions: usize,
+    classes: usize,
+    identifiers: Vec<String>,
+    variables: Vec<String>,
+}
+
+impl Visitor for Counter {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        self.stmts += 1;
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.exprs += 1;
+        if let ExprKind::Variable(name) = &expr.kind {
+            self.variables.push(name.clone());
+        }
+        walk_expr(self, expr);
+    }
+
+    fn visit_function(&mut self, decl: &FunctionDecl) {
+        self.functions += 1;
+        walk_function(self, decl);
+    }
+
+    fn visit_class(&mut self, decl: &ClassDecl) {
+        self.classes += 1;
+        walk_class(self, decl);
+    }
+
+    fn visit_identifier(&mut self, ident: &Identifier) {
+        self.identifiers.push(ident.name.clone());
+    }
+}
+
+// Renames every use of one variable, the kind of rewrite a refactoring
+// tool would perform.
+struct Rename<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
+impl VisitorMut for Rename<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let ExprKind::Variable(name) = &mut expr.kind {
+            if name == self.from {
+                *name = String::from(self.to);
+            }
+        }
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_identifier_mut(&mut self, ident: &mut Identifier) {
+        if ident.name == self.from {
+            ident.name = String::from(self.to);
+        }
+    }
+}
+
+const SOURCE: &str = "
+var speed = 2;
+function move(x, dt) { return x + speed * dt; }
+class Bat extends Enemy {
+    update(dt) { if (dt > 0) { move(1, dt); } }
+}
+for (e in entities) { while (true) { break; } }
+";
+
+#[test]
+fn test_visitor() {
+    let program = parse_program("test", SOURCE).ok().unwrap();
+    let mut counter = Counter::default();
+    counter.visit_program(&program);
+
+    assert_eq!(counter.functions, 2);
+    assert_eq!(counter.classes, 1);
+    assert_eq!(counter.stmts, 12);
+    assert_eq!(counter.exprs, 15);
+    assert_eq!(
+        counter.identifiers,
+        vec!["speed", "move", "x", "dt", "Bat", "Enemy", "update", "dt", "e"]
+    );
+    assert_eq!(
+        counter.variables,
+        vec!["x", "speed", "dt", "dt", "move", "dt", "entities"]
+    );
+}
+
+#[test]
+fn test_visitor_mut() {
+    let mut program = parse_program("test", SOURCE).ok().unwrap();
+    Rename {
+        from: "dt",
+        to: "delta",
+    }
+    .visit_program_mut(&mut program);
+
+    let mut counter = Counter::default();
+    counter.visit_program(&program);
+    assert!(!counter.identifiers.iter().any(|i| i == "dt"));
+    assert!(!counter.variables.iter().any(|v| v == "dt"));
+    assert_eq!(
+        counter.variables.iter().filter(|v| *v == "delta").count(),
+        3
+    );
+}
@@ -0,0 +1,2802 @@
+extern crate atom;
+
+use atom::builtins::{Context, Native};
+use atom::compile::*;
+use atom::error::RuntimeErrorKind;
+use atom::interp::*;
+use atom::parse::*;
+use atom::project::Project;
+use atom::value::foreign::Foreign;
+use atom::value::gc::GcConfig;
+use atom::value::{Object, Value};
+use atom::vm::cache::InlineCaches;
+use atom::vm::debug::*;
+use atom::vm::module::*;
+use atom::vm::snapshot::ForeignTypes;
+use atom::vm::*;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{self, Poll, Waker};
+
+// Collects what a script prints.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+fn run_vm(source: &str, opt_level: OptLevel, stress: bool) -> Result<String, String> {
+    let program = parse_program("test", source).unwrap();
+    let function = match compile_with(&program, opt_level) {
+        Ok(function) => function,
+        Err(errors) => panic!("unexpected compile error: {}", errors[0]),
+    };
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_gc_config(GcConfig {
+        stress,
+        ..GcConfig::default()
+    });
+    match vm.run(&function) {
+        Ok(_) => Ok(output.text()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn run_interpreter(source: &str) -> Result<String, String> {
+    let program = parse_program("test", source).unwrap();
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    match interpreter.run(&program) {
+        Ok(_) => Ok(output.text()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+// Runs the script with and without optimizing, and collecting garbage as
+// often as it can, checking that it does the same as it does in the
+// interpreter every time.
+fn run(source: &str) -> Result<String, String> {
+    let expected = run_interpreter(source);
+    for opt_level in [OptLevel::None, OptLevel::Basic] {
+        for stress in [false, true] {
+            assert_eq!(
+                run_vm(source, opt_level, stress),
+                expected,
+                "at {:?} for {}",
+                opt_level,
+                source
+            );
+        }
+    }
+    expected
+}
+
+// Coroutines only run in the virtual machine, so a script that uses them is
+// checked for doing the same whether it is optimized and collected or not.
+fn run_coroutines(source: &str) -> Result<String, String> {
+    let expected = run_vm(source, OptLevel::None, false);
+    for opt_level in [OptLevel::None, OptLevel::Basic] {
+        for stress in [false, true] {
+            assert_eq!(
+                run_vm(source, opt_level, stress),
+                expected,
+                "at {:?} for {}",
+                opt_level,
+                source
+            );
+        }
+    }
+    expected
+}
+
+fn printed(source: &str) -> String {
+    match run(source) {
+        Ok(output) => output,
+        Err(error) => panic!("unexpected runtime error: {}", error),
+    }
+}
+
+fn error(source: &str) -> String {
+    match run(source) {
+        Ok(_) => panic!("expected an error"),
+        Err(error) => error,
+    }
+}
+
+#[test]
+fn test_expressions() {
+    assert_eq!(printed("print(1 + 2 * 3, 7 % 4, 1 / 4);"), "7 3 0\n");
+    assert_eq!(
+        printed("var a = \"a\"; print(a + \"b\", a < \"b\", 2 >= 3);"),
+        "ab true false\n"
+    );
+    assert_eq!(
+        printed("var n = 6; print(-n, not n, not null, ~n, n & 3, n | 3, n ^ 3);"),
+        "-6 false true -7 2 7 5\n"
+    );
+    assert_eq!(
+        printed("var t = null; print(0 and \"yes\", t or \"no\", t and x);"),
+        "yes no null\n"
+    );
+    assert_eq!(
+        printed("var hp = 3; print(\"hp: {hp}/{hp * 2} {null} {[hp]}\");"),
+        "hp: 3/6 null [3]\n"
+    );
+    assert_eq!(
+        printed("var n = 3; print(str([1, \"a\", {b: null}]), len(\"héllo\"), 0..=n);"),
+        "[1, \"a\", {\"b\": null}] 5 0..=3\n"
+    );
+}
+
+#[test]
+fn test_variables_and_scopes() {
+    let source = "var a = 1;
+{
+    var a = 2;
+    a += 1;
+    print(a);
+}
+print(a);
+var b;
+b = a = 5;
+print(a, b);
+function f() { c = 7; }
+var c;
+f();
+print(c);";
+    assert_eq!(printed(source), "3\n1\n5 5\n7\n");
+}
+
+#[test]
+fn test_control_flow() {
+    let source = "var i = 0;
+while (i < 10) {
+    i += 1;
+    if (i % 2 == 0) continue;
+    if (i > 6) break;
+    print(i);
+}
+do { print(\"once\"); } while (false);
+var x = 0;
+outer: while (x < 3) {
+    var y = 0;
+    x += 1;
+    while (y < 3) {
+        y += 1;
+        if (y == 2) continue outer;
+        if (x == 3) break outer;
+        print(x, y);
+    }
+}";
+    assert_eq!(printed(source), "1\n3\n5\nonce\n1 1\n2 1\n");
+}
+
+#[test]
+fn test_iteration() {
+    let source = "outer: for (x in 0..3) {
+    for (y in [10, 20, 30]) {
+        if (y == 20) continue outer;
+        if (x == 2) break outer;
+        print(x, y);
+    }
+}
+for (c in \"añb\") print(c);
+for (k in {a: 1, iter: 2}) print(k);
+var xs = [1];
+for (x in xs) if (x < 3) xs = xs + [x + 1];
+var ys = [1];
+for (y in ys) if (len(ys) < 3) ys[0] = 5;
+var fs = [];
+for (i in 0..=2) fs = fs + [function() { return i; }];
+print(xs, ys, fs[0](), fs[2]());
+var it = [7].iter();
+print(it.has_next(), it.next(), it.has_next(), it);";
+    assert_eq!(
+        printed(source),
+        "0 10\n1 10\na\nñ\nb\n[\"a\", 1]\n[\"iter\", 2]\n[1, 2] [5] 0 2\ntrue 7 false <iterator>\n"
+    );
+
+    // Anything with an iter method can be gone through.
+    let source = "class Countdown {
+    init(from) { this.from = from; }
+    iter() { return CountdownIter(this.from); }
+}
+class CountdownIter {
+    init(n) { this.n = n; }
+    has_next() { return this.n > 0; }
+    next() {
+        this.n -= 1;
+        return this.n + 1;
+    }
+}
+for (n in Countdown(3)) print(n);";
+    assert_eq!(printed(source), "3\n2\n1\n");
+
+    assert_eq!(
+        error("var n = 5;\nfor (x in n) {}"),
+        "test:2:11: cannot iterate over int"
+    );
+    assert_eq!(
+        error("var it = [].iter();\nit.next();"),
+        "test:2:1: the iterator has no items left"
+    );
+    assert_eq!(
+        error("class A { iter() { return 1; } }\nfor (x in A()) {}"),
+        "test:2:1: cannot read property has_next of int"
+    );
+    assert_eq!(
+        error("var xs = [];\nxs.iter(1);"),
+        "test:2:1: list.iter expects 0 arguments but got 1"
+    );
+}
+
+#[test]
+fn test_lists() {
+    let source = "var xs = [1, 2, 3];
+xs.push(4);
+print(xs.pop(), xs.pop(), xs, xs.length());
+xs.insert(0, 0);
+xs.insert(-1, 9);
+xs.insert(xs.length(), 5);
+print(xs, xs.remove(1), xs.remove(-1), xs);
+print(xs[-1], xs[-3], \"añb\"[-2]);
+xs[-1] = 7;
+var ys = [];
+for (x in xs) ys.push(x * 2);
+print(xs, ys, xs + ys);
+var nested = [[1], \"a\"];
+print([1, [2]] == [1, [2]], [1] != [1], [1, 2] == [2, 1], nested == [[1], \"a\"]);
+var loop = [];
+loop.push(loop);
+var other = [];
+other.push(other);
+print(loop == other, loop == [loop]);";
+    assert_eq!(
+        printed(source),
+        "4 3 [1, 2] 2
+[0, 2, 9] 1 5 [0, 2, 9]
+9 0 ñ
+[0, 2, 7] [0, 4, 14] [0, 2, 7, 0, 4, 14]
+true false false true
+true true
+"
+    );
+    assert_eq!(
+        error("var xs = [1, 2];\nxs[-3];"),
+        "test:2:1: list index -3 is out of range"
+    );
+    assert_eq!(
+        error("var xs = [];\nxs.pop();"),
+        "test:2:1: cannot pop from an empty list"
+    );
+    assert_eq!(
+        error("var xs = [];\nxs.insert(2, 1);"),
+        "test:2:1: list index 2 is out of range"
+    );
+}
+
+#[test]
+fn test_maps() {
+    let source = "var m = {a: 1, [2]: \"two\", b: null};
+m[\"c\"] = 3;
+m.a = 10;
+print(m.keys(), m.values(), m.length());
+print(m.has(\"b\"), m.has(\"z\"), m.has(2), m.has(0 / 1), m[\"b\"], m.z);
+print(m.remove(\"a\"), m.remove(\"a\"), m);
+m.a = 4;
+var total = 0;
+for (entry in m) if (entry[1] != null and entry[0] != 2) total += entry[1];
+print(m, total, m.keys == m.keys, {keys: 1}.keys());";
+    assert_eq!(
+        printed(source),
+        "[\"a\", 2, \"b\", \"c\"] [10, \"two\", null, 3] 4
+true false true false null null
+10 null {2: \"two\", \"b\": null, \"c\": 3}
+{2: \"two\", \"b\": null, \"c\": 3, \"a\": 4} 7 false [\"keys\"]
+"
+    );
+    assert_eq!(
+        error("var m = {};\nm.remove(1, 2);"),
+        "test:2:1: map.remove expects 1 argument but got 2"
+    );
+}
+
+#[test]
+fn test_string_methods() {
+    let source = "var s = \"  Größe café  \";
+var t = s.trim();
+print(s.length(), t.length(), t.upper(), t.lower());
+print(t.split(\" \"), \" a  b \".split(), \"añb\".split(\"\"), \"a,,b\".split(\",\"));
+print(t.contains(\"é\"), t.starts_with(\"Grö\"), t.starts_with(\"ö\"));
+print(t.replace(\"é\", \"e\"), \"aaa\".replace(\"a\", \"ab\"));
+print(t.slice(2), t.slice(2, 5), t.slice(-3, 100), t.find(\"café\"), t.find(\"x\"));";
+    assert_eq!(
+        printed(source),
+        "14 10 GRÖSSE CAFÉ größe café
+[\"Größe\", \"café\"] [\"a\", \"b\"] [\"a\", \"ñ\", \"b\"] [\"a\", \"\", \"b\"]
+true true false
+Größe cafe ababab
+öße café öße afé 6 null
+"
+    );
+    assert_eq!(
+        error("var s = \"abc\";\ns.contains(1);"),
+        "test:2:1: string.contains expects a string but got int"
+    );
+    assert_eq!(
+        error("var s = \"abc\";\ns.upper(1);"),
+        "test:2:1: string.upper expects 0 arguments but got 1"
+    );
+    assert_eq!(
+        error("var s = \"abc\";\ns.reverse();"),
+        "test:2:3: cannot read property reverse of string"
+    );
+}
+
+#[test]
+fn test_match() {
+    // Enough cases make a switch, both for numbers in a row and for ones
+    // that have to be searched.
+    let source = "function kind(x) {
+    match (x) {
+        default: return \"many\";
+        case 1: return \"one\";
+        case 2, 3: return \"few\";
+        case \"x\": return \"letter\";
+    }
+}
+function digit(n) {
+    match (n) {
+        case 0: return \"zero\";
+        case 1: return \"one\";
+        case 2: return \"two\";
+        case 3: return \"three\";
+        case 4: return \"four\";
+    }
+    return \"?\";
+}
+function spell(word) {
+    var out = \"\";
+    match (word) {
+        case \"a\", \"an\": out = \"article\";
+        case \"run\", \"walk\": out = \"verb\";
+        case 1.5, -2: out = \"number\";
+        default: out = \"other\";
+    }
+    return out;
+}
+print(kind(1), kind(3), kind(\"x\"), kind(9));
+print(digit(0), digit(4), digit(2.5), digit(-0), digit(\"1\"));
+print(spell(\"an\"), spell(\"walk\"), spell(-2), spell(null));";
+    assert_eq!(
+        printed(source),
+        "one few letter many\nzero four ? zero ?\narticle verb number other\n"
+    );
+}
+
+#[test]
+fn test_numbers() {
+    // Ints stay ints, rounding toward zero when they are divided, and an
+    // int with a float makes a float.
+    assert_eq!(
+        printed("print(7 / 2, -7 / 2, -7 % 2, 7 % -2, 7 / 2.0, 1 + 0.5, 2 * 1.0);"),
+        "3 -3 -1 1 3.5 1.5 2.0\n"
+    );
+    assert_eq!(
+        printed("print(1 == 1.0, 2 < 2.5, 3 >= 3.0, [1, 2] == [1.0, 2.0], 0.1 + 0.2 == 0.3);"),
+        "true true true true false\n"
+    );
+    assert_eq!(
+        printed("print(~0, 5 & 3, 1 ^ 3, -9223372036854775807 - 1, -9223372036854775807 % -1);"),
+        "-1 1 2 -9223372036854775808 0\n"
+    );
+    // A whole float is the same key as the int, and the same case.
+    let source = "var m = {};
+m[1] = \"a\";
+m[2.0] = \"b\";
+print(m[1.0], m[2], len(m));
+function digit(n) {
+    match (n) {
+        case 0: return \"zero\";
+        case 1: return \"one\";
+        case 2: return \"two\";
+        case 3: return \"three\";
+    }
+    return \"?\";
+}
+print(digit(2.0), digit(2.5), digit(3));";
+    assert_eq!(printed(source), "a b 2\ntwo ? three\n");
+
+    #[cfg(not(feature = "bigint"))]
+    {
+        assert_eq!(
+            error("var n = 9223372036854775807;\nprint(n + 1);"),
+            "test:2:7: integer overflow"
+        );
+        assert_eq!(
+            error("var n = -9223372036854775807 - 1;\nprint(-n);"),
+            "test:2:7: integer overflow"
+        );
+    }
+    assert_eq!(error("print(7 % 0);"), "test:1:7: division by zero");
+    assert_eq!(
+        error("print(1.5 & 1);"),
+        "test:1:7: cannot use & with float and int"
+    );
+    assert_eq!(
+        error("[1, 2][1.0];"),
+        "test:1:1: cannot index list with float"
+    );
+    assert_eq!(
+        error("for (i in 0..2.5) {}"),
+        "test:1:11: cannot use .. with int and float"
+    );
+}
+
+#[cfg(feature = "bigint")]
+#[test]
+fn test_bigints() {
+    // Ints that overflow become bigints, which become ints again once they
+    // fit.
+    let source = "var max = 9223372036854775807;
+var big = max + 1;
+print(big, -big, big * big, big - 1 == max);
+function factorial(n) { return n < 2 ? 1 : n * factorial(n - 1); }
+print(factorial(25), factorial(25) / factorial(23), factorial(25) % 1000007);
+print(-max - 2, (-max - 1) / -1, big > max, big < 1000000000000000000000000000000.0, big == 9223372036854775808.0);";
+    assert_eq!(
+        printed(source),
+        "9223372036854775808 -9223372036854775808 85070591730234615865843651857942052864 true
+15511210043330985984000000 600 913534
+-9223372036854775809 9223372036854775808 true true true
+"
+    );
+
+    let source = "var id = bigint(\"123456789012345678901234567890\");
+var m = {};
+m[id] = \"found\";
+m[9223372036854775808.0] = \"float\";
+print(id + 1, id / bigint(\"1000000000000000000000\"), m[bigint(\"123456789012345678901234567890\")]);
+print(m[9223372036854775807 + 1], bigint(5) + 1, bigint(-0.0), len(str(id * id)));";
+    assert_eq!(
+        printed(source),
+        "123456789012345678901234567891 123456789 found\nfloat 6 0 59\n"
+    );
+
+    assert_eq!(
+        error("print((9223372036854775807 + 1) & 1);"),
+        "test:1:7: cannot use & with bigint and int"
+    );
+    assert_eq!(
+        error("print((9223372036854775807 + 1) % 0);"),
+        "test:1:7: division by zero"
+    );
+    assert_eq!(
+        error("bigint(\"12x\");"),
+        "test:1:1: cannot make a bigint out of 12x"
+    );
+    assert_eq!(
+        error("bigint(1.5);"),
+        "test:1:1: cannot make a bigint out of 1.5"
+    );
+}
+
+#[test]
+fn test_functions() {
+    let source = "function fib(n) { return n < 2 ? n : fib(n - 1) + fib(n - 2); }
+function greet(name, greeting = \"hi\", ...rest) { return \"{greeting} {name} {rest}\"; }
+print(fib(15));
+print(greet(\"bo\"), greet(\"al\", null), greet(\"cy\", \"yo\", 1, 2));
+function twice(f) { return function(x) { return f(f(x)); }; }
+@twice
+function inc(x) { return x + 1; }
+print(inc(1));
+var apply = function(f, x) { return f(x); };
+print(apply(function(x) { return x * 10; }, 4), apply(len, \"abc\"));
+function outer() {
+    function inner(n) { return n == 0 ? \"done\" : inner(n - 1); }
+    return inner(3);
+}
+print(outer(), fib, print);";
+    assert_eq!(
+        printed(source),
+        "610\nhi bo [] hi al [] yo cy [1, 2]\n3\n40 3\ndone <function fib> <function print>\n"
+    );
+}
+
+#[test]
+fn test_closures() {
+    // Closures share the variables they capture and keep them alive once
+    // the function that declared them has returned.
+    let source = "function counter() {
+    var count = 0;
+    return [function() { count += 1; return count; }, function() { return count; }];
+}
+var [inc, get] = counter();
+inc();
+inc();
+print(get());
+var fs = [];
+var i = 0;
+while (i < 3) {
+    var j = i;
+    fs = fs + [function() { return j; }];
+    i += 1;
+}
+print(fs[0](), fs[2]());
+function adder(a) {
+    return function(b) {
+        return function(c) { return a + b + c; };
+    };
+}
+print(adder(1)(2)(3));";
+    assert_eq!(printed(source), "2\n0 2\n6\n");
+}
+
+#[test]
+fn test_lists_and_maps() {
+    let source = "var xs = [1, 2, 3];
+xs[0] = 10;
+xs[1] += 5;
+print(xs, xs[1:], xs[:1], xs[5:], len(xs));
+var m = {hp: 10, [1 + 1]: \"two\"};
+m.hp -= 3;
+m[\"mp\"] = 4;
+print(m, m.missing, m[2]);
+var {hp, mp: magic} = m;
+var [a, [b, c]] = [1, [2, 3]];
+print(hp, magic, a, b, c);
+a, b = b, a;
+print(a, b);
+print(\"hello\"[1], \"hello\"[1:3], [1] + [2]);";
+    assert_eq!(
+        printed(source),
+        "[10, 7, 3] [7, 3] [10] [] 3\n{\"hp\": 7, 2: \"two\", \"mp\": 4} null two\n7 4 1 2 3\n2 1\ne el [1, 2]\n"
+    );
+
+    // Negative slice bounds count back from the end, and ones that are
+    // still before the start are moved to it.
+    assert_eq!(
+        printed(
+            "var l = [1, 2, 3, 4];
+print(l[:-1], l[-2:], l[-3:-1], l[-10:2], l[1:-10]);
+print(\"héllo\".slice(-3), \"héllo\"[-4:-2], \"héllo\".slice(0, -1));"
+        ),
+        "[1, 2, 3] [3, 4] [2, 3] [1, 2] []\nllo él héll\n"
+    );
+
+    // The object and index of an assignment are worked out before the
+    // value.
+    let source = "var log = [];
+function at(x) { log = log + [x]; return x; }
+var m = {};
+at(m)[at(\"k\")] = at(1);
+at(m).j = at(2);
+print(log, m);";
+    assert_eq!(
+        printed(source),
+        "[{\"k\": 1, \"j\": 2}, \"k\", 1, {\"k\": 1, \"j\": 2}, 2] {\"k\": 1, \"j\": 2}\n"
+    );
+}
+
+#[test]
+fn test_classes() {
+    let source = "class Animal {
+    init(name) { this.name = name; }
+    speak() { return \"{this.name} makes a sound\"; }
+    get loud() { return this.speak() + \"!\"; }
+}
+class Dog extends Animal {
+    init(name, tricks = 0) {
+        super.init(name);
+        this.tricks = tricks;
+    }
+    speak() { return super.speak() + \" like a dog\"; }
+    set age(years) { this.years = years * 7; }
+}
+var d = Dog(\"rex\");
+print(d.speak());
+print(d.loud);
+print(d.age = 3, d.years);
+var speak = d.speak;
+print(speak(), d);
+print(Dog, Dog(\"a\").init(\"b\"));";
+    assert_eq!(
+        printed(source),
+        "rex makes a sound like a dog
+rex makes a sound like a dog!
+3 21
+rex makes a sound like a dog Dog {name: \"rex\", tricks: 0, years: 21}
+<class Dog> Dog {name: \"b\", tricks: 0}
+"
+    );
+
+    // Closures inside methods see the instance, and classes declared in a
+    // function can use themselves.
+    let source = "function make() {
+    class Counter {
+        init(n) {
+            this.n = n;
+            if (n > 1) return;
+            this.next = Counter(n + 1);
+        }
+        adder() { return function(by) { this.n += by; return this.n; }; }
+    }
+    return Counter(1);
+}
+var counter = make();
+var add = counter.adder();
+add(2);
+print(add(3), counter.next.n, counter.next.adder()(1));";
+    assert_eq!(printed(source), "6 2 3\n");
+
+    // Decorators are called with the methods and then the class.
+    let source = "var made = [];
+function twice(f) { return function(x) { return f(x) * 2; }; }
+function register(c) { made = made + [c]; return c; }
+@register
+class A {
+    @twice
+    double(x) { return x; }
+}
+print(A().double(3), made);";
+    assert_eq!(printed(source), "6 [<class A>]\n");
+
+    // What a setter returns is thrown away, even from a tail call.
+    let source = "function half(n) { return n / 2; }
+class A {
+    set x(n) { return half(n); }
+    set y(n) { return len; }
+}
+var a = A();
+print(a.x = 4, a.y = 5);";
+    assert_eq!(printed(source), "4 5\n");
+}
+
+#[test]
+fn test_operator_overloading() {
+    let source = "class Vec {
+    init(x, y) { this.x = x; this.y = y; }
+    __add(other) { return Vec(this.x + other.x, this.y + other.y); }
+    __mul(k) { return Vec(this.x * k, this.y * k); }
+    __neg() { return this * -1; }
+    __eq(other) { return this.x == other.x and this.y == other.y; }
+    __lt(other) { return this.x < other.x; }
+    __index(i) { return i == 0 ? this.x : this.y; }
+    __setindex(i, value) {
+        if (i == 0) this.x = value; else this.y = value;
+        return null;
+    }
+}
+var a = Vec(1, 2);
+var b = a + Vec(3, 4) * 2;
+print(b, -a, a < b);
+print(a == Vec(1, 2), a != Vec(1, 2), a == 1, a != null);
+a[1] = 5;
+a += Vec(1, 1);
+print(a[0], a[1], a[1] += 1, a);";
+    assert_eq!(
+        printed(source),
+        "Vec {x: 7, y: 10} Vec {x: -1, y: -2} true
+true false false true
+2 6 7 Vec {x: 2, y: 7}
+"
+    );
+    assert_eq!(
+        error("class A {}\nvar a = A();\nprint(a + 1);"),
+        "test:3:7: cannot use + with instance and int"
+    );
+    assert_eq!(
+        error("class A { __index(i) { return i + 1; } }\nvar a = A();\na[\"x\"];"),
+        "test:1:31: cannot use + with string and int"
+    );
+}
+
+#[test]
+fn test_coroutines() {
+    // A coroutine gives back what it yields, and then what its function
+    // returns once it is done.
+    let source = "function count(n) {
+    var i = 0;
+    while (i < n) {
+        yield i;
+        i += 1;
+    }
+    return \"done\";
+}
+var c = coroutine(count);
+print(c(3), c.done());
+print(c(), c(), c(), c.done());";
+    assert_eq!(
+        run_coroutines(source),
+        Ok(String::from("0 false\n1 2 done true\n"))
+    );
+
+    // What a coroutine is resumed with is what its yield gives.
+    let source = "function sum() {
+    var total = 0;
+    while (true) total += yield total;
+}
+var s = coroutine(sum);
+s();
+print(s(1), s(2), s(3));";
+    assert_eq!(run_coroutines(source), Ok(String::from("1 3 6\n")));
+
+    // Functions called from a coroutine can yield for it, so behaviors can
+    // wait across frames.
+    let source = "var log = [];
+function wait(frames) {
+    while (frames > 0) {
+        yield;
+        frames -= 1;
+    }
+}
+function walk(name) {
+    log.push(name + \" starts\");
+    wait(2);
+    log.push(name + \" arrives\");
+}
+var a = coroutine(walk);
+var b = coroutine(walk);
+a(\"a\");
+b(\"b\");
+var frame = 0;
+while (not a.done() or not b.done()) {
+    frame += 1;
+    a();
+    b();
+}
+print(frame, log);";
+    assert_eq!(
+        run_coroutines(source),
+        Ok(String::from(
+            "2 [\"a starts\", \"b starts\", \"a arrives\", \"b arrives\"]\n"
+        ))
+    );
+
+    // Closures see the variables of a coroutine while it is stopped.
+    let source = "function counter() {
+    var n = 0;
+    var add = function() { n += 1; return n; };
+    yield add;
+    print(\"n is\", n);
+    n += 10;
+    yield n;
+}
+var c = coroutine(counter);
+var add = c();
+add();
+print(add());
+print(c(), add());";
+    assert_eq!(
+        run_coroutines(source),
+        Ok(String::from("2\nn is 2\n12 13\n"))
+    );
+
+    // Methods, tail calls and coroutines resuming other coroutines.
+    let source = "class Walker {
+    init(name) { this.name = name; }
+    steps() {
+        yield this.name + \" 1\";
+        return this.name + \" 2\";
+    }
+}
+function step(c) { return c(); }
+var w = coroutine(Walker(\"w\").steps);
+print(step(w), step(w));
+function inner() {
+    yield 1;
+    yield 2;
+}
+function outer() {
+    var i = coroutine(inner);
+    yield i() + 10;
+    yield i() + 20;
+}
+var o = coroutine(outer);
+print(o(), o(), o(), o.done());";
+    assert_eq!(
+        run_coroutines(source),
+        Ok(String::from("w 1 w 2\n11 22 null true\n"))
+    );
+}
+
+#[test]
+fn test_coroutine_errors() {
+    let error = |source| run_coroutines(source).unwrap_err();
+    assert_eq!(
+        error("function f() {}\nvar c = coroutine(f);\nc();\nc();"),
+        "test:4:1: cannot resume a finished coroutine"
+    );
+    assert_eq!(
+        error("var c = coroutine(function() { c(); });\nc();"),
+        "test:1:32: cannot resume a running coroutine"
+    );
+    assert_eq!(
+        error("function f() { yield 1; }\nf();"),
+        "test:1:16: cannot yield outside of a coroutine"
+    );
+    assert_eq!(
+        error("function f() { yield; }\nvar c = coroutine(f);\nc();\nc(1, 2);"),
+        "test:4:1: a coroutine is resumed with 1 value but got 2"
+    );
+    assert_eq!(
+        error("coroutine(1);"),
+        "test:1:1: cannot make a coroutine out of int"
+    );
+    assert_eq!(
+        error("coroutine(print);"),
+        "test:1:1: cannot make a coroutine out of a builtin function"
+    );
+    assert_eq!(
+        error("function f(a) {}\ncoroutine(f)();"),
+        "test:2:1: f expects 1 argument but got 0"
+    );
+}
+
+#[test]
+fn test_coroutine_host() {
+    // The host drives a coroutine of the script, a frame at a time.
+    let source = "var x = 0;
+function wait(time) {
+    while (time > 0) time -= yield;
+}
+function behave(speed) {
+    wait(2);
+    x += speed;
+    return \"arrived\";
+}
+var behavior = coroutine(behave);";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.run(&function).unwrap();
+    let behavior = vm.global("behavior").unwrap().clone();
+    let state = |vm: &Vm| match vm.heap().get(behavior.as_object().unwrap()) {
+        atom::value::Object::Coroutine(coroutine) => coroutine.state(),
+        _ => panic!("expected a coroutine"),
+    };
+
+    assert_eq!(vm.resume(&behavior, &[Value::Float(5.0)]), Ok(Value::Null));
+    let mut frames = 0;
+    let mut result = Value::Null;
+    while state(&vm) == CoroutineState::Suspended {
+        result = vm.resume(&behavior, &[Value::Float(0.5)]).unwrap();
+        frames += 1;
+    }
+    assert_eq!(frames, 4);
+    assert_eq!(result, Value::from("arrived"));
+    assert_eq!(vm.global("x"), Some(&Value::Float(5.0)));
+
+    let error = vm.resume(&behavior, &[]).unwrap_err();
+    assert_eq!(error.message(), "cannot resume a finished coroutine");
+    assert_eq!(error.trace()[0].to_string(), "in behave at test:6:5");
+}
+
+#[test]
+fn test_exceptions() {
+    // Anything can be thrown, and runtime errors are caught as their
+    // message.
+    let source = "function check(n) {
+    if (n < 0) throw {\"code\": n};
+    return n;
+}
+try {
+    check(1);
+    check(-2);
+    print(\"not reached\");
+} catch (e) {
+    print(\"caught\", e[\"code\"]);
+}
+try {
+    missing();
+} catch (e) {
+    print(e);
+}
+try {
+    throw [1, 2];
+} catch {
+    print(\"no binding\");
+}";
+    assert_eq!(
+        printed(source),
+        "caught -2\nmissing is not defined\nno binding\n"
+    );
+
+    // A finally block runs however the try is left, and one that throws
+    // again from a catch block is caught further out.
+    let source = "function f(n) {
+    try {
+        if (n == 0) return \"zero\";
+        if (n == 1) throw \"one\";
+        return \"many\";
+    } catch (e) {
+        throw \"caught \" + e;
+    } finally {
+        print(\"finally\", n);
+    }
+}
+print(f(0));
+print(f(2));
+try {
+    f(1);
+} catch (e) {
+    print(e);
+}
+var i = 0;
+while (i < 5) {
+    i += 1;
+    try {
+        if (i == 2) continue;
+        if (i == 4) break;
+    } finally {
+        print(\"left\", i);
+    }
+}";
+    assert_eq!(
+        printed(source),
+        "finally 0\nzero\nfinally 2\nmany\nfinally 1\ncaught one\n\
+         left 1\nleft 2\nleft 3\nleft 4\n"
+    );
+
+    // Throws unwind through calls, and a finally block that returns stops
+    // the exception.
+    let source = "function down(n) {
+    var local = [n];
+    if (n == 0) throw \"bottom\";
+    return down(n - 1) + 1;
+}
+function swallow() {
+    try {
+        down(20);
+    } finally {
+        return \"swallowed\";
+    }
+}
+var before = 1;
+try {
+    var inside = 2;
+    print(down(3));
+} catch (e) {
+    print(e, before);
+}
+print(swallow());
+var add = null;
+try {
+    var n = 10;
+    add = function() { n += 1; return n; };
+    throw n;
+} catch (e) {
+    print(e, add(), add());
+}";
+    assert_eq!(printed(source), "bottom 1\nswallowed\n10 11 12\n");
+
+    // What isn't caught stops the script with the message of the value.
+    assert_eq!(
+        error("try {\n    throw \"inner\";\n} finally {\n    print(1);\n}"),
+        "test:2:5: inner"
+    );
+    assert_eq!(
+        error("function f() { throw [1, 2]; }\nf();"),
+        "test:1:16: [1, 2]"
+    );
+}
+
+#[test]
+fn test_coroutine_exceptions() {
+    // A try stays around while its coroutine is stopped, and a throw out of
+    // a coroutine finishes it.
+    let source = "function gen() {
+    try {
+        yield 1;
+        throw \"inside\";
+    } catch (e) {
+        yield e;
+    }
+    throw \"outside\";
+}
+var c = coroutine(gen);
+print(c(), c());
+try {
+    c();
+} catch (e) {
+    print(e, c.done());
+}";
+    assert_eq!(
+        run_coroutines(source),
+        Ok(String::from("1 inside\noutside true\n"))
+    );
+}
+
+#[test]
+fn test_fuel() {
+    // A script that runs out of fuel stops where it is, whatever tries it
+    // is inside of, and carries on from there once it is given more.
+    let source = "var count = 0;
+function spin(n) {
+    try {
+        while (count < n) count += 1;
+    } catch (e) {
+        print(\"caught\", e);
+    }
+    return count;
+}
+var done = spin(1000);";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_fuel(Some(100));
+    let error = vm.run(&function).unwrap_err();
+    assert_eq!(error.kind(), RuntimeErrorKind::FuelExhausted);
+    assert_eq!(error.message(), "out of fuel");
+    assert_eq!(error.trace()[1].to_string(), "at test:10:12");
+    assert_eq!(vm.fuel(), Some(0));
+    assert!(vm.is_paused());
+
+    let mut stops = 1;
+    vm.add_fuel(100);
+    while let Err(error) = vm.continue_running() {
+        assert_eq!(error.kind(), RuntimeErrorKind::FuelExhausted);
+        stops += 1;
+        vm.add_fuel(100);
+    }
+    assert!(!vm.is_paused());
+    assert!(stops > 10);
+    assert_eq!(vm.global("done"), Some(&Value::Int(1000)));
+    assert_eq!(output.text(), "");
+
+    // Without a limit the same script runs to the end at once, and running
+    // another script drops one that is paused.
+    vm.set_fuel(Some(10));
+    assert!(vm.run(&function).is_err());
+    vm.set_fuel(None);
+    vm.add_fuel(5);
+    assert_eq!(vm.fuel(), None);
+    assert_eq!(vm.run(&function), Ok(Value::Null));
+    assert_eq!(vm.global("done"), Some(&Value::Int(1000)));
+}
+
+#[test]
+fn test_memory_limit() {
+    let run_limited = |source: &str| {
+        let program = parse_program("test", source).unwrap();
+        let function = compile(&program).unwrap();
+        let output = Output::default();
+        let mut vm = Vm::with_output(Box::new(output.clone()));
+        vm.set_memory_limit(Some(64 * 1024));
+        match vm.run(&function) {
+            Ok(_) => {
+                assert!(vm.heap().memory_used() <= 64 * 1024);
+                Ok(output.text())
+            }
+            Err(error) => Err(error.to_string()),
+        }
+    };
+
+    // Growing a list or making new objects both count, and a script that
+    // lets go of what it made can carry on once it has caught the error.
+    let source = "function grow(make) {
+    var items = [];
+    while (true) items.push(make ? [1, 2, 3] : 1);
+}
+try {
+    grow(true);
+} catch (e) {
+    print(e);
+}
+try {
+    grow(false);
+} catch (e) {
+    print(e);
+}
+print([1, 2]);";
+    assert_eq!(
+        run_limited(source),
+        Ok(String::from("out of memory\nout of memory\n[1, 2]\n"))
+    );
+
+    // What the script still holds on to stays counted.
+    let source = "var kept = [];
+while (true) kept.push({\"a\": 1});";
+    assert_eq!(
+        run_limited(source),
+        Err(String::from("test:2:14: out of memory"))
+    );
+
+    // So do the strings it makes, whether they are kept in objects or in
+    // variables.
+    let source = "var s = \"ab\";
+var kept = [s];
+for (i in 0..27) {
+    s = s + s;
+    kept[0] = s;
+}";
+    assert_eq!(
+        run_limited(source),
+        Err(String::from("test:4:5: out of memory"))
+    );
+    let source = "function f() {
+    var s = \"ab\";
+    for (i in 0..27) s = s + s.slice(0);
+}
+f();";
+    assert_eq!(
+        run_limited(source),
+        Err(String::from("test:3:26: out of memory"))
+    );
+    // Strings that are let go of don't count once they are collected.
+    let source = "for (i in 0..200) {
+    var s = \"ab\";
+    for (j in 0..12) s = s + s;
+}
+print(\"done\");";
+    assert_eq!(run_limited(source), Ok(String::from("done\n")));
+}
+
+#[test]
+fn test_collect_garbage() {
+    // Sweeping a little at a time frees the same objects as sweeping them
+    // all at once.
+    let source = "class Node {
+    init(value, next) {
+        this.value = value;
+        this.next = next;
+    }
+}
+var kept = null;
+var i = 0;
+while (i < 500) {
+    var garbage = Node(i, Node(i, null));
+    if (i % 10 == 0) kept = Node(i, kept);
+    i += 1;
+}
+var total = 0;
+var node = kept;
+while (node != null) {
+    total += node.value;
+    node = node.next;
+}
+print(total);";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let mut stats = Vec::new();
+    for step_budget in [None, Some(3)] {
+        let output = Output::default();
+        let mut vm = Vm::with_output(Box::new(output.clone()));
+        vm.set_gc_config(GcConfig {
+            initial_threshold: 16,
+            min_threshold: 16,
+            step_budget,
+            ..GcConfig::default()
+        });
+        vm.run(&function).unwrap();
+        assert_eq!(output.text(), "12250\n");
+        assert!(vm.heap_stats().collections > 1);
+        vm.collect_garbage();
+        stats.push(vm.heap_stats());
+    }
+    assert_eq!(stats[0].objects, stats[1].objects);
+    assert_eq!(stats[0].counts, stats[1].counts);
+    assert_eq!(stats[0].counts.get("instance"), Some(&50));
+
+    // A host can leave collecting for when it is loading, and then collect
+    // what it made along the way all at once.
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.set_gc_config(GcConfig {
+        initial_threshold: 100_000,
+        ..GcConfig::default()
+    });
+    let program = parse_program(
+        "test",
+        "var level = [];\nvar i = 0;\nwhile (i < 1000) {\n    level.push([i]);\n    i += 1;\n}",
+    )
+    .unwrap();
+    vm.run(&compile(&program).unwrap()).unwrap();
+    let loaded = vm.heap_stats();
+    assert_eq!(loaded.collections, 0);
+    assert!(loaded.counts["list"] >= 1001);
+    vm.set_global("level", ());
+    assert!(vm.collect_garbage() >= 1001);
+    let stats = vm.heap_stats();
+    assert_eq!(stats.collections, 1);
+    assert_eq!(stats.counts.get("list"), None);
+    assert!(stats.bytes < loaded.bytes);
+
+    // The strings the objects hold count toward their bytes.
+    let bytes = |source: &str| {
+        let mut vm = Vm::with_output(Box::new(Output::default()));
+        let program = parse_program("test", source).unwrap();
+        vm.run(&compile(&program).unwrap()).unwrap();
+        vm.heap_stats().bytes
+    };
+    let small = bytes("var s = \"x\";\nvar kept = [s];");
+    let big = bytes("var s = \"x\";\nfor (i in 0..17) s = s + s;\nvar kept = [s];");
+    assert!(big >= small + 2 * 131_071);
+}
+
+#[test]
+fn test_host_globals() {
+    // Globals the host sets before a script runs are there for it to read,
+    // and it leaves what it works out behind for the host.
+    let source = "var area = width * height;
+var title = name + \"!\";
+var big = area > limit;
+var none = null;
+var c = coroutine(function() { while (true) yield area; });";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.set_global("width", 16);
+    vm.set_global("height", 9);
+    vm.set_global("name", "atom");
+    vm.set_global("limit", 100.5);
+    vm.run(&function).unwrap();
+    assert_eq!(vm.get_global::<i64>("area"), Ok(144));
+    assert_eq!(vm.get_global::<f64>("area"), Ok(144.0));
+    assert_eq!(vm.get_global::<String>("title"), Ok(String::from("atom!")));
+    assert_eq!(vm.get_global::<bool>("big"), Ok(true));
+    assert_eq!(vm.get_global::<Option<bool>>("none"), Ok(None));
+    assert_eq!(vm.get_global::<Option<i64>>("width"), Ok(Some(16)));
+    assert_eq!(
+        vm.get_global::<i64>("title"),
+        Err(String::from("title is string, not int"))
+    );
+    assert_eq!(
+        vm.get_global::<bool>("depth"),
+        Err(String::from("depth is not defined"))
+    );
+
+    // Setting a global the script declared changes it where it is, for the
+    // functions of the script to see.
+    vm.set_global("area", 12);
+    assert_eq!(vm.get_global::<i64>("area"), Ok(12));
+    let c = vm.global("c").unwrap().clone();
+    assert_eq!(vm.resume(&c, &[]), Ok(Value::Int(12)));
+
+    // Objects are kept for as long as a global holds them.
+    let program = parse_program("test", "print(len(items), items[1]);").unwrap();
+    let function = compile(&program).unwrap();
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    let items = vec![Value::Int(1), Value::from("two")];
+    let items = vm.heap_mut().alloc(Object::List(items));
+    vm.set_global("items", items);
+    vm.run(&function).unwrap();
+    assert_eq!(output.text(), "2 two\n");
+    assert!(vm.get_global::<atom::value::Handle>("items").is_ok());
+}
+
+#[test]
+fn test_function_handles() {
+    // The host calls a function of the script every frame, which keeps what
+    // it works out in a global.
+    let source = "var total = 0.0;
+function on_update(dt) {
+    total += dt;
+    return total;
+}";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    vm.run(&function).unwrap();
+    let on_update = vm.get_function("on_update").unwrap();
+    for frame in 1..=3 {
+        let total = on_update.call(&mut vm, &[Value::Float(0.5)]);
+        assert_eq!(total, Ok(Value::Float(frame as f64 * 0.5)));
+    }
+    assert_eq!(vm.get_global::<f64>("total"), Ok(1.5));
+    assert_eq!(vm.get_function("total"), None);
+    assert_eq!(vm.get_function("missing"), None);
+
+    // A call that fails is traced back to the function.
+    let error = on_update.call::<_, Value>(&mut vm, ()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "test:3:5: on_update expects 1 argument but got 0"
+    );
+    let error = on_update.call::<_, f64>(&mut vm, ("a",)).unwrap_err();
+    assert_eq!(error.trace()[0].to_string(), "in on_update at test:3:5");
+    assert!(!vm.is_paused());
+
+    // The handle keeps the function after the next script takes the place
+    // of the one it came from.
+    let program = parse_program("test", "var total = \"other\";").unwrap();
+    vm.run(&compile(&program).unwrap()).unwrap();
+    assert_eq!(
+        on_update.call(&mut vm, &[Value::Float(1.0)]),
+        Ok(Value::Float(2.5))
+    );
+}
+
+#[test]
+fn test_release_function() {
+    // A handle keeps its function and what it captures until the host
+    // releases every handle it was given to it.
+    let source = "function make() {
+    var big = [1, 2, 3];
+    return function() { return len(big); };
+}
+var f = make();";
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.run(&compile(&parse_program("test", source).unwrap()).unwrap())
+        .unwrap();
+    let first = vm.get_function("f").unwrap();
+    let second = vm.get_function("f").unwrap();
+    vm.set_global("f", ());
+    vm.collect_garbage();
+    assert_eq!(vm.heap_stats().counts.get("list"), Some(&1));
+    vm.release_function(first);
+    vm.collect_garbage();
+    assert_eq!(second.call(&mut vm, ()), Ok(3));
+    vm.release_function(second);
+    vm.collect_garbage();
+    assert_eq!(vm.heap_stats().counts.get("list"), None);
+}
+
+#[test]
+fn test_host_functions() {
+    // The script calls the host, which calls back into the script, whose
+    // errors only get as far as the host.
+    let source = "function step(x) {
+    try {
+        return x * 2 + 1;
+    } catch (e) {
+        return -1;
+    }
+}
+function fail(x) { return x.missing; }
+print(twice(3), twice(\"a\"));
+try {
+    call(1);
+} catch (e) {
+    print(\"caught\", e);
+}";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    vm.define_raw_function("twice", 1, |vm, args| {
+        let step = vm.get_function("step").ok_or("step is not defined")?;
+        let once = step.call(vm, args).map_err(|e| e.to_string())?;
+        step.call(vm, &[once]).map_err(|e| e.to_string())
+    });
+    vm.define_raw_function("call", 1, |vm, args| {
+        let fail = vm.get_function("fail").ok_or("fail is not defined")?;
+        fail.call(vm, args).map_err(|e| e.to_string())
+    });
+    assert_eq!(vm.run(&function), Ok(Value::Null));
+    assert_eq!(
+        output.text(),
+        "15 -1\ncaught test:8:29: cannot read property missing of int\n"
+    );
+
+    // Host functions are called with as many arguments as they take.
+    let program = parse_program("test", "twice(1, 2);").unwrap();
+    let error = vm.run(&compile(&program).unwrap()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "test:1:1: twice expects 1 argument but got 2"
+    );
+}
+
+#[test]
+fn test_host_function_limits() {
+    // A call back from the host that runs out of fuel stops the script
+    // that called the host too, paused at the call, which is made again
+    // once there is more fuel.
+    let source = "function spin(n) {
+    var i = 0;
+    while (i < n) i += 1;
+    return i;
+}
+print(relay(1000));";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.define_raw_function("relay", 1, |vm, args| {
+        let spin = vm.get_function("spin").ok_or("spin is not defined")?;
+        spin.call(vm, args).map_err(|e| e.message().to_string())
+    });
+    vm.set_fuel(Some(500));
+    let error = vm.run(&function).unwrap_err();
+    assert_eq!(error.kind(), RuntimeErrorKind::FuelExhausted);
+    assert_eq!(error.to_string(), "test:6:7: out of fuel");
+    assert!(vm.is_paused());
+    vm.add_fuel(100000);
+    assert_eq!(vm.continue_running(), Ok(Value::Null));
+    assert_eq!(output.text(), "1000\n");
+
+    // The same goes for a timeout.
+    let source = "function spin(n) {
+    while (true) {}
+}
+relay(0);";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    vm.set_fuel(None);
+    let timeout = std::time::Duration::from_millis(20);
+    let error = vm.eval_with_timeout(&function, timeout).unwrap_err();
+    assert_eq!(error.kind(), RuntimeErrorKind::TimedOut);
+    assert_eq!(error.to_string(), "test:4:1: timed out");
+    assert!(vm.is_paused());
+}
+
+#[test]
+fn test_conversions() {
+    // Rust values go into globals, and come back out of what the script
+    // made of them.
+    let source = "var doubled = [];
+for (n in numbers) doubled.push(n * 2);
+var names = [];
+for (name in ages.keys()) if (ages[name] > 30) names.push(name);
+var swapped = [pair[1], pair[0]];
+var grid = [[1, 2], [3]];
+var total = add(sum(numbers), 1);
+var label = greet(\"atom\");
+var root = try_sqrt(16.0);
+var nothing = ignore();";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    let mut ages = std::collections::HashMap::new();
+    ages.insert("ada", 36);
+    ages.insert("alan", 29);
+    vm.set_global("numbers", vec![1, 2, 3]);
+    vm.set_global("ages", ages);
+    vm.set_global("pair", (7, "seven"));
+    vm.define_function("add", |a: i64, b: i64| a + b);
+    vm.define_function("sum", |numbers: Vec<i64>| numbers.iter().sum::<i64>());
+    vm.define_function("greet", |name: String| format!("hi {}", name));
+    vm.define_function("try_sqrt", |n: f64| {
+        if n < 0.0 {
+            Err(String::from("negative"))
+        } else {
+            Ok(n.sqrt())
+        }
+    });
+    vm.define_function("ignore", || ());
+    vm.run(&function).unwrap();
+    assert_eq!(vm.get_global::<Vec<i64>>("doubled"), Ok(vec![2, 4, 6]));
+    assert_eq!(
+        vm.get_global::<Vec<String>>("names"),
+        Ok(vec![String::from("ada")])
+    );
+    assert_eq!(
+        vm.get_global::<(String, u8)>("swapped"),
+        Ok((String::from("seven"), 7))
+    );
+    assert_eq!(
+        vm.get_global::<Vec<Vec<i32>>>("grid"),
+        Ok(vec![vec![1, 2], vec![3]])
+    );
+    let ages = vm
+        .get_global::<std::collections::HashMap<String, i64>>("ages")
+        .unwrap();
+    assert_eq!(ages["alan"], 29);
+    assert_eq!(vm.get_global::<i64>("total"), Ok(7));
+    assert_eq!(
+        vm.get_global::<String>("label"),
+        Ok(String::from("hi atom"))
+    );
+    assert_eq!(vm.get_global::<f32>("root"), Ok(4.0));
+    assert_eq!(vm.get_global::<Option<i64>>("nothing"), Ok(None));
+
+    // What can't be converted says what is wrong with it.
+    assert_eq!(
+        vm.get_global::<Vec<i64>>("swapped"),
+        Err(String::from("swapped has an item that is string, not int"))
+    );
+    assert_eq!(
+        vm.get_global::<(i64, i64, i64)>("swapped"),
+        Err(String::from("swapped has 2 items, not 3"))
+    );
+    assert_eq!(
+        vm.get_global::<std::collections::HashMap<String, String>>("ages"),
+        Err(String::from("ages has a value that is int, not string"))
+    );
+    vm.set_global("big", 300);
+    assert_eq!(
+        vm.get_global::<u8>("big"),
+        Err(String::from("big is out of range for u8"))
+    );
+    let program = parse_program("test", "add(\"a\", 1);").unwrap();
+    let error = vm.run(&compile(&program).unwrap()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "test:1:1: argument 1 of add is string, not int"
+    );
+    let program = parse_program("test", "try_sqrt(-1);").unwrap();
+    let error = vm.run(&compile(&program).unwrap()).unwrap_err();
+    assert_eq!(error.to_string(), "test:1:1: negative");
+
+    // Calls from the host take and give back Rust values too.
+    let source = "function minmax(a, b) {
+    if (a < b) return [a, b];
+    return [b, a];
+}";
+
+    let program = parse_program("test", source).unwrap();
+    vm.run(&compile(&program).unwrap()).unwrap();
+    let minmax = vm.get_function("minmax").unwrap();
+    assert_eq!(minmax.call(&mut vm, (9, 4)), Ok((4, 9)));
+    assert_eq!(
+        minmax.call(&mut vm, (2.5, 1)),
+        Ok(vec![Value::Int(1), Value::Float(2.5)])
+    );
+    let error = minmax.call::<_, String>(&mut vm, (1, 2)).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "test:2:9: minmax returned a value that is list, not string"
+    );
+}
+// A reply the test gives by hand, the way a server would.
+#[derive(Clone, Default)]
+struct Reply(Arc<Mutex<Option<Result<String, String>>>>);
+
+impl Future for Reply {
+    type Output = Result<String, String>;
+
+    fn poll(self: Pin<&mut Self>, _: &mut task::Context<'_>) -> Poll<Self::Output> {
+        match self.0.lock().unwrap().take() {
+            Some(reply) => Poll::Ready(reply),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test]
+fn test_async_functions() {
+    // Coroutines wait on the host while the script goes on, and each picks
+    // up where it stopped once the host polls its reply.
+    let source = "var log = [];
+function get(url) {
+    try {
+        var body = fetch(url);
+        log.push(url + \" \" + body);
+    } catch (e) {
+        log.push(url + \" \" + e);
+    }
+}
+function load(url) {
+    log.push(\"loading \" + url);
+    return fetch(url);
+}
+function show(url) {
+    var body = load(url);
+    log.push(url + \" \" + body);
+}
+var a = coroutine(get);
+var b = coroutine(get);
+print(a(\"a\"), b(\"b\"), a.done());
+try {
+    a();
+} catch (e) {
+    print(e);
+}
+try {
+    fetch(\"d\");
+} catch (e) {
+    print(e);
+}
+coroutine(show)(\"c\");
+coroutine(show)(\"e\");";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    let replies: Arc<Mutex<std::collections::HashMap<String, Reply>>> = Arc::default();
+    let requests = replies.clone();
+    vm.define_async_function("fetch", move |url: String| {
+        let reply = Reply::default();
+        requests.lock().unwrap().insert(url, reply.clone());
+        reply
+    });
+    let reply = |url: &str, body: Result<&str, &str>| {
+        let body = body.map(String::from).map_err(String::from);
+        *replies.lock().unwrap()[url].0.lock().unwrap() = Some(body);
+    };
+    assert_eq!(vm.run(&function), Ok(Value::Null));
+    assert_eq!(
+        output.text(),
+        "null null false\ncannot resume a waiting coroutine\n\
+         cannot wait for fetch outside of a coroutine\n"
+    );
+    assert_eq!(vm.waiting(), 4);
+
+    let mut cx = task::Context::from_waker(Waker::noop());
+    assert_eq!(vm.poll_waiting(&mut cx), Ok(0));
+    reply("b", Ok("B"));
+    reply("c", Ok("C"));
+    assert_eq!(vm.poll_waiting(&mut cx), Ok(2));
+    reply("a", Err("timed out"));
+    assert_eq!(vm.poll_waiting(&mut cx), Ok(1));
+
+    // An error nothing catches stops the coroutine, and the host gets it.
+    reply("e", Err("refused"));
+    let error = vm.poll_waiting(&mut cx).unwrap_err();
+    assert_eq!(error.to_string(), "test:12:12: refused");
+    assert_eq!(vm.waiting(), 0);
+    assert_eq!(
+        vm.get_global::<Vec<String>>("log"),
+        Ok(vec![
+            String::from("loading c"),
+            String::from("loading e"),
+            String::from("b B"),
+            String::from("c C"),
+            String::from("a timed out"),
+        ])
+    );
+}
+
+#[test]
+fn test_interrupt() {
+    // Another thread stops a script that would never end, which is left
+    // paused where it was.
+    let program = parse_program("test", "var n = 0;\nwhile (true) n += 1;").unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    let handle = vm.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        handle.interrupt();
+    });
+    let error = vm.run(&function).unwrap_err();
+    interrupter.join().unwrap();
+    assert_eq!(error.kind(), RuntimeErrorKind::Interrupted);
+    assert_eq!(error.to_string(), "test:2:1: interrupted");
+    assert!(vm.is_paused());
+
+    // An interrupt before a script starts doesn't stop it.
+    vm.interrupt_handle().interrupt();
+    let program = parse_program("test", "function f(n) { return n; }\nvar x = f(1);").unwrap();
+    assert_eq!(vm.run(&compile(&program).unwrap()), Ok(Value::Null));
+    assert!(!vm.is_paused());
+    assert_eq!(vm.global("x"), Some(&Value::Int(1)));
+}
+
+#[test]
+fn test_timeout() {
+    // A script that runs for too long stops at the timeout, and is left
+    // paused where it was.
+    let program = parse_program("test", "var n = 0;\nwhile (true) n += 1;").unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    let started = std::time::Instant::now();
+    let timeout = std::time::Duration::from_millis(20);
+    let error = vm.eval_with_timeout(&function, timeout).unwrap_err();
+    assert!(started.elapsed() >= timeout);
+    assert_eq!(error.kind(), RuntimeErrorKind::TimedOut);
+    assert_eq!(error.to_string(), "test:2:1: timed out");
+    assert!(vm.is_paused());
+
+    // One that is done in time isn't stopped, and the timeout is over
+    // with it.
+    let program = parse_program("test", "var n = 0;\nwhile (n < 100000) n += 1;").unwrap();
+    let function = compile(&program).unwrap();
+    let timeout = std::time::Duration::from_secs(60);
+    assert_eq!(vm.eval_with_timeout(&function, timeout), Ok(Value::Null));
+    assert_eq!(vm.global("n"), Some(&Value::Int(100000)));
+
+    // A timeout that is over before the script even starts still stops it.
+    let program = parse_program("test", "while (true) {}").unwrap();
+    let function = compile(&program).unwrap();
+    for timeout in [
+        std::time::Duration::ZERO,
+        std::time::Duration::from_nanos(1),
+        std::time::Duration::from_micros(100),
+    ] {
+        for _ in 0..20 {
+            let error = vm.eval_with_timeout(&function, timeout).unwrap_err();
+            assert_eq!(error.kind(), RuntimeErrorKind::TimedOut);
+        }
+    }
+
+    // Compiled code stops at the timeout too.
+    #[cfg(feature = "jit")]
+    {
+        let source = "function spin(n) {
+    while (n >= 0) n += 1;
+    return n;
+}
+spin(0);";
+        let program = parse_program("test", source).unwrap();
+        let function = compile_with(&program, OptLevel::Basic).unwrap();
+        vm.set_jit_threshold(Some(1));
+        let timeout = std::time::Duration::from_millis(20);
+        let error = vm.eval_with_timeout(&function, timeout).unwrap_err();
+        assert_eq!(error.kind(), RuntimeErrorKind::TimedOut);
+        assert_eq!(vm.jit_stats().compiled, 1);
+        assert_eq!(vm.jit_stats().bailouts, 1);
+    }
+}
+
+// Writes down where the script stopped and what it could see, and steps
+// the way it is told to.
+struct Recorder {
+    stops: Arc<Mutex<Vec<String>>>,
+    steps: Vec<Step>,
+}
+
+impl DebugListener for Recorder {
+    fn stopped(&mut self, paused: &Paused, reason: StopReason) -> Step {
+        let locals: Vec<String> = paused
+            .locals(0)
+            .iter()
+            .chain(paused.upvalues(0).iter())
+            .map(|(name, value)| format!("{}={}", name, value.display(paused.heap())))
+            .collect();
+        let frames: Vec<String> = paused.frames().iter().map(|f| f.to_string()).collect();
+        self.stops.lock().unwrap().push(format!(
+            "{:?} {} [{}]",
+            reason,
+            frames.join(", "),
+            locals.join(" ")
+        ));
+        if self.steps.is_empty() {
+            Step::Continue
+        } else {
+            self.steps.remove(0)
+        }
+    }
+}
+
+#[test]
+fn test_debugger() {
+    let source = "function add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+var x = add(1, 2);
+var y = add(x, 3);
+function outer() {
+    var n = 10;
+    var inner = function() { return n + y; };
+    return inner();
+}
+print(outer());";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let stops = Arc::new(Mutex::new(Vec::new()));
+    let debug = |steps: Vec<Step>, breakpoints: &[u32], first: Step| {
+        let mut vm = Vm::with_output(Box::new(Output::default()));
+        stops.lock().unwrap().clear();
+        vm.set_debug_listener(Some(Box::new(Recorder {
+            stops: stops.clone(),
+            steps,
+        })));
+        for &line in breakpoints {
+            vm.set_breakpoint("test", line);
+        }
+        vm.set_step(first);
+        vm.run(&function).unwrap();
+        stops.lock().unwrap().clone()
+    };
+
+    // Breakpoints stop every time their line runs, and stepping over a line
+    // doesn't stop in the functions it calls.
+    assert_eq!(
+        debug(vec![Step::Over, Step::Over], &[2], Step::Continue),
+        vec![
+            "Breakpoint in add at test:2:15, at test:5:9 [a=1 b=2]",
+            "Step in add at test:3:12, at test:5:9 [a=1 b=2 sum=3]",
+            "Step at test:6:9 []",
+            "Breakpoint in add at test:2:15, at test:6:9 [a=3 b=3]",
+        ]
+    );
+
+    // Stepping into goes down into calls, and stepping out goes back up
+    // to the line after the call.
+    let steps = vec![
+        Step::Into,
+        Step::Into,
+        Step::Out,
+        Step::Into,
+        Step::Into,
+        Step::Out,
+        Step::Continue,
+        Step::Over,
+        Step::Over,
+        Step::Into,
+    ];
+    assert_eq!(
+        debug(steps, &[8], Step::Into),
+        vec![
+            "Step at test:1:1 []",
+            "Step at test:5:9 []",
+            "Step in add at test:2:15, at test:5:9 [a=1 b=2]",
+            "Step at test:6:9 []",
+            "Step in add at test:2:15, at test:6:9 [a=3 b=3]",
+            "Step in add at test:3:12, at test:6:9 [a=3 b=3 sum=6]",
+            "Step at test:7:1 []",
+            "Breakpoint in outer at test:8:13, at test:12:7 []",
+            "Step in outer at test:9:17, at test:12:7 [n=10]",
+            "Step in outer at test:10:12, at test:12:7 [n=10 inner=<function lambda>]",
+            "Step in lambda at test:9:37, at test:12:7 [n=10]",
+        ]
+    );
+}
+
+#[test]
+fn test_profiler() {
+    let source = "function fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+function count(n) {
+    if (n == 0) return 0;
+    return count(n - 1);
+}
+function fails() {
+    throw \"no\";
+}
+function walk() {
+    yield 1;
+    yield 2;
+}
+fib(10);
+count(50);
+try { fails(); } catch (e) {}
+var w = coroutine(walk);
+w();
+w();";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    assert_eq!(vm.profile(), None);
+    vm.start_profiling();
+    vm.run(&function).unwrap();
+    let profile = vm.stop_profiling().unwrap();
+    assert_eq!(vm.profile(), None);
+
+    // Every call counts, even the ones that ended in a tail call or a throw,
+    // and every resume of a coroutine.
+    let calls = |name: &str| profile.function(name).map(|f| f.calls);
+    assert_eq!(calls("fib"), Some(177));
+    assert_eq!(calls("count"), Some(51));
+    assert_eq!(calls("fails"), Some(1));
+    assert_eq!(calls("walk"), Some(2));
+    let fib = profile.function("fib").unwrap();
+    assert_eq!((&fib.file[..], fib.line), ("test", 2));
+    for f in &profile.functions {
+        assert!(f.inclusive >= f.exclusive, "{:?}", f);
+    }
+    // The script itself is the function every other one was called from.
+    let script = profile.function("test").unwrap();
+    let total = profile.functions.iter().map(|f| f.exclusive).sum();
+    assert_eq!(script.inclusive, total);
+    #[cfg(feature = "opcode-counts")]
+    assert!(profile
+        .opcodes
+        .contains(&(atom::bytecode::OpCode::TailCall, 50)));
+    let report = profile.report();
+    assert!(report.starts_with("function"), "{}", report);
+    assert!(report.contains("fib (test:2)"), "{}", report);
+
+    // Profiling again starts over.
+    vm.start_profiling();
+    vm.run(&function).unwrap();
+    assert_eq!(vm.profile().unwrap().function("fib").unwrap().calls, 177);
+}
+
+#[test]
+fn test_trace() {
+    let source = "function double(n) {
+    return n * 2;
+}
+print(double(4));";
+    let program = parse_program("test", source).unwrap();
+    let function = compile_with(&program, OptLevel::None).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    let trace = Output::default();
+    assert!(!vm.is_tracing());
+    assert!(vm.set_trace(Some(Box::new(trace.clone()))).is_none());
+    vm.run(&function).unwrap();
+    let lines: Vec<String> = trace.text().lines().map(String::from).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "test:1 test 0000 constant      0 <function double> | <function test>",
+            "test:1 test 0002 define_slot   0 double | <function double>",
+            "test:4 test 0004 get_global    1 \"print\" | <function test>",
+            "test:4 test 0006 get_slot      0 double | <function print>",
+            "test:4 test 0008 constant      2 4 | <function double>",
+            "test:4 test 0010 call          1 | 4",
+            "test:2 double 0000 get_local     1 | 4",
+            "test:2 double 0002 constant      0 2 | 4",
+            "test:2 double 0004 multiply | 2",
+            "test:2 double 0005 return | 8",
+            "test:4 test 0012 call          1 | 8",
+            "test:4 test 0014 pop | null",
+            "test:4 test 0015 null | <function test>",
+            "test:4 test 0016 return | null",
+        ]
+    );
+
+    // Once tracing stops, nothing more is written.
+    assert!(vm.set_trace(None).is_some());
+    assert!(!vm.is_tracing());
+    vm.run(&function).unwrap();
+    assert_eq!(trace.text().lines().count(), lines.len());
+}
+
+#[test]
+fn test_threads() {
+    fn is_send<T: Send>() {}
+    is_send::<Vm>();
+
+    // The same compiled script runs on a VM of its own on every thread.
+    let source = "var total = 0;
+for (i in 0..1000) total += i;
+var names = [];
+for (i in 0..100) names = names + [\"entity\" + str(i)];
+print(total, names[99]);";
+    let program = parse_program("test", source).unwrap();
+    let function = Arc::new(compile(&program).unwrap());
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let function = function.clone();
+            std::thread::spawn(move || {
+                let out = Output::default();
+                let mut vm = Vm::with_output(Box::new(out.clone()));
+                vm.set_gc_config(GcConfig {
+                    stress: true,
+                    ..GcConfig::default()
+                });
+                vm.run(&function).unwrap();
+                out.text()
+            })
+        })
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), "499500 entity99\n");
+    }
+
+    // A VM can be made on one thread and run on another.
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.run(&function).unwrap();
+    let thread = std::thread::spawn(move || vm.run(&function).is_ok());
+    assert!(thread.join().unwrap());
+}
+
+#[test]
+fn test_modules() {
+    let mut loader = FileLoader::default();
+    loader.provide(
+        "lib/counter.at",
+        "print(\"counter loaded\");
+var count = 0;
+export function next() {
+    count += 1;
+    return count;
+}",
+    );
+    loader.provide(
+        "lib/user.at",
+        "import \"counter.at\";
+export function label() { return \"user \" + str(counter.next()); }",
+    );
+    loader.provide("a.at", "import \"b.at\";\nexport var a = 1;");
+    loader.provide("b.at", "import \"a.at\";\nexport var b = 2;");
+    loader.provide("broken.at", "throw \"broken\";");
+    loader.provide("bad.at", "var = 1;");
+    let run = |vm: &mut Vm, name: &str, source: &str| {
+        let program = parse_program(name, source).unwrap();
+        let function = compile(&program).unwrap();
+        vm.run(&function).map_err(|e| e.to_string())
+    };
+    let out = Output::default();
+    let mut vm = Vm::with_output(Box::new(out.clone()));
+    vm.set_module_loader(Some(Box::new(loader)));
+
+    // A module runs once however many modules import it, and keeps globals
+    // of its own.
+    let main = "import \"lib/counter.at\" as counter;
+import \"lib/user.at\" as user;
+var count = 100;
+print(counter.next(), counter.next(), user.label(), count);";
+    run(&mut vm, "main.at", main).unwrap();
+    assert_eq!(out.text(), "counter loaded\n1 2 user 3 100\n");
+    assert!(vm.module("lib/counter.at").is_some());
+    assert_eq!(vm.module("main.at"), None);
+
+    // Modules stay imported from one script to the next.
+    run(&mut vm, "main.at", main).unwrap();
+    assert_eq!(
+        out.text(),
+        "counter loaded\n1 2 user 3 100\n4 5 user 6 100\n"
+    );
+    vm.clear_modules();
+    assert_eq!(vm.module("lib/counter.at"), None);
+
+    assert_eq!(
+        run(&mut vm, "main.at", "import \"a.at\";"),
+        Err(String::from("b.at:1:1: import cycle: a.at -> b.at -> a.at"))
+    );
+    assert_eq!(
+        run(&mut vm, "b.at", "import \"a.at\";"),
+        Err(String::from("a.at:1:1: import cycle: b.at -> a.at -> b.at"))
+    );
+    assert_eq!(
+        run(&mut vm, "main.at", "import \"missing.at\";"),
+        Err(String::from("main.at:1:1: cannot find module missing.at"))
+    );
+    assert_eq!(
+        run(&mut vm, "main.at", "import \"bad.at\";"),
+        Err(String::from(
+            "main.at:1:1: cannot compile module bad.at: bad.at:1:5: expected variable name but found '='"
+        ))
+    );
+
+    // A module that throws isn't imported, and runs again the next time.
+    for _ in 0..2 {
+        assert_eq!(
+            run(&mut vm, "main.at", "import \"broken.at\";"),
+            Err(String::from("broken.at:1:1: broken"))
+        );
+    }
+    assert_eq!(vm.module("broken.at"), None);
+
+    // A bundle has the modules its entry point imports.
+    let mut project = Project::new();
+    project.provide("main.at", "import \"lib.at\";\nprint(lib.name);");
+    project.provide("lib.at", "export var name = \"lib\";");
+    let bundle = project
+        .build("main.at", &CompilerOptions::default())
+        .unwrap();
+    let out = Output::default();
+    let mut vm = Vm::with_output(Box::new(out.clone()));
+    vm.set_module_loader(Some(Box::new(bundle.clone())));
+    vm.run(bundle.entry()).unwrap();
+    assert_eq!(out.text(), "lib\n");
+}
+
+// A window of the host, which scripts can rename.
+struct Window {
+    title: Mutex<String>,
+}
+
+const WINDOW_METHODS: &[Native] = &[
+    Native {
+        name: "Window.title",
+        arity: 0,
+        min_arity: 0,
+        rest: false,
+        function: window_title,
+    },
+    Native {
+        name: "Window.rename",
+        arity: 1,
+        min_arity: 1,
+        rest: false,
+        function: window_rename,
+    },
+];
+
+fn window(context: &Context, value: &Value) -> Result<Arc<Window>, String> {
+    match value.as_object().map(|handle| context.heap.get(handle)) {
+        Some(Object::Foreign(foreign)) => foreign
+            .downcast_arc()
+            .ok_or_else(|| String::from("not a window")),
+        _ => Err(String::from("not a window")),
+    }
+}
+
+fn window_title(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let window = window(context, &args[0])?;
+    let title = window.title.lock().unwrap();
+    Ok(Value::from(&title[..]))
+}
+
+fn window_rename(context: &mut Context, args: &[Value]) -> Result<Value, String> {
+    let window = window(context, &args[0])?;
+    let title = args[1].as_str().ok_or("a title is a string")?;
+    *window.title.lock().unwrap() = String::from(title);
+    Ok(Value::Null)
+}
+
+#[test]
+fn test_foreign() {
+    let source = "function open(w) {
+    print(w, w.title());
+    w.rename(\"renamed\");
+    yield w == w;
+    w.close();
+}
+var c = coroutine(open);";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let out = Output::default();
+    let mut vm = Vm::with_output(Box::new(out.clone()));
+    vm.run(&function).unwrap();
+
+    let window = Window {
+        title: Mutex::new(String::from("main")),
+    };
+    let foreign = Foreign::new("Window", window).with_methods(WINDOW_METHODS);
+    let value = Value::foreign(vm.heap_mut(), foreign);
+    let c = vm.global("c").unwrap().clone();
+    assert_eq!(
+        vm.resume(&c, std::slice::from_ref(&value)),
+        Ok(Value::Bool(true))
+    );
+    assert_eq!(out.text(), "<Window> main\n");
+    assert_eq!(value.type_name(vm.heap()), "Window");
+    let window = value.as_foreign::<Window>(vm.heap()).unwrap();
+    assert_eq!(*window.title.lock().unwrap(), "renamed");
+    assert!(value.as_foreign::<String>(vm.heap()).is_none());
+    assert_eq!(
+        vm.resume(&c, &[]).unwrap_err().to_string(),
+        "test:5:7: cannot read property close of Window"
+    );
+}
+
+// Saves a window as its title.
+fn window_types() -> ForeignTypes {
+    let mut types = ForeignTypes::new();
+    types.register(
+        "Window",
+        |foreign| {
+            let window = foreign.downcast::<Window>().ok_or("not a window")?;
+            Ok(window.title.lock().unwrap().clone().into_bytes())
+        },
+        |bytes| {
+            let title = String::from_utf8(bytes.to_vec()).map_err(|_| "not a title")?;
+            let window = Window {
+                title: Mutex::new(title),
+            };
+            Ok(Foreign::new("Window", window).with_methods(WINDOW_METHODS))
+        },
+    );
+    types
+}
+
+#[test]
+fn test_snapshots() {
+    // A game saves in the middle of a coroutine, and the save is loaded
+    // into a new VM, which picks up where the coroutine stopped.
+    let source = "class Counter {
+    init(start) { this.n = start; }
+    get value() { return this.n; }
+    bump() { this.n += 1; return this.n; }
+}
+function count(counter, w) {
+    var seen = [];
+    function note(x) { seen.push(x); }
+    while (true) {
+        note(counter.bump());
+        var got = yield seen;
+        print(w.title(), got, seen, add(counter.value, 1));
+    }
+}
+var counter = Counter(10);
+var scores = {\"a\": 1, \"w\": weak(counter)};
+scores[2] = [3, 4.5];
+var c = coroutine(count);
+function total() { return scores[\"a\"] + counter.value; }
+function show() { print(scores[2], scores[\"w\"].get().value); }";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.define_function("add", |a: i64, b: i64| a + b);
+    vm.run(&function).unwrap();
+    let window = Window {
+        title: Mutex::new(String::from("main")),
+    };
+    let window = Foreign::new("Window", window).with_methods(WINDOW_METHODS);
+    let window = Value::foreign(vm.heap_mut(), window);
+    let counter = vm.global("counter").unwrap().clone();
+    let c = vm.global("c").unwrap().clone();
+    vm.resume(&c, &[counter, window]).unwrap();
+    let total = vm.get_function("total").unwrap();
+    let types = window_types();
+    let saved = vm.snapshot(&types).unwrap();
+
+    let out = Output::default();
+    let mut restored = Vm::with_output(Box::new(out.clone()));
+    restored.define_function("add", |a: i64, b: i64| a + b);
+    restored.restore(&saved, &types).unwrap();
+    assert_eq!(restored.snapshot(&types), Ok(saved.clone()));
+    let c = restored.global("c").unwrap().clone();
+    restored.resume(&c, &[Value::from("x")]).unwrap();
+    restored.resume(&c, &[Value::from("y")]).unwrap();
+    assert_eq!(out.text(), "main x [11] 12\nmain y [11, 12] 13\n");
+    assert_eq!(total.call(&mut restored, ()), Ok(14));
+    let show = restored.get_function("show").unwrap();
+    assert_eq!(show.call(&mut restored, ()), Ok(Value::Null));
+    assert!(out.text().ends_with("[3, 4.5] 13\n"));
+
+    // The VM that saved is unchanged.
+    let c = vm.global("c").unwrap().clone();
+    vm.resume(&c, &[Value::Null]).unwrap();
+    assert_eq!(total.call(&mut vm, ()), Ok(13));
+
+    // A foreign value can only be saved and loaded by a type that knows
+    // how, and the functions of the host have to be defined first.
+    assert_eq!(
+        vm.snapshot(&ForeignTypes::new()),
+        Err(String::from(
+            "cannot snapshot Window values, which have no way to be saved"
+        ))
+    );
+    let mut other = Vm::with_output(Box::new(Output::default()));
+    other.set_global("kept", 1);
+    assert_eq!(
+        other.restore(&saved, &ForeignTypes::new()),
+        Err(String::from(
+            "cannot restore Window values, which have no way to be loaded"
+        ))
+    );
+    assert_eq!(
+        other.restore(&saved, &types),
+        Err(String::from("add is not defined"))
+    );
+    assert_eq!(
+        other.restore(b"not a snapshot", &types),
+        Err(String::from("not a snapshot of a virtual machine"))
+    );
+    other.define_function("add", |a: i64, b: i64| a + b);
+    assert_eq!(
+        other.restore(&saved[..saved.len() - 1], &types),
+        Err(String::from("unexpected end of snapshot"))
+    );
+    assert_eq!(other.get_global::<i64>("kept"), Ok(1));
+
+    // Nothing can be saved while a script is running.
+    vm.define_raw_function("save", 0, |vm, _| {
+        vm.snapshot(&ForeignTypes::new()).map(|_| Value::Null)
+    });
+    let program = parse_program("test", "save();").unwrap();
+    assert_eq!(
+        vm.run(&compile(&program).unwrap()).unwrap_err().to_string(),
+        "test:1:1: cannot snapshot while a script is running"
+    );
+}
+
+#[test]
+fn test_snapshot_slots() {
+    // Objects go back in their slots when there are empty ones between.
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    let program = parse_program("test", "var a = [1]; var b = [2]; a = null;").unwrap();
+    vm.run(&compile(&program).unwrap()).unwrap();
+    vm.collect_garbage();
+    let saved = vm.snapshot(&ForeignTypes::new()).unwrap();
+    let mut restored = Vm::with_output(Box::new(Output::default()));
+    restored.restore(&saved, &ForeignTypes::new()).unwrap();
+    assert_eq!(restored.snapshot(&ForeignTypes::new()), Ok(saved));
+
+    // A slot far past the end of the snapshot is refused rather than
+    // making room for it.
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.set_global("x", vec![7i64]);
+    let mut saved = vm.snapshot(&ForeignTypes::new()).unwrap();
+    let list = [0, 0, 0, 0, 1, 3, 0, 0, 0, 0, 0, 0, 0, 7];
+    let at = saved.windows(list.len()).position(|w| w == list).unwrap();
+    saved[at - 4..at].copy_from_slice(&0xffff_fff0u32.to_be_bytes());
+    assert_eq!(
+        restored.restore(&saved, &ForeignTypes::new()),
+        Err(String::from("invalid snapshot"))
+    );
+}
+
+#[test]
+fn test_snapshot_methods() {
+    let source = "class A { init() { this.n = 1; } m() { return this.n; } }
+var a = A();
+var m = a.m;
+function make() { return A(); }";
+    let program = parse_program("test", source).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.run(&compile(&program).unwrap()).unwrap();
+    let saved = vm.snapshot(&ForeignTypes::new()).unwrap();
+    let mut restored = Vm::with_output(Box::new(Output::default()));
+    restored.restore(&saved, &ForeignTypes::new()).unwrap();
+    let make = restored.get_function("make").unwrap();
+    assert!(make.call::<_, Value>(&mut restored, ()).is_ok());
+
+    // An initializer that isn't a function, or a bound method of something
+    // that isn't one, is refused rather than called. The object each points
+    // to is swapped for an empty string, which takes as many bytes.
+    let damaged = |at: usize| {
+        let mut saved = saved.clone();
+        saved[at..at + 5].copy_from_slice(&[5, 0, 0, 0, 0]);
+        let mut restored = Vm::with_output(Box::new(Output::default()));
+        restored.restore(&saved, &ForeignTypes::new())
+    };
+    let init = b"\0\0\0\x04init\x06";
+    let at = saved.windows(init.len()).position(|w| w == init).unwrap();
+    assert_eq!(
+        damaged(at + init.len() - 1),
+        Err(String::from("invalid snapshot"))
+    );
+    // A bound method is its tag, then the receiver and the method, each an
+    // object tag and a handle.
+    let bound: Vec<usize> = saved
+        .windows(7)
+        .enumerate()
+        .filter(|(_, w)| w[0] == 10 && w[1] == 6 && w[6] == 6)
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(bound.len(), 1);
+    assert_eq!(damaged(bound[0] + 6), Err(String::from("invalid snapshot")));
+}
+
+// Runs the script with every kind of inline cache, checking it does the
+// same as in the interpreter.
+fn run_cached(source: &str) -> String {
+    let expected = printed(source);
+    let program = parse_program("test", source).unwrap();
+    let function = compile_with(&program, OptLevel::Basic).unwrap();
+    for caches in [
+        InlineCaches::Off,
+        InlineCaches::Monomorphic,
+        InlineCaches::Polymorphic,
+    ] {
+        for stress in [false, true] {
+            let output = Output::default();
+            let mut vm = Vm::with_output(Box::new(output.clone()));
+            vm.set_inline_caches(caches);
+            vm.set_gc_config(GcConfig {
+                stress,
+                ..GcConfig::default()
+            });
+            vm.run(&function).unwrap();
+            assert_eq!(output.text(), expected, "with {:?} caches", caches);
+        }
+    }
+    expected
+}
+
+#[test]
+fn test_inline_caches() {
+    // One place reading the same property of more classes than a cache
+    // holds, with their fields set in different orders.
+    let source = "class A { init() { this.x = 1; this.y = 2; } area() { return this.x; } }
+class B { init() { this.y = 3; this.x = 4; } area() { return this.y; } }
+class C extends A { area() { return super.area() * 10; } }
+class D { get area() { return function() { return 5; }; } }
+class E { init() { this.area = function() { return 6; }; } }
+class F { area() { return 7; } }
+var shapes = [A(), B(), C(), D(), E(), F(), A(), B()];
+function area(shape) { return shape.area(); }
+var total = 0;
+for (round in 0..3) {
+    for (shape in shapes) {
+        total += area(shape);
+    }
+}
+print(total);";
+    assert_eq!(run_cached(source), "108\n");
+
+    // A field set after a method was cached comes before the method, and a
+    // field written through a cache lands where it is on every instance.
+    let source = "class A {
+    init(first) {
+        if (first) { this.a = 1; this.b = 2; } else { this.b = 2; this.a = 1; }
+    }
+    m() { return \"method\"; }
+}
+function call(o) { return o.m(); }
+function bump(o) { o.a = o.a + 1; return o.a; }
+var one = A(true);
+var two = A(false);
+print(call(one), call(two), bump(one), bump(two), bump(one));
+two.m = function() { return \"field\"; };
+print(call(one), call(two));";
+    assert_eq!(run_cached(source), "method method 2 2 3\nmethod field\n");
+
+    // Classes made over and over, which collecting garbage frees and hands
+    // the handles of to the next ones.
+    let source = "function make(n) {
+    class K { value() { return n; } }
+    return K();
+}
+function value(k) { return k.value(); }
+var sum = 0;
+for (i in 0..20) {
+    sum += value(make(i));
+}
+print(sum);";
+    assert_eq!(run_cached(source), "190\n");
+}
+
+// Runs the script compiling every function on its first call, checking it
+// does the same as in the interpreter, and hands back what the JIT did.
+#[cfg(feature = "jit")]
+fn run_jit(source: &str) -> (Result<String, String>, atom::vm::jit::JitStats) {
+    let expected = run(source);
+    let program = parse_program("test", source).unwrap();
+    let function = compile_with(&program, OptLevel::Basic).unwrap();
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_jit_threshold(Some(1));
+    let result = match vm.run(&function) {
+        Ok(_) => Ok(output.text()),
+        Err(error) => Err(error.to_string()),
+    };
+    assert_eq!(result, expected, "for {}", source);
+    (result, vm.jit_stats())
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn test_jit() {
+    // Loops over ints and floats, mixed together.
+    let source = "function sum(n, scale) {
+    var s = 0.0;
+    var i = 0;
+    while (i < n) {
+        s += i * scale;
+        i += 1;
+    }
+    return s;
+}
+function steps(n) {
+    var count = 0;
+    while (n != 1) {
+        if (n % 2 == 0) n = n / 2; else n = 3 * n + 1;
+        count += 1;
+    }
+    return count;
+}
+var total = 0.0;
+for (i in 0..100) total += sum(10, 0.5);
+print(total, steps(27), steps(97));";
+    let (result, stats) = run_jit(source);
+    assert_eq!(result, Ok(String::from("2250.0 111 118\n")));
+    assert_eq!(stats.compiled, 2);
+    assert_eq!(stats.bailouts, 0);
+
+    // Comparisons, bools and null, and a function called with other types
+    // than it was compiled for.
+    let source = "function test(a, b) {
+    if (a == null) return false;
+    var r = a >= b;
+    if (a < b and !(a == b)) r = !r;
+    return r;
+}
+print(test(1, 2), test(2, 1), test(1.5, 0.5), test(\"a\", \"b\"), test(null, 1));";
+    let (result, stats) = run_jit(source);
+    assert_eq!(result, Ok(String::from("true true true true false\n")));
+    assert_eq!(stats.compiled, 1);
+
+    // Functions that do more than work out numbers are left to the
+    // interpreter.
+    let source = "function greet(n) {
+    var s = \"\";
+    for (i in 0..n) s += \"hi\";
+    return s;
+}
+function same(a, b) { if (a == b) return 1; return 0; }
+print(greet(2), same(1, 1.0));";
+    let (result, stats) = run_jit(source);
+    assert_eq!(result, Ok(String::from("hihi 1\n")));
+    assert_eq!(stats.compiled, 0);
+    assert_eq!(stats.rejected, 2);
+
+    // Dividing by zero bails out of the native code, and the interpreter
+    // runs the call again to throw the error.
+    let source = "function ratio(a, b) {
+    var r = 0;
+    if (a > 0) r = a / b; else r = a % b;
+    return r;
+}
+print(ratio(7, 2), ratio(-7, 2));
+try { ratio(1, 0); } catch (e) { print(e); }";
+    let (result, stats) = run_jit(source);
+    assert_eq!(result.unwrap().lines().next(), Some("3 -1"));
+    assert_eq!(stats.bailouts, 1);
+
+    // So does overflowing an int.
+    let source = "function grow(x) {
+    while (x < 1000000000000) x = x * x;
+    return x;
+}
+print(grow(2));";
+    let (_, stats) = run_jit(source);
+    assert_eq!(stats.compiled, 1);
+    assert_eq!(stats.bailouts, 1);
+
+    // A compiled function the host calls is done without a frame.
+    let source = "function sum(n) {
+    var s = 0;
+    while (n > 0) { s += n; n -= 1; }
+    return s;
+}";
+    let program = parse_program("test", source).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.set_jit_threshold(Some(1));
+    vm.run(&compile(&program).unwrap()).unwrap();
+    let sum = vm.get_function("sum").unwrap();
+    assert_eq!(sum.call(&mut vm, &[Value::Int(10)]), Ok(Value::Int(55)));
+    assert_eq!(sum.call(&mut vm, &[Value::Int(4)]), Ok(Value::Int(10)));
+    assert_eq!(vm.jit_stats().compiled, 1);
+}
+
+#[test]
+fn test_tail_calls() {
+    // A chain of tail calls never grows the stack, however long it is.
+    let source = "function count(n, total) {
+    if (n == 0) return total;
+    return count(n - 1, total + 1);
+}
+print(count(100000, 0));";
+    assert_eq!(
+        run_vm(source, OptLevel::Basic, false),
+        Ok(String::from("100000\n"))
+    );
+}
+
+#[test]
+fn test_garbage() {
+    // Objects the script has let go of are freed as it runs, while the
+    // ones it holds on to stay, including the ones only closures have.
+    let source = "var kept = [];
+function make(n) {
+    var box = [n];
+    return function() { return box[0]; };
+}
+var i = 0;
+while (i < 5000) {
+    var garbage = [i, {n: i}, \"{i}\"];
+    if (i % 1000 == 0) kept = kept + [make(i)];
+    i += 1;
+}
+print(kept[4](), len(kept));";
+    let program = parse_program("test", source).unwrap();
+    let function = compile(&program).unwrap();
+    for stress in [false, true] {
+        let output = Output::default();
+        let mut vm = Vm::with_output(Box::new(output.clone()));
+        vm.set_gc_config(GcConfig {
+            min_threshold: 100,
+            stress,
+            ..GcConfig::default()
+        });
+        vm.run(&function).unwrap();
+        assert_eq!(output.text(), "4000 5\n");
+        assert!(vm.heap().len() < 250, "{} objects left", vm.heap().len());
+    }
+}
+
+#[test]
+fn test_weak() {
+    // A weak reference gives its object for as long as something else
+    // keeps it, and null after.
+    let source = "var cache = {};
+function remember(key, value) { cache[key] = weak(value); }
+var kept = [1];
+remember(\"kept\", kept);
+remember(\"dropped\", [2]);
+var i = 0;
+while (i < 10) {
+    var garbage = [i];
+    i += 1;
+}
+print(cache[\"kept\"].get() == kept, cache[\"dropped\"].get());";
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    let program = parse_program("test", source).unwrap();
+    vm.run(&compile(&program).unwrap()).unwrap();
+    assert_eq!(output.text(), "true null\n");
+
+    let program = parse_program("test", "weak(1);").unwrap();
+    assert_eq!(
+        vm.run(&compile(&program).unwrap()).unwrap_err().to_string(),
+        "test:1:1: cannot make a weak reference to int"
+    );
+}
+
+static FINALIZED: AtomicUsize = AtomicUsize::new(0);
+
+fn finalize(foreign: &Foreign) {
+    let texture = foreign.downcast::<u32>().unwrap();
+    FINALIZED.fetch_add(*texture as usize, Ordering::SeqCst);
+}
+
+#[test]
+fn test_finalizers() {
+    // Foreign values are finalized once they are collected, and not while
+    // a script holds on to them.
+    let source = "var held = null;
+function hold(texture) { held = texture; yield; }
+var c = coroutine(hold);";
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    vm.set_gc_config(GcConfig {
+        stress: true,
+        ..GcConfig::default()
+    });
+    let program = parse_program("test", source).unwrap();
+    vm.run(&compile(&program).unwrap()).unwrap();
+    let texture = |vm: &mut Vm, id: u32| {
+        let foreign = Foreign::new("Texture", id).with_finalizer(finalize);
+        Value::foreign(vm.heap_mut(), foreign)
+    };
+    let held = texture(&mut vm, 1);
+    texture(&mut vm, 10);
+    let c = vm.global("c").unwrap().clone();
+    vm.resume(&c, &[held]).unwrap();
+    assert_eq!(FINALIZED.load(Ordering::SeqCst), 10);
+    assert_eq!(
+        vm.global("held").map(|held| held.type_name(vm.heap())),
+        Some("Texture")
+    );
+}
+
+#[test]
+fn test_results() {
+    let program = parse_program("test", "var x = 2;\ny = x * 3;").unwrap();
+    let function = compile(&program).unwrap();
+    let mut vm = Vm::with_output(Box::new(Output::default()));
+    assert!(vm.run(&function).is_err());
+    assert_eq!(vm.global("x"), Some(&Value::Int(2)));
+    assert_eq!(vm.global("y"), None);
+
+    // The machine can run another script after one has failed.
+    let program = parse_program("test", "var xs = [1];\nxs[0] += 1;").unwrap();
+    let function = compile(&program).unwrap();
+    assert_eq!(vm.run(&function), Ok(Value::Null));
+    let xs = vm.global("xs").unwrap();
+    assert_eq!(xs.display(vm.heap()).to_string(), "[2]");
+    assert!(vm.global("print").is_some());
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(
+        error("var a = 1;\nprint(a + \"a\");"),
+        "test:2:7: cannot use + with int and string"
+    );
+    assert_eq!(error("var x = 1;\nprint(y);"), "test:2:7: y is not defined");
+    assert_eq!(error("z = 1;"), "test:1:1: z is not defined");
+    assert_eq!(error("print(x);\nvar x = 1;"), "test:1:7: x is not defined");
+    assert_eq!(
+        error("var n = 0;\nprint(1 / n);"),
+        "test:2:7: division by zero"
+    );
+    assert_eq!(error("var f = 3;\nf();"), "test:2:1: cannot call int");
+    assert_eq!(
+        error("function call(f) { return f(); }\ncall(function(a, b = 1) {});"),
+        "test:1:27: lambda expects 1 to 2 arguments but got 0"
+    );
+    assert_eq!(
+        error("var n = 1;\nlen(n);"),
+        "test:2:1: cannot take the length of int"
+    );
+    assert_eq!(
+        error("var xs = [1, 2];\nxs[2];"),
+        "test:2:1: list index 2 is out of range"
+    );
+    assert_eq!(
+        error("var n = 1; n.x = 2;"),
+        "test:1:12: cannot set property x of int"
+    );
+    assert_eq!(
+        error("class A {}\nA(1);"),
+        "test:2:1: A expects 0 arguments but got 1"
+    );
+    assert_eq!(
+        error("class A { init(x) {} }\nA(1, 2);"),
+        "test:2:1: A.init expects 1 argument but got 2"
+    );
+    assert_eq!(
+        error("class A {}\nvar a = A();\nprint(a.x);"),
+        "test:3:9: A has no property x"
+    );
+    // A property that is missing in the middle of a chain is reported at
+    // its name rather than at the start of the chain.
+    assert_eq!(
+        error(
+            "var player = {\"inventory\": {\"items\": [{\"name\": 1}]}};
+print(player.inventory.items[0].name.first);"
+        ),
+        "test:2:38: cannot read property first of int"
+    );
+    assert_eq!(
+        error("var B = 1;\nclass A extends B {}"),
+        "test:2:17: cannot extend int"
+    );
+    assert_eq!(
+        error("class A { get x() { return 1; } }\nvar a = A();\na.x = 2;"),
+        "test:3:1: property x of A has no setter"
+    );
+    assert_eq!(
+        error("class A {}\nclass B extends A { m() { return super.m(); } }\nB().m();"),
+        "test:2:34: A has no method m"
+    );
+    assert_eq!(
+        error("var m = {};\nvar n = 10.0;\nwhile (n < n * n) n = n * n;\nm[n - n] = 1;"),
+        "test:4:1: cannot use NaN as a map key"
+    );
+
+    // The interpreter runs out of stack long before the virtual machine.
+    let source = "function f(n) { return 1 + f(n + 1); }\nf(0);";
+    assert_eq!(
+        run_vm(source, OptLevel::None, false),
+        Err(String::from("test:1:28: stack overflow"))
+    );
+    assert_eq!(
+        run_vm("import \"lib.at\";", OptLevel::None, false),
+        Err(String::from("test:1:1: cannot find module lib.at"))
+    );
+}
+
+#[test]
+fn test_scripts() {
+    // The benchmarks that only use what the virtual machine supports so
+    // far.
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/scripts");
+    for name in ["fib.at", "damage.at"] {
+        let source = std::fs::read_to_string(format!("{}/{}", dir, name)).unwrap();
+        let printed = run_vm(&source, OptLevel::Basic, true).unwrap();
+        assert_eq!(Ok(printed), run_interpreter(&source), "{}", name);
+    }
+}
+
+#[test]
+fn test_traces() {
+    let source = "function inner(x) {\n  return x + \"a\";\n}\nfunction outer() {\n  return 1 + inner(1);\n}\nprint(outer());";
+    let program = parse_program("test", source).unwrap();
+    let expected = Interpreter::with_output(Box::new(Output::default()))
+        .run(&program)
+        .unwrap_err();
+    // Optimizing inlines inner, which then has no frame of its own.
+    let function = compile_with(&program, OptLevel::None).unwrap();
+    let error = Vm::new().run(&function).unwrap_err();
+    assert_eq!(error, expected);
+    assert_eq!(error.trace()[1].to_string(), "in outer at test:5:14");
+
+    // A tail call leaves no frame behind to return to.
+    let source = "function inner() {\n  return 1 + \"a\";\n}\nfunction outer() {\n  return inner();\n}\nouter();";
+    let program = parse_program("test", source).unwrap();
+    let function = compile_with(&program, OptLevel::None).unwrap();
+    let error = Vm::new().run(&function).unwrap_err();
+    let trace: Vec<String> = error
+        .trace()
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect();
+    assert_eq!(trace, ["in inner at test:2:10", "at test:7:1"]);
+}